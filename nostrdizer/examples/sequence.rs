@@ -0,0 +1,10 @@
+//! Prints the canonical message sequence of a session as JSON
+//! `cargo run -p nostrdizer --example sequence > nostrdizer/docs/sequence.json`
+use nostrdizer::sequence::sequence;
+
+fn main() {
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&sequence()).expect("sequence serializes")
+    );
+}
@@ -0,0 +1,112 @@
+use crate::{
+    errors::Error,
+    types::{IoAuth, NostrdizerOffer},
+};
+
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// Maker that gave an address for a round
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SeenAddress {
+    pub maker: String,
+    pub seen_at: u64,
+}
+
+/// Addresses makers have given the taker, kept between rounds
+/// A maker giving the same address in two rounds would link them on chain
+/// ```
+/// use nostrdizer::{
+///     address_store::AddressStore,
+///     test_utils::{io_auth, offer},
+/// };
+///
+/// let mut store = AddressStore::default();
+/// let first_round = vec![(offer("maker", 500), io_auth(0))];
+/// assert!(store.reused(&first_round).is_empty());
+/// store.record(&first_round, 10);
+///
+/// let second_round = vec![(offer("maker", 500), io_auth(0)), (offer("other", 500), io_auth(1))];
+/// assert_eq!(store.reused(&second_round).len(), 1);
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct AddressStore {
+    #[serde(default)]
+    pub addresses: HashMap<String, SeenAddress>,
+}
+
+impl AddressStore {
+    /// Loads store from path, an empty store if there is no file
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        match path.exists() {
+            true => Ok(serde_json::from_str(&fs::read_to_string(path)?)?),
+            false => Ok(Self::default()),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        Ok(fs::write(path, serde_json::to_string_pretty(self)?)?)
+    }
+
+    /// Makers and the address they reused, either seen in an earlier round
+    /// or given more then once in this one
+    pub fn reused(&self, peer_inputs: &[(NostrdizerOffer, IoAuth)]) -> Vec<(String, String)> {
+        let mut round_addresses = HashMap::new();
+        let mut reused = vec![];
+        for (offer, io_auth) in peer_inputs {
+            for address in io_auth.addresses() {
+                let address = address.to_string();
+                let seen_before = self.addresses.contains_key(&address);
+                let seen_in_round = round_addresses
+                    .insert(address.clone(), offer.maker.clone())
+                    .is_some();
+                if seen_before || seen_in_round {
+                    reused.push((offer.maker.clone(), address));
+                }
+            }
+        }
+        reused
+    }
+
+    pub fn record(&mut self, peer_inputs: &[(NostrdizerOffer, IoAuth)], seen_at: u64) {
+        for (offer, io_auth) in peer_inputs {
+            for address in io_auth.addresses() {
+                self.addresses.insert(
+                    address.to_string(),
+                    SeenAddress {
+                        maker: offer.maker.clone(),
+                        seen_at,
+                    },
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{io_auth, offer};
+
+    #[test]
+    fn test_address_reused_in_round() {
+        let store = AddressStore::default();
+        let mut reusing = io_auth(0);
+        reusing.change_address = reusing.coinjoin_address.clone();
+
+        assert_eq!(
+            store.reused(&[(offer("maker", 0), reusing.clone())]),
+            vec![("maker".to_string(), reusing.coinjoin_address.to_string())]
+        );
+        // The second maker to give the addresses is reported
+        assert_eq!(
+            store
+                .reused(&[(offer("a", 0), io_auth(0)), (offer("b", 0), io_auth(0))])
+                .len(),
+            2
+        );
+    }
+}
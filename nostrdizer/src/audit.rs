@@ -0,0 +1,181 @@
+use crate::{
+    errors::Error,
+    payout::{PayoutEntry, PayoutHistory},
+    transcript::RoundHistory,
+    types::Amount,
+};
+
+use bdk::bitcoin::Txid;
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+/// Seconds between checks of the wallet
+pub const AUDIT_INTERVAL: u64 = 60;
+
+/// Most recent wallet transactions checked
+pub const AUDIT_TRANSACTIONS: usize = 1_000;
+
+/// Seconds a spend is given to show up in the histories before it is alerted on
+/// The maker records a CJ once the taker confirmed it, up to 120 seconds after signing
+pub const AUDIT_GRACE: u64 = 600;
+
+/// Transaction that spent coins of the wallet
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct WalletSpend {
+    pub txid: Txid,
+    /// Value sent out of the wallet, fee included
+    #[serde(with = "bdk::bitcoin::util::amount::serde::as_sat")]
+    pub amount: Amount,
+    /// When the wallet first saw the transaction
+    pub seen_at: u64,
+}
+
+/// Spends of the wallet already alerted on and how far the wallet was checked, kept between runs so each is alerted on once
+/// ```
+/// use nostrdizer::{
+///     audit::{Audit, WalletSpend},
+///     payout::PayoutHistory,
+///     transcript::RoundHistory,
+///     types::{Amount, SignedAmount},
+/// };
+/// # use bdk::bitcoin::{hashes::Hash, Txid};
+/// # let txid = |n: u8| Txid::from_slice(&[n; 32]).unwrap();
+///
+/// let spend = |n: u8| WalletSpend {
+///     txid: txid(n),
+///     amount: Amount::from_sat(50_000),
+///     seen_at: 0,
+/// };
+/// let mut payouts = PayoutHistory::default();
+/// payouts.record_earned(txid(1), SignedAmount::from_sat(500), 0);
+///
+/// let mut audit = Audit::default();
+/// let spends = [spend(1), spend(2)];
+/// let unexpected = audit.unexpected(&spends, &payouts, &RoundHistory::default(), 1_000);
+/// assert_eq!(unexpected, vec![spend(2)]);
+///
+/// audit.record_alert(txid(2));
+/// assert!(audit
+///     .unexpected(&spends, &payouts, &RoundHistory::default(), 1_000)
+///     .is_empty());
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Audit {
+    pub alerted: Vec<Txid>,
+    /// Spends seen up to this time were checked, they are not checked again once retention prunes their rounds
+    #[serde(default)]
+    pub checked_until: Option<u64>,
+}
+
+impl Audit {
+    /// Loads the audit from path, none alerted on if there is no file
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        match path.exists() {
+            true => Ok(serde_json::from_str(&fs::read_to_string(path)?)?),
+            false => Ok(Self::default()),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        Ok(fs::write(path, serde_json::to_string_pretty(self)?)?)
+    }
+
+    pub fn record_alert(&mut self, txid: Txid) {
+        if !self.alerted.contains(&txid) {
+            self.alerted.push(txid);
+        }
+    }
+
+    /// Marks the spends unexpected would have returned by now as checked
+    pub fn checked(&mut self, now: u64) {
+        let until = now.saturating_sub(AUDIT_GRACE);
+        self.checked_until = Some(
+            self.checked_until
+                .map_or(until, |checked| checked.max(until)),
+        );
+    }
+
+    /// Spends of the wallet that are not a CJ or sweep of the maker, not yet checked or alerted on
+    /// The histories are the ground truth, spends newer than [`AUDIT_GRACE`] may not be in them yet
+    pub fn unexpected(
+        &self,
+        spends: &[WalletSpend],
+        payouts: &PayoutHistory,
+        rounds: &RoundHistory,
+        now: u64,
+    ) -> Vec<WalletSpend> {
+        let known = known_spends(payouts, rounds);
+        spends
+            .iter()
+            .filter(|spend| {
+                !known.contains(&spend.txid)
+                    && !self.alerted.contains(&spend.txid)
+                    && self
+                        .checked_until
+                        .map_or(true, |checked| spend.seen_at > checked)
+                    && now.saturating_sub(spend.seen_at) >= AUDIT_GRACE
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+/// Txids of the CJs the maker signed and the sweeps it sent
+fn known_spends(payouts: &PayoutHistory, rounds: &RoundHistory) -> HashSet<Txid> {
+    let payouts = payouts.entries.iter().map(|entry| match entry {
+        PayoutEntry::Earned { txid, .. } => *txid,
+        PayoutEntry::Sweep { txid, .. } => *txid,
+    });
+    let rounds = rounds.rounds.iter().map(|round| round.txid);
+    payouts.chain(rounds).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transcript::{Completion, RoundRecord, Transcript};
+
+    use bdk::bitcoin::hashes::Hash;
+
+    #[test]
+    fn test_recent_and_recorded_spends_expected() {
+        let txid = |n: u8| Txid::from_slice(&[n; 32]).unwrap();
+        let spend = |n: u8, seen_at| WalletSpend {
+            txid: txid(n),
+            amount: Amount::from_sat(10_000),
+            seen_at,
+        };
+
+        let mut payouts = PayoutHistory::default();
+        payouts.record_sweep(txid(1), Amount::from_sat(9_000), Amount::from_sat(200), 0);
+        let mut rounds = RoundHistory::default();
+        rounds.record(RoundRecord {
+            round_id: "round".to_string(),
+            peer: "taker".to_string(),
+            txid: txid(2),
+            transcript: Transcript::default().hash(&txid(2)),
+            completion: Completion::Unconfirmed,
+            created_at: 0,
+        });
+
+        let spends = [spend(1, 0), spend(2, 0), spend(3, 0), spend(4, 900)];
+        let audit = Audit::default();
+        // Sweep and CJ are known, the last spend may not be recorded yet
+        assert_eq!(
+            audit.unexpected(&spends, &payouts, &rounds, 1_000),
+            vec![spend(3, 0)]
+        );
+        assert_eq!(audit.unexpected(&spends, &payouts, &rounds, 1_500).len(), 2);
+
+        // Checked spends are not alerted on once the CJ is pruned from the round history
+        let mut audit = audit;
+        audit.checked(1_000);
+        assert_eq!(
+            audit.unexpected(&spends, &payouts, &RoundHistory::default(), 1_500),
+            vec![spend(4, 900)]
+        );
+    }
+}
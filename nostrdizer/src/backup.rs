@@ -0,0 +1,249 @@
+use crate::{
+    errors::Error,
+    keystore::{CachedCommitment, Keystore},
+    reputation::Reputation,
+    transcript::RoundHistory,
+    types::BACKUP,
+};
+
+use nostr_rust::{
+    events::EventPrepare,
+    nips::nip4::{decrypt, encrypt},
+    nostr_client::Client as NostrClient,
+    req::ReqFilter,
+    utils::get_timestamp,
+    Identity,
+};
+use secp256k1::XOnlyPublicKey;
+use serde::{Deserialize, Serialize};
+
+use std::str::FromStr;
+
+/// Stores kept between runs, backed up to relays encrypted to the own nostr key
+/// Nostr keys of the keystore are never in a backup, only its podle commitments
+/// ```
+/// use nostrdizer::{
+///     backup::Backup,
+///     keystore::Keystore,
+///     reputation::Reputation,
+///     transcript::RoundHistory,
+/// };
+///
+/// let mut reputation = Reputation::default();
+/// reputation.record_outcome("maker", true);
+/// let backup = Backup::new(&reputation, &Keystore::default(), &RoundHistory::default());
+///
+/// // Restored on a new machine
+/// let mut restored = Reputation::default();
+/// let added = backup.merge(
+///     &mut restored,
+///     &mut Keystore::default(),
+///     &mut RoundHistory::default(),
+/// );
+/// assert_eq!(added, 1);
+/// assert_eq!(restored, reputation);
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Backup {
+    #[serde(default)]
+    pub reputation: Reputation,
+    /// Podle commitments generated, ones revealed must not be sent again
+    #[serde(default)]
+    pub commitments: Vec<CachedCommitment>,
+    #[serde(default)]
+    pub round_history: RoundHistory,
+    pub created_at: u64,
+}
+
+impl Backup {
+    pub fn new(reputation: &Reputation, keystore: &Keystore, round_history: &RoundHistory) -> Self {
+        Self {
+            reputation: reputation.clone(),
+            commitments: keystore.commitments.clone(),
+            round_history: round_history.clone(),
+            created_at: get_timestamp(),
+        }
+    }
+
+    /// Adds what the backup has to the stores without losing what they have, returns the entries added
+    /// A maker in both keeps the record of more sessions, a commitment revealed in either stays revealed
+    pub fn merge(
+        &self,
+        reputation: &mut Reputation,
+        keystore: &mut Keystore,
+        round_history: &mut RoundHistory,
+    ) -> usize {
+        let mut added = 0;
+        for (maker, record) in &self.reputation.makers {
+            match reputation.makers.get_mut(maker) {
+                Some(local) => {
+                    if record.completed + record.failed > local.completed + local.failed {
+                        *local = record.clone();
+                    }
+                }
+                None => {
+                    reputation.makers.insert(maker.clone(), record.clone());
+                    added += 1;
+                }
+            }
+        }
+
+        for commitment in &self.commitments {
+            match keystore
+                .commitments
+                .iter_mut()
+                .find(|local| local.commit == commitment.commit)
+            {
+                Some(local) => local.revealed |= commitment.revealed,
+                None => {
+                    keystore.add_commitment(commitment.clone());
+                    added += 1;
+                }
+            }
+        }
+
+        for round in &self.round_history.rounds {
+            if !round_history.rounds.contains(round) {
+                round_history.record(round.clone());
+                added += 1;
+            }
+        }
+        added
+    }
+}
+
+/// Identity of the key and a client of the relays backups are kept on
+pub fn connect(priv_key: &str, relay_urls: Vec<&str>) -> Result<(Identity, NostrClient), Error> {
+    Ok((Identity::from_str(priv_key)?, NostrClient::new(relay_urls)?))
+}
+
+/// Publishes the backup encrypted to the identity's own pub key, relays replace the last one
+/// Returns the id of the event
+pub fn publish_backup(
+    nostr_client: &mut NostrClient,
+    identity: &Identity,
+    backup: &Backup,
+) -> Result<String, Error> {
+    let own_key = XOnlyPublicKey::from_str(&identity.public_key_str)?;
+    let content = encrypt(
+        &identity.secret_key,
+        &own_key,
+        &serde_json::to_string(backup)?,
+    )?;
+
+    let event = EventPrepare {
+        pub_key: identity.public_key_str.clone(),
+        created_at: backup.created_at,
+        kind: BACKUP,
+        tags: vec![],
+        content,
+    }
+    .to_event(identity, 0);
+    nostr_client.publish_event(&event)?;
+
+    Ok(event.id)
+}
+
+/// Latest backup the identity published, none if relays have none
+pub fn fetch_backup(
+    nostr_client: &mut NostrClient,
+    identity: &Identity,
+) -> Result<Option<Backup>, Error> {
+    let filter = ReqFilter {
+        ids: None,
+        authors: Some(vec![identity.public_key_str.clone()]),
+        kinds: Some(vec![BACKUP]),
+        e: None,
+        p: None,
+        since: None,
+        until: None,
+        limit: None,
+    };
+
+    let latest = nostr_client
+        .get_events_of(vec![filter])?
+        .into_iter()
+        .filter(|event| event.pub_key == identity.public_key_str)
+        .max_by_key(|event| event.created_at);
+    let event = match latest {
+        Some(event) => event,
+        None => return Ok(None),
+    };
+
+    let own_key = XOnlyPublicKey::from_str(&identity.public_key_str)?;
+    let backup = decrypt(&identity.secret_key, &own_key, &event.content)?;
+    Ok(Some(serde_json::from_str(&backup)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transcript::{Completion, RoundRecord, Transcript};
+    use bdk::bitcoin::OutPoint;
+    use bitcoin_hashes::{sha256, Hash};
+    use secp256k1::PublicKey;
+
+    fn commitment(index: u8, revealed: bool) -> CachedCommitment {
+        CachedCommitment {
+            outpoint: OutPoint::null(),
+            index,
+            commit: sha256::Hash::hash(&[index]),
+            p2: PublicKey::from_str(
+                "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+            )
+            .unwrap(),
+            revealed,
+        }
+    }
+
+    #[test]
+    fn test_merge_keeps_local_state() {
+        let txid = OutPoint::null().txid;
+        let round = |round_id: &str| RoundRecord {
+            round_id: round_id.to_string(),
+            peer: "maker".to_string(),
+            txid,
+            transcript: Transcript::default().hash(&txid),
+            completion: Completion::Confirmed,
+            created_at: 0,
+        };
+
+        let mut backup_reputation = Reputation::default();
+        backup_reputation.record_outcome("maker", true);
+        backup_reputation.record_outcome("maker", false);
+        let backup_keystore = Keystore {
+            commitments: vec![commitment(0, true), commitment(1, false)],
+            ..Keystore::default()
+        };
+        let backup_history = RoundHistory {
+            rounds: vec![round("a"), round("b")],
+        };
+        let backup = Backup::new(&backup_reputation, &backup_keystore, &backup_history);
+
+        let mut reputation = Reputation::default();
+        reputation.record_outcome("maker", true);
+        let mut keystore = Keystore {
+            commitments: vec![commitment(0, false)],
+            ..Keystore::default()
+        };
+        let mut history = RoundHistory {
+            rounds: vec![round("a")],
+        };
+
+        // Commitment 1 and round b are new, the maker record is replaced
+        assert_eq!(
+            backup.merge(&mut reputation, &mut keystore, &mut history),
+            2
+        );
+        assert_eq!(reputation, backup_reputation);
+        assert!(keystore.commitments[0].revealed);
+        assert_eq!(keystore.commitments.len(), 2);
+        assert_eq!(history.rounds.len(), 2);
+
+        // Merging again adds nothing
+        assert_eq!(
+            backup.merge(&mut reputation, &mut keystore, &mut history),
+            0
+        );
+    }
+}
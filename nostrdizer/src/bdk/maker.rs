@@ -1,49 +1,64 @@
-use super::utils::new_wallet;
+use super::utils::{get_unconfirmed, get_unconfirmed_utxos, new_wallet};
 
 use crate::{
+    coin_selection::{check_round_cap, select_with, Candidate},
+    denomination::check_outputs,
+    display,
+    encryption::MakerEncryption,
     errors::Error,
+    fees::verify_maker_cj,
+    fill_queue::FillQueue,
+    greylist::TakerGreylist,
     maker::Maker,
+    payout::PayoutConfig,
+    publication::Publisher,
+    relay_pool::{RelayPool, RelayRole},
+    transcript::Transcript,
     types::BlockchainConfig,
-    types::{Fill, IoAuth, MakerConfig, VerifyCJInfo},
-    utils::send_signed_psbt,
+    types::{
+        DescriptorType, Fill, IoAuth, MakerConfig, UtxoHint, VerifyCJInfo, SIGNED_TRANSACTION,
+    },
+    utils::signed_psbt_message,
 };
 
 use bdk::{
-    bitcoin::{psbt::PartiallySignedTransaction, Amount, Denomination},
+    bitcoin::{psbt::PartiallySignedTransaction, Amount, OutPoint, Transaction, Txid},
+    blockchain::Blockchain,
     wallet::AddressIndex,
     SignOptions,
 };
-use nostr_rust::{keys::get_random_secret_key, nostr_client::Client as NostrClient, Identity};
+use nostr_rust::Identity;
 
 use log::debug;
-use std::str::FromStr;
+use std::collections::HashSet;
 
-use super::utils::{get_input_value, get_output_value, new_rpc_blockchain};
+use super::utils::{get_cj_values, new_rpc_blockchain};
 
 impl Maker {
-    pub fn new(
-        priv_key: Option<String>,
+    /// Connects to the relays and wallet, see [`crate::builder::MakerBuilder`]
+    pub(crate) fn connect(
+        identity: Identity,
         relay_urls: Vec<&str>,
-        config: &mut MakerConfig,
+        offer_relay_urls: Vec<&str>,
+        mut config: MakerConfig,
         blockchain_config: BlockchainConfig,
     ) -> Result<Self, Error> {
         // Nostr config
-        let priv_key = match priv_key {
-            Some(key) => key,
-            None => {
-                let (sk, _) = get_random_secret_key();
-                hex::encode(sk.as_ref())
-            }
-        };
-        let identity = Identity::from_str(&priv_key)?;
-
-        let nostr_client = NostrClient::new(relay_urls)?;
+        let relay_pool = RelayPool::new(
+            offer_relay_urls.iter().map(|r| r.to_string()).collect(),
+            relay_urls.iter().map(|r| r.to_string()).collect(),
+        );
+        let offer_client = relay_pool.connect(RelayRole::Offer, &identity, &config.relay_auth)?;
+        let nostr_client = relay_pool.connect(RelayRole::Session, &identity, &config.relay_auth)?;
 
         // Wallet config
-        let blockchain = match blockchain_config {
-            BlockchainConfig::RPC(info) => new_rpc_blockchain(info)?,
+        let (blockchain, network) = match blockchain_config {
+            BlockchainConfig::RPC(info) => {
+                let network = info.network;
+                (new_rpc_blockchain(info)?, network)
+            }
         };
-        let wallet = new_wallet(&blockchain, ("wpkh([8fa88d24/84'/1'/0'/0]tprv8hFqpTAwkZfayVk1bLc65H4Y3qcdcGJfCTntmVS9xnRa3BNXG7k5R6JK75c6z9L8LWUuUzq9kKF3uUaNQJK6gMvCLX4YHYrqcx1Gmd7k5fV/*)".to_string(), "wpkh([8fa88d24/84'/1'/0'/1]tprv8hFqpTAwkZfb1qP4H9AyEUXZzWwGSBDXRSZLrbAyv2UZZYFx2CQftd3aMXW1yLtqNqtM9gut1P5vY86AGJ2EgacpGPWWtCwTFoz3kYmWbBQ/*)".to_string()))?;
+        let wallet = new_wallet(&blockchain, ("wpkh([8fa88d24/84'/1'/0'/0]tprv8hFqpTAwkZfayVk1bLc65H4Y3qcdcGJfCTntmVS9xnRa3BNXG7k5R6JK75c6z9L8LWUuUzq9kKF3uUaNQJK6gMvCLX4YHYrqcx1Gmd7k5fV/*)".to_string(), "wpkh([8fa88d24/84'/1'/0'/1]tprv8hFqpTAwkZfb1qP4H9AyEUXZzWwGSBDXRSZLrbAyv2UZZYFx2CQftd3aMXW1yLtqNqtM9gut1P5vY86AGJ2EgacpGPWWtCwTFoz3kYmWbBQ/*)".to_string()), network)?;
 
         if config.maxsize.is_none() {
             let bal = Amount::from_sat(wallet.get_balance()?.confirmed);
@@ -52,10 +67,26 @@ impl Maker {
 
         let maker = Self {
             identity,
-            config: config.clone(),
+            config,
             nostr_client,
+            offer_client,
+            relay_pool,
             wallet,
+            blockchain,
+            fill_queue: FillQueue::default(),
+            fill_subscription: None,
             fill_commitment: None,
+            round_id: None,
+            taker: None,
+            counter_offer: None,
+            transcript: Transcript::default(),
+            network,
+            sealed_peers: HashSet::new(),
+            tagged_peers: HashSet::new(),
+            encryption: MakerEncryption::default(),
+            publisher: Publisher::default(),
+            greylist: TakerGreylist::default(),
+            fidelity_bond: None,
         };
         Ok(maker)
     }
@@ -65,32 +96,111 @@ impl Maker {
         Ok(Amount::from_sat(balance.confirmed))
     }
 
+    /// [`get_unconfirmed`] on the wallet's blockchain
+    pub fn unconfirmed_outpoints(&self, outpoints: &[OutPoint]) -> Result<Vec<OutPoint>, Error> {
+        get_unconfirmed(&self.blockchain, outpoints)
+    }
+
+    /// Gets the outpoints the wallet no longer lists as unspent, spent by a transaction seen in its last sync
+    pub fn mempool_conflicts(&self, outpoints: &[OutPoint]) -> Result<Vec<OutPoint>, Error> {
+        let unspent: Vec<OutPoint> = self
+            .wallet
+            .list_unspent()?
+            .into_iter()
+            .map(|utxo| utxo.outpoint)
+            .collect();
+        Ok(outpoints
+            .iter()
+            .filter(|outpoint| !unspent.contains(outpoint))
+            .copied()
+            .collect())
+    }
+
     pub fn get_inputs(&mut self, fill_offer: &Fill) -> Result<IoAuth, Error> {
-        let unspent = self.wallet.list_unspent()?;
+        // Silent payments are not supported by bdk wallets, they do not give out input keys
+        if fill_offer.silent_payment.is_some() {
+            return Err(Error::SilentPaymentKeysUnavailable);
+        }
+        let unconfirmed = match self.config.allow_unconfirmed {
+            true => vec![],
+            false => get_unconfirmed_utxos(&self.wallet)?,
+        };
+        let unspent: Vec<_> = self
+            .wallet
+            .list_unspent()?
+            .into_iter()
+            .filter(|utxo| !unconfirmed.contains(&utxo.outpoint))
+            // Only utxos of the round's script type are added
+            .filter(|utxo| match fill_offer.script_type {
+                Some(script_type) => script_type.matches_script(&utxo.txout.script_pubkey),
+                None => true,
+            })
+            .collect();
 
-        let mut inputs = vec![];
-        let mut value: Amount = Amount::ZERO;
+        let values: Vec<Amount> = unspent
+            .iter()
+            .map(|utxo| Amount::from_sat(utxo.txout.value))
+            .collect();
+        // Utxos worth what is owed to within dust are spent without change when wanted
+        let exact = match self.config.avoid_change {
+            true => self.exact_inputs(fill_offer, &values),
+            false => None,
+        };
+        let no_change = exact.is_some();
+        let selected = match exact {
+            Some(selected) => selected,
+            None => {
+                let candidates: Vec<Candidate> = unspent
+                    .iter()
+                    .map(|utxo| Candidate {
+                        value: Amount::from_sat(utxo.txout.value),
+                        script_pubkey: utxo.txout.script_pubkey.clone(),
+                        txid: utxo.outpoint.txid,
+                    })
+                    .collect();
+                select_with(
+                    self.config.coin_selection.selection().as_ref(),
+                    &candidates,
+                    fill_offer.amount,
+                    self.config.max_change_ratio,
+                    fill_offer.input_limits.max_per_maker(),
+                )?
+            }
+        };
+        // Change too little to be worth tracking goes to the mining fee
+        let no_change = no_change || self.donates_change(fill_offer, &values, &selected);
+        check_round_cap(&values, &selected, self.config.max_per_round)?;
 
-        for utxo in &unspent {
+        let mut inputs = vec![];
+        let mut utxo_hints = vec![];
+        for utxo in selected.into_iter().map(|i| &unspent[i]) {
             inputs.push((
                 utxo.outpoint,
-                Some(self.wallet.get_psbt_input(utxo.clone(), None, false)?),
+                self.wallet.get_psbt_input(utxo.clone(), None, false)?,
             ));
-
-            value += Amount::from_sat(utxo.txout.value);
-
-            if value >= fill_offer.amount {
-                break;
-            }
+            utxo_hints.push(UtxoHint {
+                outpoint: utxo.outpoint,
+                script_pubkey: Some(utxo.txout.script_pubkey.clone()),
+                value: Some(Amount::from_sat(utxo.txout.value)),
+                descriptor_type: DescriptorType::from_script(&utxo.txout.script_pubkey),
+            });
         }
 
         let coinjoin_address = self.wallet.get_address(AddressIndex::New)?.address;
+        // One more address for each denomination output after the first
+        let extra_coinjoin_addresses = (1..fill_offer.outputs().len())
+            .map(|_| Ok(self.wallet.get_address(AddressIndex::New)?.address))
+            .collect::<Result<Vec<_>, Error>>()?;
         let change_address = self.wallet.get_internal_address(AddressIndex::New)?.address;
 
         let maker_input = IoAuth {
             utxos: inputs,
+            utxo_hints,
             coinjoin_address,
             change_address,
+            extra_coinjoin_addresses,
+            no_change,
+            silent_payment: None,
             maker_auth_pub: "".to_string(),
             bitcoin_sig: "".to_string(),
         };
@@ -98,44 +208,65 @@ impl Maker {
         Ok(maker_input)
     }
 
+    /// Verifies the fees of a CJ and that it pays the outputs in `maker_input`
     pub fn verify_transaction(
         &mut self,
         psbt: &PartiallySignedTransaction,
-        send_amount: &Amount,
+        fill_offer: &Fill,
+        maker_input: &IoAuth,
     ) -> Result<VerifyCJInfo, Error> {
-        let (input_value, my_input_value) = get_input_value(&psbt.inputs, &self.wallet)?;
-        debug!("Input {}: {}", input_value, my_input_value);
-        let tx = psbt.clone().extract_tx();
-        let (output_value, my_output_value) = get_output_value(&tx.output, &self.wallet)?;
-        debug!("Output: {} {}", output_value, my_output_value);
-        let mining_fee = (input_value - output_value).to_signed()?;
-        let maker_fee = my_output_value.to_signed()? - my_input_value.to_signed()?;
-        debug!("MF: {}", maker_fee);
-        let abs_fee_check = maker_fee.ge(&self.config.abs_fee.to_signed()?);
-        let fee_as_percent = maker_fee.to_float_in(Denomination::Satoshi)
-            / send_amount.to_float_in(Denomination::Satoshi);
-
-        // Verify maker gets >= set fee
-        let rel_fee_check = fee_as_percent.ge(&self.config.rel_fee);
-
-        // Max send amount check
-        let max_amount_check = match &self.config.maxsize {
-            Some(max_size) => send_amount <= max_size,
-            None => true,
+        let values = get_cj_values(psbt, &self.wallet)?;
+        debug!(
+            "Input {}: {}",
+            display::sats(values.input_value),
+            display::sats(values.my_input_value)
+        );
+        debug!(
+            "Output: {} {}",
+            display::sats(values.output_value),
+            display::sats(values.my_output_value)
+        );
+
+        let tx_info = verify_maker_cj(
+            &self.round_config(),
+            fill_offer.amount,
+            fill_offer.participant_amount(),
+            self.txfee(fill_offer, maker_input),
+            &values,
+            psbt,
+        )?;
+        let tx_info = check_outputs(tx_info, psbt, fill_offer, maker_input);
+        let tx_info = self.config.policy.check_cj(tx_info, psbt, fill_offer);
+        self.check_inputs_confirmed(tx_info, psbt, maker_input)
+    }
+    /// Sends earnings to the payout address at a fee rate for a slow confirmation
+    pub fn sweep_payout(
+        &mut self,
+        payout: &PayoutConfig,
+        amount: Amount,
+    ) -> Result<(Txid, Amount), Error> {
+        let fee_rate = self.blockchain.estimate_fee(payout.conf_target as usize)?;
+        let (mut psbt, details) = {
+            let mut builder = self.wallet.build_tx();
+            builder
+                .add_recipient(payout.address.script_pubkey(), amount.to_sat())
+                .fee_rate(fee_rate)
+                .enable_rbf();
+            builder.finish()?
         };
-        debug!("ABD: {}", abs_fee_check);
-        debug!("MAX: {}", max_amount_check);
-        debug!("rel: {}", rel_fee_check);
-
-        Ok(VerifyCJInfo {
-            mining_fee,
-            maker_fee,
-            verifyed: abs_fee_check
-                && rel_fee_check
-                && max_amount_check
-                && send_amount.ge(&self.config.minsize),
-        })
+        self.wallet.sign(&mut psbt, SignOptions::default())?;
+
+        let tx = psbt.extract_tx();
+        self.blockchain.broadcast(&tx)?;
+        Ok((tx.txid(), Amount::from_sat(details.fee.unwrap_or(0))))
     }
+
+    /// Broadcasts a CJ the taker shared
+    pub fn broadcast_tx(&self, tx: &Transaction) -> Result<Txid, Error> {
+        self.blockchain.broadcast(tx)?;
+        Ok(tx.txid())
+    }
+
     pub fn sign_psbt(
         &mut self,
         psbt: PartiallySignedTransaction,
@@ -152,6 +283,9 @@ impl Maker {
         peer_pub_key: &str,
         psbt: PartiallySignedTransaction,
     ) -> Result<(), Error> {
-        send_signed_psbt(&self.identity, peer_pub_key, psbt, &mut self.nostr_client)
+        let message = signed_psbt_message(psbt, self.round_id.clone());
+        let event_id = self.publish_critical(peer_pub_key, SIGNED_TRANSACTION, &message)?;
+        self.transcript.record(&event_id);
+        Ok(())
     }
 }
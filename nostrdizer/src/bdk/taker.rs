@@ -1,68 +1,103 @@
 use super::utils::{
-    get_input_value, get_output_value, get_unspent, new_rpc_blockchain, new_wallet,
+    get_cj_values, get_unconfirmed, get_unconfirmed_utxos, get_unspent, new_rpc_blockchain,
+    new_wallet,
 };
 use crate::{
+    address_store::AddressStore,
+    capabilities::Capabilities,
+    coop::check_coop_cj,
+    display,
     errors::Error,
+    fees::{checked_add, checked_sub, maker_txfee, verify_taker_cj, DEFAULT_MIN_RELAY_FEE_RATE},
+    fidelity_bond::BondUtxo,
+    inbox::IngestStats,
+    invariants::{check_round, Violation},
+    keystore::{CachedCommitment, Keystore},
+    labels::LabelStore,
+    order_book::OrderBook,
+    partition::{spendable_sources, utxo_sources},
+    policy::output_types_match,
+    publication::Publisher,
+    relay_pool::{RelayPool, RelayRole},
+    reputation::{Reputation, ResponseTimer},
+    round::{
+        taker_change_outputs, MakerAccounting, RoundAccounting, INPUT_VSIZE, OUTPUT_VSIZE,
+        TX_OVERHEAD_VSIZE,
+    },
+    snapshot::WalletUtxo,
     taker::Taker,
     types::{
-        AuthCommitment, BlockchainConfig, CJFee, IoAuth, MaxMineingFee, NostrdizerOffer,
-        TakerConfig, VerifyCJInfo, DUST, MAX_FEE,
+        AuthBinding, AuthCommitment, BlockchainConfig, CoopJoin, DescriptorType, IoAuth,
+        NostrdizerOffer, PaymentDestination, TakerConfig, UtxoHint, VerifyCJInfo,
     },
+    utils::estimate_fee_rate,
 };
 
 use bdk::{
-    bitcoin::{psbt::PartiallySignedTransaction, Amount, Denomination, SignedAmount},
+    bitcoin::{psbt::PartiallySignedTransaction, Amount, OutPoint, Transaction, Txid},
     blockchain::Blockchain,
     wallet::{tx_builder::TxOrdering, AddressIndex},
-    KeychainKind, LocalUtxo, SignOptions,
+    FeeRate, KeychainKind, LocalUtxo, SignOptions, SyncOptions,
 };
 
-use nostr_rust::{keys::get_random_secret_key, nostr_client::Client as NostrClient, Identity};
+use nostr_rust::Identity;
 
 use log::info;
-use std::str::FromStr;
+use std::collections::{HashMap, HashSet};
 
 impl Taker {
-    pub fn new(
-        priv_key: Option<String>,
+    /// Connects to the relays and wallet, see [`crate::builder::TakerBuilder`]
+    pub(crate) fn connect(
+        identity: Identity,
         relay_urls: Vec<&str>,
         blockchain_config: BlockchainConfig,
+        config: TakerConfig,
     ) -> Result<Self, Error> {
         // Nostr config
-        let priv_key = match priv_key {
-            Some(key) => key,
-            None => {
-                let (sk, _) = get_random_secret_key();
-                hex::encode(sk.as_ref())
-            }
-        };
-        let identity = Identity::from_str(&priv_key)?;
-        let nostr_client = NostrClient::new(relay_urls)?;
+        let relay_count = relay_urls.len();
+        let relay_pool = RelayPool::new(relay_urls.iter().map(|r| r.to_string()).collect(), vec![]);
+        let nostr_client = relay_pool.connect(RelayRole::Offer, &identity, &config.relay_auth)?;
 
         // Wallet config
-        let blockchain = match blockchain_config {
-            BlockchainConfig::RPC(info) => new_rpc_blockchain(info)?,
-        };
-        let wallet = new_wallet(&blockchain, ("wpkh([5515da09/84'/1'/0'/0]tprv8iaP6UkRRJHpphe7CX866hvMp9JzLtzPiYG9CvHb2opUWfPtQSwjLsMnYxc3YD9iScG6ENBQTBkBgwnwURUdb996ij5aDTWz91xC1iVLKbS/*)".to_string(), "wpkh([5515da09/84'/1'/0'/1]tprv8iaP6UkRRJHpsiKQ7xzapBNpWiwYbWh9RE1UUWGJL94RGtxtDXWZHF7WWcyDdYPmMJkYwTEXHGRTRynSBVdPKSkEN8GZJeaZpWqzcTnvPrU/*)".to_string()))?;
-
-        let config = TakerConfig {
-            // TODO: Get this from config
-            cj_fee: CJFee {
-                rel_fee: 0.30,
-                abs_fee: Amount::from_sat(10000),
-            },
-            mining_fee: MaxMineingFee {
-                abs_fee: Amount::from_sat(10000),
-                rel_fee: 0.20,
-            },
-            minium_makers: 1,
+        let (blockchain, network) = match blockchain_config {
+            BlockchainConfig::RPC(info) => {
+                let network = info.network;
+                (new_rpc_blockchain(info)?, network)
+            }
         };
+        let order_book = OrderBook::new(relay_count, network);
+        let wallet = new_wallet(&blockchain, ("wpkh([5515da09/84'/1'/0'/0]tprv8iaP6UkRRJHpphe7CX866hvMp9JzLtzPiYG9CvHb2opUWfPtQSwjLsMnYxc3YD9iScG6ENBQTBkBgwnwURUdb996ij5aDTWz91xC1iVLKbS/*)".to_string(), "wpkh([5515da09/84'/1'/0'/1]tprv8iaP6UkRRJHpsiKQ7xzapBNpWiwYbWh9RE1UUWGJL94RGtxtDXWZHF7WWcyDdYPmMJkYwTEXHGRTRynSBVdPKSkEN8GZJeaZpWqzcTnvPrU/*)".to_string()), network)?;
+
         let taker = Self {
             identity,
             config,
             nostr_client,
+            session_client: None,
             wallet,
             blockchain,
+            network,
+            sealed_peers: HashSet::new(),
+            tagged_peers: HashSet::new(),
+            encryption_keys: HashMap::new(),
+            order_book,
+            relay_pool,
+            address_store: AddressStore::default(),
+            round_ids: HashMap::new(),
+            transcripts: HashMap::new(),
+            ingest_stats: IngestStats::default(),
+            reputation: Reputation::default(),
+            response_timer: ResponseTimer::default(),
+            publisher: Publisher::default(),
+            counter_offers: HashMap::new(),
+            latency_classes: HashMap::new(),
+            fidelity_bonds: HashMap::new(),
+            round_inputs: 0,
+            fills_sent_at: 0,
+            capabilities: Capabilities::bdk(),
+            labels: LabelStore::default(),
+            keystore: Keystore::default(),
+            round_commitment: None,
+            silent_payment: None,
         };
         Ok(taker)
     }
@@ -75,12 +110,54 @@ impl Taker {
         get_unspent(&self.wallet)
     }
 
-    /// Taker genrate podle
-    pub fn generate_podle(&self) -> Result<AuthCommitment, Error> {
-        let _unspent = self.wallet.list_unspent();
+    /// [`get_unconfirmed`] on the wallet's blockchain
+    pub fn unconfirmed_outpoints(&self, outpoints: &[OutPoint]) -> Result<Vec<OutPoint>, Error> {
+        get_unconfirmed(&self.blockchain, outpoints)
+    }
+
+    /// Fee rate in sat/vB for confirmation in the next block
+    pub fn estimate_fee_rate(&self) -> Result<f32, Error> {
+        Ok(self
+            .blockchain
+            .estimate_fee(self.config.conf_target as usize)?
+            .as_sat_per_vb())
+    }
 
-        //self.wallet.get_descriptor_for_keychain(keychain)
-        todo!()
+    /// Min fee rate in sat/vB transactions are relayed at, bdk can not ask the node so it is the default
+    pub fn min_relay_fee_rate(&self) -> Result<f32, Error> {
+        Ok(DEFAULT_MIN_RELAY_FEE_RATE)
+    }
+
+    /// Bdk can not look up utxos outside the wallet, so fidelity bonds can not be verified
+    pub fn bond_utxo(&self, _outpoint: &OutPoint) -> Result<Option<BondUtxo>, Error> {
+        Err(Error::FidelityBondsUnavailable)
+    }
+
+    /// The wallet's unspent UTXOs, synced first so spends by other apps sharing the wallet are seen
+    pub(crate) fn wallet_utxos(&self) -> Result<HashMap<OutPoint, WalletUtxo>, Error> {
+        self.wallet.sync(&self.blockchain, SyncOptions::default())?;
+        let unconfirmed = get_unconfirmed_utxos(&self.wallet)?;
+        Ok(get_unspent(&self.wallet)?
+            .into_iter()
+            .map(|utxo| {
+                let wallet_utxo = WalletUtxo {
+                    tx_out: utxo.txout,
+                    confirmed: !unconfirmed.contains(&utxo.outpoint),
+                };
+                (utxo.outpoint, wallet_utxo)
+            })
+            .collect())
+    }
+
+    /// Commitment the round's fills are sent with
+    /// Bdk wallets do not give out the private keys of their utxos, so bdk takers can not fill offers yet
+    pub fn podle_commitment(&mut self, _max_index: u8) -> Result<CachedCommitment, Error> {
+        Err(Error::PodleUnsupported)
+    }
+
+    /// Taker genrate podle, bound to the session when binding is set
+    pub fn generate_podle(&self, _binding: Option<AuthBinding>) -> Result<AuthCommitment, Error> {
+        Err(Error::PodleUnsupported)
     }
 
     pub fn combine_psbts(
@@ -113,9 +190,40 @@ impl Taker {
         send_amount: Amount,
         maker_inputs: &[(NostrdizerOffer, IoAuth)],
     ) -> Result<PartiallySignedTransaction, Error> {
+        let mut unspendable = self.ineligible_utxos()?;
+        // A sweep spends every eligible utxo
+        let sweep_balance = match self.config.sweep {
+            true => Some(self.sweep_balance()?.0),
+            false => None,
+        };
+        // Nor coins of other sources than one that covers the round, so the CJ does not link deposits
+        let target = match sweep_balance {
+            Some(balance) => balance,
+            None => self.round_target(send_amount, maker_inputs)?,
+        };
+        let other_sources = self.other_sources(target, &unspendable)?;
+        unspendable.extend(other_sources);
+        // Mining fee of a sweep is what its outputs leave
+        let mut sweep_fee = match sweep_balance {
+            Some(balance) => {
+                let payment = self
+                    .config
+                    .payment
+                    .as_ref()
+                    .map_or(Amount::ZERO, |payment| payment.amount);
+                Some(checked_sub(balance, checked_add(send_amount, payment)?)?)
+            }
+            None => None,
+        };
         let (psbt, _details) = {
             let mut builder = self.wallet.build_tx();
-            builder.ordering(TxOrdering::Untouched);
+            // Output positions are random so the taker's are not always in the same place
+            // Change is left to bdk which does not split it
+            builder.ordering(TxOrdering::Shuffle);
+            // Inputs signal RBF so a stuck CJ can be replaced, see `bump-fee --replace`
+            builder.enable_rbf();
+            builder.fee_rate(FeeRate::from_sat_per_vb(self.mining_fee_rate()));
+            builder.unspendable(unspendable);
             // Add maker cj out
             builder.add_recipient(
                 self.wallet
@@ -125,6 +233,18 @@ impl Taker {
                     .script_pubkey(),
                 send_amount.to_sat(),
             );
+            // A payment round also pays the external destination
+            if let Some(payment) = &self.config.payment {
+                match &payment.destination {
+                    PaymentDestination::Address(address) => {
+                        builder.add_recipient(address.script_pubkey(), payment.amount.to_sat());
+                    }
+                    // Silent payments are not supported by bdk wallets, they do not give out input keys
+                    PaymentDestination::SilentPayment(_) => {
+                        return Err(Error::SilentPaymentKeysUnavailable)
+                    }
+                }
+            }
             for (offer, io_auth) in maker_inputs {
                 // Adds maker CJ out
                 let script = io_auth.coinjoin_address.script_pubkey();
@@ -135,86 +255,313 @@ impl Taker {
                 }
                 builder.add_recipient(script, send_amount.to_sat());
 
-                let mut maker_input_value = 0;
+                let mut maker_input_value = Amount::ZERO;
                 // Add Maker inputs
                 for (outpoint, input) in &io_auth.utxos {
-                    // REVIEW: This really shouldn't be an option
-                    // Its only an option to work with bitcoincore
-                    // But that makes BDK and bitcoin core incompatible if done like this
-                    if let Some(input) = input {
-                        // Technically this should be done on the descriptor of the foreign utxo
-                        // In this case where its a coinjoin where all are same descriptor i think its okay to do it here
-                        let satisfaction_weight = self
+                    // Falls back to our own descriptor if maker did not send the utxo type
+                    let satisfaction_weight = match io_auth.satisfaction_weight(outpoint) {
+                        Some(weight) => weight,
+                        None => self
                             .wallet
                             .get_descriptor_for_keychain(KeychainKind::External)
                             .max_satisfaction_weight()
-                            .unwrap();
-                        builder
-                            .add_foreign_utxo(*outpoint, input.clone(), satisfaction_weight)
-                            .unwrap();
+                            .unwrap(),
+                    };
+                    builder
+                        .add_foreign_utxo(*outpoint, input.clone(), satisfaction_weight)
+                        .unwrap();
 
-                        maker_input_value += input.witness_utxo.as_ref().unwrap().value;
-                    }
+                    let utxo = input.witness_utxo.as_ref().ok_or(Error::BadInput)?;
+                    maker_input_value =
+                        checked_add(maker_input_value, Amount::from_sat(utxo.value))?;
                 }
-                let maker_fee = offer.cjfee.to_sat();
-                let change_value = maker_input_value - send_amount.to_sat() + maker_fee;
+                // Maker pays its part of the mining fee from its change
+                let txfee = maker_txfee(offer.txfee, offer.txfee_rate, io_auth.utxos.len(), 1);
+                let change_value = checked_sub(
+                    checked_add(maker_input_value, offer.cjfee)?,
+                    checked_add(send_amount, txfee)?,
+                )?;
 
-                // Add maker change
-                if change_value.gt(&DUST) {
-                    builder.add_recipient(io_auth.change_address.script_pubkey(), change_value);
+                // Add maker change, makers that declared no change leave it to the mining fee
+                let donated = match io_auth.change_output(change_value) {
+                    Some(change_value) => {
+                        builder.add_recipient(
+                            io_auth.change_address.script_pubkey(),
+                            change_value.to_sat(),
+                        );
+                        Amount::ZERO
+                    }
+                    None => change_value,
+                };
+                if let Some(fee) = sweep_fee {
+                    let fee = checked_add(checked_add(fee, txfee)?, donated)?;
+                    sweep_fee = Some(checked_sub(fee, offer.cjfee)?);
                 }
             }
+            // Every eligible utxo is spent and nothing is left for a change output
+            if let Some(fee) = sweep_fee {
+                builder.drain_wallet();
+                builder.fee_absolute(fee.to_sat());
+            }
             builder.finish().unwrap()
         };
+        if let Some(fee) = sweep_fee {
+            if estimate_fee_rate(&psbt, fee) < self.mining_fee_rate() {
+                return Err(Error::InsufficientFunds);
+            }
+        }
 
         // Check transaction details to make sure not spending too much
+        if !output_types_match(&psbt, self.payment_script().as_ref()) {
+            return Err(Error::MixedOutputTypes);
+        }
         Ok(psbt)
     }
 
+    /// Utxos the builder must not spend, unconfirmed ones unless allowed and those of another script type than the round's
+    fn ineligible_utxos(&self) -> Result<Vec<OutPoint>, Error> {
+        let mut ineligible = match self.config.allow_unconfirmed {
+            true => vec![],
+            false => get_unconfirmed_utxos(&self.wallet)?,
+        };
+        if let Some(script_type) = self.config.script_type {
+            ineligible.extend(
+                self.wallet
+                    .list_unspent()?
+                    .into_iter()
+                    .filter(|utxo| !script_type.matches_script(&utxo.txout.script_pubkey))
+                    .map(|utxo| utxo.outpoint),
+            );
+        }
+        Ok(ineligible)
+    }
+
+    /// Value and number of the utxos a sweep spends
+    pub fn sweep_balance(&mut self) -> Result<(Amount, usize), Error> {
+        let ineligible = self.ineligible_utxos()?;
+        let eligible: Vec<LocalUtxo> = self
+            .wallet
+            .list_unspent()?
+            .into_iter()
+            .filter(|utxo| !ineligible.contains(&utxo.outpoint))
+            .collect();
+        let value = eligible.iter().try_fold(Amount::ZERO, |value, utxo| {
+            checked_add(value, Amount::from_sat(utxo.txout.value))
+        })?;
+        Ok((value, eligible.len()))
+    }
+
+    /// Least the taker's inputs are worth for the round, with the mining fee of a CJ with one taker input
+    fn round_target(
+        &self,
+        send_amount: Amount,
+        maker_inputs: &[(NostrdizerOffer, IoAuth)],
+    ) -> Result<Amount, Error> {
+        let payment = match &self.config.payment {
+            Some(payment) => payment.amount,
+            None => Amount::ZERO,
+        };
+        let mut target = checked_add(send_amount, payment)?;
+        let mut maker_txfees = Amount::ZERO;
+        let mut input_count = 1;
+        for (offer, io_auth) in maker_inputs {
+            target = checked_add(target, offer.cjfee)?;
+            maker_txfees = checked_add(
+                maker_txfees,
+                maker_txfee(offer.txfee, offer.txfee_rate, io_auth.utxos.len(), 1),
+            )?;
+            input_count += io_auth.utxos.len();
+        }
+        // CJ and change output of each party
+        let output_count = 2 * (maker_inputs.len() + 1);
+        let vsize = TX_OVERHEAD_VSIZE + input_count * INPUT_VSIZE + output_count * OUTPUT_VSIZE;
+        let mining_fee = Amount::from_sat((vsize as f32 * self.mining_fee_rate()).ceil() as u64);
+        Ok(checked_sub(checked_add(target, mining_fee)?, maker_txfees).unwrap_or(Amount::ZERO))
+    }
+
+    /// Spendable utxos of other sources than the one the round's inputs are picked from
+    fn other_sources(
+        &self,
+        target: Amount,
+        unspendable: &[OutPoint],
+    ) -> Result<Vec<OutPoint>, Error> {
+        let unspent: Vec<LocalUtxo> = self
+            .wallet
+            .list_unspent()?
+            .into_iter()
+            .filter(|utxo| !unspendable.contains(&utxo.outpoint))
+            .collect();
+        let values: Vec<Amount> = unspent
+            .iter()
+            .map(|utxo| Amount::from_sat(utxo.txout.value))
+            .collect();
+        let sources: Vec<Vec<String>> = unspent
+            .iter()
+            .map(|utxo| {
+                utxo_sources(
+                    utxo.outpoint,
+                    &self.labels,
+                    |txid| self.wallet_tx(txid),
+                    |previous| self.is_own_output(previous),
+                )
+            })
+            .collect();
+        let spendable = spendable_sources(&values, &sources, target, self.config.link_sources)?;
+
+        Ok(unspent
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| !spendable.contains(i))
+            .map(|(_, utxo)| utxo.outpoint)
+            .collect())
+    }
+
+    /// Transaction of the wallet, none if it is not the wallet's
+    fn wallet_tx(&self, txid: &Txid) -> Option<Transaction> {
+        self.wallet.get_tx(txid, true).ok()??.transaction
+    }
+
+    /// Whether an output, spent or not, paid the wallet
+    fn is_own_output(&self, outpoint: &OutPoint) -> bool {
+        self.wallet_tx(&outpoint.txid)
+            .and_then(|tx| tx.output.get(outpoint.vout as usize).cloned())
+            .map(|output| self.wallet.is_mine(&output.script_pubkey).unwrap_or(false))
+            .unwrap_or(false)
+    }
+
+    /// Expected accounting of the round built with these maker inputs
+    pub fn round_accounting(
+        &self,
+        send_amount: Amount,
+        maker_inputs: &[(NostrdizerOffer, IoAuth)],
+        psbt: &PartiallySignedTransaction,
+    ) -> Result<RoundAccounting, Error> {
+        let mut makers = vec![];
+        for (offer, io_auth) in maker_inputs {
+            let input_value = io_auth
+                .utxos
+                .iter()
+                .filter_map(|(outpoint, input)| io_auth.utxo_value(outpoint, input))
+                .try_fold(Amount::ZERO, checked_add)?;
+            let mut maker =
+                MakerAccounting::new(offer, send_amount, input_value, io_auth.utxos.len())?;
+            maker.change = maker
+                .change
+                .and_then(|change| io_auth.change_output(change));
+            makers.push(maker);
+        }
+
+        let maker_input_count: usize = makers.iter().map(|m| m.input_count).sum();
+        let fee_rate = self.mining_fee_rate();
+
+        let mut accounting = RoundAccounting::new(
+            send_amount,
+            makers,
+            psbt.inputs.len().saturating_sub(maker_input_count),
+            fee_rate,
+        );
+        // A sweep has no change output
+        let change_outputs = taker_change_outputs(psbt, send_amount, maker_inputs);
+        accounting.taker_change_outputs = match self.config.sweep {
+            true => change_outputs,
+            false => change_outputs.max(1),
+        };
+        Ok(accounting)
+    }
+
+    /// Accounting invariants the CJ built with these maker inputs breaks
+    pub fn check_invariants(
+        &self,
+        accounting: &RoundAccounting,
+        maker_inputs: &[(NostrdizerOffer, IoAuth)],
+        psbt: &PartiallySignedTransaction,
+    ) -> Result<Vec<Violation>, Error> {
+        let input_value = get_cj_values(psbt, &self.wallet)?.input_value;
+        let payment = self
+            .config
+            .payment
+            .as_ref()
+            .map_or(Amount::ZERO, |payment| payment.amount);
+        check_round(accounting, maker_inputs, psbt, input_value, payment)
+    }
+
+    /// Verifies the fees of a CJ, `donated_change` is the change makers leave to the mining fee
     pub fn verify_transaction(
         &mut self,
         psbt: &PartiallySignedTransaction,
         send_amount: &Amount,
+        donated_change: Amount,
     ) -> Result<VerifyCJInfo, Error> {
-        let (input_value, my_input_value) = get_input_value(&psbt.inputs, &self.wallet)?;
-
-        let tx = psbt.clone().extract_tx();
-        let (output_value, my_output_value) = get_output_value(&tx.output, &self.wallet)?;
-        let mining_fee = (input_value - output_value).to_signed()?;
-
-        // Calculate total maker fee
-        let maker_fee: SignedAmount =
-            my_input_value.to_signed()? - my_output_value.to_signed()? - mining_fee;
-        let abs_fee_check = maker_fee.lt(&self.config.cj_fee.abs_fee.to_signed()?);
-        let fee_as_percent = maker_fee.to_float_in(Denomination::Satoshi)
-            / send_amount.to_float_in(Denomination::Satoshi);
-
-        info!("Spending: {}", my_input_value);
-        info!("Receiving: {}", my_output_value);
-
-        match input_value
-            .checked_sub(output_value)
-            .map(|val| {
-                val.gt(&Amount::from_sat(
-                    (send_amount.to_sat() as f32 * MAX_FEE).floor() as u64,
-                ))
-            })
-            .unwrap_or(true)
-        {
-            true => return Err(Error::FeesTooHigh),
-            false => (),
+        let values = get_cj_values(psbt, &self.wallet)?;
+        self.check_silent_payment(psbt)?;
+
+        info!("Spending: {}", display::sats(values.my_input_value));
+        info!("Receiving: {}", display::sats(values.my_output_value));
+
+        verify_taker_cj(&self.config, *send_amount, &values, psbt, donated_change)
+    }
+
+    /// Inputs and outputs the partner joins a co-op round with, its inputs cover amount and max_fee
+    pub fn coop_io_auth(&mut self, amount: Amount, max_fee: Amount) -> Result<IoAuth, Error> {
+        // Makers turn down CJs with unconfirmed inputs, so the partner only brings confirmed ones
+        let unconfirmed = get_unconfirmed_utxos(&self.wallet)?;
+        let target = checked_add(amount, max_fee)?;
+        let mut value = Amount::ZERO;
+        let mut utxos = vec![];
+        let mut utxo_hints = vec![];
+        for utxo in self.wallet.list_unspent()? {
+            if value >= target {
+                break;
+            }
+            if unconfirmed.contains(&utxo.outpoint)
+                || !self.config.script_type.map_or(true, |script_type| {
+                    script_type.matches_script(&utxo.txout.script_pubkey)
+                })
+            {
+                continue;
+            }
+            value += Amount::from_sat(utxo.txout.value);
+            utxo_hints.push(UtxoHint {
+                outpoint: utxo.outpoint,
+                script_pubkey: Some(utxo.txout.script_pubkey.clone()),
+                value: Some(Amount::from_sat(utxo.txout.value)),
+                descriptor_type: DescriptorType::from_script(&utxo.txout.script_pubkey),
+            });
+            utxos.push((
+                utxo.outpoint,
+                self.wallet.get_psbt_input(utxo, None, false)?,
+            ));
+        }
+        if value < target {
+            return Err(Error::InsufficientFunds);
         }
 
-        let rel_fee_check = fee_as_percent.lt(&self.config.cj_fee.rel_fee);
-        Ok(VerifyCJInfo {
-            mining_fee,
-            maker_fee,
-            verifyed: abs_fee_check
-                && rel_fee_check
-                && mining_fee.lt(&self.config.mining_fee.abs_fee.to_signed()?),
+        Ok(IoAuth {
+            utxos,
+            utxo_hints,
+            coinjoin_address: self.wallet.get_address(AddressIndex::New)?.address,
+            change_address: self.wallet.get_internal_address(AddressIndex::New)?.address,
+            extra_coinjoin_addresses: vec![],
+            no_change: false,
+            silent_payment: None,
+            maker_auth_pub: "".to_string(),
+            bitcoin_sig: "".to_string(),
         })
     }
 
+    /// Signs the lead's CJ of a co-op round once it pays the partner and costs it at most its max fee
+    pub fn sign_coop_cj(
+        &mut self,
+        psbt: PartiallySignedTransaction,
+        join: &CoopJoin,
+    ) -> Result<PartiallySignedTransaction, Error> {
+        let values = get_cj_values(&psbt, &self.wallet)?;
+        let fee = check_coop_cj(&psbt, join, &values)?;
+        info!("Co-op round costs {}", display::sats(fee));
+
+        self.sign_psbt(psbt)
+    }
+
     pub fn sign_psbt(
         &mut self,
         psbt: PartiallySignedTransaction,
@@ -1,12 +1,14 @@
 use crate::errors::Error;
-use crate::types::{Amount, RpcInfo};
+use crate::fees::{psbt_values, CJValues};
+use crate::types::{DescriptorType, RpcInfo};
 
+use bdk::bitcoincore_rpc::RpcApi;
 use bdk::{
     bitcoin::{
-        psbt::Input,
+        psbt::PartiallySignedTransaction,
         secp256k1::Secp256k1,
         util::bip32::{DerivationPath, KeySource},
-        Network, TxOut,
+        Network, OutPoint, Txid,
     },
     blockchain::{
         rpc::{Auth, RpcBlockchain, RpcConfig},
@@ -21,9 +23,10 @@ use bdk::{
     },
     miniscript::miniscript::Segwitv0,
     wallet::AddressIndex,
-    LocalUtxo, SyncOptions, Wallet,
+    KeychainKind, LocalUtxo, SyncOptions, Wallet,
 };
 
+use std::collections::HashSet;
 use std::str::FromStr;
 
 pub fn new_rpc_blockchain(blockchain_config: RpcInfo) -> Result<AnyBlockchain, Error> {
@@ -45,16 +48,29 @@ pub fn new_rpc_blockchain(blockchain_config: RpcInfo) -> Result<AnyBlockchain, E
     Ok(AnyBlockchain::Rpc(Box::new(blockchain)))
 }
 
+/// Checks change from the internal descriptor is of the same script type as CJ outputs from the external one
+pub fn check_change_type(wallet: &Wallet<AnyDatabase>) -> Result<(), Error> {
+    let descriptor_type = |keychain| {
+        DescriptorType::from_descriptor(&wallet.get_descriptor_for_keychain(keychain).to_string())
+    };
+    match descriptor_type(KeychainKind::External) == descriptor_type(KeychainKind::Internal) {
+        true => Ok(()),
+        false => Err(Error::ChangeTypeMismatch),
+    }
+}
+
 pub fn new_wallet(
     blockchain: &AnyBlockchain,
     descriptor: (String, String),
+    network: Network,
 ) -> Result<Wallet<AnyDatabase>, Error> {
     let wallet = Wallet::new(
         &descriptor.0,
         Some(&descriptor.1),
-        bdk::bitcoin::Network::Regtest,
+        network,
         AnyDatabase::Memory(MemoryDatabase::new()),
     )?;
+    check_change_type(&wallet)?;
 
     wallet.sync(blockchain, SyncOptions::default())?;
 
@@ -71,46 +87,58 @@ pub fn get_unspent(wallet: &Wallet<AnyDatabase>) -> Result<Vec<LocalUtxo>, Error
     Ok(wallet.list_unspent()?)
 }
 
-pub fn get_input_value(
-    inputs: &[Input],
-    wallet: &Wallet<AnyDatabase>,
-) -> Result<(Amount, Amount), Error> {
-    let mut my_input_value = 0;
-    let mut input_value = 0;
-    for input in inputs {
-        if let Some(script) = &input.witness_utxo {
-            if wallet.is_mine(&script.script_pubkey)? {
-                my_input_value += input.witness_utxo.as_ref().unwrap().value;
-            }
-            input_value += &input.witness_utxo.as_ref().unwrap().value;
-        }
-    }
-
-    Ok((
-        Amount::from_sat(input_value),
-        Amount::from_sat(my_input_value),
-    ))
+/// Gets the wallet's UTXOs from transactions that are not confirmed
+pub fn get_unconfirmed_utxos(wallet: &Wallet<AnyDatabase>) -> Result<Vec<OutPoint>, Error> {
+    let unconfirmed: HashSet<Txid> = wallet
+        .list_transactions(false)?
+        .into_iter()
+        .filter(|tx| tx.confirmation_time.is_none())
+        .map(|tx| tx.txid)
+        .collect();
+
+    Ok(wallet
+        .list_unspent()?
+        .into_iter()
+        .filter(|utxo| unconfirmed.contains(&utxo.outpoint.txid))
+        .map(|utxo| utxo.outpoint)
+        .collect())
 }
 
-pub fn get_output_value(
-    outputs: &[TxOut],
-    wallet: &Wallet<AnyDatabase>,
-) -> Result<(Amount, Amount), Error> {
-    let mut my_output_value = Amount::ZERO;
-    let mut output_value = Amount::ZERO;
+/// Gets the outpoints that are unconfirmed or not in the UTXO set
+/// Only an rpc blockchain can look up UTXOs of other wallets, on others every outpoint is returned
+pub fn get_unconfirmed(
+    blockchain: &AnyBlockchain,
+    outpoints: &[OutPoint],
+) -> Result<Vec<OutPoint>, Error> {
+    let rpc = match blockchain {
+        AnyBlockchain::Rpc(rpc) => rpc,
+        #[allow(unreachable_patterns)]
+        _ => return Ok(outpoints.to_vec()),
+    };
 
-    for output in outputs {
-        if wallet.is_mine(&output.script_pubkey)? {
-            my_output_value += Amount::from_sat(output.value);
+    let mut unconfirmed = vec![];
+    for outpoint in outpoints {
+        let tx_out = rpc
+            .get_tx_out(&outpoint.txid, outpoint.vout, Some(true))
+            .map_err(bdk::Error::Rpc)?;
+        match tx_out {
+            Some(tx_out) if tx_out.confirmations > 0 => (),
+            _ => unconfirmed.push(*outpoint),
         }
-        output_value += Amount::from_sat(output.value);
     }
+    Ok(unconfirmed)
+}
 
-    Ok((output_value, my_output_value))
+/// Values of a CJ psbt and of the inputs and outputs owned by the wallet
+pub fn get_cj_values(
+    psbt: &PartiallySignedTransaction,
+    wallet: &Wallet<AnyDatabase>,
+) -> Result<CJValues, Error> {
+    psbt_values(psbt, |script| Ok(wallet.is_mine(script)?))
 }
 // https://github.com/bitcoindevkit/bitcoindevkit.org
 // generate fresh descriptor strings and return them via (receive, change) tuple
-pub fn get_descriptors() -> (String, String) {
+pub fn get_descriptors(network: Network) -> (String, String) {
     // Create a new secp context
     let secp = Secp256k1::new();
 
@@ -122,7 +150,7 @@ pub fn get_descriptors() -> (String, String) {
         Mnemonic::generate((WordCount::Words12, Language::English)).unwrap();
     let mnemonic = mnemonic.into_key();
     let xkey: ExtendedKey = (mnemonic, password).into_extended_key().unwrap();
-    let xprv = xkey.into_xprv(Network::Regtest).unwrap();
+    let xprv = xkey.into_xprv(network).unwrap();
 
     // Create derived privkey from the above master privkey
     // We use the following derivation paths for receive and change keys
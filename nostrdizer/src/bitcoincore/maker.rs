@@ -1,44 +1,64 @@
-use super::utils::{get_eligible_balance, get_input_value, get_output_value};
+use super::utils::{
+    broadcast_tx, check_network, ensure_wallet, get_change_address, get_cj_address, get_cj_values,
+    get_eligible_balance, get_mempool_spent, get_spendable, get_unconfirmed, psbt_input, sign_psbt,
+    unlock_wallet, utxo_key, wallet_capabilities,
+};
 
 use crate::{
+    coin_selection::{check_round_cap, select_with, Candidate},
+    denomination::check_outputs,
+    encryption::MakerEncryption,
     errors::Error,
+    fees::verify_maker_cj,
+    fill_queue::FillQueue,
+    greylist::TakerGreylist,
     maker::Maker,
-    types::{BlockchainConfig, Fill, IoAuth, MakerConfig, VerifyCJInfo},
-    utils::send_signed_psbt,
+    payout::PayoutConfig,
+    publication::Publisher,
+    relay_pool::{RelayPool, RelayRole},
+    silent_payment::SilentPaymentShare,
+    transcript::Transcript,
+    types::{
+        BlockchainConfig, DescriptorType, Fill, IoAuth, MakerConfig, UtxoHint, VerifyCJInfo,
+        SIGNED_TRANSACTION,
+    },
+    utils::signed_psbt_message,
 };
 
-use nostr_rust::{keys::get_random_secret_key, nostr_client::Client as NostrClient, Identity};
+use nostr_rust::Identity;
 
-use log::debug;
+use log::{debug, warn};
 
 use bitcoin::{
-    blockdata::transaction::OutPoint, psbt::PartiallySignedTransaction, Amount, Denomination,
+    blockdata::transaction::OutPoint, psbt::PartiallySignedTransaction, Amount, Transaction, Txid,
 };
 use bitcoincore_rpc::{Auth, Client as RPCClient, RpcApi};
+use bitcoincore_rpc_json::{EstimateMode, ListUnspentResultEntry};
 
-use std::str::FromStr;
+use std::collections::HashSet;
 
 impl Maker {
-    pub fn new(
-        priv_key: Option<String>,
+    /// Connects to the relays and wallet, see [`crate::builder::MakerBuilder`]
+    pub(crate) fn connect(
+        identity: Identity,
         relay_urls: Vec<&str>,
-        config: &mut MakerConfig,
+        offer_relay_urls: Vec<&str>,
+        mut config: MakerConfig,
         bitcoin_core_creds: BlockchainConfig,
     ) -> Result<Self, Error> {
         let bitcoin_core_creds = match bitcoin_core_creds {
             BlockchainConfig::CoreRPC(creds) => creds,
             _ => return Err(Error::InvalidCredentials),
         };
-        let priv_key = match priv_key {
-            Some(key) => key,
-            None => {
-                let (sk, _) = get_random_secret_key();
-                hex::encode(sk.as_ref())
-            }
-        };
-        let identity = Identity::from_str(&priv_key)?;
+        let network = bitcoin_core_creds.network;
 
-        let nostr_client = NostrClient::new(relay_urls)?;
+        let relay_pool = RelayPool::new(
+            offer_relay_urls.iter().map(|r| r.to_string()).collect(),
+            relay_urls.iter().map(|r| r.to_string()).collect(),
+        );
+        let offer_client = relay_pool.connect(RelayRole::Offer, &identity, &config.relay_auth)?;
+        let nostr_client = relay_pool.connect(RelayRole::Session, &identity, &config.relay_auth)?;
+        ensure_wallet(&bitcoin_core_creds)?;
         let wallet_url = format!(
             "{}/wallet/{}",
             &bitcoin_core_creds.rpc_url, &bitcoin_core_creds.wallet_name
@@ -50,6 +70,10 @@ impl Maker {
                 bitcoin_core_creds.rpc_password,
             ),
         )?;
+        check_network(&rpc_client, network)?;
+        let wallet_passphrase = bitcoin_core_creds.wallet_passphrase;
+        // A missing or wrong passphrase fails here rather than mid round
+        drop(unlock_wallet(&rpc_client, wallet_passphrase.as_deref())?);
 
         if config.maxsize.is_none() {
             let bal = get_eligible_balance(&rpc_client)?;
@@ -58,10 +82,26 @@ impl Maker {
 
         let maker = Self {
             identity,
-            config: config.clone(),
+            config,
             nostr_client,
+            offer_client,
+            relay_pool,
             rpc_client,
+            wallet_passphrase,
+            fill_queue: FillQueue::default(),
+            fill_subscription: None,
             fill_commitment: None,
+            round_id: None,
+            taker: None,
+            counter_offer: None,
+            transcript: Transcript::default(),
+            network,
+            sealed_peers: HashSet::new(),
+            tagged_peers: HashSet::new(),
+            encryption: MakerEncryption::default(),
+            publisher: Publisher::default(),
+            greylist: TakerGreylist::default(),
+            fidelity_bond: None,
         };
         Ok(maker)
     }
@@ -72,35 +112,122 @@ impl Maker {
         peer_pub_key: &str,
         psbt: PartiallySignedTransaction,
     ) -> Result<(), Error> {
-        send_signed_psbt(&self.identity, peer_pub_key, psbt, &mut self.nostr_client)
+        let message = signed_psbt_message(psbt, self.round_id.clone());
+        let event_id = self.publish_critical(peer_pub_key, SIGNED_TRANSACTION, &message)?;
+        self.transcript.record(&event_id);
+        Ok(())
     }
 
     /// Gets maker input for CJ
     pub fn get_inputs(&mut self, fill_offer: &Fill) -> Result<IoAuth, Error> {
-        let unspent = self.rpc_client.list_unspent(None, None, None, None, None)?;
+        let mut unspent = get_spendable(&self.rpc_client, self.config.allow_unconfirmed)?;
+        // Only utxos of the round's script type are added
+        if let Some(script_type) = fill_offer.script_type {
+            unspent.retain(|utxo| script_type.matches_script(&utxo.script_pub_key));
+        }
+        // Utxos a mempool transaction the wallet does not know of spends would make the round fail
+        let outpoints: Vec<OutPoint> = unspent
+            .iter()
+            .map(|utxo| OutPoint::new(utxo.txid, utxo.vout))
+            .collect();
+        let spent = get_mempool_spent(&self.rpc_client, &outpoints)?;
+        if !spent.is_empty() {
+            warn!("Utxos already spent in the mempool are not offered: {spent:?}");
+            unspent.retain(|utxo| !spent.contains(&OutPoint::new(utxo.txid, utxo.vout)));
+        }
+        let values: Vec<Amount> = unspent.iter().map(|utxo| utxo.amount).collect();
+        // Utxos worth what is owed to within dust are spent without change when wanted
+        let exact = match self.config.avoid_change {
+            true => self.exact_inputs(fill_offer, &values),
+            false => None,
+        };
+        let no_change = exact.is_some();
+        let selected = match exact {
+            Some(selected) => selected,
+            None => {
+                let candidates: Vec<Candidate> = unspent
+                    .iter()
+                    .map(|utxo| Candidate {
+                        value: utxo.amount,
+                        script_pubkey: utxo.script_pub_key.clone(),
+                        txid: utxo.txid,
+                    })
+                    .collect();
+                select_with(
+                    self.config.coin_selection.selection().as_ref(),
+                    &candidates,
+                    fill_offer.amount,
+                    self.config.max_change_ratio,
+                    fill_offer.input_limits.max_per_maker(),
+                )?
+            }
+        };
+        // Change too little to be worth tracking goes to the mining fee
+        let no_change = no_change || self.donates_change(fill_offer, &values, &selected);
+        check_round_cap(&values, &selected, self.config.max_per_round)?;
+        let selected: Vec<ListUnspentResultEntry> =
+            selected.into_iter().map(|i| unspent[i].clone()).collect();
         let mut inputs = vec![];
-        let mut value: Amount = Amount::ZERO;
-        for utxo in unspent {
+        let mut utxo_hints = vec![];
+        for utxo in &selected {
             let input = OutPoint::new(utxo.txid, utxo.vout);
 
-            inputs.push((input, None));
-            value += utxo.amount;
-
-            if value >= fill_offer.amount {
-                break;
-            }
+            inputs.push((input, psbt_input(utxo)));
+            utxo_hints.push(UtxoHint {
+                outpoint: input,
+                descriptor_type: utxo
+                    .descriptor
+                    .as_deref()
+                    .and_then(DescriptorType::from_descriptor)
+                    .or_else(|| DescriptorType::from_script(&utxo.script_pub_key)),
+                script_pubkey: Some(utxo.script_pub_key.clone()),
+                value: Some(utxo.amount),
+            });
         }
+        // Taker derives its silent payment from every input's key, the maker's go in as a blinded share
+        let silent_payment = match &fill_offer.silent_payment {
+            Some(blinded_scan_key) => {
+                let key_source = wallet_capabilities(&self.rpc_client)?.podle_key_source();
+                let keys = selected
+                    .iter()
+                    .map(|utxo| {
+                        let priv_key = utxo_key(
+                            &self.rpc_client,
+                            utxo,
+                            key_source,
+                            self.wallet_passphrase.as_deref(),
+                            self.network,
+                        )?;
+                        Ok((
+                            OutPoint::new(utxo.txid, utxo.vout),
+                            priv_key.inner,
+                            utxo.script_pub_key.clone(),
+                        ))
+                    })
+                    .collect::<Result<Vec<_>, Error>>()?;
+                SilentPaymentShare::new(&keys, blinded_scan_key)?
+            }
+            None => None,
+        };
 
-        let coinjoin_address = self.rpc_client.get_new_address(Some("CJ out"), None)?;
+        let coinjoin_address = get_cj_address(&self.rpc_client, fill_offer.script_type)?;
         debug!("Maker cj out: {}", coinjoin_address);
+        // One more address for each denomination output after the first
+        let extra_coinjoin_addresses = (1..fill_offer.outputs().len())
+            .map(|_| get_cj_address(&self.rpc_client, fill_offer.script_type))
+            .collect::<Result<Vec<_>, _>>()?;
 
-        let change_address = self.rpc_client.get_raw_change_address(None).unwrap();
+        let change_address = get_change_address(&self.rpc_client, &coinjoin_address)?;
         debug!("Maker change out: {}", change_address);
 
         let maker_input = IoAuth {
             utxos: inputs,
+            utxo_hints,
             coinjoin_address,
             change_address,
+            extra_coinjoin_addresses,
+            no_change,
+            silent_payment,
             maker_auth_pub: "".to_string(),
             bitcoin_sig: "".to_string(),
         };
@@ -112,59 +239,79 @@ impl Maker {
         get_eligible_balance(&self.rpc_client)
     }
 
+    /// [`get_unconfirmed`] on the wallet's node
+    pub fn unconfirmed_outpoints(&self, outpoints: &[OutPoint]) -> Result<Vec<OutPoint>, Error> {
+        get_unconfirmed(&self.rpc_client, outpoints)
+    }
+
+    /// Gets the outpoints a transaction in the mempool already spends
+    pub fn mempool_conflicts(&self, outpoints: &[OutPoint]) -> Result<Vec<OutPoint>, Error> {
+        get_mempool_spent(&self.rpc_client, outpoints)
+    }
+
+    /// Verifies the fees of a CJ and that it pays the outputs in `maker_input`
     pub fn verify_transaction(
         &mut self,
         psbt: &PartiallySignedTransaction,
-        send_amount: &Amount,
+        fill_offer: &Fill,
+        maker_input: &IoAuth,
     ) -> Result<VerifyCJInfo, Error> {
         let decoded_transaction = self.rpc_client.decode_psbt(&psbt.to_string()).unwrap();
         let tx = decoded_transaction.tx;
-        let (_input_value, my_input_value) = get_input_value(&tx.vin, &self.rpc_client)?;
-        let (_output_value, my_output_value) = get_output_value(&tx.vout, &self.rpc_client)?;
-
-        let maker_fee = my_output_value.to_signed()? - my_input_value.to_signed()?;
-        debug!("Maker fee: {maker_fee}");
-
-        let mining_fee = decoded_transaction
-            .fee
-            .unwrap_or(Amount::ZERO)
-            .to_signed()?;
-
-        let abs_fee_check = maker_fee.ge(&self.config.abs_fee.to_signed()?);
-        debug!("abs value check {abs_fee_check}");
-        let fee_as_percent = maker_fee.to_float_in(Denomination::Satoshi)
-            / send_amount.to_float_in(Denomination::Satoshi);
-
-        debug!("Fee as percent {:?}", fee_as_percent);
-        let rel_fee_check = fee_as_percent.ge(&self.config.rel_fee);
-
-        debug!("rel fee check {rel_fee_check}");
-        // Max send amount check
-        let max_amount_check = match &self.config.maxsize {
-            Some(max_size) => send_amount <= max_size,
-            None => true,
+        let values = get_cj_values(&tx.vin, &tx.vout, &self.rpc_client)?;
+
+        let tx_info = verify_maker_cj(
+            &self.round_config(),
+            fill_offer.amount,
+            fill_offer.participant_amount(),
+            self.txfee(fill_offer, maker_input),
+            &values,
+            psbt,
+        )?;
+        let tx_info = check_outputs(tx_info, psbt, fill_offer, maker_input);
+        let tx_info = self.config.policy.check_cj(tx_info, psbt, fill_offer);
+        self.check_inputs_confirmed(tx_info, psbt, maker_input)
+    }
+    /// Sends earnings to the payout address at a fee rate for a slow confirmation
+    pub fn sweep_payout(
+        &mut self,
+        payout: &PayoutConfig,
+        amount: Amount,
+    ) -> Result<(Txid, Amount), Error> {
+        let txid = {
+            let _unlock = unlock_wallet(&self.rpc_client, self.wallet_passphrase.as_deref())?;
+            self.rpc_client.send_to_address(
+                &payout.address,
+                amount,
+                Some("nostrdizer payout"),
+                None,
+                None,
+                Some(true),
+                Some(payout.conf_target as u32),
+                Some(EstimateMode::Economical),
+            )?
         };
-        debug!("Max amount {max_amount_check}");
-        Ok(VerifyCJInfo {
-            mining_fee,
-            maker_fee,
-            verifyed: abs_fee_check
-                && rel_fee_check
-                && max_amount_check
-                && send_amount.ge(&self.config.minsize),
-        })
+        let fee = match self.rpc_client.get_transaction(&txid, None)?.fee {
+            Some(fee) => fee.abs().to_unsigned()?,
+            None => Amount::ZERO,
+        };
+        Ok((txid, fee))
     }
+
+    /// Broadcasts a CJ, succeeds if it was already broadcast
+    pub fn broadcast_tx(&self, tx: &Transaction) -> Result<Txid, Error> {
+        broadcast_tx(&self.rpc_client, tx)
+    }
+
     /// Maker sign psbt
     pub fn sign_psbt(
         &mut self,
         unsigned_psbt: PartiallySignedTransaction,
     ) -> Result<PartiallySignedTransaction, Error> {
-        let signed_psbt = self.rpc_client.wallet_process_psbt(
-            &unsigned_psbt.to_string(),
-            Some(true),
-            None,
-            None,
-        )?;
-        Ok(PartiallySignedTransaction::from_str(&signed_psbt.psbt).unwrap())
+        sign_psbt(
+            &unsigned_psbt,
+            &self.rpc_client,
+            self.wallet_passphrase.as_deref(),
+        )
     }
 }
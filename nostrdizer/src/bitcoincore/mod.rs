@@ -1,3 +1,4 @@
 pub mod maker;
+pub mod regtest;
 pub mod taker;
 pub mod utils;
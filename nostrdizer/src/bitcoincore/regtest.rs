@@ -0,0 +1,52 @@
+use super::utils::{check_network, ensure_wallet};
+use crate::{errors::Error, types::BitcoinCoreCredentials};
+
+use bitcoin::{Amount, Network};
+use bitcoincore_rpc::{Auth, Client as RPCClient, RpcApi};
+
+// Blocks on top of a coinbase output before it can be spent
+const COINBASE_MATURITY: u64 = 100;
+// Times coinbase outputs are mined to a wallet to reach a balance before giving up
+const MAX_MINING_ROUNDS: usize = 10;
+
+/// Client of a regtest wallet, the wallet is created if it does not exist and `create_wallet` is set
+pub fn wallet_client(creds: &BitcoinCoreCredentials) -> Result<RPCClient, Error> {
+    ensure_wallet(creds)?;
+    let rpc_client = RPCClient::new(
+        &format!("{}/wallet/{}", creds.rpc_url, creds.wallet_name),
+        Auth::UserPass(creds.rpc_username.clone(), creds.rpc_password.clone()),
+    )?;
+    check_network(&rpc_client, Network::Regtest)?;
+    Ok(rpc_client)
+}
+
+/// Mines blocks to a new address of the wallet
+pub fn mine(rpc_client: &RPCClient, blocks: u64) -> Result<(), Error> {
+    let address = rpc_client.get_new_address(None, None)?;
+    rpc_client.generate_to_address(blocks, &address)?;
+    Ok(())
+}
+
+/// Mines coinbase outputs to the wallet until it can spend amount
+pub fn ensure_balance(rpc_client: &RPCClient, amount: Amount) -> Result<(), Error> {
+    for _ in 0..MAX_MINING_ROUNDS {
+        if rpc_client.get_balance(None, None)? >= amount {
+            return Ok(());
+        }
+        mine(rpc_client, COINBASE_MATURITY + 1)?;
+    }
+    Err(Error::InsufficientFunds)
+}
+
+/// Sends amount from funder to a new address of each wallet and mines a block so the payments confirm
+pub fn fund(funder: &RPCClient, wallets: &[RPCClient], amount: Amount) -> Result<(), Error> {
+    let total = amount
+        .checked_mul(wallets.len() as u64)
+        .ok_or(Error::AmountOverflow)?;
+    ensure_balance(funder, total)?;
+    for wallet in wallets {
+        let address = wallet.get_new_address(Some("Swarm funding"), None)?;
+        funder.send_to_address(&address, amount, None, None, None, None, None, None)?;
+    }
+    mine(funder, 1)
+}
@@ -1,48 +1,74 @@
 use super::utils::{
-    get_eligible_balance, get_input_value, get_mining_fee, get_output_value, get_unspent, sign_psbt,
+    broadcast_tx, check_network, ensure_wallet, get_change_address, get_cj_address, get_cj_values,
+    get_eligible_balance, get_min_relay_fee_rate, get_mining_fee, get_spendable, get_unconfirmed,
+    get_unspent, psbt_input, sign_psbt, unlock_wallet, utxo_key, wallet_capabilities,
 };
 use crate::{
+    address_store::AddressStore,
+    capabilities::PsbtCombine,
+    commitment::{CommitmentScheme, Podle},
+    coop::check_coop_cj,
+    display,
     errors::Error,
+    fees::{checked_add, checked_sub, fee_at_rate, maker_txfee, verify_taker_cj},
+    fidelity_bond::BondUtxo,
+    inbox::IngestStats,
+    invariants::{check_round, Violation},
+    keystore::{CachedCommitment, Keystore},
+    labels::LabelStore,
+    order_book::OrderBook,
+    partition::{spendable_sources, utxo_sources},
     podle,
+    policy::output_types_match,
+    publication::Publisher,
+    relay_pool::{RelayPool, RelayRole},
+    reputation::{Reputation, ResponseTimer},
+    round::{
+        shuffle_outputs, split_change, taker_change_outputs, MakerAccounting, RoundAccounting,
+        INPUT_VSIZE, OUTPUT_VSIZE, TX_OVERHEAD_VSIZE,
+    },
+    snapshot::WalletUtxo,
     taker::Taker,
     types::{
-        AuthCommitment, BlockchainConfig, CJFee, IoAuth, MaxMineingFee, NostrdizerOffer,
-        TakerConfig, VerifyCJInfo, DUST,
+        AuthBinding, AuthCommitment, BlockchainConfig, CoopJoin, DescriptorType, IoAuth,
+        NostrdizerOffer, PaymentDestination, TakerConfig, UtxoHint, VerifyCJInfo,
     },
 };
 
 use bitcoin::psbt::PartiallySignedTransaction;
-use bitcoin::{Amount, Denomination, SignedAmount};
+use bitcoin::secp256k1::SecretKey;
+use bitcoin::{Address, Amount, OutPoint, PrivateKey, Script, Transaction, TxOut, Txid};
 use bitcoincore_rpc_json::FinalizePsbtResult;
-use nostr_rust::{keys::get_random_secret_key, nostr_client::Client as NostrClient, Identity};
+use nostr_rust::Identity;
 
 use bitcoincore_rpc::{Auth, Client as RPCClient, RpcApi};
-use bitcoincore_rpc_json::{CreateRawTransactionInput, ListUnspentResultEntry};
+use bitcoincore_rpc_json::{
+    CreateRawTransactionInput, GetTransactionResultDetailCategory, ListUnspentResultEntry,
+};
 
 use log::debug;
-use std::collections::HashMap;
+use rand::thread_rng;
+use std::collections::{HashMap, HashSet};
 use std::str::FromStr;
 
 impl Taker {
-    pub fn new(
-        priv_key: Option<String>,
+    /// Connects to the relays and wallet, see [`crate::builder::TakerBuilder`]
+    pub(crate) fn connect(
+        identity: Identity,
         relay_urls: Vec<&str>,
         bitcoin_core_creds: BlockchainConfig,
+        config: TakerConfig,
     ) -> Result<Self, Error> {
         let bitcoin_core_creds = match bitcoin_core_creds {
             BlockchainConfig::CoreRPC(creds) => creds,
             _ => return Err(Error::InvalidCredentials),
         };
+        let network = bitcoin_core_creds.network;
 
-        let priv_key = match priv_key {
-            Some(key) => key,
-            None => {
-                let (sk, _) = get_random_secret_key();
-                hex::encode(sk.as_ref())
-            }
-        };
-        let identity = Identity::from_str(&priv_key)?;
-        let nostr_client = NostrClient::new(relay_urls)?;
+        let order_book = OrderBook::new(relay_urls.len(), network);
+        let relay_pool = RelayPool::new(relay_urls.iter().map(|r| r.to_string()).collect(), vec![]);
+        let nostr_client = relay_pool.connect(RelayRole::Offer, &identity, &config.relay_auth)?;
+        ensure_wallet(&bitcoin_core_creds)?;
         let wallet_url = format!(
             "{}/wallet/{}",
             &bitcoin_core_creds.rpc_url, &bitcoin_core_creds.wallet_name
@@ -54,36 +80,70 @@ impl Taker {
                 bitcoin_core_creds.rpc_password,
             ),
         )?;
-        let config = TakerConfig {
-            // TODO: Get this from config
-            cj_fee: CJFee {
-                rel_fee: 0.30,
-                abs_fee: Amount::from_sat(10000),
-            },
-            mining_fee: MaxMineingFee {
-                abs_fee: Amount::from_sat(10000),
-                rel_fee: 0.20,
-            },
-            minium_makers: 1,
-        };
+        check_network(&rpc_client, network)?;
+        let wallet_passphrase = bitcoin_core_creds.wallet_passphrase;
+        // A missing or wrong passphrase fails here rather than mid round
+        drop(unlock_wallet(&rpc_client, wallet_passphrase.as_deref())?);
+        let capabilities = wallet_capabilities(&rpc_client)?;
+        debug!("Wallet capabilities: {:?}", capabilities);
         let taker = Self {
             identity,
             config,
             nostr_client,
+            session_client: None,
             rpc_client,
+            wallet_passphrase,
+            network,
+            sealed_peers: HashSet::new(),
+            tagged_peers: HashSet::new(),
+            encryption_keys: HashMap::new(),
+            order_book,
+            relay_pool,
+            address_store: AddressStore::default(),
+            round_ids: HashMap::new(),
+            transcripts: HashMap::new(),
+            ingest_stats: IngestStats::default(),
+            reputation: Reputation::default(),
+            response_timer: ResponseTimer::default(),
+            publisher: Publisher::default(),
+            counter_offers: HashMap::new(),
+            latency_classes: HashMap::new(),
+            fidelity_bonds: HashMap::new(),
+            round_inputs: 0,
+            fills_sent_at: 0,
+            capabilities,
+            labels: LabelStore::default(),
+            keystore: Keystore::default(),
+            round_commitment: None,
+            silent_payment: None,
         };
         Ok(taker)
     }
 
     /// Gets the taker inputs for CJ transaction
+    /// Inputs are of one source so the CJ does not link deposits, unless the taker links sources
     pub fn get_inputs(
         &mut self,
         amount: Amount,
     ) -> Result<(Amount, Vec<CreateRawTransactionInput>), Error> {
-        let unspent = self.rpc_client.list_unspent(None, None, None, None, None)?;
+        let unspent = self.eligible_unspent()?;
+        let values: Vec<Amount> = unspent.iter().map(|utxo| utxo.amount).collect();
+        let sources: Vec<Vec<String>> = unspent
+            .iter()
+            .map(|utxo| {
+                utxo_sources(
+                    outpoint(utxo),
+                    &self.labels,
+                    |txid| self.wallet_tx(txid),
+                    |previous| self.is_own_output(previous),
+                )
+            })
+            .collect();
+        let spendable = spendable_sources(&values, &sources, amount, self.config.link_sources)?;
+
         let mut inputs = vec![];
         let mut value: Amount = Amount::ZERO;
-        for utxo in unspent {
+        for utxo in spendable.into_iter().map(|i| &unspent[i]) {
             let input = CreateRawTransactionInput {
                 txid: utxo.txid,
                 vout: utxo.vout,
@@ -101,16 +161,35 @@ impl Taker {
         Ok((value, inputs))
     }
 
+    /// Utxos the taker's inputs are picked from, of the round's script type
+    fn eligible_unspent(&self) -> Result<Vec<ListUnspentResultEntry>, Error> {
+        let mut unspent = get_spendable(&self.rpc_client, self.config.allow_unconfirmed)?;
+        if let Some(script_type) = self.config.script_type {
+            unspent.retain(|utxo| script_type.matches_script(&utxo.script_pub_key));
+        }
+        Ok(unspent)
+    }
+
+    /// Value and number of the utxos a sweep spends
+    pub fn sweep_balance(&mut self) -> Result<(Amount, usize), Error> {
+        let unspent = self.eligible_unspent()?;
+        let value = unspent
+            .iter()
+            .try_fold(Amount::ZERO, |value, utxo| checked_add(value, utxo.amount))?;
+        Ok((value, unspent.len()))
+    }
+
     /// Creates CJ transaction
     #[cfg(feature = "bitcoincore")]
     // Rework this to not use btcocre types
     pub fn create_cj(
         &mut self,
         send_amount: Amount,
-        maker_inputs: &Vec<(NostrdizerOffer, IoAuth)>,
+        maker_inputs: &[(NostrdizerOffer, IoAuth)],
     ) -> Result<PartiallySignedTransaction, Error> {
         let mut outputs = HashMap::new();
         let mut total_maker_fees = Amount::ZERO;
+        let mut total_maker_txfee = Amount::ZERO;
         // REVIEW: Must be a better way to avoid nested map
         let mut inputs = maker_inputs
             .iter()
@@ -129,83 +208,205 @@ impl Taker {
 
         for (offer, maker_input) in maker_inputs {
             // Sums up total value of a makers input UTXOs
-            let maker_input_val = maker_input.utxos.iter().fold(Amount::ZERO, |val, input| {
-                val + self
+            let mut maker_input_val = Amount::ZERO;
+            for (outpoint, _) in &maker_input.utxos {
+                let tx_out = self
                     .rpc_client
-                    .get_tx_out(&input.0.txid, input.0.vout, Some(false))
-                    .unwrap()
-                    .unwrap()
-                    .value
-            });
+                    .get_tx_out(&outpoint.txid, outpoint.vout, Some(false))?
+                    .ok_or(Error::BadInput)?;
+                maker_input_val = checked_add(maker_input_val, tx_out.value)?;
+            }
             outputs.insert(maker_input.coinjoin_address.to_string(), send_amount);
 
-            let maker_fee = offer.cjfee; // Amount::from_sat(
-            let change_value = maker_input_val - send_amount + maker_fee;
-            if change_value.to_sat() > DUST {
+            let maker_fee = offer.cjfee;
+            // Maker pays its part of the mining fee from its change
+            let txfee = maker_txfee(offer.txfee, offer.txfee_rate, maker_input.utxos.len(), 1);
+            let change_value = checked_sub(
+                checked_add(maker_input_val, maker_fee)?,
+                checked_add(send_amount, txfee)?,
+            )?;
+            if let Some(change_value) = maker_input.change_output(change_value) {
                 outputs.insert(maker_input.change_address.to_string(), change_value);
             }
 
-            total_maker_fees += maker_fee;
+            total_maker_fees = checked_add(total_maker_fees, maker_fee)?;
+            total_maker_txfee = checked_add(total_maker_txfee, txfee)?;
         }
         // Taker inputs
-        // TODO: calc fee
-        let mining_fee = Amount::from_sat(500);
-        let mut taker_inputs = self.get_inputs(send_amount + total_maker_fees + mining_fee)?;
+        // Picked for the fee of the CJ with one taker input, CJ and change output
+        let fee_rate = self.mining_fee_rate();
+        let mining_fee = estimated_fee(inputs.len() + 1, outputs.len() + 2, fee_rate);
+        // A payment round also pays the external destination
+        let payment = match &self.config.payment {
+            Some(payment) => {
+                if let PaymentDestination::Address(address) = &payment.destination {
+                    outputs.insert(address.to_string(), payment.amount);
+                }
+                payment.amount
+            }
+            None => Amount::ZERO,
+        };
+        // A sweep spends every eligible utxo, otherwise makers pay part of the mining fee
+        let target = match self.config.sweep {
+            true => self.sweep_balance()?.0,
+            false => checked_sub(
+                checked_add(
+                    checked_add(checked_add(send_amount, payment)?, total_maker_fees)?,
+                    mining_fee,
+                )?,
+                total_maker_txfee,
+            )?,
+        };
+        let mut taker_inputs = self.get_inputs(target)?;
+        // A silent payment is derived from the keys of every input, so it is paid once the taker's are picked
+        if self.silent_payment.is_some() {
+            let own_inputs = self.own_input_keys(&taker_inputs.1)?;
+            let script = self.derive_silent_payment(maker_inputs, &own_inputs)?;
+            let address = Address::from_script(&script, self.network)
+                .ok_or(Error::SilentPaymentOutputMissing)?;
+            outputs.insert(address.to_string(), payment);
+        }
         inputs.append(&mut taker_inputs.1);
         // Taker output
-        let taker_cj_out = self.rpc_client.get_new_address(Some("Cj out"), None)?;
+        let taker_cj_out = get_cj_address(&self.rpc_client, self.config.script_type)?;
         outputs.insert(taker_cj_out.to_string(), send_amount);
 
-        // Taker change output
-        // REVIEW:
-        // Right now taker change is added here with a dummy amount
-        // Then replaced later, so that the fee can be calculated
-        // Be better to not have to add then replace
-        let taker_change_out = self.rpc_client.get_raw_change_address(None)?;
-        outputs.insert(taker_change_out.to_string(), Amount::from_sat(1000));
-        let transaction = self
-            .rpc_client
-            .create_raw_transaction(&inputs, &outputs, None, None)?;
-
-        // Calc change maker should get
-        // REVIEW: Not sure this fee calc is correct
-        // don't think it included sig size
-        let mining_fee = match get_mining_fee(&self.rpc_client) {
-            Ok(fee) => {
-                let cal_fee =
-                    Amount::from_sat((fee.to_sat() as usize * transaction.vsize()) as u64 / 1000);
-                if cal_fee > Amount::from_sat(270) {
-                    cal_fee
-                } else {
-                    Amount::from_sat(270)
+        // What the taker's inputs and maker contributions leave once the outputs are paid
+        let taker_left = checked_sub(
+            checked_add(taker_inputs.0, total_maker_txfee)?,
+            checked_add(checked_add(send_amount, payment)?, total_maker_fees)?,
+        )?;
+        match self.config.sweep {
+            // No change, what is left is the mining fee
+            true => {
+                let mining_fee = estimated_fee(inputs.len(), outputs.len(), fee_rate);
+                debug!(
+                    "Mining fee: {}, estimated {}",
+                    display::sats(taker_left),
+                    display::sats(mining_fee)
+                );
+                if taker_left < mining_fee {
+                    return Err(Error::InsufficientFunds);
+                }
+            }
+            false => {
+                // Taker change output
+                // REVIEW:
+                // Right now taker change is added here with a dummy amount
+                // Then replaced later, so that the fee can be calculated
+                // Be better to not have to add then replace
+                let taker_change_out = get_change_address(&self.rpc_client, &taker_cj_out)?;
+                outputs.insert(taker_change_out.to_string(), Amount::from_sat(1000));
+                // Fee of the signed CJ, the unsigned one has no witnesses to size it by
+                let mining_fee = estimated_fee(inputs.len(), outputs.len(), fee_rate);
+
+                // Calculates taker change
+                debug!("Mining fee: {}", display::sats(mining_fee));
+                let taker_change = checked_sub(taker_left, mining_fee)?;
+                // Replaces change output that has been added above
+                match split_change(taker_change, fee_rate, &mut thread_rng())
+                    .filter(|_| self.config.split_change)
+                {
+                    Some(((first, second), extra_fee)) => {
+                        debug!("Splitting change, extra fee: {}", display::sats(extra_fee));
+                        outputs.insert(taker_change_out.to_string(), first);
+                        let second_out = get_change_address(&self.rpc_client, &taker_cj_out)?;
+                        outputs.insert(second_out.to_string(), second);
+                    }
+                    None => {
+                        outputs.insert(taker_change_out.to_string(), taker_change);
+                    }
                 }
             }
-            Err(_) => Amount::from_sat(500),
-        };
-
-        // Calculates taker change
-        debug!("Mining fee: {:?} sats", mining_fee.to_sat());
-        let taker_change = taker_inputs.0.to_signed()?
-            - send_amount.to_signed()?
-            - total_maker_fees.to_signed()?
-            - mining_fee.to_signed()?;
-
-        if taker_change < Amount::ZERO.to_signed()? {
-            return Err(Error::InsufficientFunds);
         }
-        // Replaces change output that has been added above
-        outputs.insert(taker_change_out.to_string(), taker_change.to_unsigned()?);
 
         debug!("Inputs {:?}", inputs);
         debug!("Outputs: {:?}", outputs);
 
-        let psbt = self.rpc_client.create_psbt(&inputs, &outputs, None, None)?;
-
-        let psbt = PartiallySignedTransaction::from_str(&psbt).unwrap();
+        // Inputs signal RBF so a stuck CJ can be replaced, see `bump-fee --replace`
+        let psbt = self
+            .rpc_client
+            .create_psbt(&inputs, &outputs, None, Some(true))?;
+
+        let mut psbt = PartiallySignedTransaction::from_str(&psbt).unwrap();
+        // Output positions are random so the taker's are not always in the same place
+        shuffle_outputs(&mut psbt, &mut thread_rng());
+        // Maker outputs of another type would still fingerprint their change
+        if !output_types_match(&psbt, self.payment_script().as_ref()) {
+            return Err(Error::MixedOutputTypes);
+        }
 
         Ok(psbt)
     }
 
+    /// Expected accounting of the round built with these maker inputs
+    pub fn round_accounting(
+        &self,
+        send_amount: Amount,
+        maker_inputs: &[(NostrdizerOffer, IoAuth)],
+        psbt: &PartiallySignedTransaction,
+    ) -> Result<RoundAccounting, Error> {
+        let mut makers = vec![];
+        for (offer, io_auth) in maker_inputs {
+            let mut input_value = Amount::ZERO;
+            for (outpoint, _) in &io_auth.utxos {
+                if let Some(tx_out) =
+                    self.rpc_client
+                        .get_tx_out(&outpoint.txid, outpoint.vout, Some(false))?
+                {
+                    input_value = checked_add(input_value, tx_out.value)?;
+                }
+            }
+            let mut maker =
+                MakerAccounting::new(offer, send_amount, input_value, io_auth.utxos.len())?;
+            maker.change = maker
+                .change
+                .and_then(|change| io_auth.change_output(change));
+            makers.push(maker);
+        }
+
+        let maker_input_count: usize = makers.iter().map(|m| m.input_count).sum();
+        let fee_rate = self.mining_fee_rate();
+
+        let mut accounting = RoundAccounting::new(
+            send_amount,
+            makers,
+            psbt.inputs.len().saturating_sub(maker_input_count),
+            fee_rate,
+        );
+        // A sweep has no change output
+        let change_outputs = taker_change_outputs(psbt, send_amount, maker_inputs);
+        accounting.taker_change_outputs = match self.config.sweep {
+            true => change_outputs,
+            false => change_outputs.max(1),
+        };
+        Ok(accounting)
+    }
+
+    /// Accounting invariants the CJ built with these maker inputs breaks
+    pub fn check_invariants(
+        &self,
+        accounting: &RoundAccounting,
+        maker_inputs: &[(NostrdizerOffer, IoAuth)],
+        psbt: &PartiallySignedTransaction,
+    ) -> Result<Vec<Violation>, Error> {
+        let mut input_value = Amount::ZERO;
+        for txin in &psbt.unsigned_tx.input {
+            let outpoint = txin.previous_output;
+            let tx_out = self
+                .rpc_client
+                .get_tx_out(&outpoint.txid, outpoint.vout, Some(false))?
+                .ok_or(Error::BadInput)?;
+            input_value = checked_add(input_value, tx_out.value)?;
+        }
+        let payment = self
+            .config
+            .payment
+            .as_ref()
+            .map_or(Amount::ZERO, |payment| payment.amount);
+        check_round(accounting, maker_inputs, psbt, input_value, payment)
+    }
+
     /// Get unspent UTXOs
     #[cfg(feature = "bitcoincore")]
     pub fn get_unspent(&mut self) -> Result<Vec<ListUnspentResultEntry>, Error> {
@@ -216,80 +417,291 @@ impl Taker {
         &mut self,
         unsigned_psbt: PartiallySignedTransaction,
     ) -> Result<PartiallySignedTransaction, Error> {
-        sign_psbt(&unsigned_psbt, &self.rpc_client)
+        sign_psbt(
+            &unsigned_psbt,
+            &self.rpc_client,
+            self.wallet_passphrase.as_deref(),
+        )
     }
 
     pub fn combine_psbts(
         &mut self,
         psbts: &[PartiallySignedTransaction],
     ) -> Result<PartiallySignedTransaction, Error> {
-        let psbts: Vec<String> = psbts.iter().map(|p| p.to_string()).collect();
-        let result = if psbts.len() > 1 {
-            self.rpc_client.join_psbt(&psbts)?
-        } else {
-            psbts[0].clone()
-        };
-
-        Ok(PartiallySignedTransaction::from_str(&result).unwrap())
+        let (first, rest) = psbts.split_first().ok_or(Error::MakersFailedToRespond)?;
+        match self.capabilities.psbt_combine() {
+            PsbtCombine::Node if !rest.is_empty() => {
+                let psbts: Vec<String> = psbts.iter().map(|p| p.to_string()).collect();
+                let result = self.rpc_client.join_psbt(&psbts)?;
+                PartiallySignedTransaction::from_str(&result)
+                    .map_err(|err| Error::DecodeError(err.to_string()))
+            }
+            _ => rest.iter().try_fold(first.clone(), |mut combined, psbt| {
+                combined
+                    .combine(psbt.clone())
+                    .map_err(|err| Error::DecodeError(err.to_string()))?;
+                Ok(combined)
+            }),
+        }
     }
     pub fn finalize_psbt(&mut self, psbt: &str) -> Result<FinalizePsbtResult, Error> {
         Ok(self.rpc_client.finalize_psbt(psbt, None)?)
     }
 
-    /// Broadcast transaction
+    /// Broadcast transaction, succeeds if a maker already broadcast it
     pub fn broadcast_psbt(
         &mut self,
         final_psbt: PartiallySignedTransaction,
     ) -> Result<bitcoin::Txid, Error> {
-        Ok(self
-            .rpc_client
-            .send_raw_transaction(&final_psbt.extract_tx())?)
+        broadcast_tx(&self.rpc_client, &final_psbt.extract_tx())
+    }
+
+    /// Commitment the round's fills are sent with, at an index up to the max the filled makers accept
+    /// A commitment of an earlier round that failed before its auth is reused, so retries do not use up indices
+    pub fn podle_commitment(&mut self, max_index: u8) -> Result<CachedCommitment, Error> {
+        let unspent = self.rpc_client.list_unspent(None, None, None, None, None)?;
+        let outpoints: Vec<OutPoint> = unspent.iter().map(outpoint).collect();
+        if let Some(commitment) = self.keystore.unrevealed(&outpoints, max_index) {
+            return Ok(commitment.clone());
+        }
+
+        let (utxo, index) = unspent
+            .iter()
+            .find_map(|utxo| {
+                self.keystore
+                    .next_index(&outpoint(utxo), max_index)
+                    .map(|index| (utxo, index))
+            })
+            .ok_or(Error::PodleExhausted)?;
+        let (commit, p2) = podle::commitment(index, self.utxo_key(utxo)?)?;
+        let commitment = CachedCommitment {
+            outpoint: outpoint(utxo),
+            index,
+            commit,
+            p2,
+            revealed: false,
+        };
+        self.keystore.add_commitment(commitment.clone());
+        Ok(commitment)
     }
 
-    /// Taker generate podle
-    pub fn generate_podle(&self) -> Result<AuthCommitment, Error> {
-        // TODO: Get address somewhere else
+    /// Taker generate podle for the round's commitment, bound to the session when binding is set
+    pub fn generate_podle(&self, binding: Option<AuthBinding>) -> Result<AuthCommitment, Error> {
+        let commitment = self
+            .round_commitment
+            .as_ref()
+            .ok_or(Error::PodleCommitment)?;
         let unspent = self.rpc_client.list_unspent(None, None, None, None, None)?;
-        let address = unspent[0].clone().address.unwrap();
+        let utxo = unspent
+            .iter()
+            .find(|utxo| outpoint(utxo) == commitment.outpoint)
+            .ok_or(Error::PodleUtxoSpent)?;
 
-        let priv_key = self.rpc_client.dump_private_key(&address)?;
-        // let priv_key = PrivateKey::from_slice( b"\xf00\x1aD3R\xba\xa9&\xce$\xe3\xf6,\xf3j\xden\x87\x85\xee\xe8\xd4c\xd4C\x80\x1f\x81\x02j\xe9", bitcoin::Network::Regtest).unwrap();
+        Podle {
+            index: commitment.index,
+            outpoint: commitment.outpoint,
+        }
+        .generate(&self.utxo_key(utxo)?, binding)
+    }
+
+    /// Private key of a wallet utxo, see [`utxo_key`]
+    fn utxo_key(&self, utxo: &ListUnspentResultEntry) -> Result<PrivateKey, Error> {
+        utxo_key(
+            &self.rpc_client,
+            utxo,
+            self.capabilities.podle_key_source(),
+            self.wallet_passphrase.as_deref(),
+            self.network,
+        )
+    }
+
+    /// Outpoint, private key and script pubkey of each of the taker's inputs, its silent payment is derived from them
+    fn own_input_keys(
+        &self,
+        inputs: &[CreateRawTransactionInput],
+    ) -> Result<Vec<(OutPoint, SecretKey, Script)>, Error> {
+        let unspent = self.eligible_unspent()?;
+        inputs
+            .iter()
+            .map(|input| {
+                let utxo = unspent
+                    .iter()
+                    .find(|utxo| utxo.txid == input.txid && utxo.vout == input.vout)
+                    .ok_or(Error::NoMatchingUtxo)?;
+                Ok((
+                    outpoint(utxo),
+                    self.utxo_key(utxo)?.inner,
+                    utxo.script_pub_key.clone(),
+                ))
+            })
+            .collect()
+    }
 
-        podle::generate_podle(0, priv_key)
+    /// Transaction of the wallet, none if it is not the wallet's
+    fn wallet_tx(&self, txid: &Txid) -> Option<Transaction> {
+        self.rpc_client
+            .get_transaction(txid, Some(true))
+            .ok()?
+            .transaction()
+            .ok()
+    }
+
+    /// Whether an output, spent or not, paid the wallet
+    fn is_own_output(&self, outpoint: &OutPoint) -> bool {
+        match self.rpc_client.get_transaction(&outpoint.txid, Some(true)) {
+            Ok(tx) => tx.details.iter().any(|detail| {
+                detail.vout == outpoint.vout
+                    && detail.category != GetTransactionResultDetailCategory::Send
+            }),
+            Err(_) => false,
+        }
     }
 
     pub fn get_eligible_balance(&mut self) -> Result<Amount, Error> {
         get_eligible_balance(&self.rpc_client)
     }
 
+    /// [`get_unconfirmed`] on the wallet's node
+    pub fn unconfirmed_outpoints(&self, outpoints: &[OutPoint]) -> Result<Vec<OutPoint>, Error> {
+        get_unconfirmed(&self.rpc_client, outpoints)
+    }
+
+    /// The wallet's unspent UTXOs, unconfirmed and unsafe ones too
+    pub(crate) fn wallet_utxos(&self) -> Result<HashMap<OutPoint, WalletUtxo>, Error> {
+        let unspent = self
+            .rpc_client
+            .list_unspent(Some(0), None, None, Some(true), None)?;
+        Ok(unspent
+            .into_iter()
+            .map(|utxo| {
+                let wallet_utxo = WalletUtxo {
+                    tx_out: TxOut {
+                        value: utxo.amount.to_sat(),
+                        script_pubkey: utxo.script_pub_key.clone(),
+                    },
+                    confirmed: utxo.confirmations > 0,
+                };
+                (outpoint(&utxo), wallet_utxo)
+            })
+            .collect())
+    }
+
+    /// Fee rate in sat/vB for confirmation within the taker's confirmation target
+    pub fn estimate_fee_rate(&self) -> Result<f32, Error> {
+        // Core estimates in sat/kvB
+        Ok(get_mining_fee(&self.rpc_client, self.config.conf_target)?.to_sat() as f32 / 1000.0)
+    }
+
+    /// Min fee rate in sat/vB the node relays transactions at
+    pub fn min_relay_fee_rate(&self) -> Result<f32, Error> {
+        get_min_relay_fee_rate(&self.rpc_client)
+    }
+
+    /// Inputs and outputs the partner joins a co-op round with, its inputs cover amount and max_fee
+    pub fn coop_io_auth(&mut self, amount: Amount, max_fee: Amount) -> Result<IoAuth, Error> {
+        // Makers turn down CJs with unconfirmed inputs, so the partner only brings confirmed ones
+        let mut unspent = get_spendable(&self.rpc_client, false)?;
+        if let Some(script_type) = self.config.script_type {
+            unspent.retain(|utxo| script_type.matches_script(&utxo.script_pub_key));
+        }
+        let target = checked_add(amount, max_fee)?;
+        let mut value = Amount::ZERO;
+        let mut utxos = vec![];
+        let mut utxo_hints = vec![];
+        for utxo in unspent {
+            if value >= target {
+                break;
+            }
+            value += utxo.amount;
+            utxos.push((outpoint(&utxo), psbt_input(&utxo)));
+            utxo_hints.push(UtxoHint {
+                outpoint: outpoint(&utxo),
+                descriptor_type: utxo
+                    .descriptor
+                    .as_deref()
+                    .and_then(DescriptorType::from_descriptor)
+                    .or_else(|| DescriptorType::from_script(&utxo.script_pub_key)),
+                script_pubkey: Some(utxo.script_pub_key),
+                value: Some(utxo.amount),
+            });
+        }
+        if value < target {
+            return Err(Error::InsufficientFunds);
+        }
+
+        let coinjoin_address = get_cj_address(&self.rpc_client, self.config.script_type)?;
+        let change_address = get_change_address(&self.rpc_client, &coinjoin_address)?;
+        Ok(IoAuth {
+            utxos,
+            utxo_hints,
+            coinjoin_address,
+            change_address,
+            extra_coinjoin_addresses: vec![],
+            no_change: false,
+            silent_payment: None,
+            maker_auth_pub: "".to_string(),
+            bitcoin_sig: "".to_string(),
+        })
+    }
+
+    /// Signs the lead's CJ of a co-op round once it pays the partner and costs it at most its max fee
+    pub fn sign_coop_cj(
+        &mut self,
+        psbt: PartiallySignedTransaction,
+        join: &CoopJoin,
+    ) -> Result<PartiallySignedTransaction, Error> {
+        let decoded_transaction = self.rpc_client.decode_psbt(&psbt.to_string())?;
+        let tx = decoded_transaction.tx;
+        let values = get_cj_values(&tx.vin, &tx.vout, &self.rpc_client)?;
+        let fee = check_coop_cj(&psbt, join, &values)?;
+        debug!("Co-op round costs {}", display::sats(fee));
+
+        self.sign_psbt(psbt)
+    }
+
+    /// Utxo of a maker's fidelity bond, none if it is spent or not confirmed
+    pub fn bond_utxo(&self, outpoint: &OutPoint) -> Result<Option<BondUtxo>, Error> {
+        let tx_out = match self
+            .rpc_client
+            .get_tx_out(&outpoint.txid, outpoint.vout, Some(false))?
+        {
+            Some(tx_out) if tx_out.confirmations > 0 => tx_out,
+            _ => return Ok(None),
+        };
+        // Block the utxo confirmed in, counted back from the tip it was looked up at
+        let tip = self.rpc_client.get_block_header_info(&tx_out.bestblock)?;
+        let height = (tip.height + 1).saturating_sub(tx_out.confirmations as usize);
+        let block_hash = self.rpc_client.get_block_hash(height as u64)?;
+        let block = self.rpc_client.get_block_header_info(&block_hash)?;
+        Ok(Some(BondUtxo {
+            amount: tx_out.value,
+            script_pubkey: Script::from(tx_out.script_pub_key.hex),
+            confirmed_at: block.time as u64,
+        }))
+    }
+
+    /// Verifies the fees of a CJ, `donated_change` is the change makers leave to the mining fee
     pub fn verify_transaction(
         &mut self,
         psbt: &PartiallySignedTransaction,
         send_amount: &Amount,
+        donated_change: Amount,
     ) -> Result<VerifyCJInfo, Error> {
         let decoded_transaction = self.rpc_client.decode_psbt(&psbt.to_string()).unwrap();
         let tx = decoded_transaction.tx;
-        let (_input_value, my_input_value) = get_input_value(&tx.vin, &self.rpc_client)?;
-        let (_output_value, my_output_value) = get_output_value(&tx.vout, &self.rpc_client)?;
-
-        let mining_fee = decoded_transaction
-            .fee
-            .unwrap_or(Amount::ZERO)
-            .to_signed()?;
-
-        let maker_fee: SignedAmount =
-            my_input_value.to_signed()? - my_output_value.to_signed()? - mining_fee;
-        let abs_fee_check = maker_fee.lt(&self.config.cj_fee.abs_fee.to_signed()?);
-        let fee_as_percent = maker_fee.to_float_in(Denomination::Satoshi)
-            / send_amount.to_float_in(Denomination::Satoshi);
-
-        let rel_fee_check = fee_as_percent.lt(&self.config.cj_fee.rel_fee);
-        Ok(VerifyCJInfo {
-            mining_fee,
-            maker_fee,
-            verifyed: abs_fee_check
-                && rel_fee_check
-                && mining_fee.lt(&self.config.mining_fee.abs_fee.to_signed()?),
-        })
+        let values = get_cj_values(&tx.vin, &tx.vout, &self.rpc_client)?;
+        self.check_silent_payment(psbt)?;
+
+        verify_taker_cj(&self.config, *send_amount, &values, psbt, donated_change)
     }
 }
+
+/// Mining fee of a signed CJ with input_count inputs and output_count outputs at fee_rate
+fn estimated_fee(input_count: usize, output_count: usize, fee_rate: f32) -> Amount {
+    let vsize = TX_OVERHEAD_VSIZE + input_count * INPUT_VSIZE + output_count * OUTPUT_VSIZE;
+    fee_at_rate(vsize, fee_rate)
+}
+
+fn outpoint(utxo: &ListUnspentResultEntry) -> OutPoint {
+    OutPoint::new(utxo.txid, utxo.vout)
+}
@@ -1,32 +1,351 @@
-use crate::errors::Error;
+use crate::{
+    audit::WalletSpend,
+    capabilities::{Capabilities, PodleKeySource},
+    errors::Error,
+    fees::{checked_add, checked_sub, cpfp_fee, rbf_fee, CJValues},
+    fidelity_bond::FidelityBond,
+    identity::{derive_nostr_key, descriptor_key_path, descriptor_xprv},
+    types::{BitcoinCoreCredentials, DescriptorType, DUST},
+    utils::check_key_network,
+};
 
-use bitcoin::{psbt::PartiallySignedTransaction, Amount};
-use bitcoincore_rpc::{Client as RPCClient, RpcApi};
+use bitcoin::{
+    consensus::encode::{deserialize, serialize_hex},
+    psbt::{Input, PartiallySignedTransaction},
+    secp256k1::Secp256k1,
+    util::bip32::ExtendedPrivKey,
+    Address, Amount, Network, OutPoint, PrivateKey, Script, Transaction, TxOut, Txid,
+};
+use bitcoincore_rpc::{Auth, Client as RPCClient, RpcApi};
 use bitcoincore_rpc_json::{
-    GetRawTransactionResultVin, GetRawTransactionResultVout, ListUnspentResultEntry,
+    AddressType, CreateRawTransactionInput, GetRawTransactionResultVin,
+    GetRawTransactionResultVout, GetTransactionResultDetailCategory, ListUnspentResultEntry,
+    LoadWalletResult,
 };
+use log::{debug, warn};
 
+use std::collections::HashMap;
 use std::str::FromStr;
 
-/// Get output value of decoded tx
-pub fn get_output_value(
+/// Seconds an encrypted wallet is unlocked for, it is locked again as soon as signing is done
+const UNLOCK_TIMEOUT: u64 = 30;
+/// Core error code of a wrong wallet passphrase
+const RPC_WALLET_PASSPHRASE_INCORRECT: i32 = -14;
+/// Core error code of a tx that is already in the chain
+const RPC_VERIFY_ALREADY_IN_CHAIN: i32 = -27;
+/// Error code of an rpc the node does not have
+const RPC_METHOD_NOT_FOUND: i32 = -32601;
+
+/// Address type bitcoin core gives addresses of script type as
+pub fn address_type(script_type: DescriptorType) -> AddressType {
+    match script_type {
+        DescriptorType::Wpkh => AddressType::Bech32,
+        DescriptorType::ShWpkh => AddressType::P2shSegwit,
+        DescriptorType::Tr => AddressType::Bech32m,
+    }
+}
+
+/// New CJ address of the round's script type, the wallet's default type when the round has none
+pub fn get_cj_address(
+    rpc_client: &RPCClient,
+    script_type: Option<DescriptorType>,
+) -> Result<Address, Error> {
+    Ok(rpc_client.get_new_address(Some("CJ out"), script_type.map(address_type))?)
+}
+
+/// Change address of the same type as the CJ address, as core's default change type may differ
+pub fn get_change_address(rpc_client: &RPCClient, cj_address: &Address) -> Result<Address, Error> {
+    let change_type = DescriptorType::of_output(&cj_address.script_pubkey()).map(address_type);
+    Ok(rpc_client.get_raw_change_address(change_type)?)
+}
+
+/// Values of a decoded CJ and of the inputs and outputs owned by the wallet
+pub fn get_cj_values(
+    vin: &[GetRawTransactionResultVin],
     vout: &[GetRawTransactionResultVout],
     rpc_client: &RPCClient,
-) -> Result<(Amount, Amount), Error> {
-    let mut my_output_value = Amount::ZERO;
-    let mut output_value = Amount::ZERO;
+) -> Result<CJValues, Error> {
+    let mut values = CJValues::default();
+    for vin in vin {
+        let (txid, vout) = vin.txid.zip(vin.vout).ok_or(Error::BadInput)?;
+        // An input of unknown value would hide part of the mining fee
+        // Mempool is included so own unconfirmed inputs, when allowed, have a value
+        let tx_out = rpc_client
+            .get_tx_out(&txid, vout, Some(true))?
+            .ok_or(Error::BadInput)?;
+        values.add_input(
+            tx_out.value,
+            is_mine(&tx_out.script_pub_key.address, rpc_client)?,
+        )?;
+    }
     for vout in vout {
-        if let Some(address) = &vout.script_pub_key.address {
-            let info = rpc_client.get_address_info(address)?;
+        values.add_output(
+            vout.value,
+            is_mine(&vout.script_pub_key.address, rpc_client)?,
+        )?;
+    }
+
+    Ok(values)
+}
+
+/// Checks if an address belongs to the wallet
+fn is_mine(address: &Option<Address>, rpc_client: &RPCClient) -> Result<bool, Error> {
+    match address {
+        Some(address) => Ok(rpc_client.get_address_info(address)?.is_mine == Some(true)),
+        None => Ok(false),
+    }
+}
+
+/// Makes sure the wallet is loaded in bitcoin core, loading it if it is on disk
+/// and creating a descriptor wallet if it does not exist and `create_wallet` is set
+pub fn ensure_wallet(creds: &BitcoinCoreCredentials) -> Result<(), Error> {
+    // Wallets are listed, loaded and created on the node url not the wallet url
+    let rpc_client = RPCClient::new(
+        &creds.rpc_url,
+        Auth::UserPass(creds.rpc_username.clone(), creds.rpc_password.clone()),
+    )?;
+    let wallet_name = &creds.wallet_name;
+
+    if rpc_client.list_wallets()?.contains(wallet_name) {
+        return Ok(());
+    }
+
+    if rpc_client.list_wallet_dir()?.contains(wallet_name) {
+        debug!("Loading wallet {wallet_name}");
+        return match rpc_client.load_wallet(wallet_name) {
+            Ok(_) => Ok(()),
+            Err(err) => Err(Error::WalletNotLoaded(wallet_name.clone(), err.to_string())),
+        };
+    }
+
+    if !creds.create_wallet {
+        return Err(Error::WalletNotFound(wallet_name.clone()));
+    }
+
+    debug!("Creating descriptor wallet {wallet_name}");
+    // `create_wallet` does not take the descriptors arg so it is called directly
+    // Args: wallet_name, disable_private_keys, blank, passphrase, avoid_reuse, descriptors
+    rpc_client.call::<LoadWalletResult>(
+        "createwallet",
+        &[
+            wallet_name.as_str().into(),
+            false.into(),
+            false.into(),
+            serde_json::Value::Null,
+            false.into(),
+            true.into(),
+        ],
+    )?;
+
+    Ok(())
+}
+
+/// Whether the wallet is encrypted with a passphrase
+pub fn is_wallet_encrypted(rpc_client: &RPCClient) -> Result<bool, Error> {
+    // Only encrypted wallets list `unlocked_until`
+    let info: serde_json::Value = rpc_client.call("getwalletinfo", &[])?;
+    Ok(info.get("unlocked_until").is_some())
+}
+
+/// Encrypted wallet unlocked for signing, locked again when dropped
+pub struct WalletUnlock<'a> {
+    /// Set when the wallet was unlocked, unencrypted wallets have nothing to lock
+    rpc_client: Option<&'a RPCClient>,
+}
+
+impl Drop for WalletUnlock<'_> {
+    fn drop(&mut self) {
+        if let Some(rpc_client) = self.rpc_client {
+            // Core locks the wallet after the timeout even if this fails
+            if let Err(err) = rpc_client.call::<serde_json::Value>("walletlock", &[]) {
+                warn!("Could not lock wallet: {err}");
+            }
+        }
+    }
+}
+
+/// Unlocks the wallet for `UNLOCK_TIMEOUT` seconds if it is encrypted
+/// Keep the returned guard alive while signing, the wallet is locked when it is dropped
+pub fn unlock_wallet<'a>(
+    rpc_client: &'a RPCClient,
+    passphrase: Option<&str>,
+) -> Result<WalletUnlock<'a>, Error> {
+    if !is_wallet_encrypted(rpc_client)? {
+        return Ok(WalletUnlock { rpc_client: None });
+    }
+    let passphrase = passphrase.ok_or(Error::WalletLocked)?;
+
+    debug!("Unlocking wallet for {UNLOCK_TIMEOUT} seconds");
+    match rpc_client.call::<serde_json::Value>(
+        "walletpassphrase",
+        &[passphrase.into(), UNLOCK_TIMEOUT.into()],
+    ) {
+        Ok(_) => Ok(WalletUnlock {
+            rpc_client: Some(rpc_client),
+        }),
+        Err(bitcoincore_rpc::Error::JsonRpc(bitcoincore_rpc::jsonrpc::Error::Rpc(err)))
+            if err.code == RPC_WALLET_PASSPHRASE_INCORRECT =>
+        {
+            Err(Error::WrongWalletPassphrase)
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Nostr private key at `index` derived from the wallet, so a wallet backup restores it
+pub fn wallet_nostr_key(creds: &BitcoinCoreCredentials, index: u32) -> Result<String, Error> {
+    ensure_wallet(creds)?;
+    let rpc_client = RPCClient::new(
+        &format!("{}/wallet/{}", creds.rpc_url, creds.wallet_name),
+        Auth::UserPass(creds.rpc_username.clone(), creds.rpc_password.clone()),
+    )?;
+    // Private descriptors are only listed by an unlocked wallet
+    let _unlock = unlock_wallet(&rpc_client, creds.wallet_passphrase.as_deref())?;
+    derive_nostr_key(&get_wallet_xprv(&rpc_client)?, index)
+}
 
-            if info.is_mine == Some(true) {
-                my_output_value += vout.value;
+/// Sends the amount of a fidelity bond to its address from the wallet, returning the bond's outpoint
+pub fn fund_fidelity_bond(
+    creds: &BitcoinCoreCredentials,
+    bond: &FidelityBond,
+) -> Result<OutPoint, Error> {
+    ensure_wallet(creds)?;
+    let rpc_client = RPCClient::new(
+        &format!("{}/wallet/{}", creds.rpc_url, creds.wallet_name),
+        Auth::UserPass(creds.rpc_username.clone(), creds.rpc_password.clone()),
+    )?;
+    check_network(&rpc_client, creds.network)?;
+
+    let address = bond.address(creds.network)?;
+    let txid = {
+        let _unlock = unlock_wallet(&rpc_client, creds.wallet_passphrase.as_deref())?;
+        rpc_client.send_to_address(
+            &address,
+            bond.amount,
+            Some("nostrdizer fidelity bond"),
+            None,
+            None,
+            None,
+            None,
+            None,
+        )?
+    };
+    bond.find_outpoint(&rpc_client.get_raw_transaction(&txid, None)?, creds.network)
+}
+
+/// Transactions among the last `count` of the wallet that spent its coins
+/// Watch-only coins are included, so a wallet with only the maker's public descriptors can audit it
+pub fn wallet_spends(
+    creds: &BitcoinCoreCredentials,
+    count: usize,
+) -> Result<Vec<WalletSpend>, Error> {
+    ensure_wallet(creds)?;
+    let rpc_client = RPCClient::new(
+        &format!("{}/wallet/{}", creds.rpc_url, creds.wallet_name),
+        Auth::UserPass(creds.rpc_username.clone(), creds.rpc_password.clone()),
+    )?;
+    check_network(&rpc_client, creds.network)?;
+
+    let mut spends: Vec<WalletSpend> = vec![];
+    // Each output paid out of the wallet is listed on its own, with the fee of the transaction
+    for entry in rpc_client.list_transactions(None, Some(count), None, Some(true))? {
+        if !matches!(
+            entry.detail.category,
+            GetTransactionResultDetailCategory::Send
+        ) {
+            continue;
+        }
+        let sent = entry.detail.amount.abs().to_unsigned()?;
+        match spends
+            .iter_mut()
+            .find(|spend| spend.txid == entry.info.txid)
+        {
+            Some(spend) => spend.amount = checked_add(spend.amount, sent)?,
+            None => {
+                let fee = match entry.detail.fee {
+                    Some(fee) => fee.abs().to_unsigned()?,
+                    None => Amount::ZERO,
+                };
+                spends.push(WalletSpend {
+                    txid: entry.info.txid,
+                    amount: checked_add(sent, fee)?,
+                    seen_at: entry.info.timereceived,
+                });
             }
-            output_value += vout.value;
         }
     }
+    Ok(spends)
+}
 
-    Ok((output_value, my_output_value))
+/// Extended private key of the wallet's active receive descriptor
+pub fn get_wallet_xprv(rpc_client: &RPCClient) -> Result<ExtendedPrivKey, Error> {
+    // `listdescriptors` is not wrapped by the rpc client, private is set to get the xprv
+    let listed: serde_json::Value = rpc_client.call("listdescriptors", &[true.into()])?;
+    let descriptor = listed["descriptors"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|desc| desc["active"].as_bool() == Some(true))
+        .filter(|desc| desc["internal"].as_bool() != Some(true))
+        .find_map(|desc| desc["desc"].as_str())
+        .ok_or(Error::NoWalletKey)?;
+
+    descriptor_xprv(descriptor)
+}
+
+/// Private key of a wallet utxo, the wallet is only unlocked while it is dumped
+/// Descriptor wallets can not dump keys, the key is derived at the utxo's key origin instead
+pub fn utxo_key(
+    rpc_client: &RPCClient,
+    utxo: &ListUnspentResultEntry,
+    key_source: PodleKeySource,
+    wallet_passphrase: Option<&str>,
+    network: Network,
+) -> Result<PrivateKey, Error> {
+    let priv_key = match key_source {
+        PodleKeySource::DumpPrivKey => {
+            let address = utxo.address.clone().ok_or(Error::NoMatchingUtxo)?;
+            let _unlock = unlock_wallet(rpc_client, wallet_passphrase)?;
+            rpc_client.dump_private_key(&address)?
+        }
+        PodleKeySource::Descriptor => {
+            let descriptor = utxo.descriptor.as_deref().ok_or(Error::NoMatchingUtxo)?;
+            let path = descriptor_key_path(descriptor)?;
+            let xprv = {
+                let _unlock = unlock_wallet(rpc_client, wallet_passphrase)?;
+                get_wallet_xprv(rpc_client)?
+            };
+            let key = xprv.derive_priv(&Secp256k1::new(), &path)?;
+            PrivateKey::new(key.private_key, network)
+        }
+    };
+    check_key_network(&priv_key, network)?;
+    Ok(priv_key)
+}
+
+/// Capabilities of the wallet and the node it is loaded in
+pub fn wallet_capabilities(rpc_client: &RPCClient) -> Result<Capabilities, Error> {
+    let version = rpc_client.get_network_info()?.version;
+    // `descriptors` is not in the rpc client's wallet info, nodes before 0.21 only have legacy wallets
+    let wallet_info: serde_json::Value = rpc_client.call("getwalletinfo", &[])?;
+    let descriptor_wallet = wallet_info["descriptors"].as_bool().unwrap_or(false);
+    Ok(Capabilities::bitcoin_core(version, descriptor_wallet))
+}
+
+/// Checks bitcoin core is running on network
+pub fn check_network(rpc_client: &RPCClient, network: Network) -> Result<(), Error> {
+    // Core names mainnet and testnet differently
+    let chain = match network {
+        Network::Bitcoin => "main",
+        Network::Testnet => "test",
+        Network::Signet => "signet",
+        Network::Regtest => "regtest",
+    };
+
+    match rpc_client.get_blockchain_info()?.chain == chain {
+        true => Ok(()),
+        false => Err(Error::WrongNetwork(network)),
+    }
 }
 
 /// Gets balance eligible for coinjoin
@@ -40,54 +359,344 @@ pub fn get_unspent(rpc_client: &RPCClient) -> Result<Vec<ListUnspentResultEntry>
     Ok(rpc_client.list_unspent(None, None, None, Some(false), None)?)
 }
 
-/// Get mining fee to get into the next block
-pub fn get_mining_fee(rpc_client: &RPCClient) -> Result<Amount, Error> {
-    let fee = rpc_client.estimate_smart_fee(1, None)?;
+/// Gets unspent UTXOs that can be added to a CJ
+/// Own unconfirmed UTXOs are only included when `allow_unconfirmed` is set
+pub fn get_spendable(
+    rpc_client: &RPCClient,
+    allow_unconfirmed: bool,
+) -> Result<Vec<ListUnspentResultEntry>, Error> {
+    let min_conf = match allow_unconfirmed {
+        true => 0,
+        false => 1,
+    };
+    // Unsafe UTXOs are unconfirmed ones from outside keys, they are never spent
+    Ok(rpc_client.list_unspent(Some(min_conf), None, None, Some(false), None)?)
+}
 
-    if let Some(fee) = fee.fee_rate {
-        Ok(fee)
-    } else {
-        Err(Error::FeeEstimation)
+/// Psbt input of a wallet utxo, what `utxoupdatepsbt` would add for it
+/// A taker of any backend adds it to the CJ as a foreign utxo from its witness utxo
+pub fn psbt_input(utxo: &ListUnspentResultEntry) -> Input {
+    Input {
+        witness_utxo: Some(TxOut {
+            value: utxo.amount.to_sat(),
+            script_pubkey: utxo.script_pub_key.clone(),
+        }),
+        redeem_script: utxo.redeem_script.clone(),
+        witness_script: utxo.witness_script.clone(),
+        ..Default::default()
     }
 }
 
-/// Get the input value of decoded tx
-pub fn get_input_value(
-    vin: &[GetRawTransactionResultVin],
+/// Gets the outpoints that are unconfirmed or not in the UTXO set
+pub fn get_unconfirmed(
     rpc_client: &RPCClient,
-) -> Result<(Amount, Amount), Error> {
-    let mut my_input_value: bitcoin::Amount = Amount::ZERO;
-    let mut input_value = Amount::ZERO;
-    for vin in vin {
-        let txid = vin.txid;
-        let vout = vin.vout;
-
-        match (txid, vout) {
-            (Some(txid), Some(vout)) => {
-                let tx_out = rpc_client.get_tx_out(&txid, vout, Some(false))?;
-                if let Some(tx_out) = tx_out {
-                    if let Some(address) = tx_out.script_pub_key.address {
-                        let add_info = rpc_client.get_address_info(&address)?;
-                        if add_info.is_mine == Some(true) {
-                            my_input_value += tx_out.value;
-                        }
-                        input_value += tx_out.value;
-                    }
+    outpoints: &[OutPoint],
+) -> Result<Vec<OutPoint>, Error> {
+    let mut unconfirmed = vec![];
+    for outpoint in outpoints {
+        match rpc_client.get_tx_out(&outpoint.txid, outpoint.vout, Some(true))? {
+            Some(tx_out) if tx_out.confirmations > 0 => (),
+            _ => unconfirmed.push(*outpoint),
+        }
+    }
+    Ok(unconfirmed)
+}
+
+/// Gets the outpoints a transaction in the mempool already spends, such as one of another wallet sharing the keys
+/// Uses `gettxspendingprevout` on nodes that have it, older nodes look the outpoints up in the UTXO set with the mempool applied
+pub fn get_mempool_spent(
+    rpc_client: &RPCClient,
+    outpoints: &[OutPoint],
+) -> Result<Vec<OutPoint>, Error> {
+    if outpoints.is_empty() {
+        return Ok(vec![]);
+    }
+    let prevouts: Vec<serde_json::Value> = outpoints
+        .iter()
+        .map(|outpoint| serde_json::json!({ "txid": outpoint.txid.to_string(), "vout": outpoint.vout }))
+        .collect();
+    match rpc_client.call::<Vec<serde_json::Value>>("gettxspendingprevout", &[prevouts.into()]) {
+        // Answers are in the order of the outpoints, spent ones have the txid spending them
+        Ok(spending) => Ok(outpoints
+            .iter()
+            .zip(spending)
+            .filter(|(_, spending)| spending.get("spendingtxid").is_some())
+            .map(|(outpoint, _)| *outpoint)
+            .collect()),
+        Err(bitcoincore_rpc::Error::JsonRpc(bitcoincore_rpc::jsonrpc::Error::Rpc(err)))
+            if err.code == RPC_METHOD_NOT_FOUND =>
+        {
+            let mut spent = vec![];
+            for outpoint in outpoints {
+                if rpc_client
+                    .get_tx_out(&outpoint.txid, outpoint.vout, Some(true))?
+                    .is_none()
+                {
+                    spent.push(*outpoint);
                 }
             }
-            _ => panic!(),
+            Ok(spent)
         }
+        Err(err) => Err(err.into()),
     }
+}
+
+/// Get mining fee to get into the next block
+pub fn get_mining_fee(rpc_client: &RPCClient, conf_target: u16) -> Result<Amount, Error> {
+    let fee = rpc_client.estimate_smart_fee(conf_target, None)?;
 
-    Ok((input_value, my_input_value))
+    if let Some(fee) = fee.fee_rate {
+        Ok(fee)
+    } else {
+        Err(Error::FeeEstimation)
+    }
+}
+
+/// Min fee rate in sat/vB the node relays transactions at
+pub fn get_min_relay_fee_rate(rpc_client: &RPCClient) -> Result<f32, Error> {
+    // Core gives fee rates in BTC/kvB
+    Ok(rpc_client.get_network_info()?.relay_fee.to_sat() as f32 / 1000.0)
+}
+
+/// Fee rate in sat/vB a replacement has to pay on top of the transaction it replaces
+pub fn get_incremental_fee_rate(rpc_client: &RPCClient) -> Result<f32, Error> {
+    Ok(rpc_client.get_network_info()?.incremental_fee.to_sat() as f32 / 1000.0)
 }
 
-/// Sign psbt
+/// Sign psbt, unlocking the wallet while signing if it is encrypted
 pub fn sign_psbt(
     unsigned_psbt: &PartiallySignedTransaction,
     rpc_client: &RPCClient,
+    passphrase: Option<&str>,
 ) -> Result<PartiallySignedTransaction, Error> {
+    let _unlock = unlock_wallet(rpc_client, passphrase)?;
     let signed_psbt =
         rpc_client.wallet_process_psbt(&unsigned_psbt.to_string(), Some(true), None, None)?;
     Ok(PartiallySignedTransaction::from_str(&signed_psbt.psbt).unwrap())
 }
+
+/// Broadcasts tx, a tx already in the mempool or chain counts as broadcast
+/// so the taker and makers can all broadcast the same CJ
+pub fn broadcast_tx(rpc_client: &RPCClient, tx: &Transaction) -> Result<Txid, Error> {
+    match rpc_client.send_raw_transaction(tx) {
+        Ok(txid) => Ok(txid),
+        Err(bitcoincore_rpc::Error::JsonRpc(bitcoincore_rpc::jsonrpc::Error::Rpc(err)))
+            if is_already_broadcast(err.code, &err.message) =>
+        {
+            debug!("Transaction {} was already broadcast", tx.txid());
+            Ok(tx.txid())
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Submits a parent and its children with `submitpackage`, so a parent under the mempool min fee is relayed with the
+/// children paying for it. Nodes without package relay, before Core 25 or Core 25 off regtest, are sent each
+/// transaction on its own instead, which only works for a parent the mempool takes on its own
+pub fn submit_package(rpc_client: &RPCClient, package: &[Transaction]) -> Result<(), Error> {
+    let hexes: Vec<String> = package.iter().map(serialize_hex).collect();
+    match rpc_client.call::<serde_json::Value>("submitpackage", &[hexes.into()]) {
+        Ok(result) => match result.get("package_msg").and_then(|msg| msg.as_str()) {
+            // Core 25 answers without a package message, rejections are rpc errors
+            Some("success") | None => {
+                debug!("Submitted package: {result}");
+                Ok(())
+            }
+            Some(msg) => Err(Error::PackageRejected(format!("{msg}: {result}"))),
+        },
+        Err(bitcoincore_rpc::Error::JsonRpc(bitcoincore_rpc::jsonrpc::Error::Rpc(err)))
+            if err.code == RPC_METHOD_NOT_FOUND || err.message.contains("regtest") =>
+        {
+            debug!("Node has no package relay, broadcasting the transactions one by one");
+            for tx in package {
+                broadcast_tx(rpc_client, tx)?;
+            }
+            Ok(())
+        }
+        Err(bitcoincore_rpc::Error::JsonRpc(bitcoincore_rpc::jsonrpc::Error::Rpc(err))) => {
+            Err(Error::PackageRejected(err.message))
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Bumps the fee of a stuck wallet transaction, such as a CJ, with a child spending the wallet's outputs of it
+/// to a new wallet address at a fee that gets both to `fee_rate`. Returns the txid of the child
+pub fn bump_fee(creds: &BitcoinCoreCredentials, txid: &Txid, fee_rate: f32) -> Result<Txid, Error> {
+    ensure_wallet(creds)?;
+    let rpc_client = RPCClient::new(
+        &format!("{}/wallet/{}", creds.rpc_url, creds.wallet_name),
+        Auth::UserPass(creds.rpc_username.clone(), creds.rpc_password.clone()),
+    )?;
+    check_network(&rpc_client, creds.network)?;
+
+    let parent: Transaction = deserialize(&rpc_client.get_transaction(txid, None)?.hex)
+        .map_err(|err| Error::DecodeError(err.to_string()))?;
+    let mut inputs = vec![];
+    let mut input_value = Amount::ZERO;
+    for (vout, output) in parent.output.iter().enumerate() {
+        let address = Address::from_script(&output.script_pubkey, creds.network);
+        if is_mine(&address, &rpc_client)? {
+            inputs.push(CreateRawTransactionInput {
+                txid: *txid,
+                vout: vout as u32,
+                sequence: None,
+            });
+            input_value = checked_add(input_value, Amount::from_sat(output.value))?;
+        }
+    }
+    if inputs.is_empty() {
+        return Err(Error::NothingToBump(*txid));
+    }
+
+    // A parent in the mempool has its fee there, its inputs are no longer in the UTXO set
+    let parent_fee = match rpc_client.get_mempool_entry(txid) {
+        Ok(entry) => entry.fees.base,
+        Err(_) => {
+            let mut spent = Amount::ZERO;
+            for input in &parent.input {
+                let tx_out = rpc_client
+                    .get_tx_out(
+                        &input.previous_output.txid,
+                        input.previous_output.vout,
+                        Some(true),
+                    )?
+                    .ok_or(Error::BadInput)?;
+                spent = checked_add(spent, tx_out.value)?;
+            }
+            let output_value = parent.output.iter().map(|output| output.value).sum();
+            checked_sub(spent, Amount::from_sat(output_value))?
+        }
+    };
+    let fee = cpfp_fee(
+        parent_fee,
+        (parent.weight() + 3) / 4,
+        inputs.len(),
+        fee_rate,
+    );
+    let value = checked_sub(input_value, fee)?;
+    if value.to_sat() <= DUST {
+        return Err(Error::InsufficientFunds);
+    }
+    debug!("Bumping {txid} with a child paying {fee} on top of the parent's {parent_fee}");
+
+    let address = rpc_client.get_new_address(None, None)?;
+    let outputs = HashMap::from([(address.to_string(), value)]);
+    let child = rpc_client.create_raw_transaction(&inputs, &outputs, None, Some(true))?;
+    let signed = {
+        let _unlock = unlock_wallet(&rpc_client, creds.wallet_passphrase.as_deref())?;
+        rpc_client.sign_raw_transaction_with_wallet(&child, None, None)?
+    };
+    if !signed.complete {
+        debug!("Signing the child of {txid} failed: {:?}", signed.errors);
+        return Err(Error::ChildNotSigned(*txid));
+    }
+    let child: Transaction =
+        deserialize(&signed.hex).map_err(|err| Error::DecodeError(err.to_string()))?;
+
+    submit_package(&rpc_client, &[parent, child.clone()])?;
+    Ok(child.txid())
+}
+
+/// Replacement of a CJ paying a higher fee
+#[derive(Debug, Clone)]
+pub enum Replacement {
+    /// Every input was the wallet's, the replacement was broadcast
+    Broadcast(Txid),
+    /// Signed for the wallet's inputs, makers sign it again as its outputs changed
+    Unsigned(PartiallySignedTransaction),
+}
+
+/// Replaces a CJ in the mempool with one paying `fee_rate` (RBF)
+/// The increase comes out of the wallet's largest change output only, other outputs get what was signed for
+pub fn replace_by_fee(
+    creds: &BitcoinCoreCredentials,
+    txid: &Txid,
+    fee_rate: f32,
+) -> Result<Replacement, Error> {
+    ensure_wallet(creds)?;
+    let rpc_client = RPCClient::new(
+        &format!("{}/wallet/{}", creds.rpc_url, creds.wallet_name),
+        Auth::UserPass(creds.rpc_username.clone(), creds.rpc_password.clone()),
+    )?;
+    check_network(&rpc_client, creds.network)?;
+
+    let replaced: Transaction = deserialize(&rpc_client.get_transaction(txid, None)?.hex)
+        .map_err(|err| Error::DecodeError(err.to_string()))?;
+    let replaced_fee = match rpc_client.get_mempool_entry(txid) {
+        Ok(entry) if replaced.is_explicitly_rbf() => entry.fees.base,
+        _ => return Err(Error::NotReplaceable(*txid)),
+    };
+
+    let mut change = None;
+    for (vout, output) in replaced.output.iter().enumerate() {
+        if let Some(address) = Address::from_script(&output.script_pubkey, creds.network) {
+            let info = rpc_client.get_address_info(&address)?;
+            let larger = change.map_or(true, |(_, value)| output.value > value);
+            if info.is_mine == Some(true) && info.is_change == Some(true) && larger {
+                change = Some((vout, output.value));
+            }
+        }
+    }
+    let (vout, change_value) = change.ok_or(Error::NothingToBump(*txid))?;
+
+    let fee = rbf_fee(
+        replaced_fee,
+        (replaced.weight() + 3) / 4,
+        fee_rate,
+        get_incremental_fee_rate(&rpc_client)?,
+    );
+    let change_value = checked_sub(
+        Amount::from_sat(change_value),
+        checked_sub(fee, replaced_fee)?,
+    )?;
+    if change_value.to_sat() <= DUST {
+        return Err(Error::InsufficientFunds);
+    }
+    debug!("Replacing {txid} paying {fee} rather than {replaced_fee}");
+
+    let mut unsigned = replaced;
+    unsigned.output[vout].value = change_value.to_sat();
+    for input in unsigned.input.iter_mut() {
+        input.script_sig = Script::new();
+        input.witness.clear();
+    }
+    let psbt = PartiallySignedTransaction::from_unsigned_tx(unsigned)
+        .map_err(|err| Error::DecodeError(err.to_string()))?;
+    let signed = sign_psbt(&psbt, &rpc_client, creds.wallet_passphrase.as_deref())?;
+
+    let finalized = rpc_client.finalize_psbt(&signed.to_string(), None)?;
+    match finalized.hex {
+        Some(hex) if finalized.complete => {
+            let tx: Transaction =
+                deserialize(&hex).map_err(|err| Error::DecodeError(err.to_string()))?;
+            Ok(Replacement::Broadcast(broadcast_tx(&rpc_client, &tx)?))
+        }
+        _ => Ok(Replacement::Unsigned(signed)),
+    }
+}
+
+/// Whether a `sendrawtransaction` error is for a tx the node already has
+fn is_already_broadcast(code: i32, message: &str) -> bool {
+    code == RPC_VERIFY_ALREADY_IN_CHAIN
+        || message.contains("txn-already-in-mempool")
+        || message.contains("txn-already-known")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_already_broadcast() {
+        assert!(is_already_broadcast(
+            -27,
+            "Transaction already in block chain"
+        ));
+        assert!(is_already_broadcast(-26, "txn-already-in-mempool"));
+        assert!(is_already_broadcast(-26, "txn-already-known"));
+        // A conflicting spend is a real failure
+        assert!(!is_already_broadcast(-26, "txn-mempool-conflict"));
+        assert!(!is_already_broadcast(-25, "bad-txns-inputs-missingorspent"));
+    }
+}
@@ -0,0 +1,140 @@
+use crate::{
+    errors::Error,
+    types::{RejectReason, USED_COMMITMENTS},
+    utils::{event_network, network_tag},
+};
+
+use bdk::bitcoin::Network;
+use bitcoin_hashes::sha256;
+use nostr_rust::{
+    events::EventPrepare, nostr_client::Client as NostrClient, req::ReqFilter,
+    utils::get_timestamp, Identity,
+};
+use serde::{Deserialize, Serialize};
+
+use std::collections::BTreeSet;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+/// Podle commitment fills are sent with, the hash of `P2`
+pub type Commitment = sha256::Hash;
+
+/// Podle commitments opened in a maker's rounds or gossiped by other makers
+/// A commitment is only good for one round, fills sent with one already used are turned down
+/// ```
+/// use bitcoin_hashes::{sha256, Hash};
+/// use nostrdizer::{blacklist::CommitmentBlacklist, types::RejectReason};
+///
+/// let mut blacklist = CommitmentBlacklist::default();
+/// let commit = sha256::Hash::hash(b"commitment");
+/// assert_eq!(blacklist.check(&commit), None);
+///
+/// assert!(blacklist.insert(commit));
+/// assert_eq!(blacklist.check(&commit), Some(RejectReason::CommitmentUsed));
+///
+/// // Gossip of commitments already known adds nothing
+/// let other = sha256::Hash::hash(b"other");
+/// assert_eq!(blacklist.merge([commit, other]), 1);
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct CommitmentBlacklist {
+    pub commitments: BTreeSet<Commitment>,
+    /// Time gossip was last fetched up to
+    #[serde(default)]
+    pub synced_at: u64,
+}
+
+impl CommitmentBlacklist {
+    /// Loads the blacklist from path, an empty one if there is no file
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        match path.exists() {
+            true => Ok(serde_json::from_str(&fs::read_to_string(path)?)?),
+            false => Ok(Self::default()),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        file.write_all(serde_json::to_string_pretty(self)?.as_bytes())?;
+        Ok(())
+    }
+
+    /// Why a fill sent with commit is turned down, none if it was not used before
+    pub fn check(&self, commit: &Commitment) -> Option<RejectReason> {
+        match self.commitments.contains(commit) {
+            true => Some(RejectReason::CommitmentUsed),
+            false => None,
+        }
+    }
+
+    /// Burns commit, returns false if it already was
+    pub fn insert(&mut self, commit: Commitment) -> bool {
+        self.commitments.insert(commit)
+    }
+
+    /// Adds gossiped commitments, returns how many were not known
+    pub fn merge(&mut self, commitments: impl IntoIterator<Item = Commitment>) -> usize {
+        commitments
+            .into_iter()
+            .filter(|commit| self.commitments.insert(*commit))
+            .count()
+    }
+}
+
+/// Commitments a maker gossips once they are opened in its rounds
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct UsedCommitments {
+    pub commitments: Vec<Commitment>,
+}
+
+/// Publishes commitments opened in the identity's rounds for other makers to burn
+/// Returns the id of the event
+pub fn publish_used(
+    nostr_client: &mut NostrClient,
+    identity: &Identity,
+    network: Network,
+    commitments: Vec<Commitment>,
+) -> Result<String, Error> {
+    let event = EventPrepare {
+        pub_key: identity.public_key_str.clone(),
+        created_at: get_timestamp(),
+        kind: USED_COMMITMENTS,
+        tags: vec![network_tag(network)],
+        content: serde_json::to_string(&UsedCommitments { commitments })?,
+    }
+    .to_event(identity, 0);
+    nostr_client.publish_event(&event)?;
+
+    Ok(event.id)
+}
+
+/// Commitments makers on network gossiped since a time, events that do not parse are skipped
+pub fn fetch_used(
+    nostr_client: &mut NostrClient,
+    network: Network,
+    since: u64,
+) -> Result<Vec<Commitment>, Error> {
+    let filter = ReqFilter {
+        ids: None,
+        authors: None,
+        kinds: Some(vec![USED_COMMITMENTS]),
+        e: None,
+        p: None,
+        since: Some(since),
+        until: None,
+        limit: None,
+    };
+
+    Ok(nostr_client
+        .get_events_of(vec![filter])?
+        .into_iter()
+        .filter(|event| event.verify().is_ok() && event_network(event) == Some(network))
+        .filter_map(|event| serde_json::from_str::<UsedCommitments>(&event.content).ok())
+        .flat_map(|used| used.commitments)
+        .collect())
+}
@@ -0,0 +1,67 @@
+use crate::{
+    relay_auth::CLIENT_AUTH,
+    types::{
+        ABORT, ABS_OFFER, AUTH, BACKUP, CONFIRM, COOP_JOIN, FILL, FILL_ACK, IOAUTH, NETWORK_STATS,
+        PRESENCE, PUBKEY, REJECT, REL_OFFER, SEALED, SIGNED_TRANSACTION, TRANSACTION,
+        USED_COMMITMENTS,
+    },
+};
+
+/// Wallet backend the library was built with
+#[cfg(feature = "bitcoincore")]
+pub const BACKEND: &str = "bitcoincore";
+#[cfg(feature = "bdk")]
+pub const BACKEND: &str = "bdk";
+
+/// Nostr event kinds the library publishes and reads, by message
+pub const EVENT_KINDS: [(&str, u16); 20] = [
+    ("absolute offer", ABS_OFFER),
+    ("relative offer", REL_OFFER),
+    ("presence", PRESENCE),
+    ("backup", BACKUP),
+    ("fill", FILL),
+    ("pubkey", PUBKEY),
+    ("auth", AUTH),
+    ("ioauth", IOAUTH),
+    ("transaction", TRANSACTION),
+    ("signed transaction", SIGNED_TRANSACTION),
+    ("reject", REJECT),
+    ("fill ack", FILL_ACK),
+    ("confirm", CONFIRM),
+    ("abort", ABORT),
+    ("coop join", COOP_JOIN),
+    ("used commitments", USED_COMMITMENTS),
+    ("network stats", NETWORK_STATS),
+    ("sealed", SEALED),
+    ("relay auth", CLIENT_AUTH),
+    ("deletion", 5),
+];
+
+/// NIPs the protocol relies on
+pub const NIPS: [(u16, &str); 5] = [
+    (4, "encrypted messages"),
+    (9, "event deletion"),
+    (16, "replaceable events"),
+    (40, "expiration"),
+    (42, "relay auth"),
+];
+
+/// Optional features the library was built with
+/// ```
+/// use nostrdizer::build_info::{features, BACKEND};
+///
+/// assert!(features().contains(&BACKEND));
+/// ```
+pub fn features() -> Vec<&'static str> {
+    let features = [
+        ("bitcoincore", cfg!(feature = "bitcoincore")),
+        ("bdk", cfg!(feature = "bdk")),
+        ("fiat", cfg!(feature = "fiat")),
+        ("test-utils", cfg!(feature = "test-utils")),
+    ];
+    features
+        .into_iter()
+        .filter(|(_, enabled)| *enabled)
+        .map(|(feature, _)| feature)
+        .collect()
+}
@@ -0,0 +1,336 @@
+use crate::{
+    address_store::AddressStore,
+    errors::Error,
+    keystore::Keystore,
+    maker::Maker,
+    reputation::Reputation,
+    taker::Taker,
+    types::{BlockchainConfig, MakerConfig, TakerConfig},
+};
+
+#[cfg(feature = "bdk")]
+use crate::types::DescriptorType;
+
+use nostr_rust::{keys::get_random_secret_key, Identity};
+
+use std::str::FromStr;
+
+/// Nostr identity of the key, a new random one when none is given
+pub(crate) fn identity(priv_key: Option<&str>) -> Result<Identity, Error> {
+    match priv_key {
+        Some(key) => Ok(Identity::from_str(key)?),
+        None => {
+            let (sk, _) = get_random_secret_key();
+            Ok(Identity::from_str(&hex::encode(sk.as_ref()))?)
+        }
+    }
+}
+
+/// Builds a taker, the relays and blockchain config are required and the rest defaulted
+/// ```no_run
+/// use nostrdizer::{
+///     builder::TakerBuilder,
+///     types::{BlockchainConfig, TakerConfig},
+/// };
+/// # fn blockchain_config() -> BlockchainConfig { unimplemented!() }
+///
+/// let taker = TakerBuilder::new()
+///     .relays(["ws://localhost:7000"])
+///     .blockchain(blockchain_config())
+///     .config(TakerConfig {
+///         minium_makers: 2,
+///         ..TakerConfig::default()
+///     })
+///     .build()
+///     .unwrap();
+/// ```
+#[derive(Default)]
+pub struct TakerBuilder {
+    priv_key: Option<String>,
+    relays: Vec<String>,
+    blockchain_config: Option<BlockchainConfig>,
+    config: Option<TakerConfig>,
+    address_store: Option<AddressStore>,
+    reputation: Option<Reputation>,
+    keystore: Option<Keystore>,
+}
+
+impl TakerBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Nostr key, a random one is used when not set
+    pub fn priv_key(mut self, priv_key: impl Into<String>) -> Self {
+        self.priv_key = Some(priv_key.into());
+        self
+    }
+
+    pub fn relays<S: AsRef<str>>(mut self, relays: impl IntoIterator<Item = S>) -> Self {
+        self.relays = relays.into_iter().map(|r| r.as_ref().to_string()).collect();
+        self
+    }
+
+    /// Wallet backend the taker's inputs come from
+    pub fn blockchain(mut self, blockchain_config: BlockchainConfig) -> Self {
+        self.blockchain_config = Some(blockchain_config);
+        self
+    }
+
+    pub fn config(mut self, config: TakerConfig) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    pub fn address_store(mut self, address_store: AddressStore) -> Self {
+        self.address_store = Some(address_store);
+        self
+    }
+
+    pub fn reputation(mut self, reputation: Reputation) -> Self {
+        self.reputation = Some(reputation);
+        self
+    }
+
+    pub fn keystore(mut self, keystore: Keystore) -> Self {
+        self.keystore = Some(keystore);
+        self
+    }
+
+    /// Checks the components go together before anything is connected to
+    fn validate(&self) -> Result<(), Error> {
+        if self.relays.is_empty() {
+            return Err(Error::MissingComponent("relays"));
+        }
+        if self.blockchain_config.is_none() {
+            return Err(Error::MissingComponent("blockchain config"));
+        }
+        if let Some(config) = &self.config {
+            if config.minium_makers == 0 {
+                return Err(Error::InvalidConfig(
+                    "a round needs at least one maker".to_string(),
+                ));
+            }
+            // Maker CJ outputs are checked to be p2wpkh when building with bdk
+            #[cfg(feature = "bdk")]
+            if config.script_type.unwrap_or(DescriptorType::Wpkh) != DescriptorType::Wpkh {
+                return Err(Error::InvalidConfig(
+                    "the bdk wallet only takes part in wpkh rounds".to_string(),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Connects to the relays and wallet
+    pub fn build(self) -> Result<Taker, Error> {
+        self.validate()?;
+        let identity = identity(self.priv_key.as_deref())?;
+        let relays = self.relays.iter().map(String::as_str).collect();
+        let blockchain_config = self
+            .blockchain_config
+            .ok_or(Error::MissingComponent("blockchain config"))?;
+        let mut taker = Taker::connect(
+            identity,
+            relays,
+            blockchain_config,
+            self.config.unwrap_or_default(),
+        )?;
+        if let Some(address_store) = self.address_store {
+            taker.address_store = address_store;
+        }
+        if let Some(reputation) = self.reputation {
+            taker.reputation = reputation;
+        }
+        if let Some(keystore) = self.keystore {
+            taker.keystore = keystore;
+        }
+        Ok(taker)
+    }
+}
+
+impl Taker {
+    /// Taker with a random key when none is given and the default config
+    pub fn new(
+        priv_key: Option<String>,
+        relay_urls: Vec<&str>,
+        blockchain_config: BlockchainConfig,
+    ) -> Result<Self, Error> {
+        let mut builder = TakerBuilder::new()
+            .relays(relay_urls)
+            .blockchain(blockchain_config);
+        if let Some(priv_key) = priv_key {
+            builder = builder.priv_key(priv_key);
+        }
+        builder.build()
+    }
+}
+
+/// Builds a maker, the relays, blockchain config and maker config are required
+/// Offers go to the session relays when no offer relays are set
+#[derive(Default)]
+pub struct MakerBuilder {
+    priv_key: Option<String>,
+    relays: Vec<String>,
+    offer_relays: Vec<String>,
+    blockchain_config: Option<BlockchainConfig>,
+    config: Option<MakerConfig>,
+}
+
+impl MakerBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Nostr key, a random one is used when not set
+    pub fn priv_key(mut self, priv_key: impl Into<String>) -> Self {
+        self.priv_key = Some(priv_key.into());
+        self
+    }
+
+    /// Relays sessions with takers are held on
+    pub fn relays<S: AsRef<str>>(mut self, relays: impl IntoIterator<Item = S>) -> Self {
+        self.relays = relays.into_iter().map(|r| r.as_ref().to_string()).collect();
+        self
+    }
+
+    /// Relays offers are published to
+    pub fn offer_relays<S: AsRef<str>>(mut self, relays: impl IntoIterator<Item = S>) -> Self {
+        self.offer_relays = relays.into_iter().map(|r| r.as_ref().to_string()).collect();
+        self
+    }
+
+    /// Wallet backend the maker's inputs come from
+    pub fn blockchain(mut self, blockchain_config: BlockchainConfig) -> Self {
+        self.blockchain_config = Some(blockchain_config);
+        self
+    }
+
+    /// Max size is set to the eligible balance when the config has none
+    pub fn config(mut self, config: MakerConfig) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    /// Checks the components go together before anything is connected to
+    fn validate(&self) -> Result<(), Error> {
+        if self.relays.is_empty() {
+            return Err(Error::MissingComponent("relays"));
+        }
+        if self.blockchain_config.is_none() {
+            return Err(Error::MissingComponent("blockchain config"));
+        }
+        let config = self
+            .config
+            .as_ref()
+            .ok_or(Error::MissingComponent("maker config"))?;
+        if config.rel_fee < 0.0 {
+            return Err(Error::InvalidConfig(
+                "relative fee can not be negative".to_string(),
+            ));
+        }
+        if let Some(maxsize) = config.maxsize {
+            if config.minsize > maxsize {
+                return Err(Error::InvalidConfig(format!(
+                    "min size {} is over the max size {}",
+                    config.minsize, maxsize
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Connects to the relays and wallet
+    pub fn build(self) -> Result<Maker, Error> {
+        self.validate()?;
+        let identity = identity(self.priv_key.as_deref())?;
+        let relays: Vec<&str> = self.relays.iter().map(String::as_str).collect();
+        let offer_relays = match self.offer_relays.is_empty() {
+            true => relays.clone(),
+            false => self.offer_relays.iter().map(String::as_str).collect(),
+        };
+        let blockchain_config = self
+            .blockchain_config
+            .ok_or(Error::MissingComponent("blockchain config"))?;
+        let config = self.config.ok_or(Error::MissingComponent("maker config"))?;
+        Maker::connect(identity, relays, offer_relays, config, blockchain_config)
+    }
+}
+
+impl Maker {
+    /// Maker with a random key when none is given, the max size found is written back to config
+    pub fn new(
+        priv_key: Option<String>,
+        relay_urls: Vec<&str>,
+        offer_relay_urls: Vec<&str>,
+        config: &mut MakerConfig,
+        blockchain_config: BlockchainConfig,
+    ) -> Result<Self, Error> {
+        let mut builder = MakerBuilder::new()
+            .relays(relay_urls)
+            .offer_relays(offer_relay_urls)
+            .blockchain(blockchain_config)
+            .config(config.clone());
+        if let Some(priv_key) = priv_key {
+            builder = builder.priv_key(priv_key);
+        }
+        let maker = builder.build()?;
+        *config = maker.config.clone();
+        Ok(maker)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_utils::maker_config,
+        types::{Amount, Network, RpcInfo},
+    };
+
+    fn blockchain_config() -> BlockchainConfig {
+        BlockchainConfig::RPC(RpcInfo {
+            url: "http://localhost:18443".to_string(),
+            username: String::new(),
+            password: String::new(),
+            network: Network::Regtest,
+            wallet_name: "unused".to_string(),
+        })
+    }
+
+    #[test]
+    fn test_invalid_combinations_rejected() {
+        assert!(matches!(
+            TakerBuilder::new().blockchain(blockchain_config()).build(),
+            Err(Error::MissingComponent("relays"))
+        ));
+        assert!(matches!(
+            TakerBuilder::new()
+                .relays(["ws://localhost:7000"])
+                .blockchain(blockchain_config())
+                .config(TakerConfig {
+                    minium_makers: 0,
+                    ..TakerConfig::default()
+                })
+                .build(),
+            Err(Error::InvalidConfig(_))
+        ));
+
+        let maker = || {
+            MakerBuilder::new()
+                .relays(["ws://localhost:7000"])
+                .blockchain(blockchain_config())
+        };
+        assert!(matches!(
+            maker().build(),
+            Err(Error::MissingComponent("maker config"))
+        ));
+        let mut config = maker_config();
+        config.minsize = Amount::from_sat(10_000);
+        config.maxsize = Some(Amount::from_sat(5_000));
+        assert!(matches!(
+            maker().config(config).build(),
+            Err(Error::InvalidConfig(_))
+        ));
+    }
+}
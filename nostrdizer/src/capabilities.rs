@@ -0,0 +1,112 @@
+use crate::{errors::Error, types::DescriptorType};
+
+use serde::Serialize;
+
+/// What the wallet backend can do, strategies are picked by it rather than by the backend built with
+/// ```
+/// use nostrdizer::capabilities::{Capabilities, PodleKeySource, PsbtCombine};
+/// use nostrdizer::types::DescriptorType;
+///
+/// // A legacy bitcoin core wallet
+/// let legacy = Capabilities {
+///     dumpprivkey: true,
+///     psbt_join: true,
+///     taproot: false,
+///     lockunspent: true,
+///     descriptor_wallet: false,
+/// };
+/// assert_eq!(legacy.podle_key_source(), PodleKeySource::DumpPrivKey);
+/// assert_eq!(legacy.psbt_combine(), PsbtCombine::Node);
+/// assert!(legacy.check_script_type(Some(DescriptorType::Wpkh)).is_ok());
+/// assert!(legacy.check_script_type(Some(DescriptorType::Tr)).is_err());
+///
+/// let bdk = Capabilities::bdk();
+/// assert_eq!(bdk.podle_key_source(), PodleKeySource::Descriptor);
+/// assert_eq!(bdk.psbt_combine(), PsbtCombine::InProcess);
+/// assert!(bdk.check_script_type(Some(DescriptorType::Tr)).is_ok());
+/// ```
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Capabilities {
+    /// Private keys of wallet addresses can be dumped, legacy bitcoin core wallets only
+    pub dumpprivkey: bool,
+    /// Node joins psbts with `joinpsbts`, bitcoin core 0.18 and later
+    pub psbt_join: bool,
+    /// Wallet can spend and receive taproot outputs
+    pub taproot: bool,
+    /// Wallet utxos can be locked so other spends leave them alone
+    pub lockunspent: bool,
+    /// Wallet keeps its keys as output descriptors
+    pub descriptor_wallet: bool,
+}
+
+/// Where the private key of the utxo a podle is generated over comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PodleKeySource {
+    /// Dumped by the node for the utxo's address
+    DumpPrivKey,
+    /// Derived from the wallet's descriptor key at the utxo's key origin
+    Descriptor,
+}
+
+/// How the psbts makers signed are combined into one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PsbtCombine {
+    /// By the node
+    Node,
+    /// By the library
+    InProcess,
+}
+
+/// Bitcoin core version `joinpsbts` was added in
+const PSBT_JOIN_VERSION: usize = 180000;
+/// Bitcoin core version descriptor wallets can have taproot descriptors from
+const TAPROOT_VERSION: usize = 220000;
+
+impl Capabilities {
+    /// Capabilities of a bitcoin core wallet on a node of version, as `getnetworkinfo` gives it
+    pub fn bitcoin_core(version: usize, descriptor_wallet: bool) -> Self {
+        Self {
+            // Descriptor wallets do not have `dumpprivkey`
+            dumpprivkey: !descriptor_wallet,
+            psbt_join: version >= PSBT_JOIN_VERSION,
+            taproot: descriptor_wallet && version >= TAPROOT_VERSION,
+            lockunspent: true,
+            descriptor_wallet,
+        }
+    }
+
+    /// Capabilities of a bdk wallet
+    pub fn bdk() -> Self {
+        Self {
+            dumpprivkey: false,
+            psbt_join: false,
+            taproot: true,
+            lockunspent: false,
+            descriptor_wallet: true,
+        }
+    }
+
+    pub fn podle_key_source(&self) -> PodleKeySource {
+        match self.dumpprivkey {
+            true => PodleKeySource::DumpPrivKey,
+            false => PodleKeySource::Descriptor,
+        }
+    }
+
+    pub fn psbt_combine(&self) -> PsbtCombine {
+        match self.psbt_join {
+            true => PsbtCombine::Node,
+            false => PsbtCombine::InProcess,
+        }
+    }
+
+    /// Errors when the wallet can not spend and receive outputs of the round's script type
+    pub fn check_script_type(&self, script_type: Option<DescriptorType>) -> Result<(), Error> {
+        match script_type {
+            Some(DescriptorType::Tr) if !self.taproot => {
+                Err(Error::UnsupportedScriptType(DescriptorType::Tr))
+            }
+            _ => Ok(()),
+        }
+    }
+}
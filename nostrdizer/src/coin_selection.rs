@@ -0,0 +1,582 @@
+use crate::{
+    errors::Error,
+    types::{Amount, DUST},
+};
+
+use bdk::bitcoin::{Script, Txid};
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// Max branches tried before settling for the best selection found
+const MAX_TRIES: usize = 100_000;
+
+/// Picks the utxos, by index into `values`, that cover `target` with the least change
+/// Change above `max_change_ratio` of the target is refused as it fingerprints the maker
+/// No more than `max_inputs` utxos are picked, when the taker limits them
+/// ```
+/// use nostrdizer::{coin_selection::select_inputs, errors::Error, types::Amount};
+///
+/// let values: Vec<Amount> = [900_000, 60_000, 41_000, 10_000]
+///     .into_iter()
+///     .map(Amount::from_sat)
+///     .collect();
+///
+/// // Spending the 900k utxo would leave 800k of change
+/// let mut selected = select_inputs(&values, Amount::from_sat(100_000), None, None).unwrap();
+/// selected.sort();
+/// assert_eq!(selected, vec![1, 2]);
+///
+/// assert!(matches!(
+///     select_inputs(&values, Amount::from_sat(500_000), Some(0.5), None),
+///     Err(Error::ChangeTooLarge(_))
+/// ));
+///
+/// // Taker takes one input
+/// assert_eq!(
+///     select_inputs(&values, Amount::from_sat(100_000), None, Some(1)).unwrap(),
+///     vec![0]
+/// );
+/// ```
+pub fn select_inputs(
+    values: &[Amount],
+    target: Amount,
+    max_change_ratio: Option<f64>,
+    max_inputs: Option<usize>,
+) -> Result<Vec<usize>, Error> {
+    let (change, selected) =
+        branch_and_bound(values, target, DUST, max_inputs).ok_or(Error::InsufficientFunds)?;
+    check_change(change, target, max_change_ratio)?;
+    Ok(selected)
+}
+
+/// Picks the utxos, by index into `candidates`, that cover `target` with the selection
+/// Change above `max_change_ratio` of the target is refused as it fingerprints the maker
+/// ```
+/// use nostrdizer::{
+///     coin_selection::{select_with, Candidate, SelectionStrategy},
+///     types::Amount,
+/// };
+/// use bitcoin::{hashes::Hash, Script, Txid};
+///
+/// let candidate = |sats, script: u8, tx: u8| Candidate {
+///     value: Amount::from_sat(sats),
+///     script_pubkey: Script::from(vec![script]),
+///     txid: Txid::from_slice(&[tx; 32]).unwrap(),
+/// };
+/// // The first two were paid to the same address
+/// let candidates = [
+///     candidate(60_000, 1, 1),
+///     candidate(50_000, 1, 2),
+///     candidate(110_000, 2, 3),
+/// ];
+///
+/// let selection = SelectionStrategy::LargestFirst.selection();
+/// let selected = select_with(selection.as_ref(), &candidates, Amount::from_sat(50_000), None, None);
+/// assert_eq!(selected.unwrap(), vec![2]);
+///
+/// // Both utxos of the reused address are spent together
+/// let selection = SelectionStrategy::AvoidLinking.selection();
+/// let mut selected =
+///     select_with(selection.as_ref(), &candidates, Amount::from_sat(100_000), None, None).unwrap();
+/// selected.sort();
+/// assert_eq!(selected, vec![0, 1]);
+/// ```
+pub fn select_with(
+    selection: &dyn CoinSelection,
+    candidates: &[Candidate],
+    target: Amount,
+    max_change_ratio: Option<f64>,
+    max_inputs: Option<usize>,
+) -> Result<Vec<usize>, Error> {
+    let selected = selection
+        .select(candidates, target, max_inputs)
+        .ok_or(Error::InsufficientFunds)?;
+    let value: u64 = selected.iter().map(|i| candidates[*i].value.to_sat()).sum();
+    check_change(
+        Amount::from_sat(value - target.to_sat()),
+        target,
+        max_change_ratio,
+    )?;
+    Ok(selected)
+}
+
+fn check_change(
+    change: Amount,
+    target: Amount,
+    max_change_ratio: Option<f64>,
+) -> Result<(), Error> {
+    match max_change_ratio {
+        Some(max_change_ratio)
+            if change.to_sat() as f64 > target.to_sat() as f64 * max_change_ratio =>
+        {
+            Err(Error::ChangeTooLarge(change))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Utxo of the maker's wallet coin selection picks from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Candidate {
+    pub value: Amount,
+    pub script_pubkey: Script,
+    /// Transaction the utxo is an output of
+    pub txid: Txid,
+}
+
+/// How a maker picks the utxos it adds to a CJ
+pub trait CoinSelection {
+    /// Utxos, by index into `candidates`, worth at least `target`, none if they are not worth enough
+    /// No more than `max_inputs` utxos are picked, when the taker limits them
+    fn select(
+        &self,
+        candidates: &[Candidate],
+        target: Amount,
+        max_inputs: Option<usize>,
+    ) -> Option<Vec<usize>>;
+}
+
+/// Coin selections makers can configure, least change is the default
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SelectionStrategy {
+    /// Utxos that leave the least change
+    #[default]
+    LeastChange,
+    /// Largest utxos first, so few utxos are spent and the rest of the wallet is not shown
+    LargestFirst,
+    /// Utxos in random order, so repeated CJs do not show the wallet in a predictable order
+    Random,
+    /// Utxos of one cluster, with every utxo of an address spent together, so CJs do not link
+    /// addresses and earlier transactions of the wallet that are not linked yet
+    AvoidLinking,
+}
+
+impl SelectionStrategy {
+    pub fn selection(&self) -> Box<dyn CoinSelection> {
+        match self {
+            SelectionStrategy::LeastChange => Box::new(LeastChange),
+            SelectionStrategy::LargestFirst => Box::new(LargestFirst),
+            SelectionStrategy::Random => Box::new(RandomOrder),
+            SelectionStrategy::AvoidLinking => Box::new(AvoidLinking),
+        }
+    }
+}
+
+impl FromStr for SelectionStrategy {
+    type Err = Error;
+
+    fn from_str(strategy: &str) -> Result<Self, Self::Err> {
+        match strategy {
+            "least-change" => Ok(Self::LeastChange),
+            "largest-first" => Ok(Self::LargestFirst),
+            "random" => Ok(Self::Random),
+            "avoid-linking" => Ok(Self::AvoidLinking),
+            _ => Err(Error::UnknownCoinSelection(strategy.to_string())),
+        }
+    }
+}
+
+/// Searches for the utxos that leave the least change
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LeastChange;
+
+impl CoinSelection for LeastChange {
+    fn select(
+        &self,
+        candidates: &[Candidate],
+        target: Amount,
+        max_inputs: Option<usize>,
+    ) -> Option<Vec<usize>> {
+        let values: Vec<Amount> = candidates.iter().map(|c| c.value).collect();
+        branch_and_bound(&values, target, DUST, max_inputs).map(|(_, selected)| selected)
+    }
+}
+
+/// Adds the largest utxos until the target is covered
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LargestFirst;
+
+impl CoinSelection for LargestFirst {
+    fn select(
+        &self,
+        candidates: &[Candidate],
+        target: Amount,
+        max_inputs: Option<usize>,
+    ) -> Option<Vec<usize>> {
+        let mut order: Vec<usize> = (0..candidates.len()).collect();
+        order.sort_by(|a, b| candidates[*b].value.cmp(&candidates[*a].value));
+        accumulate(candidates, order, target, max_inputs)
+    }
+}
+
+/// Adds utxos in random order until the target is covered
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RandomOrder;
+
+impl CoinSelection for RandomOrder {
+    fn select(
+        &self,
+        candidates: &[Candidate],
+        target: Amount,
+        max_inputs: Option<usize>,
+    ) -> Option<Vec<usize>> {
+        let mut order: Vec<usize> = (0..candidates.len()).collect();
+        order.shuffle(&mut rand::thread_rng());
+        accumulate(candidates, order, target, max_inputs)
+    }
+}
+
+/// Spends the utxos of an address together and keeps to one cluster, utxos already linked by an address or
+/// by the transaction that created them, when one is worth enough
+/// Falls back to the least change over every address when no cluster is
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AvoidLinking;
+
+impl CoinSelection for AvoidLinking {
+    fn select(
+        &self,
+        candidates: &[Candidate],
+        target: Amount,
+        max_inputs: Option<usize>,
+    ) -> Option<Vec<usize>> {
+        // Utxos of an address are picked as one
+        let mut addresses: Vec<Vec<usize>> = vec![];
+        let mut address_of: HashMap<&Script, usize> = HashMap::new();
+        for (i, candidate) in candidates.iter().enumerate() {
+            match address_of.get(&candidate.script_pubkey) {
+                Some(address) => addresses[*address].push(i),
+                None => {
+                    address_of.insert(&candidate.script_pubkey, addresses.len());
+                    addresses.push(vec![i]);
+                }
+            }
+        }
+
+        // Addresses paid in the same transaction are in the same cluster
+        let mut cluster: Vec<usize> = (0..addresses.len()).collect();
+        let mut cluster_of_tx: HashMap<Txid, usize> = HashMap::new();
+        for (address, utxos) in addresses.iter().enumerate() {
+            for i in utxos {
+                match cluster_of_tx.get(&candidates[*i].txid) {
+                    Some(other) => {
+                        let (from, to) = (root(&cluster, address), root(&cluster, *other));
+                        cluster[from] = to;
+                    }
+                    None => {
+                        cluster_of_tx.insert(candidates[*i].txid, address);
+                    }
+                }
+            }
+        }
+
+        let value = |address: &Vec<usize>| {
+            Amount::from_sat(address.iter().map(|i| candidates[*i].value.to_sat()).sum())
+        };
+        let pick = |members: &[usize]| -> Option<(Amount, Vec<usize>)> {
+            let values: Vec<Amount> = members.iter().map(|a| value(&addresses[*a])).collect();
+            let (change, picked) = branch_and_bound(&values, target, DUST, None)?;
+            let selected: Vec<usize> = picked
+                .into_iter()
+                .flat_map(|i| addresses[members[i]].iter().copied())
+                .collect();
+            match max_inputs {
+                Some(max_inputs) if selected.len() > max_inputs => None,
+                _ => Some((change, selected)),
+            }
+        };
+
+        let mut clusters: HashMap<usize, Vec<usize>> = HashMap::new();
+        for address in 0..addresses.len() {
+            clusters
+                .entry(root(&cluster, address))
+                .or_default()
+                .push(address);
+        }
+        let mut clusters: Vec<Vec<usize>> = clusters.into_values().collect();
+        clusters.sort();
+        clusters
+            .iter()
+            .filter_map(|members| pick(members))
+            .min_by_key(|(change, _)| *change)
+            .or_else(|| pick(&(0..addresses.len()).collect::<Vec<usize>>()))
+            .map(|(_, selected)| selected)
+    }
+}
+
+/// Cluster an address was merged into
+fn root(cluster: &[usize], mut address: usize) -> usize {
+    while cluster[address] != address {
+        address = cluster[address];
+    }
+    address
+}
+
+/// Adds utxos in order until the target is covered
+fn accumulate(
+    candidates: &[Candidate],
+    order: Vec<usize>,
+    target: Amount,
+    max_inputs: Option<usize>,
+) -> Option<Vec<usize>> {
+    let mut total = Amount::ZERO;
+    let mut selected = vec![];
+    for i in order.into_iter().take(max_inputs.unwrap_or(usize::MAX)) {
+        if total >= target {
+            break;
+        }
+        total += candidates[i].value;
+        selected.push(i);
+    }
+    (total >= target).then_some(selected)
+}
+
+/// Picks the utxos, by index into `values`, worth `target` to within dust, none if there are none
+/// A selection that needs no change leaves no change output to link the maker's utxos to
+/// No more than `max_inputs` utxos are picked, when the taker limits them
+/// ```
+/// use nostrdizer::{coin_selection::select_exact, types::Amount};
+///
+/// let values: Vec<Amount> = [900_000, 60_000, 40_300, 10_000]
+///     .into_iter()
+///     .map(Amount::from_sat)
+///     .collect();
+///
+/// let mut selected = select_exact(&values, Amount::from_sat(100_000), None).unwrap();
+/// selected.sort();
+/// assert_eq!(selected, vec![1, 2]);
+///
+/// assert!(select_exact(&values, Amount::from_sat(150_000), None).is_none());
+/// assert!(select_exact(&values, Amount::from_sat(100_000), Some(1)).is_none());
+/// ```
+pub fn select_exact(
+    values: &[Amount],
+    target: Amount,
+    max_inputs: Option<usize>,
+) -> Option<Vec<usize>> {
+    match branch_and_bound(values, target, DUST, max_inputs)? {
+        (change, selected) if change.to_sat() <= DUST => Some(selected),
+        _ => None,
+    }
+}
+
+/// Whether the selected utxos, by index into `values`, leave change over `target` that is more than dust
+/// but under `below`, too little to be worth tracking in a change output
+/// ```
+/// use nostrdizer::{coin_selection::donates_change, types::Amount};
+///
+/// let values = vec![Amount::from_sat(102_000), Amount::from_sat(110_000)];
+/// let target = Amount::from_sat(100_000);
+/// let below = Amount::from_sat(5_000);
+///
+/// assert!(donates_change(&values, &[0], target, below));
+/// assert!(!donates_change(&values, &[1], target, below));
+/// ```
+pub fn donates_change(
+    values: &[Amount],
+    selected: &[usize],
+    target: Amount,
+    below: Amount,
+) -> bool {
+    let value: u64 = selected.iter().map(|i| values[*i].to_sat()).sum();
+    let change = value.saturating_sub(target.to_sat());
+    change > DUST && change < below.to_sat()
+}
+
+/// Checks the selected utxos, by index into `values`, add up to no more than `max_per_round`
+/// The least change selection spends the least value, so no other selection would fit under it
+/// ```
+/// use nostrdizer::{coin_selection::check_round_cap, errors::Error, types::Amount};
+///
+/// let values = vec![Amount::from_sat(60_000), Amount::from_sat(50_000)];
+///
+/// assert!(check_round_cap(&values, &[0, 1], Some(Amount::from_sat(110_000))).is_ok());
+/// assert!(matches!(
+///     check_round_cap(&values, &[0, 1], Some(Amount::from_sat(100_000))),
+///     Err(Error::OverRoundCap(_))
+/// ));
+/// ```
+pub fn check_round_cap(
+    values: &[Amount],
+    selected: &[usize],
+    max_per_round: Option<Amount>,
+) -> Result<(), Error> {
+    let total = Amount::from_sat(selected.iter().map(|i| values[*i].to_sat()).sum());
+    match max_per_round {
+        Some(max_per_round) if total > max_per_round => Err(Error::OverRoundCap(total)),
+        _ => Ok(()),
+    }
+}
+
+/// Searches for the selection of at most `max_inputs` utxos with the least overshoot of `target`
+/// Stops early on a selection within `tolerance` of the target
+fn branch_and_bound(
+    values: &[Amount],
+    target: Amount,
+    tolerance: u64,
+    max_inputs: Option<usize>,
+) -> Option<(Amount, Vec<usize>)> {
+    let mut order: Vec<usize> = (0..values.len()).collect();
+    // Largest first so the first branch is a small selection
+    order.sort_by(|a, b| values[*b].cmp(&values[*a]));
+
+    // Value left after each position, to prune branches that can not reach the target
+    let mut remaining = vec![0; order.len() + 1];
+    for i in (0..order.len()).rev() {
+        remaining[i] = remaining[i + 1] + values[order[i]].to_sat();
+    }
+
+    let mut search = Search {
+        values: order.iter().map(|i| values[*i].to_sat()).collect(),
+        remaining,
+        target: target.to_sat(),
+        tolerance,
+        max_inputs: max_inputs.unwrap_or(usize::MAX),
+        tries: MAX_TRIES,
+        selected: vec![],
+        best: None,
+    };
+    search.next(0, 0);
+
+    search.best.map(|(change, selected)| {
+        (
+            Amount::from_sat(change),
+            selected.into_iter().map(|i| order[i]).collect(),
+        )
+    })
+}
+
+struct Search {
+    values: Vec<u64>,
+    remaining: Vec<u64>,
+    target: u64,
+    tolerance: u64,
+    max_inputs: usize,
+    tries: usize,
+    selected: Vec<usize>,
+    best: Option<(u64, Vec<usize>)>,
+}
+
+impl Search {
+    /// Returns true once a selection within tolerance is found
+    fn next(&mut self, index: usize, total: u64) -> bool {
+        if total >= self.target {
+            let change = total - self.target;
+            if self.best.as_ref().map_or(true, |(best, _)| change < *best) {
+                self.best = Some((change, self.selected.clone()));
+            }
+            return change <= self.tolerance;
+        }
+        if index == self.values.len()
+            || total + self.remaining[index] < self.target
+            || self.selected.len() == self.max_inputs
+        {
+            return false;
+        }
+        if self.tries == 0 {
+            return false;
+        }
+        self.tries -= 1;
+
+        self.selected.push(index);
+        if self.next(index + 1, total + self.values[index]) {
+            return true;
+        }
+        self.selected.pop();
+        self.next(index + 1, total)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn amounts(sats: &[u64]) -> Vec<Amount> {
+        sats.iter().map(|sats| Amount::from_sat(*sats)).collect()
+    }
+
+    #[test]
+    fn test_least_change() {
+        let values = amounts(&[70_000, 50_000, 30_000, 25_000]);
+        let (change, mut selected) =
+            branch_and_bound(&values, Amount::from_sat(105_000), 0, None).unwrap();
+        selected.sort();
+        assert_eq!(change, Amount::ZERO);
+        assert_eq!(selected, vec![1, 2, 3]);
+
+        // A utxo of exactly the target is spent alone
+        let (change, selected) =
+            branch_and_bound(&values, Amount::from_sat(70_000), 0, None).unwrap();
+        assert_eq!(change, Amount::ZERO);
+        assert_eq!(selected, vec![0]);
+
+        // Two inputs can not be worth it exactly
+        let (change, mut selected) =
+            branch_and_bound(&values, Amount::from_sat(105_000), 0, Some(2)).unwrap();
+        selected.sort();
+        assert_eq!(change, Amount::from_sat(15_000));
+        assert_eq!(selected, vec![0, 1]);
+    }
+
+    #[test]
+    fn test_clusters_kept_apart() {
+        use bdk::bitcoin::hashes::Hash;
+
+        let candidate = |sats, script: u8, tx: u8| Candidate {
+            value: Amount::from_sat(sats),
+            script_pubkey: Script::from(vec![script]),
+            txid: Txid::from_slice(&[tx; 32]).unwrap(),
+        };
+        // Change and CJ output of one transaction, and a utxo of its own
+        let candidates = [
+            candidate(40_000, 1, 1),
+            candidate(30_000, 2, 1),
+            candidate(65_000, 3, 2),
+        ];
+
+        // The cluster of the first transaction covers the target on its own
+        let mut selected = AvoidLinking
+            .select(&candidates, Amount::from_sat(70_000), None)
+            .unwrap();
+        selected.sort();
+        assert_eq!(selected, vec![0, 1]);
+        assert_eq!(
+            LeastChange.select(&candidates, Amount::from_sat(65_000), None),
+            Some(vec![2])
+        );
+
+        // Linking clusters can not be avoided
+        let mut selected = AvoidLinking
+            .select(&candidates, Amount::from_sat(100_000), None)
+            .unwrap();
+        selected.sort();
+        assert_eq!(selected, vec![0, 2]);
+        assert_eq!(
+            AvoidLinking.select(&candidates, Amount::from_sat(100_000), Some(1)),
+            None
+        );
+
+        assert_eq!(
+            LargestFirst.select(&candidates, Amount::from_sat(100_000), Some(2)),
+            Some(vec![2, 0])
+        );
+        let mut selected = RandomOrder
+            .select(&candidates, Amount::from_sat(135_000), None)
+            .unwrap();
+        selected.sort();
+        assert_eq!(selected, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_insufficient_funds() {
+        let values = amounts(&[10_000, 20_000]);
+        assert!(matches!(
+            select_inputs(&values, Amount::from_sat(50_000), None, None),
+            Err(Error::InsufficientFunds)
+        ));
+        assert!(select_inputs(&[], Amount::from_sat(1), None, None).is_err());
+    }
+}
@@ -0,0 +1,131 @@
+use crate::{
+    errors::Error,
+    podle::{self, MAX_PODLE_INDEX},
+    types::{AuthBinding, AuthCommitment, AuthProof},
+};
+
+use bdk::bitcoin::{OutPoint, PrivateKey};
+use bitcoin_hashes::sha256;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+/// Proof a taker sends in its auth that a round costs it something, so makers can not be probed for free
+/// ```
+/// use bitcoin::PrivateKey;
+/// use nostrdizer::commitment::{CommitmentScheme, Podle};
+///
+/// let priv_key = PrivateKey::from_slice(&[7; 32], bitcoin::Network::Regtest).unwrap();
+/// let podle = Podle::default();
+/// let proof = podle.generate(&priv_key, None).unwrap();
+///
+/// let sent = podle.serialize(&proof).unwrap();
+/// let received = podle.deserialize(&sent).unwrap();
+/// assert!(podle.verify(&received, podle.commitment(&proof)).is_ok());
+/// ```
+pub trait CommitmentScheme {
+    /// What the taker proves it holds
+    type Secret;
+    type Proof: Serialize + DeserializeOwned;
+
+    fn id(&self) -> SchemeId;
+
+    /// Proof over secret, bound to the session when binding is set
+    fn generate(
+        &self,
+        secret: &Self::Secret,
+        binding: Option<AuthBinding>,
+    ) -> Result<Self::Proof, Error>;
+
+    /// Checks proof opens the commitment the taker sent in its fill
+    fn verify(&self, proof: &Self::Proof, fill_commitment: sha256::Hash) -> Result<(), Error>;
+
+    /// Commitment proof opens
+    fn commitment(&self, proof: &Self::Proof) -> sha256::Hash;
+
+    /// Session proof is bound to
+    fn binding<'a>(&self, proof: &'a Self::Proof) -> Option<&'a AuthBinding>;
+
+    fn serialize(&self, proof: &Self::Proof) -> Result<String, Error> {
+        Ok(serde_json::to_string(proof)?)
+    }
+
+    fn deserialize(&self, proof: &str) -> Result<Self::Proof, Error> {
+        Ok(serde_json::from_str(proof)?)
+    }
+}
+
+/// Commitment schemes takers and makers can agree on
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SchemeId {
+    Podle,
+}
+
+impl SchemeId {
+    /// Scheme used with a peer on protocol version, every version so far uses podle
+    pub fn for_version(_protocol_version: u16) -> Self {
+        Self::Podle
+    }
+}
+
+/// Proof of discrete log equivalence over a utxo key, the key of a utxo can only be used for a few rounds
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Podle {
+    /// NUMS point index, openings are verified at their own index up to it
+    pub index: u8,
+    /// Utxo the key is of, the nonce of the proof is derived from it
+    pub outpoint: OutPoint,
+}
+
+impl CommitmentScheme for Podle {
+    type Secret = PrivateKey;
+    type Proof = AuthCommitment;
+
+    fn id(&self) -> SchemeId {
+        SchemeId::Podle
+    }
+
+    fn generate(
+        &self,
+        secret: &PrivateKey,
+        binding: Option<AuthBinding>,
+    ) -> Result<AuthCommitment, Error> {
+        podle::generate_podle(self.index as usize, *secret, self.outpoint, binding)
+    }
+
+    fn verify(&self, proof: &AuthCommitment, fill_commitment: sha256::Hash) -> Result<(), Error> {
+        podle::verify_podle(self.index, proof.clone(), fill_commitment)
+    }
+
+    fn commitment(&self, proof: &AuthCommitment) -> sha256::Hash {
+        proof.commit
+    }
+
+    fn binding<'a>(&self, proof: &'a AuthCommitment) -> Option<&'a AuthBinding> {
+        proof.binding.as_ref()
+    }
+}
+
+impl AuthProof {
+    pub fn scheme(&self) -> SchemeId {
+        match self {
+            AuthProof::Podle(_) => SchemeId::Podle,
+        }
+    }
+
+    /// Checks the proof with its scheme, podle openings up to the max index are accepted
+    pub fn verify(&self, fill_commitment: sha256::Hash, max_podle_index: u8) -> Result<(), Error> {
+        match self {
+            AuthProof::Podle(proof) => Podle {
+                index: max_podle_index.min(MAX_PODLE_INDEX),
+                ..Default::default()
+            }
+            .verify(proof, fill_commitment),
+        }
+    }
+
+    pub fn binding(&self) -> Option<&AuthBinding> {
+        match self {
+            AuthProof::Podle(proof) => Podle::default().binding(proof),
+        }
+    }
+}
@@ -0,0 +1,339 @@
+use crate::errors::Error;
+
+use bitcoin_hashes::{sha256, Hash, HashEngine};
+use nostr_rust::nips::nip4::{decrypt, encrypt};
+use rand::random;
+use secp256k1::{KeyPair, Secp256k1, SecretKey};
+use serde::{Deserialize, Serialize};
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+/// Rounds of sha256 the passphrase of an encrypted config is stretched with
+const KDF_ROUNDS: u32 = 100_000;
+
+/// Config written by `config init`, every setting commented out
+pub const TEMPLATE: &str = r#"# nostrdizer config, flags and env vars override what is set here
+
+[nostr]
+# priv_key = ""
+# relays = ["ws://localhost:7000"]
+# offer_relays = ["ws://localhost:7000"]
+# publish_quorum = 1
+# relay_auth = []
+# relay_trust = { "ws://localhost:7000" = 1.0 }
+
+[bitcoin]
+# network = "regtest"
+# rpc_url = "http://localhost:8332"
+# rpc_username = ""
+# rpc_password = ""
+# wallet = ""
+# wallet_passphrase = ""
+
+[maker]
+# abs_fee = 1000
+# rel_fee = 0.0003
+# minsize = 10000
+# maxsize = 1000000
+# allow_unconfirmed = false
+# coin_selection = "least-change"
+
+[taker]
+# max_abs_cj_fee = 10000
+# max_rel_cj_fee = 0.3
+# max_mining_fee = 10000
+# max_fee_rate = 50.0
+# min_makers = 1
+# script_type = "wpkh"
+# allow_unconfirmed = false
+# min_bond_value = 100000
+"#;
+
+/// Nostr identity and relays
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct NostrSection {
+    pub priv_key: Option<String>,
+    pub relays: Option<Vec<String>>,
+    pub offer_relays: Option<Vec<String>>,
+    pub publish_quorum: Option<usize>,
+    pub relay_auth: Option<Vec<String>>,
+    /// Trust weight of offers seen on each relay
+    pub relay_trust: Option<BTreeMap<String, f64>>,
+}
+
+/// Bitcoin core node and wallet
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct BitcoinSection {
+    pub network: Option<String>,
+    pub rpc_url: Option<String>,
+    pub rpc_username: Option<String>,
+    pub rpc_password: Option<String>,
+    pub wallet: Option<String>,
+    pub wallet_passphrase: Option<String>,
+}
+
+/// Settings of `run-maker`, `abs_fee` is the default of `--abs-fee` and `MAKER_ABS_FEE`
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct MakerSection {
+    pub abs_fee: Option<u64>,
+    pub rel_fee: Option<f64>,
+    pub minsize: Option<u64>,
+    pub maxsize: Option<u64>,
+    pub allow_unconfirmed: Option<bool>,
+    pub coin_selection: Option<String>,
+}
+
+/// Settings of the commands that take CJs, `min_makers` is the default of `--min-makers` and `TAKER_MIN_MAKERS`
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct TakerSection {
+    pub max_abs_cj_fee: Option<u64>,
+    pub max_rel_cj_fee: Option<f64>,
+    pub max_mining_fee: Option<u64>,
+    pub max_fee_rate: Option<f32>,
+    pub min_makers: Option<usize>,
+    pub script_type: Option<String>,
+    pub allow_unconfirmed: Option<bool>,
+    pub min_bond_value: Option<u64>,
+}
+
+/// Settings of the config file, each the default of the flag and env var it stands for
+/// ```
+/// use nostrdizer::config::Config;
+///
+/// let config = Config::from_toml(
+///     r#"
+///     [nostr]
+///     relays = ["wss://relay.example"]
+///
+///     [maker]
+///     abs_fee = 1000
+///     coin_selection = "random"
+///     "#,
+/// )
+/// .unwrap();
+/// assert_eq!(config.nostr.relays, Some(vec!["wss://relay.example".to_string()]));
+/// assert_eq!(config.maker.abs_fee, Some(1000));
+/// assert_eq!(config.maker.coin_selection.as_deref(), Some("random"));
+///
+/// // Misspelt settings are errors rather than ignored
+/// assert!(Config::from_toml("[taker]\nmin_maker = 2").is_err());
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Config {
+    #[serde(default)]
+    pub nostr: NostrSection,
+    #[serde(default)]
+    pub bitcoin: BitcoinSection,
+    #[serde(default)]
+    pub maker: MakerSection,
+    #[serde(default)]
+    pub taker: TakerSection,
+}
+
+/// Config encrypted with a key stretched from a passphrase, stored as a toml file of its own
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SealedConfig {
+    /// Hex salt the key is stretched with
+    pub salt: String,
+    /// NIP-04 payload of the config's toml, encrypted to the key's own pub key
+    pub content: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SealedFile {
+    encrypted: SealedConfig,
+}
+
+/// Default path of the config, `$XDG_CONFIG_HOME/nostrdizer/config.toml` or under `~/.config`
+pub fn default_path() -> Option<PathBuf> {
+    let config_home = match env::var("XDG_CONFIG_HOME") {
+        Ok(config_home) if !config_home.is_empty() => PathBuf::from(config_home),
+        _ => PathBuf::from(env::var("HOME").ok()?).join(".config"),
+    };
+    Some(config_home.join("nostrdizer").join("config.toml"))
+}
+
+/// Key a config is encrypted with
+fn config_key(passphrase: &str, salt: &[u8]) -> Result<SecretKey, Error> {
+    let mut engine = sha256::Hash::engine();
+    engine.input(salt);
+    engine.input(passphrase.as_bytes());
+    let mut key = sha256::Hash::from_engine(engine);
+    for _ in 1..KDF_ROUNDS {
+        let mut engine = sha256::Hash::engine();
+        engine.input(&key[..]);
+        engine.input(salt);
+        key = sha256::Hash::from_engine(engine);
+    }
+    Ok(SecretKey::from_slice(&key[..])?)
+}
+
+impl Config {
+    /// Parses a plain config
+    pub fn from_toml(toml: &str) -> Result<Self, Error> {
+        toml::from_str(toml).map_err(|err| Error::InvalidConfig(err.to_string()))
+    }
+
+    pub fn to_toml(&self) -> Result<String, Error> {
+        toml::to_string_pretty(self).map_err(|err| Error::InvalidConfig(err.to_string()))
+    }
+
+    /// Reads the config at path, none when there is no file
+    /// `passphrase` is only asked for when the config is encrypted
+    pub fn load(
+        path: &Path,
+        passphrase: impl FnOnce() -> Option<String>,
+    ) -> Result<Option<Self>, Error> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let toml = fs::read_to_string(path)?;
+        match SealedConfig::from_toml(&toml)? {
+            Some(sealed) => {
+                let passphrase = passphrase().ok_or(Error::ConfigLocked)?;
+                Ok(Some(sealed.open(&passphrase)?))
+            }
+            None => Ok(Some(Self::from_toml(&toml)?)),
+        }
+    }
+
+    /// Encrypts the config with a key stretched from passphrase
+    /// ```
+    /// use nostrdizer::{config::Config, errors::Error};
+    ///
+    /// let mut config = Config::default();
+    /// config.bitcoin.rpc_password = Some("hunter2".to_string());
+    ///
+    /// let sealed = config.seal("passphrase").unwrap();
+    /// assert_eq!(sealed.open("passphrase").unwrap(), config);
+    /// assert!(matches!(sealed.open("wrong"), Err(Error::WrongConfigPassphrase)));
+    /// ```
+    pub fn seal(&self, passphrase: &str) -> Result<SealedConfig, Error> {
+        let salt: [u8; 16] = random();
+        let secret_key = config_key(passphrase, &salt)?;
+        let (own_key, _) =
+            KeyPair::from_secret_key(&Secp256k1::new(), &secret_key).x_only_public_key();
+        Ok(SealedConfig {
+            salt: hex::encode(salt),
+            content: encrypt(&secret_key, &own_key, &self.to_toml()?)?,
+        })
+    }
+}
+
+impl SealedConfig {
+    /// Sealed config in toml, none when it is a plain config
+    pub fn from_toml(toml: &str) -> Result<Option<Self>, Error> {
+        let value: toml::Value =
+            toml::from_str(toml).map_err(|err| Error::InvalidConfig(err.to_string()))?;
+        match value.get("encrypted") {
+            Some(_) => {
+                let file: SealedFile =
+                    toml::from_str(toml).map_err(|err| Error::InvalidConfig(err.to_string()))?;
+                Ok(Some(file.encrypted))
+            }
+            None => Ok(None),
+        }
+    }
+
+    pub fn to_toml(&self) -> Result<String, Error> {
+        let file = SealedFile {
+            encrypted: self.clone(),
+        };
+        toml::to_string_pretty(&file).map_err(|err| Error::InvalidConfig(err.to_string()))
+    }
+
+    /// Decrypts the config, any failure to is a wrong passphrase
+    pub fn open(&self, passphrase: &str) -> Result<Config, Error> {
+        let salt = hex::decode(&self.salt).map_err(|_| Error::InvalidConfig("salt".into()))?;
+        let secret_key = config_key(passphrase, &salt)?;
+        let (own_key, _) =
+            KeyPair::from_secret_key(&Secp256k1::new(), &secret_key).x_only_public_key();
+        let toml = decrypt(&secret_key, &own_key, &self.content)
+            .map_err(|_| Error::WrongConfigPassphrase)?;
+        Config::from_toml(&toml).map_err(|_| Error::WrongConfigPassphrase)
+    }
+}
+
+/// Writes toml to path, creating its directory, only readable by the user on unix
+/// The mode is only given to files that are created, so an existing file is set to it before it is written
+pub fn write(path: &Path, toml: &str) -> Result<(), Error> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options.open(path)?;
+    #[cfg(unix)]
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    file.write_all(toml.as_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_template_parses() {
+        let config = Config::from_toml(TEMPLATE).unwrap();
+        assert_eq!(config, Config::default());
+    }
+
+    #[test]
+    fn test_load_sealed() {
+        let dir = env::temp_dir().join(format!("nostrdizer-config-{}", random::<u64>()));
+        let path = dir.join("config.toml");
+        assert_eq!(Config::load(&path, || None).unwrap(), None);
+
+        let mut config = Config::default();
+        config.nostr.priv_key = Some("secret".to_string());
+        write(
+            &path,
+            &config.seal("passphrase").unwrap().to_toml().unwrap(),
+        )
+        .unwrap();
+        #[cfg(unix)]
+        {
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        assert!(matches!(
+            Config::load(&path, || None),
+            Err(Error::ConfigLocked)
+        ));
+        let loaded = Config::load(&path, || Some("passphrase".to_string())).unwrap();
+        assert_eq!(loaded, Some(config.clone()));
+
+        // Plain configs are read without asking for a passphrase
+        write(&path, &config.to_toml().unwrap()).unwrap();
+        let loaded = Config::load(&path, || panic!("plain config needs no passphrase")).unwrap();
+        assert_eq!(loaded, Some(config));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_existing_owner_only() {
+        let dir = env::temp_dir().join(format!("nostrdizer-config-{}", random::<u64>()));
+        let path = dir.join("config.toml");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(&path, "").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+
+        write(&path, TEMPLATE).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,79 @@
+use crate::{
+    errors::Error,
+    fees::{checked_sub, fee_at_rate, CJValues},
+    round::{INPUT_VSIZE, OUTPUT_VSIZE, TX_OVERHEAD_VSIZE},
+    types::{CoopJoin, NostrdizerOffer, PROTOCOL_VERSION},
+};
+
+use bdk::bitcoin::{psbt::PartiallySignedTransaction, Amount};
+
+/// Seconds the lead waits for its partner to join, and the partner for the lead's CJ
+pub const COOP_TIMEOUT: u64 = 600;
+
+/// Offer the lead builds the CJ with for its co-op partner
+/// The partner takes no fee and pays its share of the round's fees as its mining fee
+pub fn partner_offer(partner: &str, share: Amount) -> NostrdizerOffer {
+    NostrdizerOffer {
+        maker: partner.to_string(),
+        oid: 0,
+        txfee: share,
+        cjfee: Amount::ZERO,
+        protocol_version: PROTOCOL_VERSION,
+        txfee_rate: None,
+        latency_class: None,
+        max_podle_index: None,
+        encryption_key: None,
+    }
+}
+
+/// Partner's share of a co-op round, half the maker fees and half the mining fee makers do not pay
+/// ```
+/// use nostrdizer::{coop::fee_share, types::Amount};
+///
+/// // 6 inputs and 6 outputs of 3 parties at 2 sat/vB is 1,210 sats of mining fee
+/// let share = fee_share(Amount::from_sat(1_000), Amount::ZERO, 6, 6, 2.0);
+/// assert_eq!(share, Amount::from_sat(1_105));
+///
+/// // Makers paying all of the mining fee leave the takers only their fees
+/// let share = fee_share(Amount::from_sat(1_000), Amount::from_sat(2_000), 6, 6, 2.0);
+/// assert_eq!(share, Amount::from_sat(500));
+/// ```
+pub fn fee_share(
+    maker_fees: Amount,
+    maker_txfee: Amount,
+    input_count: usize,
+    output_count: usize,
+    fee_rate: f32,
+) -> Amount {
+    let vsize = TX_OVERHEAD_VSIZE + input_count * INPUT_VSIZE + output_count * OUTPUT_VSIZE;
+    let mining_fee = fee_at_rate(vsize, fee_rate);
+    let takers_mining_fee = checked_sub(mining_fee, maker_txfee).unwrap_or(Amount::ZERO);
+    Amount::from_sat((maker_fees + takers_mining_fee).to_sat() / 2)
+}
+
+/// Checks the lead's CJ pays the partner's CJ output and costs it at most its max fee
+/// Returns what the round costs the partner
+pub fn check_coop_cj(
+    psbt: &PartiallySignedTransaction,
+    join: &CoopJoin,
+    values: &CJValues,
+) -> Result<Amount, Error> {
+    let script_pubkey = join.io_auth.coinjoin_address.script_pubkey();
+    if !psbt
+        .unsigned_tx
+        .output
+        .iter()
+        .any(|output| output.script_pubkey == script_pubkey && output.value == join.amount.to_sat())
+    {
+        return Err(Error::MissingCoopOutput);
+    }
+
+    let fee = checked_sub(values.my_input_value, values.my_output_value)?;
+    if fee > join.max_fee {
+        return Err(Error::CoopFeeTooHigh {
+            fee,
+            max: join.max_fee,
+        });
+    }
+    Ok(fee)
+}
@@ -0,0 +1,162 @@
+use crate::{errors::Error, psbt_v2};
+
+use bdk::bitcoin::psbt::PartiallySignedTransaction;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Files the maker-signed CJ is routed through before the taker signs, for review or co-signing by other tools
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CoSigning {
+    /// Where the CJ combined from the makers' signatures is written, base64
+    pub export_unsigned: Option<PathBuf>,
+    /// Where the CJ is read back from once other tools signed it, base64
+    pub import_signed: Option<PathBuf>,
+}
+
+/// Writes the PSBT to path as base64
+pub fn export(psbt: &PartiallySignedTransaction, path: &Path) -> Result<(), Error> {
+    fs::write(path, psbt.to_string())?;
+    Ok(())
+}
+
+/// Reads a base64 PSBT from path, version 0 or 2
+pub fn import(path: &Path) -> Result<PartiallySignedTransaction, Error> {
+    let bytes = base64::decode(fs::read_to_string(path)?.trim())
+        .map_err(|err| Error::DecodeError(format!("{}: {err}", path.display())))?;
+    psbt_v2::parse(&bytes).map_err(|err| Error::DecodeError(format!("{}: {err}", path.display())))
+}
+
+/// Checks imported is psbt with signatures added and nothing else changed
+/// Signatures already in psbt have to be kept as they were, and an input may be finalized only if it was not
+/// ```
+/// use nostrdizer::{cosign::check_signatures_added, test_utils::psbt};
+/// use bdk::bitcoin::Script;
+///
+/// let cj = psbt(&[60_000], &[50_000, 9_000]);
+/// assert!(check_signatures_added(&cj, &cj).is_ok());
+///
+/// let mut imported = cj.clone();
+/// imported.inputs[0].final_script_sig = Some(Script::new());
+/// assert!(check_signatures_added(&cj, &imported).is_ok());
+///
+/// // Output paid elsewhere
+/// imported.unsigned_tx.output[1].value = 8_000;
+/// assert!(check_signatures_added(&cj, &imported).is_err());
+/// ```
+pub fn check_signatures_added(
+    psbt: &PartiallySignedTransaction,
+    imported: &PartiallySignedTransaction,
+) -> Result<(), Error> {
+    let changed = |what: String| Err(Error::PsbtChanged(what));
+    if imported.unsigned_tx != psbt.unsigned_tx {
+        return changed("transaction".to_string());
+    }
+    if imported.version != psbt.version
+        || imported.xpub != psbt.xpub
+        || imported.proprietary != psbt.proprietary
+        || imported.unknown != psbt.unknown
+    {
+        return changed("global fields".to_string());
+    }
+    if imported.outputs != psbt.outputs {
+        return changed("outputs".to_string());
+    }
+    if imported.inputs.len() != psbt.inputs.len() {
+        return changed("inputs".to_string());
+    }
+
+    for (index, (input, imported)) in psbt.inputs.iter().zip(&imported.inputs).enumerate() {
+        let kept = kept(&input.partial_sigs, &imported.partial_sigs)
+            && kept(&input.tap_script_sigs, &imported.tap_script_sigs)
+            && (input.tap_key_sig.is_none() || input.tap_key_sig == imported.tap_key_sig)
+            && (input.final_script_sig.is_none()
+                || input.final_script_sig == imported.final_script_sig)
+            && (input.final_script_witness.is_none()
+                || input.final_script_witness == imported.final_script_witness);
+        if !kept {
+            return changed(format!("signatures of input {index}"));
+        }
+
+        // Anything left different once signatures are put back is not a signature
+        let mut unsigned = imported.clone();
+        unsigned.partial_sigs = input.partial_sigs.clone();
+        unsigned.tap_script_sigs = input.tap_script_sigs.clone();
+        unsigned.tap_key_sig = input.tap_key_sig;
+        unsigned.final_script_sig = input.final_script_sig.clone();
+        unsigned.final_script_witness = input.final_script_witness.clone();
+        if &unsigned != input {
+            return changed(format!("input {index}"));
+        }
+    }
+    Ok(())
+}
+
+/// Whether every entry of before is in after as it was
+fn kept<K: Ord, V: PartialEq>(before: &BTreeMap<K, V>, after: &BTreeMap<K, V>) -> bool {
+    before
+        .iter()
+        .all(|(key, value)| after.get(key) == Some(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::psbt;
+
+    use bdk::bitcoin::{
+        secp256k1::{ecdsa::Signature, Secp256k1, SecretKey},
+        EcdsaSig, EcdsaSighashType, PublicKey, Script,
+    };
+
+    fn sig(key: u8) -> (PublicKey, EcdsaSig) {
+        let secp = Secp256k1::new();
+        let secret = SecretKey::from_slice(&[key; 32]).unwrap();
+        let public = PublicKey::new(secret.public_key(&secp));
+        let signature = Signature::from_compact(&[key; 64]).unwrap();
+        (
+            public,
+            EcdsaSig {
+                sig: signature,
+                hash_ty: EcdsaSighashType::All,
+            },
+        )
+    }
+
+    #[test]
+    fn test_signatures_kept() {
+        let mut cj = psbt(&[60_000, 50_000], &[100_000, 9_000]);
+        let (maker_key, maker_sig) = sig(1);
+        cj.inputs[0].partial_sigs.insert(maker_key, maker_sig);
+
+        // Co-signer adds a signature to the other input
+        let mut imported = cj.clone();
+        let (key, signature) = sig(2);
+        imported.inputs[1].partial_sigs.insert(key, signature);
+        assert!(check_signatures_added(&cj, &imported).is_ok());
+
+        // Maker signature dropped
+        imported.inputs[0].partial_sigs.clear();
+        assert!(check_signatures_added(&cj, &imported).is_err());
+
+        // Redeem script is not a signature
+        let mut imported = cj.clone();
+        imported.inputs[1].redeem_script = Some(Script::new());
+        assert_eq!(
+            check_signatures_added(&cj, &imported)
+                .unwrap_err()
+                .to_string(),
+            "Imported PSBT changed more than signatures: input 1"
+        );
+    }
+
+    #[test]
+    fn test_export_import() {
+        let path = std::env::temp_dir().join("nostrdizer-cosign.psbt");
+        let cj = psbt(&[60_000], &[50_000, 9_000]);
+        export(&cj, &path).unwrap();
+        assert_eq!(import(&path).unwrap(), cj);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,178 @@
+use crate::{
+    fees::checked_add,
+    types::{Amount, Fill, IoAuth, RejectReason, VerifyCJInfo, DUST},
+};
+
+use bdk::bitcoin::psbt::PartiallySignedTransaction;
+
+use std::collections::HashSet;
+
+/// Max CJ outputs a maker adds for one fill
+pub const MAX_DENOMINATION_OUTPUTS: usize = 8;
+
+/// Checks the denominations of a fill are a structure the maker supports
+/// Each denomination must be above dust, have a unique amount and the outputs must add up to the fill amount
+/// ```
+/// use nostrdizer::{
+///     denomination::check_fill,
+///     types::{Amount, Denomination, Fill, InputLimits, RejectReason},
+/// };
+/// # use bitcoin_hashes::{sha256, Hash};
+///
+/// let denomination = |sats, count| Denomination {
+///     amount: Amount::from_sat(sats),
+///     count,
+/// };
+/// let mut fill = Fill {
+///     offer_id: 0,
+///     amount: Amount::from_sat(300_000),
+///     tencpubkey: "".to_string(),
+///     commitment: sha256::Hash::hash("".as_bytes()),
+///     denominations: vec![denomination(100_000, 2), denomination(50_000, 2)],
+///     script_type: None,
+///     counter: false,
+///     cjfee: None,
+///     payment: None,
+///     silent_payment: None,
+///     invite: None,
+///     input_limits: InputLimits::default(),
+/// };
+/// assert_eq!(check_fill(&fill), None);
+///
+/// fill.amount = Amount::from_sat(250_000);
+/// assert_eq!(check_fill(&fill), Some(RejectReason::UnsupportedDenominations));
+/// ```
+pub fn check_fill(fill: &Fill) -> Option<RejectReason> {
+    if fill.denominations.is_empty() {
+        return None;
+    }
+
+    let mut amounts = HashSet::new();
+    let mut total = Amount::ZERO;
+    let mut outputs = 0;
+    for denomination in &fill.denominations {
+        if denomination.count == 0
+            || denomination.amount.to_sat() <= DUST
+            || !amounts.insert(denomination.amount)
+        {
+            return Some(RejectReason::UnsupportedDenominations);
+        }
+        outputs += denomination.count;
+        // Counts are bounded before they are multiplied
+        if outputs > MAX_DENOMINATION_OUTPUTS {
+            return Some(RejectReason::UnsupportedDenominations);
+        }
+        for _ in 0..denomination.count {
+            total = match checked_add(total, denomination.amount) {
+                Ok(total) => total,
+                Err(_) => return Some(RejectReason::UnsupportedDenominations),
+            };
+        }
+    }
+
+    match total == fill.amount {
+        true => None,
+        false => Some(RejectReason::UnsupportedDenominations),
+    }
+}
+
+/// Checks the CJ pays each of the maker's CJ addresses the amount of its output
+pub fn verify_outputs(
+    psbt: &PartiallySignedTransaction,
+    fill: &Fill,
+    maker_input: &IoAuth,
+) -> Option<RejectReason> {
+    let addresses = maker_input.coinjoin_addresses();
+    let outputs = fill.outputs();
+    if addresses.len() != outputs.len() {
+        return Some(RejectReason::UnsupportedDenominations);
+    }
+
+    addresses
+        .iter()
+        .zip(outputs)
+        .find(|(address, amount)| {
+            !psbt.unsigned_tx.output.iter().any(|output| {
+                output.script_pubkey == address.script_pubkey() && output.value == amount.to_sat()
+            })
+        })
+        .map(|(_, amount)| RejectReason::MissingCJOutput { amount })
+}
+
+/// Rejects a CJ the maker would otherwise sign if it does not pay the maker's CJ outputs
+pub fn check_outputs(
+    mut tx_info: VerifyCJInfo,
+    psbt: &PartiallySignedTransaction,
+    fill: &Fill,
+    maker_input: &IoAuth,
+) -> VerifyCJInfo {
+    if tx_info.reject_reason.is_none() {
+        if let Some(reason) = verify_outputs(psbt, fill, maker_input) {
+            tx_info.verifyed = false;
+            tx_info.reject_reason = Some(reason);
+        }
+    }
+    tx_info
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_utils::{io_auth, psbt},
+        types::{Denomination, InputLimits},
+    };
+    use bdk::bitcoin::{Address, Network, Script};
+    use bitcoin_hashes::{sha256, Hash};
+
+    #[test]
+    fn test_verify_outputs() {
+        let fill = Fill {
+            offer_id: 0,
+            amount: Amount::from_sat(150_000),
+            tencpubkey: "".to_string(),
+            commitment: sha256::Hash::hash("".as_bytes()),
+            denominations: vec![
+                Denomination {
+                    amount: Amount::from_sat(100_000),
+                    count: 1,
+                },
+                Denomination {
+                    amount: Amount::from_sat(50_000),
+                    count: 1,
+                },
+            ],
+            script_type: None,
+            counter: false,
+            cjfee: None,
+            payment: None,
+            silent_payment: None,
+            invite: None,
+            input_limits: InputLimits::default(),
+        };
+        let mut maker_input = io_auth(0);
+        maker_input.extra_coinjoin_addresses =
+            vec![Address::p2wsh(&Script::from(vec![0, 2]), Network::Regtest)];
+
+        let mut cj = psbt(&[200_000], &[100_000, 50_000, 49_000]);
+        cj.unsigned_tx.output[0].script_pubkey = maker_input.coinjoin_address.script_pubkey();
+        cj.unsigned_tx.output[1].script_pubkey =
+            maker_input.extra_coinjoin_addresses[0].script_pubkey();
+        assert_eq!(verify_outputs(&cj, &fill, &maker_input), None);
+
+        cj.unsigned_tx.output[1].value = 49_000;
+        assert_eq!(
+            verify_outputs(&cj, &fill, &maker_input),
+            Some(RejectReason::MissingCJOutput {
+                amount: Amount::from_sat(50_000)
+            })
+        );
+
+        // An address is needed for every output
+        maker_input.extra_coinjoin_addresses.clear();
+        assert_eq!(
+            verify_outputs(&cj, &fill, &maker_input),
+            Some(RejectReason::UnsupportedDenominations)
+        );
+    }
+}
@@ -0,0 +1,249 @@
+use crate::{
+    fiat::Price,
+    reputation::MakerQuality,
+    types::{Amount, Offer, RejectReason, SignedAmount},
+};
+
+/// Amount in sats with thousands separators
+/// ```
+/// use nostrdizer::{display, types::Amount};
+///
+/// assert_eq!(display::sats(Amount::from_sat(1_234_567)), "1,234,567 sats");
+/// assert_eq!(display::sats(Amount::from_sat(999)), "999 sats");
+/// ```
+pub fn sats(amount: Amount) -> String {
+    format!("{} sats", group_thousands(amount.to_sat()))
+}
+
+/// Signed amount in sats with thousands separators, negative when paid rather than earned
+pub fn signed_sats(amount: SignedAmount) -> String {
+    let sign = match amount.is_negative() {
+        true => "-",
+        false => "",
+    };
+    format!(
+        "{sign}{} sats",
+        group_thousands(amount.to_sat().unsigned_abs())
+    )
+}
+
+/// Signed amount in sats followed by its value in fiat when there is a price, for fee summaries
+/// ```
+/// use nostrdizer::{display, fiat::Price, types::SignedAmount};
+///
+/// let price = Price {
+///     currency: "USD".to_string(),
+///     btc_price: 30_000.0,
+///     fetched_at: 0,
+/// };
+/// let fee = SignedAmount::from_sat(-12_345);
+/// assert_eq!(display::with_fiat(fee, Some(&price)), "-12,345 sats (~-3.70 USD)");
+/// assert_eq!(display::with_fiat(fee, None), "-12,345 sats");
+/// ```
+pub fn with_fiat(amount: SignedAmount, price: Option<&Price>) -> String {
+    match price {
+        Some(price) => format!(
+            "{} (~{:.2} {})",
+            signed_sats(amount),
+            price.value(amount),
+            price.currency
+        ),
+        None => signed_sats(amount),
+    }
+}
+
+/// Amount in BTC with all 8 decimals
+/// ```
+/// use nostrdizer::{display, types::Amount};
+///
+/// assert_eq!(display::btc(Amount::from_sat(1_234_567)), "0.01234567 BTC");
+/// ```
+pub fn btc(amount: Amount) -> String {
+    format!("{:.8} BTC", amount.to_btc())
+}
+
+/// Amount in sats followed by BTC, for balances
+pub fn sats_and_btc(amount: Amount) -> String {
+    format!("{} ({})", sats(amount), btc(amount))
+}
+
+/// Fee rate in sat/vB
+/// ```
+/// use nostrdizer::display;
+///
+/// assert_eq!(display::fee_rate(12.345), "12.3 sat/vB");
+/// ```
+pub fn fee_rate(sat_per_vb: f32) -> String {
+    format!("{sat_per_vb:.1} sat/vB")
+}
+
+/// Ratio, such as a relative fee, as a percentage
+/// ```
+/// use nostrdizer::display;
+///
+/// assert_eq!(display::percent(0.0003), "0.03%");
+/// assert_eq!(display::percent(0.2), "20.00%");
+/// ```
+pub fn percent(ratio: f64) -> String {
+    format!("{:.2}%", ratio * 100.0)
+}
+
+/// Fee and size range of an offer
+pub fn offer(offer: &Offer) -> String {
+    let (fee, minsize, maxsize, min_fee_rate) = match offer {
+        Offer::RelOffer(offer) => (
+            format!("relative fee {}", percent(offer.cjfee)),
+            offer.minsize,
+            offer.maxsize,
+            offer.min_fee_rate,
+        ),
+        Offer::AbsOffer(offer) => (
+            format!("absolute fee {}", sats(offer.cjfee)),
+            offer.minsize,
+            offer.maxsize,
+            offer.min_fee_rate,
+        ),
+    };
+    let mut summary = format!("{fee}, {} to {}", sats(minsize), sats(maxsize));
+    if let Some(min_fee_rate) = min_fee_rate {
+        summary.push_str(&format!(", min {}", fee_rate(min_fee_rate)));
+    }
+    summary
+}
+
+/// Unix time as a UTC date and time
+/// ```
+/// use nostrdizer::display;
+///
+/// assert_eq!(display::timestamp(1_700_000_000), "2023-11-14 22:13 UTC");
+/// ```
+pub fn timestamp(secs: u64) -> String {
+    match chrono::NaiveDateTime::from_timestamp_opt(secs as i64, 0) {
+        Some(time) => time.format("%Y-%m-%d %H:%M UTC").to_string(),
+        None => format!("{secs}"),
+    }
+}
+
+/// Duration in milliseconds with thousands separators
+pub fn millis(millis: u64) -> String {
+    format!("{} ms", group_thousands(millis))
+}
+
+/// Success rate and p95 response times of a maker
+/// ```
+/// use nostrdizer::{display, reputation::Reputation};
+///
+/// let mut reputation = Reputation::default();
+/// assert_eq!(display::quality(&reputation.quality("maker")), "no sessions");
+///
+/// reputation.record_outcome("maker", true);
+/// assert_eq!(
+///     display::quality(&reputation.quality("maker")),
+///     "1 of 1 sessions completed (100.00%)"
+/// );
+/// ```
+pub fn quality(quality: &MakerQuality) -> String {
+    let mut summary = match quality.success_rate {
+        Some(success_rate) => format!(
+            "{} of {} sessions completed ({})",
+            quality.completed,
+            quality.sessions,
+            percent(success_rate)
+        ),
+        None => "no sessions".to_string(),
+    };
+    if let Some(p95) = quality.p95_fill_ack {
+        summary.push_str(&format!(", p95 fill ack {}", millis(p95)));
+    }
+    if let Some(p95) = quality.p95_sign {
+        summary.push_str(&format!(", p95 sign {}", millis(p95)));
+    }
+    summary
+}
+
+/// Why a peer turned down a fill or CJ
+pub fn reject_reason(reason: &RejectReason) -> String {
+    match reason {
+        RejectReason::CJFeeTooLow => "CJ fee too low".to_string(),
+        RejectReason::AmountOutOfRange => "amount out of range".to_string(),
+        RejectReason::FeeRateTooLow {
+            fee_rate: rate,
+            min_fee_rate,
+        } => format!(
+            "fee rate {} below min {}",
+            fee_rate(*rate),
+            fee_rate(*min_fee_rate)
+        ),
+        RejectReason::Busy => "busy".to_string(),
+        RejectReason::TooFewParticipants {
+            participants,
+            min_participants,
+        } => format!("{participants} participants, needs {min_participants}"),
+        RejectReason::ParticipantMismatch { claimed, counted } => {
+            format!("claimed {claimed} participants, counted {counted}")
+        }
+        RejectReason::UnsupportedDenominations => "unsupported denominations".to_string(),
+        RejectReason::UnconfirmedInputs { outpoints } => {
+            format!("{} unconfirmed inputs", outpoints.len())
+        }
+        RejectReason::NoSuitableInputs => "no suitable inputs".to_string(),
+        RejectReason::MissingCJOutput { amount } => {
+            format!("missing CJ output of {}", sats(*amount))
+        }
+        RejectReason::BelowPrivacyFloor { amount } => {
+            format!("CJ output of {} below the privacy floor", sats(*amount))
+        }
+        RejectReason::DustOutputs { count } => format!("{count} dust outputs"),
+        RejectReason::MixedOutputTypes => "outputs of mixed script types".to_string(),
+        RejectReason::NonstandardOutputs => "outputs to nonstandard scripts".to_string(),
+        RejectReason::InviteRequired => "invite required".to_string(),
+        RejectReason::TooManyInputs { inputs, max_inputs } => {
+            format!("{inputs} inputs, takes {max_inputs}")
+        }
+        RejectReason::PaymentOutputs { count } => format!("{count} payment outputs, needs 1"),
+        RejectReason::NonuniformOutputs { mixing, other } => {
+            format!("{other} outputs besides {mixing} mixing outputs")
+        }
+        RejectReason::CommitmentUsed => "commitment already used".to_string(),
+        RejectReason::CounterOffer(counter) => format!(
+            "counter-offer of up to {} for {}",
+            sats(counter.maxsize),
+            sats(counter.cjfee)
+        ),
+    }
+}
+
+/// Digits of `value` in groups of three separated by commas
+fn group_thousands(value: u64) -> String {
+    let digits = value.to_string();
+    let mut grouped = String::with_capacity(digits.len() + digits.len() / 3);
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i) % 3 == 0 {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+    grouped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_group_thousands() {
+        assert_eq!(group_thousands(0), "0");
+        assert_eq!(group_thousands(100), "100");
+        assert_eq!(group_thousands(1_000), "1,000");
+        assert_eq!(
+            group_thousands(2_100_000_000_000_000),
+            "2,100,000,000,000,000"
+        );
+    }
+
+    #[test]
+    fn test_signed_sats() {
+        assert_eq!(signed_sats(SignedAmount::from_sat(-12_500)), "-12,500 sats");
+        assert_eq!(signed_sats(SignedAmount::from_sat(800)), "800 sats");
+    }
+}
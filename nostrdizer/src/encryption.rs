@@ -0,0 +1,140 @@
+use crate::{errors::Error, types::NostrdizerMessage, utils::decrypt_message};
+
+use nostr_rust::{events::Event, Identity};
+use secp256k1::{
+    hashes::sha256, schnorr::Signature, KeyPair, Message, SecretKey, XOnlyPublicKey, SECP256K1,
+};
+use serde::{Deserialize, Serialize};
+
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+
+/// Tag the association of an encryption key with a nostr key is signed under
+const ASSOCIATION_TAG: &[u8] = b"nostrdizer encryption key";
+
+/// Key messages to a peer are encrypted to in place of its nostr key, signed by the nostr key
+/// The key can be rotated without rotating the nostr key reputation is kept under
+/// ```
+/// use nostr_rust::{keys::get_random_secret_key, Identity};
+/// use nostrdizer::encryption::EncryptionKey;
+/// use std::str::FromStr;
+///
+/// let (identity_key, _) = get_random_secret_key();
+/// let identity = Identity::from_str(&hex::encode(identity_key.as_ref())).unwrap();
+/// let (secret_key, _) = get_random_secret_key();
+///
+/// let encryption_key = EncryptionKey::new(&identity, &secret_key);
+/// assert!(encryption_key.verify(&identity.public_key_str).is_ok());
+///
+/// // Another nostr key can not claim it
+/// let (other_key, _) = get_random_secret_key();
+/// let other = Identity::from_str(&hex::encode(other_key.as_ref())).unwrap();
+/// assert!(encryption_key.verify(&other.public_key_str).is_err());
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct EncryptionKey {
+    /// Hex x-only public key
+    #[serde(rename = "key")]
+    pub pub_key: String,
+    /// Hex schnorr signature of the nostr key over the tagged public key
+    #[serde(rename = "sig")]
+    pub signature: String,
+}
+
+impl EncryptionKey {
+    /// Encryption key of secret_key, signed by identity
+    pub fn new(identity: &Identity, secret_key: &SecretKey) -> Self {
+        let (pub_key, _) =
+            XOnlyPublicKey::from_keypair(&KeyPair::from_secret_key(SECP256K1, secret_key));
+        let keypair = KeyPair::from_secret_key(SECP256K1, &identity.secret_key);
+        let signature = SECP256K1.sign_schnorr(&association(&pub_key), &keypair);
+
+        Self {
+            pub_key: pub_key.to_string(),
+            signature: signature.to_string(),
+        }
+    }
+
+    /// Checks the key was signed by the nostr key of peer
+    pub fn verify(&self, nostr_pub_key: &str) -> Result<(), Error> {
+        let verified = (|| -> Result<(), secp256k1::Error> {
+            let pub_key = XOnlyPublicKey::from_str(&self.pub_key)?;
+            let signature = Signature::from_str(&self.signature)?;
+            let nostr_pub_key = XOnlyPublicKey::from_str(nostr_pub_key)?;
+            SECP256K1.verify_schnorr(&signature, &association(&pub_key), &nostr_pub_key)
+        })();
+        verified.map_err(|_| Error::EncryptionKeyProof)
+    }
+}
+
+/// Message a nostr key signs to associate an encryption key with it
+fn association(pub_key: &XOnlyPublicKey) -> Message {
+    let mut data = ASSOCIATION_TAG.to_vec();
+    data.extend_from_slice(&pub_key.serialize());
+    Message::from_hashed_data::<sha256::Hash>(&data)
+}
+
+/// How a message to a peer is encrypted and published
+#[derive(Debug, Clone)]
+pub struct Envelope {
+    /// Key the message is encrypted with, the nostr key or the sender's encryption key
+    pub secret_key: SecretKey,
+    /// Key the message is encrypted to, the nostr key of the peer or its encryption key
+    pub pub_key: String,
+    /// Whether the signed event is sealed, seals are always to the nostr key of the peer
+    pub sealed: bool,
+    /// Whether the message is tagged with its type and schema version, see [`NostrdizerMessage::to_wire`]
+    pub tagged: bool,
+}
+
+/// Encryption key a maker advertises, and the takers that encrypt to it rather than the nostr key
+#[derive(Debug, Clone, Default)]
+pub struct MakerEncryption {
+    pub secret_key: Option<SecretKey>,
+    /// Takers whose messages were encrypted to the encryption key, they are answered with it
+    pub peers: HashSet<String>,
+}
+
+impl MakerEncryption {
+    /// Encryption key signed by identity to advertise, none when the nostr key is used
+    pub fn advertised(&self, identity: &Identity) -> Option<EncryptionKey> {
+        self.secret_key
+            .as_ref()
+            .map(|secret_key| EncryptionKey::new(identity, secret_key))
+    }
+
+    /// Decrypts a message of event's author, with the encryption key first then the nostr key
+    /// Takers that do not know the encryption key still encrypt to the nostr key
+    pub fn decrypt(
+        &mut self,
+        identity: &Identity,
+        event: &Event,
+    ) -> Result<NostrdizerMessage, Error> {
+        if let Some(secret_key) = &self.secret_key {
+            if let Ok(message) = decrypt_message(secret_key, &event.pub_key, &event.content) {
+                self.peers.insert(event.pub_key.clone());
+                return Ok(message);
+            }
+        }
+        let message = decrypt_message(&identity.secret_key, &event.pub_key, &event.content)?;
+        // Taker no longer knows the encryption key, it is answered with the nostr key again
+        self.peers.remove(&event.pub_key);
+        Ok(message)
+    }
+
+    /// Key messages to peer are encrypted with
+    pub fn secret_key(&self, identity: &Identity, peer_pub_key: &str) -> SecretKey {
+        match (&self.secret_key, self.peers.contains(peer_pub_key)) {
+            (Some(secret_key), true) => *secret_key,
+            _ => identity.secret_key,
+        }
+    }
+}
+
+/// Encryption key a maker is encrypted to, its nostr key when it advertised none
+pub fn peer_key<'a>(encryption_keys: &'a HashMap<String, String>, maker: &'a str) -> &'a str {
+    encryption_keys
+        .get(maker)
+        .map(String::as_str)
+        .unwrap_or(maker)
+}
@@ -1,5 +1,15 @@
-use bdk::bitcoin::util::amount::ParseAmountError;
+use crate::{
+    display::sats,
+    snapshot::Drift,
+    types::{DescriptorType, Transaction},
+};
+
+use bdk::bitcoin::{
+    util::{amount::ParseAmountError, bip32},
+    Amount, Network, OutPoint, Txid,
+};
 use nostr_rust::nips::{nip16::NIP16Error, nip9::NIP9Error};
+use serde::Serialize;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -59,18 +69,45 @@ pub enum Error {
     #[error("Insufficient funds")]
     InsufficientFunds,
 
+    #[error("Amount overflow")]
+    AmountOverflow,
+
     #[error("Taker did not respond with transaction")]
     TakerFailedToSendTransaction,
 
+    #[error("Taker aborted the session")]
+    TakerAborted,
+
     #[error("Not enough makers")]
     NotEnoughMakers,
 
+    #[error("There are no makers that match this order")]
+    NoMatchingOffers,
+
+    #[error("Transaction could not be verified")]
+    TransactionNotVerified,
+
     #[error("Could not verify podle")]
     PodleVerifyFailed,
 
     #[error("Podle commit does not match provided")]
     PodleCommitment,
 
+    #[error("Every utxo revealed podle commitments at all of the indices makers accept")]
+    PodleExhausted,
+
+    #[error("Podle opened at index {index}, only up to {max} is accepted")]
+    PodleIndexNotAccepted { index: u8, max: u8 },
+
+    #[error("Utxo of the round's podle commitment is no longer unspent")]
+    PodleUtxoSpent,
+
+    #[error("Wallet backend can not read utxo keys for podle commitments")]
+    PodleUnsupported,
+
+    #[error("Auth is bound to another session")]
+    AuthBindingMismatch,
+
     #[error("Could not get num")]
     GetNum,
 
@@ -92,6 +129,287 @@ pub enum Error {
 
     #[error("Invalid credentials")]
     InvalidCredentials,
+
+    #[error("Could not unseal sealed event")]
+    Unseal,
+
+    #[error("Encryption key is not signed by the nostr key")]
+    EncryptionKeyProof,
+
+    #[error("Only {} makers signed", _0.len())]
+    MakersFailedToSign(Vec<String>),
+
+    #[error("Makers can not sign a rebuilt round: {}", _0.join(", "))]
+    RebuildUnsupported(Vec<String>),
+
+    #[error("Taker rebuilt the round without the makers that did not sign")]
+    RoundRebuilt(Box<Transaction>),
+
+    #[error("Co-op partner {0} did not join")]
+    NoCoopJoin(String),
+
+    #[error("Co-op round costs {}, more than the max of {}", sats(*fee), sats(*max))]
+    CoopFeeTooHigh { fee: Amount, max: Amount },
+
+    #[error("Co-op round does not pay the send amount")]
+    MissingCoopOutput,
+
+    #[error("Not on {} network", _0)]
+    WrongNetwork(Network),
+
+    #[error("Unconfirmed inputs: {}", join_outpoints(_0))]
+    UnconfirmedInputs(Vec<OutPoint>),
+
+    #[error("Taker inputs changed during the round: {}", join_drift(_0))]
+    InputsDrifted(Vec<Drift>),
+
+    #[error("Imported PSBT changed more than signatures: {}", _0)]
+    PsbtChanged(String),
+
+    #[error("Least change of {} is over the max change", _0)]
+    ChangeTooLarge(Amount),
+
+    #[error("Inputs of {} are over the max per round", _0)]
+    OverRoundCap(Amount),
+
+    #[error("Wallet {} not found; run with --create-wallet to create it", _0)]
+    WalletNotFound(String),
+
+    #[error("Wallet {} not loaded and could not be loaded: {}", _0, _1)]
+    WalletNotLoaded(String, String),
+
+    #[error("IO error: {}", _0)]
+    IoError(std::io::Error),
+
+    #[error("BIP32 error: {}", _0)]
+    Bip32Error(bip32::Error),
+
+    #[error("Invalid descriptor: {}", _0)]
+    InvalidDescriptor(String),
+
+    #[error("Wallet has no extended private key to derive the nostr identity from")]
+    NoWalletKey,
+
+    #[error("Wallet is encrypted and no wallet passphrase was given")]
+    WalletLocked,
+
+    #[error("Wallet passphrase is incorrect")]
+    WrongWalletPassphrase,
+
+    #[error("Unknown script type {}, expected wpkh, shwpkh or tr", _0)]
+    UnknownScriptType(String),
+
+    #[error(
+        "Unknown latency class {}, expected clearnet-fast, clearnet or tor-slow",
+        _0
+    )]
+    UnknownLatencyClass(String),
+
+    #[error(
+        "Unknown coin selection {}, expected least-change, largest-first, random or avoid-linking",
+        _0
+    )]
+    UnknownCoinSelection(String),
+
+    #[error(
+        "Unknown maker selection {}, expected cheapest, random or fee-weighted",
+        _0
+    )]
+    UnknownMakerSelection(String),
+
+    #[error("Line {} is not a BIP-329 label", _0)]
+    InvalidLabel(usize),
+
+    #[error("No relay answered the latency probe")]
+    NoRelayAnswered,
+
+    #[error(
+        "Relays too slow for protocol timeouts: {} needs about {} ms of its {} ms",
+        _0,
+        _1,
+        _2
+    )]
+    RelaysTooSlow(String, u64, u64),
+
+    #[error("CJ outputs are not all of one script type")]
+    MixedOutputTypes,
+
+    #[error("Change descriptor is of another script type than the receive descriptor")]
+    ChangeTypeMismatch,
+
+    #[error("Only {} relays accepted the message, {} needed", _0, _1)]
+    PublishQuorum(usize, usize),
+
+    #[error("Relay {} rejected AUTH: {}", _0, _1)]
+    RelayAuthRejected(String, String),
+
+    #[error("Relays require AUTH, set them up with --relay-auth: {}", _0.join(", "))]
+    RelayAuthRequired(Vec<String>),
+
+    #[error("Address {} is not for {}", _0, _1)]
+    AddressNetwork(String, Network),
+
+    #[error("Address {} is not of a standard script type", _0)]
+    NonstandardAddress(String),
+
+    #[error("No {} given to build with", _0)]
+    MissingComponent(&'static str),
+
+    #[error("Invalid config: {}", _0)]
+    InvalidConfig(String),
+
+    #[error("Config is encrypted and no passphrase was given")]
+    ConfigLocked,
+
+    #[error("Config passphrase is incorrect")]
+    WrongConfigPassphrase,
+
+    #[error("Fee estimate of {} sat/vB is over the max of {} sat/vB", _0, _1)]
+    FeeSpike(f32, f32),
+
+    #[error("BTC price unavailable: {}", _0)]
+    PriceUnavailable(String),
+
+    #[error("No outputs of the wallet in {} to bump its fee with", _0)]
+    NothingToBump(Txid),
+
+    #[error("Wallet could not sign the child bumping the fee of {}", _0)]
+    ChildNotSigned(Txid),
+
+    #[error("{} is not in the mempool or does not signal RBF", _0)]
+    NotReplaceable(Txid),
+
+    #[error("Wallet can not spend {:?} outputs", _0)]
+    UnsupportedScriptType(DescriptorType),
+
+    #[error(
+        "No one of the wallet's {} sources covers the round, spending them together links them",
+        _0
+    )]
+    SourcesWouldLink(usize),
+
+    #[error("Package rejected: {}", _0)]
+    PackageRejected(String),
+
+    #[error("Event signature is not valid")]
+    InvalidEventSignature,
+
+    #[error("Event {} not found on the relays", _0)]
+    EventNotFound(String),
+
+    #[error("Invalid silent payment address {}", _0)]
+    InvalidSilentPaymentAddress(String),
+
+    #[error(
+        "Silent payment share from {} does not prove against its input keys",
+        _0
+    )]
+    InvalidSilentPaymentShare(String),
+
+    #[error("Input key does not spend its utxo, can not derive the silent payment")]
+    SilentPaymentInputKey,
+
+    #[error("CJ does not pay the silent payment output derived from its inputs")]
+    SilentPaymentOutputMissing,
+
+    #[error("Wallet backend can not read input keys for silent payments")]
+    SilentPaymentKeysUnavailable,
+
+    #[error("Fidelity bond is not signed by its key for the nostr key")]
+    FidelityBondProof,
+
+    #[error("Invalid fidelity bond: {}", _0)]
+    FidelityBondInvalid(String),
+
+    #[error("Wallet backend can not create or look up fidelity bonds")]
+    FidelityBondsUnavailable,
+}
+
+/// Category of a failure, stable so scripts can tell failures apart
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    NoOffers,
+    InsufficientFunds,
+    VerificationFailed,
+    RelayFailure,
+    /// Makers or the taker did not answer in time
+    Timeout,
+    Other,
+}
+
+impl Error {
+    /// ```
+    /// use nostrdizer::errors::{Error, ErrorKind};
+    ///
+    /// assert_eq!(Error::NoMatchingOffers.kind(), ErrorKind::NoOffers);
+    /// assert_eq!(Error::MakersFailedToRespond.kind(), ErrorKind::Timeout);
+    /// ```
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::NoMatchingOffers | Error::NotEnoughMakers => ErrorKind::NoOffers,
+            Error::InsufficientFunds | Error::NoMatchingUtxo | Error::SourcesWouldLink(_) => {
+                ErrorKind::InsufficientFunds
+            }
+            Error::PodleVerifyFailed
+            | Error::PodleCommitment
+            | Error::PodleIndexNotAccepted { .. }
+            | Error::AuthBindingMismatch
+            | Error::EncryptionKeyProof
+            | Error::InvalidEventSignature
+            | Error::FidelityBondProof
+            | Error::FidelityBondInvalid(_)
+            | Error::TransactionNotVerified
+            | Error::MixedOutputTypes
+            | Error::AddressNetwork(..)
+            | Error::NonstandardAddress(_)
+            | Error::OutputValueLessExpected
+            | Error::BadInput
+            | Error::PsbtChanged(_)
+            | Error::FeesTooHigh
+            | Error::MakerFeeTooHigh
+            | Error::CoopFeeTooHigh { .. }
+            | Error::MissingCoopOutput
+            | Error::InvalidSilentPaymentShare(_)
+            | Error::SilentPaymentOutputMissing => ErrorKind::VerificationFailed,
+            Error::NostrRustError(_)
+            | Error::NostrRustClientError(_)
+            | Error::NIP16(_)
+            | Error::NIP9(_)
+            | Error::Unseal
+            | Error::EventNotFound(_)
+            | Error::NoRelayAnswered
+            | Error::RelaysTooSlow(..)
+            | Error::PublishQuorum(..)
+            | Error::RelayAuthRejected(..)
+            | Error::RelayAuthRequired(_)
+            | Error::FailedToBroadcast
+            | Error::PackageRejected(_) => ErrorKind::RelayFailure,
+            Error::MakersFailedToRespond
+            | Error::MakersFailedToSign(_)
+            | Error::NoCoopJoin(_)
+            | Error::TakerFailedToSendTransaction => ErrorKind::Timeout,
+            _ => ErrorKind::Other,
+        }
+    }
+}
+
+/// Outpoints as `txid:vout` for error messages
+fn join_outpoints(outpoints: &[OutPoint]) -> String {
+    outpoints
+        .iter()
+        .map(|outpoint| outpoint.to_string())
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+/// Drift of inputs for error messages
+fn join_drift(drift: &[Drift]) -> String {
+    drift
+        .iter()
+        .map(|drift| drift.to_string())
+        .collect::<Vec<String>>()
+        .join(", ")
 }
 
 #[cfg(feature = "bitcoincore")]
@@ -101,6 +419,12 @@ impl From<bitcoincore_rpc::Error> for Error {
     }
 }
 
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Self::IoError(err)
+    }
+}
+
 impl From<serde_json::Error> for Error {
     fn from(err: serde_json::Error) -> Self {
         Self::SerdeError(err)
@@ -119,6 +443,12 @@ impl From<nostr_rust::nostr_client::ClientError> for Error {
     }
 }
 
+impl From<bip32::Error> for Error {
+    fn from(err: bip32::Error) -> Self {
+        Self::Bip32Error(err)
+    }
+}
+
 impl From<bdk::bitcoin::secp256k1::Error> for Error {
     fn from(err: bdk::bitcoin::secp256k1::Error) -> Self {
         Self::BitcoinSecpError(err)
@@ -0,0 +1,1111 @@
+use crate::{
+    display,
+    errors::Error,
+    policy::check_uniformity,
+    round::{INPUT_VSIZE, OUTPUT_VSIZE, TX_OVERHEAD_VSIZE},
+    types::{
+        Amount, CounterOffer, Fill, MakerConfig, RejectReason, SignedAmount, TakerConfig,
+        TxFeeRate, VerifyCJInfo, DUST, MAX_FEE_BPS,
+    },
+    utils::estimate_fee_rate,
+};
+
+use bdk::bitcoin::{psbt::PartiallySignedTransaction, Script};
+use log::debug;
+
+use std::cmp::Ordering;
+
+/// Basis points in a whole
+pub const BASIS_POINTS: u64 = 10_000;
+
+/// Parts per million in a whole, relative fees are worked out in them so fees under a basis point are kept
+pub const PPM: u64 = 1_000_000;
+
+/// Furthest over a maker's max size, as a ratio, fills are countered
+pub const MAX_COUNTER_MARGIN: f64 = 0.1;
+
+/// Converts a relative fee given as a fraction, as in offers and config, to parts per million
+/// Fractions of a part are rounded to the nearest, negative fees are 0
+/// ```
+/// use nostrdizer::fees::to_ppm;
+///
+/// assert_eq!(to_ppm(0.0003), 300);
+/// assert_eq!(to_ppm(0.000015), 15);
+/// assert_eq!(to_ppm(0.15), 150_000);
+/// assert_eq!(to_ppm(-0.01), 0);
+/// ```
+pub fn to_ppm(rel_fee: f64) -> u64 {
+    saturating_u64((rel_fee * PPM as f64).round())
+}
+
+/// Whole value of a fee or size worked out as a float
+/// Float to int casts saturate, so NaN and negative values from a bad rate or margin are 0
+fn saturating_u64(value: f64) -> u64 {
+    value as u64
+}
+
+/// Fee of `vsize` vbytes at `fee_rate` sat/vB, rounded up so the rate is met, NaN and negative rates pay nothing
+/// ```
+/// use nostrdizer::{fees::fee_at_rate, types::Amount};
+///
+/// assert_eq!(fee_at_rate(3, 1.5), Amount::from_sat(5));
+/// assert_eq!(fee_at_rate(100, f32::NAN), Amount::ZERO);
+/// assert_eq!(fee_at_rate(100, -1.0), Amount::ZERO);
+/// ```
+pub fn fee_at_rate(vsize: usize, fee_rate: f32) -> Amount {
+    Amount::from_sat(saturating_u64((vsize as f64 * fee_rate as f64).ceil()))
+}
+
+/// Relative fee in parts per million of a send amount, rounded down
+/// ```
+/// use nostrdizer::{fees::rel_fee_amount, types::Amount};
+///
+/// assert_eq!(rel_fee_amount(Amount::from_sat(123_456), 300), Amount::from_sat(37));
+/// assert_eq!(rel_fee_amount(Amount::from_sat(1_000_000), 15), Amount::from_sat(15));
+/// ```
+pub fn rel_fee_amount(send_amount: Amount, ppm: u64) -> Amount {
+    let fee = send_amount.to_sat() as u128 * ppm as u128 / PPM as u128;
+    Amount::from_sat(u64::try_from(fee).unwrap_or(u64::MAX))
+}
+
+/// Compares a fee to a relative fee in parts per million of the send amount without rounding
+fn cmp_rel_fee(fee: SignedAmount, send_amount: Amount, ppm: u64) -> Ordering {
+    let fee = fee.to_sat() as i128 * PPM as i128;
+    fee.cmp(&(send_amount.to_sat() as i128 * ppm as i128))
+}
+
+/// Highest mining fee that will ever be signed for a send amount
+fn max_mining_fee(send_amount: Amount) -> Amount {
+    rel_fee_amount(send_amount, MAX_FEE_BPS * (PPM / BASIS_POINTS))
+}
+
+/// Adds amounts, erroring rather then panicking if the sum is not a valid amount
+pub fn checked_add(a: Amount, b: Amount) -> Result<Amount, Error> {
+    a.checked_add(b).ok_or(Error::AmountOverflow)
+}
+
+/// Subtracts amounts, erroring rather then panicking if there is not enough to subtract from
+pub fn checked_sub(a: Amount, b: Amount) -> Result<Amount, Error> {
+    a.checked_sub(b).ok_or(Error::InsufficientFunds)
+}
+
+/// CJ amount a sweep of `balance` sends, what is left once maker fees and the taker's mining fee are taken out
+/// The taker leaves no change, so all of the balance goes to the CJ output and fees
+/// ```
+/// use nostrdizer::{errors::Error, fees::sweep_amount, types::Amount};
+///
+/// let maker_fees = [Amount::from_sat(1_000), Amount::from_sat(500)];
+/// let amount = sweep_amount(Amount::from_sat(100_000), &maker_fees, Amount::from_sat(2_500)).unwrap();
+/// assert_eq!(amount, Amount::from_sat(96_000));
+///
+/// // Nothing worth a CJ output is left
+/// assert!(matches!(
+///     sweep_amount(Amount::from_sat(4_500), &maker_fees, Amount::from_sat(2_500)),
+///     Err(Error::InsufficientFunds)
+/// ));
+/// ```
+pub fn sweep_amount(
+    balance: Amount,
+    maker_fees: &[Amount],
+    mining_fee: Amount,
+) -> Result<Amount, Error> {
+    let fees = maker_fees
+        .iter()
+        .try_fold(mining_fee, |total, fee| checked_add(total, *fee))?;
+    match checked_sub(balance, fees)? {
+        amount if amount.to_sat() > DUST => Ok(amount),
+        _ => Err(Error::InsufficientFunds),
+    }
+}
+
+/// Mining fee a maker contributes to a CJ, the flat `txfee` unless it offers a fee rate
+/// A fee rate is paid for the vbytes of the maker's inputs, CJ outputs and a change output, up to
+/// its cap. The change output is always counted so the contribution does not depend on the change
+/// ```
+/// use nostrdizer::{fees::maker_txfee, types::{Amount, TxFeeRate}};
+///
+/// let txfee_rate = TxFeeRate {
+///     fee_rate: 2.0,
+///     max_vbytes: 200,
+/// };
+/// // One input and two outputs
+/// assert_eq!(maker_txfee(Amount::ZERO, Some(txfee_rate), 1, 1), Amount::from_sat(260));
+/// // Capped at 200 vbytes
+/// assert_eq!(maker_txfee(Amount::ZERO, Some(txfee_rate), 3, 1), Amount::from_sat(400));
+/// assert_eq!(maker_txfee(Amount::from_sat(300), None, 3, 1), Amount::from_sat(300));
+/// ```
+pub fn maker_txfee(
+    txfee: Amount,
+    txfee_rate: Option<TxFeeRate>,
+    input_count: usize,
+    cj_output_count: usize,
+) -> Amount {
+    match txfee_rate {
+        Some(txfee_rate) => {
+            let vbytes = (input_count * INPUT_VSIZE + (cj_output_count + 1) * OUTPUT_VSIZE)
+                .min(txfee_rate.max_vbytes);
+            // Rounded down as the maker contributes at most the rate
+            Amount::from_sat(saturating_u64(
+                (vbytes as f64 * txfee_rate.fee_rate as f64).floor(),
+            ))
+        }
+        None => txfee,
+    }
+}
+
+/// Fee of a child spending `child_inputs` outputs of a stuck parent so the two pay `fee_rate` together (CPFP)
+/// The child pays at least for its own vbytes at the rate, even when the parent already pays enough
+/// ```
+/// use nostrdizer::{fees::cpfp_fee, types::Amount};
+///
+/// // Parent of 200 vbytes paid 1 sat/vB, the child of 110 vbytes makes up the rest
+/// assert_eq!(cpfp_fee(Amount::from_sat(200), 200, 1, 5.0), Amount::from_sat(1_350));
+/// assert_eq!(cpfp_fee(Amount::from_sat(5_000), 200, 1, 5.0), Amount::from_sat(550));
+/// ```
+pub fn cpfp_fee(
+    parent_fee: Amount,
+    parent_vsize: usize,
+    child_inputs: usize,
+    fee_rate: f32,
+) -> Amount {
+    let child_vsize = TX_OVERHEAD_VSIZE + child_inputs * INPUT_VSIZE + OUTPUT_VSIZE;
+    let package_fee = fee_at_rate(parent_vsize + child_vsize, fee_rate);
+    let child_fee = fee_at_rate(child_vsize, fee_rate);
+    Amount::from_sat(
+        package_fee
+            .to_sat()
+            .saturating_sub(parent_fee.to_sat())
+            .max(child_fee.to_sat()),
+    )
+}
+
+/// Min relay fee rate in sat/vB of bitcoin core, used when the node's can not be asked for
+pub const DEFAULT_MIN_RELAY_FEE_RATE: f32 = 1.0;
+
+/// Fee of a replacement of `vsize` paying `fee_rate`, at least the replaced fee plus its vbytes at the incremental
+/// relay fee rate so nodes relay it (BIP-125)
+/// ```
+/// use nostrdizer::{fees::rbf_fee, types::Amount};
+///
+/// // 200 vbytes that paid 1 sat/vB bumped to 5 sat/vB
+/// assert_eq!(rbf_fee(Amount::from_sat(200), 200, 5.0, 1.0), Amount::from_sat(1_000));
+/// // A rate barely over the replaced one pays at least the incremental relay fee on top
+/// assert_eq!(rbf_fee(Amount::from_sat(200), 200, 1.5, 1.0), Amount::from_sat(400));
+/// ```
+pub fn rbf_fee(
+    replaced_fee: Amount,
+    vsize: usize,
+    fee_rate: f32,
+    incremental_fee_rate: f32,
+) -> Amount {
+    let fee = fee_at_rate(vsize, fee_rate);
+    let min_fee = replaced_fee + fee_at_rate(vsize, incremental_fee_rate);
+    fee.max(min_fee)
+}
+
+/// Fee rate a CJ is built at: the estimate for the confirmation target, at least the min relay fee rate so nodes
+/// relay it and at most the taker's max fee rate. The min relay fee rate is used when fees can not be estimated
+/// ```
+/// use nostrdizer::fees::negotiate_fee_rate;
+///
+/// assert_eq!(negotiate_fee_rate(Some(12.0), 1.0, 100.0), 12.0);
+/// assert_eq!(negotiate_fee_rate(None, 1.0, 100.0), 1.0);
+/// assert_eq!(negotiate_fee_rate(Some(0.5), 1.0, 100.0), 1.0);
+/// assert_eq!(negotiate_fee_rate(Some(150.0), 1.0, 100.0), 100.0);
+/// ```
+pub fn negotiate_fee_rate(
+    estimate: Option<f32>,
+    min_relay_fee_rate: f32,
+    max_fee_rate: f32,
+) -> f32 {
+    estimate
+        .unwrap_or(min_relay_fee_rate)
+        .min(max_fee_rate)
+        .max(min_relay_fee_rate)
+}
+
+/// Value of all inputs and outputs of a CJ and of those that are our own
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CJValues {
+    pub input_value: Amount,
+    pub my_input_value: Amount,
+    pub output_value: Amount,
+    pub my_output_value: Amount,
+}
+
+impl CJValues {
+    pub fn add_input(&mut self, value: Amount, mine: bool) -> Result<(), Error> {
+        if mine {
+            self.my_input_value = checked_add(self.my_input_value, value)?;
+        }
+        self.input_value = checked_add(self.input_value, value)?;
+        Ok(())
+    }
+
+    pub fn add_output(&mut self, value: Amount, mine: bool) -> Result<(), Error> {
+        if mine {
+            self.my_output_value = checked_add(self.my_output_value, value)?;
+        }
+        self.output_value = checked_add(self.output_value, value)?;
+        Ok(())
+    }
+
+    /// Fee paid to miners, the value of the inputs not spent to outputs
+    pub fn mining_fee(&self) -> Result<Amount, Error> {
+        checked_sub(self.input_value, self.output_value)
+    }
+}
+
+/// Sums the values of a psbt using the `witness_utxo` of its inputs
+/// `is_mine` decides which scripts are our own
+pub fn psbt_values(
+    psbt: &PartiallySignedTransaction,
+    mut is_mine: impl FnMut(&Script) -> Result<bool, Error>,
+) -> Result<CJValues, Error> {
+    let mut values = CJValues::default();
+    for input in &psbt.inputs {
+        // An input of unknown value would hide part of the mining fee
+        let utxo = input.witness_utxo.as_ref().ok_or(Error::BadInput)?;
+        values.add_input(Amount::from_sat(utxo.value), is_mine(&utxo.script_pubkey)?)?;
+    }
+    for output in &psbt.unsigned_tx.output {
+        values.add_output(
+            Amount::from_sat(output.value),
+            is_mine(&output.script_pubkey)?,
+        )?;
+    }
+
+    Ok(values)
+}
+
+/// Which of the taker's fee caps a CJ is within
+/// Maker fees and mining fees are capped independently
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TakerFeeCheck {
+    pub maker_abs_fee: bool,
+    pub maker_rel_fee: bool,
+    pub mining_abs_fee: bool,
+    pub mining_rel_fee: bool,
+    pub mining_fee_rate: bool,
+}
+
+impl TakerFeeCheck {
+    pub fn new(
+        config: &TakerConfig,
+        send_amount: Amount,
+        maker_fee: SignedAmount,
+        mining_fee: SignedAmount,
+        fee_rate: f32,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            maker_abs_fee: maker_fee.le(&config.cj_fee.abs_fee.to_signed()?),
+            maker_rel_fee: cmp_rel_fee(maker_fee, send_amount, to_ppm(config.cj_fee.rel_fee))
+                .is_le(),
+            mining_abs_fee: mining_fee.le(&config.mining_fee.abs_fee.to_signed()?),
+            mining_rel_fee: cmp_rel_fee(mining_fee, send_amount, to_ppm(config.mining_fee.rel_fee))
+                .is_le(),
+            mining_fee_rate: fee_rate.le(&config.mining_fee.fee_rate),
+        })
+    }
+
+    pub fn passed(&self) -> bool {
+        self.maker_abs_fee
+            && self.maker_rel_fee
+            && self.mining_abs_fee
+            && self.mining_rel_fee
+            && self.mining_fee_rate
+    }
+}
+
+/// Errors when the fee estimate for the next block is over the max fee rate the taker pays
+/// A round started now would build a CJ the taker can not sign
+pub fn check_fee_estimate(config: &TakerConfig, estimate: f32) -> Result<(), Error> {
+    if estimate > config.mining_fee.fee_rate {
+        return Err(Error::FeeSpike(estimate, config.mining_fee.fee_rate));
+    }
+    Ok(())
+}
+
+/// Taker verification of the fees paid by a CJ
+/// `my_input_value` and `my_output_value` are the taker's own inputs and outputs
+pub fn verify_taker_fees(
+    config: &TakerConfig,
+    send_amount: Amount,
+    my_input_value: Amount,
+    my_output_value: Amount,
+    mining_fee: Amount,
+    fee_rate: f32,
+) -> Result<VerifyCJInfo, Error> {
+    if mining_fee.gt(&max_mining_fee(send_amount)) {
+        return Err(Error::FeesTooHigh);
+    }
+    let mining_fee = mining_fee.to_signed()?;
+    let maker_fee = my_input_value
+        .to_signed()?
+        .checked_sub(my_output_value.to_signed()?)
+        .and_then(|fee| fee.checked_sub(mining_fee))
+        .ok_or(Error::AmountOverflow)?;
+
+    let fee_check = TakerFeeCheck::new(config, send_amount, maker_fee, mining_fee, fee_rate)?;
+    debug!("Fee check: {fee_check:?}");
+
+    Ok(VerifyCJInfo {
+        mining_fee,
+        maker_fee,
+        fee_rate,
+        verifyed: fee_check.passed(),
+        reject_reason: None,
+    })
+}
+
+/// Taker verification of a CJ from the values of its inputs and outputs
+/// The payment of a payment round is the taker's own output, it is not a fee to makers
+/// Change makers donate to the mining fee is not held against the taker's max mining fee
+pub fn verify_taker_cj(
+    config: &TakerConfig,
+    send_amount: Amount,
+    values: &CJValues,
+    psbt: &PartiallySignedTransaction,
+    donated_change: Amount,
+) -> Result<VerifyCJInfo, Error> {
+    let mining_fee = values
+        .mining_fee()?
+        .checked_sub(donated_change)
+        .ok_or(Error::TransactionNotVerified)?;
+    let payment = config.payment.as_ref().map(|payment| payment.amount);
+    let tx_info = verify_taker_fees(
+        config,
+        send_amount,
+        values.my_input_value,
+        checked_add(values.my_output_value, payment.unwrap_or(Amount::ZERO))?,
+        mining_fee,
+        estimate_fee_rate(psbt, mining_fee),
+    )?;
+    Ok(check_uniformity(
+        tx_info,
+        psbt,
+        &[send_amount],
+        payment,
+        Amount::ZERO,
+    ))
+}
+
+/// Number of parties in a CJ, counted by outputs of the send amount
+pub fn count_participants(psbt: &PartiallySignedTransaction, send_amount: Amount) -> usize {
+    psbt.unsigned_tx
+        .output
+        .iter()
+        .filter(|output| output.value == send_amount.to_sat())
+        .count()
+}
+
+/// Gets the first maker policy a CJ fails, if any
+pub(crate) fn reject_reason(
+    config: &MakerConfig,
+    fee_check: bool,
+    amount_check: bool,
+    fee_rate: f32,
+    participants: Option<usize>,
+) -> Option<RejectReason> {
+    if !fee_check {
+        return Some(RejectReason::CJFeeTooLow);
+    }
+    if !amount_check {
+        return Some(RejectReason::AmountOutOfRange);
+    }
+    if let (Some(participants), Some(min_participants)) = (participants, config.min_participants) {
+        if participants < min_participants {
+            return Some(RejectReason::TooFewParticipants {
+                participants,
+                min_participants,
+            });
+        }
+    }
+    match config.min_fee_rate {
+        Some(min_fee_rate) if fee_rate < min_fee_rate => Some(RejectReason::FeeRateTooLow {
+            fee_rate,
+            min_fee_rate,
+        }),
+        _ => None,
+    }
+}
+
+/// The maker's side of a CJ, what the fees it earns are verified on
+#[derive(Debug, Clone, Copy)]
+pub struct MakerCJ {
+    pub send_amount: Amount,
+    /// Value of the maker's own inputs
+    pub my_input_value: Amount,
+    /// Value of the maker's own outputs
+    pub my_output_value: Amount,
+    pub mining_fee: Amount,
+    pub fee_rate: f32,
+    /// Only checked against the maker's minimum when it is known
+    pub participants: Option<usize>,
+    /// Mining fee the maker offered to contribute, it is not counted against its fee
+    pub txfee: Amount,
+}
+
+/// Maker verification of the fees earned from a CJ
+pub fn verify_maker_fees(config: &MakerConfig, cj: &MakerCJ) -> Result<VerifyCJInfo, Error> {
+    let MakerCJ {
+        send_amount,
+        my_input_value,
+        my_output_value,
+        mining_fee,
+        fee_rate,
+        participants,
+        txfee,
+    } = *cj;
+    let mining_fee = mining_fee.to_signed()?;
+    let maker_fee = my_output_value
+        .to_signed()?
+        .checked_sub(my_input_value.to_signed()?)
+        .ok_or(Error::AmountOverflow)?;
+    let fee_before_txfee = maker_fee
+        .checked_add(txfee.to_signed()?)
+        .ok_or(Error::AmountOverflow)?;
+
+    // Verify maker gets >= set fee
+    let abs_fee_check = fee_before_txfee.ge(&config.abs_fee.to_signed()?);
+    let rel_fee_check = cmp_rel_fee(fee_before_txfee, send_amount, to_ppm(config.rel_fee)).is_ge();
+    let max_amount_check = match &config.maxsize {
+        Some(max_size) => send_amount.le(max_size),
+        None => true,
+    };
+    // Maker inputs are checked too as the taker builds the CJ
+    let round_cap_check = match &config.max_per_round {
+        Some(max_per_round) => send_amount.le(max_per_round) && my_input_value.le(max_per_round),
+        None => true,
+    };
+    debug!(
+        "Maker fee: {} abs: {abs_fee_check} rel: {rel_fee_check}",
+        display::signed_sats(maker_fee)
+    );
+    debug!("Fee rate: {}", display::fee_rate(fee_rate));
+
+    let reject_reason = reject_reason(
+        config,
+        abs_fee_check && rel_fee_check,
+        max_amount_check && round_cap_check && send_amount.ge(&config.minsize),
+        fee_rate,
+        participants,
+    );
+    Ok(VerifyCJInfo {
+        mining_fee,
+        maker_fee,
+        fee_rate,
+        verifyed: reject_reason.is_none(),
+        reject_reason,
+    })
+}
+
+/// Maker verification of a CJ from the values of its inputs and outputs
+/// Participants are counted from the outputs of `participant_amount`
+pub fn verify_maker_cj(
+    config: &MakerConfig,
+    send_amount: Amount,
+    participant_amount: Amount,
+    txfee: Amount,
+    values: &CJValues,
+    psbt: &PartiallySignedTransaction,
+) -> Result<VerifyCJInfo, Error> {
+    let mining_fee = values.mining_fee()?;
+    let cj = MakerCJ {
+        send_amount,
+        my_input_value: values.my_input_value,
+        my_output_value: values.my_output_value,
+        mining_fee,
+        fee_rate: estimate_fee_rate(psbt, mining_fee),
+        participants: Some(count_participants(psbt, participant_amount)),
+        txfee,
+    };
+    verify_maker_fees(config, &cj)
+}
+
+/// Counter-offer of a maker for a fill of amount just over its max size
+/// None when the amount is within the max size, further over it than the margin or over the max per round
+/// The fee is what the maker asks for the amount raised by the margin
+/// ```
+/// use nostrdizer::{fees::counter_offer, test_utils::maker_config, types::Amount};
+///
+/// let mut config = maker_config();
+/// config.maxsize = Some(Amount::from_sat(100_000));
+/// config.counter_offer_margin = Some(0.05);
+///
+/// let counter = counter_offer(&config, Amount::from_sat(104_000)).unwrap();
+/// assert_eq!(counter.maxsize, Amount::from_sat(104_000));
+/// assert_eq!(counter.cjfee, Amount::from_sat(1_050));
+/// assert_eq!(counter_offer(&config, Amount::from_sat(100_000)), None);
+/// assert_eq!(counter_offer(&config, Amount::from_sat(106_000)), None);
+/// ```
+pub fn counter_offer(config: &MakerConfig, amount: Amount) -> Option<CounterOffer> {
+    let margin = config.counter_offer_margin?.clamp(0.0, MAX_COUNTER_MARGIN);
+    let maxsize = config.maxsize?;
+    // A NaN margin reaches nothing, so nothing is countered
+    let reach = Amount::from_sat(saturating_u64(
+        (maxsize.to_sat() as f64 * (1.0 + margin)).floor(),
+    ));
+    if amount <= maxsize
+        || amount > reach
+        || matches!(config.max_per_round, Some(max_per_round) if amount > max_per_round)
+    {
+        return None;
+    }
+    let fee = config
+        .abs_fee
+        .max(rel_fee_amount(amount, to_ppm(config.rel_fee)));
+    Some(CounterOffer {
+        maxsize: amount,
+        cjfee: Amount::from_sat((fee.to_sat() as f64 * (1.0 + margin)).ceil() as u64),
+    })
+}
+
+/// Checks a fill is within the maker's max size, or takes a counter-offer the taker agreed to
+/// Fills just over the max size are countered if the taker takes counter-offers
+pub fn check_fill_amount(
+    config: &MakerConfig,
+    fill: &Fill,
+) -> Result<Option<CounterOffer>, RejectReason> {
+    match config.maxsize {
+        Some(maxsize) if fill.amount > maxsize => (),
+        _ => return Ok(None),
+    }
+    match counter_offer(config, fill.amount) {
+        Some(counter) if matches!(fill.cjfee, Some(cjfee) if cjfee >= counter.cjfee) => {
+            Ok(Some(counter))
+        }
+        Some(counter) if fill.counter => Err(RejectReason::CounterOffer(counter)),
+        _ => Err(RejectReason::AmountOutOfRange),
+    }
+}
+
+/// Most CJ fee the taker pays a maker for the send amount, within both of its maker fee limits
+/// as the CJ is checked against both
+pub fn max_cj_fee(config: &TakerConfig, send_amount: Amount) -> Amount {
+    config
+        .cj_fee
+        .abs_fee
+        .min(rel_fee_amount(send_amount, to_ppm(config.cj_fee.rel_fee)))
+}
+
+/// Whether the taker takes a counter-offer for the send amount
+pub fn accepts_counter_offer(
+    config: &TakerConfig,
+    send_amount: Amount,
+    counter: &CounterOffer,
+) -> bool {
+    config.accept_counter_offers
+        && counter.maxsize >= send_amount
+        && counter.cjfee <= max_cj_fee(config, send_amount)
+}
+
+/// Whether an offer's max size is enough for the send amount
+/// Offers up to the counter margin under it are taken when the taker accepts counter-offers
+pub fn within_maxsize(config: &TakerConfig, maxsize: Amount, send_amount: Amount) -> bool {
+    if config.accept_counter_offers {
+        maxsize.to_sat() as f64 * (1.0 + MAX_COUNTER_MARGIN) >= send_amount.to_sat() as f64
+    } else {
+        maxsize > send_amount
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cosign::CoSigning,
+        maker_selection::MakerSelection,
+        publication::PublishQuorum,
+        relay_auth::RelayAuthConfig,
+        test_utils::{maker_config, psbt},
+        types::{CJFee, InputLimits, MaxMineingFee},
+    };
+    use bdk::bitcoin::{OutPoint, TxOut};
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    use std::collections::HashMap;
+
+    fn taker_config() -> TakerConfig {
+        TakerConfig {
+            cj_fee: CJFee {
+                abs_fee: Amount::from_sat(1000),
+                rel_fee: 0.01,
+            },
+            mining_fee: MaxMineingFee {
+                abs_fee: Amount::from_sat(2000),
+                rel_fee: 0.05,
+                fee_rate: 10.0,
+            },
+            minium_makers: 1,
+            allow_unconfirmed: false,
+            script_type: None,
+            share_final_tx: false,
+            spare_makers: 0,
+            publish_quorum: PublishQuorum::default(),
+            accept_counter_offers: false,
+            split_change: false,
+            payment: None,
+            wait_for_fees: false,
+            invite: None,
+            relay_auth: RelayAuthConfig::default(),
+            cosigning: CoSigning::default(),
+            archive_dir: None,
+            min_bond_value: None,
+            maker_selection: MakerSelection::default(),
+            input_limits: InputLimits::default(),
+            fiat: None,
+            coop_partner: None,
+            conf_target: 1,
+            link_sources: false,
+            sweep: false,
+        }
+    }
+
+    #[test]
+    fn test_taker_fees_within_caps() {
+        let send_amount = Amount::from_sat(100_000);
+        // Taker pays 500 to makers and 1500 to miners
+        let info = verify_taker_fees(
+            &taker_config(),
+            send_amount,
+            Amount::from_sat(150_000),
+            Amount::from_sat(148_000),
+            Amount::from_sat(1500),
+            5.0,
+        )
+        .unwrap();
+
+        assert_eq!(info.maker_fee, SignedAmount::from_sat(500));
+        assert!(info.verifyed);
+    }
+
+    #[test]
+    fn test_mining_fee_does_not_use_maker_cap() {
+        let send_amount = Amount::from_sat(100_000);
+        let check = TakerFeeCheck::new(
+            &taker_config(),
+            send_amount,
+            SignedAmount::from_sat(100),
+            SignedAmount::from_sat(1500),
+            5.0,
+        )
+        .unwrap();
+        assert!(check.passed());
+
+        let check = TakerFeeCheck::new(
+            &taker_config(),
+            send_amount,
+            SignedAmount::from_sat(1500),
+            SignedAmount::from_sat(100),
+            5.0,
+        )
+        .unwrap();
+        assert!(!check.maker_abs_fee);
+        assert!(check.mining_abs_fee);
+    }
+
+    #[test]
+    fn test_fee_rate_cap() {
+        let check = TakerFeeCheck::new(
+            &taker_config(),
+            Amount::from_sat(100_000),
+            SignedAmount::from_sat(100),
+            SignedAmount::from_sat(1500),
+            20.0,
+        )
+        .unwrap();
+        assert!(!check.mining_fee_rate);
+        assert!(!check.passed());
+
+        assert!(check_fee_estimate(&taker_config(), 10.0).is_ok());
+        assert!(matches!(
+            check_fee_estimate(&taker_config(), 20.0),
+            Err(Error::FeeSpike(..))
+        ));
+    }
+
+    #[test]
+    fn test_max_fee() {
+        let result = verify_taker_fees(
+            &taker_config(),
+            Amount::from_sat(10_000),
+            Amount::from_sat(20_000),
+            Amount::from_sat(10_000),
+            Amount::from_sat(5000),
+            5.0,
+        );
+        assert!(matches!(result, Err(Error::FeesTooHigh)));
+    }
+
+    #[test]
+    fn test_rel_fee_at_cap() {
+        let mut config = taker_config();
+        config.cj_fee = CJFee {
+            abs_fee: Amount::MAX_MONEY,
+            rel_fee: 0.0003,
+        };
+        let send_amount = Amount::from_sat(1_000_000);
+        let check = |maker_fee| {
+            TakerFeeCheck::new(
+                &config,
+                send_amount,
+                SignedAmount::from_sat(maker_fee),
+                SignedAmount::ZERO,
+                1.0,
+            )
+            .unwrap()
+            .maker_rel_fee
+        };
+
+        assert!(check(300));
+        assert!(!check(301));
+    }
+
+    #[test]
+    fn test_sub_basis_point_rel_fee() {
+        let mut config = taker_config();
+        // 1.5 basis points, rounding to basis points would allow 200
+        config.cj_fee = CJFee {
+            abs_fee: Amount::MAX_MONEY,
+            rel_fee: 0.00015,
+        };
+        let send_amount = Amount::from_sat(1_000_000);
+        let check = |maker_fee| {
+            TakerFeeCheck::new(
+                &config,
+                send_amount,
+                SignedAmount::from_sat(maker_fee),
+                SignedAmount::ZERO,
+                1.0,
+            )
+            .unwrap()
+            .maker_rel_fee
+        };
+
+        assert!(check(150));
+        assert!(!check(151));
+        assert_eq!(max_cj_fee(&config, send_amount), Amount::from_sat(150));
+    }
+
+    #[test]
+    fn test_overspend_is_error() {
+        assert!(matches!(
+            checked_sub(Amount::from_sat(1), Amount::from_sat(2)),
+            Err(Error::InsufficientFunds)
+        ));
+        assert!(matches!(
+            checked_add(Amount::from_sat(u64::MAX), Amount::from_sat(1)),
+            Err(Error::AmountOverflow)
+        ));
+
+        let mut values = CJValues::default();
+        values.add_input(Amount::from_sat(1000), true).unwrap();
+        values.add_output(Amount::from_sat(2000), false).unwrap();
+        assert!(matches!(values.mining_fee(), Err(Error::InsufficientFunds)));
+    }
+
+    #[test]
+    fn test_min_participants() {
+        let mut config = maker_config();
+        config.min_participants = Some(3);
+        // Taker and one maker, the maker earns 1000 sats
+        let psbt = psbt(&[150_000, 101_000], &[100_000, 48_000, 100_000, 2_000]);
+        let values = CJValues {
+            input_value: Amount::from_sat(251_000),
+            my_input_value: Amount::from_sat(101_000),
+            output_value: Amount::from_sat(250_000),
+            my_output_value: Amount::from_sat(102_000),
+        };
+
+        let send_amount = Amount::from_sat(100_000);
+        let info = verify_maker_cj(
+            &config,
+            send_amount,
+            send_amount,
+            Amount::ZERO,
+            &values,
+            &psbt,
+        )
+        .unwrap();
+        assert_eq!(
+            info.reject_reason,
+            Some(RejectReason::TooFewParticipants {
+                participants: 2,
+                min_participants: 3
+            })
+        );
+
+        config.min_participants = Some(2);
+        let info = verify_maker_cj(
+            &config,
+            send_amount,
+            send_amount,
+            Amount::ZERO,
+            &values,
+            &psbt,
+        )
+        .unwrap();
+        assert!(info.verifyed);
+    }
+
+    #[test]
+    fn test_maker_txfee() {
+        let config = maker_config();
+        // Maker earns its 1000 sat fee but pays 200 sats to the mining fee
+        let psbt = psbt(&[150_000, 101_000], &[100_000, 48_200, 100_000, 1_800]);
+        let values = CJValues {
+            input_value: Amount::from_sat(251_000),
+            my_input_value: Amount::from_sat(101_000),
+            output_value: Amount::from_sat(250_000),
+            my_output_value: Amount::from_sat(101_800),
+        };
+
+        let send_amount = Amount::from_sat(100_000);
+        let info = verify_maker_cj(
+            &config,
+            send_amount,
+            send_amount,
+            Amount::from_sat(200),
+            &values,
+            &psbt,
+        )
+        .unwrap();
+        assert!(info.verifyed);
+        assert_eq!(info.maker_fee, SignedAmount::from_sat(800));
+
+        // Taker can not take more then the maker offered
+        let info = verify_maker_cj(
+            &config,
+            send_amount,
+            send_amount,
+            Amount::from_sat(100),
+            &values,
+            &psbt,
+        )
+        .unwrap();
+        assert_eq!(info.reject_reason, Some(RejectReason::CJFeeTooLow));
+    }
+
+    #[test]
+    fn test_maker_round_cap() {
+        let mut config = maker_config();
+        config.max_per_round = Some(Amount::from_sat(150_000));
+        let send_amount = Amount::from_sat(100_000);
+        let verify = |config: &MakerConfig, my_input_value| {
+            let cj = MakerCJ {
+                send_amount,
+                my_input_value: Amount::from_sat(my_input_value),
+                my_output_value: Amount::from_sat(my_input_value + 1000),
+                mining_fee: Amount::from_sat(500),
+                fee_rate: 5.0,
+                participants: None,
+                txfee: Amount::ZERO,
+            };
+            verify_maker_fees(config, &cj).unwrap()
+        };
+
+        assert!(verify(&config, 120_000).verifyed);
+        // More of the maker's balance than the cap would enter the round
+        assert_eq!(
+            verify(&config, 200_000).reject_reason,
+            Some(RejectReason::AmountOutOfRange)
+        );
+        config.max_per_round = None;
+        assert!(verify(&config, 200_000).verifyed);
+    }
+
+    #[test]
+    fn test_maker_fee_rate_floor() {
+        let mut config = maker_config();
+        config.min_fee_rate = Some(5.0);
+        let verify = |fee_rate| {
+            let cj = MakerCJ {
+                send_amount: Amount::from_sat(100_000),
+                my_input_value: Amount::from_sat(120_000),
+                my_output_value: Amount::from_sat(121_000),
+                mining_fee: Amount::from_sat(500),
+                fee_rate,
+                participants: None,
+                txfee: Amount::ZERO,
+            };
+            verify_maker_fees(&config, &cj).unwrap()
+        };
+
+        assert!(verify(5.0).verifyed);
+        let info = verify(2.0);
+        assert!(!info.verifyed);
+        assert_eq!(
+            info.reject_reason,
+            Some(RejectReason::FeeRateTooLow {
+                fee_rate: 2.0,
+                min_fee_rate: 5.0
+            })
+        );
+    }
+
+    /// Synthetic CJ between a taker (owner 0) and makers (owners 1..)
+    /// with the fees each party is known to pay or earn
+    struct SyntheticCJ {
+        psbt: PartiallySignedTransaction,
+        send_amount: Amount,
+        cj_fees: Vec<u64>,
+        mining_fee: u64,
+    }
+
+    fn owner_script(owner: usize) -> Script {
+        Script::from(vec![owner as u8])
+    }
+
+    fn synthetic_cj(rng: &mut StdRng) -> SyntheticCJ {
+        let send_amount = rng.gen_range(10_000..10_000_000);
+        let cj_fees: Vec<u64> = (0..rng.gen_range(1..5))
+            .map(|_| rng.gen_range(0..5_000))
+            .collect();
+        let mining_fee = rng.gen_range(0..send_amount / 10);
+
+        // (value, owner) of each input and output
+        let mut inputs = vec![];
+        let mut outputs = vec![];
+        let taker_input = send_amount + cj_fees.iter().sum::<u64>() + mining_fee;
+        let taker_change = rng.gen_range(0..1_000_000);
+        inputs.push((taker_input + taker_change, 0));
+        outputs.push((send_amount, 0));
+        outputs.push((taker_change, 0));
+        for (i, cj_fee) in cj_fees.iter().enumerate() {
+            let maker_change = rng.gen_range(0..1_000_000);
+            // Makers may spend more then one input
+            let split = rng.gen_range(0..=send_amount);
+            inputs.push((split, i + 1));
+            inputs.push((send_amount - split + maker_change, i + 1));
+            outputs.push((send_amount, i + 1));
+            outputs.push((maker_change + cj_fee, i + 1));
+        }
+
+        let input_values: Vec<u64> = inputs.iter().map(|(v, _)| *v).collect();
+        let output_values: Vec<u64> = outputs.iter().map(|(v, _)| *v).collect();
+        let mut psbt = psbt(&input_values, &output_values);
+        for (input, (_, owner)) in psbt.inputs.iter_mut().zip(&inputs) {
+            input.witness_utxo.as_mut().unwrap().script_pubkey = owner_script(*owner);
+        }
+        for (output, (_, owner)) in psbt.unsigned_tx.output.iter_mut().zip(&outputs) {
+            output.script_pubkey = owner_script(*owner);
+        }
+
+        SyntheticCJ {
+            psbt,
+            send_amount: Amount::from_sat(send_amount),
+            cj_fees,
+            mining_fee,
+        }
+    }
+
+    /// Values as the BDK backend gets them, from the psbt
+    fn bdk_values(cj: &SyntheticCJ, owner: usize) -> CJValues {
+        psbt_values(&cj.psbt, |script| Ok(script == &owner_script(owner))).unwrap()
+    }
+
+    /// Values as the Core backend gets them, looking up the outputs spent by the tx
+    fn core_values(cj: &SyntheticCJ, owner: usize) -> CJValues {
+        let utxos: HashMap<OutPoint, TxOut> = cj
+            .psbt
+            .unsigned_tx
+            .input
+            .iter()
+            .zip(&cj.psbt.inputs)
+            .map(|(txin, input)| (txin.previous_output, input.witness_utxo.clone().unwrap()))
+            .collect();
+
+        let mut values = CJValues::default();
+        for txin in &cj.psbt.unsigned_tx.input {
+            let utxo = &utxos[&txin.previous_output];
+            values
+                .add_input(
+                    Amount::from_sat(utxo.value),
+                    utxo.script_pubkey == owner_script(owner),
+                )
+                .unwrap();
+        }
+        for output in &cj.psbt.unsigned_tx.output {
+            values
+                .add_output(
+                    Amount::from_sat(output.value),
+                    output.script_pubkey == owner_script(owner),
+                )
+                .unwrap();
+        }
+        values
+    }
+
+    #[test]
+    fn test_backends_agree_with_oracle() {
+        let mut rng = StdRng::seed_from_u64(1935);
+        for _ in 0..500 {
+            let cj = synthetic_cj(&mut rng);
+            // Taker was not limited by the max fee when building the CJ
+            let mut config = taker_config();
+            config.mining_fee.abs_fee = Amount::MAX_MONEY;
+
+            let total_cj_fee = cj.cj_fees.iter().sum::<u64>() as i64;
+            let (bdk, core) = (bdk_values(&cj, 0), core_values(&cj, 0));
+            assert_eq!(bdk, core);
+            assert_eq!(bdk.mining_fee().unwrap(), Amount::from_sat(cj.mining_fee));
+
+            let bdk_info =
+                verify_taker_cj(&config, cj.send_amount, &bdk, &cj.psbt, Amount::ZERO).unwrap();
+            let core_info =
+                verify_taker_cj(&config, cj.send_amount, &core, &cj.psbt, Amount::ZERO).unwrap();
+            for info in [&bdk_info, &core_info] {
+                assert_eq!(info.maker_fee, SignedAmount::from_sat(total_cj_fee));
+                assert_eq!(
+                    info.mining_fee,
+                    SignedAmount::from_sat(cj.mining_fee as i64)
+                );
+            }
+            assert_eq!(bdk_info.verifyed, core_info.verifyed);
+
+            for (i, cj_fee) in cj.cj_fees.iter().enumerate() {
+                let (bdk, core) = (bdk_values(&cj, i + 1), core_values(&cj, i + 1));
+                assert_eq!(bdk, core);
+
+                let bdk_info = verify_maker_cj(
+                    &maker_config(),
+                    cj.send_amount,
+                    cj.send_amount,
+                    Amount::ZERO,
+                    &bdk,
+                    &cj.psbt,
+                )
+                .unwrap();
+                let core_info = verify_maker_cj(
+                    &maker_config(),
+                    cj.send_amount,
+                    cj.send_amount,
+                    Amount::ZERO,
+                    &core,
+                    &cj.psbt,
+                )
+                .unwrap();
+                for info in [&bdk_info, &core_info] {
+                    // Makers see what they earn as positive, as the taker sees what it pays
+                    assert_eq!(info.maker_fee, SignedAmount::from_sat(*cj_fee as i64));
+                    assert_eq!(
+                        info.mining_fee,
+                        SignedAmount::from_sat(cj.mining_fee as i64)
+                    );
+                    assert_eq!(info.verifyed, *cj_fee >= 1000);
+                }
+                assert_eq!(bdk_info.reject_reason, core_info.reject_reason);
+            }
+        }
+    }
+
+    #[test]
+    fn test_unknown_input_value() {
+        let mut psbt = psbt(&[20_000], &[10_000]);
+        psbt.inputs[0].witness_utxo = None;
+        assert!(matches!(
+            psbt_values(&psbt, |_| Ok(true)),
+            Err(Error::BadInput)
+        ));
+    }
+}
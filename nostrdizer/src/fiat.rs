@@ -0,0 +1,212 @@
+use crate::{errors::Error, types::SignedAmount};
+
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Seconds a fetched price is used before it is fetched again
+pub const PRICE_MAX_AGE: u64 = 3600;
+
+/// Price API queried with the `fiat` feature, `{currency}` is replaced by the lowercase currency code
+pub const DEFAULT_PRICE_URL: &str =
+    "https://api.coingecko.com/api/v3/simple/price?ids=bitcoin&vs_currencies={currency}";
+
+/// Currency amounts are also shown in, only for display, the protocol only deals in sats
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FiatConfig {
+    /// Currency code, such as USD
+    pub currency: String,
+    /// File the last fetched prices are kept in, used when the source can not be reached
+    pub cache_path: PathBuf,
+    /// Seconds a cached price is used before it is fetched again
+    pub max_age: u64,
+}
+
+/// Price of a BTC in a currency
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Price {
+    pub currency: String,
+    pub btc_price: f64,
+    pub fetched_at: u64,
+}
+
+impl Price {
+    /// Value of sats in the currency
+    /// ```
+    /// use nostrdizer::{fiat::Price, types::SignedAmount};
+    ///
+    /// let price = Price {
+    ///     currency: "USD".to_string(),
+    ///     btc_price: 30_000.0,
+    ///     fetched_at: 0,
+    /// };
+    /// assert_eq!(price.value(SignedAmount::from_sat(10_000)), 3.0);
+    /// ```
+    pub fn value(&self, amount: SignedAmount) -> f64 {
+        amount.to_btc() * self.btc_price
+    }
+}
+
+/// Where BTC prices are fetched from
+pub trait PriceSource {
+    fn btc_price(&self, currency: &str) -> Result<f64, Error>;
+}
+
+/// Last price fetched in each currency, kept between runs
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct PriceCache {
+    pub prices: HashMap<String, Price>,
+}
+
+impl PriceCache {
+    /// Loads the cache from path, empty if there is no file
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        match path.exists() {
+            true => Ok(serde_json::from_str(&fs::read_to_string(path)?)?),
+            false => Ok(Self::default()),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        Ok(fs::write(path, serde_json::to_string_pretty(self)?)?)
+    }
+
+    /// Price in currency, fetched from the source once the cached one is older than max age
+    /// When the source fails or there is none the cached price is used however old, none if there is none
+    /// ```
+    /// use nostrdizer::{
+    ///     errors::Error,
+    ///     fiat::{PriceCache, PriceSource},
+    /// };
+    ///
+    /// struct Fixed(f64);
+    /// impl PriceSource for Fixed {
+    ///     fn btc_price(&self, _currency: &str) -> Result<f64, Error> {
+    ///         Ok(self.0)
+    ///     }
+    /// }
+    ///
+    /// let mut cache = PriceCache::default();
+    /// let price = cache.price(Some(&Fixed(30_000.0)), "USD", 3600, 0).unwrap();
+    /// assert_eq!(price.btc_price, 30_000.0);
+    ///
+    /// // Fresh enough, not fetched again
+    /// let price = cache.price(Some(&Fixed(40_000.0)), "USD", 3600, 100).unwrap();
+    /// assert_eq!(price.btc_price, 30_000.0);
+    ///
+    /// // Offline, the last price is kept
+    /// assert_eq!(cache.price(None, "USD", 3600, 10_000).unwrap().fetched_at, 0);
+    /// assert!(cache.price(None, "EUR", 3600, 10_000).is_none());
+    /// ```
+    pub fn price(
+        &mut self,
+        source: Option<&dyn PriceSource>,
+        currency: &str,
+        max_age: u64,
+        now: u64,
+    ) -> Option<Price> {
+        let currency = currency.to_uppercase();
+        if let Some(price) = self.prices.get(&currency) {
+            if now.saturating_sub(price.fetched_at) < max_age {
+                return Some(price.clone());
+            }
+        }
+        if let Some(source) = source {
+            match source.btc_price(&currency) {
+                Ok(btc_price) => {
+                    let price = Price {
+                        currency: currency.clone(),
+                        btc_price,
+                        fetched_at: now,
+                    };
+                    self.prices.insert(currency.clone(), price);
+                }
+                Err(err) => debug!("Could not fetch the BTC price in {currency}: {err}"),
+            }
+        }
+        self.prices.get(&currency).cloned()
+    }
+}
+
+/// Prices from a JSON API that answers `{"bitcoin": {"<currency>": <price>}}`, as CoinGecko does
+#[cfg(feature = "fiat")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpPriceSource {
+    /// Url with `{currency}` where the lowercase currency code goes
+    pub url: String,
+}
+
+#[cfg(feature = "fiat")]
+impl Default for HttpPriceSource {
+    fn default() -> Self {
+        Self {
+            url: DEFAULT_PRICE_URL.to_string(),
+        }
+    }
+}
+
+#[cfg(feature = "fiat")]
+impl PriceSource for HttpPriceSource {
+    fn btc_price(&self, currency: &str) -> Result<f64, Error> {
+        let currency = currency.to_lowercase();
+        let url = self.url.replace("{currency}", &currency);
+        let response: serde_json::Value = ureq::get(&url)
+            .timeout(std::time::Duration::from_secs(10))
+            .call()
+            .map_err(|err| Error::PriceUnavailable(err.to_string()))?
+            .into_json()?;
+        response["bitcoin"][&currency]
+            .as_f64()
+            .ok_or_else(|| Error::PriceUnavailable(format!("no {currency} price in {response}")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Failing;
+
+    impl PriceSource for Failing {
+        fn btc_price(&self, _currency: &str) -> Result<f64, Error> {
+            Err(Error::PriceUnavailable("offline".to_string()))
+        }
+    }
+
+    #[test]
+    fn test_stale_price_kept_offline() {
+        let mut cache = PriceCache::default();
+        cache.prices.insert(
+            "EUR".to_string(),
+            Price {
+                currency: "EUR".to_string(),
+                btc_price: 25_000.0,
+                fetched_at: 0,
+            },
+        );
+        // Currency codes are not case sensitive
+        let price = cache.price(Some(&Failing), "eur", 3600, 7200).unwrap();
+        assert_eq!(price.btc_price, 25_000.0);
+        assert!(cache.price(Some(&Failing), "USD", 3600, 7200).is_none());
+    }
+
+    #[test]
+    fn test_save_load() {
+        let path = std::env::temp_dir().join("nostrdizer-prices.json");
+        let mut cache = PriceCache::default();
+        cache.prices.insert(
+            "USD".to_string(),
+            Price {
+                currency: "USD".to_string(),
+                btc_price: 30_000.5,
+                fetched_at: 10,
+            },
+        );
+        cache.save(&path).unwrap();
+        assert_eq!(PriceCache::load(&path).unwrap(), cache);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,446 @@
+use crate::{errors::Error, types::Amount};
+
+use bdk::bitcoin::{
+    blockdata::{
+        opcodes::all::{OP_CHECKSIGVERIFY, OP_CLTV},
+        script::Builder,
+    },
+    hashes::sha256,
+    secp256k1::{ecdsa::Signature, Message, Secp256k1, SecretKey},
+    Address, Network, OutPoint, PrivateKey, PublicKey, Script, Transaction,
+};
+use nostr_rust::keys::get_random_secret_key;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use std::{collections::HashMap, str::FromStr};
+
+/// Tag the bond key signs the nostr key it backs under
+const BOND_TAG: &[u8] = b"nostrdizer fidelity bond";
+
+/// Yearly interest the value of coins locked in a bond is worked out with, as in JoinMarket
+const INTEREST_RATE: f64 = 0.015;
+
+/// Bond values are raised to it for the weight a maker is picked with, so splitting a bond over sybils costs
+const BOND_VALUE_EXPONENT: f64 = 1.3;
+
+// Seconds in a year
+const YEAR: f64 = 31_556_952.0;
+
+/// Locktimes from it on are unix times, block heights under it
+pub const LOCKTIME_THRESHOLD: u32 = 500_000_000;
+
+/// Script of a bond, spendable by the key once the locktime passed, `and_v(v:pk(key),after(locktime))` as miniscript
+/// ```
+/// use bdk::bitcoin::{secp256k1::{Secp256k1, SecretKey}, PublicKey};
+/// use nostrdizer::fidelity_bond::bond_script;
+///
+/// let secret_key = SecretKey::from_slice(&[1; 32]).unwrap();
+/// let pub_key = PublicKey::new(secret_key.public_key(&Secp256k1::new()));
+/// let script = bond_script(&pub_key, 1_800_000_000);
+/// // Key, OP_CHECKSIGVERIFY, locktime, OP_CLTV
+/// assert_eq!(script.as_bytes()[34..], [0xad, 0x04, 0x00, 0xd2, 0x49, 0x6b, 0xb1]);
+/// ```
+pub fn bond_script(pub_key: &PublicKey, locktime: u32) -> Script {
+    Builder::new()
+        .push_key(pub_key)
+        .push_opcode(OP_CHECKSIGVERIFY)
+        .push_int(locktime as i64)
+        .push_opcode(OP_CLTV)
+        .into_script()
+}
+
+/// Value coins locked in a bond are worth, what the interest on them over the lock would have been
+/// The value falls away once the locktime passed, see JoinMarket's fidelity bond docs
+/// ```
+/// use nostrdizer::{fidelity_bond::bond_value, types::Amount};
+///
+/// let year = 31_556_952;
+/// let locked = Amount::from_sat(100_000_000);
+/// let one_year = bond_value(locked, 0, year, 0);
+/// let two_years = bond_value(locked, 0, 2 * year, 0);
+/// assert_eq!(one_year, Amount::from_sat(1_511_306));
+/// assert!(two_years > one_year);
+///
+/// // Expired a year ago
+/// assert_eq!(bond_value(locked, 0, year, 3 * year), Amount::ZERO);
+/// ```
+pub fn bond_value(amount: Amount, confirmed_at: u64, locktime: u64, now: u64) -> Amount {
+    let locked = locktime.saturating_sub(confirmed_at) as f64 / YEAR;
+    let expired = now.saturating_sub(locktime) as f64 / YEAR;
+    let interest = |years: f64| ((INTEREST_RATE * years).exp() - 1.0).min(1.0);
+    let rate = (interest(locked) - interest(expired)).max(0.0);
+    Amount::from_sat((amount.to_sat() as f64 * rate) as u64)
+}
+
+/// Weight a maker with a bond of value is picked with
+pub fn bond_weight(value: Amount) -> f64 {
+    (value.to_sat() as f64).powf(BOND_VALUE_EXPONENT)
+}
+
+/// Makers in a random order, each coming before the rest more often the more weight it has
+/// Makers of no weight are left out
+/// ```
+/// use nostrdizer::fidelity_bond::weighted_order;
+/// use rand::thread_rng;
+///
+/// let weights = vec![("a".to_string(), 1.0), ("b".to_string(), 0.0), ("c".to_string(), 1e9)];
+/// let order = weighted_order(&weights, &mut thread_rng());
+/// assert_eq!(order.len(), 2);
+/// assert!(!order.contains(&"b".to_string()));
+/// ```
+pub fn weighted_order<R: Rng>(weights: &[(String, f64)], rng: &mut R) -> Vec<String> {
+    // Each maker draws u^(1/w), Efraimidis and Spirakis
+    let mut keys: Vec<(f64, &String)> = weights
+        .iter()
+        .filter(|(_, weight)| *weight > 0.0)
+        .map(|(maker, weight)| (rng.gen::<f64>().powf(1.0 / weight), maker))
+        .collect();
+    keys.sort_by(|a, b| b.0.total_cmp(&a.0));
+    keys.into_iter().map(|(_, maker)| maker.clone()).collect()
+}
+
+/// Ranks the makers of verified bonds in the order they are picked by bond weight
+/// Bonds of no value are ranked after the others
+pub fn rank_bonds<R: Rng>(bonds: &mut HashMap<String, VerifiedBond>, rng: &mut R) {
+    let weights: Vec<(String, f64)> = bonds
+        .iter()
+        .map(|(maker, bond)| (maker.clone(), bond_weight(bond.value)))
+        .collect();
+    let order = weighted_order(&weights, rng);
+    for (maker, bond) in bonds.iter_mut() {
+        bond.rank = order
+            .iter()
+            .position(|ranked| ranked == maker)
+            .unwrap_or(order.len());
+    }
+}
+
+/// Whether the maker has a verified bond worth at least `min_bond_value`
+pub fn meets_min_bond(
+    bonds: &HashMap<String, VerifiedBond>,
+    maker: &str,
+    min_bond_value: Amount,
+) -> bool {
+    bonds
+        .get(maker)
+        .map_or(false, |bond| bond.value >= min_bond_value)
+}
+
+/// Coins a maker locked in a bond, kept in the keystore so they can be spent once the lock passed
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct FidelityBond {
+    /// Hex private key the coins are locked to
+    pub key: String,
+    /// Unix time the coins are locked until
+    pub locktime: u32,
+    /// Utxo the coins were sent to, none until they are
+    #[serde(default)]
+    pub outpoint: Option<OutPoint>,
+    #[serde(with = "bdk::bitcoin::util::amount::serde::as_sat")]
+    pub amount: Amount,
+}
+
+impl FidelityBond {
+    /// Bond of amount with a new key, kept before the coins are sent so the key is not lost
+    pub fn generate(locktime: u32, amount: Amount, now: u64) -> Result<Self, Error> {
+        if locktime < LOCKTIME_THRESHOLD || locktime as u64 <= now {
+            return Err(Error::FidelityBondInvalid(
+                "locktime has to be a unix time to come".to_string(),
+            ));
+        }
+        let (secret_key, _) = get_random_secret_key();
+        Ok(Self {
+            key: hex::encode(secret_key.as_ref()),
+            locktime,
+            outpoint: None,
+            amount,
+        })
+    }
+
+    pub fn secret_key(&self) -> Result<SecretKey, Error> {
+        Ok(SecretKey::from_str(&self.key)?)
+    }
+
+    pub fn pub_key(&self) -> Result<PublicKey, Error> {
+        Ok(PublicKey::new(
+            self.secret_key()?.public_key(&Secp256k1::new()),
+        ))
+    }
+
+    /// Address the coins were sent to
+    pub fn address(&self, network: Network) -> Result<Address, Error> {
+        Ok(Address::p2wsh(
+            &bond_script(&self.pub_key()?, self.locktime),
+            network,
+        ))
+    }
+
+    /// Descriptor of the bond with its private key, a wallet it is imported into can spend the coins once the lock passed
+    pub fn descriptor(&self, network: Network) -> Result<String, Error> {
+        let key = PrivateKey::new(self.secret_key()?, network);
+        Ok(format!(
+            "wsh(and_v(v:pk({}),after({})))",
+            key.to_wif(),
+            self.locktime
+        ))
+    }
+
+    /// Outpoint of the transaction paying the bond
+    pub fn find_outpoint(&self, tx: &Transaction, network: Network) -> Result<OutPoint, Error> {
+        let script_pubkey = self.address(network)?.script_pubkey();
+        match tx
+            .output
+            .iter()
+            .position(|output| output.script_pubkey == script_pubkey)
+        {
+            Some(vout) => Ok(OutPoint::new(tx.txid(), vout as u32)),
+            None => Err(Error::FidelityBondInvalid(format!(
+                "{} does not pay the bond",
+                tx.txid()
+            ))),
+        }
+    }
+
+    /// Proof of the bond for offers of the maker with the nostr key
+    pub fn proof(&self, nostr_pub_key: &str) -> Result<FidelityBondProof, Error> {
+        let outpoint = match self.outpoint {
+            Some(outpoint) => outpoint,
+            None => {
+                return Err(Error::FidelityBondInvalid(
+                    "coins are not sent to the bond".to_string(),
+                ))
+            }
+        };
+        let secp = Secp256k1::new();
+        let mut proof = FidelityBondProof {
+            outpoint,
+            locktime: self.locktime,
+            pub_key: self.pub_key()?,
+            signature: String::new(),
+        };
+        let signature = secp.sign_ecdsa(&proof.message(nostr_pub_key), &self.secret_key()?);
+        proof.signature = signature.to_string();
+        Ok(proof)
+    }
+
+    /// Whether the coins are still locked at `now`
+    pub fn is_locked(&self, now: u64) -> bool {
+        now < self.locktime as u64
+    }
+}
+
+/// Bond a maker advertises in its offers, signed by the bond key for the maker's nostr key so no other maker can claim it
+/// Takers look the utxo up to check it is unspent and pays the bond script
+/// ```
+/// use bdk::bitcoin::{hashes::Hash, OutPoint, Txid};
+/// use nostrdizer::{fidelity_bond::FidelityBond, types::Amount};
+///
+/// let bond = FidelityBond {
+///     key: "01".repeat(32),
+///     locktime: 1_800_000_000,
+///     outpoint: Some(OutPoint::new(Txid::all_zeros(), 0)),
+///     amount: Amount::from_sat(10_000_000),
+/// };
+/// let maker = "f".repeat(64);
+/// let proof = bond.proof(&maker).unwrap();
+/// assert!(proof.verify(&maker).is_ok());
+/// assert!(proof.verify(&"e".repeat(64)).is_err());
+/// assert_eq!(proof.script_pubkey(), bond.address(bdk::bitcoin::Network::Regtest).unwrap().script_pubkey());
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct FidelityBondProof {
+    #[serde(rename = "utxo")]
+    pub outpoint: OutPoint,
+    pub locktime: u32,
+    #[serde(rename = "key")]
+    pub pub_key: PublicKey,
+    /// Hex DER signature of the bond key over the tagged nostr key and the bond
+    #[serde(rename = "sig")]
+    pub signature: String,
+}
+
+impl FidelityBondProof {
+    /// Checks the bond key signed the bond for the nostr key of the maker
+    pub fn verify(&self, nostr_pub_key: &str) -> Result<(), Error> {
+        if self.locktime < LOCKTIME_THRESHOLD {
+            return Err(Error::FidelityBondInvalid(
+                "locktime is not a time".to_string(),
+            ));
+        }
+        let signature =
+            Signature::from_str(&self.signature).map_err(|_| Error::FidelityBondProof)?;
+        Secp256k1::verification_only()
+            .verify_ecdsa(
+                &self.message(nostr_pub_key),
+                &signature,
+                &self.pub_key.inner,
+            )
+            .map_err(|_| Error::FidelityBondProof)
+    }
+
+    /// Script the utxo of the bond has to pay
+    pub fn script_pubkey(&self) -> Script {
+        bond_script(&self.pub_key, self.locktime).to_v0_p2wsh()
+    }
+
+    /// Value of the bond once its utxo was looked up, checking the utxo is the bond's
+    pub fn value(&self, utxo: &BondUtxo, now: u64) -> Result<Amount, Error> {
+        if utxo.script_pubkey != self.script_pubkey() {
+            return Err(Error::FidelityBondInvalid(format!(
+                "{} does not pay the bond script",
+                self.outpoint
+            )));
+        }
+        Ok(bond_value(
+            utxo.amount,
+            utxo.confirmed_at,
+            self.locktime as u64,
+            now,
+        ))
+    }
+
+    fn message(&self, nostr_pub_key: &str) -> Message {
+        let mut data = BOND_TAG.to_vec();
+        data.extend_from_slice(nostr_pub_key.as_bytes());
+        data.extend_from_slice(self.outpoint.to_string().as_bytes());
+        data.extend_from_slice(&self.locktime.to_le_bytes());
+        Message::from_hashed_data::<sha256::Hash>(&data)
+    }
+}
+
+/// Unspent utxo of a bond as the chain has it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BondUtxo {
+    pub amount: Amount,
+    pub script_pubkey: Script,
+    /// Time of the block the utxo confirmed in
+    pub confirmed_at: u64,
+}
+
+/// Bond of a maker a taker verified against the chain
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VerifiedBond {
+    /// Coins locked in the bond
+    pub amount: Amount,
+    pub locktime: u32,
+    /// What the bond is worth, see [`bond_value`]
+    pub value: Amount,
+    /// Place of the maker when makers with bonds are picked by bond weight, see [`rank_bonds`]
+    pub rank: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::{rngs::StdRng, SeedableRng};
+
+    /// Proof of a bond locked to the key, signed for the maker
+    fn signed_proof(key: u8, locktime: u32, maker: &str) -> FidelityBondProof {
+        let secp = Secp256k1::new();
+        let secret_key = SecretKey::from_slice(&[key; 32]).unwrap();
+        let mut proof = FidelityBondProof {
+            outpoint: OutPoint::null(),
+            locktime,
+            pub_key: PublicKey::new(secret_key.public_key(&secp)),
+            signature: String::new(),
+        };
+        proof.signature = secp
+            .sign_ecdsa(&proof.message(maker), &secret_key)
+            .to_string();
+        proof
+    }
+
+    fn verified(value: u64) -> VerifiedBond {
+        VerifiedBond {
+            amount: Amount::from_sat(value * 100),
+            locktime: 1_800_000_000,
+            value: Amount::from_sat(value),
+            rank: 0,
+        }
+    }
+
+    #[test]
+    fn test_proof_signed_for_maker() {
+        let proof = signed_proof(1, 1_800_000_000, "maker");
+        assert!(proof.verify("maker").is_ok());
+        // Another maker can not claim the bond
+        assert!(matches!(
+            proof.verify("sybil"),
+            Err(Error::FidelityBondProof)
+        ));
+
+        let heights = signed_proof(1, 800_000, "maker");
+        assert!(matches!(
+            heights.verify("maker"),
+            Err(Error::FidelityBondInvalid(_))
+        ));
+    }
+
+    #[test]
+    fn test_heavier_bond_first() {
+        let weights = vec![
+            ("small".to_string(), bond_weight(Amount::from_sat(100_000))),
+            (
+                "large".to_string(),
+                bond_weight(Amount::from_sat(10_000_000)),
+            ),
+        ];
+        let mut rng = StdRng::seed_from_u64(2011);
+        let large_first = (0..1_000)
+            .filter(|_| weighted_order(&weights, &mut rng)[0] == "large")
+            .count();
+        // A sybil splitting the large bond in a hundred would be picked first far less
+        assert!(large_first > 990, "{large_first}");
+    }
+
+    #[test]
+    fn test_worthless_bond_ranked_last() {
+        let mut bonds: HashMap<String, VerifiedBond> = [
+            ("large".to_string(), verified(10_000_000)),
+            ("small".to_string(), verified(100)),
+            ("expired".to_string(), verified(0)),
+        ]
+        .into_iter()
+        .collect();
+        rank_bonds(&mut bonds, &mut StdRng::seed_from_u64(1997));
+
+        assert_eq!(bonds["large"].rank, 0);
+        assert_eq!(bonds["small"].rank, 1);
+        assert_eq!(bonds["expired"].rank, 2);
+    }
+
+    #[test]
+    fn test_min_bond_value() {
+        let bonds: HashMap<String, VerifiedBond> = [
+            ("large".to_string(), verified(100_000)),
+            ("small".to_string(), verified(9_999)),
+        ]
+        .into_iter()
+        .collect();
+        let min_bond_value = Amount::from_sat(10_000);
+
+        assert!(meets_min_bond(&bonds, "large", min_bond_value));
+        assert!(!meets_min_bond(&bonds, "small", min_bond_value));
+        // Makers without a verified bond are left out once one is required
+        assert!(!meets_min_bond(&bonds, "unbonded", min_bond_value));
+    }
+
+    #[test]
+    fn test_value_of_utxo() {
+        let proof = signed_proof(2, 1_800_000_000, "maker");
+        let utxo = BondUtxo {
+            amount: Amount::from_sat(10_000_000),
+            script_pubkey: proof.script_pubkey(),
+            confirmed_at: 1_700_000_000,
+        };
+        assert!(proof.value(&utxo, 1_700_000_000).unwrap() > Amount::ZERO);
+
+        let other = BondUtxo {
+            script_pubkey: bond_script(&proof.pub_key, 1_700_000_001).to_v0_p2wsh(),
+            ..utxo
+        };
+        assert!(proof.value(&other, 1_700_000_000).is_err());
+    }
+}
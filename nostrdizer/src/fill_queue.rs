@@ -0,0 +1,195 @@
+use crate::types::Fill;
+
+use std::collections::VecDeque;
+
+/// A fill waiting for the maker to finish its current round
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueuedFill {
+    /// Round id derived from the fill event
+    pub round_id: String,
+    /// Id of the signed fill event, the first message of the round transcript
+    pub fill_event_id: String,
+    pub taker: String,
+    pub fill: Fill,
+    pub sealed: bool,
+    pub created_at: u64,
+}
+
+/// Why a fill was not queued
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueueError {
+    /// Queue is at capacity, the taker should be told the maker is busy
+    Full,
+    /// Taker already has as many fills queued as it is allowed
+    TakerLimit,
+    /// Same fill was already queued, seen on another relay
+    Duplicate,
+}
+
+/// Bounded first come first served queue of fills
+/// ```
+/// use nostrdizer::{
+///     fill_queue::{FillQueue, QueueError, QueuedFill},
+///     types::{Amount, Fill, InputLimits},
+/// };
+/// # use bitcoin_hashes::{sha256, Hash};
+///
+/// let queued = |taker: &str, created_at| QueuedFill {
+///     round_id: taker.to_string(),
+///     fill_event_id: taker.to_string(),
+///     taker: taker.to_string(),
+///     fill: Fill {
+///         offer_id: 0,
+///         protocol_version: None,
+///         amount: Amount::from_sat(10_000),
+///         tencpubkey: "".to_string(),
+///         commitment: sha256::Hash::hash(taker.as_bytes()),
+///         denominations: vec![],
+///         script_type: None,
+///         counter: false,
+///         cjfee: None,
+///         payment: None,
+///         silent_payment: None,
+///         invite: None,
+///         input_limits: InputLimits::default(),
+///     },
+///     sealed: false,
+///     created_at,
+/// };
+///
+/// let mut queue = FillQueue::new(2, 1, 60);
+/// queue.push(queued("a", 10)).unwrap();
+/// queue.push(queued("b", 11)).unwrap();
+/// assert_eq!(queue.push(queued("c", 12)), Err(QueueError::Full));
+///
+/// assert_eq!(queue.pop(20).unwrap().taker, "a");
+/// // Fills older then max age are dropped as the taker will have given up
+/// assert!(queue.pop(100).is_none());
+/// ```
+#[derive(Debug, Clone)]
+pub struct FillQueue {
+    pub capacity: usize,
+    /// Max fills queued for one taker
+    pub per_taker: usize,
+    /// Seconds after a fill was created that it is no longer worth answering
+    pub max_age: u64,
+    fills: VecDeque<QueuedFill>,
+}
+
+impl Default for FillQueue {
+    fn default() -> Self {
+        Self::new(10, 1, 60)
+    }
+}
+
+impl FillQueue {
+    pub fn new(capacity: usize, per_taker: usize, max_age: u64) -> Self {
+        Self {
+            capacity,
+            per_taker,
+            max_age,
+            fills: VecDeque::new(),
+        }
+    }
+
+    pub fn push(&mut self, fill: QueuedFill) -> Result<(), QueueError> {
+        if self
+            .fills
+            .iter()
+            .any(|f| f.taker == fill.taker && f.fill == fill.fill)
+        {
+            return Err(QueueError::Duplicate);
+        }
+        if self.fills.iter().filter(|f| f.taker == fill.taker).count() >= self.per_taker {
+            return Err(QueueError::TakerLimit);
+        }
+        if self.fills.len() >= self.capacity {
+            return Err(QueueError::Full);
+        }
+
+        self.fills.push_back(fill);
+        Ok(())
+    }
+
+    /// Next fill that is not older then `max_age`
+    pub fn pop(&mut self, now: u64) -> Option<QueuedFill> {
+        while let Some(fill) = self.fills.pop_front() {
+            if now.saturating_sub(fill.created_at) <= self.max_age {
+                return Some(fill);
+            }
+        }
+        None
+    }
+
+    pub fn len(&self) -> usize {
+        self.fills.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fills.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{Amount, InputLimits};
+    use bitcoin_hashes::{sha256, Hash};
+
+    fn queued(taker: &str, oid: u32) -> QueuedFill {
+        QueuedFill {
+            round_id: format!("{taker}{oid}"),
+            fill_event_id: format!("{taker}{oid}"),
+            taker: taker.to_string(),
+            fill: Fill {
+                offer_id: oid,
+                protocol_version: None,
+                amount: Amount::from_sat(10_000),
+                tencpubkey: "".to_string(),
+                commitment: sha256::Hash::hash("".as_bytes()),
+                denominations: vec![],
+                script_type: None,
+                counter: false,
+                cjfee: None,
+                payment: None,
+                silent_payment: None,
+                invite: None,
+                input_limits: InputLimits::default(),
+            },
+            sealed: false,
+            created_at: 0,
+        }
+    }
+
+    #[test]
+    fn test_per_taker_limit() {
+        let mut queue = FillQueue::new(10, 1, 60);
+        queue.push(queued("a", 0)).unwrap();
+
+        assert_eq!(queue.push(queued("a", 0)), Err(QueueError::Duplicate));
+        assert_eq!(queue.push(queued("a", 1)), Err(QueueError::TakerLimit));
+        assert!(queue.push(queued("b", 0)).is_ok());
+        assert_eq!(queue.len(), 2);
+    }
+
+    #[test]
+    fn test_first_come_first_served() {
+        let mut queue = FillQueue::new(10, 2, 60);
+        queue.push(queued("a", 0)).unwrap();
+        queue.push(queued("b", 0)).unwrap();
+        queue.push(queued("a", 1)).unwrap();
+
+        let order: Vec<(String, u32)> = std::iter::from_fn(|| queue.pop(0))
+            .map(|f| (f.taker, f.fill.offer_id))
+            .collect();
+        assert_eq!(
+            order,
+            vec![
+                ("a".to_string(), 0),
+                ("b".to_string(), 0),
+                ("a".to_string(), 1)
+            ]
+        );
+        assert!(queue.is_empty());
+    }
+}
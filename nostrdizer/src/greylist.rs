@@ -0,0 +1,129 @@
+use crate::errors::Error;
+
+use serde::{Deserialize, Serialize};
+
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+/// When takers sending auths that do not verify are greylisted
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GreylistPolicy {
+    /// Failed auths a taker is greylisted after
+    pub max_failures: u32,
+    /// Seconds fills from a greylisted taker are ignored
+    pub cooldown: u64,
+}
+
+impl Default for GreylistPolicy {
+    fn default() -> Self {
+        Self {
+            max_failures: 3,
+            cooldown: 86400,
+        }
+    }
+}
+
+/// Auths of a taker that failed to verify
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct TakerFailures {
+    /// Failures since the taker was last greylisted
+    pub failures: u32,
+    /// Failures since the taker was first seen
+    pub total_failures: u32,
+    pub last_failure: u64,
+    /// Time fills from the taker are ignored until
+    pub greylisted_until: Option<u64>,
+}
+
+/// Takers, by nostr pubkey, whose podle openings failed to verify
+/// Repeated failures from one taker suggest it is probing, its fills are ignored for a cooldown once it has failed too often
+/// ```
+/// use nostrdizer::greylist::{GreylistPolicy, TakerGreylist};
+///
+/// let policy = GreylistPolicy { max_failures: 2, cooldown: 100 };
+/// let mut greylist = TakerGreylist::default();
+///
+/// assert!(!greylist.record_failure("taker", 10, &policy));
+/// assert!(!greylist.is_greylisted("taker", 10));
+/// assert!(greylist.record_failure("taker", 20, &policy));
+/// assert!(greylist.is_greylisted("taker", 50));
+/// assert_eq!(greylist.greylisted(50), 1);
+///
+/// // Taker gets a fresh count once the cooldown is over
+/// assert!(!greylist.is_greylisted("taker", 120));
+/// assert!(!greylist.record_failure("taker", 130, &policy));
+/// assert_eq!(greylist.failures("taker"), 3);
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct TakerGreylist {
+    pub takers: BTreeMap<String, TakerFailures>,
+}
+
+impl TakerGreylist {
+    /// Loads the greylist from path, an empty one if there is no file
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        match path.exists() {
+            true => Ok(serde_json::from_str(&fs::read_to_string(path)?)?),
+            false => Ok(Self::default()),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        file.write_all(serde_json::to_string_pretty(self)?.as_bytes())?;
+        Ok(())
+    }
+
+    /// Counts a failed auth of taker, returns true if it greylists the taker
+    pub fn record_failure(&mut self, taker: &str, now: u64, policy: &GreylistPolicy) -> bool {
+        let entry = self.takers.entry(taker.to_string()).or_default();
+        if entry.greylisted_until.map(|until| until <= now) == Some(true) {
+            entry.failures = 0;
+            entry.greylisted_until = None;
+        }
+        entry.failures += 1;
+        entry.total_failures += 1;
+        entry.last_failure = now;
+        if entry.greylisted_until.is_none() && entry.failures >= policy.max_failures {
+            entry.greylisted_until = Some(now + policy.cooldown);
+            return true;
+        }
+        false
+    }
+
+    /// Whether fills from taker are ignored at now
+    pub fn is_greylisted(&self, taker: &str, now: u64) -> bool {
+        self.takers
+            .get(taker)
+            .and_then(|entry| entry.greylisted_until)
+            .map(|until| until > now)
+            .unwrap_or(false)
+    }
+
+    /// Failed auths of taker since it was first seen
+    pub fn failures(&self, taker: &str) -> u32 {
+        self.takers
+            .get(taker)
+            .map(|entry| entry.total_failures)
+            .unwrap_or(0)
+    }
+
+    /// Takers greylisted at now
+    pub fn greylisted(&self, now: u64) -> usize {
+        self.takers
+            .keys()
+            .filter(|taker| self.is_greylisted(taker, now))
+            .count()
+    }
+
+    /// Failed auths of every taker
+    pub fn total_failures(&self) -> u32 {
+        self.takers.values().map(|entry| entry.total_failures).sum()
+    }
+}
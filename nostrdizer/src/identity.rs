@@ -0,0 +1,161 @@
+use crate::errors::Error;
+
+use bdk::bitcoin::secp256k1::{Secp256k1, SecretKey};
+use bdk::bitcoin::util::bip32::{ChildNumber, DerivationPath, ExtendedPrivKey};
+use bdk::miniscript::descriptor::{Descriptor, DescriptorPublicKey, DescriptorSecretKey};
+use bitcoin_hashes::{hmac, sha512, Hash, HashEngine};
+use nostr_rust::Identity;
+
+use std::str::FromStr;
+
+/// BIP-85 purpose, no key spending funds is derived under it
+const BIP85_PURPOSE: u32 = 83696968;
+/// BIP-85 application for hex entropy
+const BIP85_HEX: u32 = 128169;
+/// HMAC key BIP-85 hashes derived keys with so the entropy reveals no wallet key
+const BIP85_HMAC_KEY: &[u8] = b"bip-entropy-from-k";
+/// Bytes in a nostr private key
+const NOSTR_KEY_LEN: u32 = 32;
+
+/// Path the nostr key at `index` is derived at, m/83696968'/128169'/32'/index'
+pub fn nostr_key_path(index: u32) -> Result<DerivationPath, Error> {
+    Ok(DerivationPath::from(vec![
+        ChildNumber::from_hardened_idx(BIP85_PURPOSE)?,
+        ChildNumber::from_hardened_idx(BIP85_HEX)?,
+        ChildNumber::from_hardened_idx(NOSTR_KEY_LEN)?,
+        ChildNumber::from_hardened_idx(index)?,
+    ]))
+}
+
+/// Nostr private key, as hex, derived from the wallet key with BIP-85
+/// Backing up the wallet backs up the maker identity, but the nostr key can not spend
+/// ```
+/// use nostrdizer::identity::derive_nostr_key;
+/// use nostrdizer::types::Network;
+/// use bdk::bitcoin::util::bip32::ExtendedPrivKey;
+///
+/// let xprv = ExtendedPrivKey::new_master(Network::Regtest, &[7; 32]).unwrap();
+/// let priv_key = derive_nostr_key(&xprv, 0).unwrap();
+///
+/// // The same wallet always gives the same identity, each index a new one
+/// assert_eq!(priv_key, derive_nostr_key(&xprv, 0).unwrap());
+/// assert_ne!(priv_key, derive_nostr_key(&xprv, 1).unwrap());
+/// ```
+pub fn derive_nostr_key(xprv: &ExtendedPrivKey, index: u32) -> Result<String, Error> {
+    let entropy = bip85_entropy(xprv, &nostr_key_path(index)?)?;
+    let secret_key = SecretKey::from_slice(&entropy[..NOSTR_KEY_LEN as usize])?;
+    Ok(hex::encode(secret_key.secret_bytes()))
+}
+
+/// Hex public key of a hex nostr private key
+pub fn nostr_pub_key(priv_key: &str) -> Result<String, Error> {
+    Ok(Identity::from_str(priv_key)?.public_key_str)
+}
+
+/// Extended private key of a descriptor listed with its private keys
+pub fn descriptor_xprv(descriptor: &str) -> Result<ExtendedPrivKey, Error> {
+    let secp = Secp256k1::new();
+    let (_, key_map) = Descriptor::<DescriptorPublicKey>::parse_descriptor(&secp, descriptor)
+        .map_err(|err| Error::InvalidDescriptor(err.to_string()))?;
+
+    key_map
+        .into_values()
+        .find_map(|key| match key {
+            DescriptorSecretKey::XPrv(xkey) => Some(xkey.xkey),
+            _ => None,
+        })
+        .ok_or(Error::NoWalletKey)
+}
+
+/// Path from the master key of the key in a descriptor with its key origin, as bitcoin core lists utxos
+/// such as `wpkh([d34db33f/84h/1h/0h/0/3]02..)#checksum`
+pub fn descriptor_key_path(descriptor: &str) -> Result<DerivationPath, Error> {
+    let origin = descriptor
+        .split_once('[')
+        .and_then(|(_, rest)| rest.split_once(']'))
+        .map(|(origin, _)| origin)
+        .ok_or_else(|| Error::InvalidDescriptor(descriptor.to_string()))?;
+    // The origin starts with the fingerprint of the master key
+    let path = match origin.split_once('/') {
+        Some((_fingerprint, path)) => format!("m/{path}"),
+        None => "m".to_string(),
+    };
+    DerivationPath::from_str(&path).map_err(|err| Error::InvalidDescriptor(err.to_string()))
+}
+
+/// BIP-85 entropy from the key at hardened `path`
+fn bip85_entropy(xprv: &ExtendedPrivKey, path: &DerivationPath) -> Result<[u8; 64], Error> {
+    let secp = Secp256k1::new();
+    let child = xprv.derive_priv(&secp, path)?;
+
+    let mut engine = hmac::HmacEngine::<sha512::Hash>::new(BIP85_HMAC_KEY);
+    engine.input(&child.private_key.secret_bytes());
+    Ok(hmac::Hmac::<sha512::Hash>::from_engine(engine).into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bdk::bitcoin::util::bip32::ExtendedPubKey;
+
+    // Master key of the BIP-85 test vectors
+    const MASTER: &str = "xprv9s21ZrQH143K2LBWUUQRFXhucrQqBpKdRRxNVq2zBqsx8HVqFk2uYo8kmbaLLHRdqtQpUm98uKfu3vca1LqdGhUtyoFnCNkfmXRyPXLjbKb";
+
+    #[test]
+    fn test_bip85_vectors() {
+        let xprv = ExtendedPrivKey::from_str(MASTER).unwrap();
+
+        let path = DerivationPath::from_str("m/83696968'/0'/0'").unwrap();
+        assert_eq!(
+            hex::encode(bip85_entropy(&xprv, &path).unwrap()),
+            "efecfbccffea313214232d29e71563d941229afb4338c21f9517c41aaa0d16f00b83d2a09ef747e7a64e8e2bd5a14869e693da66ce94ac2da570ab7ee48618f7"
+        );
+
+        let path = DerivationPath::from_str("m/83696968'/128169'/64'/0'").unwrap();
+        assert_eq!(
+            hex::encode(bip85_entropy(&xprv, &path).unwrap()),
+            "492db4698cf3b73a5a24998aa3e9d7fa96275d85724a91e71aa2d645442f878555d078fd1f1f67e368976f04137b1f7a0d19232136ca50c44614af72b5582a5c"
+        );
+    }
+
+    #[test]
+    fn test_derive_nostr_key() {
+        let xprv = ExtendedPrivKey::from_str(MASTER).unwrap();
+        assert_eq!(
+            nostr_key_path(0).unwrap().to_string(),
+            "m/83696968'/128169'/32'/0'"
+        );
+        assert_eq!(
+            derive_nostr_key(&xprv, 0).unwrap(),
+            "ea3ceb0b02ee8e587779c63f4b7b3a21e950a213f1ec53cab608d13e8796e6dc"
+        );
+    }
+
+    #[test]
+    fn test_descriptor_key_path() {
+        let path = descriptor_key_path("wpkh([d34db33f/84h/1h/0h/0/3]02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5)#qwlqgth7").unwrap();
+        assert_eq!(path, DerivationPath::from_str("m/84'/1'/0'/0/3").unwrap());
+
+        // Keys without an origin can not be derived
+        assert!(descriptor_key_path(
+            "wpkh(02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5)"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_descriptor_xprv() {
+        let xprv = ExtendedPrivKey::from_str(MASTER).unwrap();
+        let descriptor = format!("wpkh({}/84'/0'/0'/0/*)", MASTER);
+        assert_eq!(descriptor_xprv(&descriptor).unwrap(), xprv);
+
+        // A watch only descriptor has nothing to derive from
+        let xpub = ExtendedPubKey::from_priv(&Secp256k1::new(), &xprv);
+        let descriptor = format!("wpkh({}/0/*)", xpub);
+        assert!(matches!(
+            descriptor_xprv(&descriptor),
+            Err(Error::NoWalletKey)
+        ));
+    }
+}
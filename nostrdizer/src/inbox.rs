@@ -0,0 +1,160 @@
+use std::collections::{HashMap, HashSet};
+
+/// Counts of the events a session took in, to spot relays flooding it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IngestStats {
+    pub accepted: usize,
+    /// Repeated messages from a peer that already sent one
+    pub duplicate: usize,
+    /// Messages from peers that are not in the session
+    pub unsolicited: usize,
+}
+
+impl IngestStats {
+    pub fn add(&mut self, other: &IngestStats) {
+        self.accepted += other.accepted;
+        self.duplicate += other.duplicate;
+        self.unsolicited += other.unsolicited;
+    }
+}
+
+/// Messages of one kind from the peers of a session
+/// Only the first message of each expected peer is kept, so it never holds more than one per peer
+/// however many pub keys a relay sends events from
+/// ```
+/// use nostrdizer::inbox::SessionInbox;
+///
+/// let mut inbox = SessionInbox::new(["a".to_string(), "b".to_string()]);
+/// assert!(inbox.accept("a", 1));
+/// assert!(!inbox.accept("a", 2));
+/// assert!(!inbox.accept("mallory", 3));
+/// assert!(!inbox.is_complete());
+///
+/// assert!(inbox.accept("b", 4));
+/// assert!(inbox.is_complete());
+/// assert_eq!(inbox.stats.unsolicited, 1);
+/// ```
+#[derive(Debug, Clone)]
+pub struct SessionInbox<T> {
+    expected: HashSet<String>,
+    messages: HashMap<String, T>,
+    pub stats: IngestStats,
+}
+
+impl<T> SessionInbox<T> {
+    pub fn new(expected: impl IntoIterator<Item = String>) -> Self {
+        Self {
+            expected: expected.into_iter().collect(),
+            messages: HashMap::new(),
+            stats: IngestStats::default(),
+        }
+    }
+
+    /// Whether peer is in the session, checked before decrypting its events
+    /// Events from other peers are counted as unsolicited
+    pub fn is_expected(&mut self, peer: &str) -> bool {
+        let expected = self.expected.contains(peer);
+        if !expected {
+            self.stats.unsolicited += 1;
+        }
+        expected
+    }
+
+    /// Keeps the message if it is the first from an expected peer
+    pub fn accept(&mut self, peer: &str, message: T) -> bool {
+        if !self.is_expected(peer) {
+            return false;
+        }
+        if self.messages.contains_key(peer) {
+            self.stats.duplicate += 1;
+            return false;
+        }
+        self.stats.accepted += 1;
+        self.messages.insert(peer.to_string(), message);
+        true
+    }
+
+    /// Stops expecting peer, whose message was turned down, so the session does not wait for it
+    pub fn reject(&mut self, peer: &str) {
+        self.expected.remove(peer);
+    }
+
+    /// Every expected peer has sent a message
+    pub fn is_complete(&self) -> bool {
+        self.messages.len() == self.expected.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    /// Peers that have sent a message
+    pub fn peers(&self) -> Vec<String> {
+        self.messages.keys().cloned().collect()
+    }
+
+    /// Expected peers that have not sent a message
+    pub fn missing(&self) -> Vec<String> {
+        self.expected
+            .iter()
+            .filter(|peer| !self.messages.contains_key(*peer))
+            .cloned()
+            .collect()
+    }
+
+    pub fn into_messages(self) -> HashMap<String, T> {
+        self.messages
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flood_is_not_kept() {
+        let mut inbox = SessionInbox::new(["maker".to_string()]);
+        for i in 0..10_000 {
+            inbox.accept(&format!("flood{i}"), i);
+            inbox.accept("maker", i);
+        }
+
+        assert_eq!(inbox.len(), 1);
+        assert_eq!(inbox.into_messages().get("maker"), Some(&0));
+    }
+
+    #[test]
+    fn test_rejected_peer_not_waited_for() {
+        let mut inbox = SessionInbox::new(["a".to_string(), "b".to_string()]);
+        inbox.accept("a", 1);
+        inbox.reject("b");
+
+        assert!(inbox.is_complete());
+        assert!(inbox.missing().is_empty());
+        assert!(!inbox.accept("b", 2));
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut inbox = SessionInbox::new(["a".to_string()]);
+        inbox.accept("a", ());
+        inbox.accept("a", ());
+        inbox.accept("b", ());
+
+        let mut total = IngestStats::default();
+        total.add(&inbox.stats);
+        total.add(&inbox.stats);
+        assert_eq!(
+            total,
+            IngestStats {
+                accepted: 2,
+                duplicate: 2,
+                unsolicited: 2,
+            }
+        );
+    }
+}
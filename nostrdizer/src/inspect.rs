@@ -0,0 +1,316 @@
+use crate::{
+    blacklist::UsedCommitments,
+    build_info::EVENT_KINDS,
+    errors::Error,
+    stats::NetworkStats,
+    types::{
+        NostrdizerMessage, ABORT, ABS_OFFER, AUTH, BACKUP, CONFIRM, COOP_JOIN, FILL, FILL_ACK,
+        IOAUTH, NETWORK_STATS, PRESENCE, PUBKEY, REJECT, REL_OFFER, SEALED, SIGNED_TRANSACTION,
+        TRANSACTION, USED_COMMITMENTS,
+    },
+    utils::{decrypt_message, event_network, unseal_event},
+};
+
+use bdk::bitcoin::Network;
+use nostr_rust::{events::Event, nostr_client::Client as NostrClient, req::ReqFilter, Identity};
+use secp256k1::SecretKey;
+use serde::Serialize;
+
+use std::str::FromStr;
+
+// Kinds of the messages of a session, NIP-04 encrypted between taker and maker
+const SESSION_KINDS: [u16; 11] = [
+    FILL,
+    FILL_ACK,
+    PUBKEY,
+    AUTH,
+    IOAUTH,
+    TRANSACTION,
+    SIGNED_TRANSACTION,
+    REJECT,
+    CONFIRM,
+    ABORT,
+    COOP_JOIN,
+];
+
+/// Event taken apart for inspection
+#[derive(Serialize, Debug)]
+pub struct DecodedEvent {
+    pub id: String,
+    pub kind: u16,
+    /// Name of the nostrdizer message the kind is for, none for kinds the protocol does not use
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kind_name: Option<&'static str>,
+    pub author: String,
+    /// Peer the event is tagged to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recipient: Option<String>,
+    pub created_at: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub network: Option<Network>,
+    /// Id of the sealed event the event was unsealed from
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sealed_id: Option<String>,
+    pub content: DecodedContent,
+}
+
+/// Content of an inspected event
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum DecodedContent {
+    Message(NostrdizerMessage),
+    UsedCommitments(UsedCommitments),
+    NetworkStats(NetworkStats),
+    /// Encrypted to a key that was not given, the local key is not a party
+    Encrypted,
+    /// Content of kinds that are not nostrdizer messages, or that did not parse
+    Raw(String),
+}
+
+/// Parses a raw event, its signature has to be valid
+pub fn parse_event(raw: &str) -> Result<Event, Error> {
+    let event: Event = serde_json::from_str(raw.trim())?;
+    match event.verify() {
+        Ok(_) => Ok(event),
+        Err(_) => Err(Error::InvalidEventSignature),
+    }
+}
+
+/// Fetches the event with id from the relays
+pub fn fetch_event(relay_urls: Vec<&str>, id: &str) -> Result<Event, Error> {
+    let mut nostr_client = NostrClient::new(relay_urls)?;
+    let filter = ReqFilter {
+        ids: Some(vec![id.to_string()]),
+        authors: None,
+        kinds: None,
+        e: None,
+        p: None,
+        since: None,
+        until: None,
+        limit: Some(1),
+    };
+
+    let event = nostr_client
+        .get_events_of(vec![filter])?
+        .into_iter()
+        .find(|event| event.id == id);
+    match event {
+        Some(event) if event.verify().is_ok() => Ok(event),
+        Some(_) => Err(Error::InvalidEventSignature),
+        None => Err(Error::EventNotFound(id.to_string())),
+    }
+}
+
+/// Identifies the nostrdizer kind of event and parses its content
+/// Sealed events and session messages are decrypted when `priv_key`, or a maker's `encryption_key`,
+/// is the author or the recipient, they are left encrypted otherwise
+pub fn decode(
+    event: Event,
+    priv_key: Option<&str>,
+    encryption_key: Option<&str>,
+) -> Result<DecodedEvent, Error> {
+    let identity = match priv_key {
+        Some(priv_key) => Some(Identity::from_str(priv_key)?),
+        None => None,
+    };
+    let mut secret_keys: Vec<SecretKey> = identity
+        .iter()
+        .map(|identity| identity.secret_key)
+        .collect();
+    if let Some(encryption_key) = encryption_key {
+        secret_keys.push(Identity::from_str(encryption_key)?.secret_key);
+    }
+
+    // Seals are always to the nostr key
+    let (sealed_id, event) = match &identity {
+        Some(identity) if event.kind == SEALED => match unseal_event(identity, event.clone()) {
+            Ok(inner) => (Some(event.id), inner),
+            Err(_) => (None, event),
+        },
+        _ => (None, event),
+    };
+    let recipient = event
+        .tags
+        .iter()
+        .find(|tag| tag.first().map(|name| name == "p").unwrap_or(false))
+        .and_then(|tag| tag.get(1))
+        .cloned();
+
+    let content = match event.kind {
+        ABS_OFFER | REL_OFFER | PRESENCE => match serde_json::from_str(&event.content) {
+            Ok(message) => DecodedContent::Message(message),
+            Err(_) => DecodedContent::Raw(event.content.clone()),
+        },
+        USED_COMMITMENTS => match serde_json::from_str(&event.content) {
+            Ok(used) => DecodedContent::UsedCommitments(used),
+            Err(_) => DecodedContent::Raw(event.content.clone()),
+        },
+        NETWORK_STATS => match serde_json::from_str(&event.content) {
+            Ok(stats) => DecodedContent::NetworkStats(stats),
+            Err(_) => DecodedContent::Raw(event.content.clone()),
+        },
+        // Backups are encrypted to the key's own pub key and read back with restore
+        SEALED | BACKUP => DecodedContent::Encrypted,
+        kind if SESSION_KINDS.contains(&kind) => {
+            decrypt_session(&event, recipient.as_deref(), &secret_keys)
+        }
+        _ => DecodedContent::Raw(event.content.clone()),
+    };
+
+    Ok(DecodedEvent {
+        kind_name: EVENT_KINDS
+            .iter()
+            .find(|(_, kind)| *kind == event.kind)
+            .map(|(name, _)| *name),
+        network: event_network(&event),
+        id: event.id,
+        kind: event.kind,
+        author: event.pub_key,
+        recipient,
+        created_at: event.created_at,
+        sealed_id,
+        content,
+    })
+}
+
+/// Decrypts a session message with any of the keys, as its recipient or as its author
+fn decrypt_session(
+    event: &Event,
+    recipient: Option<&str>,
+    secret_keys: &[SecretKey],
+) -> DecodedContent {
+    let peers: Vec<&str> = [Some(event.pub_key.as_str()), recipient]
+        .into_iter()
+        .flatten()
+        .collect();
+    secret_keys
+        .iter()
+        .flat_map(|secret_key| peers.iter().map(move |peer| (secret_key, *peer)))
+        .find_map(|(secret_key, peer)| decrypt_message(secret_key, peer, &event.content).ok())
+        .map(DecodedContent::Message)
+        .unwrap_or(DecodedContent::Encrypted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        encryption::Envelope,
+        test_utils::fill,
+        types::{NostrdizerMessageKind, NostrdizerMessages, Presence, PresenceStatus},
+        utils::message_event,
+    };
+    use nostr_rust::{events::EventPrepare, keys::get_random_secret_key, utils::get_timestamp};
+
+    fn identity() -> (String, Identity) {
+        let (secret_key, _) = get_random_secret_key();
+        let priv_key = hex::encode(secret_key.as_ref());
+        (priv_key.clone(), Identity::from_str(&priv_key).unwrap())
+    }
+
+    fn fill_event(taker: &Identity, maker: &Identity, sealed: bool) -> Event {
+        let message = NostrdizerMessage {
+            event_type: NostrdizerMessageKind::FillOffer,
+            event: NostrdizerMessages::Fill(fill(100_000)),
+            round_id: None,
+        };
+        let envelope = Envelope {
+            secret_key: taker.secret_key,
+            pub_key: maker.public_key_str.clone(),
+            sealed,
+            tagged: false,
+        };
+        message_event(taker, &maker.public_key_str, FILL, &message, &envelope)
+            .unwrap()
+            .1
+    }
+
+    #[test]
+    fn test_decode_presence() {
+        let (_, maker) = identity();
+        let presence = NostrdizerMessage {
+            event_type: NostrdizerMessageKind::Presence,
+            event: NostrdizerMessages::Presence(Presence {
+                status: PresenceStatus::Online,
+            }),
+            round_id: None,
+        };
+        let event = EventPrepare {
+            pub_key: maker.public_key_str.clone(),
+            created_at: get_timestamp(),
+            kind: PRESENCE,
+            tags: vec![],
+            content: serde_json::to_string(&presence).unwrap(),
+        }
+        .to_event(&maker, 0);
+
+        let decoded = decode(event, None, None).unwrap();
+        assert_eq!(decoded.kind_name, Some("presence"));
+        assert_eq!(decoded.author, maker.public_key_str);
+        assert!(matches!(
+            decoded.content,
+            DecodedContent::Message(NostrdizerMessage {
+                event: NostrdizerMessages::Presence(Presence {
+                    status: PresenceStatus::Online
+                }),
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_decode_session_message() {
+        let (taker_key, taker) = identity();
+        let (maker_key, maker) = identity();
+        let (outsider_key, _) = identity();
+
+        // Recipient and author can both read it
+        for priv_key in [&maker_key, &taker_key] {
+            let decoded = decode(fill_event(&taker, &maker, false), Some(priv_key), None).unwrap();
+            assert_eq!(decoded.kind_name, Some("fill"));
+            assert_eq!(decoded.recipient, Some(maker.public_key_str.clone()));
+            assert!(matches!(
+                decoded.content,
+                DecodedContent::Message(NostrdizerMessage {
+                    event: NostrdizerMessages::Fill(_),
+                    ..
+                })
+            ));
+        }
+
+        let decoded = decode(fill_event(&taker, &maker, false), Some(&outsider_key), None).unwrap();
+        assert!(matches!(decoded.content, DecodedContent::Encrypted));
+    }
+
+    #[test]
+    fn test_decode_sealed() {
+        let (_, taker) = identity();
+        let (maker_key, maker) = identity();
+        let sealed = fill_event(&taker, &maker, true);
+        let sealed_id = sealed.id.clone();
+
+        // Only the recipient can unseal it
+        let decoded = decode(sealed.clone(), None, None).unwrap();
+        assert_eq!(decoded.kind, SEALED);
+        assert!(matches!(decoded.content, DecodedContent::Encrypted));
+
+        let decoded = decode(sealed, Some(&maker_key), None).unwrap();
+        assert_eq!(decoded.kind, FILL);
+        assert_eq!(decoded.author, taker.public_key_str);
+        assert_eq!(decoded.sealed_id, Some(sealed_id));
+    }
+
+    #[test]
+    fn test_parse_event_signature() {
+        let (_, maker) = identity();
+        let event = fill_event(&maker, &maker, false);
+        let raw = serde_json::to_string(&event).unwrap();
+        assert!(parse_event(&raw).is_ok());
+
+        let tampered = raw.replace(&event.content, "tampered");
+        assert!(matches!(
+            parse_event(&tampered),
+            Err(Error::InvalidEventSignature)
+        ));
+    }
+}
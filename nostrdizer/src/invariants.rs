@@ -0,0 +1,287 @@
+use crate::{
+    errors::Error,
+    fees::checked_add,
+    round::RoundAccounting,
+    types::{Amount, IoAuth, NostrdizerOffer, SignedAmount},
+};
+
+use bdk::bitcoin::psbt::PartiallySignedTransaction;
+
+/// Accounting a CJ the taker built breaks, each one is a bug in how the round was built
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Violation {
+    /// Outputs are worth more than the inputs
+    ValueCreated { inputs: Amount, outputs: Amount },
+    /// Makers contribute more to the mining fee than the CJ pays miners, the taker keeps the rest
+    MakerTxfeeOverMiningFee {
+        mining_fee: Amount,
+        maker_txfee: Amount,
+    },
+    /// Maker gets back other than its inputs plus its fee less its mining fee contribution
+    MakerDelta {
+        maker: String,
+        expected: SignedAmount,
+        actual: SignedAmount,
+    },
+    /// Taker pays other than the maker fees, its part of the mining fee and any payment
+    TakerDelta {
+        expected: SignedAmount,
+        actual: SignedAmount,
+    },
+}
+
+/// Checks the value of a CJ is accounted for: inputs are outputs plus the mining fee, each maker gets
+/// its fee less its mining fee contribution, and the taker pays the maker fees and the rest of the mining fee
+/// `input_value` is the value of every input and `payment` what a payment round pays an external destination
+/// Maker change too small to be an output goes to the mining fee rather than the maker
+/// ```
+/// use nostrdizer::{
+///     invariants::{check_round, Violation},
+///     round::{MakerAccounting, RoundAccounting},
+///     test_utils::{io_auth, offer, psbt},
+///     types::{Amount, SignedAmount},
+/// };
+///
+/// let send_amount = Amount::from_sat(100_000);
+/// let maker = (offer("maker", 500), io_auth(0));
+/// let accounting = RoundAccounting::new(
+///     send_amount,
+///     vec![MakerAccounting::new(&maker.0, send_amount, Amount::from_sat(120_000), 1).unwrap()],
+///     1,
+///     1.0,
+/// );
+///
+/// // Maker gets 120,500 back, taker pays the fee and 500 of mining fee from its 150,000
+/// let mut cj = psbt(&[120_000, 150_000], &[100_000, 20_500, 100_000, 49_000]);
+/// cj.unsigned_tx.output[0].script_pubkey = maker.1.coinjoin_address.script_pubkey();
+/// cj.unsigned_tx.output[1].script_pubkey = maker.1.change_address.script_pubkey();
+/// let maker_inputs = [maker];
+/// let input_value = Amount::from_sat(270_000);
+/// assert!(check_round(&accounting, &maker_inputs, &cj, input_value, Amount::ZERO)
+///     .unwrap()
+///     .is_empty());
+///
+/// // Maker change shaved to the taker
+/// cj.unsigned_tx.output[1].value = 20_000;
+/// cj.unsigned_tx.output[3].value = 49_500;
+/// assert_eq!(
+///     check_round(&accounting, &maker_inputs, &cj, input_value, Amount::ZERO).unwrap()[0],
+///     Violation::MakerDelta {
+///         maker: "maker".to_string(),
+///         expected: SignedAmount::from_sat(500),
+///         actual: SignedAmount::ZERO,
+///     }
+/// );
+/// ```
+pub fn check_round(
+    accounting: &RoundAccounting,
+    maker_inputs: &[(NostrdizerOffer, IoAuth)],
+    psbt: &PartiallySignedTransaction,
+    input_value: Amount,
+    payment: Amount,
+) -> Result<Vec<Violation>, Error> {
+    let mut violations = vec![];
+    let output_value = psbt
+        .unsigned_tx
+        .output
+        .iter()
+        .map(|output| Amount::from_sat(output.value))
+        .try_fold(Amount::ZERO, checked_add)?;
+    if output_value > input_value {
+        violations.push(Violation::ValueCreated {
+            inputs: input_value,
+            outputs: output_value,
+        });
+        return Ok(violations);
+    }
+    let mining_fee = input_value - output_value;
+
+    let mut maker_input_value = Amount::ZERO;
+    let mut maker_output_value = Amount::ZERO;
+    // Maker change too small for an output, which the mining fee gets
+    let mut dropped_change = Amount::ZERO;
+    for maker in &accounting.makers {
+        let scripts: Vec<_> = maker_inputs
+            .iter()
+            .filter(|(offer, _)| offer.maker == maker.maker)
+            .flat_map(|(_, io_auth)| io_auth.addresses())
+            .map(|address| address.script_pubkey())
+            .collect();
+        let output = psbt
+            .unsigned_tx
+            .output
+            .iter()
+            .filter(|output| scripts.contains(&output.script_pubkey))
+            .map(|output| Amount::from_sat(output.value))
+            .try_fold(Amount::ZERO, checked_add)?;
+        let fee = maker.cjfee.to_signed()? - maker.txfee.to_signed()?;
+        let expected = match maker.change {
+            Some(_) => fee,
+            None => {
+                // Change left after the CJ output, that is not given back
+                let change =
+                    (maker.input_value.to_signed()? + fee) - accounting.send_amount.to_signed()?;
+                dropped_change =
+                    checked_add(dropped_change, change.to_unsigned().unwrap_or(Amount::ZERO))?;
+                accounting.send_amount.to_signed()? - maker.input_value.to_signed()?
+            }
+        };
+        let actual = output.to_signed()? - maker.input_value.to_signed()?;
+        if actual != expected {
+            violations.push(Violation::MakerDelta {
+                maker: maker.maker.clone(),
+                expected,
+                actual,
+            });
+        }
+        maker_input_value = checked_add(maker_input_value, maker.input_value)?;
+        maker_output_value = checked_add(maker_output_value, output)?;
+    }
+
+    let maker_txfee = checked_add(accounting.total_maker_txfee(), dropped_change)?;
+    if maker_txfee > mining_fee {
+        violations.push(Violation::MakerTxfeeOverMiningFee {
+            mining_fee,
+            maker_txfee,
+        });
+    }
+
+    // Taker pays the miners what the makers do not
+    let taker_mining_fee = mining_fee.to_signed()? - maker_txfee.to_signed()?;
+    let expected =
+        -(accounting.total_maker_fees().to_signed()? + taker_mining_fee + payment.to_signed()?);
+    // The payment output is not the taker's
+    let actual =
+        (output_value.to_signed()? - maker_output_value.to_signed()? - payment.to_signed()?)
+            - (input_value.to_signed()? - maker_input_value.to_signed()?);
+    if actual != expected {
+        violations.push(Violation::TakerDelta { expected, actual });
+    }
+    Ok(violations)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        round::MakerAccounting,
+        test_utils::{io_auth, offer, psbt},
+        types::TxFeeRate,
+    };
+
+    use bdk::bitcoin::Script;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    /// Accounting, maker inputs, CJ and value of its inputs
+    type BuiltRound = (
+        RoundAccounting,
+        Vec<(NostrdizerOffer, IoAuth)>,
+        PartiallySignedTransaction,
+        Amount,
+    );
+
+    /// Round built the way the backends build it, with random makers and taker change
+    /// Outputs are maker CJ outputs and change, then the taker CJ output, change and payment
+    fn built_round(rng: &mut StdRng, payment: Amount) -> BuiltRound {
+        let send_amount = Amount::from_sat(rng.gen_range(50_000..1_000_000));
+        let mut makers = vec![];
+        let mut maker_inputs = vec![];
+        let mut inputs = vec![];
+        let mut outputs = vec![];
+        let mut scripts = vec![];
+        for n in 0..rng.gen_range(1..6u8) {
+            let mut maker_offer = offer(&n.to_string(), rng.gen_range(0..2_000));
+            if rng.gen_bool(0.5) {
+                maker_offer.txfee_rate = Some(TxFeeRate {
+                    fee_rate: rng.gen_range(1.0..5.0),
+                    max_vbytes: 300,
+                });
+            }
+            let io_auth = io_auth(n);
+            // Change of some makers is dust
+            let input_value = send_amount.to_sat() + rng.gen_range(1_000..50_000);
+            let mut maker =
+                MakerAccounting::new(&maker_offer, send_amount, Amount::from_sat(input_value), 1)
+                    .unwrap();
+            maker.change = maker
+                .change
+                .and_then(|change| io_auth.change_output(change));
+
+            inputs.push(input_value);
+            outputs.push(send_amount.to_sat());
+            scripts.push(io_auth.coinjoin_address.script_pubkey());
+            if let Some(change) = maker.change {
+                outputs.push(change.to_sat());
+                scripts.push(io_auth.change_address.script_pubkey());
+            }
+            makers.push(maker);
+            maker_inputs.push((maker_offer, io_auth));
+        }
+        let mut accounting = RoundAccounting::new(send_amount, makers, 1, 1.0);
+        accounting.fee_rate = rng.gen_range(1.0..20.0);
+
+        let taker_mining_fee = accounting.taker_mining_fee();
+        let taker_input = send_amount.to_sat()
+            + payment.to_sat()
+            + accounting.total_maker_fees().to_sat()
+            + taker_mining_fee.to_sat()
+            + rng.gen_range(1_000..100_000);
+        let taker_change = taker_input
+            - send_amount.to_sat()
+            - payment.to_sat()
+            - accounting.total_maker_fees().to_sat()
+            - taker_mining_fee.to_sat();
+        inputs.push(taker_input);
+        outputs.extend([send_amount.to_sat(), taker_change]);
+        if payment > Amount::ZERO {
+            outputs.push(payment.to_sat());
+        }
+
+        let mut cj = psbt(&inputs, &outputs);
+        for (output, script) in cj.unsigned_tx.output.iter_mut().zip(scripts) {
+            output.script_pubkey = script;
+        }
+        let input_value = Amount::from_sat(inputs.iter().sum());
+        (accounting, maker_inputs, cj, input_value)
+    }
+
+    #[test]
+    fn test_built_rounds_hold_invariants() {
+        let mut rng = StdRng::seed_from_u64(1984);
+        for _ in 0..500 {
+            let payment = match rng.gen_bool(0.3) {
+                true => Amount::from_sat(rng.gen_range(10_000..100_000)),
+                false => Amount::ZERO,
+            };
+            let (accounting, maker_inputs, cj, input_value) = built_round(&mut rng, payment);
+            assert_eq!(
+                check_round(&accounting, &maker_inputs, &cj, input_value, payment).unwrap(),
+                vec![]
+            );
+        }
+    }
+
+    #[test]
+    fn test_broken_rounds_caught() {
+        let mut rng = StdRng::seed_from_u64(1985);
+        let (accounting, maker_inputs, mut cj, input_value) = built_round(&mut rng, Amount::ZERO);
+        let check = |cj: &PartiallySignedTransaction, input_value| {
+            check_round(&accounting, &maker_inputs, cj, input_value, Amount::ZERO).unwrap()
+        };
+
+        // Outputs worth more than the inputs
+        assert!(matches!(
+            check(&cj, Amount::from_sat(1_000))[..],
+            [Violation::ValueCreated { .. }]
+        ));
+
+        // First maker's CJ output paid to the taker
+        cj.unsigned_tx.output[0].script_pubkey = Script::new();
+        let violations = check(&cj, input_value);
+        assert!(matches!(violations[0], Violation::MakerDelta { .. }));
+        assert!(matches!(
+            violations.last(),
+            Some(Violation::TakerDelta { .. })
+        ));
+    }
+}
@@ -0,0 +1,136 @@
+use crate::{errors::Error, types::RejectReason};
+
+use bitcoin_hashes::{hmac, sha256, Hash, HashEngine};
+use nostr_rust::keys::get_random_secret_key;
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashSet;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+
+/// Secret invite tokens of a maker are minted with, and takers whose tokens are revoked
+/// Makers of a pool share the secret out of band so a token is good with any of them
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct InviteStore {
+    /// Hex key tokens are a HMAC with
+    pub secret: String,
+    /// Taker pubkeys whose tokens are no longer taken
+    #[serde(default, skip_serializing_if = "HashSet::is_empty")]
+    pub revoked: HashSet<String>,
+}
+
+impl Default for InviteStore {
+    fn default() -> Self {
+        let (sk, _) = get_random_secret_key();
+        Self {
+            secret: hex::encode(sk.as_ref()),
+            revoked: HashSet::new(),
+        }
+    }
+}
+
+impl InviteStore {
+    /// Loads invites from path, with a new secret if there is no file
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        match path.exists() {
+            true => Ok(serde_json::from_str(&fs::read_to_string(path)?)?),
+            false => Ok(Self::default()),
+        }
+    }
+
+    /// Writes invites to path, only readable by the user on unix
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+
+        let mut file = options.open(path)?;
+        file.write_all(serde_json::to_string_pretty(self)?.as_bytes())?;
+        Ok(())
+    }
+
+    /// Token of a taker, the HMAC of its pubkey, taken again if it was revoked
+    pub fn mint(&mut self, taker: &str) -> Result<String, Error> {
+        self.revoked.remove(taker);
+        token(&self.secret, taker)
+    }
+
+    /// Turns down the taker's token from now on
+    pub fn revoke(&mut self, taker: &str) {
+        self.revoked.insert(taker.to_string());
+    }
+
+    /// Why a fill from taker is turned down, none if it carries a token minted for it that is not revoked
+    /// ```
+    /// use nostrdizer::{invite::InviteStore, types::RejectReason};
+    ///
+    /// let mut invites = InviteStore::default();
+    /// let token = invites.mint("taker").unwrap();
+    ///
+    /// assert_eq!(invites.check("taker", Some(&token)), None);
+    /// assert_eq!(invites.check("other", Some(&token)), Some(RejectReason::InviteRequired));
+    /// assert_eq!(invites.check("taker", None), Some(RejectReason::InviteRequired));
+    ///
+    /// invites.revoke("taker");
+    /// assert_eq!(invites.check("taker", Some(&token)), Some(RejectReason::InviteRequired));
+    /// ```
+    pub fn check(&self, taker: &str, invite: Option<&str>) -> Option<RejectReason> {
+        let valid = match (invite, token(&self.secret, taker)) {
+            (Some(invite), Ok(token)) => invite == token && !self.revoked.contains(taker),
+            _ => false,
+        };
+        match valid {
+            true => None,
+            false => Some(RejectReason::InviteRequired),
+        }
+    }
+}
+
+/// HMAC-SHA256 of the taker pubkey with the hex secret
+fn token(secret: &str, taker: &str) -> Result<String, Error> {
+    let secret = hex::decode(secret).map_err(|_| Error::InvalidConfig("invite secret".into()))?;
+    let mut engine = hmac::HmacEngine::<sha256::Hash>::new(&secret);
+    engine.input(taker.as_bytes());
+    Ok(hmac::Hmac::<sha256::Hash>::from_engine(engine).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokens_shared_by_pool() {
+        let mut first = InviteStore::default();
+        let second = InviteStore {
+            secret: first.secret.clone(),
+            revoked: HashSet::new(),
+        };
+        let token = first.mint("taker").unwrap();
+        assert_eq!(second.check("taker", Some(&token)), None);
+
+        // Makers with another secret do not take it
+        assert_eq!(
+            InviteStore::default().check("taker", Some(&token)),
+            Some(RejectReason::InviteRequired)
+        );
+
+        // Minting again takes back a revoke
+        first.revoke("taker");
+        assert_eq!(first.mint("taker").unwrap(), token);
+        assert_eq!(first.check("taker", Some(&token)), None);
+    }
+
+    #[test]
+    fn test_save_load() {
+        let path = std::env::temp_dir().join("nostrdizer-invites.json");
+        let mut invites = InviteStore::default();
+        invites.revoke("taker");
+        invites.save(&path).unwrap();
+        assert_eq!(InviteStore::load(&path).unwrap(), invites);
+        std::fs::remove_file(path).unwrap();
+    }
+}
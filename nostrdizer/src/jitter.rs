@@ -0,0 +1,71 @@
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+/// Seconds between offer refreshes while the maker waits for fills, before jitter
+pub const OFFER_REFRESH: u64 = 600;
+
+/// Random delays in publishing a maker's offers
+/// Makers restarting at once, after an outage of a relay they share, would otherwise flood relays together
+/// and refresh their offers in step, so the book would show which offers came up together
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OfferJitter {
+    /// Most seconds offers are held back before they are published
+    pub max_delay: u64,
+    /// Most seconds a refresh comes early or late, capped to a quarter of [`OFFER_REFRESH`]
+    pub refresh_spread: u64,
+    /// Most seconds between publishing one offer of the maker and the next
+    pub max_stagger: u64,
+}
+
+impl Default for OfferJitter {
+    fn default() -> Self {
+        Self {
+            max_delay: 30,
+            refresh_spread: 120,
+            max_stagger: 10,
+        }
+    }
+}
+
+impl OfferJitter {
+    /// Offers are published and refreshed on time
+    pub fn none() -> Self {
+        Self {
+            max_delay: 0,
+            refresh_spread: 0,
+            max_stagger: 0,
+        }
+    }
+
+    /// Seconds to hold offers back before publishing them
+    pub fn delay(&self, rng: &mut impl Rng) -> u64 {
+        rng.gen_range(0..=self.max_delay)
+    }
+
+    /// Seconds until offers are refreshed
+    /// ```
+    /// use nostrdizer::jitter::{OfferJitter, OFFER_REFRESH};
+    /// use rand::thread_rng;
+    ///
+    /// let jitter = OfferJitter::default();
+    /// let interval = jitter.refresh_interval(&mut thread_rng());
+    /// assert!((480..=720).contains(&interval));
+    ///
+    /// // Spread does not go over a quarter of the interval, presence has to outlast it
+    /// let jitter = OfferJitter {
+    ///     refresh_spread: OFFER_REFRESH,
+    ///     ..OfferJitter::default()
+    /// };
+    /// assert!(jitter.refresh_interval(&mut thread_rng()) <= OFFER_REFRESH * 5 / 4);
+    /// assert_eq!(OfferJitter::none().refresh_interval(&mut thread_rng()), OFFER_REFRESH);
+    /// ```
+    pub fn refresh_interval(&self, rng: &mut impl Rng) -> u64 {
+        let spread = self.refresh_spread.min(OFFER_REFRESH / 4);
+        OFFER_REFRESH - spread + rng.gen_range(0..=2 * spread)
+    }
+
+    /// Seconds between publishing one offer and the next
+    pub fn stagger(&self, rng: &mut impl Rng) -> u64 {
+        rng.gen_range(0..=self.max_stagger)
+    }
+}
@@ -0,0 +1,303 @@
+use crate::{errors::Error, fidelity_bond::FidelityBond, podle::MAX_PODLE_INDEX};
+
+use bdk::bitcoin::OutPoint;
+use bitcoin_hashes::sha256;
+use nostr_rust::{keys::get_random_secret_key, Identity};
+use secp256k1::{PublicKey, SecretKey};
+use serde::{Deserialize, Serialize};
+
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Podle commitment to a utxo key at a NUMS index, kept so a retried round reuses it rather then another index
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CachedCommitment {
+    pub outpoint: OutPoint,
+    pub index: u8,
+    /// Commitment fills are sent with, hash of `p2`
+    pub commit: sha256::Hash,
+    /// Point revealed with the opening in auths
+    #[serde(rename = "P2")]
+    pub p2: PublicKey,
+    /// Sent to makers in an auth, makers may have shared it so it is not used again
+    pub revealed: bool,
+}
+
+/// Nostr keys a maker has used and podle commitments a taker has generated, kept between runs
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Keystore {
+    /// Hex private key currently in use
+    pub current: Option<String>,
+    /// Hex private keys used before that may still have offers published
+    #[serde(default)]
+    pub previous: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub commitments: Vec<CachedCommitment>,
+    /// Hex private key messages are encrypted to when the maker advertises a separate encryption key
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encryption_key: Option<String>,
+    /// Coins the maker locked in fidelity bonds, kept once they expire so the coins can be spent
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fidelity_bonds: Vec<FidelityBond>,
+}
+
+impl Keystore {
+    /// Loads keystore from path, an empty keystore if there is no file
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        match path.exists() {
+            true => Ok(serde_json::from_str(&fs::read_to_string(path)?)?),
+            false => Ok(Self::default()),
+        }
+    }
+
+    /// Writes keystore to path, only readable by the user on unix
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+
+        let mut file = options.open(path)?;
+        file.write_all(serde_json::to_string_pretty(self)?.as_bytes())?;
+        Ok(())
+    }
+
+    /// Gets the key to use, keeping the current key if none is given
+    /// A new key is generated if there is no current key
+    /// The replaced key is kept so its offers can be deleted
+    pub fn priv_key(&mut self, priv_key: Option<String>) -> String {
+        let priv_key = match (priv_key, &self.current) {
+            (Some(key), _) => key,
+            (None, Some(current)) => current.clone(),
+            (None, None) => {
+                let (sk, _) = get_random_secret_key();
+                hex::encode(sk.as_ref())
+            }
+        };
+
+        if let Some(current) = self.current.take() {
+            if current != priv_key && !self.previous.contains(&current) {
+                self.previous.push(current);
+            }
+        }
+        self.previous.retain(|key| key != &priv_key);
+        self.current = Some(priv_key.clone());
+
+        priv_key
+    }
+
+    /// Key messages to the maker are encrypted to, a new key is generated if there is none
+    pub fn encryption_key(&mut self) -> Result<SecretKey, Error> {
+        let encryption_key = match &self.encryption_key {
+            Some(key) => key.clone(),
+            None => self.rotate_encryption_key(),
+        };
+        Ok(Identity::from_str(&encryption_key)?.secret_key)
+    }
+
+    /// Replaces the encryption key, the nostr key and the reputation kept under it stay the same
+    /// Returns the new hex private key
+    pub fn rotate_encryption_key(&mut self) -> String {
+        let (sk, _) = get_random_secret_key();
+        let encryption_key = hex::encode(sk.as_ref());
+        self.encryption_key = Some(encryption_key.clone());
+        encryption_key
+    }
+
+    /// Bond the maker advertises at `now`, the largest of those sent to that are still locked
+    pub fn fidelity_bond(&self, now: u64) -> Option<&FidelityBond> {
+        self.fidelity_bonds
+            .iter()
+            .filter(|bond| bond.outpoint.is_some() && bond.is_locked(now))
+            .max_by_key(|bond| bond.amount)
+    }
+
+    pub fn previous_identities(&self) -> Result<Vec<Identity>, Error> {
+        self.previous
+            .iter()
+            .map(|key| Ok(Identity::from_str(key)?))
+            .collect()
+    }
+
+    /// Forgets previous keys once their offers are deleted
+    pub fn remove_previous(&mut self, pub_keys: &[String]) -> Result<(), Error> {
+        let identities = self.previous_identities()?;
+        self.previous = self
+            .previous
+            .iter()
+            .zip(identities)
+            .filter(|(_, identity)| !pub_keys.contains(&identity.public_key_str))
+            .map(|(key, _)| key.clone())
+            .collect();
+        Ok(())
+    }
+
+    /// Commitment generated but not revealed of the first of the utxos that has one up to the max index, at its lowest index
+    pub fn unrevealed(&self, outpoints: &[OutPoint], max_index: u8) -> Option<&CachedCommitment> {
+        outpoints.iter().find_map(|outpoint| {
+            self.commitments
+                .iter()
+                .filter(|commitment| {
+                    &commitment.outpoint == outpoint
+                        && !commitment.revealed
+                        && commitment.index <= max_index
+                })
+                .min_by_key(|commitment| commitment.index)
+        })
+    }
+
+    /// Lowest index up to the max of a utxo no commitment was generated at, none once every such index is used
+    pub fn next_index(&self, outpoint: &OutPoint, max_index: u8) -> Option<u8> {
+        (0..=max_index.min(MAX_PODLE_INDEX)).find(|index| {
+            !self
+                .commitments
+                .iter()
+                .any(|commitment| &commitment.outpoint == outpoint && commitment.index == *index)
+        })
+    }
+
+    pub fn add_commitment(&mut self, commitment: CachedCommitment) {
+        self.commitments.push(commitment);
+    }
+
+    /// Removes the revealed commitments of utxos that are spent, they can not be sent again
+    /// Commitments of unspent utxos are kept so their revealed indexes are not generated again
+    /// Returns the number of commitments removed
+    pub fn purge_revealed(&mut self, unspent: &[OutPoint]) -> usize {
+        let before = self.commitments.len();
+        self.commitments
+            .retain(|commitment| !commitment.revealed || unspent.contains(&commitment.outpoint));
+        before - self.commitments.len()
+    }
+
+    /// Marks a commitment revealed so it is not sent in another round
+    pub fn reveal(&mut self, commit: &sha256::Hash) {
+        for commitment in self
+            .commitments
+            .iter_mut()
+            .filter(|commitment| &commitment.commit == commit)
+        {
+            commitment.revealed = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Amount;
+
+    #[test]
+    fn test_key_persisted() {
+        let mut keystore = Keystore::default();
+        let key = keystore.priv_key(None);
+
+        assert_eq!(keystore.priv_key(None), key);
+        assert!(keystore.previous.is_empty());
+    }
+
+    #[test]
+    fn test_encryption_key_rotated() {
+        let mut keystore = Keystore::default();
+        let nostr_key = keystore.priv_key(None);
+        let encryption_key = keystore.encryption_key().unwrap();
+        assert_eq!(keystore.encryption_key().unwrap(), encryption_key);
+
+        keystore.rotate_encryption_key();
+        assert_ne!(keystore.encryption_key().unwrap(), encryption_key);
+        assert_eq!(keystore.priv_key(None), nostr_key);
+    }
+
+    #[test]
+    fn test_replaced_key_kept() {
+        let mut keystore = Keystore::default();
+        let old_key = keystore.priv_key(None);
+        let (sk, _) = get_random_secret_key();
+        let new_key = keystore.priv_key(Some(hex::encode(sk.as_ref())));
+
+        assert_eq!(keystore.current, Some(new_key));
+        assert_eq!(keystore.previous, vec![old_key.clone()]);
+
+        let old_pub_key = Identity::from_str(&old_key).unwrap().public_key_str;
+        keystore.remove_previous(&[old_pub_key]).unwrap();
+        assert!(keystore.previous.is_empty());
+    }
+
+    #[test]
+    fn test_unrevealed_commitment_reused() {
+        let outpoint = OutPoint::default();
+        let priv_key =
+            bdk::bitcoin::PrivateKey::from_slice(&[7; 32], bdk::bitcoin::Network::Regtest).unwrap();
+        let mut keystore = Keystore::default();
+
+        for _ in 0..=MAX_PODLE_INDEX {
+            // Until it is revealed the commitment is the one a retried round is sent with
+            let index = keystore.next_index(&outpoint, MAX_PODLE_INDEX).unwrap();
+            let (commit, p2) = crate::podle::commitment(index, priv_key).unwrap();
+            keystore.add_commitment(CachedCommitment {
+                outpoint,
+                index,
+                commit,
+                p2,
+                revealed: false,
+            });
+            assert_eq!(
+                keystore
+                    .unrevealed(&[outpoint], MAX_PODLE_INDEX)
+                    .unwrap()
+                    .index,
+                index
+            );
+            // Makers that accept lower indices are not sent it
+            if index > 0 {
+                assert_eq!(keystore.unrevealed(&[outpoint], index - 1), None);
+                assert_eq!(keystore.next_index(&outpoint, index - 1), None);
+            }
+
+            keystore.reveal(&commit);
+            assert_eq!(keystore.unrevealed(&[outpoint], MAX_PODLE_INDEX), None);
+        }
+        assert_eq!(keystore.next_index(&outpoint, MAX_PODLE_INDEX), None);
+
+        // Revealed commitments are only purged once the utxo is spent
+        assert_eq!(keystore.purge_revealed(&[outpoint]), 0);
+        assert_eq!(keystore.purge_revealed(&[]), MAX_PODLE_INDEX as usize + 1);
+    }
+
+    #[test]
+    fn test_save_load() {
+        let path = std::env::temp_dir().join("nostrdizer_test_keystore.json");
+        let mut keystore = Keystore::default();
+        keystore.priv_key(None);
+
+        keystore.save(&path).unwrap();
+        assert_eq!(Keystore::load(&path).unwrap(), keystore);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_largest_locked_bond_advertised() {
+        let bond = |amount, locktime, sent: bool| FidelityBond {
+            key: "01".repeat(32),
+            locktime,
+            outpoint: sent.then(OutPoint::null),
+            amount: Amount::from_sat(amount),
+        };
+        let mut keystore = Keystore::default();
+        assert!(keystore.fidelity_bond(1_700_000_000).is_none());
+
+        keystore.fidelity_bonds = vec![
+            bond(1_000_000, 1_800_000_000, true),
+            bond(9_000_000, 1_600_000_000, true),
+            bond(5_000_000, 1_800_000_000, false),
+            bond(2_000_000, 1_800_000_000, true),
+        ];
+        // The larger bonds expired or were never sent to
+        let advertised = keystore.fidelity_bond(1_700_000_000).unwrap();
+        assert_eq!(advertised.amount, Amount::from_sat(2_000_000));
+    }
+}
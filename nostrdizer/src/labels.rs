@@ -0,0 +1,253 @@
+use crate::{
+    errors::Error,
+    transcript::RoundHistory,
+    types::{BitcoinTransaction, OutPoint, Txid},
+};
+
+use bdk::bitcoin::TxOut;
+use serde::{Deserialize, Serialize};
+
+use std::fs;
+use std::path::Path;
+
+// Labels nostrdizer gives the CJs it takes part in and their outputs
+pub const CJ_LABEL: &str = "nostrdizer coinjoin";
+pub const CJ_OUTPUT_LABEL: &str = "nostrdizer coinjoin output";
+pub const CJ_CHANGE_LABEL: &str = "nostrdizer coinjoin change";
+
+/// What a BIP-329 label is for
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LabelType {
+    Tx,
+    Addr,
+    Pubkey,
+    Input,
+    Output,
+    Xpub,
+}
+
+/// BIP-329 wallet label, one per line of an export
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Label {
+    #[serde(rename = "type")]
+    pub label_type: LabelType,
+    /// Txid, address, `txid:vout` or key the label is for
+    #[serde(rename = "ref")]
+    pub reference: String,
+    #[serde(default)]
+    pub label: String,
+    /// Descriptor of the wallet the label is from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<String>,
+    /// Whether an output may be spent, only set on outputs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spendable: Option<bool>,
+}
+
+impl Label {
+    pub fn tx(txid: &Txid, label: &str) -> Self {
+        Self {
+            label_type: LabelType::Tx,
+            reference: txid.to_string(),
+            label: label.to_string(),
+            origin: None,
+            spendable: None,
+        }
+    }
+
+    pub fn output(outpoint: &OutPoint, label: &str) -> Self {
+        Self {
+            label_type: LabelType::Output,
+            reference: outpoint.to_string(),
+            label: label.to_string(),
+            origin: None,
+            spendable: Some(true),
+        }
+    }
+}
+
+/// Labels of CJs and their outputs, kept in BIP-329 format so they can be imported into other wallets
+/// ```
+/// use bdk::bitcoin::Address;
+/// use nostrdizer::{
+///     labels::{LabelStore, LabelType, CJ_CHANGE_LABEL, CJ_LABEL, CJ_OUTPUT_LABEL},
+///     test_utils::psbt,
+/// };
+/// use std::str::FromStr;
+///
+/// let own = Address::from_str("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4")
+///     .unwrap()
+///     .script_pubkey();
+/// let mut tx = psbt(&[200_000], &[50_000, 50_000, 49_000]).unsigned_tx;
+/// tx.output[1].script_pubkey = own.clone();
+/// tx.output[2].script_pubkey = own.clone();
+///
+/// let mut labels = LabelStore::default();
+/// labels.label_cj(&tx, |output| output.script_pubkey == own);
+///
+/// let txid = tx.txid().to_string();
+/// assert_eq!(labels.get(LabelType::Tx, &txid).unwrap().label, CJ_LABEL);
+/// let output = |vout| labels.get(LabelType::Output, &format!("{txid}:{vout}"));
+/// assert!(output(0).is_none());
+/// assert_eq!(output(1).unwrap().label, CJ_OUTPUT_LABEL);
+/// assert_eq!(output(2).unwrap().label, CJ_CHANGE_LABEL);
+///
+/// let export = labels.export().unwrap();
+/// assert_eq!(export.lines().count(), 3);
+/// assert_eq!(LabelStore::parse(&export).unwrap(), labels);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LabelStore {
+    pub labels: Vec<Label>,
+}
+
+impl LabelStore {
+    /// Loads labels from path, no labels if there is no file
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        match path.exists() {
+            true => Self::parse(&fs::read_to_string(path)?),
+            false => Ok(Self::default()),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        Ok(fs::write(path, self.export()?)?)
+    }
+
+    /// Reads labels in BIP-329 JSON lines, blank lines are skipped
+    pub fn parse(jsonl: &str) -> Result<Self, Error> {
+        let mut store = Self::default();
+        store.import(jsonl)?;
+        Ok(store)
+    }
+
+    /// Labels as BIP-329 JSON lines
+    pub fn export(&self) -> Result<String, Error> {
+        let mut jsonl = String::new();
+        for label in &self.labels {
+            jsonl.push_str(&serde_json::to_string(label)?);
+            jsonl.push('\n');
+        }
+        Ok(jsonl)
+    }
+
+    pub fn get(&self, label_type: LabelType, reference: &str) -> Option<&Label> {
+        self.labels
+            .iter()
+            .find(|label| label.label_type == label_type && label.reference == reference)
+    }
+
+    /// Adds label, replacing one of the same type and ref
+    pub fn set(&mut self, label: Label) {
+        match self.labels.iter_mut().find(|existing| {
+            existing.label_type == label.label_type && existing.reference == label.reference
+        }) {
+            Some(existing) => *existing = label,
+            None => self.labels.push(label),
+        }
+    }
+
+    /// Adds labels exported by another wallet, returns how many were read
+    /// Imported labels replace the ones of the same type and ref
+    pub fn import(&mut self, jsonl: &str) -> Result<usize, Error> {
+        let mut count = 0;
+        for (index, line) in jsonl.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let label = serde_json::from_str(line).map_err(|_| Error::InvalidLabel(index + 1))?;
+            self.set(label);
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Labels a CJ and the outputs of it that are own
+    /// Own outputs of the value other outputs have are CJ outputs, the rest change
+    pub fn label_cj(&mut self, tx: &BitcoinTransaction, is_own: impl Fn(&TxOut) -> bool) {
+        let txid = tx.txid();
+        self.set(Label::tx(&txid, CJ_LABEL));
+        for (vout, output) in tx.output.iter().enumerate() {
+            if !is_own(output) {
+                continue;
+            }
+            let label = match is_cj_output(tx, output) {
+                true => CJ_OUTPUT_LABEL,
+                false => CJ_CHANGE_LABEL,
+            };
+            self.set(Label::output(&OutPoint::new(txid, vout as u32), label));
+        }
+    }
+
+    /// Labels CJs of the round history that have no label, returns how many were added
+    /// Rounds from before labels were kept only get a label on the transaction
+    pub fn label_history(&mut self, history: &RoundHistory) -> usize {
+        let mut count = 0;
+        for round in &history.rounds {
+            if self.get(LabelType::Tx, &round.txid.to_string()).is_none() {
+                self.set(Label::tx(&round.txid, CJ_LABEL));
+                count += 1;
+            }
+        }
+        count
+    }
+}
+
+/// Whether an output is one of the equal valued CJ outputs
+pub fn is_cj_output(tx: &BitcoinTransaction, output: &TxOut) -> bool {
+    tx.output.iter().filter(|o| o.value == output.value).count() > 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transcript::{Completion, RoundRecord, Transcript};
+    use serde_json::json;
+
+    #[test]
+    fn test_import_keeps_other_wallet_labels() {
+        let txid = OutPoint::null().txid;
+        let mut labels = LabelStore::default();
+        labels.set(Label::tx(&txid, CJ_LABEL));
+
+        // Sparrow export with a label of its own for the same tx
+        let tx = json!({
+            "type": "tx",
+            "ref": txid.to_string(),
+            "label": "Payment",
+            "origin": "wpkh([d34db33f/84'/0'/0'])"
+        });
+        let addr = json!({
+            "type": "addr",
+            "ref": "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4",
+            "label": "Savings"
+        });
+        let jsonl = format!("{tx}\n\n{addr}\n");
+        assert_eq!(labels.import(&jsonl).unwrap(), 2);
+        assert_eq!(labels.labels.len(), 2);
+        assert_eq!(
+            labels.get(LabelType::Tx, &txid.to_string()).unwrap().label,
+            "Payment"
+        );
+
+        assert!(matches!(
+            labels.import("{\"type\":\"tx\"}"),
+            Err(Error::InvalidLabel(1))
+        ));
+
+        // Rounds already labelled are not labelled again
+        let history = RoundHistory {
+            rounds: vec![RoundRecord {
+                round_id: "round".to_string(),
+                peer: "maker".to_string(),
+                txid,
+                transcript: Transcript::default().hash(&txid),
+                completion: Completion::Confirmed,
+                created_at: 0,
+            }],
+        };
+        assert_eq!(labels.label_history(&history), 0);
+        assert_eq!(LabelStore::default().label_history(&history), 1);
+    }
+}
@@ -0,0 +1,246 @@
+use crate::{errors::Error, subscription::SubscriptionGuard};
+
+use nostr_rust::{nostr_client::Client as NostrClient, req::ReqFilter};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+// Each step is a message to the maker and its answer, each relayed there and back
+const ROUND_TRIPS_PER_STEP: u64 = 2;
+// Headroom for the maker's own work and for relays slowing down during the round
+const SAFETY_FACTOR: u64 = 3;
+
+/// Steps of a round the taker waits on makers for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ProtocolStep {
+    FillAck,
+    IoAuth,
+    SignedTx,
+    Confirm,
+}
+
+impl ProtocolStep {
+    pub const ALL: [ProtocolStep; 4] = [
+        ProtocolStep::FillAck,
+        ProtocolStep::IoAuth,
+        ProtocolStep::SignedTx,
+        ProtocolStep::Confirm,
+    ];
+
+    /// Seconds the taker waits for makers to answer
+    pub fn timeout(&self) -> u64 {
+        match self {
+            ProtocolStep::FillAck => 30,
+            ProtocolStep::IoAuth => 60,
+            ProtocolStep::SignedTx => 60,
+            ProtocolStep::Confirm => 30,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ProtocolStep::FillAck => "fill ack",
+            ProtocolStep::IoAuth => "ioauth",
+            ProtocolStep::SignedTx => "signed transaction",
+            ProtocolStep::Confirm => "confirm",
+        }
+    }
+}
+
+/// How quickly a maker answers, advertised in its offers so takers give slow makers longer
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "kebab-case")]
+pub enum LatencyClass {
+    ClearnetFast,
+    Clearnet,
+    TorSlow,
+}
+
+impl LatencyClass {
+    /// Seconds the taker waits for a maker of this class at step
+    pub fn timeout(&self, step: ProtocolStep) -> u64 {
+        match self {
+            LatencyClass::ClearnetFast => step.timeout() / 2,
+            LatencyClass::Clearnet => step.timeout(),
+            LatencyClass::TorSlow => step.timeout() * 2,
+        }
+    }
+}
+
+impl FromStr for LatencyClass {
+    type Err = Error;
+
+    fn from_str(class: &str) -> Result<Self, Self::Err> {
+        match class {
+            "clearnet-fast" => Ok(Self::ClearnetFast),
+            "clearnet" => Ok(Self::Clearnet),
+            "tor-slow" => Ok(Self::TorSlow),
+            _ => Err(Error::UnknownLatencyClass(class.to_string())),
+        }
+    }
+}
+
+/// Seconds the taker waits at step for the makers it has no answer from, until the slowest of them is due
+/// Makers that advertise no class get the step's default timeout
+/// ```
+/// use nostrdizer::latency::{deadline, LatencyClass, ProtocolStep};
+///
+/// let fast = Some(LatencyClass::ClearnetFast);
+/// assert_eq!(deadline(ProtocolStep::IoAuth, [fast, fast]), 30);
+/// assert_eq!(deadline(ProtocolStep::IoAuth, [fast, None]), 60);
+/// assert_eq!(
+///     deadline(ProtocolStep::IoAuth, [fast, Some(LatencyClass::TorSlow)]),
+///     120
+/// );
+/// ```
+pub fn deadline(
+    step: ProtocolStep,
+    classes: impl IntoIterator<Item = Option<LatencyClass>>,
+) -> u64 {
+    classes
+        .into_iter()
+        .map(|class| match class {
+            Some(class) => class.timeout(step),
+            None => step.timeout(),
+        })
+        .max()
+        .unwrap_or_else(|| step.timeout())
+}
+
+/// Round trip time of a relay, none if it did not answer the probe
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelayLatency {
+    pub relay: String,
+    /// Milliseconds from the query being sent to the relay's end of stored events
+    pub rtt: Option<u64>,
+}
+
+/// Expected time of a step against the time the taker waits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StepBudget {
+    pub step: ProtocolStep,
+    /// Milliseconds the step is expected to take
+    pub estimate: u64,
+    /// Milliseconds the taker waits for the step
+    pub timeout: u64,
+}
+
+impl StepBudget {
+    pub fn fits(&self) -> bool {
+        self.estimate <= self.timeout
+    }
+}
+
+/// Expected time of each step, from the fastest relay as messages are sent to every relay
+/// ```
+/// use nostrdizer::latency::{budget, RelayLatency};
+///
+/// let latencies = vec![
+///     RelayLatency {
+///         relay: "wss://slow".to_string(),
+///         rtt: None,
+///     },
+///     RelayLatency {
+///         relay: "wss://fast".to_string(),
+///         rtt: Some(400),
+///     },
+/// ];
+///
+/// let steps = budget(&latencies).unwrap();
+/// assert_eq!(steps[0].estimate, 2_400);
+/// assert!(steps.iter().all(|step| step.fits()));
+/// ```
+pub fn budget(latencies: &[RelayLatency]) -> Result<Vec<StepBudget>, Error> {
+    let rtt = latencies
+        .iter()
+        .filter_map(|latency| latency.rtt)
+        .min()
+        .ok_or(Error::NoRelayAnswered)?;
+
+    Ok(ProtocolStep::ALL
+        .into_iter()
+        .map(|step| StepBudget {
+            step,
+            estimate: rtt * ROUND_TRIPS_PER_STEP * SAFETY_FACTOR,
+            timeout: step.timeout() * 1000,
+        })
+        .collect())
+}
+
+/// Fails on the first step the relays are too slow for, so a round is not started only to fail halfway
+/// ```
+/// use nostrdizer::{
+///     errors::Error,
+///     latency::{check_budget, RelayLatency},
+/// };
+///
+/// let latencies = vec![RelayLatency {
+///     relay: "wss://slow".to_string(),
+///     rtt: Some(6_000),
+/// }];
+///
+/// assert!(matches!(
+///     check_budget(&latencies),
+///     Err(Error::RelaysTooSlow(_, 36_000, 30_000))
+/// ));
+/// ```
+pub fn check_budget(latencies: &[RelayLatency]) -> Result<Vec<StepBudget>, Error> {
+    let steps = budget(latencies)?;
+    match steps.iter().find(|step| !step.fits()) {
+        Some(step) => Err(Error::RelaysTooSlow(
+            step.step.name().to_string(),
+            step.estimate,
+            step.timeout,
+        )),
+        None => Ok(steps),
+    }
+}
+
+/// Times each relay's answer to a query that matches no events
+/// Relays that do not answer within the shortest step timeout have no round trip time
+pub fn probe(
+    nostr_client: &mut NostrClient,
+    relays: &[String],
+) -> Result<Vec<RelayLatency>, Error> {
+    let filter = ReqFilter {
+        ids: Some(vec!["0".repeat(64)]),
+        authors: None,
+        kinds: None,
+        e: None,
+        p: None,
+        since: None,
+        until: None,
+        limit: None,
+    };
+    let timeout = ProtocolStep::ALL
+        .iter()
+        .map(|step| step.timeout())
+        .min()
+        .unwrap_or_default();
+
+    let started = Instant::now();
+    let mut subscription = SubscriptionGuard::subscribe(nostr_client, vec![filter])?;
+    let mut rtts: HashMap<String, u64> = HashMap::new();
+    while rtts.len() < relays.len() && started.elapsed() < Duration::from_secs(timeout) {
+        for (relay, message) in subscription.next_data()? {
+            if let Ok(message) = serde_json::from_str::<Value>(&message.to_string()) {
+                if message[0] == "EOSE" && message[1].as_str() == Some(subscription.id()) {
+                    rtts.entry(relay)
+                        .or_insert(started.elapsed().as_millis() as u64);
+                }
+            }
+        }
+    }
+    drop(subscription);
+
+    Ok(relays
+        .iter()
+        .map(|relay| RelayLatency {
+            relay: relay.clone(),
+            rtt: rtts.get(relay).copied(),
+        })
+        .collect())
+}
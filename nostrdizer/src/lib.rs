@@ -1,10 +1,70 @@
+// Backends implement the same Maker and Taker methods, exactly one is built in
+#[cfg(all(feature = "bitcoincore", feature = "bdk"))]
+compile_error!(
+    "features `bitcoincore` and `bdk` can not both be enabled, select one wallet backend"
+);
+#[cfg(not(any(feature = "bitcoincore", feature = "bdk")))]
+compile_error!("select a wallet backend with the `bitcoincore` or `bdk` feature");
+
+pub mod address_store;
+pub mod audit;
+pub mod backup;
 #[cfg(feature = "bdk")]
 pub mod bdk;
 #[cfg(feature = "bitcoincore")]
 pub mod bitcoincore;
+pub mod blacklist;
+pub mod build_info;
+#[cfg(any(feature = "bitcoincore", feature = "bdk"))]
+pub mod builder;
+pub mod capabilities;
+pub mod coin_selection;
+pub mod commitment;
+pub mod config;
+pub mod coop;
+pub mod cosign;
+pub mod denomination;
+pub mod display;
+pub mod encryption;
 pub mod errors;
+pub mod fees;
+pub mod fiat;
+pub mod fidelity_bond;
+pub mod fill_queue;
+pub mod greylist;
+pub mod identity;
+pub mod inbox;
+pub mod inspect;
+pub mod invariants;
+pub mod invite;
+pub mod jitter;
+pub mod keystore;
+pub mod labels;
+pub mod latency;
 pub mod maker;
+pub mod maker_selection;
+pub mod order_book;
+pub mod partition;
+pub mod payout;
 pub mod podle;
+pub mod policy;
+pub mod psbt_v2;
+pub mod publication;
+pub mod relay_auth;
+pub mod relay_pool;
+pub mod reputation;
+pub mod retention;
+pub mod round;
+pub mod sequence;
+pub mod silent_payment;
+pub mod snapshot;
+pub mod standby;
+pub mod stats;
+pub mod subscription;
 pub mod taker;
+// Fixtures for tests and doc examples
+#[cfg(any(test, feature = "test-utils"))]
+pub mod test_utils;
+pub mod transcript;
 pub mod types;
 pub mod utils;
@@ -1,58 +1,146 @@
 use crate::{
+    backup::{self, Backup},
+    blacklist::{self, Commitment},
+    coin_selection, display,
+    encryption::{Envelope, MakerEncryption},
     errors::Error,
-    podle,
+    fees,
+    fidelity_bond::FidelityBondProof,
+    fill_queue::{FillQueue, QueueError, QueuedFill},
+    greylist::TakerGreylist,
+    payout::{PayoutConfig, PayoutHistory},
+    podle::MAX_PODLE_INDEX,
+    publication::Publisher,
+    relay_pool::{RelayPool, RelayRole},
+    round::round_id,
+    subscription::{self, SubscriptionGuard},
+    transcript::{Completion, RoundRecord, Transcript},
     types::{
-        AbsOffer, Amount, AuthCommitment, Fill, IoAuth, MakerConfig, NostrdizerMessage,
-        NostrdizerMessageKind, NostrdizerMessages, Offer, Pubkey, RelOffer, ABS_OFFER, AUTH, FILL,
-        IOAUTH, REL_OFFER, TRANSACTION,
+        AbsOffer, Amount, AuthProof, Confirm, CounterOffer, Fill, FillAck, IoAuth, MakerConfig,
+        MakerStatus, NostrdizerMessage, NostrdizerMessageKind, NostrdizerMessages, Offer, Presence,
+        PresenceStatus, Pubkey, RejectReason, RelOffer, Transaction, VerifyCJInfo, ABORT,
+        ABS_OFFER, AUTH, CONFIRM, DUST, FILL, FILL_ACK, IOAUTH, PROTOCOL_VERSION, PUBKEY,
+        REL_OFFER, SEALED, SESSION_TIMEOUT, TRANSACTION,
     },
-    utils::{self, decrypt_message},
+    utils::{self, unseal_event},
 };
 
-use bdk::bitcoin::psbt::PartiallySignedTransaction;
+use bdk::bitcoin::{psbt::PartiallySignedTransaction, Network, OutPoint, Txid};
 
 #[cfg(feature = "bdk")]
-use bdk::{database::AnyDatabase, wallet::Wallet};
+use bdk::{blockchain::AnyBlockchain, database::AnyDatabase, wallet::Wallet};
 use bitcoin_hashes::sha256;
 
 use nostr_rust::{
-    events::{Event, EventPrepare},
-    nostr_client::Client as NostrClient,
-    req::ReqFilter,
-    utils::get_timestamp,
+    events::Event, nostr_client::Client as NostrClient, req::ReqFilter, utils::get_timestamp,
     Identity,
 };
 
 #[cfg(feature = "bitcoincore")]
 use bitcoincore_rpc::Client as RPCClient;
 
+use log::{debug, warn};
 use serde_json::Value;
 
 use rand::{thread_rng, Rng};
+use std::collections::HashSet;
+use std::thread;
+use std::time::Duration;
+
+// Seconds online presence lasts, longer than the most seconds between offer refreshes
+const PRESENCE_TTL: u64 = 900;
+
 pub struct Maker {
     pub identity: Identity,
     pub config: MakerConfig,
     pub nostr_client: NostrClient,
     #[cfg(feature = "bitcoincore")]
     pub rpc_client: RPCClient,
+    /// Passphrase the wallet is unlocked with to sign
+    #[cfg(feature = "bitcoincore")]
+    pub wallet_passphrase: Option<String>,
     #[cfg(feature = "bdk")]
     pub wallet: Wallet<AnyDatabase>,
+    #[cfg(feature = "bdk")]
+    pub blockchain: AnyBlockchain,
+    /// Client for the relays offers are published on
+    pub offer_client: NostrClient,
+    pub relay_pool: RelayPool,
+    pub fill_queue: FillQueue,
+    pub(crate) fill_subscription: Option<String>,
     pub fill_commitment: Option<sha256::Hash>,
+    /// Round of the fill being answered
+    pub round_id: Option<String>,
+    /// Taker of the fill being answered
+    pub taker: Option<String>,
+    /// Counter-offer the taker took for the fill being answered
+    pub counter_offer: Option<CounterOffer>,
+    /// Messages of the round being answered
+    pub transcript: Transcript,
+    pub network: Network,
+    /// Takers that sent sealed messages
+    pub sealed_peers: HashSet<String>,
+    /// Takers whose fills said they read tagged messages
+    pub tagged_peers: HashSet<String>,
+    /// Key the maker advertises for messages to be encrypted to instead of its nostr key
+    pub encryption: MakerEncryption,
+    pub publisher: Publisher,
+    /// Takers whose auths failed to verify
+    pub greylist: TakerGreylist,
+    /// Bond advertised in the offers
+    pub fidelity_bond: Option<FidelityBondProof>,
 }
 
 impl Maker {
+    /// Checks the fill is within the max size, or takes the counter-offer the taker agreed to
+    pub fn check_fill_amount(&mut self, fill_offer: &Fill) -> Option<RejectReason> {
+        match fees::check_fill_amount(&self.config, fill_offer) {
+            Ok(counter) => {
+                self.counter_offer = counter;
+                None
+            }
+            Err(reason) => Some(reason),
+        }
+    }
+
+    /// Config the round is verified against, on the terms of a counter-offer the taker took
+    pub fn round_config(&self) -> MakerConfig {
+        let mut config = self.config.clone();
+        if let Some(counter) = self.counter_offer {
+            config.maxsize = Some(counter.maxsize);
+            config.abs_fee = counter.cjfee;
+            config.rel_fee = 0.0;
+        }
+        config
+    }
+
+    /// Publishes the offers after a random delay, refreshing the maker's online presence
     pub fn publish_offer(&mut self) -> Result<(), Error> {
+        let delay = self.config.offer_jitter.delay(&mut thread_rng());
+        thread::sleep(Duration::from_secs(delay));
+        self.publish_offers()
+    }
+
+    /// Publishes the offers a random time apart
+    fn publish_offers(&mut self) -> Result<(), Error> {
         let mut rng = thread_rng();
 
         let maxsize = match self.config.maxsize {
             Some(maxsize) => maxsize,
             None => self.get_eligible_balance()?,
         };
+        // Takers are not offered more than can enter one round
+        let maxsize = match self.config.max_per_round {
+            Some(max_per_round) => maxsize.min(max_per_round),
+            None => maxsize,
+        };
 
         // TODO: This should be set better
         if maxsize < Amount::from_sat(5000) {
             return Err(Error::NoMatchingUtxo);
         }
+        let tags = [utils::network_tag(self.network)];
+
         // Publish Relative Offer
         let offer = RelOffer {
             offer_id: rng.gen(),
@@ -60,15 +148,28 @@ impl Maker {
             minsize: self.config.minsize,
             maxsize,
             txfee: Amount::ZERO,
+            txfee_rate: self.config.txfee_rate,
+            min_fee_rate: self.config.min_fee_rate,
+            protocol_version: PROTOCOL_VERSION,
+            latency_class: self.config.latency_class,
+            valid_from: self.config.valid_from,
+            max_podle_index: self.config.max_podle_index,
+            encryption_key: self.encryption.advertised(&self.identity),
+            fidelity_bond: self.fidelity_bond.clone(),
         };
 
         let content = serde_json::to_string(&NostrdizerMessage {
             event_type: NostrdizerMessageKind::Offer,
             event: NostrdizerMessages::Offer(Offer::RelOffer(offer)),
+            round_id: None,
         })?;
 
-        self.nostr_client
-            .publish_replaceable_event(&self.identity, 124, &content, &[], 0)?;
+        self.offer_client
+            .publish_replaceable_event(&self.identity, 124, &content, &tags, 0)?;
+
+        // Offers of one maker do not show up in the book at the same moment
+        let stagger = self.config.offer_jitter.stagger(&mut rng);
+        thread::sleep(Duration::from_secs(stagger));
 
         // Publish Absolute Offer
         let offer = AbsOffer {
@@ -77,19 +178,73 @@ impl Maker {
             minsize: self.config.minsize,
             maxsize,
             txfee: Amount::ZERO,
-            // TODO:
+            txfee_rate: self.config.txfee_rate,
+            min_fee_rate: self.config.min_fee_rate,
+            protocol_version: PROTOCOL_VERSION,
+            latency_class: self.config.latency_class,
+            valid_from: self.config.valid_from,
+            max_podle_index: self.config.max_podle_index,
+            encryption_key: self.encryption.advertised(&self.identity),
+            fidelity_bond: self.fidelity_bond.clone(),
         };
         let content = serde_json::to_string(&NostrdizerMessage {
             event_type: NostrdizerMessageKind::Offer,
             event: NostrdizerMessages::Offer(Offer::AbsOffer(offer)),
+            round_id: None,
         })?;
 
-        self.nostr_client
-            .publish_replaceable_event(&self.identity, 123, &content, &[], 0)?;
+        self.offer_client
+            .publish_replaceable_event(&self.identity, 123, &content, &tags, 0)?;
 
+        self.publish_presence(PresenceStatus::Online)
+    }
+
+    /// Publishes whether the maker is online, online presence expires unless refreshed
+    pub fn publish_presence(&mut self, status: PresenceStatus) -> Result<(), Error> {
+        let mut tags = vec![utils::network_tag(self.network)];
+        if status == PresenceStatus::Online {
+            tags.push(utils::expiration_tag(get_timestamp() + PRESENCE_TTL));
+        }
+
+        let content = serde_json::to_string(&NostrdizerMessage {
+            event_type: NostrdizerMessageKind::Presence,
+            event: NostrdizerMessages::Presence(Presence { status }),
+            round_id: None,
+        })?;
+
+        self.offer_client
+            .publish_replaceable_event(&self.identity, 125, &content, &tags, 0)?;
+
+        Ok(())
+    }
+
+    /// Publishes the backup a standby sharing the identity merges when it takes over, replacing the last one
+    pub fn replicate(&mut self, replica: &Backup) -> Result<(), Error> {
+        let event_id = backup::publish_backup(&mut self.nostr_client, &self.identity, replica)?;
+        debug!(
+            "Replicated {} rounds in {event_id}",
+            replica.round_history.rounds.len()
+        );
         Ok(())
     }
 
+    /// Gossips a commitment opened in the maker's round so other makers turn down fills with it
+    pub fn publish_used_commitment(&mut self, commit: Commitment) -> Result<(), Error> {
+        let event_id = blacklist::publish_used(
+            &mut self.offer_client,
+            &self.identity,
+            self.network,
+            vec![commit],
+        )?;
+        debug!("Gossiped used commitment {commit} in {event_id}");
+        Ok(())
+    }
+
+    /// Commitments makers gossiped since a time
+    pub fn fetch_used_commitments(&mut self, since: u64) -> Result<Vec<Commitment>, Error> {
+        blacklist::fetch_used(&mut self.offer_client, self.network, since)
+    }
+
     /// Get active offer
     pub fn get_active_offer(&mut self) -> Result<Option<Offer>, Error> {
         let filter = ReqFilter {
@@ -103,7 +258,7 @@ impl Maker {
             limit: None,
         };
 
-        if let Ok(events) = self.nostr_client.get_events_of(vec![filter]) {
+        if let Ok(events) = self.offer_client.get_events_of(vec![filter]) {
             if !events.is_empty() {
                 let offer_event = events.last().unwrap();
 
@@ -129,70 +284,214 @@ impl Maker {
             limit: None,
         };
 
-        if let Ok(events) = self.nostr_client.get_events_of(vec![filter]) {
+        if let Ok(events) = self.offer_client.get_events_of(vec![filter]) {
             for event in events {
                 let event_id = &event.id;
-                self.nostr_client
+                self.offer_client
                     .delete_event(&self.identity, event_id, 0)?;
             }
         }
         Ok(())
     }
 
+    /// Deletes offers left on the offer relays by identities the maker used before
+    /// Returns the pub keys that no longer have offers published
+    pub fn delete_stale_offers(&mut self, previous: &[Identity]) -> Result<Vec<String>, Error> {
+        let mut cleaned = vec![];
+        for identity in previous {
+            let filter = ReqFilter {
+                ids: None,
+                authors: Some(vec![identity.public_key_str.clone()]),
+                kinds: Some(vec![REL_OFFER, ABS_OFFER]),
+                e: None,
+                p: None,
+                since: None,
+                until: None,
+                limit: None,
+            };
+
+            let events = self.offer_client.get_events_of(vec![filter])?;
+            for event in &events {
+                self.offer_client.delete_event(identity, &event.id, 0)?;
+            }
+            debug!(
+                "Deleted {} stale offers of {}",
+                events.len(),
+                identity.public_key_str
+            );
+            cleaned.push(identity.public_key_str.clone());
+        }
+        Ok(cleaned)
+    }
+
     /// Maker waits for fill offer
+    /// Fills that arrive together or during a round are queued and answered in order
     pub fn get_fill_offer(&mut self) -> Result<(String, Fill), Error> {
-        let filter = ReqFilter {
-            ids: None,
-            authors: None,
-            kinds: Some(vec![FILL]),
-            e: None,
-            p: Some(vec![self.identity.public_key_str.clone()]),
-            since: None,
-            until: None,
-            limit: None,
+        // Subscription is kept between rounds so fills sent during a round are not missed
+        let subscription_id = match &self.fill_subscription {
+            Some(subscription_id) => subscription_id.clone(),
+            None => {
+                let filter = ReqFilter {
+                    ids: None,
+                    authors: None,
+                    kinds: Some(vec![FILL, SEALED]),
+                    e: None,
+                    p: Some(vec![self.identity.public_key_str.clone()]),
+                    since: None,
+                    until: None,
+                    limit: None,
+                };
+                let subscription_id = self.offer_client.subscribe(vec![filter])?;
+                subscription::opened();
+                self.fill_subscription = Some(subscription_id.clone());
+                subscription_id
+            }
         };
 
-        let subcription_id = self.nostr_client.subscribe(vec![filter])?;
-        let mut time = get_timestamp();
+        // Refreshes are spread so makers that started together do not stay in step
+        let mut refresh_at =
+            get_timestamp() + self.config.offer_jitter.refresh_interval(&mut thread_rng());
         loop {
-            let data = self.nostr_client.next_data()?;
-            for (_, message) in data {
+            if let Some(queued) = self.fill_queue.pop(get_timestamp()) {
+                self.fill_commitment = Some(queued.fill.commitment);
+                self.round_id = Some(queued.round_id);
+                self.taker = Some(queued.taker.clone());
+                self.counter_offer = None;
+                self.transcript = Transcript::default();
+                self.transcript.record(&queued.fill_event_id);
+                if queued.sealed {
+                    self.sealed_peers.insert(queued.taker.clone());
+                }
+                if queued.fill.tagged() {
+                    self.tagged_peers.insert(queued.taker.clone());
+                }
+                return Ok((queued.taker, queued.fill));
+            }
+
+            let data = self.offer_client.next_data()?;
+            for (relay, message) in data {
+                self.relay_pool
+                    .record_message(RelayRole::Offer, &relay, get_timestamp());
                 if let Ok(event) = serde_json::from_str::<Value>(&message.to_string()) {
-                    if event[0] == "EOSE" && event[1].as_str() == Some(&subcription_id) {
-                        break;
+                    if event[0] == "EOSE" && event[1].as_str() == Some(&subscription_id) {
+                        continue;
                     }
 
                     if let Ok(event) = serde_json::from_value::<Event>(event[2].clone()) {
+                        let sealed = event.kind == SEALED;
+                        let event = match unseal_event(&self.identity, event) {
+                            Ok(event) => event,
+                            Err(_) => continue,
+                        };
+                        // Plain fills of a greylisted taker are dropped before they are decrypted, sealed ones once opened
+                        if self.greylist.is_greylisted(&event.pub_key, get_timestamp()) {
+                            debug!("Ignoring fill from greylisted {}", event.pub_key);
+                            continue;
+                        }
                         if event.kind == FILL
                             && event.tags[0].contains(&self.identity.public_key_str)
                         {
-                            if let NostrdizerMessages::Fill(fill_offer) = decrypt_message(
-                                &self.identity.secret_key,
-                                &event.pub_key,
-                                &event.content,
-                            )?
-                            .event
+                            if let NostrdizerMessages::Fill(fill_offer) =
+                                self.encryption.decrypt(&self.identity, &event)?.event
                             {
-                                // TODO: Verify commitment in fill offer
-                                self.fill_commitment = Some(fill_offer.commitment);
-                                return Ok((event.pub_key, fill_offer));
+                                self.queue_fill(QueuedFill {
+                                    round_id: round_id(&event.id, &event.pub_key),
+                                    fill_event_id: event.id,
+                                    taker: event.pub_key,
+                                    fill: fill_offer,
+                                    sealed,
+                                    created_at: event.created_at,
+                                })?;
                             }
                         }
                     }
                 }
             }
-            if get_timestamp().gt(&(time + 600)) {
-                self.publish_offer()?;
-                time = get_timestamp();
+            if get_timestamp() > refresh_at {
+                self.publish_offers()?;
+                refresh_at =
+                    get_timestamp() + self.config.offer_jitter.refresh_interval(&mut thread_rng());
             }
         }
     }
 
-    pub fn get_commitment_auth(&mut self) -> Result<AuthCommitment, Error> {
+    /// Sweeps unswept earnings to the payout address if a sweep is due
+    pub fn sweep_if_due(
+        &mut self,
+        payout: &PayoutConfig,
+        history: &mut PayoutHistory,
+    ) -> Result<Option<Txid>, Error> {
+        let now = get_timestamp();
+        if !history.sweep_due(payout.threshold, payout.interval, now) {
+            return Ok(None);
+        }
+
+        let amount = history.unswept()?;
+        let (txid, fee) = self.sweep_payout(payout, amount)?;
+        debug!(
+            "Swept {} to {} in {txid}",
+            display::sats(amount),
+            payout.address
+        );
+        history.record_sweep(txid, amount, fee, now);
+        Ok(Some(txid))
+    }
+
+    pub fn status(&self) -> MakerStatus {
+        MakerStatus {
+            pub_key: self.identity.public_key_str.clone(),
+            queued_fills: self.fill_queue.len(),
+            active_subscriptions: subscription::active_subscriptions(),
+            session_relays: self.relay_pool.delivery(RelayRole::Session),
+            auth_failures: self.greylist.total_failures(),
+            greylisted_takers: self.greylist.greylisted(get_timestamp()),
+            updated_at: get_timestamp(),
+        }
+    }
+
+    /// Closes the subscription to fills kept between rounds
+    pub fn close_fill_subscription(&mut self) -> Result<(), Error> {
+        if let Some(subscription_id) = self.fill_subscription.take() {
+            subscription::closed();
+            self.offer_client.unsubscribe(&subscription_id)?;
+        }
+        Ok(())
+    }
+
+    /// Queues a fill, telling the taker the maker is busy if the queue is full
+    fn queue_fill(&mut self, queued: QueuedFill) -> Result<(), Error> {
+        let taker = queued.taker.clone();
+        let (sealed, tagged) = (queued.sealed, queued.fill.tagged());
+        let round_id = queued.round_id.clone();
+        match self.fill_queue.push(queued) {
+            Ok(()) => Ok(()),
+            Err(QueueError::Full) => {
+                debug!("Fill queue full, rejecting {taker}");
+                utils::send_reject(
+                    &self.identity,
+                    &taker,
+                    RejectReason::Busy,
+                    Some(round_id),
+                    &Envelope {
+                        sealed,
+                        tagged,
+                        ..self.envelope(&taker)
+                    },
+                    &mut self.offer_client,
+                )
+            }
+            Err(err) => {
+                debug!("Fill from {taker} not queued: {err:?}");
+                Ok(())
+            }
+        }
+    }
+
+    pub fn get_commitment_auth(&mut self) -> Result<AuthProof, Error> {
         let filter = ReqFilter {
             ids: None,
             authors: None,
-            kinds: Some(vec![AUTH]),
+            kinds: Some(vec![AUTH, ABORT, SEALED]),
             e: None,
             p: Some(vec![self.identity.public_key_str.clone()]),
             since: None,
@@ -200,44 +499,80 @@ impl Maker {
             limit: None,
         };
 
-        let subscription_id = self.nostr_client.subscribe(vec![filter])?;
+        // Read before subscribing, the subscription borrows the maker's client
+        let taker = self.taker.clone();
+        let mut subscription = SubscriptionGuard::subscribe(&mut self.nostr_client, vec![filter])?;
 
         let started_waiting = get_timestamp();
         loop {
-            let data = self.nostr_client.next_data()?;
-            for (_, message) in data {
+            let data = subscription.next_data()?;
+            for (relay, message) in data {
+                self.relay_pool
+                    .record_message(RelayRole::Session, &relay, get_timestamp());
                 if let Ok(event) = serde_json::from_str::<Value>(&message.to_string()) {
-                    if event[0] == "EOSE" && event[1].as_str() == Some(&subscription_id) {
+                    if event[0] == "EOSE" && event[1].as_str() == Some(subscription.id()) {
                         break;
                     }
                     if let Ok(event) = serde_json::from_value::<Event>(event[2].clone()) {
+                        let event = match unseal_event(&self.identity, event) {
+                            Ok(event) => event,
+                            Err(_) => continue,
+                        };
+                        self.relay_pool
+                            .record_event(RelayRole::Session, &relay, &event);
                         if event.verify().is_ok()
-                            && event.kind == AUTH
+                            && (event.kind == AUTH || event.kind == ABORT)
                             && event.tags[0].contains(&self.identity.public_key_str)
                         {
-                            if let NostrdizerMessages::Auth(auth_commitment) = decrypt_message(
-                                &self.identity.secret_key,
-                                &event.pub_key,
-                                &event.content,
-                            )?
-                            .event
-                            {
-                                self.nostr_client.unsubscribe(&subscription_id)?;
-                                return Ok(auth_commitment);
+                            match self.encryption.decrypt(&self.identity, &event)?.event {
+                                NostrdizerMessages::Auth(auth_proof) => {
+                                    self.transcript.record(&event.id);
+                                    return Ok(auth_proof);
+                                }
+                                // Taker filled spare makers and has enough without this one
+                                NostrdizerMessages::Abort(_)
+                                    if taker.as_ref() == Some(&event.pub_key) =>
+                                {
+                                    return Err(Error::TakerAborted);
+                                }
+                                _ => (),
                             }
                         }
                     }
                 }
             }
-            if started_waiting.gt(&(started_waiting + 300)) {
+            if get_timestamp() - started_waiting > SESSION_TIMEOUT {
                 return Err(Error::TakerFailedToSendTransaction);
             }
         }
     }
 
-    /// Maker verify podle
-    pub fn verify_podle(&self, auth_commitment: AuthCommitment) -> Result<(), Error> {
-        podle::verify_podle(0, auth_commitment, self.fill_commitment.unwrap())
+    /// Maker verifies the taker's proof with its commitment scheme
+    pub fn verify_commitment(&self, auth_proof: &AuthProof) -> Result<(), Error> {
+        // An opening bound to another session was passed on by the maker it was sent to
+        if let Some(binding) = auth_proof.binding() {
+            if self.transcript.event_ids.first() != Some(&binding.fill) {
+                return Err(Error::AuthBindingMismatch);
+            }
+        }
+        auth_proof.verify(
+            self.fill_commitment.unwrap(),
+            self.config.max_podle_index.unwrap_or(MAX_PODLE_INDEX),
+        )
+    }
+
+    /// Counts an auth from taker that failed to verify, returns true if it greylists the taker
+    pub fn record_auth_failure(&mut self, peer_pub_key: &str) -> bool {
+        let greylisted =
+            self.greylist
+                .record_failure(peer_pub_key, get_timestamp(), &self.config.greylist);
+        if greylisted {
+            warn!(
+                "Greylisted {peer_pub_key} for {} seconds after {} failed auths",
+                self.config.greylist.cooldown, self.config.greylist.max_failures
+            );
+        }
+        greylisted
     }
 
     /// Send maker input
@@ -249,35 +584,112 @@ impl Maker {
         let message = NostrdizerMessage {
             event_type: NostrdizerMessageKind::MakerPsbt,
             event: NostrdizerMessages::MakerInputs(maker_input),
+            round_id: self.round_id.clone(),
         };
 
-        let encypted_content =
-            utils::encrypt_message(&self.identity.secret_key, peer_pub_key, &message)?;
-
-        let event = EventPrepare {
-            pub_key: self.identity.public_key_str.clone(),
-            created_at: get_timestamp(),
-            kind: IOAUTH,
-            tags: vec![vec!["p".to_string(), peer_pub_key.to_string()]],
-            content: encypted_content,
-        }
-        .to_event(&self.identity, 0);
-
-        self.nostr_client.publish_event(&event)?;
-
-        /*
-        self.nostr_client.publish_ephemeral_event(
+        let event_id = utils::send_message(
             &self.identity,
+            peer_pub_key,
             IOAUTH,
-            &encypted_content,
-            &[vec!["p".to_string(), peer_pub_key.to_string()]],
-            0,
+            &message,
+            &self.envelope(peer_pub_key),
+            &mut self.nostr_client,
         )?;
-        */
+        self.transcript.record(&event_id);
+        Ok(())
+    }
+
+    /// Tells taker which relays the rest of the session is on, leaving out relays found dropping session events
+    pub fn send_fill_ack(&mut self, peer_pub_key: &str) -> Result<(), Error> {
+        let message = NostrdizerMessage {
+            event_type: NostrdizerMessageKind::FillAck,
+            event: NostrdizerMessages::FillAck(FillAck {
+                session_relays: self.relay_pool.preferred(RelayRole::Session),
+                encryption_key: self.encryption.advertised(&self.identity),
+            }),
+            round_id: self.round_id.clone(),
+        };
 
+        let event_id = utils::send_message(
+            &self.identity,
+            peer_pub_key,
+            FILL_ACK,
+            &message,
+            &self.envelope(peer_pub_key),
+            &mut self.offer_client,
+        )?;
+        self.transcript.record(&event_id);
         Ok(())
     }
 
+    /// Tell taker why its fill will not be taken, on the relays the fill was sent on
+    pub fn send_fill_reject(
+        &mut self,
+        peer_pub_key: &str,
+        reason: RejectReason,
+    ) -> Result<(), Error> {
+        utils::send_reject(
+            &self.identity,
+            peer_pub_key,
+            reason,
+            self.round_id.clone(),
+            &self.envelope(peer_pub_key),
+            &mut self.offer_client,
+        )
+    }
+
+    /// Tell taker why the CJ will not be signed
+    pub fn send_reject(&mut self, peer_pub_key: &str, reason: RejectReason) -> Result<(), Error> {
+        utils::send_reject(
+            &self.identity,
+            peer_pub_key,
+            reason,
+            self.round_id.clone(),
+            &self.envelope(peer_pub_key),
+            &mut self.nostr_client,
+        )
+    }
+
+    /// Whether messages to peer should be sealed
+    pub fn sealed(&self, peer_pub_key: &str) -> bool {
+        self.sealed_peers.contains(peer_pub_key)
+    }
+
+    /// How messages to peer are encrypted and published
+    pub fn envelope(&self, peer_pub_key: &str) -> Envelope {
+        Envelope {
+            secret_key: self.encryption.secret_key(&self.identity, peer_pub_key),
+            pub_key: peer_pub_key.to_string(),
+            sealed: self.sealed(peer_pub_key),
+            tagged: self.tagged_peers.contains(peer_pub_key),
+        }
+    }
+
+    /// Publishes a message the round can not go on without, until the publish quorum of session relays accepts it
+    /// Returns the id of the signed event
+    pub(crate) fn publish_critical(
+        &mut self,
+        peer_pub_key: &str,
+        kind: u16,
+        message: &NostrdizerMessage,
+    ) -> Result<String, Error> {
+        let (event_id, event) = utils::message_event(
+            &self.identity,
+            peer_pub_key,
+            kind,
+            message,
+            &self.envelope(peer_pub_key),
+        )?;
+        let relays = self.relay_pool.preferred(RelayRole::Session);
+        self.publisher.publish(
+            &mut self.nostr_client,
+            &relays,
+            &self.config.publish_quorum,
+            &event,
+        )?;
+        Ok(event_id)
+    }
+
     /// Send pubkey message
     /// This is a dumby message for now
     pub fn send_pubkey(&mut self, peer_pub_key: &str) -> Result<(), Error> {
@@ -286,28 +698,149 @@ impl Maker {
             event: NostrdizerMessages::PubKey(Pubkey {
                 mencpubkey: "".to_string(),
             }),
+            round_id: self.round_id.clone(),
         };
 
-        let encrypted_content =
-            utils::encrypt_message(&self.identity.secret_key, peer_pub_key, &message)?;
-
-        self.nostr_client.publish_ephemeral_event(
+        utils::send_message(
             &self.identity,
-            126,
-            &encrypted_content,
-            &[vec!["p".to_string(), peer_pub_key.to_string()]],
-            0,
+            peer_pub_key,
+            PUBKEY,
+            &message,
+            &self.envelope(peer_pub_key),
+            &mut self.nostr_client,
         )?;
-
         Ok(())
     }
 
+    /// Mining fee the maker offered to contribute for its inputs and the fill's CJ outputs
+    pub(crate) fn txfee(&self, fill_offer: &Fill, maker_input: &IoAuth) -> Amount {
+        let txfee = fees::maker_txfee(
+            Amount::ZERO,
+            self.config.txfee_rate,
+            maker_input.utxos.len(),
+            fill_offer.outputs().len(),
+        );
+        // Inputs without change are over what is owed by up to dust, or the change the maker donates,
+        // which goes to the mining fee
+        match maker_input.no_change {
+            true => txfee + self.donated_change_max(),
+            false => txfee,
+        }
+    }
+
+    /// Most change a maker without a change output gives to the mining fee
+    fn donated_change_max(&self) -> Amount {
+        match self.config.donate_change_below {
+            Some(below) if below.to_sat() > DUST => below,
+            _ => Amount::from_sat(DUST),
+        }
+    }
+
+    /// What the maker owes for the fill with `input_count` inputs
+    /// The maker owes its CJ outputs and its part of the mining fee, less the CJ fee
+    fn owed(&self, fill_offer: &Fill, input_count: usize) -> Option<Amount> {
+        let outputs = fill_offer.outputs();
+        let cj_value = outputs
+            .iter()
+            .copied()
+            .try_fold(Amount::ZERO, fees::checked_add)
+            .ok()?;
+        let cjfee = fees::rel_fee_amount(fill_offer.amount, fees::to_ppm(self.config.rel_fee))
+            .max(self.config.abs_fee);
+        let txfee = fees::maker_txfee(
+            Amount::ZERO,
+            self.config.txfee_rate,
+            input_count,
+            outputs.len(),
+        );
+        fees::checked_sub(fees::checked_add(cj_value, txfee).ok()?, cjfee).ok()
+    }
+
+    /// Whether the selected utxos, by index into `values`, leave change the maker donates to the mining fee
+    pub(crate) fn donates_change(
+        &self,
+        fill_offer: &Fill,
+        values: &[Amount],
+        selected: &[usize],
+    ) -> bool {
+        match (
+            self.config.donate_change_below,
+            self.owed(fill_offer, selected.len()),
+        ) {
+            (Some(below), Some(owed)) => {
+                coin_selection::donates_change(values, selected, owed, below)
+            }
+            _ => false,
+        }
+    }
+
+    /// Utxos, by index into `values`, worth what the maker owes for the fill to within dust
+    pub(crate) fn exact_inputs(&self, fill_offer: &Fill, values: &[Amount]) -> Option<Vec<usize>> {
+        // The mining fee depends on the number of inputs, so selection is redone until they agree
+        let mut input_count = 1;
+        for _ in 0..values.len() {
+            let owed = self.owed(fill_offer, input_count)?;
+            let selected = coin_selection::select_exact(
+                values,
+                owed,
+                fill_offer.input_limits.max_per_maker(),
+            )?;
+            if selected.len() == input_count {
+                return Some(selected);
+            }
+            input_count = selected.len();
+        }
+        None
+    }
+
+    /// Checks the number of participants the taker claims matches the CJ outputs
+    /// Takers on earlier versions do not send a claim
+    pub fn verify_claimed_participants(
+        &self,
+        transaction: &Transaction,
+        send_amount: Amount,
+    ) -> Option<RejectReason> {
+        let counted = fees::count_participants(&transaction.psbt, send_amount);
+        match transaction.participants {
+            Some(claimed) if claimed != counted => {
+                Some(RejectReason::ParticipantMismatch { claimed, counted })
+            }
+            _ => None,
+        }
+    }
+
+    /// Rejects a CJ the maker would otherwise sign if it spends unconfirmed inputs of others
+    pub(crate) fn check_inputs_confirmed(
+        &self,
+        mut tx_info: VerifyCJInfo,
+        psbt: &PartiallySignedTransaction,
+        maker_input: &IoAuth,
+    ) -> Result<VerifyCJInfo, Error> {
+        if tx_info.reject_reason.is_some() {
+            return Ok(tx_info);
+        }
+        // Own inputs were already picked following the maker's policy
+        let counterparty_inputs: Vec<OutPoint> = psbt
+            .unsigned_tx
+            .input
+            .iter()
+            .map(|input| input.previous_output)
+            .filter(|outpoint| !maker_input.utxos.iter().any(|(own, _)| own == outpoint))
+            .collect();
+        let outpoints = self.unconfirmed_outpoints(&counterparty_inputs)?;
+        if !outpoints.is_empty() {
+            tx_info.verifyed = false;
+            tx_info.reject_reason = Some(RejectReason::UnconfirmedInputs { outpoints });
+        }
+        Ok(tx_info)
+    }
+
     /// Maker waits for unsigned CJ transaction
-    pub fn get_unsigned_cj_transaction(&mut self) -> Result<PartiallySignedTransaction, Error> {
+    pub fn get_unsigned_cj_transaction(&mut self) -> Result<Transaction, Error> {
         let filter = ReqFilter {
             ids: None,
             authors: None,
-            kinds: Some(vec![TRANSACTION]),
+            kinds: Some(vec![TRANSACTION, ABORT, SEALED]),
             e: None,
             p: Some(vec![self.identity.public_key_str.clone()]),
             since: None,
@@ -315,39 +848,159 @@ impl Maker {
             limit: None,
         };
 
-        let subscription_id = self.nostr_client.subscribe(vec![filter])?;
+        // Only the taker of the fill can abort it
+        let taker = self.taker.clone();
+        let mut subscription = SubscriptionGuard::subscribe(&mut self.nostr_client, vec![filter])?;
 
         let started_waiting = get_timestamp();
         loop {
-            let data = self.nostr_client.next_data()?;
-            for (_, message) in data {
+            let data = subscription.next_data()?;
+            for (relay, message) in data {
+                self.relay_pool
+                    .record_message(RelayRole::Session, &relay, get_timestamp());
                 if let Ok(event) = serde_json::from_str::<Value>(&message.to_string()) {
-                    if event[0] == "EOSE" && event[1].as_str() == Some(&subscription_id) {
+                    if event[0] == "EOSE" && event[1].as_str() == Some(subscription.id()) {
                         break;
                     }
                     if let Ok(event) = serde_json::from_value::<Event>(event[2].clone()) {
+                        let event = match unseal_event(&self.identity, event) {
+                            Ok(event) => event,
+                            Err(_) => continue,
+                        };
+                        self.relay_pool
+                            .record_event(RelayRole::Session, &relay, &event);
                         if event.verify().is_ok()
-                            && event.kind == TRANSACTION
+                            && (event.kind == TRANSACTION || event.kind == ABORT)
                             && event.tags[0].contains(&self.identity.public_key_str)
                         {
-                            if let NostrdizerMessages::UnsignedCJ(unsigned_tx_hex) =
-                                decrypt_message(
-                                    &self.identity.secret_key,
-                                    &event.pub_key,
-                                    &event.content,
-                                )?
-                                .event
-                            {
-                                self.nostr_client.unsubscribe(&subscription_id)?;
-                                return Ok(unsigned_tx_hex.psbt);
+                            match self.encryption.decrypt(&self.identity, &event)?.event {
+                                NostrdizerMessages::UnsignedCJ(unsigned_tx) => {
+                                    self.transcript.record(&event.id);
+                                    return Ok(unsigned_tx);
+                                }
+                                // Taker dropped the maker's inputs and used a spare maker
+                                NostrdizerMessages::Abort(abort)
+                                    if taker.as_ref() == Some(&event.pub_key) =>
+                                {
+                                    if let Some(reason) = abort.reason {
+                                        warn!(
+                                            "Taker turned down inputs: {}",
+                                            display::reject_reason(&reason)
+                                        );
+                                    }
+                                    return Err(Error::TakerAborted);
+                                }
+                                _ => (),
                             }
                         }
                     }
                 }
             }
-            if started_waiting.gt(&(started_waiting + 300)) {
+            if get_timestamp() - started_waiting > SESSION_TIMEOUT {
                 return Err(Error::TakerFailedToSendTransaction);
             }
         }
     }
+
+    /// Waits for the taker to confirm the broadcast CJ and answers with the maker's transcript hash
+    /// Takers on earlier versions do not confirm, their rounds are recorded as unconfirmed, as are rounds the taker
+    /// aborted before broadcasting
+    /// A CJ the taker rebuilt without makers that did not sign is returned as `Error::RoundRebuilt` to be signed again
+    pub fn confirm_round(&mut self, peer_pub_key: &str, txid: &Txid) -> Result<RoundRecord, Error> {
+        let transcript = self.transcript.hash(txid);
+        let filter = ReqFilter {
+            ids: None,
+            authors: None,
+            kinds: Some(vec![CONFIRM, ABORT, TRANSACTION, SEALED]),
+            e: None,
+            p: Some(vec![self.identity.public_key_str.clone()]),
+            since: None,
+            until: None,
+            limit: None,
+        };
+
+        let mut confirm = None;
+        let mut subscription = SubscriptionGuard::subscribe(&mut self.nostr_client, vec![filter])?;
+        let started_waiting = get_timestamp();
+        // Taker waits for the other makers to sign before broadcasting
+        'waiting: while get_timestamp() - started_waiting < 120 {
+            for (relay, message) in subscription.next_data()? {
+                self.relay_pool
+                    .record_message(RelayRole::Session, &relay, get_timestamp());
+                if let Ok(event) = serde_json::from_str::<Value>(&message.to_string()) {
+                    if let Ok(event) = serde_json::from_value::<Event>(event[2].clone()) {
+                        let event = match unseal_event(&self.identity, event) {
+                            Ok(event) => event,
+                            Err(_) => continue,
+                        };
+                        self.relay_pool
+                            .record_event(RelayRole::Session, &relay, &event);
+                        if event.verify().is_ok()
+                            && (event.kind == CONFIRM
+                                || event.kind == ABORT
+                                || event.kind == TRANSACTION)
+                            && event.pub_key == peer_pub_key
+                            // The CJ the maker signed is sent again by relays
+                            && !self.transcript.contains(&event.id)
+                        {
+                            match self.encryption.decrypt(&self.identity, &event)?.event {
+                                NostrdizerMessages::Confirm(taker_confirm) => {
+                                    confirm = Some(taker_confirm);
+                                    break 'waiting;
+                                }
+                                // Round was cancelled, the CJ is not broadcast
+                                NostrdizerMessages::Abort(_) => {
+                                    debug!("Taker aborted the round before broadcasting {txid}");
+                                    break 'waiting;
+                                }
+                                // Not all makers signed, the taker sent a CJ without them to sign again
+                                NostrdizerMessages::UnsignedCJ(unsigned_tx) => {
+                                    self.transcript.record(&event.id);
+                                    return Err(Error::RoundRebuilt(Box::new(unsigned_tx)));
+                                }
+                                _ => (),
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        drop(subscription);
+
+        // Only the CJ the maker signed is broadcast
+        if let Some(tx) = confirm.as_ref().and_then(|confirm| confirm.tx.as_ref()) {
+            if self.config.will_broadcast && &tx.txid() == txid {
+                match self.broadcast_tx(tx) {
+                    Ok(_) => debug!("Broadcast CJ {txid}"),
+                    Err(err) => warn!("Could not broadcast CJ {txid}: {err}"),
+                }
+            }
+        }
+
+        let completion = Completion::check(transcript, txid, confirm.as_ref());
+        // Taker is answered on a mismatch too so both sides record it
+        if confirm.is_some() {
+            utils::send_confirm(
+                &self.identity,
+                peer_pub_key,
+                Confirm {
+                    txid: *txid,
+                    transcript,
+                    tx: None,
+                },
+                self.round_id.clone(),
+                &self.envelope(peer_pub_key),
+                &mut self.nostr_client,
+            )?;
+        }
+
+        Ok(RoundRecord {
+            round_id: self.round_id.clone().unwrap_or_default(),
+            peer: peer_pub_key.to_string(),
+            txid: *txid,
+            transcript,
+            completion,
+            created_at: get_timestamp(),
+        })
+    }
 }
@@ -0,0 +1,136 @@
+use crate::{errors::Error, fidelity_bond::weighted_order, types::NostrdizerOffer};
+
+use bdk::bitcoin::Amount;
+use rand::{seq::SliceRandom, Rng};
+use serde::{Deserialize, Serialize};
+
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+
+/// How the taker orders the makers of matching offers it fills
+/// Makers with fidelity bonds are filled before the rest whatever the selection
+/// ```
+/// use nostrdizer::maker_selection::MakerSelection;
+/// use std::str::FromStr;
+///
+/// assert_eq!(MakerSelection::default(), MakerSelection::Cheapest);
+/// assert_eq!(
+///     MakerSelection::from_str("fee-weighted").unwrap(),
+///     MakerSelection::FeeWeighted
+/// );
+/// assert!(MakerSelection::from_str("lowest").is_err());
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum MakerSelection {
+    /// Lowest fee first, a maker undercutting the others with many keys is in every round
+    #[default]
+    Cheapest,
+    /// Random order, any fee up to the taker's max fee is as likely to be filled
+    Random,
+    /// Random order, cheaper makers first more often
+    /// Weighted by how far the fee is under the taker's max fee, so undercutting can at most double a maker's weight
+    /// against one asking half the max fee
+    FeeWeighted,
+}
+
+impl MakerSelection {
+    /// Orders offers for filling, `max_fee` is the most CJ fee the taker pays a maker
+    /// Offers of a maker stay together, in the order they were in
+    pub fn order<R: Rng>(&self, offers: &mut [NostrdizerOffer], max_fee: Amount, rng: &mut R) {
+        // Sorts are stable so offers seen on more trusted relays stay first when keys are equal
+        match self {
+            MakerSelection::Cheapest => offers.sort_by_key(|o| o.cjfee),
+            MakerSelection::Random => {
+                let mut makers = first_offers(offers);
+                makers.shuffle(rng);
+                let position = positions(makers.into_iter().map(|o| o.maker.clone()));
+                offers.sort_by_key(|o| position[&o.maker]);
+            }
+            MakerSelection::FeeWeighted => {
+                // A maker with several offers is weighted once, by its first
+                let weights: Vec<(String, f64)> = first_offers(offers)
+                    .into_iter()
+                    .map(|o| {
+                        let under_max = max_fee.to_sat().saturating_sub(o.cjfee.to_sat());
+                        // Makers asking the max fee can still be picked
+                        (o.maker.clone(), (under_max + 1) as f64)
+                    })
+                    .collect();
+                let position = positions(weighted_order(&weights, rng).into_iter());
+                offers.sort_by_key(|o| position[&o.maker]);
+            }
+        }
+    }
+}
+
+/// First offer of each maker
+fn first_offers(offers: &[NostrdizerOffer]) -> Vec<&NostrdizerOffer> {
+    let mut seen = HashSet::new();
+    offers.iter().filter(|o| seen.insert(&o.maker)).collect()
+}
+
+/// Index of the first time each maker comes in the order
+fn positions(order: impl Iterator<Item = String>) -> HashMap<String, usize> {
+    let mut positions = HashMap::new();
+    for (i, maker) in order.enumerate() {
+        positions.entry(maker).or_insert(i);
+    }
+    positions
+}
+
+impl FromStr for MakerSelection {
+    type Err = Error;
+
+    fn from_str(selection: &str) -> Result<Self, Self::Err> {
+        match selection {
+            "cheapest" => Ok(Self::Cheapest),
+            "random" => Ok(Self::Random),
+            "fee-weighted" => Ok(Self::FeeWeighted),
+            _ => Err(Error::UnknownMakerSelection(selection.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::offer;
+    use rand::thread_rng;
+
+    fn first_picks(selection: MakerSelection, offers: &[NostrdizerOffer]) -> HashMap<String, u32> {
+        let mut picks = HashMap::new();
+        for _ in 0..2_000 {
+            let mut ordered = offers.to_vec();
+            selection.order(&mut ordered, Amount::from_sat(1_000), &mut thread_rng());
+            *picks.entry(ordered[0].maker.clone()).or_insert(0) += 1;
+        }
+        picks
+    }
+
+    #[test]
+    fn test_undercutting_does_not_take_every_round() {
+        let offers = vec![offer("fair", 500), offer("sybil", 0), offer("fair2", 500)];
+
+        let mut cheapest = offers.clone();
+        MakerSelection::Cheapest.order(&mut cheapest, Amount::from_sat(1_000), &mut thread_rng());
+        assert_eq!(cheapest[0].maker, "sybil");
+
+        // The sybil has about twice the weight of each fair maker, first in about half the rounds
+        let picks = first_picks(MakerSelection::FeeWeighted, &offers);
+        assert!(picks["sybil"] > picks["fair"]);
+        assert!(picks["sybil"] < 1_500);
+        assert!(picks["fair"] > 200 && picks["fair2"] > 200);
+
+        let picks = first_picks(MakerSelection::Random, &offers);
+        assert!(picks.values().all(|firsts| *firsts > 400));
+    }
+
+    #[test]
+    fn test_offers_of_a_maker_stay_together() {
+        let mut offers = vec![offer("a", 10), offer("b", 10), offer("a", 20)];
+        MakerSelection::Random.order(&mut offers, Amount::from_sat(1_000), &mut thread_rng());
+        let a: Vec<usize> = (0..3).filter(|i| offers[*i].maker == "a").collect();
+        assert_eq!(a[1] - a[0], 1);
+    }
+}
@@ -0,0 +1,1067 @@
+use crate::{
+    errors::Error,
+    subscription::{RelayClient, SubscriptionGuard},
+    types::{
+        Amount, NostrdizerMessage, NostrdizerMessages, Offer, PresenceStatus, ABS_OFFER, PRESENCE,
+        REL_OFFER,
+    },
+    utils::{event_expiration, event_network},
+};
+
+use bdk::bitcoin::Network;
+
+use log::{debug, warn};
+use nostr_rust::{events::Event, req::ReqFilter, utils::get_timestamp};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+// Seconds to wait for relays to send stored offers
+const FETCH_TIMEOUT: u64 = 10;
+// Seconds a fetch goes back before the last one, for events relays got late or signed with a skewed clock
+const SYNC_OVERLAP: u64 = 60;
+// NIP-09 event deletion, makers delete their offers when they stop
+const DELETION: u16 = 5;
+// Offer events asked of each relay at a time
+const PAGE_SIZE: u64 = 500;
+// Bytes of the largest relay message read, offers and presence are far smaller
+const MAX_MESSAGE_SIZE: usize = 64 * 1024;
+// Bytes of the largest event content parsed
+const MAX_CONTENT_SIZE: usize = 16 * 1024;
+// Offer events added to the book in one fetch, older ones are not read
+const MAX_OFFERS_PER_FETCH: usize = 5_000;
+
+/// Offer events a relay sent in a page
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct RelayPage {
+    events: u64,
+    /// Created at of the oldest of them
+    oldest: u64,
+}
+
+/// Until of the next page, none once no relay sent a full page
+/// Relays that sent a full page may have older offers, the page goes back as far as the one that sent the newest
+/// so no relay skips any, offers seen again are upserted as they were
+fn next_cursor(pages: &HashMap<String, RelayPage>, until: Option<u64>) -> Option<u64> {
+    let cursor = pages
+        .values()
+        .filter(|page| page.events >= PAGE_SIZE)
+        .map(|page| page.oldest)
+        .max()?;
+    match until {
+        // A whole page in one second would be asked for again and again and relays can not be paged within
+        // a second, the rest of that second is skipped
+        Some(until) if cursor >= until => {
+            warn!("Relays sent a full page of offers created at {until}, the rest of them are skipped");
+            until.checked_sub(1)
+        }
+        _ => Some(cursor),
+    }
+}
+
+/// How much offers seen on each relay are trusted
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RelayTrust {
+    /// Trust weight per relay url
+    pub weights: HashMap<String, f64>,
+    /// Weight of relays not in `weights`
+    pub default_weight: f64,
+    /// Offers with a total relay weight below this are low trust
+    pub low_trust_threshold: f64,
+    /// Seconds an offer stays in the book after it was last seen
+    pub ttl: u64,
+    /// Seconds a low trust offer stays in the book after it was last seen
+    pub low_trust_ttl: u64,
+}
+
+impl Default for RelayTrust {
+    fn default() -> Self {
+        Self {
+            weights: HashMap::new(),
+            default_weight: 1.0,
+            low_trust_threshold: 1.0,
+            ttl: 3600,
+            low_trust_ttl: 300,
+        }
+    }
+}
+
+impl RelayTrust {
+    pub fn weight(&self, relay: &str) -> f64 {
+        *self.weights.get(relay).unwrap_or(&self.default_weight)
+    }
+}
+
+/// When makers are suspected to be sybils run by one operator
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SybilHeuristic {
+    /// Seconds apart offers are published within to count as published together
+    pub window: u64,
+    /// Makers that share at least this many signals are in one cluster
+    pub threshold: u32,
+}
+
+impl Default for SybilHeuristic {
+    fn default() -> Self {
+        // Each signal alone is common among honest makers run with default configs
+        Self {
+            window: 10,
+            threshold: 3,
+        }
+    }
+}
+
+/// An offer and the relays it was seen on
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OfferEntry {
+    pub maker: String,
+    pub offer: Offer,
+    pub relays: HashSet<String>,
+    /// Creation time of the offer event
+    pub published_at: u64,
+    pub last_seen: u64,
+    /// Id of the offer event, the maker deletes the offer by it
+    #[serde(default)]
+    pub event_id: Option<String>,
+}
+
+/// Latest presence a maker published
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MakerPresence {
+    pub status: PresenceStatus,
+    pub expires_at: Option<u64>,
+    pub created_at: u64,
+}
+
+/// Maker offers collected from relays
+/// The book is kept up to date between fetches: once synced, relays are only asked for offers published,
+/// replaced or deleted since, so offers stay in it while their makers refresh them
+#[derive(Debug, Clone)]
+pub struct OrderBook {
+    pub trust: RelayTrust,
+    /// Only offers tagged for this network are added
+    pub network: Network,
+    /// Drop offers of makers that have not published online presence
+    pub online_only: bool,
+    pub sybil: SybilHeuristic,
+    /// Keep only the most corroborated maker of each suspected sybil cluster
+    pub one_per_cluster: bool,
+    relay_count: usize,
+    /// Offers keyed by maker and offer kind, as offers are replaceable events
+    entries: HashMap<(String, u16), OfferEntry>,
+    presence: HashMap<String, MakerPresence>,
+    /// Time of the last fetch a relay finished sending offers for
+    synced_at: Option<u64>,
+}
+
+/// Offers and presence of a book kept between runs, so the next fetch only asks for what changed
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct BookCache {
+    network: Network,
+    synced_at: Option<u64>,
+    offers: Vec<(u16, OfferEntry)>,
+    presence: Vec<(String, MakerPresence)>,
+}
+
+impl OrderBook {
+    pub fn new(relay_count: usize, network: Network) -> Self {
+        Self {
+            trust: RelayTrust::default(),
+            network,
+            online_only: false,
+            sybil: SybilHeuristic::default(),
+            one_per_cluster: true,
+            relay_count,
+            entries: HashMap::new(),
+            presence: HashMap::new(),
+            synced_at: None,
+        }
+    }
+
+    /// Adds an offer seen on a relay
+    pub fn insert(
+        &mut self,
+        relay: &str,
+        maker: String,
+        kind: u16,
+        offer: Offer,
+        published_at: u64,
+        seen_at: u64,
+    ) {
+        let seen = OfferEntry {
+            maker,
+            offer,
+            relays: HashSet::new(),
+            published_at,
+            last_seen: seen_at,
+            event_id: None,
+        };
+        self.upsert(relay, kind, seen);
+    }
+
+    /// Adds the offer of an event seen on a relay, replacing an older offer of the maker of the same kind
+    /// `seen` is the offer as the relay sent it, the relay is added to it here
+    fn upsert(&mut self, relay: &str, kind: u16, seen: OfferEntry) {
+        let entry = self
+            .entries
+            .entry((seen.maker.clone(), kind))
+            .or_insert_with(|| seen.clone());
+
+        if seen.published_at >= entry.published_at {
+            entry.offer = seen.offer;
+            entry.published_at = seen.published_at;
+            entry.event_id = seen.event_id;
+        }
+        entry.relays.insert(relay.to_string());
+        entry.last_seen = entry.last_seen.max(seen.last_seen);
+    }
+
+    /// Removes the offer of an event its maker deleted, offers the maker published since are kept
+    /// Returns whether an offer was removed
+    pub fn delete(&mut self, maker: &str, event_id: &str) -> bool {
+        let deleted: Vec<(String, u16)> = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| {
+                entry.maker == maker && entry.event_id.as_deref() == Some(event_id)
+            })
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in &deleted {
+            self.entries.remove(key);
+        }
+        !deleted.is_empty()
+    }
+
+    /// Adds a maker's presence, keeping the latest
+    pub fn insert_presence(&mut self, maker: String, presence: MakerPresence) {
+        match self.presence.get(&maker) {
+            Some(current) if current.created_at > presence.created_at => (),
+            _ => {
+                self.presence.insert(maker, presence);
+            }
+        }
+    }
+
+    /// Whether maker is online at `now`, `None` if it has not published presence
+    /// ```
+    /// use nostrdizer::{
+    ///     order_book::{MakerPresence, OrderBook},
+    ///     types::{Network, PresenceStatus},
+    /// };
+    ///
+    /// let mut order_book = OrderBook::new(1, Network::Regtest);
+    /// order_book.insert_presence(
+    ///     "maker".to_string(),
+    ///     MakerPresence {
+    ///         status: PresenceStatus::Online,
+    ///         expires_at: Some(100),
+    ///         created_at: 0,
+    ///     },
+    /// );
+    ///
+    /// assert_eq!(order_book.is_online("maker", 50), Some(true));
+    /// // Presence that is not refreshed expires
+    /// assert_eq!(order_book.is_online("maker", 150), Some(false));
+    /// assert_eq!(order_book.is_online("other", 50), None);
+    /// ```
+    pub fn is_online(&self, maker: &str, now: u64) -> Option<bool> {
+        self.presence.get(maker).map(|presence| {
+            presence.status == PresenceStatus::Online
+                && presence
+                    .expires_at
+                    .map_or(true, |expires_at| now < expires_at)
+        })
+    }
+
+    /// Total trust weight of the relays an offer was seen on
+    pub fn score(&self, entry: &OfferEntry) -> f64 {
+        entry.relays.iter().map(|r| self.trust.weight(r)).sum()
+    }
+
+    pub fn is_low_trust(&self, entry: &OfferEntry) -> bool {
+        self.score(entry) < self.trust.low_trust_threshold
+    }
+
+    /// Number of signals that the makers of two offers are one operator:
+    /// identical offers, published together and seen on the same subset of relays
+    pub fn sybil_score(&self, a: &OfferEntry, b: &OfferEntry) -> u32 {
+        if a.maker == b.maker {
+            return 0;
+        }
+        let signals = [
+            fingerprint(&a.offer) == fingerprint(&b.offer),
+            a.published_at.abs_diff(b.published_at) <= self.sybil.window,
+            // Every maker is on every relay when the taker only uses a few
+            a.relays == b.relays && a.relays.len() < self.relay_count,
+        ];
+        signals.into_iter().filter(|signal| *signal).count() as u32
+    }
+
+    /// Groups of makers suspected to be run by one operator, each sorted
+    /// ```
+    /// use nostrdizer::{
+    ///     order_book::OrderBook,
+    ///     types::{AbsOffer, Amount, Network, Offer, ABS_OFFER},
+    /// };
+    ///
+    /// let offer = |cjfee| {
+    ///     Offer::AbsOffer(AbsOffer {
+    ///         offer_id: 0,
+    ///         minsize: Amount::from_sat(5000),
+    ///         maxsize: Amount::from_sat(100_000),
+    ///         txfee: Amount::ZERO,
+    ///         txfee_rate: None,
+    ///         cjfee: Amount::from_sat(cjfee),
+    ///         min_fee_rate: None,
+    ///         protocol_version: 0,
+    ///         latency_class: None,
+    ///         valid_from: None,
+    ///         max_podle_index: None,
+    ///         encryption_key: None,
+    ///         fidelity_bond: None,
+    ///     })
+    /// };
+    /// let mut order_book = OrderBook::new(2, Network::Regtest);
+    /// // Identical offers published seconds apart on one relay
+    /// order_book.insert("wss://one", "a".to_string(), ABS_OFFER, offer(100), 0, 0);
+    /// order_book.insert("wss://one", "b".to_string(), ABS_OFFER, offer(100), 3, 0);
+    /// order_book.insert("wss://one", "c".to_string(), ABS_OFFER, offer(200), 3, 0);
+    ///
+    /// assert_eq!(order_book.clusters(), vec![vec!["a".to_string(), "b".to_string()]]);
+    /// assert_eq!(order_book.offers().len(), 2);
+    /// ```
+    pub fn clusters(&self) -> Vec<Vec<String>> {
+        let entries: Vec<&OfferEntry> = self.entries.values().collect();
+        let mut cluster_of: HashMap<&str, usize> = HashMap::new();
+        let mut clusters: Vec<HashSet<&str>> = Vec::new();
+
+        for (i, a) in entries.iter().enumerate() {
+            for b in &entries[i + 1..] {
+                if self.sybil_score(a, b) < self.sybil.threshold {
+                    continue;
+                }
+                match (
+                    cluster_of.get(a.maker.as_str()),
+                    cluster_of.get(b.maker.as_str()),
+                ) {
+                    (Some(&x), Some(&y)) if x != y => {
+                        // Merge the two clusters
+                        let merged: Vec<&str> = clusters[y].drain().collect();
+                        for maker in merged {
+                            cluster_of.insert(maker, x);
+                            clusters[x].insert(maker);
+                        }
+                    }
+                    (Some(_), Some(_)) => (),
+                    (Some(&x), None) | (None, Some(&x)) => {
+                        for maker in [a.maker.as_str(), b.maker.as_str()] {
+                            cluster_of.insert(maker, x);
+                            clusters[x].insert(maker);
+                        }
+                    }
+                    (None, None) => {
+                        cluster_of.insert(&a.maker, clusters.len());
+                        cluster_of.insert(&b.maker, clusters.len());
+                        clusters.push(HashSet::from([a.maker.as_str(), b.maker.as_str()]));
+                    }
+                }
+            }
+        }
+
+        let mut clusters: Vec<Vec<String>> = clusters
+            .into_iter()
+            .filter(|cluster| !cluster.is_empty())
+            .map(|cluster| {
+                let mut makers: Vec<String> = cluster.into_iter().map(String::from).collect();
+                makers.sort();
+                makers
+            })
+            .collect();
+        clusters.sort();
+        clusters
+    }
+
+    /// Removes offers past their ttl, and offers of makers that are offline
+    /// Makers that have not published presence are only removed when `online_only` is set
+    pub fn prune(&mut self, now: u64) {
+        let expired: Vec<(String, u16)> = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| {
+                let ttl = match self.is_low_trust(entry) {
+                    true => self.trust.low_trust_ttl,
+                    false => self.trust.ttl,
+                };
+                let online = self
+                    .is_online(&entry.maker, now)
+                    .unwrap_or(!self.online_only);
+                now.saturating_sub(entry.last_seen) > ttl || !online
+            })
+            .map(|(key, _)| key.clone())
+            .collect();
+
+        for key in expired {
+            self.entries.remove(&key);
+        }
+    }
+
+    /// Offers in the book, most corroborated first
+    /// With `one_per_cluster` only offers of the first maker of each suspected sybil cluster are kept
+    pub fn offers(&self) -> Vec<(String, Offer)> {
+        let mut entries: Vec<&OfferEntry> = self.entries.values().collect();
+        entries.sort_by(|a, b| {
+            self.score(b)
+                .total_cmp(&self.score(a))
+                .then(a.maker.cmp(&b.maker))
+        });
+
+        let mut hidden = HashSet::new();
+        if self.one_per_cluster {
+            for cluster in self.clusters() {
+                let kept = entries
+                    .iter()
+                    .find(|e| cluster.contains(&e.maker))
+                    .map(|e| e.maker.clone());
+                hidden.extend(
+                    cluster
+                        .into_iter()
+                        .filter(|maker| Some(maker) != kept.as_ref()),
+                );
+            }
+        }
+
+        entries
+            .into_iter()
+            .filter(|e| !hidden.contains(&e.maker))
+            .map(|e| (e.maker.clone(), e.offer.clone()))
+            .collect()
+    }
+
+    /// Offers in the book a taker sending amount can fill at `now`, see [`OrderBook::offers`]
+    /// Offers not valid yet and offers with a min size over amount are left out
+    /// ```
+    /// use nostrdizer::{
+    ///     order_book::OrderBook,
+    ///     types::{AbsOffer, Amount, Network, Offer, ABS_OFFER},
+    /// };
+    ///
+    /// let offer = |minsize, valid_from| {
+    ///     Offer::AbsOffer(AbsOffer {
+    ///         offer_id: 0,
+    ///         minsize: Amount::from_sat(minsize),
+    ///         maxsize: Amount::from_sat(1_000_000),
+    ///         txfee: Amount::ZERO,
+    ///         txfee_rate: None,
+    ///         cjfee: Amount::from_sat(100),
+    ///         min_fee_rate: None,
+    ///         protocol_version: 0,
+    ///         latency_class: None,
+    ///         valid_from,
+    ///         max_podle_index: None,
+    ///         encryption_key: None,
+    ///         fidelity_bond: None,
+    ///     })
+    /// };
+    /// let mut order_book = OrderBook::new(1, Network::Regtest);
+    /// order_book.one_per_cluster = false;
+    /// order_book.insert("wss://one", "a".to_string(), ABS_OFFER, offer(5_000, None), 0, 0);
+    /// order_book.insert("wss://one", "b".to_string(), ABS_OFFER, offer(500_000, None), 0, 0);
+    /// order_book.insert("wss://one", "c".to_string(), ABS_OFFER, offer(5_000, Some(100)), 0, 0);
+    ///
+    /// let makers = |now| -> Vec<String> {
+    ///     order_book
+    ///         .offers_for(Amount::from_sat(100_000), now)
+    ///         .into_iter()
+    ///         .map(|(maker, _)| maker)
+    ///         .collect()
+    /// };
+    /// assert_eq!(makers(50), vec!["a".to_string()]);
+    /// assert_eq!(makers(100), vec!["a".to_string(), "c".to_string()]);
+    /// ```
+    pub fn offers_for(&self, amount: Amount, now: u64) -> Vec<(String, Offer)> {
+        self.offers()
+            .into_iter()
+            .filter(|(_, offer)| offer.is_valid(now))
+            .filter(|(_, offer)| match offer {
+                Offer::AbsOffer(offer) => offer.minsize < amount,
+                Offer::RelOffer(offer) => offer.minsize < amount,
+            })
+            .collect()
+    }
+
+    /// Querys relays for offers, recording which relay each was seen on
+    /// Once the book is synced only offers and presence published since, and deletions, are asked for
+    /// Offers are asked for a page at a time, newest first, so busy relays are read with bounded memory
+    /// ```
+    /// use nostrdizer::{
+    ///     order_book::OrderBook,
+    ///     test_utils::{abs_offer, identity, offer_event, MockRelay},
+    ///     types::{Network, Offer},
+    /// };
+    ///
+    /// let (maker, other) = (identity(1), identity(2));
+    /// let mut relay = MockRelay::new(
+    ///     "wss://one",
+    ///     vec![
+    ///         offer_event(&maker, abs_offer(100), Network::Regtest, 10),
+    ///         offer_event(&other, abs_offer(100), Network::Signet, 10),
+    ///     ],
+    /// );
+    ///
+    /// let mut order_book = OrderBook::new(1, Network::Regtest);
+    /// order_book.fetch(&mut relay).unwrap();
+    ///
+    /// // Offers for another network are left out
+    /// let offers = order_book.offers();
+    /// assert_eq!(offers.len(), 1);
+    /// assert_eq!(offers[0].0, maker.public_key_str);
+    /// assert!(matches!(offers[0].1, Offer::AbsOffer(_)));
+    /// ```
+    pub fn fetch<C: RelayClient>(&mut self, nostr_client: &mut C) -> Result<(), Error> {
+        let since = self
+            .synced_at
+            .map(|synced_at| synced_at.saturating_sub(SYNC_OVERLAP));
+        let fetched_at = get_timestamp();
+        let mut until = None;
+        let mut synced = false;
+        let mut offers = 0;
+        loop {
+            let mut filters = vec![ReqFilter {
+                ids: None,
+                authors: None,
+                kinds: Some(vec![ABS_OFFER, REL_OFFER, PRESENCE]),
+                e: None,
+                p: None,
+                since,
+                until,
+                limit: Some(PAGE_SIZE),
+            }];
+            // Relays do not send offers deleted before the first fetch
+            // Deletions are few, they are all asked for with the first page
+            if since.is_some() && until.is_none() {
+                filters.push(ReqFilter {
+                    ids: None,
+                    authors: None,
+                    kinds: Some(vec![DELETION]),
+                    e: None,
+                    p: None,
+                    since,
+                    until: None,
+                    limit: None,
+                });
+            }
+
+            let (finished, pages) = self.fetch_page(nostr_client, filters, &mut offers)?;
+            // The first page decides whether the book is synced, later pages only add older offers
+            if until.is_none() {
+                synced = finished;
+            }
+            until = match (next_cursor(&pages, until), since) {
+                (Some(cursor), Some(since)) if cursor < since => break,
+                (Some(cursor), _) => Some(cursor),
+                (None, _) => break,
+            };
+            if offers >= MAX_OFFERS_PER_FETCH {
+                warn!("Read {offers} offers, older ones are not read");
+                break;
+            }
+        }
+
+        // A fetch no relay finished may have missed offers, the next one asks from the last sync again
+        if synced {
+            self.synced_at = Some(fetched_at);
+        }
+        self.prune(get_timestamp());
+
+        Ok(())
+    }
+
+    /// Adds an offer, presence or deletion event a relay sent at `seen_at`
+    /// Returns whether the event verified, offers and presence for other networks are verified but not added
+    /// ```
+    /// use nostrdizer::{
+    ///     order_book::OrderBook,
+    ///     test_utils::{abs_offer, identity, offer_event},
+    ///     types::{Amount, Network, Offer},
+    /// };
+    ///
+    /// let mut order_book = OrderBook::new(2, Network::Regtest);
+    /// let (maker, other) = (identity(1), identity(2));
+    ///
+    /// // An offer seen on both relays, then replaced by a newer one
+    /// let offer = offer_event(&maker, abs_offer(100), Network::Regtest, 10);
+    /// assert!(order_book.ingest("wss://one", offer.clone(), 20));
+    /// assert!(order_book.ingest("wss://two", offer, 20));
+    /// let newer = offer_event(&maker, abs_offer(200), Network::Regtest, 15);
+    /// assert!(order_book.ingest("wss://one", newer, 20));
+    ///
+    /// // Offers for another network and forged offers are left out
+    /// let signet = offer_event(&other, abs_offer(100), Network::Signet, 10);
+    /// assert!(order_book.ingest("wss://one", signet, 20));
+    /// let mut forged = offer_event(&other, abs_offer(100), Network::Regtest, 10);
+    /// forged.pub_key = maker.public_key_str.clone();
+    /// assert!(!order_book.ingest("wss://one", forged, 20));
+    ///
+    /// let offers = order_book.offers_for(Amount::from_sat(100_000), 20);
+    /// assert_eq!(offers.len(), 1);
+    /// assert_eq!(offers[0].0, maker.public_key_str);
+    /// assert!(matches!(&offers[0].1, Offer::AbsOffer(offer) if offer.cjfee == Amount::from_sat(200)));
+    /// ```
+    pub fn ingest(&mut self, relay: &str, event: Event, seen_at: u64) -> bool {
+        if event.verify().is_err() {
+            return false;
+        }
+        // Deletions are not tagged with a network, only the maker's own offers are deleted
+        if event.kind == DELETION {
+            for tag in event
+                .tags
+                .iter()
+                .filter(|tag| tag.first().map(String::as_str) == Some("e"))
+            {
+                if let Some(event_id) = tag.get(1) {
+                    self.delete(&event.pub_key, event_id);
+                }
+            }
+            return true;
+        }
+        if event_network(&event) != Some(self.network) {
+            return true;
+        }
+        match serde_json::from_str(&event.content) {
+            Ok(NostrdizerMessage {
+                event: NostrdizerMessages::Offer(offer),
+                ..
+            }) => {
+                let seen = OfferEntry {
+                    maker: event.pub_key,
+                    offer,
+                    relays: HashSet::new(),
+                    published_at: event.created_at,
+                    last_seen: seen_at,
+                    event_id: Some(event.id),
+                };
+                self.upsert(relay, event.kind, seen)
+            }
+            Ok(NostrdizerMessage {
+                event: NostrdizerMessages::Presence(presence),
+                ..
+            }) => self.insert_presence(
+                event.pub_key.clone(),
+                MakerPresence {
+                    status: presence.status,
+                    expires_at: event_expiration(&event),
+                    created_at: event.created_at,
+                },
+            ),
+            _ => (),
+        }
+        true
+    }
+
+    /// Reads one page of events from the relays, returns whether any relay finished sending it
+    /// and the offer events each relay sent
+    /// `offers` counts the offers read in the fetch, those past [`MAX_OFFERS_PER_FETCH`] are skipped
+    fn fetch_page<C: RelayClient>(
+        &mut self,
+        nostr_client: &mut C,
+        filters: Vec<ReqFilter>,
+        offers: &mut usize,
+    ) -> Result<(bool, HashMap<String, RelayPage>), Error> {
+        let mut subscription = SubscriptionGuard::subscribe(nostr_client, filters)?;
+        let mut pages: HashMap<String, RelayPage> = HashMap::new();
+
+        let mut finished_relays = HashSet::new();
+        let mut skipped = 0;
+        let started_waiting = get_timestamp();
+        while finished_relays.len() < self.relay_count
+            && get_timestamp() - started_waiting < FETCH_TIMEOUT
+        {
+            for (relay, message) in subscription.next_data()? {
+                // Messages are dropped before they are copied if they are too big for any protocol message
+                if message.len() > MAX_MESSAGE_SIZE {
+                    debug!("Skipping a {} byte message from {relay}", message.len());
+                    continue;
+                }
+                if let Ok(message) = serde_json::from_str::<Value>(&message.to_string()) {
+                    if message[0] == "EOSE" && message[1].as_str() == Some(subscription.id()) {
+                        finished_relays.insert(relay);
+                        continue;
+                    }
+
+                    // Content is checked before the event and the offer in it are parsed
+                    let content_size = message[2]["content"].as_str().map_or(0, str::len);
+                    if content_size > MAX_CONTENT_SIZE {
+                        debug!(
+                            "Skipping an event with {content_size} bytes of content from {relay}"
+                        );
+                        continue;
+                    }
+                    let is_offer = matches!(
+                        message[2]["kind"].as_u64(),
+                        Some(kind) if kind == u64::from(ABS_OFFER) || kind == u64::from(REL_OFFER)
+                    );
+                    if is_offer && *offers >= MAX_OFFERS_PER_FETCH {
+                        skipped += 1;
+                        continue;
+                    }
+
+                    if let Ok(event) = serde_json::from_value::<Event>(message[2].clone()) {
+                        let (kind, created_at) = (event.kind, event.created_at);
+                        if !self.ingest(&relay, event, get_timestamp()) || kind == DELETION {
+                            continue;
+                        }
+                        if is_offer {
+                            *offers += 1;
+                        }
+                        // The cursor of the next page is the oldest offer a relay sent, whatever its network
+                        let page = pages.entry(relay.clone()).or_insert(RelayPage {
+                            events: 0,
+                            oldest: created_at,
+                        });
+                        page.events += 1;
+                        page.oldest = page.oldest.min(created_at);
+                    }
+                }
+            }
+        }
+
+        if skipped > 0 {
+            warn!("Skipped {skipped} offers over the {MAX_OFFERS_PER_FETCH} read in a fetch");
+        }
+        Ok((!finished_relays.is_empty(), pages))
+    }
+
+    /// Adds the offers and presence cached at path and syncs from where the cache left off
+    /// A missing file or the cache of another network adds nothing
+    pub fn load_cache(&mut self, path: &Path) -> Result<(), Error> {
+        if !path.exists() {
+            return Ok(());
+        }
+        let cache: BookCache = serde_json::from_str(&fs::read_to_string(path)?)?;
+        if cache.network != self.network {
+            return Ok(());
+        }
+        for (kind, entry) in cache.offers {
+            self.entries.insert((entry.maker.clone(), kind), entry);
+        }
+        for (maker, presence) in cache.presence {
+            self.insert_presence(maker, presence);
+        }
+        self.synced_at = cache.synced_at;
+        self.prune(get_timestamp());
+        Ok(())
+    }
+
+    pub fn save_cache(&self, path: &Path) -> Result<(), Error> {
+        let cache = BookCache {
+            network: self.network,
+            synced_at: self.synced_at,
+            offers: self
+                .entries
+                .iter()
+                .map(|((_, kind), entry)| (*kind, entry.clone()))
+                .collect(),
+            presence: self
+                .presence
+                .iter()
+                .map(|(maker, presence)| (maker.clone(), presence.clone()))
+                .collect(),
+        };
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        file.write_all(serde_json::to_string_pretty(&cache)?.as_bytes())?;
+        Ok(())
+    }
+}
+
+/// Kind, size range and fee of an offer, identical for makers run with one config
+fn fingerprint(offer: &Offer) -> (bool, Amount, Amount, u64) {
+    match offer {
+        Offer::AbsOffer(offer) => (false, offer.minsize, offer.maxsize, offer.cjfee.to_sat()),
+        Offer::RelOffer(offer) => (true, offer.minsize, offer.maxsize, offer.cjfee.to_bits()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::abs_offer;
+
+    fn order_book() -> OrderBook {
+        let mut order_book = OrderBook::new(2, Network::Regtest);
+        order_book
+            .trust
+            .weights
+            .insert("wss://spam".to_string(), 0.1);
+        order_book
+    }
+
+    #[test]
+    fn test_corroborated_offers_first() {
+        let mut order_book = order_book();
+        order_book.insert(
+            "wss://spam",
+            "a".to_string(),
+            ABS_OFFER,
+            abs_offer(100),
+            0,
+            0,
+        );
+        order_book.insert(
+            "wss://one",
+            "b".to_string(),
+            ABS_OFFER,
+            abs_offer(100),
+            100,
+            0,
+        );
+        order_book.insert(
+            "wss://two",
+            "b".to_string(),
+            ABS_OFFER,
+            abs_offer(100),
+            100,
+            0,
+        );
+
+        let makers: Vec<String> = order_book.offers().into_iter().map(|o| o.0).collect();
+        assert_eq!(makers, vec!["b".to_string(), "a".to_string()]);
+    }
+
+    #[test]
+    fn test_low_trust_offers_expire_first() {
+        let mut order_book = order_book();
+        order_book.insert(
+            "wss://spam",
+            "a".to_string(),
+            ABS_OFFER,
+            abs_offer(100),
+            0,
+            0,
+        );
+        order_book.insert(
+            "wss://one",
+            "b".to_string(),
+            ABS_OFFER,
+            abs_offer(100),
+            100,
+            0,
+        );
+
+        order_book.prune(order_book.trust.low_trust_ttl + 1);
+        assert_eq!(order_book.offers().len(), 1);
+
+        order_book.prune(order_book.trust.ttl + 1);
+        assert!(order_book.offers().is_empty());
+    }
+
+    #[test]
+    fn test_offline_makers_pruned() {
+        let mut order_book = order_book();
+        order_book.insert(
+            "wss://one",
+            "a".to_string(),
+            ABS_OFFER,
+            abs_offer(100),
+            0,
+            0,
+        );
+        order_book.insert(
+            "wss://one",
+            "b".to_string(),
+            ABS_OFFER,
+            abs_offer(100),
+            100,
+            0,
+        );
+        order_book.insert(
+            "wss://one",
+            "c".to_string(),
+            ABS_OFFER,
+            abs_offer(100),
+            200,
+            0,
+        );
+        let presence = |status, created_at| MakerPresence {
+            status,
+            expires_at: None,
+            created_at,
+        };
+        order_book.insert_presence("a".to_string(), presence(PresenceStatus::Online, 0));
+        order_book.insert_presence("b".to_string(), presence(PresenceStatus::Online, 0));
+        order_book.insert_presence("b".to_string(), presence(PresenceStatus::Offline, 10));
+        // An older presence does not replace a newer one
+        order_book.insert_presence("b".to_string(), presence(PresenceStatus::Online, 5));
+
+        // Makers without presence stay unless only online makers are wanted
+        order_book.prune(10);
+        assert_eq!(order_book.offers().len(), 2);
+
+        order_book.online_only = true;
+        order_book.prune(10);
+        let makers: Vec<String> = order_book.offers().into_iter().map(|o| o.0).collect();
+        assert_eq!(makers, vec!["a".to_string()]);
+    }
+
+    #[test]
+    fn test_clusters_merged_and_override() {
+        let mut order_book = order_book();
+        order_book.insert(
+            "wss://one",
+            "a".to_string(),
+            ABS_OFFER,
+            abs_offer(100),
+            0,
+            0,
+        );
+        order_book.insert(
+            "wss://one",
+            "b".to_string(),
+            ABS_OFFER,
+            abs_offer(100),
+            8,
+            0,
+        );
+        order_book.insert(
+            "wss://one",
+            "c".to_string(),
+            ABS_OFFER,
+            abs_offer(100),
+            16,
+            0,
+        );
+        // Identical offer published long after
+        order_book.insert(
+            "wss://one",
+            "d".to_string(),
+            ABS_OFFER,
+            abs_offer(100),
+            600,
+            0,
+        );
+
+        // a and c are only linked through b
+        assert_eq!(
+            order_book.clusters(),
+            vec![vec!["a".to_string(), "b".to_string(), "c".to_string()]]
+        );
+        let makers: Vec<String> = order_book.offers().into_iter().map(|o| o.0).collect();
+        assert_eq!(makers, vec!["a".to_string(), "d".to_string()]);
+
+        order_book.one_per_cluster = false;
+        assert_eq!(order_book.offers().len(), 4);
+    }
+
+    #[test]
+    fn test_replaced_offer() {
+        let mut order_book = order_book();
+        order_book.insert(
+            "wss://one",
+            "a".to_string(),
+            ABS_OFFER,
+            abs_offer(100),
+            0,
+            0,
+        );
+        order_book.insert(
+            "wss://one",
+            "a".to_string(),
+            ABS_OFFER,
+            abs_offer(100),
+            10,
+            10,
+        );
+        order_book.insert(
+            "wss://one",
+            "a".to_string(),
+            REL_OFFER,
+            abs_offer(100),
+            10,
+            10,
+        );
+
+        assert_eq!(order_book.offers().len(), 2);
+    }
+
+    #[test]
+    fn test_next_page_cursor() {
+        let page = |events, oldest| RelayPage { events, oldest };
+        let mut pages = HashMap::new();
+        pages.insert("wss://quiet".to_string(), page(3, 100));
+        assert_eq!(next_cursor(&pages, None), None);
+
+        // Goes back only as far as the full relay with the newest oldest offer, so neither skips any
+        pages.insert("wss://busy".to_string(), page(PAGE_SIZE, 900));
+        pages.insert("wss://busier".to_string(), page(PAGE_SIZE, 700));
+        assert_eq!(next_cursor(&pages, None), Some(900));
+        assert_eq!(next_cursor(&pages, Some(1_000)), Some(900));
+
+        // A full page all in the second of the cursor moves past it
+        assert_eq!(next_cursor(&pages, Some(900)), Some(899));
+    }
+
+    /// Offer of maker a from the event `event_id`, seen when it was published
+    fn offer_event(published_at: u64, event_id: &str) -> OfferEntry {
+        OfferEntry {
+            maker: "a".to_string(),
+            offer: abs_offer(100),
+            relays: HashSet::new(),
+            published_at,
+            last_seen: published_at,
+            event_id: Some(event_id.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_deleted_offer() {
+        let mut order_book = order_book();
+        order_book.upsert("wss://one", ABS_OFFER, offer_event(0, "first"));
+
+        // Only the maker of the offer deletes it
+        assert!(!order_book.delete("b", "first"));
+        // A deletion of the offer it replaced leaves the new offer
+        order_book.upsert("wss://one", ABS_OFFER, offer_event(10, "second"));
+        assert!(!order_book.delete("a", "first"));
+        assert_eq!(order_book.offers().len(), 1);
+
+        assert!(order_book.delete("a", "second"));
+        assert!(order_book.offers().is_empty());
+    }
+
+    #[test]
+    fn test_cache_round_trip() {
+        let path = std::env::temp_dir().join("nostrdizer-order-book-cache.json");
+        let now = get_timestamp();
+        let mut order_book = order_book();
+        order_book.insert(
+            "wss://one",
+            "a".to_string(),
+            ABS_OFFER,
+            abs_offer(100),
+            now,
+            now,
+        );
+        order_book.synced_at = Some(now);
+        order_book.save_cache(&path).unwrap();
+
+        let mut cached = OrderBook::new(2, Network::Regtest);
+        cached.load_cache(&path).unwrap();
+        assert_eq!(cached.offers().len(), 1);
+        assert_eq!(cached.synced_at, Some(now));
+
+        // Offers of another network are not loaded
+        let mut other = OrderBook::new(2, Network::Signet);
+        other.load_cache(&path).unwrap();
+        assert!(other.offers().is_empty());
+        assert_eq!(other.synced_at, None);
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,217 @@
+use crate::{
+    display,
+    errors::Error,
+    labels::{is_cj_output, LabelStore, LabelType, CJ_CHANGE_LABEL, CJ_LABEL, CJ_OUTPUT_LABEL},
+    types::{Amount, BitcoinTransaction, OutPoint, Txid},
+};
+
+use log::warn;
+
+use std::collections::HashMap;
+
+/// Wallet spends a utxo is traced back through before the transaction is taken as its source
+pub const MAX_SOURCE_DEPTH: usize = 8;
+
+/// Where the coins of a wallet utxo came from, utxos with a source in common are already linked on chain
+/// Sources are the deposits the utxo descends from through the wallet's own spends, and its label
+/// CJ outputs are their own source, the CJ broke their link to its inputs
+/// ```
+/// use bitcoin::{hashes::Hash, OutPoint, PackedLockTime, Transaction, TxIn, TxOut, Txid};
+/// use nostrdizer::{labels::LabelStore, partition::utxo_sources};
+///
+/// let tx = |inputs: Vec<OutPoint>, values: Vec<u64>| Transaction {
+///     version: 2,
+///     lock_time: PackedLockTime::ZERO,
+///     input: inputs
+///         .into_iter()
+///         .map(|previous_output| TxIn { previous_output, ..Default::default() })
+///         .collect(),
+///     output: values
+///         .into_iter()
+///         .map(|value| TxOut { value, ..Default::default() })
+///         .collect(),
+/// };
+/// let external = OutPoint::new(Txid::from_slice(&[1; 32]).unwrap(), 0);
+/// let deposit = tx(vec![external], vec![100_000]);
+/// let spend = tx(vec![OutPoint::new(deposit.txid(), 0)], vec![40_000, 59_000]);
+/// let txs = [deposit.clone(), spend.clone()];
+/// let wallet_tx = |txid: &Txid| txs.iter().find(|tx| tx.txid() == *txid).cloned();
+/// let is_own = |outpoint: &OutPoint| outpoint.txid == deposit.txid();
+///
+/// // Change of a spend is traced back to the deposit
+/// let change = OutPoint::new(spend.txid(), 1);
+/// let sources = utxo_sources(change, &LabelStore::default(), wallet_tx, is_own);
+/// assert_eq!(sources, vec![format!("tx:{}", deposit.txid())]);
+/// ```
+pub fn utxo_sources(
+    outpoint: OutPoint,
+    labels: &LabelStore,
+    wallet_tx: impl Fn(&Txid) -> Option<BitcoinTransaction>,
+    is_own: impl Fn(&OutPoint) -> bool,
+) -> Vec<String> {
+    let mut sources = vec![];
+    // Labels the user gave a deposit, the labels of CJs are not a source
+    let label = labels
+        .get(LabelType::Output, &outpoint.to_string())
+        .or_else(|| labels.get(LabelType::Tx, &outpoint.txid.to_string()))
+        .filter(|label| {
+            ![CJ_LABEL, CJ_OUTPUT_LABEL, CJ_CHANGE_LABEL].contains(&label.label.as_str())
+        })
+        .filter(|label| !label.label.is_empty());
+    if let Some(label) = label {
+        sources.push(format!("label:{}", label.label));
+    }
+    trace(
+        outpoint,
+        &wallet_tx,
+        &is_own,
+        MAX_SOURCE_DEPTH,
+        &mut sources,
+    );
+    sources.sort();
+    sources.dedup();
+    sources
+}
+
+fn trace(
+    outpoint: OutPoint,
+    wallet_tx: &impl Fn(&Txid) -> Option<BitcoinTransaction>,
+    is_own: &impl Fn(&OutPoint) -> bool,
+    depth: usize,
+    sources: &mut Vec<String>,
+) {
+    let tx = match wallet_tx(&outpoint.txid) {
+        Some(tx) => tx,
+        None => {
+            sources.push(format!("tx:{}", outpoint.txid));
+            return;
+        }
+    };
+    if let Some(output) = tx.output.get(outpoint.vout as usize) {
+        if is_cj_output(&tx, output) {
+            sources.push(format!("cj:{outpoint}"));
+            return;
+        }
+    }
+
+    let own_inputs: Vec<OutPoint> = tx
+        .input
+        .iter()
+        .map(|input| input.previous_output)
+        .filter(|previous| is_own(previous))
+        .collect();
+    if own_inputs.is_empty() || depth == 0 {
+        sources.push(format!("tx:{}", outpoint.txid));
+        return;
+    }
+    for previous in own_inputs {
+        trace(previous, wallet_tx, is_own, depth - 1, sources);
+    }
+}
+
+/// Utxos, by index into `sources`, grouped by the sources they have in common
+/// ```
+/// use nostrdizer::partition::source_clusters;
+///
+/// let sources = |names: &[&str]| names.iter().map(|name| name.to_string()).collect::<Vec<_>>();
+/// let clusters = source_clusters(&[
+///     sources(&["tx:a"]),
+///     sources(&["tx:b"]),
+///     sources(&["tx:a", "label:exchange"]),
+///     sources(&["label:exchange"]),
+/// ]);
+/// assert_eq!(clusters, vec![vec![0, 2, 3], vec![1]]);
+/// ```
+pub fn source_clusters(sources: &[Vec<String>]) -> Vec<Vec<usize>> {
+    let mut cluster: Vec<usize> = (0..sources.len()).collect();
+    let mut utxo_of_source: HashMap<&str, usize> = HashMap::new();
+    for (utxo, utxo_sources) in sources.iter().enumerate() {
+        for source in utxo_sources {
+            match utxo_of_source.get(source.as_str()) {
+                Some(other) => {
+                    let (from, to) = (root(&cluster, utxo), root(&cluster, *other));
+                    cluster[from] = to;
+                }
+                None => {
+                    utxo_of_source.insert(source, utxo);
+                }
+            }
+        }
+    }
+
+    let mut clusters: HashMap<usize, Vec<usize>> = HashMap::new();
+    for utxo in 0..sources.len() {
+        clusters.entry(root(&cluster, utxo)).or_default().push(utxo);
+    }
+    let mut clusters: Vec<Vec<usize>> = clusters.into_values().collect();
+    clusters.sort();
+    clusters
+}
+
+/// Cluster a utxo was merged into
+fn root(cluster: &[usize], mut utxo: usize) -> usize {
+    while cluster[utxo] != utxo {
+        utxo = cluster[utxo];
+    }
+    utxo
+}
+
+/// Utxos, by index into `values`, a round's taker inputs are picked from
+/// The cluster of the least value that covers `target`, so the round does not link sources and larger clusters
+/// are kept for larger rounds. When no cluster does every utxo is returned if the taker links sources
+/// ```
+/// use nostrdizer::{errors::Error, partition::spendable_sources, types::Amount};
+///
+/// let values: Vec<Amount> = [60_000, 50_000, 80_000]
+///     .into_iter()
+///     .map(Amount::from_sat)
+///     .collect();
+/// let sources: Vec<Vec<String>> = ["tx:a", "tx:a", "tx:b"]
+///     .iter()
+///     .map(|source| vec![source.to_string()])
+///     .collect();
+/// let spendable = |sats, link_sources| {
+///     spendable_sources(&values, &sources, Amount::from_sat(sats), link_sources)
+/// };
+///
+/// assert_eq!(spendable(70_000, false).unwrap(), vec![2]);
+/// assert_eq!(spendable(100_000, false).unwrap(), vec![0, 1]);
+///
+/// // No one source is worth enough
+/// assert!(matches!(spendable(150_000, false), Err(Error::SourcesWouldLink(2))));
+/// assert_eq!(spendable(150_000, true).unwrap(), vec![0, 1, 2]);
+/// ```
+pub fn spendable_sources(
+    values: &[Amount],
+    sources: &[Vec<String>],
+    target: Amount,
+    link_sources: bool,
+) -> Result<Vec<usize>, Error> {
+    let clusters = source_clusters(sources);
+    let value = |utxos: &[usize]| -> Amount {
+        Amount::from_sat(utxos.iter().map(|i| values[*i].to_sat()).sum())
+    };
+
+    if let Some(cluster) = clusters
+        .iter()
+        .filter(|cluster| value(cluster) >= target)
+        .min_by_key(|cluster| value(cluster))
+    {
+        return Ok(cluster.clone());
+    }
+
+    let all: Vec<usize> = (0..values.len()).collect();
+    if value(&all) < target {
+        return Err(Error::InsufficientFunds);
+    }
+    if !link_sources {
+        return Err(Error::SourcesWouldLink(clusters.len()));
+    }
+    warn!(
+        "No one source of the wallet's coins covers {}, spending coins of {} sources together links them: \
+         anyone can tell from the CJ that they belong to one wallet",
+        display::sats(target),
+        clusters.len()
+    );
+    Ok(all)
+}
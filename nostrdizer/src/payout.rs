@@ -0,0 +1,196 @@
+use crate::{
+    errors::Error,
+    fees::{checked_add, checked_sub},
+    types::{Amount, SignedAmount},
+};
+
+use bdk::bitcoin::{Address, Network, Txid};
+use serde::{Deserialize, Serialize};
+
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Where and when a maker sweeps the fees it has earned
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PayoutConfig {
+    /// Cold address earnings are sent to
+    pub address: Address,
+    /// Earnings are only swept once there is at least this much
+    pub threshold: Amount,
+    /// Min seconds between sweeps
+    pub interval: u64,
+    /// Confirmation target used to estimate the fee rate, high so the sweep is cheap
+    pub conf_target: u16,
+}
+
+impl PayoutConfig {
+    pub fn new(
+        address: &str,
+        network: Network,
+        threshold: Amount,
+        interval: u64,
+    ) -> Result<Self, Error> {
+        let address =
+            Address::from_str(address).map_err(|_| Error::FromStringError(address.to_string()))?;
+        if !address.is_valid_for_network(network) {
+            return Err(Error::WrongNetwork(network));
+        }
+
+        Ok(Self {
+            address,
+            threshold,
+            interval,
+            conf_target: 144,
+        })
+    }
+}
+
+/// Entry in a maker's payout history
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PayoutEntry {
+    /// Fee earned from signing a CJ
+    Earned {
+        txid: Txid,
+        #[serde(with = "bdk::bitcoin::util::amount::serde::as_sat")]
+        amount: Amount,
+        created_at: u64,
+    },
+    /// Earnings sent to the payout address
+    Sweep {
+        txid: Txid,
+        #[serde(with = "bdk::bitcoin::util::amount::serde::as_sat")]
+        amount: Amount,
+        #[serde(with = "bdk::bitcoin::util::amount::serde::as_sat")]
+        fee: Amount,
+        created_at: u64,
+    },
+}
+
+/// Earnings and sweeps of a maker, kept between runs
+/// ```
+/// use nostrdizer::{
+///     payout::PayoutHistory,
+///     types::{Amount, SignedAmount},
+/// };
+/// # use bdk::bitcoin::OutPoint;
+/// # let txid = OutPoint::null().txid;
+///
+/// let mut history = PayoutHistory::default();
+/// history.record_earned(txid, SignedAmount::from_sat(3_000), 10);
+/// history.record_earned(txid, SignedAmount::from_sat(2_000), 20);
+/// assert!(history.sweep_due(Amount::from_sat(5_000), 3600, 20));
+///
+/// history.record_sweep(txid, Amount::from_sat(5_000), Amount::from_sat(200), 30);
+/// assert_eq!(history.unswept().unwrap(), Amount::ZERO);
+/// assert!(!history.sweep_due(Amount::ZERO, 3600, 40));
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct PayoutHistory {
+    pub entries: Vec<PayoutEntry>,
+}
+
+impl PayoutHistory {
+    /// Loads history from path, an empty history if there is no file
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        match path.exists() {
+            true => Ok(serde_json::from_str(&fs::read_to_string(path)?)?),
+            false => Ok(Self::default()),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        Ok(fs::write(path, serde_json::to_string_pretty(self)?)?)
+    }
+
+    /// Records the fee of a signed CJ, losses are not earnings
+    pub fn record_earned(&mut self, txid: Txid, maker_fee: SignedAmount, created_at: u64) {
+        if let Ok(amount) = maker_fee.to_unsigned() {
+            if amount > Amount::ZERO {
+                self.entries.push(PayoutEntry::Earned {
+                    txid,
+                    amount,
+                    created_at,
+                });
+            }
+        }
+    }
+
+    pub fn record_sweep(&mut self, txid: Txid, amount: Amount, fee: Amount, created_at: u64) {
+        self.entries.push(PayoutEntry::Sweep {
+            txid,
+            amount,
+            fee,
+            created_at,
+        });
+    }
+
+    /// Earnings that have not been swept
+    pub fn unswept(&self) -> Result<Amount, Error> {
+        let mut earned = Amount::ZERO;
+        let mut swept = Amount::ZERO;
+        for entry in &self.entries {
+            match entry {
+                PayoutEntry::Earned { amount, .. } => earned = checked_add(earned, *amount)?,
+                PayoutEntry::Sweep { amount, .. } => swept = checked_add(swept, *amount)?,
+            }
+        }
+        // Sweeps are never more then was earned so this only fails on an edited history
+        checked_sub(earned, swept)
+    }
+
+    pub fn last_sweep(&self) -> Option<u64> {
+        self.entries
+            .iter()
+            .filter_map(|entry| match entry {
+                PayoutEntry::Sweep { created_at, .. } => Some(*created_at),
+                PayoutEntry::Earned { .. } => None,
+            })
+            .max()
+    }
+
+    /// Whether enough has been earned, and long enough has passed, to sweep
+    pub fn sweep_due(&self, threshold: Amount, interval: u64, now: u64) -> bool {
+        let interval_passed = match self.last_sweep() {
+            Some(last_sweep) => now.saturating_sub(last_sweep) >= interval,
+            None => true,
+        };
+        let unswept = self.unswept().unwrap_or(Amount::ZERO);
+
+        interval_passed && unswept > Amount::ZERO && unswept >= threshold
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bdk::bitcoin::{OutPoint, Script};
+
+    #[test]
+    fn test_sweep_interval() {
+        let txid = OutPoint::null().txid;
+        let mut history = PayoutHistory::default();
+        history.record_earned(txid, SignedAmount::from_sat(1_000), 0);
+        history.record_sweep(txid, Amount::from_sat(1_000), Amount::from_sat(100), 100);
+        history.record_earned(txid, SignedAmount::from_sat(2_000), 150);
+        // Losses are not counted
+        history.record_earned(txid, SignedAmount::from_sat(-500), 160);
+
+        assert_eq!(history.unswept().unwrap(), Amount::from_sat(2_000));
+        assert!(!history.sweep_due(Amount::from_sat(1_000), 3600, 200));
+        assert!(history.sweep_due(Amount::from_sat(1_000), 3600, 3700));
+        assert!(!history.sweep_due(Amount::from_sat(5_000), 3600, 3700));
+    }
+
+    #[test]
+    fn test_payout_address_network() {
+        let address = Address::p2wsh(&Script::new(), Network::Regtest).to_string();
+        let address = address.as_str();
+        assert!(PayoutConfig::new(address, Network::Regtest, Amount::ZERO, 0).is_ok());
+        assert!(matches!(
+            PayoutConfig::new(address, Network::Bitcoin, Amount::ZERO, 0),
+            Err(Error::WrongNetwork(Network::Bitcoin))
+        ));
+    }
+}
@@ -1,9 +1,15 @@
-use super::{errors::Error, types::AuthCommitment};
+use super::{
+    errors::Error,
+    types::{AuthBinding, AuthCommitment},
+};
 
 use num_bigint::BigInt;
 
-use bdk::bitcoin::{consensus::Decodable, PrivateKey};
-use bitcoin_hashes::{sha256, Hash};
+use bdk::bitcoin::{
+    consensus::{encode::serialize, Decodable},
+    OutPoint, PrivateKey,
+};
+use bitcoin_hashes::{hmac, sha256, Hash, HashEngine};
 use secp256k1::{PublicKey, Scalar, Secp256k1, SecretKey};
 
 use log::debug;
@@ -50,26 +56,121 @@ fn modulo(a: &BigInt, b: &BigInt) -> BigInt {
     ((a % b) + b) % b
 }
 
-/// Generate podle commitment
+/// Challenge of the podle proof, committing to the session when the opening is bound to one
+fn challenge(points: [[u8; 33]; 4], binding: &Option<AuthBinding>) -> sha256::Hash {
+    let mut data = points.concat();
+    if let Some(binding) = binding {
+        data.extend_from_slice(&binding.hash()[..]);
+    }
+    sha256::Hash::hash(&data)
+}
+
+/// Tag the data podle nonces are derived from starts with
+const NONCE_TAG: &[u8] = b"nostrdizer podle nonce";
+
+/// Nonce `k` of a podle, derived RFC6979 style with HMAC-SHA256 from the utxo key and a hash of the utxo,
+/// the NUMS index and the session the opening is bound to
+/// An opening generated again for the same round is the same opening, and `k` does not hang on the system's randomness
+/// ```
+/// use bitcoin::{OutPoint, PrivateKey};
+/// use nostrdizer::{podle::podle_nonce, types::AuthBinding};
+///
+/// let priv_key = PrivateKey::from_slice(&[7; 32], bitcoin::Network::Regtest).unwrap();
+/// let outpoint = OutPoint::default();
+/// let nonce = podle_nonce(&priv_key, &outpoint, 0, &None);
+///
+/// assert_eq!(nonce, podle_nonce(&priv_key, &outpoint, 0, &None));
+/// assert_ne!(nonce, podle_nonce(&priv_key, &outpoint, 1, &None));
+/// let binding = Some(AuthBinding::new(&[], "fill"));
+/// assert_ne!(nonce, podle_nonce(&priv_key, &outpoint, 0, &binding));
+/// ```
+pub fn podle_nonce(
+    priv_key: &PrivateKey,
+    outpoint: &OutPoint,
+    index: u8,
+    binding: &Option<AuthBinding>,
+) -> Scalar {
+    let mut data = NONCE_TAG.to_vec();
+    data.extend(serialize(outpoint));
+    data.push(index);
+    if let Some(binding) = binding {
+        data.extend_from_slice(&binding.hash()[..]);
+    }
+    let h1 = sha256::Hash::hash(&data);
+    let x = priv_key.to_bytes();
+
+    let hmac = |key: &[u8], data: &[&[u8]]| -> [u8; 32] {
+        let mut engine = hmac::HmacEngine::<sha256::Hash>::new(key);
+        for part in data {
+            engine.input(part);
+        }
+        hmac::Hmac::<sha256::Hash>::from_engine(engine).into_inner()
+    };
+
+    let v = [1u8; 32];
+    let k = hmac(&[0u8; 32], &[&v[..], &[0u8][..], &x[..], &h1[..]]);
+    let v = hmac(&k, &[&v[..]]);
+    let mut k = hmac(&k, &[&v[..], &[1u8][..], &x[..], &h1[..]]);
+    let mut v = hmac(&k, &[&v[..]]);
+    loop {
+        v = hmac(&k, &[&v[..]]);
+        // Candidates that are zero or over the curve order are skipped
+        if let Ok(nonce) = SecretKey::from_slice(&v) {
+            return Scalar::from(nonce);
+        }
+        k = hmac(&k, &[&v[..], &[0u8][..]]);
+        v = hmac(&k, &[&v[..]]);
+    }
+}
+
+/// Highest NUMS index takers commit at and makers accept, each utxo can open one more rounds than this
+pub const MAX_PODLE_INDEX: u8 = 2;
+
+/// Commitment to a utxo key at a NUMS index, the hash of P2 fills are sent with and P2
+/// ```
+/// use bitcoin::{OutPoint, PrivateKey};
+/// use nostrdizer::podle::{commitment, generate_podle};
+///
+/// let priv_key = PrivateKey::from_slice(&[7; 32], bitcoin::Network::Regtest).unwrap();
+/// let (commit, p2) = commitment(1, priv_key).unwrap();
+///
+/// let proof = generate_podle(1, priv_key, OutPoint::default(), None).unwrap();
+/// assert_eq!((proof.commit, proof.p2), (commit, p2));
+/// assert_ne!(commitment(0, priv_key).unwrap().0, commit);
+/// ```
+pub fn commitment(index: u8, priv_key: PrivateKey) -> Result<(sha256::Hash, PublicKey), Error> {
+    let p2 = get_p2(priv_key.inner, get_nums(index)?);
+    Ok((sha256::Hash::hash(&p2.serialize()), p2))
+}
+
+/// Generate podle commitment over the key of the utxo at outpoint, its nonce is derived with [`podle_nonce`]
 /// ```
-/// use bitcoin::PrivateKey;
+/// use bitcoin::{OutPoint, PrivateKey};
 /// use nostrdizer::podle::{generate_podle, verify_podle};
 ///
 /// let priv_key = PrivateKey::from_slice( b"\xf00\x1aD3R\xba\xa9&\xce$\xe3\xf6,\xf3j\xden\x87\x85\xee\xe8\xd4c\xd4C\x80\x1f\x81\x02j\xe9", bitcoin::Network::Regtest).unwrap();
-/// let result = generate_podle(0, priv_key).unwrap();
+/// let result = generate_podle(0, priv_key, OutPoint::default(), None).unwrap();
 ///
 /// assert_eq!(result.p.serialize(), [2, 30, 229, 220, 10, 194, 200, 105, 195, 110, 225, 178, 244, 49, 52, 230, 190, 215, 102, 72, 155, 101, 23, 157, 93, 141, 120, 51, 3, 66, 151, 108, 172]);
 /// assert_eq!(result.p2.serialize(), [3, 244, 231, 197, 180, 185, 249, 244, 106, 38, 41, 229, 149, 221, 9, 249, 222, 147, 89, 33, 173, 206, 237, 228, 134, 107, 138, 213, 252, 51, 51, 243, 147]);
-///    // let k =  Scalar::from_be_bytes(*b"\x8d\xe6\xc8-\xc63EYf\xdf\x18\xe7d\xb4\xf9k\xbc\xd6z5\xef\\\xdfvI\xc5\x1b\x07\x87\x91\xcc\x89").unwrap();
 /// verify_podle(0, result.clone(), result.commit).unwrap();
+///
+/// // Generated again for the same utxo and session it is the same opening
+/// let again = generate_podle(0, priv_key, OutPoint::default(), None).unwrap();
+/// assert_eq!((again.sig, again.e), (result.sig, result.e));
 /// ```
-pub fn generate_podle(index: usize, priv_key: PrivateKey) -> Result<AuthCommitment, Error> {
+pub fn generate_podle(
+    index: usize,
+    priv_key: PrivateKey,
+    outpoint: OutPoint,
+    binding: Option<AuthBinding>,
+) -> Result<AuthCommitment, Error> {
     let ctx = Secp256k1::new();
     // P
     let pub_key = priv_key.public_key(&ctx).inner;
     //debug!("g P: {:?}", pub_key);
     // k
-    let k = Scalar::random();
+    let k = podle_nonce(&priv_key, &outpoint, index as u8, &binding);
     // KG
     let kg = SecretKey::from_slice(&k.to_be_bytes())
         .unwrap()
@@ -84,14 +185,15 @@ pub fn generate_podle(index: usize, priv_key: PrivateKey) -> Result<AuthCommitme
 
     let commitment = sha256::Hash::hash(&p2.serialize());
     // e
-    let arrays = [
-        kg.serialize(),
-        kj.serialize(),
-        pub_key.serialize(),
-        p2.serialize(),
-    ]
-    .concat();
-    let e = sha256::Hash::hash(&arrays);
+    let e = challenge(
+        [
+            kg.serialize(),
+            kj.serialize(),
+            pub_key.serialize(),
+            p2.serialize(),
+        ],
+        &binding,
+    );
 
     let priv_int = decode(&priv_key.to_bytes());
 
@@ -110,21 +212,41 @@ pub fn generate_podle(index: usize, priv_key: PrivateKey) -> Result<AuthCommitme
         commit: commitment,
         sig,
         e,
+        index: Some(index as u8),
+        binding,
     };
     //debug!("Result: {:#?}", result);
     Ok(result)
 }
 
-/// Verify a podle commitment
+/// Verify a podle commitment at its index, or at every index up to `index` when it was sent without one
 /// ```
-/// use nostrdizer::podle::{generate_podle, verify_podle};
-/// use bitcoin::PrivateKey;
+/// use nostrdizer::{
+///     podle::{generate_podle, verify_podle},
+///     types::AuthBinding,
+/// };
+/// use bitcoin::{OutPoint, PrivateKey};
 /// // Not really a great test as it assumes generate is correct
 /// let priv_key = PrivateKey::from_slice( b"\xf00\x1aD3R\xba\xa9&\xce$\xe3\xf6,\xf3j\xden\x87\x85\xee\xe8\xd4c\xd4C\x80\x1f\x81\x02j\xe9", bitcoin::Network::Regtest).unwrap();
-/// let auth = generate_podle(0, priv_key).unwrap();
+/// let auth = generate_podle(0, priv_key, OutPoint::default(), None).unwrap();
 ///
 /// verify_podle(0, auth.clone(), auth.commit);
 ///
+/// // A bound opening does not verify once the session it is bound to is changed
+/// let mut auth = generate_podle(
+///     0,
+///     priv_key,
+///     OutPoint::default(),
+///     Some(AuthBinding::new(&[], "fill")),
+/// ).unwrap();
+/// assert!(verify_podle(0, auth.clone(), auth.commit).is_ok());
+/// auth.binding = Some(AuthBinding::new(&[], "other fill"));
+/// assert!(verify_podle(0, auth.clone(), auth.commit).is_err());
+///
+/// // Openings at an index over the max are turned down without trying the lower ones
+/// let auth = generate_podle(2, priv_key, OutPoint::default(), None).unwrap();
+/// assert!(verify_podle(2, auth.clone(), auth.commit).is_ok());
+/// assert!(verify_podle(1, auth.clone(), auth.commit).is_err());
 /// ```
 pub fn verify_podle(
     index: u8,
@@ -137,6 +259,17 @@ pub fn verify_podle(
     let sig = auth_commitment.sig;
     let e = auth_commitment.e;
     let commitment = auth_commitment.commit;
+    let binding = auth_commitment.binding;
+    let indices = match auth_commitment.index {
+        Some(opened) if opened > index => {
+            return Err(Error::PodleIndexNotAccepted {
+                index: opened,
+                max: index,
+            })
+        }
+        Some(opened) => opened..=opened,
+        None => 0..=index,
+    };
 
     let hash_p2 = sha256::Hash::hash(&p2.serialize());
 
@@ -151,7 +284,7 @@ pub fn verify_podle(
     let s_g = sig_priv.public_key(&ctx);
     let sig_scalar = Scalar::from_be_bytes(sig.try_into().unwrap()).unwrap();
 
-    for i in 0..=index {
+    for i in indices {
         let j = get_nums(i)?;
         debug!("J: {j}");
         let s_j = j.mul_tweak(&ctx, &sig_scalar)?;
@@ -178,14 +311,14 @@ pub fn verify_podle(
         let k_g_ser = s_g.combine(&e_p_neg)?;
         let k_j_ser = s_j.combine(&e_p2_neg)?;
 
-        let e_check = bitcoin_hashes::sha256::Hash::hash(
-            &[
+        let e_check = challenge(
+            [
                 k_g_ser.serialize(),
                 k_j_ser.serialize(),
                 p.serialize(),
                 p2.serialize(),
-            ]
-            .concat(),
+            ],
+            &binding,
         );
 
         if e_check == e {
@@ -561,4 +694,42 @@ mod tests {
             "03f4e7c5b4b9f9f46a2629e595dd09f9de935921adceede4866b8ad5fc3333f393".to_string()
         );
     }
+
+    fn nonce_vector() -> (PrivateKey, OutPoint) {
+        let priv_key = PrivateKey::from_slice(&[7; 32], bdk::bitcoin::Network::Regtest).unwrap();
+        let outpoint = OutPoint::from_str(&format!("{}:0", "01".repeat(32))).unwrap();
+        (priv_key, outpoint)
+    }
+
+    #[test]
+    fn test_podle_nonce() {
+        let (priv_key, outpoint) = nonce_vector();
+        let nonce = podle_nonce(&priv_key, &outpoint, 0, &None);
+
+        assert_eq!(
+            nonce.to_be_bytes().to_vec(),
+            hex::decode("8d97d38cb65ca37db2ff24542db03b363cf314897ce5f680af684ad55dfdc6e7")
+                .unwrap()
+        );
+
+        let other = OutPoint::from_str(&format!("{}:1", "01".repeat(32))).unwrap();
+        assert_ne!(nonce, podle_nonce(&priv_key, &other, 0, &None));
+    }
+
+    #[test]
+    fn test_generate_podle_vector() {
+        let (priv_key, outpoint) = nonce_vector();
+        let auth = generate_podle(0, priv_key, outpoint, None).unwrap();
+
+        assert_eq!(
+            auth.e.to_string(),
+            "d1646795857a7ddc390c3e2664c01e205a5004811bff4c8f66b93d161b7f4ded"
+        );
+        assert_eq!(
+            auth.sig,
+            hex::decode("413992525e5af3e67fb751a9989aab2efb2a0b10d6b370d083902257089bb718")
+                .unwrap()
+        );
+        verify_podle(0, auth.clone(), auth.commit).unwrap();
+    }
 }
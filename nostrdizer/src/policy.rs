@@ -0,0 +1,365 @@
+use crate::types::{Amount, DescriptorType, Fill, RejectReason, VerifyCJInfo};
+
+use bdk::bitcoin::{psbt::PartiallySignedTransaction, Address, Network, Script};
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashSet;
+
+/// Rounds a maker turns down as they use up its liquidity and addresses for next to no fee or privacy
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RoundPolicy {
+    /// Smallest CJ output worth mixing
+    #[serde(with = "bdk::bitcoin::util::amount::serde::as_sat")]
+    pub min_amount: Amount,
+    /// Outputs at or below this only tag the address they pay
+    #[serde(with = "bdk::bitcoin::util::amount::serde::as_sat")]
+    pub dust_threshold: Amount,
+    /// Most outputs at or below the dust threshold a CJ may have, change of the makers can be this small
+    pub max_dust_outputs: usize,
+    /// Outputs this close to a CJ output value count as mixing outputs
+    #[serde(default, with = "bdk::bitcoin::util::amount::serde::as_sat")]
+    pub uniformity_tolerance: Amount,
+}
+
+impl Default for RoundPolicy {
+    fn default() -> Self {
+        Self {
+            min_amount: Amount::from_sat(10_000),
+            dust_threshold: Amount::from_sat(1_000),
+            max_dust_outputs: 2,
+            uniformity_tolerance: Amount::ZERO,
+        }
+    }
+}
+
+impl RoundPolicy {
+    /// Checks every CJ output the fill asks for is above the privacy floor
+    /// ```
+    /// use nostrdizer::{
+    ///     policy::RoundPolicy,
+    ///     test_utils::fill,
+    ///     types::{Amount, RejectReason},
+    /// };
+    ///
+    /// let policy = RoundPolicy::default();
+    /// assert_eq!(policy.check_fill(&fill(100_000)), None);
+    /// assert_eq!(
+    ///     policy.check_fill(&fill(5_000)),
+    ///     Some(RejectReason::BelowPrivacyFloor {
+    ///         amount: Amount::from_sat(5_000)
+    ///     })
+    /// );
+    /// ```
+    pub fn check_fill(&self, fill: &Fill) -> Option<RejectReason> {
+        fill.outputs()
+            .into_iter()
+            .find(|amount| *amount < self.min_amount)
+            .map(|amount| RejectReason::BelowPrivacyFloor { amount })
+    }
+
+    /// Number of CJ outputs at or below the dust threshold
+    pub fn dust_outputs(&self, psbt: &PartiallySignedTransaction) -> usize {
+        psbt.unsigned_tx
+            .output
+            .iter()
+            .filter(|output| output.value <= self.dust_threshold.to_sat())
+            .count()
+    }
+
+    /// Rejects a CJ the maker would otherwise sign if it is structured like dust forwarding spam
+    /// or its outputs tell the parties apart
+    pub fn check_cj(
+        &self,
+        mut tx_info: VerifyCJInfo,
+        psbt: &PartiallySignedTransaction,
+        fill: &Fill,
+    ) -> VerifyCJInfo {
+        let dust_outputs = self.dust_outputs(psbt);
+        if tx_info.reject_reason.is_none() && dust_outputs > self.max_dust_outputs {
+            tx_info.verifyed = false;
+            tx_info.reject_reason = Some(RejectReason::DustOutputs {
+                count: dust_outputs,
+            });
+        }
+        if tx_info.reject_reason.is_none() && !standard_outputs(psbt) {
+            tx_info.verifyed = false;
+            tx_info.reject_reason = Some(RejectReason::NonstandardOutputs);
+        }
+        tx_info = check_uniformity(
+            tx_info,
+            psbt,
+            &fill.outputs(),
+            fill.payment,
+            self.uniformity_tolerance,
+        );
+        let payment = payment_script(psbt, fill.payment);
+        if tx_info.reject_reason.is_none() && !output_types_match(psbt, payment) {
+            tx_info.verifyed = false;
+            tx_info.reject_reason = Some(RejectReason::MixedOutputTypes);
+        }
+        tx_info
+    }
+}
+
+/// Whether an output value is within tolerance of one of the CJ output values
+fn is_mixing(value: u64, mixing: &[Amount], tolerance: Amount) -> bool {
+    mixing
+        .iter()
+        .any(|amount| value.abs_diff(amount.to_sat()) <= tolerance.to_sat())
+}
+
+/// Checks the outputs of a CJ are uniform, so those that are not mixing outputs can all pass for change
+/// Each party has at most one change output, one more is allowed for a taker that splits its change
+/// A payment round, with a `payment` value, pays exactly one output of that value on top
+/// ```
+/// use nostrdizer::{
+///     policy::check_uniformity,
+///     test_utils::psbt,
+///     types::{Amount, RejectReason, SignedAmount, VerifyCJInfo},
+/// };
+///
+/// let tx_info = VerifyCJInfo {
+///     mining_fee: SignedAmount::from_sat(500),
+///     maker_fee: SignedAmount::from_sat(500),
+///     fee_rate: 1.0,
+///     verifyed: true,
+///     reject_reason: None,
+/// };
+/// let mixing = [Amount::from_sat(50_000)];
+///
+/// // Two mixing outputs, their change, split taker change and a payment of 30,000
+/// let cj = psbt(&[200_000], &[50_000, 50_000, 20_000, 19_000, 18_000, 30_000]);
+/// let checked = check_uniformity(tx_info.clone(), &cj, &mixing, None, Amount::ZERO);
+/// assert!(!checked.verifyed);
+/// assert_eq!(
+///     checked.reject_reason,
+///     Some(RejectReason::NonuniformOutputs { mixing: 2, other: 4 })
+/// );
+///
+/// let payment = Some(Amount::from_sat(30_000));
+/// assert!(check_uniformity(tx_info, &cj, &mixing, payment, Amount::ZERO).verifyed);
+/// ```
+pub fn check_uniformity(
+    mut tx_info: VerifyCJInfo,
+    psbt: &PartiallySignedTransaction,
+    mixing: &[Amount],
+    payment: Option<Amount>,
+    tolerance: Amount,
+) -> VerifyCJInfo {
+    if tx_info.reject_reason.is_some() {
+        return tx_info;
+    }
+    let (mixing_outputs, mut other): (Vec<_>, Vec<_>) = psbt
+        .unsigned_tx
+        .output
+        .iter()
+        .partition(|output| is_mixing(output.value, mixing, tolerance));
+
+    if let Some(payment) = payment {
+        let count = other
+            .iter()
+            .filter(|output| output.value == payment.to_sat())
+            .count();
+        if count != 1 {
+            tx_info.verifyed = false;
+            tx_info.reject_reason = Some(RejectReason::PaymentOutputs { count });
+            return tx_info;
+        }
+        other.retain(|output| output.value != payment.to_sat());
+    }
+    if other.len() > mixing_outputs.len() + 1 {
+        tx_info.verifyed = false;
+        tx_info.reject_reason = Some(RejectReason::NonuniformOutputs {
+            mixing: mixing_outputs.len(),
+            other: other.len(),
+        });
+    }
+    tx_info
+}
+
+/// Script of the one output of the payment value, the external destination of a payment round
+pub fn payment_script(
+    psbt: &PartiallySignedTransaction,
+    payment: Option<Amount>,
+) -> Option<&Script> {
+    let payment = payment?.to_sat();
+    let mut outputs = psbt
+        .unsigned_tx
+        .output
+        .iter()
+        .filter(|output| output.value == payment);
+    match (outputs.next(), outputs.next()) {
+        (Some(output), None) => Some(&output.script_pubkey),
+        _ => None,
+    }
+}
+
+/// Whether every output pays a standard script, scripts do not encode a network so any is used
+pub fn standard_outputs(psbt: &PartiallySignedTransaction) -> bool {
+    psbt.unsigned_tx.output.iter().all(|output| {
+        Address::from_script(&output.script_pubkey, Network::Bitcoin)
+            .and_then(|address| address.address_type())
+            .is_some()
+    })
+}
+
+/// Whether every output but the payment output to an external address is of one script type
+/// ```
+/// use bdk::bitcoin::Address;
+/// use nostrdizer::{policy::output_types_match, test_utils::psbt};
+/// use std::str::FromStr;
+///
+/// let wpkh = Address::from_str("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4").unwrap();
+/// let mut cj = psbt(&[200_000], &[50_000, 50_000, 99_000]);
+/// for output in cj.unsigned_tx.output.iter_mut() {
+///     output.script_pubkey = wpkh.script_pubkey();
+/// }
+/// assert!(output_types_match(&cj, None));
+///
+/// // Change to a p2sh address among p2wpkh outputs
+/// let sh = Address::from_str("3J98t1WpEZ73CNmQviecrnyiWrnqRhWNLy").unwrap();
+/// cj.unsigned_tx.output[2].script_pubkey = sh.script_pubkey();
+/// assert!(!output_types_match(&cj, None));
+/// assert!(output_types_match(&cj, Some(&sh.script_pubkey())));
+/// ```
+pub fn output_types_match(psbt: &PartiallySignedTransaction, payment: Option<&Script>) -> bool {
+    let types: HashSet<Option<DescriptorType>> = psbt
+        .unsigned_tx
+        .output
+        .iter()
+        .filter(|output| Some(&output.script_pubkey) != payment)
+        .map(|output| DescriptorType::of_output(&output.script_pubkey))
+        .collect();
+    types.len() <= 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_utils::{fill, psbt},
+        types::{Denomination, SignedAmount},
+    };
+    use std::str::FromStr;
+
+    fn tx_info() -> VerifyCJInfo {
+        VerifyCJInfo {
+            mining_fee: SignedAmount::from_sat(500),
+            maker_fee: SignedAmount::from_sat(500),
+            fee_rate: 1.0,
+            verifyed: true,
+            reject_reason: None,
+        }
+    }
+
+    #[test]
+    fn test_dust_forwarding_rejected() {
+        let policy = RoundPolicy::default();
+
+        // Two makers with change just above dust
+        let psbt_ok = psbt(&[200_000], &[50_000, 50_000, 50_000, 600, 700, 48_000]);
+        assert!(policy.check_cj(tx_info(), &psbt_ok, &fill(50_000)).verifyed);
+
+        let spam = psbt(&[200_000], &[50_000, 50_000, 600, 600, 600, 600, 97_000]);
+        let checked = policy.check_cj(tx_info(), &spam, &fill(50_000));
+        assert!(!checked.verifyed);
+        assert_eq!(
+            checked.reject_reason,
+            Some(RejectReason::DustOutputs { count: 4 })
+        );
+    }
+
+    #[test]
+    fn test_nonstandard_output_rejected() {
+        let wpkh = Address::from_str("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4").unwrap();
+        let mut cj = psbt(&[200_000], &[50_000, 50_000, 99_000]);
+        for output in cj.unsigned_tx.output.iter_mut() {
+            output.script_pubkey = wpkh.script_pubkey();
+        }
+        assert!(
+            RoundPolicy::default()
+                .check_cj(tx_info(), &cj, &fill(50_000))
+                .verifyed
+        );
+
+        // Witness v0 program of neither 20 nor 32 bytes can never be spent
+        cj.unsigned_tx.output[1].script_pubkey = Script::from(vec![0x00, 0x02, 0x01, 0x02]);
+        let checked = RoundPolicy::default().check_cj(tx_info(), &cj, &fill(50_000));
+        assert!(!checked.verifyed);
+        assert_eq!(
+            checked.reject_reason,
+            Some(RejectReason::NonstandardOutputs)
+        );
+    }
+
+    #[test]
+    fn test_payment_round() {
+        let wpkh = Address::from_str("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4").unwrap();
+        let sh = Address::from_str("3J98t1WpEZ73CNmQviecrnyiWrnqRhWNLy").unwrap();
+        let mut cj = psbt(
+            &[200_000],
+            &[50_000, 50_000, 20_000, 19_000, 18_000, 30_000],
+        );
+        for output in cj.unsigned_tx.output.iter_mut() {
+            output.script_pubkey = wpkh.script_pubkey();
+        }
+        // Payment to an address of another type
+        cj.unsigned_tx.output[5].script_pubkey = sh.script_pubkey();
+
+        let mut payment_fill = fill(50_000);
+        payment_fill.payment = Some(Amount::from_sat(30_000));
+        let policy = RoundPolicy::default();
+        assert!(policy.check_cj(tx_info(), &cj, &payment_fill).verifyed);
+        assert_eq!(
+            policy.check_cj(tx_info(), &cj, &fill(50_000)).reject_reason,
+            Some(RejectReason::NonuniformOutputs {
+                mixing: 2,
+                other: 4
+            })
+        );
+
+        // The payment output has to be the only one of its value
+        cj.unsigned_tx.output[3].value = 30_000;
+        assert_eq!(
+            policy.check_cj(tx_info(), &cj, &payment_fill).reject_reason,
+            Some(RejectReason::PaymentOutputs { count: 2 })
+        );
+
+        // Outputs off the CJ amount count as mixing outputs within the tolerance
+        let cj = psbt(&[200_000], &[50_000, 49_990, 20_000, 19_000, 18_000]);
+        let uniform = |tolerance| {
+            check_uniformity(
+                tx_info(),
+                &cj,
+                &[Amount::from_sat(50_000)],
+                None,
+                Amount::from_sat(tolerance),
+            )
+            .verifyed
+        };
+        assert!(!uniform(0));
+        assert!(uniform(10));
+    }
+
+    #[test]
+    fn test_small_denomination_rejected() {
+        let mut fill = fill(105_000);
+        fill.denominations = vec![
+            Denomination {
+                amount: Amount::from_sat(100_000),
+                count: 1,
+            },
+            Denomination {
+                amount: Amount::from_sat(5_000),
+                count: 1,
+            },
+        ];
+
+        assert_eq!(
+            RoundPolicy::default().check_fill(&fill),
+            Some(RejectReason::BelowPrivacyFloor {
+                amount: Amount::from_sat(5_000)
+            })
+        );
+    }
+}
@@ -0,0 +1,395 @@
+use crate::errors::Error;
+
+use bdk::bitcoin::{
+    consensus::encode::{deserialize, serialize},
+    psbt::PartiallySignedTransaction,
+    OutPoint, PackedLockTime, Script, Sequence, Transaction, TxIn, TxOut, Txid, Witness,
+};
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Every PSBT starts with it, BIP-174
+const MAGIC: &[u8] = b"psbt\xff";
+
+// Key types of the fields v2 adds and v0 keeps in the unsigned transaction instead, BIP-370
+const GLOBAL_UNSIGNED_TX: u8 = 0x00;
+const GLOBAL_TX_VERSION: u8 = 0x02;
+const GLOBAL_FALLBACK_LOCKTIME: u8 = 0x03;
+const GLOBAL_INPUT_COUNT: u8 = 0x04;
+const GLOBAL_OUTPUT_COUNT: u8 = 0x05;
+const GLOBAL_TX_MODIFIABLE: u8 = 0x06;
+const GLOBAL_VERSION: u8 = 0xfb;
+const IN_PREVIOUS_TXID: u8 = 0x0e;
+const IN_OUTPUT_INDEX: u8 = 0x0f;
+const IN_SEQUENCE: u8 = 0x10;
+const IN_REQUIRED_TIME_LOCKTIME: u8 = 0x11;
+const IN_REQUIRED_HEIGHT_LOCKTIME: u8 = 0x12;
+const OUT_AMOUNT: u8 = 0x03;
+const OUT_SCRIPT: u8 = 0x04;
+
+/// Key value pairs of one map of a PSBT, keys start with their type
+type Map = Vec<(Vec<u8>, Vec<u8>)>;
+
+/// Serializes the PSBT as version 2, the unsigned transaction is split into the fields of its inputs and outputs
+/// ```
+/// use nostrdizer::{psbt_v2, test_utils::psbt};
+///
+/// let cj = psbt(&[60_000], &[50_000, 9_000]);
+/// let v2 = psbt_v2::to_v2(&cj).unwrap();
+/// assert!(psbt_v2::is_v2(&v2));
+/// assert_eq!(psbt_v2::from_v2(&v2).unwrap(), cj);
+/// ```
+pub fn to_v2(psbt: &PartiallySignedTransaction) -> Result<Vec<u8>, Error> {
+    let tx = &psbt.unsigned_tx;
+    let mut maps = read_maps(&serialize(psbt))?;
+    let (global, rest) = maps.split_at_mut(1);
+    let (inputs, outputs) = rest.split_at_mut(tx.input.len());
+
+    let global = &mut global[0];
+    global.retain(|(key, _)| !matches!(key[..], [GLOBAL_UNSIGNED_TX] | [GLOBAL_VERSION]));
+    global.push((vec![GLOBAL_TX_VERSION], tx.version.to_le_bytes().to_vec()));
+    global.push((
+        vec![GLOBAL_FALLBACK_LOCKTIME],
+        tx.lock_time.0.to_le_bytes().to_vec(),
+    ));
+    global.push((vec![GLOBAL_INPUT_COUNT], compact_size(tx.input.len())));
+    global.push((vec![GLOBAL_OUTPUT_COUNT], compact_size(tx.output.len())));
+    global.push((vec![GLOBAL_VERSION], 2u32.to_le_bytes().to_vec()));
+
+    for (map, input) in inputs.iter_mut().zip(&tx.input) {
+        map.push((
+            vec![IN_PREVIOUS_TXID],
+            serialize(&input.previous_output.txid),
+        ));
+        map.push((
+            vec![IN_OUTPUT_INDEX],
+            input.previous_output.vout.to_le_bytes().to_vec(),
+        ));
+        map.push((vec![IN_SEQUENCE], input.sequence.0.to_le_bytes().to_vec()));
+    }
+    for (map, output) in outputs.iter_mut().zip(&tx.output) {
+        map.push((
+            vec![OUT_AMOUNT],
+            (output.value as i64).to_le_bytes().to_vec(),
+        ));
+        map.push((vec![OUT_SCRIPT], output.script_pubkey.to_bytes()));
+    }
+    Ok(write_maps(&maps))
+}
+
+/// Parses a version 2 PSBT, rebuilding the unsigned transaction of version 0 from its fields
+pub fn from_v2(bytes: &[u8]) -> Result<PartiallySignedTransaction, Error> {
+    let mut maps = read_maps(bytes)?;
+    let global = &maps[0];
+    if field(global, GLOBAL_VERSION).map(le::<4>).transpose()? != Some(2u32.to_le_bytes()) {
+        return Err(Error::DecodeError("PSBT is not version 2".to_string()));
+    }
+    let count = |key_type| match field(global, key_type) {
+        Some(value) => read_compact_size(&mut &value[..]),
+        None => Err(missing(key_type)),
+    };
+    let (input_count, output_count) = (count(GLOBAL_INPUT_COUNT)?, count(GLOBAL_OUTPUT_COUNT)?);
+    if maps.len() != 1 + input_count + output_count {
+        return Err(Error::DecodeError(format!(
+            "PSBT has {} maps for {input_count} inputs and {output_count} outputs",
+            maps.len()
+        )));
+    }
+    let version = match field(global, GLOBAL_TX_VERSION) {
+        Some(value) => i32::from_le_bytes(le(value)?),
+        None => return Err(missing(GLOBAL_TX_VERSION)),
+    };
+    let fallback = match field(global, GLOBAL_FALLBACK_LOCKTIME) {
+        Some(value) => u32::from_le_bytes(le(value)?),
+        None => 0,
+    };
+
+    let mut input = Vec::with_capacity(input_count);
+    let mut locks = Vec::with_capacity(input_count);
+    for map in &maps[1..=input_count] {
+        let txid: Txid = match field(map, IN_PREVIOUS_TXID) {
+            Some(value) => deserialize(value)
+                .map_err(|err| Error::DecodeError(format!("Previous txid: {err}")))?,
+            None => return Err(missing(IN_PREVIOUS_TXID)),
+        };
+        let vout = match field(map, IN_OUTPUT_INDEX) {
+            Some(value) => u32::from_le_bytes(le(value)?),
+            None => return Err(missing(IN_OUTPUT_INDEX)),
+        };
+        let sequence = match field(map, IN_SEQUENCE) {
+            Some(value) => Sequence(u32::from_le_bytes(le(value)?)),
+            None => Sequence::MAX,
+        };
+        let lock = |key_type| {
+            field(map, key_type)
+                .map(|value| le(value).map(u32::from_le_bytes))
+                .transpose()
+        };
+        locks.push((
+            lock(IN_REQUIRED_TIME_LOCKTIME)?,
+            lock(IN_REQUIRED_HEIGHT_LOCKTIME)?,
+        ));
+        input.push(TxIn {
+            previous_output: OutPoint { txid, vout },
+            script_sig: Script::new(),
+            sequence,
+            witness: Witness::new(),
+        });
+    }
+    let mut output = Vec::with_capacity(output_count);
+    for map in &maps[1 + input_count..] {
+        let value = match field(map, OUT_AMOUNT) {
+            Some(value) => i64::from_le_bytes(le(value)?),
+            None => return Err(missing(OUT_AMOUNT)),
+        };
+        let script_pubkey = match field(map, OUT_SCRIPT) {
+            Some(value) => Script::from(value.to_vec()),
+            None => return Err(missing(OUT_SCRIPT)),
+        };
+        output.push(TxOut {
+            value: u64::try_from(value)
+                .map_err(|_| Error::DecodeError(format!("Negative output amount {value}")))?,
+            script_pubkey,
+        });
+    }
+    let tx = Transaction {
+        version,
+        lock_time: PackedLockTime(lock_time(&locks, fallback)?),
+        input,
+        output,
+    };
+
+    let (global, rest) = maps.split_at_mut(1);
+    let (inputs, outputs) = rest.split_at_mut(input_count);
+    global[0].retain(|(key, _)| {
+        !matches!(
+            key[..],
+            [GLOBAL_TX_VERSION]
+                | [GLOBAL_FALLBACK_LOCKTIME]
+                | [GLOBAL_INPUT_COUNT]
+                | [GLOBAL_OUTPUT_COUNT]
+                | [GLOBAL_TX_MODIFIABLE]
+                | [GLOBAL_VERSION]
+        )
+    });
+    global[0].insert(0, (vec![GLOBAL_UNSIGNED_TX], serialize(&tx)));
+    for map in inputs {
+        map.retain(|(key, _)| {
+            !matches!(
+                key[..],
+                [IN_PREVIOUS_TXID]
+                    | [IN_OUTPUT_INDEX]
+                    | [IN_SEQUENCE]
+                    | [IN_REQUIRED_TIME_LOCKTIME]
+                    | [IN_REQUIRED_HEIGHT_LOCKTIME]
+            )
+        });
+    }
+    for map in outputs {
+        map.retain(|(key, _)| !matches!(key[..], [OUT_AMOUNT] | [OUT_SCRIPT]));
+    }
+    deserialize(&write_maps(&maps)).map_err(|err| Error::DecodeError(err.to_string()))
+}
+
+/// Whether the bytes are a PSBT of version 2
+pub fn is_v2(bytes: &[u8]) -> bool {
+    match read_maps(bytes) {
+        Ok(maps) => field(&maps[0], GLOBAL_VERSION) == Some(&2u32.to_le_bytes()[..]),
+        Err(_) => false,
+    }
+}
+
+/// Parses a PSBT of version 0 or 2
+pub fn parse(bytes: &[u8]) -> Result<PartiallySignedTransaction, Error> {
+    match is_v2(bytes) {
+        true => from_v2(bytes),
+        false => deserialize(bytes).map_err(|err| Error::DecodeError(err.to_string())),
+    }
+}
+
+/// Writes the PSBT to dir as base64 both as version 0 and 2, named by its txid
+/// Returns the paths of the version 0 and 2 files
+pub fn archive(psbt: &PartiallySignedTransaction, dir: &Path) -> Result<(PathBuf, PathBuf), Error> {
+    fs::create_dir_all(dir)?;
+    let txid = psbt.unsigned_tx.txid();
+    let (v0_path, v2_path) = (
+        dir.join(format!("{txid}.psbt")),
+        dir.join(format!("{txid}.v2.psbt")),
+    );
+    fs::write(&v0_path, psbt.to_string())?;
+    fs::write(&v2_path, base64::encode(to_v2(psbt)?))?;
+    Ok((v0_path, v2_path))
+}
+
+/// Lock time of the transaction, the latest any input requires of the type every input with one allows
+/// Heights are preferred when all inputs allow both, the fallback is used when none requires one
+fn lock_time(locks: &[(Option<u32>, Option<u32>)], fallback: u32) -> Result<u32, Error> {
+    let locks: Vec<_> = locks
+        .iter()
+        .filter(|(time, height)| time.is_some() || height.is_some())
+        .collect();
+    let heights: Option<Vec<u32>> = locks.iter().map(|(_, height)| *height).collect();
+    let times: Option<Vec<u32>> = locks.iter().map(|(time, _)| *time).collect();
+    match (heights, times) {
+        (Some(heights), _) => Ok(heights.into_iter().max().unwrap_or(fallback)),
+        (None, Some(times)) => Ok(times.into_iter().max().unwrap_or(fallback)),
+        (None, None) => Err(Error::DecodeError(
+            "Inputs require both a height and a time lock time".to_string(),
+        )),
+    }
+}
+
+fn missing(key_type: u8) -> Error {
+    Error::DecodeError(format!("PSBT is missing field {key_type:#04x}"))
+}
+
+/// Value of the field of the key type, fields with key data are never looked up
+fn field(map: &Map, key_type: u8) -> Option<&[u8]> {
+    map.iter()
+        .find(|(key, _)| key[..] == [key_type])
+        .map(|(_, value)| &value[..])
+}
+
+fn le<const N: usize>(value: &[u8]) -> Result<[u8; N], Error> {
+    value
+        .try_into()
+        .map_err(|_| Error::DecodeError(format!("Field of {} bytes, {N} expected", value.len())))
+}
+
+/// Splits a serialized PSBT into its global map followed by the map of each input and output
+fn read_maps(bytes: &[u8]) -> Result<Vec<Map>, Error> {
+    let mut bytes = match bytes.strip_prefix(MAGIC) {
+        Some(bytes) => bytes,
+        None => return Err(Error::DecodeError("Not a PSBT".to_string())),
+    };
+    let mut maps = vec![];
+    let mut map = Map::new();
+    while !bytes.is_empty() {
+        let key_len = read_compact_size(&mut bytes)?;
+        // A key of no length ends the map
+        if key_len == 0 {
+            maps.push(std::mem::take(&mut map));
+            continue;
+        }
+        let key = take(&mut bytes, key_len)?;
+        let value_len = read_compact_size(&mut bytes)?;
+        map.push((key, take(&mut bytes, value_len)?));
+    }
+    if !map.is_empty() || maps.is_empty() {
+        return Err(Error::DecodeError("PSBT ends within a map".to_string()));
+    }
+    Ok(maps)
+}
+
+fn write_maps(maps: &[Map]) -> Vec<u8> {
+    let mut bytes = MAGIC.to_vec();
+    for map in maps {
+        for (key, value) in map {
+            bytes.extend(compact_size(key.len()));
+            bytes.extend(key);
+            bytes.extend(compact_size(value.len()));
+            bytes.extend(value);
+        }
+        bytes.push(0x00);
+    }
+    bytes
+}
+
+fn take(bytes: &mut &[u8], len: usize) -> Result<Vec<u8>, Error> {
+    if bytes.len() < len {
+        return Err(Error::DecodeError("PSBT ends within a field".to_string()));
+    }
+    let (taken, rest) = bytes.split_at(len);
+    *bytes = rest;
+    Ok(taken.to_vec())
+}
+
+fn compact_size(n: usize) -> Vec<u8> {
+    match n {
+        0..=0xfc => vec![n as u8],
+        0xfd..=0xffff => [&[0xfd][..], &(n as u16).to_le_bytes()].concat(),
+        0x1_0000..=0xffff_ffff => [&[0xfe][..], &(n as u32).to_le_bytes()].concat(),
+        _ => [&[0xff][..], &(n as u64).to_le_bytes()].concat(),
+    }
+}
+
+fn read_compact_size(bytes: &mut &[u8]) -> Result<usize, Error> {
+    let prefix = take(bytes, 1)?[0];
+    let n = match prefix {
+        0xfd => u16::from_le_bytes(le(&take(bytes, 2)?)?) as u64,
+        0xfe => u32::from_le_bytes(le(&take(bytes, 4)?)?) as u64,
+        0xff => u64::from_le_bytes(le(&take(bytes, 8)?)?),
+        n => n as u64,
+    };
+    usize::try_from(n).map_err(|_| Error::DecodeError(format!("Length {n} too large")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::psbt;
+
+    use bdk::bitcoin::hashes::Hash;
+
+    #[test]
+    fn test_round_trip() {
+        let mut cj = psbt(&[60_000, 40_000], &[50_000, 49_000]);
+        cj.unsigned_tx.lock_time = PackedLockTime(760_000);
+        cj.unsigned_tx.input[1].previous_output.txid = Txid::from_slice(&[7; 32]).unwrap();
+        cj.unsigned_tx.input[1].sequence = Sequence::ENABLE_RBF_NO_LOCKTIME;
+        cj.unsigned_tx.output[0].script_pubkey = Script::from(vec![0x51]);
+        cj.inputs[0].final_script_witness = Some(Witness::from_vec(vec![vec![1; 72]]));
+
+        let v2 = to_v2(&cj).unwrap();
+        let maps = read_maps(&v2).unwrap();
+        assert_eq!(maps.len(), 5);
+        assert!(field(&maps[0], GLOBAL_UNSIGNED_TX).is_none());
+        assert_eq!(field(&maps[0], GLOBAL_INPUT_COUNT), Some(&[2][..]));
+        assert_eq!(
+            field(&maps[2], IN_PREVIOUS_TXID),
+            Some(&[7; 32][..]),
+            "txid is in the byte order of the transaction"
+        );
+        assert_eq!(field(&maps[3], OUT_SCRIPT), Some(&[0x51][..]));
+
+        assert!(is_v2(&v2));
+        assert!(!is_v2(&serialize(&cj)));
+        assert_eq!(from_v2(&v2).unwrap(), cj);
+        assert_eq!(parse(&v2).unwrap(), parse(&serialize(&cj)).unwrap());
+        assert!(from_v2(&serialize(&cj)).is_err());
+    }
+
+    #[test]
+    fn test_lock_time() {
+        assert_eq!(lock_time(&[(None, None)], 5).unwrap(), 5);
+        // Heights when every input allows them
+        assert_eq!(
+            lock_time(
+                &[(Some(1_700_000_000), Some(800_000)), (None, Some(800_100))],
+                0
+            )
+            .unwrap(),
+            800_100
+        );
+        assert_eq!(
+            lock_time(
+                &[
+                    (Some(1_700_000_000), None),
+                    (Some(1_700_000_100), Some(800_000))
+                ],
+                0
+            )
+            .unwrap(),
+            1_700_000_100
+        );
+        assert!(lock_time(&[(Some(1_700_000_000), None), (None, Some(800_000))], 0).is_err());
+    }
+
+    #[test]
+    fn test_truncated() {
+        let v2 = to_v2(&psbt(&[60_000], &[50_000])).unwrap();
+        assert!(from_v2(&v2[..v2.len() - 1]).is_err());
+        assert!(!is_v2(&v2[..v2.len() - 3]));
+    }
+}
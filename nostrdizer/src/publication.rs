@@ -0,0 +1,236 @@
+use crate::errors::Error;
+
+use log::debug;
+use nostr_rust::{events::Event, nostr_client::Client as NostrClient};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Relays that have to accept a critical message before the step it starts is done
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PublishQuorum {
+    /// Relays that have to answer OK, capped at the relays connected to
+    pub relays: usize,
+    /// Seconds to wait for OKs before publishing again
+    pub timeout: u64,
+    /// Times the message is published before giving up
+    pub attempts: u32,
+}
+
+impl Default for PublishQuorum {
+    fn default() -> Self {
+        Self {
+            relays: 1,
+            timeout: 10,
+            attempts: 3,
+        }
+    }
+}
+
+/// What a relay answered to an event
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RelayStatus {
+    Pending,
+    Accepted,
+    Rejected(String),
+}
+
+/// Answer of each relay to a published event
+/// ```
+/// use nostrdizer::publication::{Publication, RelayStatus};
+/// use serde_json::json;
+///
+/// let relays = vec!["wss://a".to_string(), "wss://b".to_string()];
+/// let mut publication = Publication::new("id", &relays);
+/// publication.record("wss://a", &json!(["OK", "id", true, ""]));
+/// publication.record("wss://b", &json!(["OK", "other", true, ""]));
+///
+/// assert!(publication.reached(1));
+/// assert!(!publication.reached(2));
+/// assert_eq!(publication.pending(), vec!["wss://b".to_string()]);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Publication {
+    pub event_id: String,
+    pub statuses: HashMap<String, RelayStatus>,
+}
+
+impl Publication {
+    pub fn new(event_id: &str, relays: &[String]) -> Self {
+        Self {
+            event_id: event_id.to_string(),
+            statuses: relays
+                .iter()
+                .map(|relay| (relay.clone(), RelayStatus::Pending))
+                .collect(),
+        }
+    }
+
+    /// Records an `OK` message of relay, other messages are ignored
+    pub fn record(&mut self, relay: &str, message: &Value) {
+        if message[0] != "OK" || message[1].as_str() != Some(&self.event_id) {
+            return;
+        }
+        let status = match message[2].as_bool() {
+            Some(true) => RelayStatus::Accepted,
+            _ => RelayStatus::Rejected(message[3].as_str().unwrap_or_default().to_string()),
+        };
+        if let Some(current) = self.statuses.get_mut(relay) {
+            // A relay that accepted may answer a republished event with a rejection as a duplicate
+            if *current != RelayStatus::Accepted {
+                *current = status;
+            }
+        }
+    }
+
+    pub fn accepted(&self) -> usize {
+        self.statuses
+            .values()
+            .filter(|status| **status == RelayStatus::Accepted)
+            .count()
+    }
+
+    /// Relays that have not answered, sorted
+    pub fn pending(&self) -> Vec<String> {
+        let mut pending: Vec<String> = self
+            .statuses
+            .iter()
+            .filter(|(_, status)| **status == RelayStatus::Pending)
+            .map(|(relay, _)| relay.clone())
+            .collect();
+        pending.sort();
+        pending
+    }
+
+    /// Relays that turned the event down until the client authenticates with NIP-42, sorted
+    pub fn auth_required(&self) -> Vec<String> {
+        let mut relays: Vec<String> = self
+            .statuses
+            .iter()
+            .filter(|(_, status)| {
+                matches!(status, RelayStatus::Rejected(reason) if reason.starts_with("auth-required:"))
+            })
+            .map(|(relay, _)| relay.clone())
+            .collect();
+        relays.sort();
+        relays
+    }
+
+    /// Whether quorum relays accepted, or every relay when there are fewer
+    pub fn reached(&self, quorum: usize) -> bool {
+        self.accepted() >= quorum.min(self.statuses.len())
+    }
+}
+
+/// Publishes critical messages until a quorum of relays accepts them
+/// Events that reached quorum before every relay answered are published again with the next message
+#[derive(Debug, Clone, Default)]
+pub struct Publisher {
+    behind: Vec<(Event, Publication)>,
+}
+
+impl Publisher {
+    pub fn publish(
+        &mut self,
+        nostr_client: &mut NostrClient,
+        relays: &[String],
+        quorum: &PublishQuorum,
+        event: &Event,
+    ) -> Result<Publication, Error> {
+        let mut publication = Publication::new(&event.id, relays);
+        let mut behind = std::mem::take(&mut self.behind);
+        for (event, _) in &behind {
+            nostr_client.publish_event(event)?;
+        }
+
+        for attempt in 1..=quorum.attempts.max(1) {
+            nostr_client.publish_event(event)?;
+            let started = Instant::now();
+            while !publication.reached(quorum.relays)
+                && started.elapsed() < Duration::from_secs(quorum.timeout)
+            {
+                for (relay, message) in nostr_client.next_data()? {
+                    if let Ok(message) = serde_json::from_str::<Value>(&message.to_string()) {
+                        publication.record(&relay, &message);
+                        for (_, earlier) in behind.iter_mut() {
+                            earlier.record(&relay, &message);
+                        }
+                    }
+                }
+            }
+            debug!(
+                "Event {} attempt {attempt}: {:?}",
+                event.id, publication.statuses
+            );
+            if publication.reached(quorum.relays) {
+                break;
+            }
+        }
+
+        behind.retain(|(_, earlier)| !earlier.pending().is_empty());
+        if !publication.reached(quorum.relays) {
+            self.behind = behind;
+            let auth_required = publication.auth_required();
+            if !auth_required.is_empty() {
+                return Err(Error::RelayAuthRequired(auth_required));
+            }
+            return Err(Error::PublishQuorum(
+                publication.accepted(),
+                quorum.relays.min(relays.len()),
+            ));
+        }
+        if !publication.pending().is_empty() {
+            behind.push((event.clone(), publication.clone()));
+        }
+        self.behind = behind;
+        Ok(publication)
+    }
+
+    /// Events that reached quorum but some relays have not answered
+    pub fn behind(&self) -> Vec<&Publication> {
+        self.behind
+            .iter()
+            .map(|(_, publication)| publication)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_duplicate_does_not_undo_accept() {
+        let relays = vec!["wss://a".to_string(), "wss://b".to_string()];
+        let mut publication = Publication::new("id", &relays);
+        publication.record("wss://a", &json!(["OK", "id", true, ""]));
+        publication.record("wss://a", &json!(["OK", "id", false, "duplicate:"]));
+        publication.record("wss://b", &json!(["OK", "id", false, "blocked:"]));
+
+        assert_eq!(publication.accepted(), 1);
+        assert!(publication.pending().is_empty());
+        assert_eq!(
+            publication.statuses["wss://b"],
+            RelayStatus::Rejected("blocked:".to_string())
+        );
+        // Quorum is capped at the relays connected to
+        assert!(!Publication::new("id", &relays).reached(5));
+        assert!(publication.reached(1));
+    }
+
+    #[test]
+    fn test_auth_required() {
+        let relays = vec!["wss://a".to_string(), "wss://b".to_string()];
+        let mut publication = Publication::new("id", &relays);
+        publication.record(
+            "wss://a",
+            &json!(["OK", "id", false, "auth-required: paid relay"]),
+        );
+        publication.record("wss://b", &json!(["OK", "id", false, "blocked:"]));
+
+        assert_eq!(publication.auth_required(), vec!["wss://a".to_string()]);
+    }
+}
@@ -0,0 +1,209 @@
+use crate::{
+    errors::Error,
+    publication::{Publication, RelayStatus},
+};
+
+use log::debug;
+use nostr_rust::{
+    events::{Event, EventPrepare},
+    nostr_client::Client as NostrClient,
+    utils::get_timestamp,
+    Identity,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tungstenite::Message;
+
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// Kind of the event a client authenticates to a relay with, NIP-42
+pub const CLIENT_AUTH: u16 = 22242;
+
+/// Seconds to wait for relays to send a challenge and accept the answer to it
+pub const AUTH_TIMEOUT: u64 = 5;
+
+/// Key a relay is authenticated with
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthKey {
+    /// The nostr identity messages are signed with
+    Identity,
+    /// A hex secret key only used to authenticate, so the relay does not learn the identity
+    Key(String),
+}
+
+/// Relays that require NIP-42 AUTH before they accept events, and the key each is authenticated with
+/// Challenges of other relays are not answered
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct RelayAuthConfig {
+    #[serde(default)]
+    pub relays: HashMap<String, AuthKey>,
+}
+
+impl RelayAuthConfig {
+    /// Adds a relay given as `url`, authenticated with the identity, or `url=hex secret key`
+    /// ```
+    /// use nostrdizer::relay_auth::{AuthKey, RelayAuthConfig};
+    ///
+    /// let key = "4d1c1e5c1e5c1e5c1e5c1e5c1e5c1e5c1e5c1e5c1e5c1e5c1e5c1e5c1e5c1e5c";
+    /// let mut config = RelayAuthConfig::default();
+    /// config.add("wss://paid.relay").unwrap();
+    /// config.add(&format!("wss://other.relay?token=a={key}")).unwrap();
+    ///
+    /// assert_eq!(config.relays["wss://paid.relay"], AuthKey::Identity);
+    /// assert_eq!(config.relays["wss://other.relay?token=a"], AuthKey::Key(key.to_string()));
+    /// ```
+    pub fn add(&mut self, relay: &str) -> Result<(), Error> {
+        let (relay, key) = match relay.rsplit_once('=') {
+            Some((url, key)) if key.len() == 64 && key.chars().all(|c| c.is_ascii_hexdigit()) => {
+                Identity::from_str(key).map_err(|_| {
+                    Error::InvalidConfig(format!("AUTH key of {url} is not a hex secret key"))
+                })?;
+                (url, AuthKey::Key(key.to_string()))
+            }
+            _ => (relay, AuthKey::Identity),
+        };
+        self.relays.insert(relay.to_string(), key);
+        Ok(())
+    }
+}
+
+/// Challenge of an `AUTH` message from a relay
+/// ```
+/// use nostrdizer::relay_auth::challenge;
+/// use serde_json::json;
+///
+/// assert_eq!(challenge(&json!(["AUTH", "abc"])), Some("abc"));
+/// assert_eq!(challenge(&json!(["NOTICE", "abc"])), None);
+/// ```
+pub fn challenge(message: &Value) -> Option<&str> {
+    match message[0] == "AUTH" {
+        true => message[1].as_str(),
+        false => None,
+    }
+}
+
+/// Signed event answering the challenge of relay
+pub fn auth_event(identity: &Identity, relay: &str, challenge: &str) -> Event {
+    EventPrepare {
+        pub_key: identity.public_key_str.clone(),
+        created_at: get_timestamp(),
+        kind: CLIENT_AUTH,
+        tags: vec![
+            vec!["relay".to_string(), relay.to_string()],
+            vec!["challenge".to_string(), challenge.to_string()],
+        ],
+        content: "".to_string(),
+    }
+    .to_event(identity, 0)
+}
+
+/// Answers the challenges of the relays in the config the client is connected to
+/// Errors when one rejects the answer, relays that send no challenge in time are expected to ask when they need it
+pub fn authenticate(
+    client: &mut NostrClient,
+    identity: &Identity,
+    config: &RelayAuthConfig,
+) -> Result<(), Error> {
+    // AUTH sent to each relay, none until it challenges
+    let mut answers: HashMap<String, Option<Publication>> = config
+        .relays
+        .keys()
+        .filter(|relay| client.relays.contains_key(*relay))
+        .map(|relay| (relay.clone(), None))
+        .collect();
+    let answered = |answers: &HashMap<String, Option<Publication>>| {
+        answers
+            .values()
+            .all(|answer| matches!(answer, Some(publication) if publication.pending().is_empty()))
+    };
+
+    let started = Instant::now();
+    while !answered(&answers) && started.elapsed() < Duration::from_secs(AUTH_TIMEOUT) {
+        for (relay, message) in client.next_data()? {
+            let message = match serde_json::from_str::<Value>(&message.to_string()) {
+                Ok(message) => message,
+                Err(_) => continue,
+            };
+            let answer = match answers.get_mut(&relay) {
+                Some(answer) => answer,
+                None => continue,
+            };
+            if let Some(challenge) = challenge(&message) {
+                let event = match &config.relays[&relay] {
+                    AuthKey::Identity => auth_event(identity, &relay, challenge),
+                    AuthKey::Key(key) => auth_event(&Identity::from_str(key)?, &relay, challenge),
+                };
+                send_to(client, &relay, &json!(["AUTH", event]))?;
+                *answer = Some(Publication::new(&event.id, &[relay.clone()]));
+                continue;
+            }
+            if let Some(publication) = answer {
+                publication.record(&relay, &message);
+                if let Some(RelayStatus::Rejected(reason)) = publication.statuses.get(&relay) {
+                    return Err(Error::RelayAuthRejected(relay, reason.clone()));
+                }
+            }
+        }
+    }
+
+    for (relay, answer) in answers {
+        match answer {
+            Some(publication) if !publication.pending().is_empty() => {
+                return Err(Error::RelayAuthRejected(relay, "no answer".to_string()))
+            }
+            Some(_) => debug!("Authenticated to {relay}"),
+            None => debug!("{relay} sent no AUTH challenge"),
+        }
+    }
+    Ok(())
+}
+
+/// Sends a message to one of the relays of the client
+fn send_to(client: &mut NostrClient, relay: &str, message: &Value) -> Result<(), Error> {
+    let failed = |reason: String| Error::RelayAuthRejected(relay.to_string(), reason);
+    let socket = client
+        .relays
+        .get(relay)
+        .ok_or_else(|| failed("not connected".to_string()))?;
+    let mut socket = socket
+        .lock()
+        .map_err(|_| failed("connection poisoned".to_string()))?;
+    socket
+        .send_message(&Message::text(message.to_string()))
+        .map_err(|err| failed(format!("{err:?}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_auth_event() {
+        let identity =
+            Identity::from_str("4d1c1e5c1e5c1e5c1e5c1e5c1e5c1e5c1e5c1e5c1e5c1e5c1e5c1e5c1e5c1e5c")
+                .unwrap();
+        let event = auth_event(&identity, "wss://paid.relay", "challenge");
+
+        assert!(event.verify().is_ok());
+        assert_eq!(event.kind, CLIENT_AUTH);
+        assert_eq!(event.tags[0], vec!["relay", "wss://paid.relay"]);
+        assert_eq!(event.tags[1], vec!["challenge", "challenge"]);
+    }
+
+    #[test]
+    fn test_config_round_trip() {
+        let mut config = RelayAuthConfig::default();
+        config.add("wss://paid.relay").unwrap();
+        config
+            .add("wss://other.relay=4d1c1e5c1e5c1e5c1e5c1e5c1e5c1e5c1e5c1e5c1e5c1e5c1e5c1e5c1e5c1e5c")
+            .unwrap();
+        let json = serde_json::to_string(&config).unwrap();
+        assert_eq!(
+            serde_json::from_str::<RelayAuthConfig>(&json).unwrap(),
+            config
+        );
+    }
+}
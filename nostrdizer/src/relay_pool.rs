@@ -0,0 +1,296 @@
+use crate::{
+    errors::Error,
+    relay_auth::{self, RelayAuthConfig},
+};
+
+use nostr_rust::{events::Event, nostr_client::Client as NostrClient, Identity};
+use serde::{Deserialize, Serialize};
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// Session events kept to tell which relays delivered them, the oldest are dropped
+const MAX_TRACKED_EVENTS: usize = 1_000;
+
+/// Events a relay is expected to have delivered before it can be found dropping them
+const MIN_EXPECTED_EVENTS: u64 = 10;
+
+/// Share of expected events a relay has to deliver to not be dropping them
+const MIN_DELIVERY_RATIO: f64 = 0.5;
+
+/// What a set of relays is used for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RelayRole {
+    /// Publishing and querying offers, and the fill that starts a session
+    Offer,
+    /// Negotiating a CJ after the fill
+    Session,
+}
+
+/// Health of a relay from the data received from it
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RelayHealth {
+    pub messages: u64,
+    pub last_seen: Option<u64>,
+}
+
+/// How many of the events a relay was expected to deliver it did
+/// A relay is expected to deliver an event when it has delivered others of the same peer, so it is one the peer uses
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RelayDelivery {
+    pub relay: String,
+    pub delivered: u64,
+    pub missed: u64,
+    /// Missed so many events it is used for sessions only when no other relay is left
+    pub dropping: bool,
+}
+
+/// Events received and the relays that delivered each, by sending peer
+#[derive(Debug, Clone, Default)]
+struct DeliveryLog {
+    /// Event ids in the order they were first received
+    order: VecDeque<String>,
+    events: HashMap<String, (String, HashSet<String>)>,
+    /// Relays each peer's events came from
+    peer_relays: HashMap<String, HashSet<String>>,
+}
+
+/// Offer and session relays, with health tracked separately for each role
+/// ```
+/// use nostrdizer::relay_pool::{RelayPool, RelayRole};
+///
+/// let mut pool = RelayPool::new(
+///     vec!["wss://offers".to_string()],
+///     vec!["wss://offers".to_string(), "wss://session".to_string()],
+/// );
+/// pool.record_message(RelayRole::Session, "wss://offers", 10);
+///
+/// assert_eq!(pool.stale(RelayRole::Session, 20, 60), vec!["wss://session".to_string()]);
+/// assert_eq!(pool.stale(RelayRole::Offer, 20, 60), vec!["wss://offers".to_string()]);
+/// ```
+#[derive(Debug, Clone)]
+pub struct RelayPool {
+    pub offer_relays: Vec<String>,
+    pub session_relays: Vec<String>,
+    health: HashMap<(RelayRole, String), RelayHealth>,
+    deliveries: HashMap<RelayRole, DeliveryLog>,
+}
+
+impl RelayPool {
+    pub fn new(offer_relays: Vec<String>, session_relays: Vec<String>) -> Self {
+        Self {
+            offer_relays,
+            session_relays,
+            health: HashMap::new(),
+            deliveries: HashMap::new(),
+        }
+    }
+
+    pub fn relays(&self, role: RelayRole) -> &[String] {
+        match role {
+            RelayRole::Offer => &self.offer_relays,
+            RelayRole::Session => &self.session_relays,
+        }
+    }
+
+    /// Adds relays to a role
+    pub fn add_relays(&mut self, role: RelayRole, relays: &[String]) {
+        let role_relays = match role {
+            RelayRole::Offer => &mut self.offer_relays,
+            RelayRole::Session => &mut self.session_relays,
+        };
+        for relay in relays {
+            if !role_relays.contains(relay) {
+                role_relays.push(relay.clone());
+            }
+        }
+    }
+
+    /// Relays of a role that deliver the events sent on them, all of them if every one is dropping events
+    pub fn preferred(&self, role: RelayRole) -> Vec<String> {
+        let dropping = self.dropping(role);
+        let preferred: Vec<String> = self
+            .relays(role)
+            .iter()
+            .filter(|relay| !dropping.contains(relay))
+            .cloned()
+            .collect();
+        match preferred.is_empty() {
+            true => self.relays(role).to_vec(),
+            false => preferred,
+        }
+    }
+
+    /// Connects a client to the preferred relays of a role, authenticating to those that require it
+    pub fn connect(
+        &self,
+        role: RelayRole,
+        identity: &Identity,
+        auth: &RelayAuthConfig,
+    ) -> Result<NostrClient, Error> {
+        let relays = self.preferred(role);
+        let relays: Vec<&str> = relays.iter().map(|r| r.as_str()).collect();
+        let mut client = NostrClient::new(relays)?;
+        relay_auth::authenticate(&mut client, identity, auth)?;
+        Ok(client)
+    }
+
+    pub fn record_message(&mut self, role: RelayRole, relay: &str, at: u64) {
+        let health = self.health.entry((role, relay.to_string())).or_default();
+        health.messages += 1;
+        health.last_seen = Some(at);
+    }
+
+    /// Records that relay delivered a signed event, unsealed if it was sealed
+    pub fn record_event(&mut self, role: RelayRole, relay: &str, event: &Event) {
+        if event.verify().is_err() {
+            return;
+        }
+        let log = self.deliveries.entry(role).or_default();
+        if !log.events.contains_key(&event.id) {
+            log.order.push_back(event.id.clone());
+        }
+        log.events
+            .entry(event.id.clone())
+            .or_insert_with(|| (event.pub_key.clone(), HashSet::new()))
+            .1
+            .insert(relay.to_string());
+        log.peer_relays
+            .entry(event.pub_key.clone())
+            .or_default()
+            .insert(relay.to_string());
+
+        while log.order.len() > MAX_TRACKED_EVENTS {
+            if let Some(oldest) = log.order.pop_front() {
+                log.events.remove(&oldest);
+            }
+        }
+    }
+
+    /// Events each relay of a role delivered and missed
+    /// ```
+    /// use nostrdizer::relay_pool::{RelayPool, RelayRole};
+    /// # use nostr_rust::{events::EventPrepare, Identity};
+    /// # use std::str::FromStr;
+    /// # let identity = Identity::from_str("4d1c1e5c1e5c1e5c1e5c1e5c1e5c1e5c1e5c1e5c1e5c1e5c1e5c1e5c1e5c1e5c").unwrap();
+    /// # let event = |n: u64| EventPrepare {
+    /// #     pub_key: identity.public_key_str.clone(),
+    /// #     created_at: n,
+    /// #     kind: 128,
+    /// #     tags: vec![],
+    /// #     content: "".to_string(),
+    /// # }
+    /// # .to_event(&identity, 0);
+    ///
+    /// let relays = vec!["wss://good".to_string(), "wss://lossy".to_string()];
+    /// let mut pool = RelayPool::new(relays.clone(), relays);
+    /// for n in 0..10 {
+    ///     pool.record_event(RelayRole::Session, "wss://good", &event(n));
+    /// }
+    /// // Accepts the peer's events but only delivers one
+    /// pool.record_event(RelayRole::Session, "wss://lossy", &event(0));
+    ///
+    /// let delivery = pool.delivery(RelayRole::Session);
+    /// assert_eq!((delivery[1].delivered, delivery[1].missed), (1, 9));
+    /// assert_eq!(pool.dropping(RelayRole::Session), vec!["wss://lossy".to_string()]);
+    /// assert_eq!(pool.preferred(RelayRole::Session), vec!["wss://good".to_string()]);
+    /// ```
+    pub fn delivery(&self, role: RelayRole) -> Vec<RelayDelivery> {
+        let log = self.deliveries.get(&role);
+        self.relays(role)
+            .iter()
+            .map(|relay| {
+                let (mut delivered, mut missed) = (0, 0);
+                for (peer, relays) in log.iter().flat_map(|log| log.events.values()) {
+                    let expected = log
+                        .and_then(|log| log.peer_relays.get(peer))
+                        .map_or(false, |peer_relays| peer_relays.contains(relay));
+                    match (expected, relays.contains(relay)) {
+                        (_, true) => delivered += 1,
+                        (true, false) => missed += 1,
+                        (false, false) => (),
+                    }
+                }
+                let expected = delivered + missed;
+                RelayDelivery {
+                    relay: relay.clone(),
+                    delivered,
+                    missed,
+                    dropping: expected >= MIN_EXPECTED_EVENTS
+                        && (delivered as f64) < expected as f64 * MIN_DELIVERY_RATIO,
+                }
+            })
+            .collect()
+    }
+
+    /// Relays of a role that consistently fail to deliver the events of peers that use them
+    pub fn dropping(&self, role: RelayRole) -> Vec<String> {
+        self.delivery(role)
+            .into_iter()
+            .filter(|delivery| delivery.dropping)
+            .map(|delivery| delivery.relay)
+            .collect()
+    }
+
+    pub fn health(&self, role: RelayRole, relay: &str) -> RelayHealth {
+        self.health
+            .get(&(role, relay.to_string()))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Relays of a role that have not sent anything in `max_age` seconds
+    pub fn stale(&self, role: RelayRole, now: u64, max_age: u64) -> Vec<String> {
+        self.relays(role)
+            .iter()
+            .filter(|relay| match self.health(role, relay).last_seen {
+                Some(last_seen) => now.saturating_sub(last_seen) > max_age,
+                None => true,
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use nostr_rust::events::EventPrepare;
+    use std::str::FromStr;
+
+    fn event(identity: &Identity, created_at: u64) -> Event {
+        EventPrepare {
+            pub_key: identity.public_key_str.clone(),
+            created_at,
+            kind: 128,
+            tags: vec![],
+            content: "".to_string(),
+        }
+        .to_event(identity, 0)
+    }
+
+    #[test]
+    fn test_relays_of_other_peers_not_dropping() {
+        let alice = Identity::from_str(&"1".repeat(64)).unwrap();
+        let bob = Identity::from_str(&"2".repeat(64)).unwrap();
+        let relays = vec!["wss://alice".to_string(), "wss://bob".to_string()];
+        let mut pool = RelayPool::new(vec![], relays);
+
+        // Each peer only uses its own relay
+        for n in 0..20 {
+            pool.record_event(RelayRole::Session, "wss://alice", &event(&alice, n));
+            pool.record_event(RelayRole::Session, "wss://bob", &event(&bob, n));
+        }
+        let delivery = pool.delivery(RelayRole::Session);
+        assert!(delivery
+            .iter()
+            .all(|relay| relay.delivered == 20 && relay.missed == 0));
+        assert!(pool.dropping(RelayRole::Session).is_empty());
+
+        // Too few events to tell
+        let mut pool = RelayPool::new(vec![], vec!["wss://alice".to_string()]);
+        pool.record_event(RelayRole::Session, "wss://alice", &event(&alice, 0));
+        pool.record_event(RelayRole::Session, "wss://other", &event(&alice, 1));
+        assert!(pool.dropping(RelayRole::Session).is_empty());
+    }
+}
@@ -0,0 +1,220 @@
+use crate::errors::Error;
+
+use nostr_rust::utils::get_timestamp;
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::time::Instant;
+
+// Response times kept for each step of a maker, older ones are dropped
+const MAX_SAMPLES: usize = 100;
+
+/// Steps of a session the maker's response time is measured for
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Step {
+    /// Fill sent until the fill ack
+    FillAck,
+    /// Unsigned CJ sent until the signed CJ
+    Sign,
+}
+
+/// Response times and outcomes of the sessions with a maker
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct MakerRecord {
+    /// Response times in milliseconds of each step, oldest first
+    #[serde(default)]
+    pub latencies: HashMap<Step, Vec<u64>>,
+    /// Sessions that ended in a broadcast CJ with the maker in it
+    pub completed: u32,
+    /// Sessions the maker dropped out of after being sent auth
+    pub failed: u32,
+    /// When the last session with the maker ended, records from before retention was kept are pruned first
+    #[serde(default)]
+    pub last_session: u64,
+}
+
+/// How reliable a maker has been, from its record
+#[derive(Debug, Clone, PartialEq)]
+pub struct MakerQuality {
+    pub maker: String,
+    /// p95 fill ack time in milliseconds
+    pub p95_fill_ack: Option<u64>,
+    /// p95 signing time in milliseconds
+    pub p95_sign: Option<u64>,
+    /// Ratio of sessions completed, none before the first session
+    pub success_rate: Option<f64>,
+    pub completed: u32,
+    pub sessions: u32,
+}
+
+/// Response times and outcomes of makers the taker has had sessions with, kept between runs
+/// ```
+/// use nostrdizer::reputation::{Reputation, Step};
+///
+/// let mut reputation = Reputation::default();
+/// reputation.record_latency("slow", Step::Sign, 9_000);
+/// reputation.record_outcome("slow", true);
+/// reputation.record_outcome("slow", false);
+/// reputation.record_latency("fast", Step::Sign, 800);
+/// reputation.record_outcome("fast", true);
+///
+/// let ranked = reputation.ranked();
+/// assert_eq!(ranked[0].maker, "fast");
+/// assert_eq!(ranked[1].success_rate, Some(0.5));
+/// assert_eq!(ranked[1].p95_sign, Some(9_000));
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Reputation {
+    #[serde(default)]
+    pub makers: HashMap<String, MakerRecord>,
+}
+
+impl Reputation {
+    /// Loads reputation from path, an empty reputation if there is no file
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        match path.exists() {
+            true => Ok(serde_json::from_str(&fs::read_to_string(path)?)?),
+            false => Ok(Self::default()),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        Ok(fs::write(path, serde_json::to_string_pretty(self)?)?)
+    }
+
+    pub fn record_latency(&mut self, maker: &str, step: Step, millis: u64) {
+        let samples = self
+            .makers
+            .entry(maker.to_string())
+            .or_default()
+            .latencies
+            .entry(step)
+            .or_default();
+        samples.push(millis);
+        if samples.len() > MAX_SAMPLES {
+            samples.remove(0);
+        }
+    }
+
+    pub fn record_outcome(&mut self, maker: &str, completed: bool) {
+        let record = self.makers.entry(maker.to_string()).or_default();
+        match completed {
+            true => record.completed += 1,
+            false => record.failed += 1,
+        }
+        record.last_session = get_timestamp();
+    }
+
+    pub fn quality(&self, maker: &str) -> MakerQuality {
+        let record = self.makers.get(maker).cloned().unwrap_or_default();
+        let sessions = record.completed + record.failed;
+        let p95 = |step| {
+            record
+                .latencies
+                .get(&step)
+                .and_then(|samples| percentile(samples, 95))
+        };
+        MakerQuality {
+            maker: maker.to_string(),
+            p95_fill_ack: p95(Step::FillAck),
+            p95_sign: p95(Step::Sign),
+            success_rate: match sessions {
+                0 => None,
+                _ => Some(record.completed as f64 / sessions as f64),
+            },
+            completed: record.completed,
+            sessions,
+        }
+    }
+
+    /// Every maker on record, highest success rate first then fastest p95 signing time
+    pub fn ranked(&self) -> Vec<MakerQuality> {
+        let mut ranked: Vec<MakerQuality> = self
+            .makers
+            .keys()
+            .map(|maker| self.quality(maker))
+            .collect();
+        ranked.sort_by(|a, b| {
+            b.success_rate
+                .unwrap_or(0.0)
+                .total_cmp(&a.success_rate.unwrap_or(0.0))
+                .then(
+                    a.p95_sign
+                        .unwrap_or(u64::MAX)
+                        .cmp(&b.p95_sign.unwrap_or(u64::MAX)),
+                )
+                .then(a.maker.cmp(&b.maker))
+        });
+        ranked
+    }
+}
+
+/// Nearest rank percentile of samples, none if there are no samples
+/// ```
+/// use nostrdizer::reputation::percentile;
+///
+/// let samples: Vec<u64> = (1..=100).collect();
+/// assert_eq!(percentile(&samples, 95), Some(95));
+/// assert_eq!(percentile(&[7], 95), Some(7));
+/// assert_eq!(percentile(&[], 95), None);
+/// ```
+pub fn percentile(samples: &[u64], percent: u64) -> Option<u64> {
+    if samples.is_empty() {
+        return None;
+    }
+    let mut sorted = samples.to_vec();
+    sorted.sort_unstable();
+    let rank = (percent as usize * sorted.len()).div_ceil(100).max(1);
+    Some(sorted[rank - 1])
+}
+
+/// When the taker sent each maker the message it is waiting on an answer to
+#[derive(Debug, Clone, Default)]
+pub struct ResponseTimer {
+    started: HashMap<(String, Step), Instant>,
+}
+
+impl ResponseTimer {
+    pub fn start(&mut self, maker: &str, step: Step) {
+        self.started
+            .insert((maker.to_string(), step), Instant::now());
+    }
+
+    /// Milliseconds since step was started for maker, none if it was not
+    pub fn stop(&mut self, maker: &str, step: Step) -> Option<u64> {
+        self.started
+            .remove(&(maker.to_string(), step))
+            .map(|started| started.elapsed().as_millis() as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_samples_are_capped() {
+        let mut reputation = Reputation::default();
+        for millis in 0..(MAX_SAMPLES as u64 + 10) {
+            reputation.record_latency("maker", Step::FillAck, millis);
+        }
+
+        let samples = &reputation.makers["maker"].latencies[&Step::FillAck];
+        assert_eq!(samples.len(), MAX_SAMPLES);
+        assert_eq!(samples[0], 10);
+    }
+
+    #[test]
+    fn test_timer() {
+        let mut timer = ResponseTimer::default();
+        timer.start("maker", Step::Sign);
+
+        assert!(timer.stop("maker", Step::FillAck).is_none());
+        assert!(timer.stop("maker", Step::Sign).is_some());
+        // A second answer is not timed again
+        assert!(timer.stop("maker", Step::Sign).is_none());
+    }
+}
@@ -0,0 +1,143 @@
+use crate::{errors::Error, reputation::Reputation, transcript::RoundHistory};
+
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+/// Seconds in a day
+const DAY: u64 = 86_400;
+
+/// How long round history, reputation and logs are kept, everything is kept when neither limit is set
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    /// Days data is kept after it was recorded
+    pub max_days: Option<u64>,
+    /// Bytes the round history is kept under, the oldest rounds are pruned first
+    pub max_size: Option<u64>,
+}
+
+impl RetentionPolicy {
+    /// Data recorded before the cutoff is pruned
+    pub fn cutoff(&self, now: u64) -> Option<u64> {
+        self.max_days.map(|days| now.saturating_sub(days * DAY))
+    }
+
+    /// Prunes rounds older than the max days, then the oldest until the history is under the max size
+    /// Returns the number of rounds pruned
+    /// ```
+    /// use nostrdizer::{
+    ///     retention::RetentionPolicy,
+    ///     transcript::{Completion, RoundHistory, RoundRecord, Transcript},
+    ///     types::OutPoint,
+    /// };
+    /// # let txid = OutPoint::null().txid;
+    ///
+    /// let round = |created_at| RoundRecord {
+    ///     round_id: "round".to_string(),
+    ///     peer: "peer".to_string(),
+    ///     txid,
+    ///     transcript: Transcript::default().hash(&txid),
+    ///     completion: Completion::Confirmed,
+    ///     created_at,
+    /// };
+    /// let mut history = RoundHistory::default();
+    /// history.record(round(0));
+    /// history.record(round(10 * 86_400));
+    ///
+    /// let policy = RetentionPolicy {
+    ///     max_days: Some(7),
+    ///     max_size: None,
+    /// };
+    /// assert_eq!(policy.prune_rounds(&mut history, 12 * 86_400).unwrap(), 1);
+    /// assert_eq!(history.rounds[0].created_at, 10 * 86_400);
+    /// ```
+    pub fn prune_rounds(&self, history: &mut RoundHistory, now: u64) -> Result<usize, Error> {
+        let before = history.rounds.len();
+        if let Some(cutoff) = self.cutoff(now) {
+            history.rounds.retain(|round| round.created_at >= cutoff);
+        }
+        if let Some(max_size) = self.max_size {
+            history.rounds.sort_by_key(|round| round.created_at);
+            while !history.rounds.is_empty()
+                && serde_json::to_string_pretty(history)?.len() as u64 > max_size
+            {
+                history.rounds.remove(0);
+            }
+        }
+        Ok(before - history.rounds.len())
+    }
+
+    /// Prunes the records of makers there was no session with since the cutoff
+    /// Returns the number of makers pruned
+    pub fn prune_reputation(&self, reputation: &mut Reputation, now: u64) -> usize {
+        let before = reputation.makers.len();
+        if let Some(cutoff) = self.cutoff(now) {
+            reputation
+                .makers
+                .retain(|_, record| record.last_session >= cutoff);
+        }
+        before - reputation.makers.len()
+    }
+}
+
+/// Overwrites a file with zeros before removing it, so its contents are not left in the freed blocks
+/// Filesystems that copy on write or journal data, and SSDs, may still keep old copies
+pub fn secure_delete(path: &Path) -> Result<(), Error> {
+    let len = fs::metadata(path)?.len();
+    let mut file = OpenOptions::new().write(true).open(path)?;
+    file.write_all(&vec![0; len as usize])?;
+    file.sync_all()?;
+    drop(file);
+    fs::remove_file(path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transcript::{Completion, RoundRecord, Transcript};
+
+    use bdk::bitcoin::OutPoint;
+    use rand::random;
+
+    #[test]
+    fn test_prune_to_size() {
+        let txid = OutPoint::null().txid;
+        let mut history = RoundHistory::default();
+        for created_at in [30, 10, 20] {
+            history.record(RoundRecord {
+                round_id: "round".to_string(),
+                peer: "peer".to_string(),
+                txid,
+                transcript: Transcript::default().hash(&txid),
+                completion: Completion::Confirmed,
+                created_at,
+            });
+        }
+        let size = serde_json::to_string_pretty(&history).unwrap().len() as u64;
+        let policy = RetentionPolicy {
+            max_days: None,
+            max_size: Some(size - 1),
+        };
+
+        // Oldest round goes first
+        assert_eq!(policy.prune_rounds(&mut history, 0).unwrap(), 1);
+        let kept: Vec<u64> = history.rounds.iter().map(|r| r.created_at).collect();
+        assert_eq!(kept, vec![20, 30]);
+
+        // Nothing is pruned without limits
+        let mut pruned = history.clone();
+        let keep_all = RetentionPolicy::default();
+        assert_eq!(keep_all.prune_rounds(&mut pruned, u64::MAX).unwrap(), 0);
+        assert_eq!(pruned, history);
+    }
+
+    #[test]
+    fn test_secure_delete() {
+        let path =
+            std::env::temp_dir().join(format!("nostrdizer-secure-delete-{}.psbt", random::<u64>()));
+        fs::write(&path, "cHNidP8B").unwrap();
+        secure_delete(&path).unwrap();
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,399 @@
+use crate::{
+    errors::Error,
+    fees::{checked_add, checked_sub, maker_txfee},
+    types::{IoAuth, NostrdizerOffer, VerifyCJInfo, DUST},
+};
+
+use bdk::bitcoin::{psbt::PartiallySignedTransaction, Amount};
+use bitcoin_hashes::{sha256, Hash};
+use rand::{seq::SliceRandom, Rng};
+
+// Estimated vsize of p2wpkh inputs and outputs, and the tx overhead
+pub(crate) const INPUT_VSIZE: usize = 68;
+pub(crate) const OUTPUT_VSIZE: usize = 31;
+pub(crate) const TX_OVERHEAD_VSIZE: usize = 11;
+
+// Hex chars of the hash kept in a round id
+const ROUND_ID_LEN: usize = 16;
+
+// Each part of split change is worth at least this many times the fee of spending it
+const SPLIT_MIN_SPEND_MULTIPLE: u64 = 10;
+
+/// Id of a round both the taker and maker derive from the fill event,
+/// included in later messages and logs so peers can match them up
+/// ```
+/// use nostrdizer::round::round_id;
+///
+/// let id = round_id("fill event id", "taker pubkey");
+/// assert_eq!(id.len(), 16);
+/// assert_eq!(id, round_id("fill event id", "taker pubkey"));
+/// assert_ne!(id, round_id("other fill event id", "taker pubkey"));
+/// ```
+pub fn round_id(fill_event_id: &str, taker_pub_key: &str) -> String {
+    let hash = sha256::Hash::hash(format!("{fill_event_id}{taker_pub_key}").as_bytes());
+    hash.to_string()[..ROUND_ID_LEN].to_string()
+}
+
+/// What a maker in the round is expected to put in and get back
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MakerAccounting {
+    pub maker: String,
+    pub cjfee: Amount,
+    /// Amount maker contributes to the mining fee
+    pub txfee: Amount,
+    pub input_value: Amount,
+    pub input_count: usize,
+    /// Change returned to the maker, `None` if it would be dust or the maker declared no change
+    pub change: Option<Amount>,
+}
+
+/// Expected fees and outputs of a CJ round
+/// ```
+/// use nostrdizer::{
+///     round::{MakerAccounting, RoundAccounting},
+///     test_utils::{offer, psbt},
+///     types::{Amount, SignedAmount, VerifyCJInfo},
+/// };
+///
+/// let send_amount = Amount::from_sat(100_000);
+/// let maker_input = Amount::from_sat(120_000);
+/// let maker = MakerAccounting::new(&offer("maker", 500), send_amount, maker_input, 1).unwrap();
+/// let round = RoundAccounting::new(send_amount, vec![maker], 1, 1.0);
+///
+/// // Maker gets its input back plus the fee, taker pays fee and mining fee from change
+/// let psbt = psbt(&[120_000, 150_000], &[100_000, 100_000, 20_500, 49_000]);
+/// let tx_info = VerifyCJInfo {
+///     mining_fee: SignedAmount::from_sat(500),
+///     maker_fee: SignedAmount::from_sat(500),
+///     fee_rate: 1.0,
+///     verifyed: true,
+///     reject_reason: None,
+/// };
+///
+/// assert!(round.verify(&psbt, &tx_info));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoundAccounting {
+    pub send_amount: Amount,
+    pub makers: Vec<MakerAccounting>,
+    pub taker_input_count: usize,
+    /// Taker change is split in two outputs when it is worth it
+    pub taker_change_outputs: usize,
+    /// Fee rate in sat/vB the mining fee is derived from
+    pub fee_rate: f32,
+}
+
+impl MakerAccounting {
+    pub fn new(
+        offer: &NostrdizerOffer,
+        send_amount: Amount,
+        input_value: Amount,
+        input_count: usize,
+    ) -> Result<Self, Error> {
+        // Takers ask each maker for a single CJ output
+        let txfee = maker_txfee(offer.txfee, offer.txfee_rate, input_count, 1);
+        let change = checked_sub(
+            checked_add(input_value, offer.cjfee)?,
+            checked_add(send_amount, txfee)?,
+        )?;
+
+        Ok(Self {
+            maker: offer.maker.clone(),
+            cjfee: offer.cjfee,
+            txfee,
+            input_value,
+            input_count,
+            change: (change.to_sat() > DUST).then_some(change),
+        })
+    }
+}
+
+impl RoundAccounting {
+    pub fn new(
+        send_amount: Amount,
+        makers: Vec<MakerAccounting>,
+        taker_input_count: usize,
+        fee_rate: f32,
+    ) -> Self {
+        Self {
+            send_amount,
+            makers,
+            taker_input_count,
+            taker_change_outputs: 1,
+            fee_rate,
+        }
+    }
+
+    /// Recalculates the round for the makers that are still participating
+    pub fn retain_makers(&self, surviving: &[String]) -> Result<Self, Error> {
+        let makers: Vec<MakerAccounting> = self
+            .makers
+            .iter()
+            .filter(|m| surviving.contains(&m.maker))
+            .cloned()
+            .collect();
+
+        if makers.is_empty() {
+            return Err(Error::NotEnoughMakers);
+        }
+
+        Ok(Self {
+            makers,
+            ..self.clone()
+        })
+    }
+
+    /// Total CJ fees paid to makers
+    pub fn total_maker_fees(&self) -> Amount {
+        self.makers
+            .iter()
+            .fold(Amount::ZERO, |total, m| total + m.cjfee)
+    }
+
+    /// Total mining fee contributed by makers
+    pub fn total_maker_txfee(&self) -> Amount {
+        self.makers
+            .iter()
+            .fold(Amount::ZERO, |total, m| total + m.txfee)
+    }
+
+    /// Change of makers without a change output, which goes to the mining fee
+    pub fn donated_change(&self) -> Amount {
+        self.makers
+            .iter()
+            .filter(|m| m.change.is_none())
+            .map(|m| {
+                (m.input_value + m.cjfee)
+                    .checked_sub(self.send_amount + m.txfee)
+                    .unwrap_or(Amount::ZERO)
+            })
+            .fold(Amount::ZERO, |total, change| total + change)
+    }
+
+    /// Number of equal valued CJ outputs, one per maker plus the taker
+    pub fn cj_outputs(&self) -> usize {
+        self.makers.len() + 1
+    }
+
+    /// Number of maker change outputs plus the taker change outputs
+    pub fn change_outputs(&self) -> usize {
+        self.makers.iter().filter(|m| m.change.is_some()).count() + self.taker_change_outputs
+    }
+
+    pub fn input_count(&self) -> usize {
+        self.makers.iter().map(|m| m.input_count).sum::<usize>() + self.taker_input_count
+    }
+
+    /// Estimated vsize of the signed transaction
+    pub fn estimated_vsize(&self) -> usize {
+        TX_OVERHEAD_VSIZE
+            + self.input_count() * INPUT_VSIZE
+            + (self.cj_outputs() + self.change_outputs()) * OUTPUT_VSIZE
+    }
+
+    /// Mining fee for the round at the fee rate
+    pub fn mining_fee(&self) -> Amount {
+        Amount::from_sat((self.estimated_vsize() as f32 * self.fee_rate).ceil() as u64)
+    }
+
+    /// Part of the mining fee the taker pays after maker contributions
+    pub fn taker_mining_fee(&self) -> Amount {
+        self.mining_fee()
+            .checked_sub(self.total_maker_txfee())
+            .unwrap_or(Amount::ZERO)
+    }
+
+    /// Checks a built CJ matches the expected outputs and does not pay makers more then expected
+    pub fn verify(&self, psbt: &PartiallySignedTransaction, tx_info: &VerifyCJInfo) -> bool {
+        let output_count_check =
+            psbt.unsigned_tx.output.len() <= self.cj_outputs() + self.change_outputs();
+        let cj_output_check = psbt
+            .unsigned_tx
+            .output
+            .iter()
+            .filter(|o| o.value == self.send_amount.to_sat())
+            .count()
+            == self.cj_outputs();
+        // Makers are paid their fees less what they contribute to the mining fee
+        let maker_fee_check = tx_info.maker_fee.to_sat()
+            <= self.total_maker_fees().to_sat() as i64 - self.total_maker_txfee().to_sat() as i64;
+
+        output_count_check && cj_output_check && maker_fee_check
+    }
+}
+
+/// Number of outputs of a CJ that are neither CJ outputs nor maker change
+pub fn taker_change_outputs(
+    psbt: &PartiallySignedTransaction,
+    send_amount: Amount,
+    maker_inputs: &[(NostrdizerOffer, IoAuth)],
+) -> usize {
+    let maker_change: Vec<_> = maker_inputs
+        .iter()
+        .map(|(_, io_auth)| io_auth.change_address.script_pubkey())
+        .collect();
+    psbt.unsigned_tx
+        .output
+        .iter()
+        .filter(|output| {
+            output.value != send_amount.to_sat() && !maker_change.contains(&output.script_pubkey)
+        })
+        .count()
+}
+
+/// Splits taker change in two outputs of random value, so it is not the one odd output of the CJ
+/// Returns the parts and the mining fee of the extra output, none when either part would not be worth spending
+/// ```
+/// use nostrdizer::{round::split_change, types::Amount};
+/// use rand::thread_rng;
+///
+/// let change = Amount::from_sat(200_000);
+/// let ((first, second), extra_fee) = split_change(change, 2.0, &mut thread_rng()).unwrap();
+/// assert_eq!(extra_fee, Amount::from_sat(62));
+/// assert_eq!(first + second + extra_fee, change);
+///
+/// assert!(split_change(Amount::from_sat(2_000), 2.0, &mut thread_rng()).is_none());
+/// ```
+pub fn split_change(
+    change: Amount,
+    fee_rate: f32,
+    rng: &mut impl Rng,
+) -> Option<((Amount, Amount), Amount)> {
+    let extra_fee = (OUTPUT_VSIZE as f32 * fee_rate).ceil() as u64;
+    let spend_fee = (INPUT_VSIZE as f32 * fee_rate).ceil() as u64;
+    let min_part = (DUST + 1).max(spend_fee * SPLIT_MIN_SPEND_MULTIPLE);
+    let total = change.to_sat().checked_sub(extra_fee)?;
+    if total < 2 * min_part {
+        return None;
+    }
+    let first = rng.gen_range(min_part..=total - min_part);
+    Some((
+        (Amount::from_sat(first), Amount::from_sat(total - first)),
+        Amount::from_sat(extra_fee),
+    ))
+}
+
+/// Puts the outputs of a psbt in random order, so their position does not tell whose they are
+pub fn shuffle_outputs(psbt: &mut PartiallySignedTransaction, rng: &mut impl Rng) {
+    let mut outputs: Vec<_> = psbt
+        .unsigned_tx
+        .output
+        .drain(..)
+        .zip(psbt.outputs.drain(..))
+        .collect();
+    outputs.shuffle(rng);
+    (psbt.unsigned_tx.output, psbt.outputs) = outputs.into_iter().unzip();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_utils::offer, types::TxFeeRate};
+
+    fn round() -> RoundAccounting {
+        let send_amount = Amount::from_sat(100_000);
+        let makers = vec![
+            MakerAccounting::new(&offer("a", 100), send_amount, Amount::from_sat(150_000), 1)
+                .unwrap(),
+            MakerAccounting::new(&offer("b", 200), send_amount, Amount::from_sat(100_000), 1)
+                .unwrap(),
+            MakerAccounting::new(&offer("c", 300), send_amount, Amount::from_sat(250_000), 2)
+                .unwrap(),
+        ];
+        RoundAccounting::new(send_amount, makers, 1, 2.0)
+    }
+
+    #[test]
+    fn test_full_round() {
+        let round = round();
+
+        assert_eq!(round.total_maker_fees(), Amount::from_sat(600));
+        assert_eq!(round.cj_outputs(), 4);
+        // Maker b change is only its fee which is dust
+        assert_eq!(round.change_outputs(), 3);
+        assert_eq!(round.input_count(), 5);
+        assert_eq!(round.estimated_vsize(), 11 + 5 * 68 + 7 * 31);
+        assert_eq!(round.mining_fee(), Amount::from_sat(1136));
+    }
+
+    #[test]
+    fn test_donated_change() {
+        let mut round = round();
+        // Maker b's dust change goes to the mining fee
+        assert_eq!(round.donated_change(), Amount::from_sat(200));
+
+        // Maker a donates the change it does not track
+        round.makers[0].change = None;
+        assert_eq!(round.donated_change(), Amount::from_sat(50_300));
+        assert_eq!(round.change_outputs(), 2);
+    }
+
+    #[test]
+    fn test_split_change_accounted() {
+        let mut round = round();
+        round.taker_change_outputs = 2;
+
+        assert_eq!(round.change_outputs(), 4);
+        assert_eq!(round.estimated_vsize(), 11 + 5 * 68 + 8 * 31);
+        // Each part is worth spending at the fee rate
+        let mut rng = rand::thread_rng();
+        for _ in 0..100 {
+            let ((first, second), _) =
+                split_change(Amount::from_sat(3_000), 1.0, &mut rng).unwrap();
+            assert!(first >= Amount::from_sat(680) && second >= Amount::from_sat(680));
+        }
+    }
+
+    #[test]
+    fn test_three_to_two_makers() {
+        let round = round();
+        let degraded = round
+            .retain_makers(&["a".to_string(), "c".to_string()])
+            .unwrap();
+
+        assert_eq!(degraded.makers.len(), 2);
+        assert_eq!(degraded.total_maker_fees(), Amount::from_sat(400));
+        assert_eq!(degraded.cj_outputs(), 3);
+        assert_eq!(degraded.change_outputs(), 3);
+        assert_eq!(degraded.input_count(), 4);
+        assert_eq!(degraded.estimated_vsize(), 11 + 4 * 68 + 6 * 31);
+        assert!(degraded.mining_fee() < round.mining_fee());
+        assert_eq!(degraded.taker_mining_fee(), degraded.mining_fee());
+    }
+
+    #[test]
+    fn test_maker_txfee_rate() {
+        let mut maker_offer = offer("a", 100);
+        maker_offer.txfee_rate = Some(TxFeeRate {
+            fee_rate: 2.0,
+            max_vbytes: 1000,
+        });
+        let maker = MakerAccounting::new(
+            &maker_offer,
+            Amount::from_sat(100_000),
+            Amount::from_sat(150_000),
+            1,
+        )
+        .unwrap();
+
+        // One input, the CJ output and change
+        assert_eq!(maker.txfee, Amount::from_sat(2 * (68 + 2 * 31)));
+        assert_eq!(maker.change, Some(Amount::from_sat(50_100 - 260)));
+    }
+
+    #[test]
+    fn test_no_surviving_makers() {
+        assert!(round().retain_makers(&[]).is_err());
+    }
+
+    #[test]
+    fn test_maker_change_underflow() {
+        let result = MakerAccounting::new(
+            &offer("a", 100),
+            Amount::from_sat(100_000),
+            Amount::from_sat(50_000),
+            1,
+        );
+        assert!(result.is_err());
+    }
+}
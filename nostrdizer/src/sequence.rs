@@ -0,0 +1,272 @@
+use crate::types::{
+    NostrdizerMessages, Offer, ABORT, ABORT_VERSION, ABS_OFFER, AUTH, CONFIRM, CONFIRM_VERSION,
+    COOP_JOIN, FILL, FILL_ACK, FILL_ACK_VERSION, IOAUTH, PRESENCE, PUBKEY, REJECT, REL_OFFER,
+    SIGNED_TRANSACTION, TRANSACTION,
+};
+
+use serde::{Deserialize, Serialize};
+
+/// Side of a session a message is sent by
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Party {
+    Taker,
+    Maker,
+}
+
+/// States a session between a taker and a maker goes through
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionState {
+    /// Taker picked the maker's offer
+    Offered,
+    Filled,
+    /// Maker told the taker its session relays
+    Acked,
+    /// Taker revealed its podle
+    Authorized,
+    /// Maker sent the inputs and outputs it wants in the CJ
+    InputsSent,
+    /// Taker sent the unsigned CJ
+    Proposed,
+    Signed,
+    /// Taker broadcast the CJ and sent its transcript hash
+    Broadcast,
+    /// Maker answered with its transcript hash
+    Confirmed,
+    Rejected,
+    Aborted,
+}
+
+/// Message of a session and the state it moves the session from and to
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Transition {
+    /// Name of the message in FLOW.md
+    pub message: String,
+    pub kind: u16,
+    pub sender: Party,
+    pub from: SessionState,
+    pub to: SessionState,
+    /// Protocol version peers send the message from, earlier peers skip it
+    pub since_version: u16,
+}
+
+/// Message name, kind, sender, state sent in, state moved to and protocol version sent from
+type Step = (&'static str, u16, Party, SessionState, SessionState, u16);
+
+/// Messages of a session that completes, in the order they are sent
+pub const HAPPY_PATH: [Step; 8] = [
+    (
+        "fill",
+        FILL,
+        Party::Taker,
+        SessionState::Offered,
+        SessionState::Filled,
+        1,
+    ),
+    (
+        "fill ack",
+        FILL_ACK,
+        Party::Maker,
+        SessionState::Filled,
+        SessionState::Acked,
+        FILL_ACK_VERSION,
+    ),
+    (
+        "auth",
+        AUTH,
+        Party::Taker,
+        SessionState::Acked,
+        SessionState::Authorized,
+        1,
+    ),
+    (
+        "ioauth",
+        IOAUTH,
+        Party::Maker,
+        SessionState::Authorized,
+        SessionState::InputsSent,
+        1,
+    ),
+    (
+        "transaction",
+        TRANSACTION,
+        Party::Taker,
+        SessionState::InputsSent,
+        SessionState::Proposed,
+        1,
+    ),
+    (
+        "signed transaction",
+        SIGNED_TRANSACTION,
+        Party::Maker,
+        SessionState::Proposed,
+        SessionState::Signed,
+        1,
+    ),
+    (
+        "confirm",
+        CONFIRM,
+        Party::Taker,
+        SessionState::Signed,
+        SessionState::Broadcast,
+        CONFIRM_VERSION,
+    ),
+    (
+        "confirm",
+        CONFIRM,
+        Party::Maker,
+        SessionState::Broadcast,
+        SessionState::Confirmed,
+        CONFIRM_VERSION,
+    ),
+];
+
+/// Messages that end a session early, from the states they can be sent in
+pub const EXITS: [Step; 5] = [
+    // Maker is busy or has no inputs for the fill
+    (
+        "reject",
+        REJECT,
+        Party::Maker,
+        SessionState::Filled,
+        SessionState::Rejected,
+        1,
+    ),
+    // Maker will not sign the CJ
+    (
+        "reject",
+        REJECT,
+        Party::Maker,
+        SessionState::Proposed,
+        SessionState::Rejected,
+        1,
+    ),
+    // Taker has enough makers without this one, or fees spiked
+    (
+        "abort",
+        ABORT,
+        Party::Taker,
+        SessionState::Acked,
+        SessionState::Aborted,
+        ABORT_VERSION,
+    ),
+    (
+        "abort",
+        ABORT,
+        Party::Taker,
+        SessionState::InputsSent,
+        SessionState::Aborted,
+        ABORT_VERSION,
+    ),
+    // Round was cancelled after the maker signed, before the CJ was broadcast
+    (
+        "abort",
+        ABORT,
+        Party::Taker,
+        SessionState::Signed,
+        SessionState::Aborted,
+        ABORT_VERSION,
+    ),
+];
+
+/// The canonical sequence of a session, the completing messages followed by the early exits
+/// Written to `docs/sequence.json` by `cargo run --example sequence`, tests fail when the two differ
+pub fn sequence() -> Vec<Transition> {
+    HAPPY_PATH
+        .iter()
+        .chain(EXITS.iter())
+        .map(
+            |(message, kind, sender, from, to, since_version)| Transition {
+                message: message.to_string(),
+                kind: *kind,
+                sender: *sender,
+                from: *from,
+                to: *to,
+                since_version: *since_version,
+            },
+        )
+        .collect()
+}
+
+impl SessionState {
+    /// State a session moves to when sender sends an event of kind, none if the message is out of sequence
+    /// ```
+    /// use nostrdizer::{
+    ///     sequence::{Party, SessionState},
+    ///     types::{AUTH, FILL, REJECT},
+    /// };
+    ///
+    /// let state = SessionState::Offered.next(Party::Taker, FILL).unwrap();
+    /// assert_eq!(state, SessionState::Filled);
+    /// // Auth before the maker acked the fill
+    /// assert_eq!(state.next(Party::Taker, AUTH), None);
+    /// assert_eq!(state.next(Party::Maker, REJECT), Some(SessionState::Rejected));
+    /// ```
+    pub fn next(&self, sender: Party, kind: u16) -> Option<SessionState> {
+        HAPPY_PATH
+            .iter()
+            .chain(EXITS.iter())
+            .find(|(_, k, s, from, _, _)| *k == kind && *s == sender && from == self)
+            .map(|(_, _, _, _, to, _)| *to)
+    }
+}
+
+/// Event kind a message is sent as
+pub fn event_kind(message: &NostrdizerMessages) -> u16 {
+    match message {
+        NostrdizerMessages::Offer(Offer::AbsOffer(_)) => ABS_OFFER,
+        NostrdizerMessages::Offer(Offer::RelOffer(_)) => REL_OFFER,
+        NostrdizerMessages::Fill(_) => FILL,
+        NostrdizerMessages::FillAck(_) => FILL_ACK,
+        NostrdizerMessages::PubKey(_) => PUBKEY,
+        NostrdizerMessages::Auth(_) => AUTH,
+        NostrdizerMessages::MakerInputs(_) => IOAUTH,
+        NostrdizerMessages::UnsignedCJ(_) => TRANSACTION,
+        NostrdizerMessages::SignedCJ(_) => SIGNED_TRANSACTION,
+        NostrdizerMessages::Reject(_) => REJECT,
+        NostrdizerMessages::Confirm(_) => CONFIRM,
+        NostrdizerMessages::Presence(_) => PRESENCE,
+        NostrdizerMessages::Abort(_) => ABORT,
+        NostrdizerMessages::CoopJoin(_) => COOP_JOIN,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build_info::EVENT_KINDS;
+
+    #[test]
+    fn test_spec_up_to_date() {
+        let spec: Vec<Transition> =
+            serde_json::from_str(include_str!("../docs/sequence.json")).unwrap();
+        assert_eq!(
+            spec,
+            sequence(),
+            "docs/sequence.json is out of date, run `just sequence`"
+        );
+    }
+
+    #[test]
+    fn test_happy_path_completes() {
+        let state = HAPPY_PATH
+            .iter()
+            .try_fold(SessionState::Offered, |state, (_, kind, sender, ..)| {
+                state.next(*sender, *kind)
+            });
+        assert_eq!(state, Some(SessionState::Confirmed));
+    }
+
+    #[test]
+    fn test_kinds_listed() {
+        for transition in sequence() {
+            assert!(
+                EVENT_KINDS.contains(&(transition.message.as_str(), transition.kind)),
+                "{} is not listed as kind {}",
+                transition.message,
+                transition.kind
+            );
+        }
+    }
+}
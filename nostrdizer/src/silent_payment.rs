@@ -0,0 +1,601 @@
+use crate::errors::Error;
+
+use bdk::bitcoin::{
+    bech32::{self, u5, FromBase32, ToBase32, Variant},
+    consensus::encode::serialize,
+    secp256k1::{constants::CURVE_ORDER, KeyPair, Parity, PublicKey, Scalar, Secp256k1, SecretKey},
+    util::schnorr::{TapTweak, TweakedPublicKey},
+    Network, OutPoint, Script,
+};
+use bitcoin_hashes::{sha256, Hash, HashEngine};
+use num_bigint::{BigInt, Sign};
+use rand::thread_rng;
+use serde::{Deserialize, Serialize};
+
+use std::fmt;
+use std::str::FromStr;
+
+/// Scan key of a silent payment address multiplied by a key of the taker's round
+/// Makers work out their share of the payment with it without learning who is paid
+pub type BlindedScanKey = PublicKey;
+
+/// Tag of the challenge of a share's proof
+const SHARE_TAG: &str = "nostrdizer/SilentPaymentShare";
+
+/// BIP-352 silent payment address, the scan and spend keys outputs to it are derived from
+/// ```
+/// use bitcoin::{secp256k1::{PublicKey, Secp256k1, SecretKey}, Network};
+/// use nostrdizer::silent_payment::SilentPaymentAddress;
+/// use std::str::FromStr;
+///
+/// let secp = Secp256k1::new();
+/// let address = SilentPaymentAddress {
+///     scan: PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[1; 32]).unwrap()),
+///     spend: PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[2; 32]).unwrap()),
+///     network: Network::Regtest,
+/// };
+/// let encoded = address.to_string();
+/// assert!(encoded.starts_with("sprt1q"));
+/// assert_eq!(SilentPaymentAddress::from_str(&encoded).unwrap(), address);
+/// assert!(SilentPaymentAddress::from_str("bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080").is_err());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SilentPaymentAddress {
+    pub scan: PublicKey,
+    pub spend: PublicKey,
+    /// Testnet for testnet and signet, they share a prefix
+    pub network: Network,
+}
+
+impl SilentPaymentAddress {
+    /// Whether the address can be paid on network
+    pub fn is_for(&self, network: Network) -> bool {
+        hrp(self.network) == hrp(network)
+    }
+}
+
+/// Prefix of silent payment addresses on network
+fn hrp(network: Network) -> &'static str {
+    match network {
+        Network::Bitcoin => "sp",
+        Network::Testnet | Network::Signet => "tsp",
+        Network::Regtest => "sprt",
+    }
+}
+
+impl FromStr for SilentPaymentAddress {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::InvalidSilentPaymentAddress(s.to_string());
+        let (prefix, data, variant) = bech32::decode(s).map_err(|_| invalid())?;
+        let network = match prefix.as_str() {
+            "sp" => Network::Bitcoin,
+            "tsp" => Network::Testnet,
+            "sprt" => Network::Regtest,
+            _ => return Err(invalid()),
+        };
+        let (version, payload) = data.split_first().ok_or_else(invalid)?;
+        let payload = Vec::<u8>::from_base32(payload).map_err(|_| invalid())?;
+        // Later versions may add data after the keys, version 31 is not to be read
+        let keys = match version.to_u8() {
+            0 if payload.len() == 66 => &payload[..],
+            1..=30 if payload.len() >= 66 => &payload[..66],
+            _ => return Err(invalid()),
+        };
+        if variant != Variant::Bech32m {
+            return Err(invalid());
+        }
+        Ok(Self {
+            scan: PublicKey::from_slice(&keys[..33]).map_err(|_| invalid())?,
+            spend: PublicKey::from_slice(&keys[33..]).map_err(|_| invalid())?,
+            network,
+        })
+    }
+}
+
+impl fmt::Display for SilentPaymentAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut payload = self.scan.serialize().to_vec();
+        payload.extend(self.spend.serialize());
+        let mut data = vec![u5::try_from_u8(0).map_err(|_| fmt::Error)?];
+        data.extend(payload.to_base32());
+        let encoded =
+            bech32::encode(hrp(self.network), data, Variant::Bech32m).map_err(|_| fmt::Error)?;
+        write!(f, "{encoded}")
+    }
+}
+
+/// BIP-340 style tagged hash
+fn tagged_hash(tag: &str, data: &[&[u8]]) -> [u8; 32] {
+    let tag = sha256::Hash::hash(tag.as_bytes());
+    let mut engine = sha256::Hash::engine();
+    engine.input(&tag[..]);
+    engine.input(&tag[..]);
+    for part in data {
+        engine.input(part);
+    }
+    sha256::Hash::from_engine(engine).into_inner()
+}
+
+/// Whether inputs spending script_pubkey count towards a silent payment
+/// Nested segwit is taken to be `sh(wpkh)`, the only nested type wallets here spend
+pub fn counts(script_pubkey: &Script) -> bool {
+    script_pubkey.is_v0_p2wpkh()
+        || script_pubkey.is_p2sh()
+        || script_pubkey.is_p2pkh()
+        || script_pubkey.is_v1_p2tr()
+}
+
+/// Key an input spending script_pubkey counts with, none for types silent payments leave out
+/// A taproot input counts with the key of its output key, negated to the one with even y
+pub fn input_secret(
+    secret_key: SecretKey,
+    script_pubkey: &Script,
+) -> Result<Option<SecretKey>, Error> {
+    if !counts(script_pubkey) {
+        return Ok(None);
+    }
+    if !script_pubkey.is_v1_p2tr() {
+        return Ok(Some(secret_key));
+    }
+    // Wallet keys of taproot utxos are internal keys of key path only outputs
+    let secp = Secp256k1::new();
+    let tweaked = KeyPair::from_secret_key(&secp, &secret_key)
+        .tap_tweak(&secp, None)
+        .to_inner();
+    let (output_key, parity) = tweaked.x_only_public_key();
+    if script_pubkey.as_bytes()[2..] != output_key.serialize() {
+        return Err(Error::SilentPaymentInputKey);
+    }
+    let secret_key = SecretKey::from_keypair(&tweaked);
+    Ok(Some(match parity {
+        Parity::Odd => secret_key.negate(),
+        Parity::Even => secret_key,
+    }))
+}
+
+/// Key an input spending script_pubkey counts with, given the public key it is spent with
+/// None if the key does not spend the script or its type is left out
+pub fn input_pubkey(pubkey: &PublicKey, script_pubkey: &Script) -> Option<PublicKey> {
+    let key = bdk::bitcoin::PublicKey::new(*pubkey);
+    let wpkh = Script::new_v0_p2wpkh(&key.wpubkey_hash()?);
+    let spends = if script_pubkey.is_v0_p2wpkh() {
+        *script_pubkey == wpkh
+    } else if script_pubkey.is_p2sh() {
+        *script_pubkey == Script::new_p2sh(&wpkh.script_hash())
+    } else if script_pubkey.is_p2pkh() {
+        *script_pubkey == Script::new_p2pkh(&key.pubkey_hash())
+    } else if script_pubkey.is_v1_p2tr() {
+        let (x_only, parity) = pubkey.x_only_public_key();
+        parity == Parity::Even && script_pubkey.as_bytes()[2..] == x_only.serialize()
+    } else {
+        false
+    };
+    spends.then_some(*pubkey)
+}
+
+/// Sum of keys, none when there are none
+fn sum_secrets(secrets: impl IntoIterator<Item = SecretKey>) -> Result<Option<SecretKey>, Error> {
+    let mut sum: Option<SecretKey> = None;
+    for secret in secrets {
+        sum = match sum {
+            Some(sum) => Some(sum.add_tweak(&Scalar::from(secret))?),
+            None => Some(secret),
+        };
+    }
+    Ok(sum)
+}
+
+/// Inverse of a key modulo the curve order
+fn inverse(secret_key: &SecretKey) -> Result<Scalar, Error> {
+    let n = BigInt::from_bytes_be(Sign::Plus, &CURVE_ORDER);
+    let key = BigInt::from_bytes_be(Sign::Plus, &secret_key.secret_bytes());
+    let (_, bytes) = key.modpow(&(&n - 2), &n).to_bytes_be();
+    let mut inverse = [0u8; 32];
+    inverse[32 - bytes.len()..].copy_from_slice(&bytes);
+    Scalar::from_be_bytes(inverse).map_err(|_| Error::SilentPaymentInputKey)
+}
+
+/// Challenge of a share's proof
+fn challenge(points: [&PublicKey; 5]) -> [u8; 32] {
+    let points: Vec<[u8; 33]> = points.iter().map(|point| point.serialize()).collect();
+    let points: Vec<&[u8]> = points.iter().map(|point| &point[..]).collect();
+    tagged_hash(SHARE_TAG, &points)
+}
+
+/// What a maker sends with its inputs towards the taker's silent payment
+/// The share is the sum of the keys its inputs count with times the blinded scan key, with a proof it is of those keys
+/// ```
+/// use bitcoin::{secp256k1::{PublicKey, Secp256k1, SecretKey}, OutPoint, PrivateKey, Network};
+/// use nostrdizer::silent_payment::SilentPaymentShare;
+///
+/// let secp = Secp256k1::new();
+/// let key = PrivateKey::new(SecretKey::from_slice(&[3; 32]).unwrap(), Network::Regtest);
+/// let script = bitcoin::Address::p2wpkh(&key.public_key(&secp), Network::Regtest)
+///     .unwrap()
+///     .script_pubkey();
+/// let blinded = PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&[4; 32]).unwrap());
+///
+/// let share = SilentPaymentShare::new(&[(OutPoint::default(), key.inner, script)], &blinded)
+///     .unwrap()
+///     .unwrap();
+/// assert_eq!(share.verify(&blinded).unwrap(), key.public_key(&secp).inner);
+///
+/// // Proof is for the blinded key it was made with
+/// assert!(share.verify(&PublicKey::from_secret_key(&secp, &key.inner)).is_err());
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SilentPaymentShare {
+    /// Key each input counts with, inputs of types silent payments leave out have none
+    #[serde(rename = "keys")]
+    pub pubkeys: Vec<(OutPoint, PublicKey)>,
+    pub share: PublicKey,
+    /// Hex challenge and response of the proof the share and the keys have the same discrete log
+    #[serde(rename = "e")]
+    pub challenge: String,
+    #[serde(rename = "s")]
+    pub response: String,
+}
+
+impl SilentPaymentShare {
+    /// Share of the inputs, given as outpoint, private key and script pubkey, none if none of them count
+    pub fn new(
+        inputs: &[(OutPoint, SecretKey, Script)],
+        blinded_scan_key: &BlindedScanKey,
+    ) -> Result<Option<Self>, Error> {
+        let secp = Secp256k1::new();
+        let mut pubkeys = vec![];
+        let mut secrets = vec![];
+        for (outpoint, secret_key, script_pubkey) in inputs {
+            if let Some(secret) = input_secret(*secret_key, script_pubkey)? {
+                pubkeys.push((*outpoint, PublicKey::from_secret_key(&secp, &secret)));
+                secrets.push(secret);
+            }
+        }
+        let secret = match sum_secrets(secrets)? {
+            Some(secret) => secret,
+            None => return Ok(None),
+        };
+
+        let share = blinded_scan_key.mul_tweak(&secp, &Scalar::from(secret))?;
+        let nonce = SecretKey::new(&mut thread_rng());
+        let challenge = challenge([
+            &PublicKey::from_secret_key(&secp, &secret),
+            blinded_scan_key,
+            &share,
+            &PublicKey::from_secret_key(&secp, &nonce),
+            &blinded_scan_key.mul_tweak(&secp, &Scalar::from(nonce))?,
+        ]);
+        let e = Scalar::from_be_bytes(challenge).map_err(|_| Error::SilentPaymentInputKey)?;
+        let response = secret.mul_tweak(&e)?.add_tweak(&Scalar::from(nonce))?;
+
+        Ok(Some(Self {
+            pubkeys,
+            share,
+            challenge: hex::encode(challenge),
+            response: hex::encode(response.secret_bytes()),
+        }))
+    }
+
+    /// Checks the proof, returns the sum of the keys the share is of
+    pub fn verify(&self, blinded_scan_key: &BlindedScanKey) -> Result<PublicKey, Error> {
+        let invalid = || Error::InvalidSilentPaymentShare(self.share.to_string());
+        let secp = Secp256k1::new();
+        let keys: Vec<&PublicKey> = self.pubkeys.iter().map(|(_, key)| key).collect();
+        let sum = PublicKey::combine_keys(&keys).map_err(|_| invalid())?;
+        let challenge: [u8; 32] = hex::decode(&self.challenge)
+            .ok()
+            .and_then(|challenge| challenge.try_into().ok())
+            .ok_or_else(invalid)?;
+        let e = Scalar::from_be_bytes(challenge).map_err(|_| invalid())?;
+        let response = hex::decode(&self.response)
+            .ok()
+            .and_then(|response| SecretKey::from_slice(&response).ok())
+            .ok_or_else(invalid)?;
+
+        // Nonce points are recovered from the response, the challenge has to commit to them
+        let nonce_point = PublicKey::from_secret_key(&secp, &response)
+            .combine(&sum.mul_tweak(&secp, &e)?.negate(&secp))
+            .map_err(|_| invalid())?;
+        let blinded_nonce_point = blinded_scan_key
+            .mul_tweak(&secp, &Scalar::from(response))?
+            .combine(&self.share.mul_tweak(&secp, &e)?.negate(&secp))
+            .map_err(|_| invalid())?;
+        let expected = self::challenge([
+            &sum,
+            blinded_scan_key,
+            &self.share,
+            &nonce_point,
+            &blinded_nonce_point,
+        ]);
+        match expected == challenge {
+            true => Ok(sum),
+            false => Err(invalid()),
+        }
+    }
+}
+
+/// Taker's side of a silent payment out of a CJ
+/// The output is derived from the keys of every input, makers add theirs as shares of a blinded scan key
+#[derive(Debug, Clone)]
+pub struct SilentPaymentSender {
+    pub address: SilentPaymentAddress,
+    /// Key of the round the scan key is blinded with
+    blinding: SecretKey,
+    /// Inputs the output was derived from, and its key
+    pub derived: Option<(Vec<OutPoint>, TweakedPublicKey)>,
+}
+
+impl SilentPaymentSender {
+    pub fn new(address: SilentPaymentAddress) -> Self {
+        Self {
+            address,
+            blinding: SecretKey::new(&mut thread_rng()),
+            derived: None,
+        }
+    }
+
+    /// Scan key makers work out their shares with
+    pub fn blinded_scan_key(&self) -> Result<BlindedScanKey, Error> {
+        Ok(self
+            .address
+            .scan
+            .mul_tweak(&Secp256k1::new(), &Scalar::from(self.blinding))?)
+    }
+
+    /// Output key of the payment, `outpoints` are every input of the CJ
+    /// `own_inputs` are the private key and script pubkey of the taker's inputs, `shares` the key sum and share of each maker
+    /// ```
+    /// use bitcoin::{secp256k1::{PublicKey, Secp256k1, SecretKey}, Address, Network, OutPoint, PrivateKey};
+    /// use nostrdizer::silent_payment::{SilentPaymentAddress, SilentPaymentSender, SilentPaymentShare};
+    ///
+    /// let secp = Secp256k1::new();
+    /// let key = |byte| PrivateKey::new(SecretKey::from_slice(&[byte; 32]).unwrap(), Network::Regtest);
+    /// let script = |key: &PrivateKey| Address::p2wpkh(&key.public_key(&secp), Network::Regtest)
+    ///     .unwrap()
+    ///     .script_pubkey();
+    /// let address = SilentPaymentAddress {
+    ///     scan: key(1).public_key(&secp).inner,
+    ///     spend: key(2).public_key(&secp).inner,
+    ///     network: Network::Regtest,
+    /// };
+    /// let (taker_key, maker_key) = (key(3), key(4));
+    /// let outpoints = [OutPoint::default(), OutPoint { vout: 1, ..Default::default() }];
+    ///
+    /// // Taker's own keys alone derive the same output as the taker and maker keys split in shares
+    /// let mut alone = SilentPaymentSender::new(address);
+    /// let own = [(taker_key.inner, script(&taker_key)), (maker_key.inner, script(&maker_key))];
+    /// let expected = alone.derive(&outpoints, &own, &[]).unwrap();
+    ///
+    /// let mut sender = SilentPaymentSender::new(address);
+    /// let blinded = sender.blinded_scan_key().unwrap();
+    /// let share = SilentPaymentShare::new(&[(outpoints[1], maker_key.inner, script(&maker_key))], &blinded)
+    ///     .unwrap()
+    ///     .unwrap();
+    /// let shares = [(share.verify(&blinded).unwrap(), share.share)];
+    /// let own = [(taker_key.inner, script(&taker_key))];
+    /// assert_eq!(sender.derive(&outpoints, &own, &shares).unwrap(), expected);
+    /// ```
+    pub fn derive(
+        &mut self,
+        outpoints: &[OutPoint],
+        own_inputs: &[(SecretKey, Script)],
+        shares: &[(PublicKey, PublicKey)],
+    ) -> Result<TweakedPublicKey, Error> {
+        let secp = Secp256k1::new();
+        let mut own_secrets = vec![];
+        for (secret_key, script_pubkey) in own_inputs {
+            if let Some(secret) = input_secret(*secret_key, script_pubkey)? {
+                own_secrets.push(secret);
+            }
+        }
+        let own_secret = sum_secrets(own_secrets)?;
+
+        // Sum of every counted input key, and of the keys times the scan key
+        let mut keys = vec![];
+        let mut ecdh = vec![];
+        if let Some(secret) = own_secret {
+            keys.push(PublicKey::from_secret_key(&secp, &secret));
+            ecdh.push(self.address.scan.mul_tweak(&secp, &Scalar::from(secret))?);
+        }
+        let unblind = inverse(&self.blinding)?;
+        for (key, share) in shares {
+            keys.push(*key);
+            ecdh.push(share.mul_tweak(&secp, &unblind)?);
+        }
+        let key_sum = PublicKey::combine_keys(&keys.iter().collect::<Vec<_>>())
+            .map_err(|_| Error::SilentPaymentInputKey)?;
+        let ecdh = PublicKey::combine_keys(&ecdh.iter().collect::<Vec<_>>())
+            .map_err(|_| Error::SilentPaymentInputKey)?;
+
+        let smallest = outpoints
+            .iter()
+            .map(serialize)
+            .min()
+            .ok_or(Error::SilentPaymentInputKey)?;
+        let input_hash = tagged_hash("BIP0352/Inputs", &[&smallest, &key_sum.serialize()]);
+        let input_hash =
+            Scalar::from_be_bytes(input_hash).map_err(|_| Error::SilentPaymentInputKey)?;
+        let shared_secret = ecdh.mul_tweak(&secp, &input_hash)?;
+
+        // One output to the address, so k is 0
+        let tweak = tagged_hash(
+            "BIP0352/SharedSecret",
+            &[&shared_secret.serialize(), &0u32.to_be_bytes()],
+        );
+        let tweak = Scalar::from_be_bytes(tweak).map_err(|_| Error::SilentPaymentInputKey)?;
+        let (output_key, _) = self
+            .address
+            .spend
+            .add_exp_tweak(&secp, &tweak)?
+            .x_only_public_key();
+        let output_key = TweakedPublicKey::dangerous_assume_tweaked(output_key);
+        self.derived = Some((outpoints.to_vec(), output_key));
+        Ok(output_key)
+    }
+
+    /// Errors when the CJ does not spend the inputs the output was derived from, or does not pay amount to it
+    pub fn check(
+        &self,
+        psbt: &bdk::bitcoin::psbt::PartiallySignedTransaction,
+        amount: u64,
+    ) -> Result<(), Error> {
+        let (outpoints, output_key) = self
+            .derived
+            .as_ref()
+            .ok_or(Error::SilentPaymentOutputMissing)?;
+        let mut spent: Vec<OutPoint> = psbt
+            .unsigned_tx
+            .input
+            .iter()
+            .map(|input| input.previous_output)
+            .collect();
+        let mut outpoints = outpoints.clone();
+        spent.sort();
+        outpoints.sort();
+        let script = Script::new_v1_p2tr_tweaked(*output_key);
+        let paid = psbt
+            .unsigned_tx
+            .output
+            .iter()
+            .any(|output| output.script_pubkey == script && output.value == amount);
+        match spent == outpoints && paid {
+            true => Ok(()),
+            false => Err(Error::SilentPaymentOutputMissing),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bdk::bitcoin::{secp256k1::XOnlyPublicKey, Address, PrivateKey};
+
+    fn key(byte: u8) -> SecretKey {
+        SecretKey::from_slice(&[byte; 32]).unwrap()
+    }
+
+    /// Output key the receiver finds with its scan key, from the public keys of the inputs
+    /// Written apart from the sending code as it is checked against this, not the BIP-352 test vectors
+    fn scan(
+        scan_key: SecretKey,
+        spend: PublicKey,
+        outpoints: &[OutPoint],
+        keys: &[PublicKey],
+    ) -> XOnlyPublicKey {
+        let secp = Secp256k1::new();
+        let key_sum = PublicKey::combine_keys(&keys.iter().collect::<Vec<_>>()).unwrap();
+        let smallest = outpoints.iter().map(serialize).min().unwrap();
+        let input_hash = tagged_hash("BIP0352/Inputs", &[&smallest, &key_sum.serialize()]);
+        let shared_secret = key_sum
+            .mul_tweak(&secp, &Scalar::from_be_bytes(input_hash).unwrap())
+            .unwrap()
+            .mul_tweak(&secp, &Scalar::from(scan_key))
+            .unwrap();
+        let tweak = tagged_hash(
+            "BIP0352/SharedSecret",
+            &[&shared_secret.serialize(), &0u32.to_be_bytes()],
+        );
+        spend
+            .add_exp_tweak(&secp, &Scalar::from_be_bytes(tweak).unwrap())
+            .unwrap()
+            .x_only_public_key()
+            .0
+    }
+
+    #[test]
+    fn test_receiver_finds_cj_output() {
+        let secp = Secp256k1::new();
+        let (scan_key, spend_key) = (key(1), key(2));
+        let address = SilentPaymentAddress {
+            scan: PublicKey::from_secret_key(&secp, &scan_key),
+            spend: PublicKey::from_secret_key(&secp, &spend_key),
+            network: Network::Regtest,
+        };
+        let wpkh = |secret: &SecretKey| {
+            Address::p2wpkh(
+                &PrivateKey::new(*secret, Network::Regtest).public_key(&secp),
+                Network::Regtest,
+            )
+            .unwrap()
+            .script_pubkey()
+        };
+        let tr = |secret: &SecretKey| {
+            let internal = KeyPair::from_secret_key(&secp, secret)
+                .x_only_public_key()
+                .0;
+            Address::p2tr(&secp, internal, None, Network::Regtest).script_pubkey()
+        };
+        let outpoint = |vout| OutPoint {
+            vout,
+            ..Default::default()
+        };
+        let (taker_key, maker_key, taproot_key, p2wsh) = (key(3), key(4), key(5), key(6));
+        let outpoints = [outpoint(3), outpoint(0), outpoint(1), outpoint(2)];
+
+        let mut sender = SilentPaymentSender::new(address);
+        let blinded = sender.blinded_scan_key().unwrap();
+        // Maker spends a taproot and a p2wsh utxo, only the taproot one counts
+        let maker_share = SilentPaymentShare::new(
+            &[
+                (outpoints[1], maker_key, wpkh(&maker_key)),
+                (outpoints[2], taproot_key, tr(&taproot_key)),
+                (
+                    outpoints[3],
+                    p2wsh,
+                    Script::new_v0_p2wsh(&wpkh(&p2wsh).wscript_hash()),
+                ),
+            ],
+            &blinded,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(maker_share.pubkeys.len(), 2);
+        for (outpoint, pubkey) in &maker_share.pubkeys {
+            let script = match outpoint.vout {
+                0 => wpkh(&maker_key),
+                _ => tr(&taproot_key),
+            };
+            assert_eq!(input_pubkey(pubkey, &script), Some(*pubkey));
+        }
+        let maker_sum = maker_share.verify(&blinded).unwrap();
+
+        let output_key = sender
+            .derive(
+                &outpoints,
+                &[(taker_key, wpkh(&taker_key))],
+                &[(maker_sum, maker_share.share)],
+            )
+            .unwrap();
+
+        let mut keys = vec![PublicKey::from_secret_key(&secp, &taker_key)];
+        keys.extend(maker_share.pubkeys.iter().map(|(_, pubkey)| *pubkey));
+        assert_eq!(
+            output_key.to_inner(),
+            scan(scan_key, address.spend, &outpoints, &keys)
+        );
+    }
+
+    #[test]
+    fn test_forged_share_rejected() {
+        let secp = Secp256k1::new();
+        let blinded = PublicKey::from_secret_key(&secp, &key(7));
+        let script = Address::p2wpkh(
+            &PrivateKey::new(key(8), Network::Regtest).public_key(&secp),
+            Network::Regtest,
+        )
+        .unwrap()
+        .script_pubkey();
+        let mut share = SilentPaymentShare::new(&[(OutPoint::default(), key(8), script)], &blinded)
+            .unwrap()
+            .unwrap();
+
+        // A share of another key does not prove against the input's key
+        share.share = blinded.mul_tweak(&secp, &Scalar::from(key(9))).unwrap();
+        assert!(matches!(
+            share.verify(&blinded),
+            Err(Error::InvalidSilentPaymentShare(_))
+        ));
+    }
+}
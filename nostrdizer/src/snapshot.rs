@@ -0,0 +1,186 @@
+use crate::types::{IoAuth, NostrdizerOffer};
+
+use bdk::bitcoin::{psbt::PartiallySignedTransaction, OutPoint, TxOut};
+
+use std::collections::HashMap;
+use std::fmt;
+
+/// What the wallet knows of one of its unspent UTXOs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WalletUtxo {
+    pub tx_out: TxOut,
+    pub confirmed: bool,
+}
+
+/// How the wallet's view of a taker input changed since the CJ was built
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Drift {
+    /// No longer unspent in the wallet, spent by another app sharing it or dropped by a reorg
+    Gone(OutPoint),
+    /// Pays another value or script than when it was selected
+    Changed(OutPoint),
+    /// Was confirmed and is not anymore, its block was reorged out
+    Unconfirmed(OutPoint),
+}
+
+impl Drift {
+    pub fn outpoint(&self) -> OutPoint {
+        match self {
+            Drift::Gone(outpoint) | Drift::Changed(outpoint) | Drift::Unconfirmed(outpoint) => {
+                *outpoint
+            }
+        }
+    }
+}
+
+impl fmt::Display for Drift {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Drift::Gone(outpoint) => write!(f, "{outpoint} is no longer unspent"),
+            Drift::Changed(outpoint) => write!(f, "{outpoint} changed"),
+            Drift::Unconfirmed(outpoint) => write!(f, "{outpoint} is no longer confirmed"),
+        }
+    }
+}
+
+/// The wallet's view of the taker's inputs of a CJ when it was built
+/// Checked again before the taker signs so a concurrent spend or reorg aborts the round
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct UtxoSnapshot {
+    utxos: HashMap<OutPoint, Option<WalletUtxo>>,
+}
+
+impl UtxoSnapshot {
+    /// Snapshot of the inputs of the CJ that are not a maker's, from the wallet's unspent UTXOs
+    /// ```
+    /// use nostrdizer::{
+    ///     snapshot::{Drift, UtxoSnapshot, WalletUtxo},
+    ///     test_utils::psbt,
+    /// };
+    /// use std::collections::HashMap;
+    ///
+    /// let cj = psbt(&[60_000], &[50_000, 9_000]);
+    /// let outpoint = cj.unsigned_tx.input[0].previous_output;
+    /// let utxo = WalletUtxo {
+    ///     tx_out: cj.inputs[0].witness_utxo.clone().unwrap(),
+    ///     confirmed: true,
+    /// };
+    /// let unspent = HashMap::from([(outpoint, utxo)]);
+    /// let snapshot = UtxoSnapshot::new(&cj, &[], &unspent);
+    ///
+    /// assert!(snapshot.drift(&unspent).is_empty());
+    /// // Another app sharing the wallet spent it
+    /// assert_eq!(snapshot.drift(&HashMap::new()), vec![Drift::Gone(outpoint)]);
+    /// ```
+    pub fn new(
+        psbt: &PartiallySignedTransaction,
+        maker_inputs: &[(NostrdizerOffer, IoAuth)],
+        unspent: &HashMap<OutPoint, WalletUtxo>,
+    ) -> Self {
+        let maker_outpoints: Vec<OutPoint> = maker_inputs
+            .iter()
+            .flat_map(|(_, io_auth)| io_auth.utxos.iter().map(|(outpoint, _)| *outpoint))
+            .collect();
+        let utxos = psbt
+            .unsigned_tx
+            .input
+            .iter()
+            .map(|txin| txin.previous_output)
+            .filter(|outpoint| !maker_outpoints.contains(outpoint))
+            .map(|outpoint| (outpoint, unspent.get(&outpoint).cloned()))
+            .collect();
+        Self { utxos }
+    }
+
+    /// Outpoints of the taker's inputs
+    pub fn outpoints(&self) -> impl Iterator<Item = &OutPoint> {
+        self.utxos.keys()
+    }
+
+    /// How the taker's inputs differ in the wallet's unspent UTXOs now, empty if none do
+    /// Inputs the wallet did not have when the snapshot was taken are gone
+    pub fn drift(&self, unspent: &HashMap<OutPoint, WalletUtxo>) -> Vec<Drift> {
+        let mut drift: Vec<Drift> = self
+            .utxos
+            .iter()
+            .filter_map(|(outpoint, before)| match (before, unspent.get(outpoint)) {
+                (Some(before), Some(now)) if before.tx_out != now.tx_out => {
+                    Some(Drift::Changed(*outpoint))
+                }
+                (Some(before), Some(now)) if before.confirmed && !now.confirmed => {
+                    Some(Drift::Unconfirmed(*outpoint))
+                }
+                (Some(_), Some(_)) => None,
+                _ => Some(Drift::Gone(*outpoint)),
+            })
+            .collect();
+        drift.sort_by_key(Drift::outpoint);
+        drift
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{io_auth, offer, psbt};
+    use bdk::bitcoin::psbt::Input;
+
+    fn unspent(cj: &PartiallySignedTransaction) -> HashMap<OutPoint, WalletUtxo> {
+        cj.unsigned_tx
+            .input
+            .iter()
+            .zip(&cj.inputs)
+            .map(|(txin, input)| {
+                let utxo = WalletUtxo {
+                    tx_out: input.witness_utxo.clone().unwrap(),
+                    confirmed: true,
+                };
+                (txin.previous_output, utxo)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_maker_inputs_not_snapshot() {
+        let cj = psbt(&[120_000, 150_000], &[100_000, 20_000, 100_000, 49_000]);
+        let mut maker = io_auth(0);
+        maker.utxos = vec![(cj.unsigned_tx.input[0].previous_output, Input::default())];
+        let snapshot = UtxoSnapshot::new(&cj, &[(offer("maker", 500), maker)], &unspent(&cj));
+
+        let outpoints: Vec<&OutPoint> = snapshot.outpoints().collect();
+        assert_eq!(outpoints, vec![&cj.unsigned_tx.input[1].previous_output]);
+        // Makers spending their own inputs is not the taker's wallet changing
+        let mut now = unspent(&cj);
+        now.remove(&cj.unsigned_tx.input[0].previous_output);
+        assert!(snapshot.drift(&now).is_empty());
+    }
+
+    #[test]
+    fn test_drift() {
+        let cj = psbt(&[60_000, 50_000, 40_000, 30_000], &[170_000]);
+        let outpoints: Vec<OutPoint> = cj
+            .unsigned_tx
+            .input
+            .iter()
+            .map(|txin| txin.previous_output)
+            .collect();
+        let mut before = unspent(&cj);
+        // Not in the wallet when the CJ was built
+        before.remove(&outpoints[3]);
+        let snapshot = UtxoSnapshot::new(&cj, &[], &before);
+
+        let mut now = unspent(&cj);
+        now.remove(&outpoints[0]);
+        now.get_mut(&outpoints[1]).unwrap().tx_out.value = 1;
+        now.get_mut(&outpoints[2]).unwrap().confirmed = false;
+        assert_eq!(
+            snapshot.drift(&now),
+            vec![
+                Drift::Gone(outpoints[0]),
+                Drift::Changed(outpoints[1]),
+                Drift::Unconfirmed(outpoints[2]),
+                Drift::Gone(outpoints[3]),
+            ]
+        );
+    }
+}
@@ -0,0 +1,125 @@
+use crate::{
+    errors::Error,
+    order_book::MakerPresence,
+    types::{NostrdizerMessage, NostrdizerMessages, PresenceStatus, PRESENCE},
+    utils::{event_expiration, event_network},
+};
+
+use bdk::bitcoin::Network;
+use nostr_rust::{nostr_client::Client as NostrClient, req::ReqFilter};
+
+/// Seconds between checks of the primary's presence
+pub const POLL_INTERVAL: u64 = 30;
+
+/// Seconds without a refresh of the primary's online presence before a standby takes over
+/// Longer than the most seconds between offer refreshes so a primary waiting for fills is not taken over
+pub const TAKEOVER_AFTER: u64 = 900;
+
+/// Whether the primary sharing the maker identity stopped, so the standby takes over its offers and sessions
+/// It stopped when it published offline presence, or its online presence expired or was not refreshed for
+/// `takeover_after` seconds. A primary that published no presence is waited on from `watching_since`
+/// ```
+/// use nostrdizer::{order_book::MakerPresence, standby::primary_stopped, types::PresenceStatus};
+///
+/// let heartbeat = MakerPresence {
+///     status: PresenceStatus::Online,
+///     expires_at: Some(900),
+///     created_at: 0,
+/// };
+/// assert!(!primary_stopped(Some(&heartbeat), 0, 900, 600));
+/// // Heartbeats stopped
+/// assert!(primary_stopped(Some(&heartbeat), 0, 900, 900));
+///
+/// let shut_down = MakerPresence {
+///     status: PresenceStatus::Offline,
+///     expires_at: None,
+///     created_at: 0,
+/// };
+/// assert!(primary_stopped(Some(&shut_down), 0, 900, 10));
+/// ```
+pub fn primary_stopped(
+    presence: Option<&MakerPresence>,
+    watching_since: u64,
+    takeover_after: u64,
+    now: u64,
+) -> bool {
+    match presence {
+        Some(presence) if presence.status == PresenceStatus::Online => {
+            let expired = presence
+                .expires_at
+                .map_or(false, |expires_at| now >= expires_at);
+            expired || now >= presence.created_at + takeover_after
+        }
+        Some(_) => true,
+        None => now >= watching_since + takeover_after,
+    }
+}
+
+/// Latest presence the maker published for network, none if relays have none
+pub fn fetch_presence(
+    nostr_client: &mut NostrClient,
+    maker: &str,
+    network: Network,
+) -> Result<Option<MakerPresence>, Error> {
+    let filter = ReqFilter {
+        ids: None,
+        authors: Some(vec![maker.to_string()]),
+        kinds: Some(vec![PRESENCE]),
+        e: None,
+        p: None,
+        since: None,
+        until: None,
+        limit: None,
+    };
+
+    let presence = nostr_client
+        .get_events_of(vec![filter])?
+        .into_iter()
+        .filter(|event| {
+            event.pub_key == maker
+                && event.verify().is_ok()
+                && event_network(event) == Some(network)
+        })
+        .filter_map(|event| match serde_json::from_str(&event.content) {
+            Ok(NostrdizerMessage {
+                event: NostrdizerMessages::Presence(presence),
+                ..
+            }) => Some(MakerPresence {
+                status: presence.status,
+                expires_at: event_expiration(&event),
+                created_at: event.created_at,
+            }),
+            _ => None,
+        })
+        .max_by_key(|presence| presence.created_at);
+    Ok(presence)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_takeover_waits_for_heartbeats() {
+        // Primary not seen yet is given the same time to heartbeat
+        assert!(!primary_stopped(None, 100, 900, 500));
+        assert!(primary_stopped(None, 100, 900, 1_000));
+
+        // Refreshed presence keeps the standby silent
+        let heartbeat = |created_at| MakerPresence {
+            status: PresenceStatus::Online,
+            expires_at: Some(created_at + 900),
+            created_at,
+        };
+        assert!(!primary_stopped(Some(&heartbeat(600)), 0, 900, 1_200));
+
+        // Presence without an expiration still has to be refreshed
+        let presence = MakerPresence {
+            status: PresenceStatus::Online,
+            expires_at: None,
+            created_at: 0,
+        };
+        assert!(!primary_stopped(Some(&presence), 0, 300, 299));
+        assert!(primary_stopped(Some(&presence), 0, 300, 300));
+    }
+}
@@ -0,0 +1,367 @@
+use crate::{
+    errors::Error,
+    types::{Amount, NETWORK_STATS},
+    utils::{event_network, network_tag},
+};
+
+use bdk::bitcoin::Network;
+use nostr_rust::{
+    events::EventPrepare, keys::get_random_secret_key, nostr_client::Client as NostrClient,
+    req::ReqFilter, Identity,
+};
+use serde::{Deserialize, Serialize};
+
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Min seconds between stats reports of a taker
+pub const STATS_INTERVAL: u64 = 24 * 60 * 60;
+
+/// Times in reports are rounded down to the hour, so a report does not point at the round it ends with
+const TIME_GRANULARITY: u64 = 60 * 60;
+
+/// Largest size of the 1-2-5 series, in sats, that is not over amount
+/// ```
+/// use nostrdizer::{stats::size_bucket, types::Amount};
+///
+/// assert_eq!(size_bucket(Amount::from_sat(1_234_567)), Amount::from_sat(1_000_000));
+/// assert_eq!(size_bucket(Amount::from_sat(4_999_999)), Amount::from_sat(2_000_000));
+/// assert_eq!(size_bucket(Amount::from_sat(5_000_000)), Amount::from_sat(5_000_000));
+/// assert_eq!(size_bucket(Amount::ZERO), Amount::ZERO);
+/// ```
+pub fn size_bucket(amount: Amount) -> Amount {
+    let sats = amount.to_sat();
+    let mut bucket = 0;
+    let mut magnitude: u64 = 1;
+    loop {
+        for step in [1, 2, 5] {
+            let size = match magnitude.checked_mul(step) {
+                Some(size) if size <= sats => size,
+                _ => return Amount::from_sat(bucket),
+            };
+            bucket = size;
+        }
+        magnitude = match magnitude.checked_mul(10) {
+            Some(magnitude) => magnitude,
+            None => return Amount::from_sat(bucket),
+        };
+    }
+}
+
+/// Lower median of values each counted weight times, none if there are none
+fn median(mut values: Vec<(u64, u64)>) -> Option<u64> {
+    values.sort_unstable();
+    let total: u64 = values.iter().map(|(_, weight)| weight).sum();
+    let mut counted = 0;
+    for (value, weight) in values {
+        counted += weight;
+        if counted * 2 >= total && weight > 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+fn coarse(time: u64) -> u64 {
+    time - time % TIME_GRANULARITY
+}
+
+/// Completed round as a taker keeps it for its stats, its size is already bucketed
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RoundSample {
+    #[serde(with = "bdk::bitcoin::util::amount::serde::as_sat")]
+    pub size: Amount,
+    /// Fee paid to each maker on average, in parts per million of the amount sent
+    pub fee_ppm: u64,
+    pub makers: u32,
+}
+
+impl RoundSample {
+    /// Round sending amount with makers, that were paid maker_fees in all
+    pub fn new(amount: Amount, maker_fees: Amount, makers: usize) -> Self {
+        let per_maker = maker_fees.to_sat() / makers.max(1) as u64;
+        Self {
+            size: size_bucket(amount),
+            fee_ppm: per_maker.saturating_mul(1_000_000) / amount.to_sat().max(1),
+            makers: makers as u32,
+        }
+    }
+}
+
+/// Anonymous stats of the rounds a taker completed in a period
+/// Only takers report, so each round is counted once
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct NetworkStats {
+    /// Start of the period
+    pub since: u64,
+    /// End of the period
+    pub until: u64,
+    pub rounds: u32,
+    /// Median maker fee, in parts per million of the amount sent
+    pub median_fee_ppm: u64,
+    pub median_makers: u32,
+    /// Rounds by size bucket, in sats
+    pub sizes: BTreeMap<u64, u32>,
+}
+
+impl NetworkStats {
+    /// Counts in the report add up, reports that do not are not totalled
+    pub fn is_consistent(&self) -> bool {
+        self.rounds > 0
+            && self.since <= self.until
+            && self
+                .sizes
+                .values()
+                .map(|rounds| *rounds as u64)
+                .sum::<u64>()
+                == self.rounds as u64
+    }
+}
+
+/// Rounds a taker completed since it last reported, kept between runs
+/// ```
+/// use nostrdizer::{
+///     stats::{RoundSample, StatsLog, STATS_INTERVAL},
+///     types::Amount,
+/// };
+///
+/// let mut log = StatsLog::default();
+/// let start = 1_700_000_000;
+/// log.record(RoundSample::new(Amount::from_sat(1_500_000), Amount::from_sat(450), 3), start);
+/// // The first report waits a whole period
+/// assert!(!log.is_due(start, STATS_INTERVAL));
+/// assert!(log.is_due(start + STATS_INTERVAL, STATS_INTERVAL));
+///
+/// let stats = log.stats(start + STATS_INTERVAL);
+/// assert_eq!(stats.rounds, 1);
+/// assert_eq!(stats.median_fee_ppm, 100);
+/// assert_eq!(stats.sizes.get(&1_000_000), Some(&1));
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct StatsLog {
+    pub samples: Vec<RoundSample>,
+    /// Time stats were last reported, or rounds were first logged
+    #[serde(default)]
+    pub reported_at: u64,
+}
+
+impl StatsLog {
+    /// Loads the log from path, an empty one if there is no file
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        match path.exists() {
+            true => Ok(serde_json::from_str(&fs::read_to_string(path)?)?),
+            false => Ok(Self::default()),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        file.write_all(serde_json::to_string_pretty(self)?.as_bytes())?;
+        Ok(())
+    }
+
+    pub fn record(&mut self, sample: RoundSample, now: u64) {
+        if self.reported_at == 0 {
+            self.reported_at = coarse(now);
+        }
+        self.samples.push(sample);
+    }
+
+    /// A period has passed since the last report and there are rounds to report
+    pub fn is_due(&self, now: u64, interval: u64) -> bool {
+        !self.samples.is_empty() && coarse(now) >= self.reported_at + interval
+    }
+
+    /// Stats of the rounds logged, for a period ending now
+    pub fn stats(&self, now: u64) -> NetworkStats {
+        let mut sizes = BTreeMap::new();
+        for sample in &self.samples {
+            *sizes.entry(sample.size.to_sat()).or_insert(0) += 1;
+        }
+        NetworkStats {
+            since: self.reported_at,
+            until: coarse(now),
+            rounds: self.samples.len() as u32,
+            median_fee_ppm: median(self.samples.iter().map(|s| (s.fee_ppm, 1)).collect())
+                .unwrap_or_default(),
+            median_makers: median(self.samples.iter().map(|s| (s.makers as u64, 1)).collect())
+                .unwrap_or_default() as u32,
+            sizes,
+        }
+    }
+
+    /// Drops the rounds once they are reported
+    pub fn clear(&mut self, now: u64) {
+        self.samples.clear();
+        self.reported_at = coarse(now);
+    }
+}
+
+/// Stats reports of the network added up
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NetworkTotals {
+    pub reports: usize,
+    pub rounds: u64,
+    /// Median of the reports' median maker fees, weighted by their rounds, in parts per million
+    pub median_fee_ppm: Option<u64>,
+    pub median_makers: Option<u64>,
+    /// Rounds by size bucket, in sats
+    pub sizes: BTreeMap<u64, u64>,
+}
+
+impl NetworkTotals {
+    /// Totals of the consistent reports
+    pub fn new(reports: &[NetworkStats]) -> Self {
+        let reports: Vec<&NetworkStats> = reports.iter().filter(|r| r.is_consistent()).collect();
+        let mut sizes = BTreeMap::new();
+        for report in &reports {
+            for (size, rounds) in &report.sizes {
+                *sizes.entry(*size).or_insert(0) += *rounds as u64;
+            }
+        }
+        Self {
+            reports: reports.len(),
+            rounds: reports.iter().map(|r| r.rounds as u64).sum(),
+            median_fee_ppm: median(
+                reports
+                    .iter()
+                    .map(|r| (r.median_fee_ppm, r.rounds as u64))
+                    .collect(),
+            ),
+            median_makers: median(
+                reports
+                    .iter()
+                    .map(|r| (r.median_makers as u64, r.rounds as u64))
+                    .collect(),
+            ),
+            sizes,
+        }
+    }
+
+    /// Size bucket with the most rounds
+    pub fn typical_size(&self) -> Option<Amount> {
+        self.sizes
+            .iter()
+            .max_by_key(|(size, rounds)| (**rounds, **size))
+            .map(|(size, _)| Amount::from_sat(*size))
+    }
+}
+
+/// Identity of a fresh key, each report is published with a new one so reports can not be linked
+pub fn reporter_identity() -> Result<Identity, Error> {
+    let (sk, _) = get_random_secret_key();
+    Ok(Identity::from_str(&hex::encode(sk.as_ref()))?)
+}
+
+/// Publishes stats of the network's rounds, returns the id of the event
+pub fn publish(
+    nostr_client: &mut NostrClient,
+    identity: &Identity,
+    network: Network,
+    stats: &NetworkStats,
+) -> Result<String, Error> {
+    let event = EventPrepare {
+        pub_key: identity.public_key_str.clone(),
+        created_at: stats.until,
+        kind: NETWORK_STATS,
+        tags: vec![network_tag(network)],
+        content: serde_json::to_string(stats)?,
+    }
+    .to_event(identity, 0);
+    nostr_client.publish_event(&event)?;
+
+    Ok(event.id)
+}
+
+/// Stats reports of network published since a time, events that do not parse are skipped
+/// An event seen on more than one relay is counted once
+pub fn fetch(
+    nostr_client: &mut NostrClient,
+    network: Network,
+    since: u64,
+) -> Result<Vec<NetworkStats>, Error> {
+    let filter = ReqFilter {
+        ids: None,
+        authors: None,
+        kinds: Some(vec![NETWORK_STATS]),
+        e: None,
+        p: None,
+        since: Some(since),
+        until: None,
+        limit: None,
+    };
+
+    let mut seen = HashSet::new();
+    Ok(nostr_client
+        .get_events_of(vec![filter])?
+        .into_iter()
+        .filter(|event| event.verify().is_ok() && event_network(event) == Some(network))
+        .filter(|event| seen.insert(event.id.clone()))
+        .filter_map(|event| serde_json::from_str::<NetworkStats>(&event.content).ok())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(rounds: u32, median_fee_ppm: u64, sizes: &[(u64, u32)]) -> NetworkStats {
+        NetworkStats {
+            since: 0,
+            until: STATS_INTERVAL,
+            rounds,
+            median_fee_ppm,
+            median_makers: 4,
+            sizes: sizes.iter().copied().collect(),
+        }
+    }
+
+    #[test]
+    fn test_totals_weighted_by_rounds() {
+        let reports = vec![
+            report(1, 500, &[(100_000, 1)]),
+            report(3, 100, &[(1_000_000, 2), (100_000, 1)]),
+            // Sizes do not add up to the rounds
+            report(2, 900, &[(1_000_000, 5)]),
+        ];
+        let totals = NetworkTotals::new(&reports);
+        assert_eq!(totals.reports, 2);
+        assert_eq!(totals.rounds, 4);
+        assert_eq!(totals.median_fee_ppm, Some(100));
+        assert_eq!(totals.median_makers, Some(4));
+        assert_eq!(totals.sizes.get(&100_000), Some(&2));
+        assert_eq!(totals.typical_size(), Some(Amount::from_sat(1_000_000)));
+    }
+
+    #[test]
+    fn test_report_times_are_coarse() {
+        let mut log = StatsLog::default();
+        let start = 1_700_000_123;
+        log.record(
+            RoundSample::new(Amount::from_sat(200_000), Amount::from_sat(20), 1),
+            start,
+        );
+        let stats = log.stats(start + STATS_INTERVAL + 45);
+        assert_eq!(stats.since % TIME_GRANULARITY, 0);
+        assert_eq!(stats.until % TIME_GRANULARITY, 0);
+
+        log.clear(start + STATS_INTERVAL);
+        assert!(log.samples.is_empty());
+        log.record(
+            RoundSample::new(Amount::from_sat(200_000), Amount::from_sat(20), 1),
+            start + STATS_INTERVAL,
+        );
+        assert!(!log.is_due(
+            start + STATS_INTERVAL * 2 - TIME_GRANULARITY,
+            STATS_INTERVAL
+        ));
+        assert!(log.is_due(start + STATS_INTERVAL * 2, STATS_INTERVAL));
+    }
+}
@@ -0,0 +1,113 @@
+use crate::errors::Error;
+
+use log::debug;
+use nostr_rust::{nostr_client::Client as NostrClient, req::ReqFilter};
+use tungstenite::Message;
+
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Subscriptions currently open on any client
+static ACTIVE_SUBSCRIPTIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// Number of subscriptions that have not been closed
+pub fn active_subscriptions() -> usize {
+    ACTIVE_SUBSCRIPTIONS.load(Ordering::SeqCst)
+}
+
+/// Counts a subscription that is not closed by a guard
+pub(crate) fn opened() {
+    ACTIVE_SUBSCRIPTIONS.fetch_add(1, Ordering::SeqCst);
+}
+
+/// Counts a closed subscription
+pub(crate) fn closed() {
+    // Never goes below 0 if closed is called without opened
+    let _ = ACTIVE_SUBSCRIPTIONS.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |count| {
+        count.checked_sub(1)
+    });
+}
+
+/// What a subscription needs of the client of the relays it is on
+/// Implemented for the nostr client, tests and doc examples read from relays stood in for by `test_utils::MockRelay`
+pub trait RelayClient {
+    /// Sends the filters to the relays, returning the id of the subscription
+    fn subscribe(&mut self, filters: Vec<ReqFilter>) -> Result<String, Error>;
+
+    fn unsubscribe(&mut self, id: &str) -> Result<(), Error>;
+
+    /// Messages relays sent since the last call, with the url of the relay that sent each
+    fn next_data(&mut self) -> Result<Vec<(String, Message)>, Error>;
+}
+
+impl RelayClient for NostrClient {
+    fn subscribe(&mut self, filters: Vec<ReqFilter>) -> Result<String, Error> {
+        Ok(NostrClient::subscribe(self, filters)?)
+    }
+
+    fn unsubscribe(&mut self, id: &str) -> Result<(), Error> {
+        NostrClient::unsubscribe(self, id)?;
+        Ok(())
+    }
+
+    fn next_data(&mut self) -> Result<Vec<(String, Message)>, Error> {
+        Ok(NostrClient::next_data(self)?)
+    }
+}
+
+/// Subscription that is unsubscribed from when dropped,
+/// so early returns and errors while waiting do not leak it
+/// The client the subscription is on is used through the guard
+pub struct SubscriptionGuard<'a, C: RelayClient = NostrClient> {
+    client: &'a mut C,
+    id: String,
+}
+
+impl<'a, C: RelayClient> SubscriptionGuard<'a, C> {
+    pub fn subscribe(client: &'a mut C, filters: Vec<ReqFilter>) -> Result<Self, Error> {
+        let id = client.subscribe(filters)?;
+        opened();
+        Ok(Self { client, id })
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+impl<C: RelayClient> Deref for SubscriptionGuard<'_, C> {
+    type Target = C;
+
+    fn deref(&self) -> &C {
+        self.client
+    }
+}
+
+impl<C: RelayClient> DerefMut for SubscriptionGuard<'_, C> {
+    fn deref_mut(&mut self) -> &mut C {
+        self.client
+    }
+}
+
+impl<C: RelayClient> Drop for SubscriptionGuard<'_, C> {
+    fn drop(&mut self) {
+        if let Err(err) = self.client.unsubscribe(&self.id) {
+            debug!("Could not unsubscribe from {}: {err:?}", self.id);
+        }
+        closed();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_count() {
+        let before = active_subscriptions();
+        opened();
+        assert_eq!(active_subscriptions(), before + 1);
+        closed();
+        assert_eq!(active_subscriptions(), before);
+    }
+}
@@ -1,25 +1,54 @@
+pub mod scheduler;
+
 use super::{
+    address_store::AddressStore,
+    capabilities::Capabilities,
+    commitment::SchemeId,
+    coop::{self, COOP_TIMEOUT},
+    display,
+    encryption::{peer_key, Envelope},
     errors::Error,
+    fees::{self, rel_fee_amount, to_ppm},
+    fidelity_bond::{meets_min_bond, rank_bonds, FidelityBondProof, VerifiedBond},
+    inbox::{IngestStats, SessionInbox},
+    keystore::{CachedCommitment, Keystore},
+    labels::LabelStore,
+    latency::{self, LatencyClass, ProtocolStep, RelayLatency},
+    order_book::OrderBook,
+    podle::MAX_PODLE_INDEX,
+    publication::Publisher,
+    relay_pool::{RelayPool, RelayRole},
+    reputation::{Reputation, ResponseTimer, Step},
+    round::{round_id, RoundAccounting, INPUT_VSIZE, OUTPUT_VSIZE, TX_OVERHEAD_VSIZE},
+    silent_payment::{self, input_pubkey, SilentPaymentSender},
+    snapshot::UtxoSnapshot,
+    stats::{self, NetworkStats},
+    subscription::SubscriptionGuard,
+    transcript::{Completion, RoundRecord, Transcript},
     types::{
-        AuthCommitment, Fill, IoAuth, NostrdizerMessage, NostrdizerMessageKind, NostrdizerMessages,
-        NostrdizerOffer, Offer, TakerConfig, Transaction, AUTH, FILL, IOAUTH, PUBKEY,
-        SIGNED_TRANSACTION, TRANSACTION,
+        Abort, AuthBinding, AuthProof, BitcoinTransaction, Confirm, CoopJoin, CounterOffer, Fill,
+        IoAuth, NostrdizerMessage, NostrdizerMessageKind, NostrdizerMessages, NostrdizerOffer,
+        Offer, PaymentDestination, Reject, RejectReason, TakerConfig, Transaction, ABORT,
+        ABORT_VERSION, AUTH, AUTH_BINDING_VERSION, CONFIRM, CONFIRM_VERSION, COOP_JOIN, FILL,
+        FILL_ACK, FILL_ACK_VERSION, IOAUTH, PROTOCOL_VERSION, PSBT_INPUT_VERSION, PUBKEY,
+        REBUILD_VERSION, REJECT, SEALED, SEALED_VERSION, SESSION_TIMEOUT, SIGNED_TRANSACTION,
+        SILENT_PAYMENT_VERSION, TAGGED_VERSION, TRANSACTION,
     },
-    utils::{self, decrypt_message},
+    utils::{self, decrypt_message, unseal_event},
 };
 
-use bdk::bitcoin::{psbt::PartiallySignedTransaction, Amount, Denomination};
-use bitcoin_hashes::{sha256, Hash};
+use bdk::bitcoin::{
+    psbt::PartiallySignedTransaction, secp256k1::SecretKey, Amount, Network, OutPoint, Script,
+};
+use bitcoin_hashes::sha256;
 
-use log::debug;
+use log::{debug, warn};
+use rand::thread_rng;
 
 #[cfg(feature = "bitcoincore")]
 use bitcoincore_rpc::Client as RPCClient;
 use nostr_rust::{
-    events::{Event, EventPrepare},
-    nostr_client::Client as NostrClient,
-    req::ReqFilter,
-    utils::get_timestamp,
+    events::Event, nostr_client::Client as NostrClient, req::ReqFilter, utils::get_timestamp,
     Identity,
 };
 
@@ -30,13 +59,60 @@ use std::collections::HashSet;
 pub struct Taker {
     pub identity: Identity,
     pub config: TakerConfig,
+    /// Client of the offer relays, offers are fetched and fills sent on it
     pub nostr_client: NostrClient,
+    /// Client of the session relays makers acked in the round, sessions run on the offer relays without one
+    pub session_client: Option<NostrClient>,
     #[cfg(feature = "bitcoincore")]
     pub rpc_client: RPCClient,
+    /// Passphrase the wallet is unlocked with to sign
+    #[cfg(feature = "bitcoincore")]
+    pub wallet_passphrase: Option<String>,
     #[cfg(feature = "bdk")]
     pub wallet: Wallet<AnyDatabase>,
     #[cfg(feature = "bdk")]
     pub blockchain: AnyBlockchain,
+    pub network: Network,
+    /// Makers whose offers support sealed messages
+    pub sealed_peers: HashSet<String>,
+    /// Makers whose offers read tagged messages
+    pub tagged_peers: HashSet<String>,
+    /// Verified encryption keys makers advertised, messages to them are encrypted to it rather than their nostr key
+    pub encryption_keys: HashMap<String, String>,
+    pub order_book: OrderBook,
+    pub relay_pool: RelayPool,
+    /// Addresses makers gave in earlier rounds
+    pub address_store: AddressStore,
+    /// Round id of the session with each maker
+    pub round_ids: HashMap<String, String>,
+    /// Messages of the session with each maker
+    pub transcripts: HashMap<String, Transcript>,
+    /// Events taken in from maker sessions
+    pub ingest_stats: IngestStats,
+    /// Response times and outcomes of makers
+    pub reputation: Reputation,
+    pub response_timer: ResponseTimer,
+    pub publisher: Publisher,
+    /// Counter-offers taken from makers in the round being matched
+    pub counter_offers: HashMap<String, CounterOffer>,
+    /// Podle commitments generated and revealed, kept between runs
+    pub keystore: Keystore,
+    /// Commitment every fill of the round being matched is sent with
+    pub round_commitment: Option<CachedCommitment>,
+    /// Silent payment of the round being matched, its scan key is blinded anew each round
+    pub silent_payment: Option<SilentPaymentSender>,
+    /// Latency classes the makers of the round being matched advertised
+    pub latency_classes: HashMap<String, LatencyClass>,
+    /// Fidelity bonds of the makers of the round being matched that verified against the chain
+    pub fidelity_bonds: HashMap<String, VerifiedBond>,
+    /// Inputs makers of the round being matched sent and the taker took, spares included
+    pub round_inputs: usize,
+    /// When the last fills were sent, acks published before it answer another round
+    pub fills_sent_at: u64,
+    /// What the wallet backend can do
+    pub capabilities: Capabilities,
+    /// Labels of the wallet, labelled utxos are of the source of their label
+    pub labels: LabelStore,
 }
 
 impl Taker {
@@ -49,7 +125,7 @@ impl Taker {
         let filter = ReqFilter {
             ids: None,
             authors: None,
-            kinds: Some(vec![PUBKEY]),
+            kinds: Some(vec![PUBKEY, SEALED]),
             e: None,
             p: Some(vec![self.identity.public_key_str.clone()]),
             since: None,
@@ -57,37 +133,40 @@ impl Taker {
             limit: None,
         };
 
-        let subscription_id = self.nostr_client.subscribe(vec![filter])?;
+        let mut subscription = SubscriptionGuard::subscribe(&mut self.nostr_client, vec![filter])?;
 
         let started_waiting = get_timestamp();
         loop {
-            let data = self.nostr_client.next_data()?;
+            let data = subscription.next_data()?;
             for (_, message) in data {
                 if let Ok(event) = serde_json::from_str::<Value>(&message.to_string()) {
-                    if event[0] == "EOSE" && event[1].as_str() == Some(&subscription_id) {
+                    if event[0] == "EOSE" && event[1].as_str() == Some(subscription.id()) {
                         break;
                     }
                     if let Ok(event) = serde_json::from_value::<Event>(event[2].clone()) {
+                        let event = match unseal_event(&self.identity, event) {
+                            Ok(event) => event,
+                            Err(_) => continue,
+                        };
                         if event.verify().is_ok()
                             && event.kind == PUBKEY
                             && event.tags[0].contains(&self.identity.public_key_str)
                         {
                             if let NostrdizerMessages::PubKey(_pubkey) = decrypt_message(
                                 &self.identity.secret_key,
-                                &event.pub_key,
+                                peer_key(&self.encryption_keys, &event.pub_key),
                                 &event.content,
                             )?
                             .event
                             {
-                                self.nostr_client.unsubscribe(&subscription_id)?;
                                 return Ok(());
                             }
                         }
                     }
                 }
             }
-            if started_waiting.gt(&(started_waiting + 300)) {
-                return Err(Error::TakerFailedToSendTransaction);
+            if get_timestamp() - started_waiting > SESSION_TIMEOUT {
+                return Err(Error::MakersFailedToRespond);
             }
         }
     }
@@ -101,75 +180,139 @@ impl Taker {
     }
     */
 
-    /// Gets signed peer tx
+    /// Gets signed peer tx from the makers in the round
+    /// Signatures from other pub keys are dropped unread
     pub fn get_signed_peer_transaction(
         &mut self,
-        peer_count: usize,
+        makers: &[String],
     ) -> Result<Vec<PartiallySignedTransaction>, Error> {
         let filter = ReqFilter {
             ids: None,
             authors: None,
-            kinds: Some(vec![SIGNED_TRANSACTION]),
+            kinds: Some(vec![SIGNED_TRANSACTION, SEALED]),
             e: None,
             p: Some(vec![self.identity.public_key_str.clone()]),
-            since: None,
+            // Ignore signatures of a previous attempt at the round
+            since: Some(get_timestamp()),
             until: None,
             limit: None,
         };
 
-        let subcription_id = self.nostr_client.subscribe(vec![filter])?;
+        let client = self
+            .session_client
+            .as_mut()
+            .unwrap_or(&mut self.nostr_client);
+        let mut subscription = SubscriptionGuard::subscribe(client, vec![filter])?;
 
-        let mut peer_signed_transaction = HashMap::new();
+        let mut inbox = SessionInbox::new(makers.iter().cloned());
+        let started_waiting = get_timestamp();
         loop {
-            let data = self.nostr_client.next_data()?;
-            for (_, message) in data {
+            let data = subscription.next_data()?;
+            for (relay, message) in data {
+                self.relay_pool
+                    .record_message(RelayRole::Session, &relay, get_timestamp());
                 if let Ok(event) = serde_json::from_str::<Value>(&message.to_string()) {
-                    if event[0] == "EOSE" && event[1].as_str() == Some(&subcription_id) {
+                    if event[0] == "EOSE" && event[1].as_str() == Some(subscription.id()) {
                         break;
                     }
 
                     if let Ok(event) = serde_json::from_value::<Event>(event[2].clone()) {
+                        let event = match unseal_event(&self.identity, event) {
+                            Ok(event) => event,
+                            Err(_) => continue,
+                        };
+                        self.relay_pool
+                            .record_event(RelayRole::Session, &relay, &event);
                         if event.verify().is_ok()
                             && event.kind == SIGNED_TRANSACTION
                             && event.tags[0].contains(&self.identity.public_key_str)
+                            && inbox.is_expected(&event.pub_key)
                         {
                             if let NostrdizerMessages::SignedCJ(signed_tx) = decrypt_message(
                                 &self.identity.secret_key,
-                                &event.pub_key,
+                                peer_key(&self.encryption_keys, &event.pub_key),
                                 &event.content,
                             )?
                             .event
                             {
-                                peer_signed_transaction
-                                    .insert(event.pub_key.to_string(), signed_tx);
-
-                                if peer_signed_transaction.len() >= peer_count {
-                                    /*
-                                    let txs: Vec<String> = peer_signed_transaction
-                                        .values()
-                                        .map(|p| hex::encode(p.tx.clone()))
-                                        .collect();
-
-                                    let combined_transaction = "".to_string();
-                                    // self.combine_raw_transaction(&txs)?;
-                                        */
-
-                                    let psbts = peer_signed_transaction
-                                        .values()
-                                        .map(|p| p.psbt.clone())
-                                        .collect();
-
-                                    return Ok(psbts);
+                                if inbox.accept(&event.pub_key, signed_tx) {
+                                    record_response(
+                                        &mut self.response_timer,
+                                        &mut self.reputation,
+                                        &event.pub_key,
+                                        Step::Sign,
+                                    );
+                                    debug!(
+                                        "[round {}] Got signed transaction from {}",
+                                        round_label(&self.round_ids, &event.pub_key),
+                                        event.pub_key
+                                    );
+                                    self.transcripts
+                                        .entry(event.pub_key.clone())
+                                        .or_default()
+                                        .record(&event.id);
                                 }
                             }
                         }
                     }
                 }
             }
+            if inbox.is_complete()
+                || get_timestamp() - started_waiting
+                    > step_timeout(
+                        &self.latency_classes,
+                        ProtocolStep::SignedTx,
+                        &inbox.missing(),
+                    )
+            {
+                break;
+            }
         }
+        drop(subscription);
+        self.record_ingest("signed transactions", &inbox.stats);
+
+        if !inbox.is_complete() {
+            return Err(Error::MakersFailedToSign(inbox.peers()));
+        }
+        Ok(inbox
+            .into_messages()
+            .into_values()
+            .map(|signed_tx| signed_tx.psbt)
+            .collect())
+    }
+
+    /// Adds the stats of a session inbox to the taker totals
+    fn record_ingest(&mut self, messages: &str, stats: &IngestStats) {
+        match stats.unsolicited {
+            0 => debug!("Ingested {messages}: {stats:?}"),
+            _ => warn!("Dropped unsolicited {messages}: {stats:?}"),
+        }
+        self.ingest_stats.add(stats);
+    }
+
+    /// Drops makers that did not sign from the round
+    /// Returns the accounting the rebuilt transaction is verified against
+    /// Makers that signed have to be on `REBUILD_VERSION` to sign the rebuilt transaction
+    pub fn rebuild_round(
+        &self,
+        accounting: &RoundAccounting,
+        peer_inputs: &mut Vec<(NostrdizerOffer, IoAuth)>,
+        signed_makers: &[String],
+    ) -> Result<RoundAccounting, Error> {
+        if signed_makers.len() < self.config.minium_makers {
+            return Err(Error::NotEnoughMakers);
+        }
+        let outdated = rebuild_unsupported(peer_inputs, signed_makers);
+        if !outdated.is_empty() {
+            return Err(Error::RebuildUnsupported(outdated));
+        }
+        peer_inputs.retain(|(offer, _)| signed_makers.contains(&offer.maker));
+
+        accounting.retain_makers(signed_makers)
     }
 
     /// Gets peer maker inputs from relay
+    /// Returns the inputs of the makers that sent them before the timeout, which may be fewer than `peer_count`
     pub fn get_peer_inputs(
         &mut self,
         peer_count: usize,
@@ -179,7 +322,7 @@ impl Taker {
         let filter = ReqFilter {
             ids: None,
             authors: None,
-            kinds: Some(vec![IOAUTH]),
+            kinds: Some(vec![IOAUTH, SEALED]),
             e: None,
             p: Some(vec![self.identity.public_key_str.clone()]),
             since: None,
@@ -187,59 +330,175 @@ impl Taker {
             limit: None,
         };
 
-        let subcription_id = &self.nostr_client.subscribe(vec![filter])?;
+        let client = self
+            .session_client
+            .as_mut()
+            .unwrap_or(&mut self.nostr_client);
+        let mut subscription = SubscriptionGuard::subscribe(client, vec![filter])?;
 
-        let mut peer_inputs = vec![];
+        let mut inbox = SessionInbox::new(matching_offers.iter().map(|offer| offer.maker.clone()));
+        // Makers whose inputs are turned down, aborted once the subscription is closed
+        let mut turned_down = vec![];
         // Get time stamp that waiting started
         let started_waiting = get_timestamp();
         loop {
-            let data = &self.nostr_client.next_data()?;
-            for (_, message) in data {
+            let data = subscription.next_data()?;
+            for (relay, message) in data {
+                self.relay_pool
+                    .record_message(RelayRole::Session, &relay, get_timestamp());
                 if let Ok(event) = serde_json::from_str::<Value>(&message.to_string()) {
-                    if event[0] == "EOSE" && event[1].as_str() == Some(subcription_id) {
+                    if event[0] == "EOSE" && event[1].as_str() == Some(subscription.id()) {
                         break;
                     }
 
                     if let Ok(event) = serde_json::from_value::<Event>(event[2].clone()) {
+                        let event = match unseal_event(&self.identity, event) {
+                            Ok(event) => event,
+                            Err(_) => continue,
+                        };
+                        self.relay_pool
+                            .record_event(RelayRole::Session, &relay, &event);
                         if event.verify().is_ok()
                             && event.kind == IOAUTH
                             && event.tags[0].contains(&self.identity.public_key_str)
+                            && inbox.is_expected(&event.pub_key)
                         {
                             if let NostrdizerMessages::MakerInputs(maker_input) = decrypt_message(
                                 &self.identity.secret_key,
-                                &event.pub_key,
+                                peer_key(&self.encryption_keys, &event.pub_key),
                                 &event.content,
                             )?
                             .event
                             {
-                                peer_inputs.push((
-                                    // Finds the peers matching offer
-                                    // pushes (offer, input)
-                                    matching_offers
-                                        .clone()
-                                        .iter()
-                                        .find(|o| o.maker == event.pub_key)
-                                        .unwrap()
-                                        .clone(),
-                                    maker_input,
-                                ));
+                                // Outputs to addresses of another network, or nonstandard scripts, would burn the funds
+                                if let Err(err) =
+                                    maker_input.addresses().into_iter().try_for_each(|address| {
+                                        utils::check_address(address, self.network)
+                                    })
+                                {
+                                    warn!("Maker {} sent a bad address: {err}", event.pub_key);
+                                    continue;
+                                }
+                                // Inputs of another script type would partition the CJ on chain
+                                if let Some(script_type) = self.config.script_type {
+                                    let mismatched = maker_input.mismatched_inputs(script_type);
+                                    if !mismatched.is_empty() {
+                                        debug!(
+                                            "Maker {} sent inputs that are not {:?}: {:?}",
+                                            event.pub_key, script_type, mismatched
+                                        );
+                                        continue;
+                                    }
+                                }
+                                // Many small inputs bloat the fee every participant pays
+                                let inputs = maker_input.utxos.len();
+                                if let Some(reason) =
+                                    self.config.input_limits.check(inputs, self.round_inputs)
+                                {
+                                    warn!(
+                                        "Maker {} sent too many inputs: {}",
+                                        event.pub_key,
+                                        display::reject_reason(&reason)
+                                    );
+                                    turned_down.push((event.pub_key.clone(), reason));
+                                    inbox.reject(&event.pub_key);
+                                    continue;
+                                }
+                                if inbox.accept(&event.pub_key, maker_input) {
+                                    self.round_inputs += inputs;
+                                    debug!(
+                                        "[round {}] Got inputs from {}",
+                                        round_label(&self.round_ids, &event.pub_key),
+                                        event.pub_key
+                                    );
+                                    self.transcripts
+                                        .entry(event.pub_key.clone())
+                                        .or_default()
+                                        .record(&event.id);
+                                }
                             }
                         }
                     }
                 }
-                // TODO: Change this to time out and then be > then min makers
-                if peer_inputs.len() >= peer_count {
-                    return Ok(peer_inputs);
-                }
-                if get_timestamp() - started_waiting > 60 {
-                    if peer_inputs.len() > self.config.minium_makers {
-                        return Ok(peer_inputs);
-                    } else {
-                        return Err(Error::MakersFailedToRespond);
-                    }
-                }
+            }
+            // Caller checks there are enough makers, spare makers may stand in for the rest
+            if inbox.len() >= peer_count
+                || inbox.is_complete()
+                || get_timestamp() - started_waiting
+                    > step_timeout(
+                        &self.latency_classes,
+                        ProtocolStep::IoAuth,
+                        &inbox.missing(),
+                    )
+            {
+                break;
+            }
+        }
+        drop(subscription);
+        self.record_ingest("maker inputs", &inbox.stats);
+        for (maker, reason) in turned_down {
+            if let Some(offer) = matching_offers.iter().find(|o| o.maker == maker) {
+                self.abort_session(offer, Some(reason))?;
+            }
+        }
+
+        // Pairs each maker's inputs with its offer
+        let mut maker_inputs = inbox.into_messages();
+        Ok(matching_offers
+            .into_iter()
+            .filter_map(|offer| {
+                maker_inputs
+                    .remove(&offer.maker)
+                    .map(|maker_input| (offer, maker_input))
+            })
+            .collect())
+    }
+
+    /// Drops makers that gave addresses seen in an earlier or concurrent round
+    /// The addresses of the remaining makers are recorded, returns the makers dropped
+    pub fn drop_reused_addresses(
+        &mut self,
+        peer_inputs: &mut Vec<(NostrdizerOffer, IoAuth)>,
+    ) -> Vec<String> {
+        let mut reusing_makers = vec![];
+        for (maker, address) in self.address_store.reused(peer_inputs) {
+            warn!("Maker {maker} reused address {address}");
+            if !reusing_makers.contains(&maker) {
+                reusing_makers.push(maker);
             }
         }
+        peer_inputs.retain(|(offer, _)| !reusing_makers.contains(&offer.maker));
+        self.address_store.record(peer_inputs, get_timestamp());
+
+        reusing_makers
+    }
+
+    /// Drops makers that gave inputs that are unconfirmed or not in the UTXO set
+    /// Returns the dropped makers and their unconfirmed inputs
+    pub fn drop_unconfirmed_inputs(
+        &self,
+        peer_inputs: &mut Vec<(NostrdizerOffer, IoAuth)>,
+    ) -> Result<Vec<(String, Vec<OutPoint>)>, Error> {
+        let mut dropped = vec![];
+        for (offer, io_auth) in peer_inputs.iter() {
+            let outpoints: Vec<OutPoint> = io_auth
+                .utxos
+                .iter()
+                .map(|(outpoint, _)| *outpoint)
+                .collect();
+            let unconfirmed = self.unconfirmed_outpoints(&outpoints)?;
+            if !unconfirmed.is_empty() {
+                warn!(
+                    "[round {}] Maker {} sent unconfirmed inputs",
+                    self.maker_round_id(&offer.maker),
+                    offer.maker
+                );
+                dropped.push((offer.maker.clone(), unconfirmed));
+            }
+        }
+        peer_inputs.retain(|(offer, _)| !dropped.iter().any(|(maker, _)| maker == &offer.maker));
+
+        Ok(dropped)
     }
 
     /// Send fill offer from taker to maker
@@ -249,55 +508,101 @@ impl Taker {
         peer_count: usize,
         matching_offers: &mut Vec<NostrdizerOffer>,
     ) -> Result<Vec<NostrdizerOffer>, Error> {
-        // Sorts vec by lowest CJ fee
-        matching_offers.sort_by_key(|o| o.cjfee);
+        // Makers with verified fidelity bonds first, in the order they were picked by bond weight,
+        // then in the order of the taker's maker selection
+        self.config.maker_selection.order(
+            matching_offers,
+            fees::max_cj_fee(&self.config, send_amount),
+            &mut thread_rng(),
+        );
+        matching_offers.sort_by_key(|o| {
+            let rank = self.fidelity_bonds.get(&o.maker).map(|bond| bond.rank);
+            rank.unwrap_or(usize::MAX)
+        });
         // Removes dupicate maker offers
         let unique_makers: HashSet<String> =
             matching_offers.iter().map(|o| o.clone().maker).collect();
         matching_offers.retain(|o| unique_makers.contains(&o.maker));
 
+        self.fills_sent_at = get_timestamp();
         let mut last_peer = 0;
-        // let commitment = self.generate_podle()?;
-        //let commitment = commitment.commit; // sha256::Hash::hash(commitment.p2.to_string().as_bytes());
-        // TODO: Need to get the priv key from
-
-        let commitment = sha256::Hash::hash("".as_bytes());
+        // Fills of the round, replacements for busy makers included, commit to the same utxo
+        // The index is one every maker that would be filled first accepts
+        let (commitment, index) = match &self.round_commitment {
+            Some(commitment) => (commitment.commit, commitment.index),
+            None => {
+                let max_index = matching_offers
+                    .iter()
+                    .take(peer_count)
+                    .map(|o| o.max_podle_index.unwrap_or(MAX_PODLE_INDEX))
+                    .min()
+                    .unwrap_or(MAX_PODLE_INDEX);
+                let commitment = self.podle_commitment(max_index)?;
+                let commit = (commitment.commit, commitment.index);
+                self.round_commitment = Some(commitment);
+                commit
+            }
+        };
         let mut matched_peers = vec![];
         for peer in matching_offers.iter_mut() {
+            if peer.max_podle_index.unwrap_or(MAX_PODLE_INDEX) < index {
+                debug!(
+                    "Not filling {}, it does not accept podle index {index}",
+                    peer.maker
+                );
+                continue;
+            }
             //debug!("Peer: {:?} Offer: {:?}", peer.0, peer.1);
             let fill_offer = Fill {
                 offer_id: peer.oid,
+                protocol_version: Some(PROTOCOL_VERSION),
                 amount: send_amount,
                 tencpubkey: "".to_string(),
                 commitment,
+                denominations: vec![],
+                script_type: self.config.script_type,
+                counter: self.config.accept_counter_offers,
+                cjfee: self
+                    .counter_offers
+                    .get(&peer.maker)
+                    .map(|counter| counter.cjfee),
+                payment: self.config.payment.as_ref().map(|payment| payment.amount),
+                silent_payment: self
+                    .silent_payment
+                    .as_ref()
+                    .map(|sender| sender.blinded_scan_key())
+                    .transpose()?,
+                invite: self.config.invite.clone(),
+                input_limits: self.config.input_limits,
             };
             let message = NostrdizerMessage {
                 event_type: NostrdizerMessageKind::FillOffer,
                 event: NostrdizerMessages::Fill(fill_offer),
+                round_id: None,
             };
             debug!("{:?}", message);
-            let encypted_content =
-                utils::encrypt_message(&self.identity.secret_key, &peer.maker, &message)?;
-
-            let event = EventPrepare {
-                pub_key: self.identity.public_key_str.clone(),
-                created_at: get_timestamp(),
-                kind: FILL,
-                tags: vec![vec!["p".to_string(), peer.maker.to_string()]],
-                content: encypted_content,
-            }
-            .to_event(&self.identity, 0);
 
-            /*
-            self.nostr_client.publish_ephemeral_event(
+            if peer.protocol_version >= SEALED_VERSION {
+                self.sealed_peers.insert(peer.maker.clone());
+            }
+            if peer.protocol_version >= TAGGED_VERSION {
+                self.tagged_peers.insert(peer.maker.clone());
+            }
+            let fill_event_id = utils::send_message(
                 &self.identity,
-                125,
-                &encypted_content,
-                &[vec!["p".to_string(), peer.maker.to_string()]],
-                0,
+                &peer.maker,
+                FILL,
+                &message,
+                &self.envelope(&peer.maker),
+                &mut self.nostr_client,
             )?;
-            */
-            self.nostr_client.publish_event(&event)?;
+            let round_id = round_id(&fill_event_id, &self.identity.public_key_str);
+            debug!("[round {round_id}] Sent fill to {}", peer.maker);
+            self.round_ids.insert(peer.maker.clone(), round_id);
+            self.response_timer.start(&peer.maker, Step::FillAck);
+            let mut transcript = Transcript::default();
+            transcript.record(&fill_event_id);
+            self.transcripts.insert(peer.maker.clone(), transcript);
             matched_peers.push(peer.clone());
             last_peer += 1;
             if last_peer >= peer_count {
@@ -308,126 +613,977 @@ impl Taker {
         Ok(matched_peers)
     }
 
-    /// Publish the podle commitment
+    /// Waits for makers to send the relays their session is on
+    /// Makers on earlier protocol versions, or that do not answer, stay on the offer relays
+    /// Returns the makers that are too busy to take the fill, or turned it down
+    /// Makers whose counter-offer is taken are returned too, to be filled again on its terms
+    pub fn get_fill_acks(
+        &mut self,
+        send_amount: Amount,
+        matched_offers: &[NostrdizerOffer],
+    ) -> Result<Vec<String>, Error> {
+        let mut busy = vec![];
+        let mut waiting: HashSet<String> = matched_offers
+            .iter()
+            .filter(|o| o.protocol_version >= FILL_ACK_VERSION)
+            .map(|o| o.maker.clone())
+            .collect();
+        // Revealed once the subscription is closed
+        let mut commitment_used = false;
+
+        if !waiting.is_empty() {
+            // Sealed acks are published by a throwaway key, the maker is checked once they are unsealed
+            let filter = ReqFilter {
+                ids: None,
+                authors: None,
+                kinds: Some(vec![FILL_ACK, REJECT, SEALED]),
+                e: None,
+                p: Some(vec![self.identity.public_key_str.clone()]),
+                since: Some(self.fills_sent_at),
+                until: None,
+                limit: None,
+            };
+            let mut subscription =
+                SubscriptionGuard::subscribe(&mut self.nostr_client, vec![filter])?;
+
+            let started_waiting = get_timestamp();
+            while !waiting.is_empty()
+                && get_timestamp() - started_waiting
+                    < step_timeout(&self.latency_classes, ProtocolStep::FillAck, &waiting)
+            {
+                for (relay, message) in subscription.next_data()? {
+                    self.relay_pool
+                        .record_message(RelayRole::Offer, &relay, get_timestamp());
+                    if let Ok(event) = serde_json::from_str::<Value>(&message.to_string()) {
+                        if let Ok(event) = serde_json::from_value::<Event>(event[2].clone()) {
+                            let event = match unseal_event(&self.identity, event) {
+                                Ok(event) => event,
+                                Err(_) => continue,
+                            };
+                            if event.verify().is_ok()
+                                && (event.kind == FILL_ACK || event.kind == REJECT)
+                                && waiting.contains(&event.pub_key)
+                            {
+                                // A maker or relay sending an event that does not decrypt does not end the wait for the others
+                                let message = match decrypt_message(
+                                    &self.identity.secret_key,
+                                    peer_key(&self.encryption_keys, &event.pub_key),
+                                    &event.content,
+                                ) {
+                                    Ok(message) => message,
+                                    Err(err) => {
+                                        debug!(
+                                            "Skipping fill ack of {} that does not decrypt: {err}",
+                                            event.pub_key
+                                        );
+                                        continue;
+                                    }
+                                };
+                                match message.event {
+                                    NostrdizerMessages::FillAck(fill_ack) => {
+                                        record_response(
+                                            &mut self.response_timer,
+                                            &mut self.reputation,
+                                            &event.pub_key,
+                                            Step::FillAck,
+                                        );
+                                        self.relay_pool.add_relays(
+                                            RelayRole::Session,
+                                            &fill_ack.session_relays,
+                                        );
+                                        // Messages after the ack are encrypted to the key the maker acked with
+                                        if let Some(key) = fill_ack
+                                            .encryption_key
+                                            .filter(|key| key.verify(&event.pub_key).is_ok())
+                                        {
+                                            self.encryption_keys
+                                                .insert(event.pub_key.clone(), key.pub_key);
+                                        }
+                                        self.transcripts
+                                            .entry(event.pub_key.clone())
+                                            .or_default()
+                                            .record(&event.id);
+                                        waiting.remove(&event.pub_key);
+                                    }
+                                    NostrdizerMessages::Reject(Reject {
+                                        reason: RejectReason::Busy,
+                                    }) => {
+                                        debug!(
+                                            "[round {}] Maker {} is busy",
+                                            round_label(&self.round_ids, &event.pub_key),
+                                            event.pub_key
+                                        );
+                                        waiting.remove(&event.pub_key);
+                                        busy.push(event.pub_key);
+                                    }
+                                    // Only the first counter-offer of a maker is taken, so it can not keep raising its fee
+                                    NostrdizerMessages::Reject(Reject {
+                                        reason: RejectReason::CounterOffer(counter),
+                                    }) if !self.counter_offers.contains_key(&event.pub_key)
+                                        && fees::accepts_counter_offer(
+                                            &self.config,
+                                            send_amount,
+                                            &counter,
+                                        ) =>
+                                    {
+                                        debug!(
+                                            "[round {}] Taking counter-offer of maker {}: {:?}",
+                                            round_label(&self.round_ids, &event.pub_key),
+                                            event.pub_key,
+                                            counter
+                                        );
+                                        self.counter_offers.insert(event.pub_key.clone(), counter);
+                                        waiting.remove(&event.pub_key);
+                                        busy.push(event.pub_key);
+                                    }
+                                    // Commitment was burned in another round, the next round is sent with another
+                                    NostrdizerMessages::Reject(Reject {
+                                        reason: RejectReason::CommitmentUsed,
+                                    }) => {
+                                        warn!(
+                                            "[round {}] Maker {} has seen the commitment used",
+                                            round_label(&self.round_ids, &event.pub_key),
+                                            event.pub_key
+                                        );
+                                        commitment_used = true;
+                                        waiting.remove(&event.pub_key);
+                                        busy.push(event.pub_key);
+                                    }
+                                    NostrdizerMessages::Reject(Reject { reason }) => {
+                                        warn!(
+                                            "[round {}] Maker {} rejected fill: {:?}",
+                                            round_label(&self.round_ids, &event.pub_key),
+                                            event.pub_key,
+                                            reason
+                                        );
+                                        waiting.remove(&event.pub_key);
+                                        busy.push(event.pub_key);
+                                    }
+                                    _ => (),
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        if commitment_used {
+            self.reveal_commitment();
+        }
+
+        if !waiting.is_empty()
+            || matched_offers
+                .iter()
+                .any(|o| o.protocol_version < FILL_ACK_VERSION)
+        {
+            let offer_relays = self.relay_pool.offer_relays.clone();
+            self.relay_pool
+                .add_relays(RelayRole::Session, &offer_relays);
+        }
+
+        Ok(busy)
+    }
+
+    /// Moves the round's sessions to the session relays makers sent in their fill acks
+    /// The offer client stays connected for the next round's offers and fills
+    pub fn connect_session_relays(&mut self) -> Result<(), Error> {
+        let offer_relays: HashSet<&String> = self.relay_pool.offer_relays.iter().collect();
+        let session_relays: HashSet<&String> = self.relay_pool.session_relays.iter().collect();
+        if offer_relays != session_relays {
+            debug!("Session relays: {:?}", self.relay_pool.session_relays);
+            self.session_client = Some(self.relay_pool.connect(
+                RelayRole::Session,
+                &self.identity,
+                &self.config.relay_auth,
+            )?);
+        }
+
+        Ok(())
+    }
+
+    /// Disconnects from the session relays of the round, the next round uses those its makers ack
+    pub fn end_round(&mut self) {
+        self.session_client = None;
+        self.relay_pool.session_relays.clear();
+    }
+
+    /// Round trip time of each offer relay, probed before a round so slow relays fail it early
+    pub fn probe_relays(&mut self) -> Result<Vec<RelayLatency>, Error> {
+        let relays = self.relay_pool.relays(RelayRole::Offer).to_vec();
+        latency::probe(&mut self.nostr_client, &relays)
+    }
+
+    /// Publishes stats of the taker's rounds to the offer relays from a fresh key, relays are authenticated to as that key too
+    pub fn publish_network_stats(&mut self, stats: &NetworkStats) -> Result<String, Error> {
+        let reporter = stats::reporter_identity()?;
+        let mut client =
+            self.relay_pool
+                .connect(RelayRole::Offer, &reporter, &self.config.relay_auth)?;
+        stats::publish(&mut client, &reporter, self.network, stats)
+    }
+
+    /// Stats reports takers published since a time
+    pub fn fetch_network_stats(&mut self, since: u64) -> Result<Vec<NetworkStats>, Error> {
+        stats::fetch(&mut self.nostr_client, self.network, since)
+    }
+
+    /// Session the podle opening sent to the maker of offer is bound to
+    /// `makers` are all the makers sent auth with it, makers on earlier protocol versions get an unbound opening
+    pub fn auth_binding(&self, makers: &[String], offer: &NostrdizerOffer) -> Option<AuthBinding> {
+        if offer.protocol_version < AUTH_BINDING_VERSION {
+            return None;
+        }
+        let fill_event_id = self.transcripts.get(&offer.maker)?.event_ids.first()?;
+        Some(AuthBinding::new(makers, fill_event_id))
+    }
+
+    /// Proof for the maker of offer, of the commitment scheme its protocol version uses
+    pub fn generate_auth(
+        &self,
+        offer: &NostrdizerOffer,
+        binding: Option<AuthBinding>,
+    ) -> Result<AuthProof, Error> {
+        match SchemeId::for_version(offer.protocol_version) {
+            SchemeId::Podle => Ok(AuthProof::Podle(self.generate_podle(binding)?)),
+        }
+    }
+
+    /// Marks the round's commitment revealed, before the auths opening it are sent so a failed send does not reuse it
+    pub fn reveal_commitment(&mut self) {
+        if let Some(commitment) = &self.round_commitment {
+            self.keystore.reveal(&commitment.commit);
+        }
+    }
+
+    /// Publish the commitment proof
     pub fn send_auth_message(
         &mut self,
-        auth_commitment: AuthCommitment,
+        auth_proof: AuthProof,
         matched_offers: Vec<NostrdizerOffer>,
     ) -> Result<(), Error> {
-        let message = NostrdizerMessage {
-            event_type: NostrdizerMessageKind::Auth,
-            event: NostrdizerMessages::Auth(auth_commitment),
-        };
-
         for offer in matched_offers {
-            let encypted_content =
-                utils::encrypt_message(&self.identity.secret_key, &offer.maker, &message)?;
-            let event = EventPrepare {
-                pub_key: self.identity.public_key_str.clone(),
-                kind: AUTH,
-                created_at: get_timestamp(),
-                tags: vec![vec!["p".to_string(), offer.maker]],
-                content: encypted_content,
-            }
-            .to_event(&self.identity, 0);
+            let message = NostrdizerMessage {
+                event_type: NostrdizerMessageKind::Auth,
+                event: NostrdizerMessages::Auth(auth_proof.clone()),
+                round_id: self.round_ids.get(&offer.maker).cloned(),
+            };
+            let event_id = self.publish_critical(&offer.maker, AUTH, &message)?;
+            self.transcripts
+                .entry(offer.maker)
+                .or_default()
+                .record(&event_id);
+        }
+        Ok(())
+    }
 
-            self.nostr_client.publish_event(&event)?;
+    /// Ends the sessions of makers that were filled but are not in the CJ
+    /// Makers on earlier protocol versions are not sent an abort and time out instead
+    pub fn send_abort(&mut self, makers: &[NostrdizerOffer]) -> Result<(), Error> {
+        for offer in makers {
+            self.abort_session(offer, None)?;
+        }
+        Ok(())
+    }
 
-            /*
-            self.nostr_client.publish_ephemeral_event(
-                &self.identity,
-                127,
-                &encypted_content,
-                &[vec!["p".to_string(), offer.maker]],
-                0,
-            )?;
-            */
+    /// Ends the session of a maker, with why its inputs were turned down if they were
+    fn abort_session(
+        &mut self,
+        offer: &NostrdizerOffer,
+        reason: Option<RejectReason>,
+    ) -> Result<(), Error> {
+        if offer.protocol_version < ABORT_VERSION {
+            return Ok(());
         }
+        let message = NostrdizerMessage {
+            event_type: NostrdizerMessageKind::Abort,
+            event: NostrdizerMessages::Abort(Abort { reason }),
+            round_id: self.round_ids.get(&offer.maker).cloned(),
+        };
+        utils::send_message(
+            &self.identity,
+            &offer.maker,
+            ABORT,
+            &message,
+            &self.envelope(&offer.maker),
+            self.session_client
+                .as_mut()
+                .unwrap_or(&mut self.nostr_client),
+        )?;
+        debug!(
+            "[round {}] Aborted session with {}",
+            self.maker_round_id(&offer.maker),
+            offer.maker
+        );
         Ok(())
     }
 
     /// Get offers that match send sorted for lowest fee first
+    /// Counter-offers, latency classes, the podle commitment and the silent payment blinding of an earlier round are dropped
     pub fn get_matching_offers(
         &mut self,
         send_amount: Amount,
     ) -> Result<Vec<NostrdizerOffer>, Error> {
-        let offers = self.get_offers()?;
-        let matching_offers = offers
+        self.capabilities
+            .check_script_type(self.config.script_type)?;
+        self.order_book.fetch(&mut self.nostr_client)?;
+        self.counter_offers.clear();
+        self.round_commitment = None;
+        self.round_inputs = 0;
+        self.silent_payment = self
+            .config
+            .payment
+            .as_ref()
+            .and_then(|payment| payment.silent_payment())
+            .map(|address| SilentPaymentSender::new(*address));
+        // Makers on earlier versions send no share of their keys to derive a silent payment with
+        let min_version = match self.silent_payment {
+            Some(_) => SILENT_PAYMENT_VERSION,
+            None => PSBT_INPUT_VERSION,
+        };
+        // Upcoming offers are for liquidity the maker does not have ready yet
+        let offers = self.order_book.offers_for(send_amount, get_timestamp());
+        // Offers are pre-filtered against the rate the CJ will be built at, not the taker's max
+        let fee_rate = self.mining_fee_rate();
+        let offers: Vec<(String, Offer)> = offers
             .into_iter()
+            // Inputs of makers on earlier versions may have no psbt input to build the CJ with
+            .filter(|(_k, offer)| {
+                let protocol_version = match offer {
+                    Offer::AbsOffer(offer) => offer.protocol_version,
+                    Offer::RelOffer(offer) => offer.protocol_version,
+                };
+                protocol_version >= min_version
+            })
             .filter(|(_k, offer)| match offer {
+                // Offers with a fee rate floor above what taker will pay are skipped
                 Offer::AbsOffer(offer) => {
-                    offer.maxsize > send_amount
-                        && offer.minsize < send_amount
+                    fees::within_maxsize(&self.config, offer.maxsize, send_amount)
                         && offer.cjfee < self.config.cj_fee.abs_fee
+                        && offer.min_fee_rate.unwrap_or(0.0) <= fee_rate
                 }
                 Offer::RelOffer(offer) => {
-                    offer.maxsize > send_amount
-                        && offer.minsize < send_amount
-                        && offer.cjfee < self.config.cj_fee.rel_fee
+                    fees::within_maxsize(&self.config, offer.maxsize, send_amount)
+                        && to_ppm(offer.cjfee) < to_ppm(self.config.cj_fee.rel_fee)
+                        && offer.min_fee_rate.unwrap_or(0.0) <= fee_rate
                 }
             })
+            .collect();
+        // Both offers of a maker advertise the same bond
+        let bonds: HashMap<String, FidelityBondProof> = offers
+            .iter()
+            .filter_map(|(k, offer)| offer.fidelity_bond().map(|bond| (k.clone(), bond.clone())))
+            .collect();
+        self.fidelity_bonds = self.verify_bonds(bonds)?;
+        let mut matching_offers: Vec<NostrdizerOffer> = offers
+            .into_iter()
             .map(|(k, offer)| match offer {
                 Offer::AbsOffer(offer) => NostrdizerOffer {
                     maker: k,
                     oid: offer.offer_id,
                     txfee: offer.txfee,
+                    txfee_rate: offer.txfee_rate,
                     cjfee: offer.cjfee,
+                    protocol_version: offer.protocol_version,
+                    latency_class: offer.latency_class,
+                    max_podle_index: offer.max_podle_index,
+                    encryption_key: offer.encryption_key,
                 },
-                Offer::RelOffer(offer) => {
-                    let cjfee = (offer.cjfee * send_amount.to_float_in(Denomination::Satoshi))
-                        .floor() as u64;
-                    NostrdizerOffer {
-                        maker: k,
-                        oid: offer.offer_id,
-                        txfee: offer.txfee,
-                        cjfee: Amount::from_sat(cjfee),
-                    }
-                }
+                Offer::RelOffer(offer) => NostrdizerOffer {
+                    maker: k,
+                    oid: offer.offer_id,
+                    txfee: offer.txfee,
+                    txfee_rate: offer.txfee_rate,
+                    cjfee: rel_fee_amount(send_amount, to_ppm(offer.cjfee)),
+                    protocol_version: offer.protocol_version,
+                    latency_class: offer.latency_class,
+                    max_podle_index: offer.max_podle_index,
+                    encryption_key: offer.encryption_key,
+                },
+            })
+            .collect();
+        if let Some(min_bond_value) = self.config.min_bond_value {
+            matching_offers
+                .retain(|o| meets_min_bond(&self.fidelity_bonds, &o.maker, min_bond_value));
+        }
+        self.latency_classes = matching_offers
+            .iter()
+            .filter_map(|o| o.latency_class.map(|class| (o.maker.clone(), class)))
+            .collect();
+        // Keys without a valid signature of the maker are ignored, messages are encrypted to its nostr key
+        self.encryption_keys = matching_offers
+            .iter()
+            .filter_map(|o| {
+                o.encryption_key
+                    .as_ref()
+                    .filter(|key| key.verify(&o.maker).is_ok())
+                    .map(|key| (o.maker.clone(), key.pub_key.clone()))
             })
             .collect();
 
         Ok(matching_offers)
     }
 
-    /// Gets current offers
+    /// CJ amount of a sweep with `makers` of these offers, see [`fees::sweep_amount`]
+    /// Fees are those of the priciest of the cheapest offers, so the makers filled, or spares standing in, cost no more.
+    /// Relative fees are of the amount the offers were matched for, a sweep matches them for its whole balance
+    pub fn sweep_amount(
+        &mut self,
+        offers: &[NostrdizerOffer],
+        makers: usize,
+    ) -> Result<Amount, Error> {
+        let (balance, input_count) = self.sweep_balance()?;
+        let mut maker_fees: Vec<Amount> = offers.iter().map(|offer| offer.cjfee).collect();
+        maker_fees.sort();
+        maker_fees.truncate(makers + self.config.spare_makers);
+        maker_fees.reverse();
+        maker_fees.truncate(makers);
+
+        // Each maker brings at least an input, its CJ output and change, and the taker its CJ output
+        let vsize = TX_OVERHEAD_VSIZE
+            + (input_count + makers) * INPUT_VSIZE
+            + (2 * makers + 1) * OUTPUT_VSIZE;
+        let mining_fee = Amount::from_sat((vsize as f32 * self.mining_fee_rate()).ceil() as u64);
+        // A payment is paid from the balance too
+        let payment = match &self.config.payment {
+            Some(payment) => payment.amount,
+            None => Amount::ZERO,
+        };
+        fees::sweep_amount(
+            balance,
+            &maker_fees,
+            fees::checked_add(mining_fee, payment)?,
+        )
+    }
+
+    /// Fee rate the round's CJ is built at, see [`fees::negotiate_fee_rate`]
+    pub fn mining_fee_rate(&self) -> f32 {
+        let min_relay_fee_rate = self
+            .min_relay_fee_rate()
+            .unwrap_or(fees::DEFAULT_MIN_RELAY_FEE_RATE);
+        fees::negotiate_fee_rate(
+            self.estimate_fee_rate().ok(),
+            min_relay_fee_rate,
+            self.config.mining_fee.fee_rate,
+        )
+    }
+
+    /// Checks the bonds makers advertised against the chain, ranking the makers whose bonds verify by bond weight
+    /// Bonds that do not verify are left out as if the maker had none
+    fn verify_bonds(
+        &self,
+        bonds: HashMap<String, FidelityBondProof>,
+    ) -> Result<HashMap<String, VerifiedBond>, Error> {
+        let now = get_timestamp();
+        let mut verified = HashMap::new();
+        for (maker, proof) in bonds {
+            if let Err(err) = proof.verify(&maker) {
+                debug!("Ignoring the bond of {maker}: {err}");
+                continue;
+            }
+            let utxo = match self.bond_utxo(&proof.outpoint) {
+                Ok(Some(utxo)) => utxo,
+                Ok(None) => {
+                    debug!("Ignoring the bond of {maker}, it is spent or not confirmed");
+                    continue;
+                }
+                // Every maker is taken as having no bond unless bonds are required
+                Err(Error::FidelityBondsUnavailable) if self.config.min_bond_value.is_none() => {
+                    return Ok(HashMap::new())
+                }
+                Err(err) => return Err(err),
+            };
+            match proof.value(&utxo, now) {
+                Ok(value) => {
+                    let bond = VerifiedBond {
+                        amount: utxo.amount,
+                        locktime: proof.locktime,
+                        value,
+                        rank: 0,
+                    };
+                    verified.insert(maker, bond);
+                }
+                Err(err) => debug!("Ignoring the bond of {maker}: {err}"),
+            }
+        }
+        rank_bonds(&mut verified, &mut thread_rng());
+        Ok(verified)
+    }
+
+    /// Errors when fees have spiked over the taker's max fee rate
+    /// Fee rates that can not be estimated, as on a fresh regtest node, are not a spike
+    pub fn check_fees(&self) -> Result<(), Error> {
+        match self.estimate_fee_rate() {
+            Ok(estimate) => fees::check_fee_estimate(&self.config, estimate),
+            Err(err) => {
+                debug!("Could not estimate fee rate: {err}");
+                Ok(())
+            }
+        }
+    }
+
+    /// Snapshot of the wallet's view of the taker's inputs of a CJ, see [`UtxoSnapshot`]
+    pub fn snapshot_inputs(
+        &self,
+        psbt: &PartiallySignedTransaction,
+        maker_inputs: &[(NostrdizerOffer, IoAuth)],
+    ) -> Result<UtxoSnapshot, Error> {
+        Ok(UtxoSnapshot::new(psbt, maker_inputs, &self.wallet_utxos()?))
+    }
+
+    /// Errors when the taker's inputs changed in the wallet since the snapshot
+    pub fn check_inputs(&self, snapshot: &UtxoSnapshot) -> Result<(), Error> {
+        let drift = snapshot.drift(&self.wallet_utxos()?);
+        match drift.is_empty() {
+            true => Ok(()),
+            false => Err(Error::InputsDrifted(drift)),
+        }
+    }
+
+    /// Script of the round's silent payment output, derived from the taker's own inputs and each maker's share
+    /// A share has to prove against the keys of the maker's inputs, with a key for each input of a type that counts
+    pub(crate) fn derive_silent_payment(
+        &mut self,
+        maker_inputs: &[(NostrdizerOffer, IoAuth)],
+        own_inputs: &[(OutPoint, SecretKey, Script)],
+    ) -> Result<Script, Error> {
+        let sender = self
+            .silent_payment
+            .as_mut()
+            .ok_or(Error::SilentPaymentOutputMissing)?;
+        let blinded_scan_key = sender.blinded_scan_key()?;
+        let mut outpoints = vec![];
+        let mut shares = vec![];
+        for (offer, io_auth) in maker_inputs {
+            let invalid = || Error::InvalidSilentPaymentShare(offer.maker.clone());
+            let mut counted = vec![];
+            for (outpoint, input) in &io_auth.utxos {
+                let utxo = input.witness_utxo.as_ref().ok_or(Error::BadInput)?;
+                if silent_payment::counts(&utxo.script_pubkey) {
+                    counted.push((outpoint, &utxo.script_pubkey));
+                }
+                outpoints.push(*outpoint);
+            }
+            match &io_auth.silent_payment {
+                Some(share) => {
+                    let keys_match = share.pubkeys.len() == counted.len()
+                        && counted.iter().all(|(outpoint, script_pubkey)| {
+                            share.pubkeys.iter().any(|(key_outpoint, key)| {
+                                key_outpoint == *outpoint
+                                    && input_pubkey(key, script_pubkey).is_some()
+                            })
+                        });
+                    if !keys_match {
+                        return Err(invalid());
+                    }
+                    let key_sum = share.verify(&blinded_scan_key).map_err(|_| invalid())?;
+                    shares.push((key_sum, share.share));
+                }
+                None if counted.is_empty() => (),
+                None => return Err(invalid()),
+            }
+        }
+        outpoints.extend(own_inputs.iter().map(|(outpoint, _, _)| *outpoint));
+        let own_keys: Vec<(SecretKey, Script)> = own_inputs
+            .iter()
+            .map(|(_, secret_key, script_pubkey)| (*secret_key, script_pubkey.clone()))
+            .collect();
+        let output_key = sender.derive(&outpoints, &own_keys, &shares)?;
+        Ok(Script::new_v1_p2tr_tweaked(output_key))
+    }
+
+    /// Script of the payment round's external output, for a silent payment the one derived for the CJ
+    pub(crate) fn payment_script(&self) -> Option<Script> {
+        match &self.config.payment.as_ref()?.destination {
+            PaymentDestination::Address(address) => Some(address.script_pubkey()),
+            PaymentDestination::SilentPayment(_) => self
+                .silent_payment
+                .as_ref()?
+                .derived
+                .as_ref()
+                .map(|(_, output_key)| Script::new_v1_p2tr_tweaked(*output_key)),
+        }
+    }
+
+    /// Errors when the CJ of a silent payment round does not pay the output derived from its inputs
+    pub(crate) fn check_silent_payment(
+        &self,
+        psbt: &PartiallySignedTransaction,
+    ) -> Result<(), Error> {
+        match (&self.config.payment, &self.silent_payment) {
+            (Some(payment), Some(sender)) => sender.check(psbt, payment.amount.to_sat()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Gets current offers, most corroborated by trusted relays first
+    /// Only what changed since the last call is fetched
     pub fn get_offers(&mut self) -> Result<Vec<(String, Offer)>, Error> {
-        utils::get_offers(&mut self.nostr_client)
+        self.order_book.fetch(&mut self.nostr_client)?;
+        Ok(self.order_book.offers())
     }
 
     /// Publish unsigned cj transaction to relay
+    /// `participants` is the number of parties in the CJ including the taker
     pub fn send_unsigned_transaction(
         &mut self,
         peer_pub_key: &str,
         psbt: &PartiallySignedTransaction,
+        participants: usize,
     ) -> Result<(), Error> {
         let message = NostrdizerMessage {
             event_type: NostrdizerMessageKind::UnsignedCJ,
-            event: NostrdizerMessages::UnsignedCJ(Transaction { psbt: psbt.clone() }),
+            event: NostrdizerMessages::UnsignedCJ(Transaction {
+                psbt: psbt.clone(),
+                participants: Some(participants),
+            }),
+            round_id: self.round_ids.get(peer_pub_key).cloned(),
+        };
+
+        let event_id = self.publish_critical(peer_pub_key, TRANSACTION, &message)?;
+        self.response_timer.start(peer_pub_key, Step::Sign);
+        self.transcripts
+            .entry(peer_pub_key.to_string())
+            .or_default()
+            .record(&event_id);
+        Ok(())
+    }
+
+    /// Sends each maker the transcript hash of its session once the CJ is broadcast,
+    /// and checks the hash each maker answers with
+    /// Makers on earlier protocol versions do not confirm, their rounds are recorded as unconfirmed
+    pub fn confirm_round(
+        &mut self,
+        makers: &[NostrdizerOffer],
+        final_tx: &BitcoinTransaction,
+    ) -> Result<Vec<RoundRecord>, Error> {
+        let txid = &final_tx.txid();
+        let transcripts: HashMap<String, sha256::Hash> = makers
+            .iter()
+            .map(|offer| {
+                let transcript = self.transcripts.get(&offer.maker).cloned();
+                (
+                    offer.maker.clone(),
+                    transcript.unwrap_or_default().hash(txid),
+                )
+            })
+            .collect();
+        let mut waiting: HashSet<String> = makers
+            .iter()
+            .filter(|offer| offer.protocol_version >= CONFIRM_VERSION)
+            .map(|offer| offer.maker.clone())
+            .collect();
+        let mut confirms = HashMap::new();
+
+        if !waiting.is_empty() {
+            let filter = ReqFilter {
+                ids: None,
+                authors: None,
+                kinds: Some(vec![CONFIRM, SEALED]),
+                e: None,
+                p: Some(vec![self.identity.public_key_str.clone()]),
+                since: Some(get_timestamp()),
+                until: None,
+                limit: None,
+            };
+            // Subscribed before sending so answers are not missed
+            let client = self
+                .session_client
+                .as_mut()
+                .unwrap_or(&mut self.nostr_client);
+            let mut subscription = SubscriptionGuard::subscribe(client, vec![filter])?;
+            for maker in &waiting {
+                utils::send_confirm(
+                    &self.identity,
+                    maker,
+                    Confirm {
+                        txid: *txid,
+                        transcript: transcripts[maker],
+                        tx: self.config.share_final_tx.then(|| final_tx.clone()),
+                    },
+                    self.round_ids.get(maker).cloned(),
+                    &Envelope {
+                        secret_key: self.identity.secret_key,
+                        pub_key: peer_key(&self.encryption_keys, maker).to_string(),
+                        sealed: self.sealed_peers.contains(maker),
+                        tagged: self.tagged_peers.contains(maker),
+                    },
+                    &mut subscription,
+                )?;
+            }
+
+            let started_waiting = get_timestamp();
+            while !waiting.is_empty()
+                && get_timestamp() - started_waiting
+                    < step_timeout(&self.latency_classes, ProtocolStep::Confirm, &waiting)
+            {
+                for (relay, message) in subscription.next_data()? {
+                    self.relay_pool
+                        .record_message(RelayRole::Session, &relay, get_timestamp());
+                    if let Ok(event) = serde_json::from_str::<Value>(&message.to_string()) {
+                        if let Ok(event) = serde_json::from_value::<Event>(event[2].clone()) {
+                            let event = match unseal_event(&self.identity, event) {
+                                Ok(event) => event,
+                                Err(_) => continue,
+                            };
+                            self.relay_pool
+                                .record_event(RelayRole::Session, &relay, &event);
+                            if event.verify().is_ok()
+                                && event.kind == CONFIRM
+                                && waiting.contains(&event.pub_key)
+                            {
+                                if let NostrdizerMessages::Confirm(confirm) = decrypt_message(
+                                    &self.identity.secret_key,
+                                    peer_key(&self.encryption_keys, &event.pub_key),
+                                    &event.content,
+                                )?
+                                .event
+                                {
+                                    waiting.remove(&event.pub_key);
+                                    confirms.insert(event.pub_key, confirm);
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(makers
+            .iter()
+            .map(|offer| {
+                let transcript = transcripts[&offer.maker];
+                RoundRecord {
+                    round_id: self.maker_round_id(&offer.maker).to_string(),
+                    peer: offer.maker.clone(),
+                    txid: *txid,
+                    transcript,
+                    completion: Completion::check(transcript, txid, confirms.get(&offer.maker)),
+                    created_at: get_timestamp(),
+                }
+            })
+            .collect())
+    }
+
+    /// Waits for the co-op partner to join a round of send_amount
+    /// The partner reads the offer relays, so session messages are published to them as well
+    pub fn get_coop_join(&mut self, partner: &str, send_amount: Amount) -> Result<CoopJoin, Error> {
+        let join = self.next_message_from(partner, COOP_JOIN, |message| match message {
+            NostrdizerMessages::CoopJoin(join) if join.amount == send_amount => Some(join),
+            _ => None,
+        })?;
+        let offer_relays = self.relay_pool.offer_relays.clone();
+        self.relay_pool
+            .add_relays(RelayRole::Session, &offer_relays);
+        join.ok_or_else(|| Error::NoCoopJoin(partner.to_string()))
+    }
+
+    /// Sends the lead of a co-op round the partner's inputs and outputs
+    pub fn send_coop_join(&mut self, lead: &str, join: CoopJoin) -> Result<(), Error> {
+        let message = NostrdizerMessage {
+            event_type: NostrdizerMessageKind::CoopJoin,
+            event: NostrdizerMessages::CoopJoin(join),
+            round_id: None,
         };
+        self.publish_critical(lead, COOP_JOIN, &message)?;
+        Ok(())
+    }
 
-        let encrypted_content =
-            utils::encrypt_message(&self.identity.secret_key, peer_pub_key, &message)?;
+    /// Waits for the unsigned CJ of the co-op round lead
+    pub fn get_coop_transaction(
+        &mut self,
+        lead: &str,
+    ) -> Result<PartiallySignedTransaction, Error> {
+        self.next_message_from(lead, TRANSACTION, |message| match message {
+            NostrdizerMessages::UnsignedCJ(transaction) => Some(transaction.psbt),
+            _ => None,
+        })?
+        .ok_or(Error::TakerFailedToSendTransaction)
+    }
 
-        let event = EventPrepare {
-            pub_key: self.identity.public_key_str.clone(),
-            created_at: get_timestamp(),
-            kind: TRANSACTION,
-            tags: vec![vec!["p".to_string(), peer_pub_key.to_string()]],
-            content: encrypted_content,
+    /// Sends the lead of a co-op round the CJ signed by the partner
+    pub fn send_coop_signature(
+        &mut self,
+        lead: &str,
+        psbt: PartiallySignedTransaction,
+    ) -> Result<(), Error> {
+        let message = utils::signed_psbt_message(psbt, None);
+        self.publish_critical(lead, SIGNED_TRANSACTION, &message)?;
+        Ok(())
+    }
+
+    /// Partner's share of the fees of a co-op round with the makers of peer_inputs
+    /// The lead's inputs are not picked yet, it is counted with one
+    pub fn coop_fee_share(
+        &self,
+        peer_inputs: &[(NostrdizerOffer, IoAuth)],
+        join: &CoopJoin,
+    ) -> Result<Amount, Error> {
+        let mut maker_fees = Amount::ZERO;
+        let mut maker_txfee = Amount::ZERO;
+        let mut input_count = join.io_auth.utxos.len() + 1;
+        for (offer, io_auth) in peer_inputs {
+            maker_fees = fees::checked_add(maker_fees, offer.cjfee)?;
+            maker_txfee = fees::checked_add(
+                maker_txfee,
+                fees::maker_txfee(offer.txfee, offer.txfee_rate, io_auth.utxos.len(), 1),
+            )?;
+            input_count += io_auth.utxos.len();
         }
-        .to_event(&self.identity, 0);
+        // A CJ and a change output for each maker and both takers
+        let output_count = (peer_inputs.len() + 2) * 2;
+        Ok(coop::fee_share(
+            maker_fees,
+            maker_txfee,
+            input_count,
+            output_count,
+            self.mining_fee_rate(),
+        ))
+    }
+
+    /// First message of kind from peer that accept takes, none once the co-op timeout passes
+    fn next_message_from<T>(
+        &mut self,
+        peer: &str,
+        kind: u16,
+        mut accept: impl FnMut(NostrdizerMessages) -> Option<T>,
+    ) -> Result<Option<T>, Error> {
+        let started_waiting = get_timestamp();
+        let filter = ReqFilter {
+            ids: None,
+            authors: None,
+            kinds: Some(vec![kind, SEALED]),
+            e: None,
+            p: Some(vec![self.identity.public_key_str.clone()]),
+            since: Some(started_waiting - COOP_TIMEOUT),
+            until: None,
+            limit: None,
+        };
+        let mut subscription = SubscriptionGuard::subscribe(&mut self.nostr_client, vec![filter])?;
 
-        self.nostr_client.publish_event(&event)?;
-        /*
-        self.nostr_client.publish_ephemeral_event(
+        while get_timestamp() - started_waiting < COOP_TIMEOUT {
+            for (_relay, message) in subscription.next_data()? {
+                if let Ok(event) = serde_json::from_str::<Value>(&message.to_string()) {
+                    if let Ok(event) = serde_json::from_value::<Event>(event[2].clone()) {
+                        let event = match unseal_event(&self.identity, event) {
+                            Ok(event) => event,
+                            Err(_) => continue,
+                        };
+                        if event.verify().is_ok() && event.kind == kind && event.pub_key == peer {
+                            if let Ok(message) = decrypt_message(
+                                &self.identity.secret_key,
+                                peer_key(&self.encryption_keys, peer),
+                                &event.content,
+                            ) {
+                                if let Some(taken) = accept(message.event) {
+                                    return Ok(Some(taken));
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Whether messages to peer should be sealed
+    pub fn sealed(&self, peer_pub_key: &str) -> bool {
+        self.sealed_peers.contains(peer_pub_key)
+    }
+
+    /// How messages to peer are encrypted and published
+    pub fn envelope(&self, peer_pub_key: &str) -> Envelope {
+        Envelope {
+            secret_key: self.identity.secret_key,
+            pub_key: peer_key(&self.encryption_keys, peer_pub_key).to_string(),
+            sealed: self.sealed(peer_pub_key),
+            tagged: self.tagged_peers.contains(peer_pub_key),
+        }
+    }
+
+    /// Publishes a message the round can not go on without, until the publish quorum of relays accepts it
+    /// Returns the id of the signed event
+    fn publish_critical(
+        &mut self,
+        peer_pub_key: &str,
+        kind: u16,
+        message: &NostrdizerMessage,
+    ) -> Result<String, Error> {
+        let (event_id, event) = utils::message_event(
             &self.identity,
-            129,
-            &encrypted_content,
-            &[vec!["p".to_string(), peer_pub_key.to_string()]],
-            0,
+            peer_pub_key,
+            kind,
+            message,
+            &self.envelope(peer_pub_key),
+        )?;
+        // Sessions only move to the session relays once makers sent some
+        let relays = match self.relay_pool.session_relays.is_empty() {
+            true => self.relay_pool.offer_relays.clone(),
+            false => self.relay_pool.preferred(RelayRole::Session),
+        };
+        self.publisher.publish(
+            self.session_client
+                .as_mut()
+                .unwrap_or(&mut self.nostr_client),
+            &relays,
+            &self.config.publish_quorum,
+            &event,
         )?;
-        */
+        Ok(event_id)
+    }
 
-        Ok(())
+    /// Round id of the session with maker, for logs and errors
+    pub fn maker_round_id(&self, maker: &str) -> &str {
+        round_label(&self.round_ids, maker)
+    }
+}
+
+/// Makers that signed the round on a protocol version that can not sign it again once rebuilt
+pub fn rebuild_unsupported(
+    peer_inputs: &[(NostrdizerOffer, IoAuth)],
+    signed_makers: &[String],
+) -> Vec<String> {
+    peer_inputs
+        .iter()
+        .filter(|(offer, _)| {
+            signed_makers.contains(&offer.maker) && offer.protocol_version < REBUILD_VERSION
+        })
+        .map(|(offer, _)| offer.maker.clone())
+        .collect()
+}
+
+// Separate from `maker_round_id` so it can be used while the client is borrowed
+fn round_label<'a>(round_ids: &'a HashMap<String, String>, maker: &str) -> &'a str {
+    round_ids
+        .get(maker)
+        .map(|round_id| round_id.as_str())
+        .unwrap_or("unknown")
+}
+
+/// Records how long maker took to answer step, if the taker was waiting on it
+/// Takes the fields rather than the taker so it can be used while the client is borrowed
+fn record_response(
+    timer: &mut ResponseTimer,
+    reputation: &mut Reputation,
+    maker: &str,
+    step: Step,
+) {
+    if let Some(millis) = timer.stop(maker, step) {
+        reputation.record_latency(maker, step, millis);
     }
 }
+
+/// Seconds to wait at step for makers, long enough for the slowest of them
+/// Free of the taker so the wait loops can check it while their subscription borrows the client
+fn step_timeout<'a>(
+    latency_classes: &HashMap<String, LatencyClass>,
+    step: ProtocolStep,
+    makers: impl IntoIterator<Item = &'a String>,
+) -> u64 {
+    latency::deadline(
+        step,
+        makers
+            .into_iter()
+            .map(|maker| latency_classes.get(maker).copied()),
+    )
+}
@@ -0,0 +1,304 @@
+use crate::{
+    errors::Error,
+    types::{Amount, Network, Payment, Txid, DUST},
+};
+
+use rand::{seq::SliceRandom, Rng};
+use serde::{Deserialize, Serialize};
+
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+/// Times a round of a schedule is tried before the tumble stops
+pub const MAX_ROUND_ATTEMPTS: u32 = 3;
+
+/// Shape of the schedule a tumble runs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScheduleConfig {
+    /// Sats paid to the destinations in all
+    pub amount: Amount,
+    /// Addresses the coins end up at, each is paid in a round of its own
+    pub destinations: Vec<String>,
+    /// Rounds to the wallet itself before any destination is paid
+    pub mixing_rounds: usize,
+    /// Makers of each round, picked at random in the range
+    pub makers: RangeInclusive<usize>,
+    /// Seconds waited before each round, picked at random in the range
+    pub delay: RangeInclusive<u64>,
+}
+
+/// Round of a schedule
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ScheduledRound {
+    #[serde(with = "bdk::bitcoin::util::amount::serde::as_sat")]
+    pub amount: Amount,
+    pub makers: usize,
+    /// Seconds waited after the round before it
+    pub delay: u64,
+    /// Address the round pays, none for a round to the wallet itself
+    #[serde(default)]
+    pub destination: Option<String>,
+    /// CJ of the round once it completed
+    #[serde(default)]
+    pub txid: Option<Txid>,
+    /// Times the round failed
+    #[serde(default)]
+    pub attempts: u32,
+}
+
+impl ScheduledRound {
+    /// Payment of a round to a destination, it pays the CJ amount so the destination's output is one of the equal outputs
+    pub fn payment(&self, network: Network) -> Result<Option<Payment>, Error> {
+        self.destination
+            .as_ref()
+            .map(|destination| Payment::new(destination, self.amount, network))
+            .transpose()
+    }
+}
+
+/// Rounds of a tumble and how far it got, kept between runs so a stopped tumble resumes
+/// ```
+/// use nostrdizer::{
+///     taker::scheduler::{Schedule, ScheduleConfig},
+///     types::{Amount, Network},
+/// };
+/// # use bdk::bitcoin::OutPoint;
+/// use rand::thread_rng;
+///
+/// let config = ScheduleConfig {
+///     amount: Amount::from_sat(1_000_000),
+///     destinations: vec![
+///         "bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080".to_string(),
+///         "bcrt1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3qzf4jry".to_string(),
+///     ],
+///     mixing_rounds: 3,
+///     makers: 3..=5,
+///     delay: 60..=600,
+/// };
+/// let mut schedule = Schedule::generate(&config, Network::Regtest, 0, &mut thread_rng()).unwrap();
+/// assert_eq!(schedule.rounds.len(), 5);
+/// let paid: u64 = schedule
+///     .rounds
+///     .iter()
+///     .filter(|round| round.destination.is_some())
+///     .map(|round| round.amount.to_sat())
+///     .sum();
+/// assert_eq!(paid, config.amount.to_sat());
+///
+/// assert_eq!(schedule.next_round(), Some(0));
+/// schedule.complete(0, OutPoint::null().txid, 100);
+/// assert_eq!(schedule.next_round(), Some(1));
+/// assert_eq!(schedule.starts_at(1), 100 + schedule.rounds[1].delay);
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Schedule {
+    pub rounds: Vec<ScheduledRound>,
+    /// Time the last round completed, or the schedule was made, delays count from it
+    pub last_round_at: u64,
+}
+
+impl Schedule {
+    /// Random schedule of mixing rounds to the wallet, then one round to each destination in a random order
+    /// The amount is split at random between the destinations, mixing rounds send between half and all of it
+    pub fn generate<R: Rng>(
+        config: &ScheduleConfig,
+        network: Network,
+        now: u64,
+        rng: &mut R,
+    ) -> Result<Self, Error> {
+        if config.destinations.is_empty() {
+            return Err(Error::InvalidConfig(
+                "A schedule needs a destination".to_string(),
+            ));
+        }
+        if config.makers.is_empty() || *config.makers.start() == 0 || config.delay.is_empty() {
+            return Err(Error::InvalidConfig(
+                "Makers and delays of a schedule need a range".to_string(),
+            ));
+        }
+
+        let mut rounds = vec![];
+        for _ in 0..config.mixing_rounds {
+            let share = rng.gen_range(0.5..=1.0);
+            let amount = Amount::from_sat((config.amount.to_sat() as f64 * share) as u64);
+            rounds.push(scheduled_round(config, amount, None, rng));
+        }
+
+        let mut destinations = config.destinations.clone();
+        destinations.shuffle(rng);
+        for (destination, amount) in
+            destinations
+                .into_iter()
+                .zip(split(config.amount, config.destinations.len(), rng))
+        {
+            if amount <= DUST {
+                return Err(Error::InvalidConfig(format!(
+                    "{amount} is too little to pay {destination} in a round"
+                )));
+            }
+            rounds.push(scheduled_round(config, amount, Some(destination), rng));
+        }
+
+        let schedule = Self {
+            rounds,
+            last_round_at: now,
+        };
+        // Destinations are checked before any round is run
+        for round in &schedule.rounds {
+            round.payment(network)?;
+        }
+        Ok(schedule)
+    }
+
+    /// Loads the schedule from path, none if there is no file
+    pub fn load(path: &Path) -> Result<Option<Self>, Error> {
+        match path.exists() {
+            true => Ok(Some(serde_json::from_str(&fs::read_to_string(path)?)?)),
+            false => Ok(None),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        file.write_all(serde_json::to_string_pretty(self)?.as_bytes())?;
+        Ok(())
+    }
+
+    /// Index of the first round that has not completed, none once all have
+    pub fn next_round(&self) -> Option<usize> {
+        self.rounds.iter().position(|round| round.txid.is_none())
+    }
+
+    /// Time the round at index runs from
+    pub fn starts_at(&self, index: usize) -> u64 {
+        self.last_round_at + self.rounds[index].delay
+    }
+
+    pub fn completed(&self) -> usize {
+        self.rounds
+            .iter()
+            .filter(|round| round.txid.is_some())
+            .count()
+    }
+
+    pub fn complete(&mut self, index: usize, txid: Txid, now: u64) {
+        self.rounds[index].txid = Some(txid);
+        self.last_round_at = now;
+    }
+
+    /// Counts a failed try of the round at index, returns whether it can be tried again
+    /// The next try waits the round's delay again
+    pub fn fail(&mut self, index: usize, now: u64) -> bool {
+        let round = &mut self.rounds[index];
+        round.attempts += 1;
+        self.last_round_at = now;
+        round.attempts < MAX_ROUND_ATTEMPTS
+    }
+}
+
+/// Round of amount with makers and a delay picked at random
+fn scheduled_round<R: Rng>(
+    config: &ScheduleConfig,
+    amount: Amount,
+    destination: Option<String>,
+    rng: &mut R,
+) -> ScheduledRound {
+    ScheduledRound {
+        amount,
+        makers: rng.gen_range(config.makers.clone()),
+        delay: rng.gen_range(config.delay.clone()),
+        destination,
+        txid: None,
+        attempts: 0,
+    }
+}
+
+/// Amount split at random in parts of between about half and one and a half times an even share
+fn split<R: Rng>(amount: Amount, parts: usize, rng: &mut R) -> Vec<Amount> {
+    let weights: Vec<f64> = (0..parts).map(|_| rng.gen_range(0.5..1.5)).collect();
+    let total: f64 = weights.iter().sum();
+    let mut left = amount.to_sat();
+    let mut amounts: Vec<Amount> = weights
+        .iter()
+        .take(parts.saturating_sub(1))
+        .map(|weight| {
+            let part = ((amount.to_sat() as f64 * weight / total) as u64).min(left);
+            left -= part;
+            Amount::from_sat(part)
+        })
+        .collect();
+    // What rounding left goes to the last part so the parts add up to the amount
+    amounts.push(Amount::from_sat(left));
+    amounts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bdk::bitcoin::OutPoint;
+    use rand::thread_rng;
+
+    fn config(destinations: Vec<&str>) -> ScheduleConfig {
+        ScheduleConfig {
+            amount: Amount::from_sat(500_000),
+            destinations: destinations.into_iter().map(String::from).collect(),
+            mixing_rounds: 2,
+            makers: 2..=4,
+            delay: 10..=20,
+        }
+    }
+
+    #[test]
+    fn test_resumes_after_restart() {
+        let config = config(vec!["bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080"]);
+        let mut schedule =
+            Schedule::generate(&config, Network::Regtest, 0, &mut thread_rng()).unwrap();
+        for round in &schedule.rounds {
+            assert!((2..=4).contains(&round.makers));
+            assert!((10..=20).contains(&round.delay));
+        }
+        // The destination is paid last
+        assert!(schedule.rounds[2].destination.is_some());
+
+        schedule.complete(0, OutPoint::null().txid, 50);
+        assert!(schedule.fail(1, 80));
+        let path = std::env::temp_dir().join("nostrdizer-schedule.json");
+        schedule.save(&path).unwrap();
+        let resumed = Schedule::load(&path).unwrap().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(resumed, schedule);
+        assert_eq!(resumed.next_round(), Some(1));
+        assert_eq!(resumed.starts_at(1), 80 + resumed.rounds[1].delay);
+
+        let mut exhausted = resumed;
+        assert!(exhausted.fail(1, 90));
+        assert!(!exhausted.fail(1, 100));
+    }
+
+    #[test]
+    fn test_destinations_checked() {
+        let mainnet = config(vec!["bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kqhcywg"]);
+        assert!(Schedule::generate(&mainnet, Network::Regtest, 0, &mut thread_rng()).is_err());
+        assert!(
+            Schedule::generate(&config(vec![]), Network::Regtest, 0, &mut thread_rng()).is_err()
+        );
+    }
+
+    #[test]
+    fn test_split_adds_up() {
+        let parts = split(Amount::from_sat(1_000_001), 3, &mut thread_rng());
+        assert_eq!(parts.len(), 3);
+        assert_eq!(
+            parts.iter().map(|part| part.to_sat()).sum::<u64>(),
+            1_000_001
+        );
+        assert!(parts.iter().all(|part| part.to_sat() > 1_000_001 / 3 / 4));
+    }
+}
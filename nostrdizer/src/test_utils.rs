@@ -0,0 +1,275 @@
+use crate::{
+    coin_selection::SelectionStrategy,
+    errors::Error,
+    greylist::GreylistPolicy,
+    jitter::OfferJitter,
+    policy::RoundPolicy,
+    publication::PublishQuorum,
+    relay_auth::RelayAuthConfig,
+    subscription::RelayClient,
+    types::{
+        AbsOffer, Amount, Fill, InputLimits, IoAuth, MakerConfig, NostrdizerMessage,
+        NostrdizerMessageKind, NostrdizerMessages, NostrdizerOffer, Offer, ABS_OFFER,
+        PROTOCOL_VERSION, REL_OFFER,
+    },
+    utils::network_tag,
+};
+
+use bdk::bitcoin::{
+    psbt::PartiallySignedTransaction, Address, Network, OutPoint, PackedLockTime, Script, Sequence,
+    Transaction, TxIn, TxOut, Witness,
+};
+use bitcoin_hashes::{sha256, Hash};
+use nostr_rust::{
+    events::{Event, EventPrepare},
+    req::ReqFilter,
+    Identity,
+};
+use serde_json::json;
+use tungstenite::Message;
+
+use std::str::FromStr;
+
+/// Offer from `maker` with an absolute cj fee
+pub fn offer(maker: &str, cjfee: u64) -> NostrdizerOffer {
+    NostrdizerOffer {
+        maker: maker.to_string(),
+        oid: 0,
+        txfee: Amount::ZERO,
+        cjfee: Amount::from_sat(cjfee),
+        protocol_version: 0,
+        txfee_rate: None,
+        latency_class: None,
+        max_podle_index: None,
+        encryption_key: None,
+    }
+}
+
+/// Absolute offer of 5,000 to 1,000,000 sats with a cj fee of `cjfee` sats, as a maker publishes it
+pub fn abs_offer(cjfee: u64) -> Offer {
+    Offer::AbsOffer(AbsOffer {
+        offer_id: 0,
+        minsize: Amount::from_sat(5_000),
+        maxsize: Amount::from_sat(1_000_000),
+        txfee: Amount::ZERO,
+        txfee_rate: None,
+        cjfee: Amount::from_sat(cjfee),
+        min_fee_rate: None,
+        protocol_version: 0,
+        latency_class: None,
+        valid_from: None,
+        max_podle_index: None,
+        encryption_key: None,
+        fidelity_bond: None,
+    })
+}
+
+/// Nostr identity with a secret key of `n` repeated, standing in for a taker or maker
+pub fn identity(n: u8) -> Identity {
+    Identity::from_str(&hex::encode([n; 32])).unwrap()
+}
+
+/// Offer event signed by identity and tagged for network, as a relay sends it
+pub fn offer_event(identity: &Identity, offer: Offer, network: Network, created_at: u64) -> Event {
+    let kind = match offer {
+        Offer::AbsOffer(_) => ABS_OFFER,
+        Offer::RelOffer(_) => REL_OFFER,
+    };
+    let content = serde_json::to_string(&NostrdizerMessage {
+        event_type: NostrdizerMessageKind::Offer,
+        event: NostrdizerMessages::Offer(offer),
+        round_id: None,
+    })
+    .unwrap();
+    EventPrepare {
+        pub_key: identity.public_key_str.clone(),
+        created_at,
+        kind,
+        tags: vec![network_tag(network)],
+        content,
+    }
+    .to_event(identity, 0)
+}
+
+/// Fill of `amount` sats asking for a single CJ output
+pub fn fill(amount: u64) -> Fill {
+    Fill {
+        offer_id: 0,
+        protocol_version: Some(PROTOCOL_VERSION),
+        amount: Amount::from_sat(amount),
+        tencpubkey: "".to_string(),
+        commitment: sha256::Hash::hash("".as_bytes()),
+        denominations: vec![],
+        script_type: None,
+        counter: false,
+        cjfee: None,
+        payment: None,
+        silent_payment: None,
+        invite: None,
+        input_limits: InputLimits::default(),
+    }
+}
+
+/// Maker config with an absolute fee of 1000 sats and no limits
+pub fn maker_config() -> MakerConfig {
+    MakerConfig {
+        abs_fee: Amount::from_sat(1000),
+        rel_fee: 0.0,
+        minsize: Amount::ZERO,
+        maxsize: None,
+        max_per_round: None,
+        will_broadcast: true,
+        min_fee_rate: None,
+        min_participants: None,
+        allow_unconfirmed: false,
+        max_change_ratio: None,
+        txfee_rate: None,
+        avoid_change: false,
+        donate_change_below: None,
+        policy: RoundPolicy::default(),
+        publish_quorum: PublishQuorum::default(),
+        counter_offer_margin: None,
+        latency_class: None,
+        relay_auth: RelayAuthConfig::default(),
+        valid_from: None,
+        max_podle_index: None,
+        coin_selection: SelectionStrategy::default(),
+        offer_jitter: OfferJitter::none(),
+        greylist: GreylistPolicy::default(),
+    }
+}
+
+/// Maker inputs with regtest addresses that differ for each `n`
+pub fn io_auth(n: u8) -> IoAuth {
+    IoAuth {
+        utxos: vec![],
+        utxo_hints: vec![],
+        coinjoin_address: Address::p2wsh(&Script::from(vec![n, 0]), Network::Regtest),
+        change_address: Address::p2wsh(&Script::from(vec![n, 1]), Network::Regtest),
+        extra_coinjoin_addresses: vec![],
+        no_change: false,
+        silent_payment: None,
+        maker_auth_pub: "".to_string(),
+        bitcoin_sig: "".to_string(),
+    }
+}
+
+/// Unsigned psbt spending inputs of the given sat values to outputs of the given sat values
+/// Inputs have their `witness_utxo` set so fees can be calculated from the psbt
+/// ```
+/// use nostrdizer::test_utils::psbt;
+///
+/// let psbt = psbt(&[60_000, 50_000], &[100_000, 9_000]);
+///
+/// assert_eq!(psbt.inputs.len(), 2);
+/// assert_eq!(psbt.unsigned_tx.output[1].value, 9_000);
+/// ```
+pub fn psbt(inputs: &[u64], outputs: &[u64]) -> PartiallySignedTransaction {
+    let tx = Transaction {
+        version: 2,
+        lock_time: PackedLockTime::ZERO,
+        input: (0..inputs.len())
+            .map(|vout| TxIn {
+                previous_output: OutPoint {
+                    txid: OutPoint::null().txid,
+                    vout: vout as u32,
+                },
+                script_sig: Script::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            })
+            .collect(),
+        output: outputs
+            .iter()
+            .map(|value| TxOut {
+                value: *value,
+                script_pubkey: Script::new(),
+            })
+            .collect(),
+    };
+
+    let mut psbt = PartiallySignedTransaction::from_unsigned_tx(tx).unwrap();
+    for (input, value) in psbt.inputs.iter_mut().zip(inputs) {
+        input.witness_utxo = Some(TxOut {
+            value: *value,
+            script_pubkey: Script::new(),
+        });
+    }
+
+    psbt
+}
+
+/// Relay holding `events`, standing in for the relays of a client
+/// Each subscription is sent the events its filters match, newest first up to their limit, then EOSE
+pub struct MockRelay {
+    pub url: String,
+    pub events: Vec<Event>,
+    /// Subscriptions that have not been sent their events yet
+    pending: Vec<(String, Vec<ReqFilter>)>,
+    subscriptions: usize,
+}
+
+impl MockRelay {
+    pub fn new(url: &str, events: Vec<Event>) -> Self {
+        Self {
+            url: url.to_string(),
+            events,
+            pending: vec![],
+            subscriptions: 0,
+        }
+    }
+
+    /// Events matching the kinds, authors and time bounds of filter
+    fn matching(&self, filter: &ReqFilter) -> Vec<&Event> {
+        let mut events: Vec<&Event> = self
+            .events
+            .iter()
+            .filter(|event| {
+                filter
+                    .kinds
+                    .as_ref()
+                    .map_or(true, |kinds| kinds.contains(&event.kind))
+                    && filter
+                        .authors
+                        .as_ref()
+                        .map_or(true, |authors| authors.contains(&event.pub_key))
+                    && filter.since.map_or(true, |since| event.created_at >= since)
+                    && filter.until.map_or(true, |until| event.created_at <= until)
+            })
+            .collect();
+        events.sort_by_key(|event| std::cmp::Reverse(event.created_at));
+        if let Some(limit) = filter.limit {
+            events.truncate(limit as usize);
+        }
+        events
+    }
+}
+
+impl RelayClient for MockRelay {
+    fn subscribe(&mut self, filters: Vec<ReqFilter>) -> Result<String, Error> {
+        self.subscriptions += 1;
+        let id = format!("mock-{}", self.subscriptions);
+        self.pending.push((id.clone(), filters));
+        Ok(id)
+    }
+
+    fn unsubscribe(&mut self, id: &str) -> Result<(), Error> {
+        self.pending.retain(|(pending, _)| pending != id);
+        Ok(())
+    }
+
+    fn next_data(&mut self) -> Result<Vec<(String, Message)>, Error> {
+        let mut data = vec![];
+        for (id, filters) in std::mem::take(&mut self.pending) {
+            for filter in &filters {
+                for event in self.matching(filter) {
+                    let message = json!(["EVENT", id, event]).to_string();
+                    data.push((self.url.clone(), Message::Text(message)));
+                }
+            }
+            let eose = json!(["EOSE", id]).to_string();
+            data.push((self.url.clone(), Message::Text(eose)));
+        }
+        Ok(data)
+    }
+}
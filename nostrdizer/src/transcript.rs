@@ -0,0 +1,204 @@
+use crate::{
+    errors::Error,
+    types::{Confirm, Txid},
+};
+
+use bitcoin_hashes::{sha256, Hash, HashEngine};
+use serde::{Deserialize, Serialize};
+
+use std::fs;
+use std::path::Path;
+
+/// Ids of the signed events sent between a taker and a maker in a round, in order
+/// Both sides record the same ids, so a message a relay dropped or changed gives a different hash
+/// ```
+/// use nostrdizer::{transcript::Transcript, types::OutPoint};
+/// # let txid = OutPoint::null().txid;
+///
+/// let mut taker = Transcript::default();
+/// let mut maker = Transcript::default();
+/// for event_id in ["fill", "fill ack", "auth", "ioauth"] {
+///     taker.record(event_id);
+///     maker.record(event_id);
+/// }
+/// taker.record("tx");
+/// assert_ne!(taker.hash(&txid), maker.hash(&txid));
+///
+/// maker.record("tx");
+/// assert_eq!(taker.hash(&txid), maker.hash(&txid));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Transcript {
+    pub event_ids: Vec<String>,
+}
+
+impl Transcript {
+    pub fn record(&mut self, event_id: &str) {
+        self.event_ids.push(event_id.to_string());
+    }
+
+    pub fn contains(&self, event_id: &str) -> bool {
+        self.event_ids.iter().any(|recorded| recorded == event_id)
+    }
+
+    /// Hash of the event ids in order followed by the txid of the CJ
+    pub fn hash(&self, txid: &Txid) -> sha256::Hash {
+        let mut engine = sha256::Hash::engine();
+        for event_id in &self.event_ids {
+            engine.input(event_id.as_bytes());
+            engine.input(b"\n");
+        }
+        engine.input(txid.to_string().as_bytes());
+        sha256::Hash::from_engine(engine)
+    }
+}
+
+/// How a round ended once its CJ was broadcast
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Completion {
+    /// Peer saw the same messages and txid
+    Confirmed,
+    /// Peer saw different messages or a different txid
+    Mismatch,
+    /// Peer did not confirm in time
+    Unconfirmed,
+}
+
+impl Completion {
+    /// Compares the confirm a peer sent to the own transcript hash
+    pub fn check(transcript: sha256::Hash, txid: &Txid, confirm: Option<&Confirm>) -> Self {
+        match confirm {
+            Some(confirm) if confirm.transcript == transcript && &confirm.txid == txid => {
+                Self::Confirmed
+            }
+            Some(_) => Self::Mismatch,
+            None => Self::Unconfirmed,
+        }
+    }
+}
+
+/// Round with a peer whose CJ was broadcast
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RoundRecord {
+    pub round_id: String,
+    /// Pub key of the counterparty
+    pub peer: String,
+    pub txid: Txid,
+    /// Own transcript hash
+    pub transcript: sha256::Hash,
+    pub completion: Completion,
+    pub created_at: u64,
+}
+
+/// Rounds a taker or maker completed, kept between runs
+/// ```
+/// use nostrdizer::{
+///     transcript::{Completion, RoundHistory, RoundRecord, Transcript},
+///     types::OutPoint,
+/// };
+/// # let txid = OutPoint::null().txid;
+///
+/// let record = |peer: &str, completion| RoundRecord {
+///     round_id: "round".to_string(),
+///     peer: peer.to_string(),
+///     txid,
+///     transcript: Transcript::default().hash(&txid),
+///     completion,
+///     created_at: 0,
+/// };
+///
+/// let mut history = RoundHistory::default();
+/// history.record(record("maker", Completion::Confirmed));
+/// history.record(record("maker", Completion::Mismatch));
+/// history.record(record("other", Completion::Confirmed));
+///
+/// assert_eq!(history.confirmed_rounds("maker"), 1);
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct RoundHistory {
+    pub rounds: Vec<RoundRecord>,
+}
+
+impl RoundHistory {
+    /// Loads history from path, an empty history if there is no file
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        match path.exists() {
+            true => Ok(serde_json::from_str(&fs::read_to_string(path)?)?),
+            false => Ok(Self::default()),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        Ok(fs::write(path, serde_json::to_string_pretty(self)?)?)
+    }
+
+    pub fn record(&mut self, record: RoundRecord) {
+        self.rounds.push(record);
+    }
+
+    /// Number of rounds with peer that both sides confirmed
+    pub fn confirmed_rounds(&self, peer: &str) -> usize {
+        self.rounds
+            .iter()
+            .filter(|round| round.peer == peer && round.completion == Completion::Confirmed)
+            .count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bdk::bitcoin::OutPoint;
+
+    fn transcript(event_ids: &[&str]) -> Transcript {
+        let mut transcript = Transcript::default();
+        for event_id in event_ids {
+            transcript.record(event_id);
+        }
+        transcript
+    }
+
+    #[test]
+    fn test_transcript_hash() {
+        let txid = OutPoint::null().txid;
+        let hash = transcript(&["fill", "ack", "auth"]).hash(&txid);
+
+        // Reordered or dropped messages change the hash
+        assert_ne!(hash, transcript(&["fill", "auth", "ack"]).hash(&txid));
+        assert_ne!(hash, transcript(&["fill", "auth"]).hash(&txid));
+        // Ids are separated so they can not be split differently
+        assert_ne!(hash, transcript(&["fil", "lack", "auth"]).hash(&txid));
+    }
+
+    #[test]
+    fn test_completion() {
+        let txid = OutPoint::null().txid;
+        let hash = transcript(&["fill", "ack"]).hash(&txid);
+
+        let confirm = Confirm {
+            txid,
+            transcript: hash,
+            tx: None,
+        };
+        assert_eq!(
+            Completion::check(hash, &txid, Some(&confirm)),
+            Completion::Confirmed
+        );
+
+        let tampered = Confirm {
+            txid,
+            transcript: transcript(&["fill"]).hash(&txid),
+            tx: None,
+        };
+        assert_eq!(
+            Completion::check(hash, &txid, Some(&tampered)),
+            Completion::Mismatch
+        );
+        assert_eq!(
+            Completion::check(hash, &txid, None),
+            Completion::Unconfirmed
+        );
+    }
+}
@@ -1,28 +1,84 @@
-pub use bdk::bitcoin::{Amount, Network};
+pub use bdk::bitcoin::{
+    psbt::PartiallySignedTransaction, Amount, Network, OutPoint, SignedAmount,
+    Transaction as BitcoinTransaction, Txid,
+};
 
-use bdk::bitcoin::{
-    psbt::{Input, PartiallySignedTransaction},
-    Address, OutPoint, SignedAmount,
+use crate::{
+    coin_selection::SelectionStrategy,
+    cosign::CoSigning,
+    encryption::EncryptionKey,
+    errors::Error,
+    fiat::FiatConfig,
+    fidelity_bond::FidelityBondProof,
+    greylist::GreylistPolicy,
+    jitter::OfferJitter,
+    latency::LatencyClass,
+    maker_selection::MakerSelection,
+    policy::RoundPolicy,
+    publication::PublishQuorum,
+    relay_auth::RelayAuthConfig,
+    relay_pool::RelayDelivery,
+    silent_payment::{BlindedScanKey, SilentPaymentAddress, SilentPaymentShare},
+    utils::check_address,
 };
-use bitcoin_hashes::sha256::Hash;
+
+use bdk::bitcoin::{psbt::Input, Address, Script};
+use bitcoin_hashes::{sha256::Hash, Hash as _, HashEngine};
 use secp256k1::PublicKey;
 use serde::{Deserialize, Serialize};
 
+use std::path::PathBuf;
+use std::str::FromStr;
+
 // Nostr Message Kinds
 pub const ABS_OFFER: u16 = 10123;
 pub const REL_OFFER: u16 = 10124;
+pub const PRESENCE: u16 = 10125;
+pub const BACKUP: u16 = 10126;
 pub const FILL: u16 = 125;
 pub const PUBKEY: u16 = 126;
 pub const AUTH: u16 = 127;
 pub const IOAUTH: u16 = 128;
 pub const TRANSACTION: u16 = 129;
 pub const SIGNED_TRANSACTION: u16 = 130;
+pub const REJECT: u16 = 131;
+pub const FILL_ACK: u16 = 132;
+pub const CONFIRM: u16 = 133;
+pub const ABORT: u16 = 134;
+pub const COOP_JOIN: u16 = 135;
+pub const USED_COMMITMENTS: u16 = 136;
+pub const NETWORK_STATS: u16 = 137;
+pub const SEALED: u16 = 138;
+
+// Protocol version advertised in offers
+pub const PROTOCOL_VERSION: u16 = 9;
+// First protocol version that accepts sealed messages
+pub const SEALED_VERSION: u16 = 1;
+// First protocol version where makers answer a fill with their session relays
+pub const FILL_ACK_VERSION: u16 = 2;
+// First protocol version where makers confirm the round transcript once the CJ is broadcast
+pub const CONFIRM_VERSION: u16 = 3;
+// First protocol version where makers end a session when the taker aborts it
+pub const ABORT_VERSION: u16 = 4;
+// First protocol version where makers check the auth is bound to their session
+pub const AUTH_BINDING_VERSION: u16 = 5;
+// First protocol version where makers send a psbt input for every utxo, bitcoin core makers sent none before
+pub const PSBT_INPUT_VERSION: u16 = 6;
+// First protocol version where makers send a share of their input keys towards a silent payment
+pub const SILENT_PAYMENT_VERSION: u16 = 7;
+// First protocol version where makers sign the CJ again when the taker rebuilds it without makers that did not sign
+pub const REBUILD_VERSION: u16 = 8;
+// First protocol version that reads messages tagged with their type and schema version
+pub const TAGGED_VERSION: u16 = 9;
+
+// Seconds a peer waits for the next message of a session before giving up on it
+pub const SESSION_TIMEOUT: u64 = 300;
 
 // Dust limit
 pub const DUST: u64 = 546;
 
-// Max fee percent
-pub const MAX_FEE: f32 = 0.15;
+// Max fee in basis points of the send amount
+pub const MAX_FEE_BPS: u64 = 1_500;
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct NostrdizerOffer {
@@ -32,6 +88,26 @@ pub struct NostrdizerOffer {
     pub txfee: Amount,
     #[serde(with = "bdk::bitcoin::util::amount::serde::as_sat")]
     pub cjfee: Amount,
+    pub protocol_version: u16,
+    #[serde(default)]
+    pub txfee_rate: Option<TxFeeRate>,
+    #[serde(default)]
+    pub latency_class: Option<LatencyClass>,
+    #[serde(default)]
+    pub max_podle_index: Option<u8>,
+    #[serde(default)]
+    pub encryption_key: Option<EncryptionKey>,
+}
+
+/// Mining fee a maker contributes for the vbytes of its inputs and outputs
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct TxFeeRate {
+    /// sat/vB
+    #[serde(rename = "rate")]
+    pub fee_rate: f32,
+    /// Max vbytes the maker contributes for
+    #[serde(rename = "maxvbytes")]
+    pub max_vbytes: usize,
 }
 
 /// Maker Relative Offer
@@ -50,8 +126,40 @@ pub struct RelOffer {
     /// Amount Maker will contribute to mining fee
     #[serde(with = "bdk::bitcoin::util::amount::serde::as_sat")]
     pub txfee: Amount,
+    /// Mining fee maker contributes at a fee rate, used instead of `txfee` when set
+    #[serde(default, rename = "txfeerate", skip_serializing_if = "Option::is_none")]
+    pub txfee_rate: Option<TxFeeRate>,
     /// CJ Fee maker expects
     pub cjfee: f64,
+    /// Min mining fee rate in sat/vB maker will sign
+    #[serde(
+        default,
+        rename = "minfeerate",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub min_fee_rate: Option<f32>,
+    /// Protocol version maker supports
+    #[serde(default)]
+    pub protocol_version: u16,
+    /// How quickly the maker answers, takers wait the default timeouts when not set
+    #[serde(default, rename = "latency", skip_serializing_if = "Option::is_none")]
+    pub latency_class: Option<LatencyClass>,
+    /// Unix time the offer can be filled from, announced before the maker's liquidity is ready
+    #[serde(default, rename = "validfrom", skip_serializing_if = "Option::is_none")]
+    pub valid_from: Option<u64>,
+    /// Highest podle index the maker accepts, takers assume [`MAX_PODLE_INDEX`](crate::podle::MAX_PODLE_INDEX) when not set
+    #[serde(
+        default,
+        rename = "maxpodleindex",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub max_podle_index: Option<u8>,
+    /// Key messages to the maker are encrypted to, its nostr key when not set
+    #[serde(default, rename = "enckey", skip_serializing_if = "Option::is_none")]
+    pub encryption_key: Option<EncryptionKey>,
+    /// Coins the maker locked to make sybils costly, takers verify it against the chain
+    #[serde(default, rename = "bond", skip_serializing_if = "Option::is_none")]
+    pub fidelity_bond: Option<FidelityBondProof>,
 }
 
 /// Maker Absolute offer
@@ -70,30 +178,303 @@ pub struct AbsOffer {
     /// Amount Maker will contribute to mining fee
     #[serde(with = "bdk::bitcoin::util::amount::serde::as_sat")]
     pub txfee: Amount,
+    /// Mining fee maker contributes at a fee rate, used instead of `txfee` when set
+    #[serde(default, rename = "txfeerate", skip_serializing_if = "Option::is_none")]
+    pub txfee_rate: Option<TxFeeRate>,
     /// CJ Fee maker expects
     #[serde(with = "bdk::bitcoin::util::amount::serde::as_sat")]
     pub cjfee: Amount,
+    /// Min mining fee rate in sat/vB maker will sign
+    #[serde(
+        default,
+        rename = "minfeerate",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub min_fee_rate: Option<f32>,
+    /// Protocol version maker supports
+    #[serde(default)]
+    pub protocol_version: u16,
+    /// How quickly the maker answers, takers wait the default timeouts when not set
+    #[serde(default, rename = "latency", skip_serializing_if = "Option::is_none")]
+    pub latency_class: Option<LatencyClass>,
+    /// Unix time the offer can be filled from, announced before the maker's liquidity is ready
+    #[serde(default, rename = "validfrom", skip_serializing_if = "Option::is_none")]
+    pub valid_from: Option<u64>,
+    /// Highest podle index the maker accepts, takers assume [`MAX_PODLE_INDEX`](crate::podle::MAX_PODLE_INDEX) when not set
+    #[serde(
+        default,
+        rename = "maxpodleindex",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub max_podle_index: Option<u8>,
+    /// Key messages to the maker are encrypted to, its nostr key when not set
+    #[serde(default, rename = "enckey", skip_serializing_if = "Option::is_none")]
+    pub encryption_key: Option<EncryptionKey>,
+    /// Coins the maker locked to make sybils costly, takers verify it against the chain
+    #[serde(default, rename = "bond", skip_serializing_if = "Option::is_none")]
+    pub fidelity_bond: Option<FidelityBondProof>,
 }
 
+/// Version of the tagged wire format of offers and messages, the formats from before it was versioned are version 0
+pub const SCHEMA_VERSION: u16 = 1;
+
+/// Maker offer
+/// Published in the externally tagged format every taker reads, as a maker can not tell which takers read the tagged one
+/// Offers with their `type` and schema `version` as fields are read too, fields an offer does not know are ignored
+/// ```
+/// use nostrdizer::types::Offer;
+/// use serde_json::json;
+///
+/// let legacy = json!({
+///     "sw0absoffer": { "oid": 1, "minsize": 10000, "maxsize": 100000, "txfee": 0, "cjfee": 500 }
+/// });
+/// let offer: Offer = serde_json::from_value(legacy.clone()).unwrap();
+/// assert_eq!(serde_json::to_value(&offer).unwrap(), legacy);
+///
+/// let tagged = json!({
+///     "type": "sw0absoffer", "version": 2, "oid": 1, "minsize": 10000, "maxsize": 100000,
+///     "txfee": 0, "cjfee": 500, "fidelity_proof": "proof"
+/// });
+/// assert!(serde_json::from_value::<Offer>(tagged).is_ok());
+/// ```
 #[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(from = "WireOffer", into = "LegacyOffer")]
 pub enum Offer {
+    RelOffer(RelOffer),
+    AbsOffer(AbsOffer),
+}
+
+/// Offer with its type and schema version as fields, every version so far reads the same fields
+#[derive(Deserialize)]
+#[serde(tag = "type")]
+enum TaggedOffer {
+    #[serde(rename = "sw0reloffer")]
+    RelOffer(RelOffer),
+    #[serde(rename = "sw0absoffer")]
+    AbsOffer(AbsOffer),
+}
+
+/// Offer of schema version 0, tagged by its type as the key of an object
+#[derive(Serialize, Deserialize)]
+enum LegacyOffer {
     #[serde(rename = "sw0reloffer")]
     RelOffer(RelOffer),
     #[serde(rename = "sw0absoffer")]
     AbsOffer(AbsOffer),
 }
 
+/// Offer in any of the formats that are read
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum WireOffer {
+    Tagged(TaggedOffer),
+    Legacy(LegacyOffer),
+}
+
+impl From<WireOffer> for Offer {
+    fn from(offer: WireOffer) -> Self {
+        match offer {
+            WireOffer::Tagged(TaggedOffer::RelOffer(offer)) => Offer::RelOffer(offer),
+            WireOffer::Tagged(TaggedOffer::AbsOffer(offer)) => Offer::AbsOffer(offer),
+            WireOffer::Legacy(LegacyOffer::RelOffer(offer)) => Offer::RelOffer(offer),
+            WireOffer::Legacy(LegacyOffer::AbsOffer(offer)) => Offer::AbsOffer(offer),
+        }
+    }
+}
+
+impl Offer {
+    /// Unix time the offer can be filled from, none if it can be filled now
+    pub fn valid_from(&self) -> Option<u64> {
+        match self {
+            Offer::RelOffer(offer) => offer.valid_from,
+            Offer::AbsOffer(offer) => offer.valid_from,
+        }
+    }
+
+    /// Bond the maker advertises in the offer, not yet verified
+    pub fn fidelity_bond(&self) -> Option<&FidelityBondProof> {
+        match self {
+            Offer::RelOffer(offer) => offer.fidelity_bond.as_ref(),
+            Offer::AbsOffer(offer) => offer.fidelity_bond.as_ref(),
+        }
+    }
+
+    /// Whether the offer can be filled at `now`, offers announced ahead are upcoming until then
+    /// ```
+    /// use nostrdizer::types::Offer;
+    /// use serde_json::json;
+    ///
+    /// let offer: Offer = serde_json::from_value(json!({
+    ///     "type": "sw0absoffer", "version": 1, "oid": 1, "minsize": 10000, "maxsize": 100000,
+    ///     "txfee": 0, "cjfee": 500, "validfrom": 1000
+    /// }))
+    /// .unwrap();
+    ///
+    /// assert!(!offer.is_valid(999));
+    /// assert!(offer.is_valid(1000));
+    /// ```
+    pub fn is_valid(&self, now: u64) -> bool {
+        self.valid_from()
+            .map_or(true, |valid_from| now >= valid_from)
+    }
+}
+
+impl From<Offer> for LegacyOffer {
+    fn from(offer: Offer) -> Self {
+        match offer {
+            Offer::RelOffer(offer) => LegacyOffer::RelOffer(offer),
+            Offer::AbsOffer(offer) => LegacyOffer::AbsOffer(offer),
+        }
+    }
+}
+
 /// Taker Fill
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 #[serde(rename = "fill")]
 pub struct Fill {
     #[serde(rename = "oid")]
     pub offer_id: u32,
+    /// Protocol version of the taker, takers before `TAGGED_VERSION` do not send it
+    #[serde(default, rename = "protover", skip_serializing_if = "Option::is_none")]
+    pub protocol_version: Option<u16>,
     #[serde(with = "bdk::bitcoin::util::amount::serde::as_sat")]
     pub amount: Amount,
     pub tencpubkey: String,
     /// Used for Poodle Hash of P2
     pub commitment: Hash,
+    /// CJ outputs the maker adds, a single output of `amount` when empty
+    #[serde(default, rename = "denoms", skip_serializing_if = "Vec::is_empty")]
+    pub denominations: Vec<Denomination>,
+    /// Script type every input of the round is, any type when not set
+    #[serde(default, rename = "stype", skip_serializing_if = "Option::is_none")]
+    pub script_type: Option<DescriptorType>,
+    /// Taker takes a counter-offer in place of a reject when the fill is just outside the offer
+    #[serde(
+        default,
+        rename = "counter",
+        skip_serializing_if = "std::ops::Not::not"
+    )]
+    pub counter: bool,
+    /// Fee of a counter-offer the taker accepted, the offer's fee when not set
+    #[serde(
+        default,
+        with = "bdk::bitcoin::util::amount::serde::as_sat::opt",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub cjfee: Option<Amount>,
+    /// Value of the one output of a payment round that pays an external destination, a mix round when not set
+    #[serde(
+        default,
+        rename = "pay",
+        with = "bdk::bitcoin::util::amount::serde::as_sat::opt",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub payment: Option<Amount>,
+    /// Blinded scan key of the silent payment of a payment round, makers send their share of it with their inputs
+    #[serde(default, rename = "spscan", skip_serializing_if = "Option::is_none")]
+    pub silent_payment: Option<BlindedScanKey>,
+    /// Invite token of the taker, makers of a private pool only take fills carrying one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub invite: Option<String>,
+    /// Most inputs the taker takes from makers, makers select no more than it allows
+    #[serde(
+        default,
+        rename = "inlimit",
+        skip_serializing_if = "InputLimits::is_none"
+    )]
+    pub input_limits: InputLimits,
+}
+
+/// Most inputs a taker takes in a round, so a maker spending many small utxos does not bloat the CJ's fee
+/// ```
+/// use nostrdizer::types::{InputLimits, RejectReason};
+///
+/// let limits = InputLimits {
+///     per_maker: Some(5),
+///     total: Some(8),
+/// };
+/// assert_eq!(limits.max_per_maker(), Some(5));
+/// assert_eq!(limits.check(3, 0), None);
+/// assert_eq!(
+///     limits.check(6, 0),
+///     Some(RejectReason::TooManyInputs { inputs: 6, max_inputs: 5 })
+/// );
+/// // Other makers already sent 6 of the 8
+/// assert_eq!(
+///     limits.check(3, 6),
+///     Some(RejectReason::TooManyInputs { inputs: 3, max_inputs: 2 })
+/// );
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InputLimits {
+    /// Inputs of one maker
+    #[serde(default, rename = "maker", skip_serializing_if = "Option::is_none")]
+    pub per_maker: Option<usize>,
+    /// Inputs of every maker together
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total: Option<usize>,
+}
+
+impl InputLimits {
+    pub fn is_none(&self) -> bool {
+        self.per_maker.is_none() && self.total.is_none()
+    }
+
+    /// Most inputs one maker can add, the total caps it too
+    pub fn max_per_maker(&self) -> Option<usize> {
+        match (self.per_maker, self.total) {
+            (Some(per_maker), Some(total)) => Some(per_maker.min(total)),
+            (per_maker, total) => per_maker.or(total),
+        }
+    }
+
+    /// Why a maker's inputs are turned down when other makers already added `taken` inputs, none if they fit
+    pub fn check(&self, inputs: usize, taken: usize) -> Option<RejectReason> {
+        let left = self.total.map(|total| total.saturating_sub(taken));
+        let max_inputs = match (self.per_maker, left) {
+            (Some(per_maker), Some(left)) => per_maker.min(left),
+            (per_maker, left) => per_maker.or(left)?,
+        };
+        match inputs > max_inputs {
+            true => Some(RejectReason::TooManyInputs { inputs, max_inputs }),
+            false => None,
+        }
+    }
+}
+
+impl Fill {
+    /// Value of each CJ output the maker adds, in the order of the denominations
+    pub fn outputs(&self) -> Vec<Amount> {
+        if self.denominations.is_empty() {
+            return vec![self.amount];
+        }
+        self.denominations
+            .iter()
+            .flat_map(|d| std::iter::repeat(d.amount).take(d.count))
+            .collect()
+    }
+
+    /// Value of the output every participant has one of, used to count participants
+    pub fn participant_amount(&self) -> Amount {
+        match self.denominations.first() {
+            Some(denomination) => denomination.amount,
+            None => self.amount,
+        }
+    }
+
+    /// Whether the taker reads tagged messages
+    pub fn tagged(&self) -> bool {
+        self.protocol_version
+            .map_or(false, |version| version >= TAGGED_VERSION)
+    }
+}
+
+/// Number of equal valued CJ outputs of an amount a maker adds
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Denomination {
+    #[serde(with = "bdk::bitcoin::util::amount::serde::as_sat")]
+    pub amount: Amount,
+    pub count: usize,
 }
 
 /// Maker pubkey
@@ -107,24 +488,260 @@ pub struct Pubkey {
 #[serde(rename = "tx")]
 pub struct Transaction {
     pub psbt: PartiallySignedTransaction,
+    /// Number of parties the taker says are in the CJ, including itself
+    #[serde(default)]
+    pub participants: Option<usize>,
+}
+
+/// Script type of a UTXO, used to know the weight of spending it
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum DescriptorType {
+    Wpkh,
+    ShWpkh,
+    Tr,
+}
+
+impl DescriptorType {
+    /// Gets the type from an output descriptor such as `wpkh([fingerprint/84h/1h/0h/0/0]02..)#checksum`
+    /// ```
+    /// use nostrdizer::types::DescriptorType;
+    ///
+    /// let descriptor_type = DescriptorType::from_descriptor("sh(wpkh([8fa88d24/49h/1h/0h/0/0]02..))");
+    ///
+    /// assert_eq!(descriptor_type, Some(DescriptorType::ShWpkh));
+    /// assert_eq!(descriptor_type.unwrap().max_satisfaction_weight(), 204);
+    /// ```
+    pub fn from_descriptor(descriptor: &str) -> Option<Self> {
+        if descriptor.starts_with("wpkh(") {
+            Some(Self::Wpkh)
+        } else if descriptor.starts_with("sh(wpkh(") {
+            Some(Self::ShWpkh)
+        } else if descriptor.starts_with("tr(") {
+            Some(Self::Tr)
+        } else {
+            None
+        }
+    }
+
+    /// Gets the type of script pubkeys that have only one way to be spent
+    pub fn from_script(script: &Script) -> Option<Self> {
+        if script.is_v0_p2wpkh() {
+            Some(Self::Wpkh)
+        } else if script.is_v1_p2tr() {
+            Some(Self::Tr)
+        } else {
+            None
+        }
+    }
+
+    /// Gets the type of an output, any P2SH output counts as `ShWpkh`
+    pub fn of_output(script: &Script) -> Option<Self> {
+        Self::from_script(script).or_else(|| script.is_p2sh().then_some(Self::ShWpkh))
+    }
+
+    /// Whether a script pubkey is of this type
+    /// Any P2SH script counts as `ShWpkh` as the redeem script is not known until it is spent
+    /// ```
+    /// use bdk::bitcoin::Address;
+    /// use nostrdizer::types::DescriptorType;
+    /// use std::str::FromStr;
+    ///
+    /// let address = Address::from_str("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4").unwrap();
+    ///
+    /// assert!(DescriptorType::Wpkh.matches_script(&address.script_pubkey()));
+    /// assert!(!DescriptorType::ShWpkh.matches_script(&address.script_pubkey()));
+    /// ```
+    pub fn matches_script(&self, script: &Script) -> bool {
+        match self {
+            Self::Wpkh => script.is_v0_p2wpkh(),
+            Self::ShWpkh => script.is_p2sh(),
+            Self::Tr => script.is_v1_p2tr(),
+        }
+    }
+
+    /// Max weight of the script sig and witness spending this type
+    // Same as miniscript max_satisfaction_weight, scriptSig length is counted as non witness data
+    pub fn max_satisfaction_weight(&self) -> usize {
+        match self {
+            // scriptSig len, witness items, sig, pubkey
+            Self::Wpkh => 4 + 1 + 73 + 34,
+            // scriptSig with redeem script, witness items, sig, pubkey
+            Self::ShWpkh => 4 * 24 + 1 + 73 + 34,
+            // scriptSig len, witness items, schnorr sig
+            Self::Tr => 4 + 1 + 66,
+        }
+    }
+}
+
+impl FromStr for DescriptorType {
+    type Err = Error;
+
+    fn from_str(script_type: &str) -> Result<Self, Self::Err> {
+        match script_type {
+            "wpkh" => Ok(Self::Wpkh),
+            "shwpkh" => Ok(Self::ShWpkh),
+            "tr" => Ok(Self::Tr),
+            _ => Err(Error::UnknownScriptType(script_type.to_string())),
+        }
+    }
+}
+
+/// What a maker knows about one of its UTXOs
+/// Lets takers on any backend add it as a foreign input when there is no psbt input
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct UtxoHint {
+    pub outpoint: OutPoint,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub script_pubkey: Option<Script>,
+    #[serde(
+        default,
+        with = "bdk::bitcoin::util::amount::serde::as_sat::opt",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub value: Option<Amount>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub descriptor_type: Option<DescriptorType>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename = "ioauth")]
 pub struct IoAuth {
-    // TODO: input should not be an option
-    // Its an issue between compatibility of BDK and core
+    /// Psbt input of each utxo with its witness utxo, so a taker of any backend can add it as a foreign utxo
     #[serde(rename = "ulist")]
-    pub utxos: Vec<(OutPoint, Option<Input>)>,
+    pub utxos: Vec<(OutPoint, Input)>,
+    /// Hints for utxos, the descriptor type sizes the utxo's witness
+    #[serde(default, rename = "uhints")]
+    pub utxo_hints: Vec<UtxoHint>,
     pub maker_auth_pub: String,
     #[serde(rename = "coinjoinA")]
     pub coinjoin_address: Address,
     #[serde(rename = "changeA")]
     pub change_address: Address,
+    /// Addresses of the denomination outputs after the first, in fill order
+    #[serde(default, rename = "coinjoinAs", skip_serializing_if = "Vec::is_empty")]
+    pub extra_coinjoin_addresses: Vec<Address>,
+    /// Inputs are worth what the maker owes to within dust, so it gets no change output
+    #[serde(
+        default,
+        rename = "nochange",
+        skip_serializing_if = "std::ops::Not::not"
+    )]
+    pub no_change: bool,
+    /// Share of the inputs' keys towards the taker's silent payment, none when the fill has no scan key
+    #[serde(default, rename = "spshare", skip_serializing_if = "Option::is_none")]
+    pub silent_payment: Option<SilentPaymentShare>,
     /// bitcoin signature of mencpubkey
     pub bitcoin_sig: String,
 }
 
+impl IoAuth {
+    /// Address of each CJ output, in fill order
+    pub fn coinjoin_addresses(&self) -> Vec<&Address> {
+        std::iter::once(&self.coinjoin_address)
+            .chain(&self.extra_coinjoin_addresses)
+            .collect()
+    }
+
+    /// CJ and change addresses
+    pub fn addresses(&self) -> Vec<&Address> {
+        let mut addresses = self.coinjoin_addresses();
+        addresses.push(&self.change_address);
+        addresses
+    }
+
+    /// Change output the taker adds for the maker, none when it is dust or the maker declared no change
+    /// ```
+    /// use nostrdizer::{test_utils::io_auth, types::Amount};
+    ///
+    /// let mut io_auth = io_auth(0);
+    /// assert_eq!(io_auth.change_output(Amount::from_sat(546)), None);
+    /// assert!(io_auth.change_output(Amount::from_sat(2000)).is_some());
+    ///
+    /// io_auth.no_change = true;
+    /// assert_eq!(io_auth.change_output(Amount::from_sat(2000)), None);
+    /// ```
+    pub fn change_output(&self, change: Amount) -> Option<Amount> {
+        (!self.no_change && change.to_sat() > DUST).then_some(change)
+    }
+
+    pub fn hint(&self, outpoint: &OutPoint) -> Option<&UtxoHint> {
+        self.utxo_hints.iter().find(|h| &h.outpoint == outpoint)
+    }
+
+    /// Script pubkey of a utxo from its psbt input
+    pub fn script_pubkey(&self, outpoint: &OutPoint, input: &Input) -> Option<Script> {
+        match (&input.witness_utxo, &input.non_witness_utxo) {
+            (Some(tx_out), _) => Some(tx_out.script_pubkey.clone()),
+            (None, Some(tx)) => Some(tx.output.get(outpoint.vout as usize)?.script_pubkey.clone()),
+            (None, None) => None,
+        }
+    }
+
+    /// Utxos that are not of `script_type`, or whose script pubkey is not known
+    /// ```
+    /// use bdk::bitcoin::{psbt::Input, Address, TxOut};
+    /// use nostrdizer::{
+    ///     test_utils::io_auth,
+    ///     types::{DescriptorType, OutPoint},
+    /// };
+    /// use std::str::FromStr;
+    ///
+    /// let utxo = |vout, address: &str| {
+    ///     let input = Input {
+    ///         witness_utxo: Some(TxOut {
+    ///             value: 100_000,
+    ///             script_pubkey: Address::from_str(address).unwrap().script_pubkey(),
+    ///         }),
+    ///         ..Default::default()
+    ///     };
+    ///     (OutPoint::new(OutPoint::null().txid, vout), input)
+    /// };
+    /// let mut maker_input = io_auth(0);
+    /// maker_input.utxos = vec![
+    ///     utxo(0, "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4"),
+    ///     utxo(1, "3J98t1WpEZ73CNmQviecrnyiWrnqRhWNLy"),
+    /// ];
+    ///
+    /// // An input without a witness utxo can not be checked
+    /// maker_input.utxos.push((OutPoint::new(OutPoint::null().txid, 2), Input::default()));
+    ///
+    /// // The nested segwit input would stand out in a native segwit round
+    /// let mismatched = maker_input.mismatched_inputs(DescriptorType::Wpkh);
+    /// assert_eq!(mismatched, vec![maker_input.utxos[1].0, maker_input.utxos[2].0]);
+    /// ```
+    pub fn mismatched_inputs(&self, script_type: DescriptorType) -> Vec<OutPoint> {
+        self.utxos
+            .iter()
+            .filter(
+                |(outpoint, input)| match self.script_pubkey(outpoint, input) {
+                    Some(script) => !script_type.matches_script(&script),
+                    None => true,
+                },
+            )
+            .map(|(outpoint, _)| *outpoint)
+            .collect()
+    }
+
+    /// Value of a utxo from its psbt input
+    pub fn utxo_value(&self, outpoint: &OutPoint, input: &Input) -> Option<Amount> {
+        match (&input.witness_utxo, &input.non_witness_utxo) {
+            (Some(tx_out), _) => Some(Amount::from_sat(tx_out.value)),
+            (None, Some(tx)) => Some(Amount::from_sat(
+                tx.output.get(outpoint.vout as usize)?.value,
+            )),
+            (None, None) => None,
+        }
+    }
+
+    /// Max satisfaction weight of a utxo if the maker sent its type
+    pub fn satisfaction_weight(&self, outpoint: &OutPoint) -> Option<usize> {
+        self.hint(outpoint)?
+            .descriptor_type
+            .map(|t| t.max_satisfaction_weight())
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename = "sig")]
 pub struct SignedTransaction {
@@ -132,6 +749,143 @@ pub struct SignedTransaction {
     pub psbt: PartiallySignedTransaction,
 }
 
+/// Maker acknowledgement of a fill
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename = "fillack")]
+pub struct FillAck {
+    /// Relays the rest of the session is negotiated on
+    #[serde(rename = "relays")]
+    pub session_relays: Vec<String>,
+    /// Key the rest of the session is encrypted to, for takers that saw an offer without it
+    #[serde(default, rename = "enckey", skip_serializing_if = "Option::is_none")]
+    pub encryption_key: Option<EncryptionKey>,
+}
+
+/// Reason a peer refused to continue a CJ
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum RejectReason {
+    /// Maker fee is less then the maker's offer
+    CJFeeTooLow,
+    /// Send amount is outside of the maker's min and max size, or its inputs are over its max per round
+    AmountOutOfRange,
+    /// Transaction fee rate is below the maker's floor
+    FeeRateTooLow { fee_rate: f32, min_fee_rate: f32 },
+    /// Maker has too many fills queued to take this one
+    Busy,
+    /// CJ has fewer equal valued outputs then the maker requires
+    TooFewParticipants {
+        participants: usize,
+        min_participants: usize,
+    },
+    /// Taker claimed a different number of participants then the CJ has
+    ParticipantMismatch { claimed: usize, counted: usize },
+    /// Fill asks for denominations the maker does not support
+    UnsupportedDenominations,
+    /// CJ spends counterparty inputs that are not confirmed
+    UnconfirmedInputs { outpoints: Vec<OutPoint> },
+    /// Maker has no inputs that cover the fill without too much change or going over its max per round
+    NoSuitableInputs,
+    /// CJ does not pay one of the maker's CJ outputs
+    MissingCJOutput {
+        #[serde(with = "bdk::bitcoin::util::amount::serde::as_sat")]
+        amount: Amount,
+    },
+    /// Fill asks for a CJ output too small to be worth mixing
+    BelowPrivacyFloor {
+        #[serde(with = "bdk::bitcoin::util::amount::serde::as_sat")]
+        amount: Amount,
+    },
+    /// CJ has more outputs near dust then the maker allows, as dust forwarding spam does
+    DustOutputs { count: usize },
+    /// CJ outputs are not all of one script type, so change can be told apart by its type
+    MixedOutputTypes,
+    /// Fill is just over the maker's max size, it takes the round on these terms
+    CounterOffer(CounterOffer),
+    /// CJ pays a script that is not of a standard type, which may be unspendable
+    NonstandardOutputs,
+    /// Payment round does not have exactly one output of the payment value
+    PaymentOutputs { count: usize },
+    /// CJ has more outputs than its mixing outputs could have change
+    NonuniformOutputs { mixing: usize, other: usize },
+    /// Maker only takes fills with a valid invite token for the taker
+    InviteRequired,
+    /// Maker sent more inputs than the taker takes in the round
+    TooManyInputs { inputs: usize, max_inputs: usize },
+    /// Fill's podle commitment was opened in an earlier round
+    CommitmentUsed,
+}
+
+/// Terms a maker takes a fill just over its max size for
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CounterOffer {
+    /// Largest amount the maker takes for the round
+    #[serde(with = "bdk::bitcoin::util::amount::serde::as_sat")]
+    pub maxsize: Amount,
+    /// Fee the maker takes the round for
+    #[serde(with = "bdk::bitcoin::util::amount::serde::as_sat")]
+    pub cjfee: Amount,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename = "reject")]
+pub struct Reject {
+    pub reason: RejectReason,
+}
+
+/// Taker ended the session before the CJ, sent to makers it filled but does not need
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(rename = "abort")]
+pub struct Abort {
+    /// Why the taker turned down the maker's inputs, none when the maker is not needed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<RejectReason>,
+}
+
+/// Taker co-funding the round of another taker, sent to the taker leading it
+/// The partner joins the CJ like a maker that takes no fee and pays its share of the fees
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename = "coopjoin")]
+pub struct CoopJoin {
+    /// Send amount the partner's CJ output is
+    #[serde(with = "bdk::bitcoin::util::amount::serde::as_sat")]
+    pub amount: Amount,
+    /// Most the partner pays of the fees
+    #[serde(with = "bdk::bitcoin::util::amount::serde::as_sat", rename = "maxfee")]
+    pub max_fee: Amount,
+    /// Partner's inputs and outputs
+    #[serde(rename = "io")]
+    pub io_auth: IoAuth,
+    /// Makers the partner picked, filled by the lead on top of its own
+    #[serde(default)]
+    pub makers: Vec<String>,
+}
+
+/// Whether a maker is taking fills
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PresenceStatus {
+    Online,
+    Offline,
+}
+
+/// Maker presence, replaced as the maker goes on and offline
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename = "presence")]
+pub struct Presence {
+    pub status: PresenceStatus,
+}
+
+/// Hash of the round transcript, exchanged once the CJ is broadcast
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename = "confirm")]
+pub struct Confirm {
+    pub txid: Txid,
+    pub transcript: Hash,
+    /// Final CJ shared by the taker so a maker that will broadcast can
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tx: Option<BitcoinTransaction>,
+}
+
 /// Possible messages that can be sent
 #[derive(Serialize, Deserialize, Debug, Clone)]
 // Look at these they may be able to tag better and remove the nostrdizer message type field
@@ -139,11 +893,17 @@ pub struct SignedTransaction {
 pub enum NostrdizerMessages {
     Offer(Offer),
     Fill(Fill),
+    FillAck(FillAck),
     PubKey(Pubkey),
-    Auth(AuthCommitment),
+    Auth(AuthProof),
     MakerInputs(IoAuth),
     UnsignedCJ(Transaction),
     SignedCJ(SignedTransaction),
+    Reject(Reject),
+    Confirm(Confirm),
+    Presence(Presence),
+    Abort(Abort),
+    CoopJoin(CoopJoin),
 }
 
 /// Kinds of `NostrdizerMessages`
@@ -153,6 +913,8 @@ pub enum NostrdizerMessageKind {
     Offer,
     /// Taker filling offer
     FillOffer,
+    /// Maker session relays
+    FillAck,
     /// Maker pub key
     MakerPubkey,
     /// TakerAuth
@@ -164,22 +926,233 @@ pub enum NostrdizerMessageKind {
     UnsignedCJ,
     /// Signed CJ transactions
     SignedCJ,
+    /// Peer refused to continue
+    Reject,
+    /// Transcript hash once the CJ is broadcast
+    Confirm,
+    /// Maker online or offline
+    Presence,
+    /// Taker ended the session
+    Abort,
+    /// Taker co-funding the round
+    CoopJoin,
 }
 
+/// Protocol message
+/// Serialized in the legacy format every peer reads, [`NostrdizerMessage::to_wire`] tags it for peers that read the tagged one
 #[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(from = "WireMessage")]
 pub struct NostrdizerMessage {
     pub event_type: NostrdizerMessageKind,
     pub event: NostrdizerMessages,
+    /// Round the message is part of, set on every message after the fill
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub round_id: Option<String>,
+}
+
+impl NostrdizerMessage {
+    /// Message as it is sent to a peer, with its `type` and schema `version` as fields when the peer reads them
+    /// Peers before `TAGGED_VERSION` only read the legacy format, both are read
+    /// ```
+    /// use nostrdizer::{
+    ///     test_utils::fill,
+    ///     types::{NostrdizerMessage, NostrdizerMessageKind, NostrdizerMessages},
+    /// };
+    /// use serde_json::Value;
+    ///
+    /// let message = NostrdizerMessage {
+    ///     event_type: NostrdizerMessageKind::FillOffer,
+    ///     event: NostrdizerMessages::Fill(fill(100_000)),
+    ///     round_id: Some("round".to_string()),
+    /// };
+    /// let tagged: Value = serde_json::from_str(&message.to_wire(true).unwrap()).unwrap();
+    /// assert_eq!(tagged["type"], "fill");
+    /// assert_eq!(tagged["version"], 1);
+    /// assert_eq!(tagged["amount"], 100_000);
+    ///
+    /// for tagged in [true, false] {
+    ///     let read: NostrdizerMessage = serde_json::from_str(&message.to_wire(tagged).unwrap()).unwrap();
+    ///     assert_eq!(read.event_type, NostrdizerMessageKind::FillOffer);
+    ///     assert_eq!(read.round_id.as_deref(), Some("round"));
+    /// }
+    /// ```
+    pub fn to_wire(&self, tagged: bool) -> Result<String, serde_json::Error> {
+        match tagged {
+            true => serde_json::to_string(&TaggedMessage::from(self.clone())),
+            false => serde_json::to_string(self),
+        }
+    }
+}
+
+/// Message with its type and schema version as fields alongside the fields of the message
+#[derive(Serialize, Deserialize)]
+struct TaggedMessage {
+    version: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    round_id: Option<String>,
+    #[serde(flatten)]
+    event: TaggedEvent,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type")]
+enum TaggedEvent {
+    #[serde(rename = "offer")]
+    Offer(Offer),
+    #[serde(rename = "fill")]
+    Fill(Fill),
+    #[serde(rename = "fillack")]
+    FillAck(FillAck),
+    #[serde(rename = "pubkey")]
+    PubKey(Pubkey),
+    #[serde(rename = "auth")]
+    Auth(AuthProof),
+    #[serde(rename = "ioauth")]
+    MakerInputs(IoAuth),
+    #[serde(rename = "tx")]
+    UnsignedCJ(Transaction),
+    #[serde(rename = "sig")]
+    SignedCJ(SignedTransaction),
+    #[serde(rename = "reject")]
+    Reject(Reject),
+    #[serde(rename = "confirm")]
+    Confirm(Confirm),
+    #[serde(rename = "presence")]
+    Presence(Presence),
+    #[serde(rename = "abort")]
+    Abort(Abort),
+    #[serde(rename = "coopjoin")]
+    CoopJoin(CoopJoin),
+}
+
+/// Message of schema version 0, with its kind and the message tagged by its type
+#[derive(Deserialize)]
+struct LegacyMessage {
+    event_type: NostrdizerMessageKind,
+    event: NostrdizerMessages,
+    #[serde(default)]
+    round_id: Option<String>,
+}
+
+/// Message in any of the formats that are read
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum WireMessage {
+    Tagged(TaggedMessage),
+    Legacy(LegacyMessage),
+}
+
+impl From<NostrdizerMessage> for TaggedMessage {
+    fn from(message: NostrdizerMessage) -> Self {
+        let event = match message.event {
+            NostrdizerMessages::Offer(offer) => TaggedEvent::Offer(offer),
+            NostrdizerMessages::Fill(fill) => TaggedEvent::Fill(fill),
+            NostrdizerMessages::FillAck(ack) => TaggedEvent::FillAck(ack),
+            NostrdizerMessages::PubKey(pubkey) => TaggedEvent::PubKey(pubkey),
+            NostrdizerMessages::Auth(auth) => TaggedEvent::Auth(auth),
+            NostrdizerMessages::MakerInputs(io_auth) => TaggedEvent::MakerInputs(io_auth),
+            NostrdizerMessages::UnsignedCJ(tx) => TaggedEvent::UnsignedCJ(tx),
+            NostrdizerMessages::SignedCJ(tx) => TaggedEvent::SignedCJ(tx),
+            NostrdizerMessages::Reject(reject) => TaggedEvent::Reject(reject),
+            NostrdizerMessages::Confirm(confirm) => TaggedEvent::Confirm(confirm),
+            NostrdizerMessages::Presence(presence) => TaggedEvent::Presence(presence),
+            NostrdizerMessages::Abort(abort) => TaggedEvent::Abort(abort),
+            NostrdizerMessages::CoopJoin(join) => TaggedEvent::CoopJoin(join),
+        };
+        TaggedMessage {
+            version: SCHEMA_VERSION,
+            round_id: message.round_id,
+            event,
+        }
+    }
+}
+
+impl From<WireMessage> for NostrdizerMessage {
+    fn from(message: WireMessage) -> Self {
+        let message = match message {
+            WireMessage::Legacy(message) => {
+                return NostrdizerMessage {
+                    event_type: message.event_type,
+                    event: message.event,
+                    round_id: message.round_id,
+                }
+            }
+            WireMessage::Tagged(message) => message,
+        };
+        // The kind is implied by the type of a tagged message
+        let (event_type, event) = match message.event {
+            TaggedEvent::Offer(offer) => (
+                NostrdizerMessageKind::Offer,
+                NostrdizerMessages::Offer(offer),
+            ),
+            TaggedEvent::Fill(fill) => (
+                NostrdizerMessageKind::FillOffer,
+                NostrdizerMessages::Fill(fill),
+            ),
+            TaggedEvent::FillAck(ack) => (
+                NostrdizerMessageKind::FillAck,
+                NostrdizerMessages::FillAck(ack),
+            ),
+            TaggedEvent::PubKey(pubkey) => (
+                NostrdizerMessageKind::MakerPubkey,
+                NostrdizerMessages::PubKey(pubkey),
+            ),
+            TaggedEvent::Auth(auth) => {
+                (NostrdizerMessageKind::Auth, NostrdizerMessages::Auth(auth))
+            }
+            TaggedEvent::MakerInputs(io_auth) => (
+                NostrdizerMessageKind::MakerInput,
+                NostrdizerMessages::MakerInputs(io_auth),
+            ),
+            TaggedEvent::UnsignedCJ(tx) => (
+                NostrdizerMessageKind::UnsignedCJ,
+                NostrdizerMessages::UnsignedCJ(tx),
+            ),
+            TaggedEvent::SignedCJ(tx) => (
+                NostrdizerMessageKind::SignedCJ,
+                NostrdizerMessages::SignedCJ(tx),
+            ),
+            TaggedEvent::Reject(reject) => (
+                NostrdizerMessageKind::Reject,
+                NostrdizerMessages::Reject(reject),
+            ),
+            TaggedEvent::Confirm(confirm) => (
+                NostrdizerMessageKind::Confirm,
+                NostrdizerMessages::Confirm(confirm),
+            ),
+            TaggedEvent::Presence(presence) => (
+                NostrdizerMessageKind::Presence,
+                NostrdizerMessages::Presence(presence),
+            ),
+            TaggedEvent::Abort(abort) => (
+                NostrdizerMessageKind::Abort,
+                NostrdizerMessages::Abort(abort),
+            ),
+            TaggedEvent::CoopJoin(join) => (
+                NostrdizerMessageKind::CoopJoin,
+                NostrdizerMessages::CoopJoin(join),
+            ),
+        };
+        NostrdizerMessage {
+            event_type,
+            event,
+            round_id: message.round_id,
+        }
+    }
 }
 
 /// Final CJ transaction info
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct VerifyCJInfo {
-    #[serde(with = "bdk::bitcoin::util::amount::serde::as_btc")]
+    #[serde(with = "bdk::bitcoin::util::amount::serde::as_sat")]
     pub mining_fee: SignedAmount,
-    #[serde(with = "bdk::bitcoin::util::amount::serde::as_btc")]
+    #[serde(with = "bdk::bitcoin::util::amount::serde::as_sat")]
     pub maker_fee: SignedAmount,
+    /// Estimated fee rate in sat/vB once signed
+    pub fee_rate: f32,
     pub verifyed: bool,
+    /// Why the transaction failed verification
+    pub reject_reason: Option<RejectReason>,
 }
 
 /// CJ Fee required for transaction
@@ -188,7 +1161,7 @@ pub struct VerifyCJInfo {
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct CJFee {
     /// Absolute CJ fee
-    #[serde(with = "bdk::bitcoin::util::amount::serde::as_btc")]
+    #[serde(with = "bdk::bitcoin::util::amount::serde::as_sat")]
     pub abs_fee: Amount,
     /// Relative CJ fee
     pub rel_fee: f64,
@@ -198,10 +1171,12 @@ pub struct CJFee {
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct MaxMineingFee {
     /// Max absolute value of mining fee
-    #[serde(with = "bdk::bitcoin::util::amount::serde::as_btc")]
+    #[serde(with = "bdk::bitcoin::util::amount::serde::as_sat")]
     pub abs_fee: Amount,
     /// Max mining fee as percent of send amount
     pub rel_fee: f64,
+    /// Max mining fee rate in sat/vB
+    pub fee_rate: f32,
 }
 
 // TODO: Need to serialize correctly
@@ -214,26 +1189,295 @@ pub struct AuthCommitment {
     pub commit: Hash,
     pub sig: Vec<u8>,
     pub e: Hash,
+    /// NUMS index of P2, makers try every index they accept when not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index: Option<u8>,
+    /// Session the opening is for, committed to in `e`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub binding: Option<AuthBinding>,
+}
+
+/// Proof sent in an auth, of the commitment scheme used with the peer
+/// Untagged so podle proofs are sent as they were before there were other schemes
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum AuthProof {
+    Podle(AuthCommitment),
+}
+
+/// Session a podle opening is bound to, so a maker can not pass it on to another maker as its own
+/// ```
+/// use nostrdizer::types::AuthBinding;
+///
+/// let binding = AuthBinding::new(&["b".to_string(), "a".to_string()], "fill");
+/// // Maker set does not depend on the order makers were filled in
+/// assert_eq!(binding, AuthBinding::new(&["a".to_string(), "b".to_string()], "fill"));
+/// assert_ne!(binding.hash(), AuthBinding::new(&["a".to_string()], "fill").hash());
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct AuthBinding {
+    /// sha256 of the sorted pub keys of the makers the taker sent auth to
+    pub maker_set: Hash,
+    /// Id of the fill event that started the session
+    pub fill: String,
+}
+
+impl AuthBinding {
+    pub fn new(makers: &[String], fill_event_id: &str) -> Self {
+        let mut makers = makers.to_vec();
+        makers.sort();
+        let mut engine = Hash::engine();
+        for maker in &makers {
+            engine.input(maker.as_bytes());
+            engine.input(b"\n");
+        }
+        Self {
+            maker_set: Hash::from_engine(engine),
+            fill: fill_event_id.to_string(),
+        }
+    }
+
+    /// Hash committed to in the podle challenge
+    pub fn hash(&self) -> Hash {
+        let mut engine = Hash::engine();
+        engine.input(&self.maker_set[..]);
+        engine.input(self.fill.as_bytes());
+        Hash::from_engine(engine)
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct MakerConfig {
-    #[serde(with = "bdk::bitcoin::util::amount::serde::as_btc")]
+    #[serde(with = "bdk::bitcoin::util::amount::serde::as_sat")]
     pub abs_fee: Amount,
     pub rel_fee: f64,
-    #[serde(with = "bdk::bitcoin::util::amount::serde::as_btc")]
+    #[serde(with = "bdk::bitcoin::util::amount::serde::as_sat")]
     pub minsize: Amount,
-    #[serde(default, with = "bdk::bitcoin::util::amount::serde::as_btc::opt")]
+    #[serde(default, with = "bdk::bitcoin::util::amount::serde::as_sat::opt")]
     pub maxsize: Option<Amount>,
+    /// Max value of the maker's inputs in one CJ, the offer maxsize is capped to it
+    #[serde(default, with = "bdk::bitcoin::util::amount::serde::as_sat::opt")]
+    pub max_per_round: Option<Amount>,
+    /// Broadcast the final CJ when the taker shares it
     pub will_broadcast: bool,
+    /// Min mining fee rate in sat/vB the maker will sign
+    #[serde(default)]
+    pub min_fee_rate: Option<f32>,
+    /// Min parties, counted by equal valued outputs, in a CJ the maker will sign
+    #[serde(default)]
+    pub min_participants: Option<usize>,
+    /// Add own unconfirmed utxos to CJs, counterparty inputs must always be confirmed
+    #[serde(default)]
+    pub allow_unconfirmed: bool,
+    /// Max change, as a ratio of the fill amount, the maker's inputs may leave
+    #[serde(default)]
+    pub max_change_ratio: Option<f64>,
+    /// Mining fee the maker contributes at a fee rate
+    #[serde(default)]
+    pub txfee_rate: Option<TxFeeRate>,
+    /// Spend utxos worth the fill to within dust when there are some, donating the difference to the mining fee
+    #[serde(default)]
+    pub avoid_change: bool,
+    /// Change under this is not worth tracking, it goes to the mining fee rather than a change output
+    #[serde(default, with = "bdk::bitcoin::util::amount::serde::as_sat::opt")]
+    pub donate_change_below: Option<Amount>,
+    /// Rounds turned down as not worth the maker's liquidity
+    #[serde(default)]
+    pub policy: RoundPolicy,
+    /// Session relays that have to accept the signed CJ
+    #[serde(default)]
+    pub publish_quorum: PublishQuorum,
+    /// Ratio over its max size the maker counters fills up to, at its fee raised by the ratio
+    #[serde(default)]
+    pub counter_offer_margin: Option<f64>,
+    /// How quickly the maker answers, advertised in its offers
+    #[serde(default)]
+    pub latency_class: Option<LatencyClass>,
+    /// Relays the maker authenticates to with NIP-42
+    #[serde(default)]
+    pub relay_auth: RelayAuthConfig,
+    /// Unix time offers can be filled from, for liquidity that is not ready yet
+    #[serde(default)]
+    pub valid_from: Option<u64>,
+    /// Highest podle index the maker accepts, advertised in its offers
+    /// Lower than [`MAX_PODLE_INDEX`](crate::podle::MAX_PODLE_INDEX) each utxo can be sent to the maker in fewer rounds
+    #[serde(default)]
+    pub max_podle_index: Option<u8>,
+    /// How the maker picks the utxos it adds, when it does not spend utxos worth the fill without change
+    #[serde(default)]
+    pub coin_selection: SelectionStrategy,
+    /// Random delays in publishing and refreshing offers
+    #[serde(default)]
+    pub offer_jitter: OfferJitter,
+    /// When takers whose auths fail to verify have their fills ignored
+    #[serde(default)]
+    pub greylist: GreylistPolicy,
+}
+
+/// State of a running maker, written out for debugging
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MakerStatus {
+    pub pub_key: String,
+    pub queued_fills: usize,
+    /// Relay subscriptions that are open
+    pub active_subscriptions: usize,
+    /// Session events each session relay delivered and missed
+    #[serde(default)]
+    pub session_relays: Vec<RelayDelivery>,
+    /// Auths from takers that failed to verify
+    #[serde(default)]
+    pub auth_failures: u32,
+    /// Takers whose fills are ignored for failing auths
+    #[serde(default)]
+    pub greylisted_takers: usize,
+    pub updated_at: u64,
 }
 
 pub struct TakerConfig {
     pub cj_fee: CJFee,
     pub mining_fee: MaxMineingFee,
     pub minium_makers: usize,
+    /// Spend own unconfirmed utxos, counterparty inputs must always be confirmed
+    pub allow_unconfirmed: bool,
+    /// Script type every input of the round must be, declared in fills
+    pub script_type: Option<DescriptorType>,
+    /// Share the final CJ with makers, so makers that will broadcast also broadcast it
+    pub share_final_tx: bool,
+    /// Makers filled on top of the ones needed, to stand in for makers that do not send inputs
+    pub spare_makers: usize,
+    /// Relays that have to accept the auth and unsigned CJ
+    pub publish_quorum: PublishQuorum,
+    /// Fill offers just under the send amount and take the maker's counter-offer within the fee limits
+    pub accept_counter_offers: bool,
+    /// Split the taker change in two outputs of random value when both are worth spending
+    pub split_change: bool,
+    /// External destination paid in the CJ, making it a payment round
+    pub payment: Option<Payment>,
+    /// Wait for fee estimates to drop under the max fee rate and retry a round aborted on a fee spike
+    pub wait_for_fees: bool,
+    /// Invite token sent in fills, for makers of a private pool
+    pub invite: Option<String>,
+    /// Relays the taker authenticates to with NIP-42
+    pub relay_auth: RelayAuthConfig,
+    /// Files the maker-signed CJ goes through before the taker signs
+    pub cosigning: CoSigning,
+    /// Directory the signed CJ of each round is archived to as PSBT version 0 and 2
+    pub archive_dir: Option<PathBuf>,
+    /// Makers without a verified fidelity bond worth at least this are not filled
+    pub min_bond_value: Option<Amount>,
+    /// Order makers without a fidelity bond are filled in
+    pub maker_selection: MakerSelection,
+    /// Most inputs taken from makers in a round
+    pub input_limits: InputLimits,
+    /// Currency fee summaries also show fees in, none for sats only
+    pub fiat: Option<FiatConfig>,
+    /// Taker co-funding the round, experimental
+    pub coop_partner: Option<String>,
+    /// Blocks the CJ should confirm within, the fee rate is estimated for it
+    pub conf_target: u16,
+    /// Spend coins of several sources together when no one source covers the round, linking them
+    pub link_sources: bool,
+    /// Spend every eligible utxo with no change output, fees are taken out of the CJ amount
+    pub sweep: bool,
+}
+
+/// Output a payment round pays to an external destination, besides the taker's CJ output
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Payment {
+    pub destination: PaymentDestination,
+    pub amount: Amount,
+}
+
+/// Where a payment round pays to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PaymentDestination {
+    Address(Address),
+    /// Output is derived from the keys of every input of the CJ, makers' included
+    SilentPayment(SilentPaymentAddress),
+}
+
+impl Payment {
+    /// Payment to an address of a standard type, or a silent payment address, on network
+    /// ```
+    /// use bitcoin::{Amount, Network};
+    /// use nostrdizer::types::{Payment, PaymentDestination};
+    ///
+    /// let amount = Amount::from_sat(30_000);
+    /// let payment = Payment::new("bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080", amount, Network::Regtest).unwrap();
+    /// assert!(matches!(payment.destination, PaymentDestination::Address(_)));
+    ///
+    /// let silent = "sprt1qqfumuen7l8wthtz45p3ftn58pvrs9xlumvkuu2xet8egzkcklqtesqkxq3legs0d04knq32qd62uqlxct3mcujuvau7202avpxu4cuy7u5fwjczx";
+    /// let payment = Payment::new(silent, amount, Network::Regtest).unwrap();
+    /// assert!(matches!(payment.destination, PaymentDestination::SilentPayment(_)));
+    /// assert!(Payment::new(silent, amount, Network::Bitcoin).is_err());
+    /// ```
+    pub fn new(address: &str, amount: Amount, network: Network) -> Result<Self, Error> {
+        if let Ok(silent_payment) = SilentPaymentAddress::from_str(address) {
+            return match silent_payment.is_for(network) {
+                true => Ok(Self {
+                    destination: PaymentDestination::SilentPayment(silent_payment),
+                    amount,
+                }),
+                false => Err(Error::AddressNetwork(address.to_string(), network)),
+            };
+        }
+        let address =
+            Address::from_str(address).map_err(|_| Error::FromStringError(address.to_string()))?;
+        check_address(&address, network)?;
+        Ok(Self {
+            destination: PaymentDestination::Address(address),
+            amount,
+        })
+    }
+
+    /// Silent payment address paid, none for a plain address
+    pub fn silent_payment(&self) -> Option<&SilentPaymentAddress> {
+        match &self.destination {
+            PaymentDestination::SilentPayment(address) => Some(address),
+            PaymentDestination::Address(_) => None,
+        }
+    }
+}
+
+impl Default for TakerConfig {
+    fn default() -> Self {
+        Self {
+            cj_fee: CJFee {
+                rel_fee: 0.30,
+                abs_fee: Amount::from_sat(10000),
+            },
+            mining_fee: MaxMineingFee {
+                abs_fee: Amount::from_sat(10000),
+                rel_fee: 0.20,
+                fee_rate: 100.0,
+            },
+            minium_makers: 1,
+            allow_unconfirmed: false,
+            script_type: None,
+            share_final_tx: false,
+            spare_makers: 0,
+            publish_quorum: PublishQuorum::default(),
+            accept_counter_offers: false,
+            split_change: false,
+            payment: None,
+            wait_for_fees: false,
+            invite: None,
+            relay_auth: RelayAuthConfig::default(),
+            cosigning: CoSigning::default(),
+            archive_dir: None,
+            min_bond_value: None,
+            maker_selection: MakerSelection::default(),
+            input_limits: InputLimits::default(),
+            fiat: None,
+            coop_partner: None,
+            conf_target: 1,
+            link_sources: false,
+            sweep: false,
+        }
+    }
 }
 
+#[derive(Clone)]
 pub struct RpcInfo {
     pub url: String,
     pub username: String,
@@ -242,13 +1486,20 @@ pub struct RpcInfo {
     pub wallet_name: String,
 }
 
+#[derive(Clone)]
 pub struct BitcoinCoreCredentials {
     pub rpc_url: String,
     pub wallet_name: String,
     pub rpc_username: String,
     pub rpc_password: String,
+    pub network: Network,
+    /// Create the wallet if bitcoin core does not have it
+    pub create_wallet: bool,
+    /// Passphrase of an encrypted wallet, it is only unlocked while signing
+    pub wallet_passphrase: Option<String>,
 }
 
+#[derive(Clone)]
 pub enum BlockchainConfig {
     #[cfg(feature = "bitcoincore")]
     CoreRPC(BitcoinCoreCredentials),
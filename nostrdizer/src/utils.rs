@@ -1,17 +1,19 @@
 use super::{
+    encryption::Envelope,
     errors::Error,
+    sequence,
     types::{
-        NostrdizerMessage, NostrdizerMessageKind, NostrdizerMessages, Offer, SignedTransaction,
-        ABS_OFFER, REL_OFFER, SIGNED_TRANSACTION,
+        Confirm, DescriptorType, NostrdizerMessage, NostrdizerMessageKind, NostrdizerMessages,
+        Reject, RejectReason, SignedTransaction, CONFIRM, REJECT, SEALED,
     },
 };
 
-use bdk::bitcoin::psbt::PartiallySignedTransaction;
+use bdk::bitcoin::{psbt::PartiallySignedTransaction, Address, Amount, Network, PrivateKey};
 use nostr_rust::{
-    events::EventPrepare,
+    events::{Event, EventPrepare},
+    keys::get_random_secret_key,
     nips::nip4::{decrypt, encrypt},
     nostr_client::Client as NostrClient,
-    req::ReqFilter,
     utils::get_timestamp,
     Identity,
 };
@@ -20,76 +22,333 @@ use secp256k1::{SecretKey, XOnlyPublicKey};
 
 use std::str::FromStr;
 
-/// Querys nostr realys of current offers
-pub fn get_offers(nostr_client: &mut NostrClient) -> Result<Vec<(String, Offer)>, Error> {
-    let filter = ReqFilter {
-        ids: None,
-        authors: None,
-        kinds: Some(vec![ABS_OFFER, REL_OFFER]),
-        e: None,
-        p: None,
-        since: None,
-        until: None,
-        limit: None,
-    };
-
-    let mut offers = Vec::new();
-
-    let events = nostr_client.get_events_of(vec![filter])?;
-    for event in events {
-        let j_event: NostrdizerMessage = serde_json::from_str(&event.content)?;
-        if let NostrdizerMessages::Offer(offer) = j_event.event {
-            offers.push((event.pub_key, offer));
-        }
+/// Signed psbt message to the taker
+pub fn signed_psbt_message(
+    psbt: PartiallySignedTransaction,
+    round_id: Option<String>,
+) -> NostrdizerMessage {
+    NostrdizerMessage {
+        event_type: NostrdizerMessageKind::SignedCJ,
+        event: NostrdizerMessages::SignedCJ(SignedTransaction { psbt }),
+        round_id,
     }
+}
+
+/// Sends reject message to peer
+pub fn send_reject(
+    identity: &Identity,
+    peer_pub_key: &str,
+    reason: RejectReason,
+    round_id: Option<String>,
+    envelope: &Envelope,
+    nostr_client: &mut NostrClient,
+) -> Result<(), Error> {
+    let message = NostrdizerMessage {
+        event_type: NostrdizerMessageKind::Reject,
+        event: NostrdizerMessages::Reject(Reject { reason }),
+        round_id,
+    };
 
-    Ok(offers.clone())
+    send_message(
+        identity,
+        peer_pub_key,
+        REJECT,
+        &message,
+        envelope,
+        nostr_client,
+    )?;
+    Ok(())
 }
 
-/// Sends signed psbt to peer
-pub fn send_signed_psbt(
+/// Sends the transcript hash of the round to peer
+pub fn send_confirm(
     identity: &Identity,
     peer_pub_key: &str,
-    psbt: PartiallySignedTransaction,
+    confirm: Confirm,
+    round_id: Option<String>,
+    envelope: &Envelope,
     nostr_client: &mut NostrClient,
 ) -> Result<(), Error> {
-    let event = NostrdizerMessage {
-        event_type: NostrdizerMessageKind::SignedCJ,
-        event: NostrdizerMessages::SignedCJ(SignedTransaction { psbt }),
+    let message = NostrdizerMessage {
+        event_type: NostrdizerMessageKind::Confirm,
+        event: NostrdizerMessages::Confirm(confirm),
+        round_id,
     };
-    let encrypt_message = encrypt_message(&identity.secret_key, peer_pub_key, &event)?;
+
+    send_message(
+        identity,
+        peer_pub_key,
+        CONFIRM,
+        &message,
+        envelope,
+        nostr_client,
+    )?;
+    Ok(())
+}
+
+/// Encrypts and publishes a protocol message to peer
+/// Returns the id of the signed event, the id the peer sees once it is unsealed
+pub fn send_message(
+    identity: &Identity,
+    peer_pub_key: &str,
+    kind: u16,
+    message: &NostrdizerMessage,
+    envelope: &Envelope,
+    nostr_client: &mut NostrClient,
+) -> Result<String, Error> {
+    let (event_id, event) = message_event(identity, peer_pub_key, kind, message, envelope)?;
+    nostr_client.publish_event(&event)?;
+
+    Ok(event_id)
+}
+
+/// Encrypted protocol message to peer ready to publish, with the id of the signed event
+/// ```
+/// use nostrdizer::{
+///     encryption::{Envelope, MakerEncryption},
+///     fees,
+///     test_utils::{fill, identity, maker_config},
+///     types::{NostrdizerMessage, NostrdizerMessageKind, NostrdizerMessages, FILL},
+///     utils::{message_event, unseal_event},
+/// };
+///
+/// let (taker, maker) = (identity(1), identity(2));
+/// // Taker fills the maker's offer
+/// let message = NostrdizerMessage {
+///     event_type: NostrdizerMessageKind::FillOffer,
+///     event: NostrdizerMessages::Fill(fill(100_000)),
+///     round_id: Some("round".to_string()),
+/// };
+/// let envelope = Envelope {
+///     secret_key: taker.secret_key,
+///     pub_key: maker.public_key_str.clone(),
+///     sealed: true,
+///     tagged: true,
+/// };
+/// let (_, event) = message_event(&taker, &maker.public_key_str, FILL, &message, &envelope).unwrap();
+///
+/// // Maker reads the fill and checks it is one it takes
+/// let event = unseal_event(&maker, event).unwrap();
+/// assert_eq!(event.pub_key, taker.public_key_str);
+/// match MakerEncryption::default().decrypt(&maker, &event).unwrap().event {
+///     NostrdizerMessages::Fill(fill) => {
+///         assert!(fees::check_fill_amount(&maker_config(), &fill).is_ok())
+///     }
+///     _ => panic!("expected a fill"),
+/// }
+/// ```
+pub fn message_event(
+    identity: &Identity,
+    peer_pub_key: &str,
+    kind: u16,
+    message: &NostrdizerMessage,
+    envelope: &Envelope,
+) -> Result<(String, Event), Error> {
+    debug_assert_eq!(
+        sequence::event_kind(&message.event),
+        kind,
+        "message sent as the wrong kind"
+    );
+    let encrypted_content = encrypt_message(
+        &envelope.secret_key,
+        &envelope.pub_key,
+        message,
+        envelope.tagged,
+    )?;
 
     let event = EventPrepare {
         pub_key: identity.public_key_str.clone(),
         created_at: get_timestamp(),
-        kind: SIGNED_TRANSACTION,
+        kind,
         tags: vec![vec!["p".to_string(), peer_pub_key.to_string()]],
-        content: encrypt_message,
+        content: encrypted_content,
     }
     .to_event(identity, 0);
 
-    nostr_client.publish_event(&event)?;
-    /*
+    let event_id = event.id.clone();
+    let event = match envelope.sealed {
+        true => seal_event(&event, peer_pub_key)?,
+        false => event,
+    };
 
-    nostr_client.publish_ephemeral_event(
-        identity,
-        130,
-        &encrypt_message,
-        &[vec!["p".to_string(), peer_pub_key.to_string()]],
-        0,
-    )?;
-    */
+    Ok((event_id, event))
+}
+
+/// Seals a signed event for peer: NIP-04 encrypts it to the peer's key in an event signed by a throwaway key,
+/// so relays only see the throwaway key and the recipient tag
+// This is not NIP-59, there is no NIP-44 encryption or separate seal layer as the signed inner event authenticates
+// the sender. Timestamps are not randomized as peers fetch session messages since the session started
+pub fn seal_event(event: &Event, peer_pub_key: &str) -> Result<Event, Error> {
+    let (sk, _) = get_random_secret_key();
+    let throwaway = Identity::from_str(&hex::encode(sk.as_ref()))?;
+
+    let x_pub_key = XOnlyPublicKey::from_str(peer_pub_key)?;
+    let content = encrypt(&sk, &x_pub_key, &serde_json::to_string(event)?)?;
+
+    Ok(EventPrepare {
+        pub_key: throwaway.public_key_str.clone(),
+        created_at: get_timestamp(),
+        kind: SEALED,
+        tags: vec![vec!["p".to_string(), peer_pub_key.to_string()]],
+        content,
+    }
+    .to_event(&throwaway, 0))
+}
+
+/// Gets the signed event a sealed event holds, see [`seal_event`]
+/// Events that are not sealed are returned as is
+pub fn unseal_event(identity: &Identity, event: Event) -> Result<Event, Error> {
+    if event.kind != SEALED {
+        return Ok(event);
+    }
+
+    let x_pub_key = XOnlyPublicKey::from_str(&event.pub_key)?;
+    let inner: Event =
+        serde_json::from_str(&decrypt(&identity.secret_key, &x_pub_key, &event.content)?)?;
+
+    // Inner event has to be signed by the actual sender
+    if inner.verify().is_err() || inner.kind == SEALED {
+        return Err(Error::Unseal);
+    }
+
+    Ok(inner)
+}
+
+/// Estimates the fee rate in sat/vB a psbt will pay once all inputs are signed
+/// Each input is weighed by the type of the utxo it spends, inputs of unknown type as p2wpkh
+/// ```
+/// use nostrdizer::{test_utils::psbt, types::Amount, utils::estimate_fee_rate};
+///
+/// // Fixture outputs have empty scripts so 1 input 2 outputs is 97 vB once signed
+/// let psbt = psbt(&[100_000], &[50_000, 49_000]);
+/// let fee_rate = estimate_fee_rate(&psbt, Amount::from_sat(970));
+///
+/// assert_eq!(fee_rate, 10.0);
+/// ```
+pub fn estimate_fee_rate(psbt: &PartiallySignedTransaction, mining_fee: Amount) -> f32 {
+    let tx = &psbt.unsigned_tx;
+    let satisfaction_weight: usize = tx
+        .input
+        .iter()
+        .zip(&psbt.inputs)
+        .map(|(tx_in, input)| {
+            let script_type = match (&input.witness_utxo, &input.non_witness_utxo) {
+                (Some(tx_out), _) => DescriptorType::of_output(&tx_out.script_pubkey),
+                (None, Some(prev_tx)) => prev_tx
+                    .output
+                    .get(tx_in.previous_output.vout as usize)
+                    .and_then(|tx_out| DescriptorType::of_output(&tx_out.script_pubkey)),
+                (None, None) => None,
+            };
+            // The empty script sig's length is already in the unsigned tx
+            script_type
+                .unwrap_or(DescriptorType::Wpkh)
+                .max_satisfaction_weight()
+                - 4
+        })
+        .sum();
+    // Segwit marker and flag are only counted once there are witnesses
+    let weight = tx.weight() + 2 + satisfaction_weight;
+    let vsize = weight.div_ceil(4);
 
+    mining_fee.to_sat() as f32 / vsize as f32
+}
+
+/// Tag marking the bitcoin network an offer is for
+pub fn network_tag(network: Network) -> Vec<String> {
+    vec!["network".to_string(), network.to_string()]
+}
+
+/// NIP-40 tag asking relays to drop an event after `expires_at`
+pub fn expiration_tag(expires_at: u64) -> Vec<String> {
+    vec!["expiration".to_string(), expires_at.to_string()]
+}
+
+/// Gets the time an event expires, `None` if it does not
+pub fn event_expiration(event: &Event) -> Option<u64> {
+    event
+        .tags
+        .iter()
+        .find(|tag| tag.first().map(|t| t.as_str()) == Some("expiration"))
+        .and_then(|tag| tag.get(1))
+        .and_then(|expires_at| expires_at.parse().ok())
+}
+
+/// Gets the network an event is tagged for
+/// Events without a network tag are from before they were added so are regtest
+/// ```
+/// use nostrdizer::{types::Network, utils::{event_network, network_tag}};
+/// # use nostr_rust::{events::EventPrepare, Identity};
+/// # use std::str::FromStr;
+/// # let identity = Identity::from_str("4d1c1e5c1e5c1e5c1e5c1e5c1e5c1e5c1e5c1e5c1e5c1e5c1e5c1e5c1e5c1e5c").unwrap();
+///
+/// let event = EventPrepare {
+///     pub_key: identity.public_key_str.clone(),
+///     created_at: 0,
+///     kind: 123,
+///     tags: vec![network_tag(Network::Signet)],
+///     content: "".to_string(),
+/// }
+/// .to_event(&identity, 0);
+///
+/// assert_eq!(event_network(&event), Some(Network::Signet));
+/// ```
+pub fn event_network(event: &Event) -> Option<Network> {
+    match event
+        .tags
+        .iter()
+        .find(|tag| tag.first().map(|t| t.as_str()) == Some("network"))
+    {
+        Some(tag) => tag.get(1).and_then(|n| Network::from_str(n).ok()),
+        None => Some(Network::Regtest),
+    }
+}
+
+/// Checks an address a counterparty sent is encoded for network and pays a standard script
+/// Testnet and signet addresses are the same, as are legacy testnet and regtest addresses
+/// ```
+/// use bdk::bitcoin::Address;
+/// use nostrdizer::{types::Network, utils::check_address};
+/// use std::str::FromStr;
+///
+/// let address = Address::from_str("tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx").unwrap();
+/// assert!(check_address(&address, Network::Signet).is_ok());
+/// assert!(check_address(&address, Network::Regtest).is_err());
+/// assert!(check_address(&address, Network::Bitcoin).is_err());
+/// ```
+pub fn check_address(address: &Address, network: Network) -> Result<(), Error> {
+    let on_network = Address {
+        payload: address.payload.clone(),
+        network,
+    };
+    if on_network.to_string() != address.to_string() {
+        return Err(Error::AddressNetwork(address.to_string(), network));
+    }
+    // Unknown witness versions and programs of the wrong length are burned or unspendable
+    if address.address_type().is_none() {
+        return Err(Error::NonstandardAddress(address.to_string()));
+    }
     Ok(())
 }
 
+/// Checks a private key parsed from WIF is for network
+// Testnet, signet and regtest keys share a WIF prefix
+pub fn check_key_network(priv_key: &PrivateKey, network: Network) -> Result<(), Error> {
+    match (priv_key.network == Network::Bitcoin) == (network == Network::Bitcoin) {
+        true => Ok(()),
+        false => Err(Error::WrongNetwork(network)),
+    }
+}
+
+/// Encrypts message to pk, tagged for peers that read tagged messages
 pub fn encrypt_message(
     sk: &SecretKey,
     pk: &str,
     message: &NostrdizerMessage,
+    tagged: bool,
 ) -> Result<String, Error> {
     let x_pub_key = XOnlyPublicKey::from_str(pk)?;
-    Ok(encrypt(sk, &x_pub_key, &serde_json::to_string(&message)?)?)
+    Ok(encrypt(sk, &x_pub_key, &message.to_wire(tagged)?)?)
 }
 
 pub fn decrypt_message(
@@ -100,3 +359,147 @@ pub fn decrypt_message(
     let x = XOnlyPublicKey::from_str(pk)?;
     Ok(serde_json::from_str(&decrypt(sk, &x, message)?)?)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        test_utils::{abs_offer, fill, io_auth, psbt},
+        types::{Abort, Transaction},
+    };
+    use bdk::bitcoin::TxOut;
+
+    #[test]
+    fn test_fee_rate_by_input_type() {
+        let fee_rate = |address: &str| {
+            let mut psbt = psbt(&[100_000], &[50_000, 49_000]);
+            psbt.inputs[0].witness_utxo = Some(TxOut {
+                value: 100_000,
+                script_pubkey: Address::from_str(address).unwrap().script_pubkey(),
+            });
+            estimate_fee_rate(&psbt, Amount::from_sat(970))
+        };
+
+        assert_eq!(fee_rate("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4"), 10.0);
+        // A key path spend is lighter than p2wpkh, the nested script sig heavier
+        assert!(fee_rate("bc1p5d7rjq7g6rdk2yhzks9smlaqtedr4dekq08ge8ztwac72sfr9rusxg3297") > 11.0);
+        assert!(fee_rate("3J98t1WpEZ73CNmQviecrnyiWrnqRhWNLy") < 8.1);
+    }
+
+    #[test]
+    fn test_seal_round_trip() {
+        let identity = |secret: &str| Identity::from_str(&secret.repeat(32)).unwrap();
+        let (sender, recipient, outsider) = (identity("01"), identity("02"), identity("03"));
+        let event = EventPrepare {
+            pub_key: sender.public_key_str.clone(),
+            created_at: get_timestamp(),
+            kind: CONFIRM,
+            tags: vec![],
+            content: "content".to_string(),
+        }
+        .to_event(&sender, 0);
+
+        let sealed = seal_event(&event, &recipient.public_key_str).unwrap();
+        assert_eq!(sealed.kind, SEALED);
+        assert_ne!(sealed.pub_key, sender.public_key_str);
+        assert_eq!(
+            sealed.tags,
+            vec![vec!["p".to_string(), recipient.public_key_str.clone()]]
+        );
+
+        let unsealed = unseal_event(&recipient, sealed.clone()).unwrap();
+        assert_eq!(unsealed.id, event.id);
+        assert_eq!(unsealed.pub_key, sender.public_key_str);
+        // Events that are not sealed pass through
+        assert_eq!(
+            unseal_event(&recipient, event.clone()).unwrap().id,
+            event.id
+        );
+
+        assert!(unseal_event(&outsider, sealed).is_err());
+    }
+
+    #[test]
+    fn test_messages_read_in_both_formats() {
+        let messages = vec![
+            (
+                NostrdizerMessageKind::Offer,
+                NostrdizerMessages::Offer(abs_offer(500)),
+            ),
+            (
+                NostrdizerMessageKind::FillOffer,
+                NostrdizerMessages::Fill(fill(100_000)),
+            ),
+            (
+                NostrdizerMessageKind::MakerInput,
+                NostrdizerMessages::MakerInputs(io_auth(0)),
+            ),
+            (
+                NostrdizerMessageKind::UnsignedCJ,
+                NostrdizerMessages::UnsignedCJ(Transaction {
+                    psbt: psbt(&[100_000], &[50_000]),
+                    participants: Some(3),
+                }),
+            ),
+            (
+                NostrdizerMessageKind::SignedCJ,
+                NostrdizerMessages::SignedCJ(SignedTransaction {
+                    psbt: psbt(&[100_000], &[50_000]),
+                }),
+            ),
+            (
+                NostrdizerMessageKind::Abort,
+                NostrdizerMessages::Abort(Abort::default()),
+            ),
+        ];
+        for (event_type, event) in messages {
+            let message = NostrdizerMessage {
+                event_type,
+                event,
+                round_id: Some("round".to_string()),
+            };
+            for tagged in [true, false] {
+                let wire = message.to_wire(tagged).unwrap();
+                let read: NostrdizerMessage = serde_json::from_str(&wire).unwrap();
+                assert_eq!(read.event_type, message.event_type);
+                assert_eq!(
+                    serde_json::to_value(&read.event).unwrap(),
+                    serde_json::to_value(&message.event).unwrap()
+                );
+                assert_eq!(read.round_id.as_deref(), Some("round"));
+            }
+        }
+    }
+
+    #[test]
+    fn test_reject_reasons_round_trip() {
+        let reasons = vec![
+            RejectReason::FeeRateTooLow {
+                fee_rate: 1.5,
+                min_fee_rate: 2.0,
+            },
+            RejectReason::Busy,
+            RejectReason::UnconfirmedInputs {
+                outpoints: vec![bdk::bitcoin::OutPoint::null()],
+            },
+            RejectReason::MissingCJOutput {
+                amount: Amount::from_sat(100_000),
+            },
+        ];
+        for reason in reasons {
+            let message = NostrdizerMessage {
+                event_type: NostrdizerMessageKind::Reject,
+                event: NostrdizerMessages::Reject(Reject {
+                    reason: reason.clone(),
+                }),
+                round_id: None,
+            };
+            let sent: NostrdizerMessage =
+                serde_json::from_str(&serde_json::to_string(&message).unwrap()).unwrap();
+            match sent.event {
+                NostrdizerMessages::Reject(reject) => assert_eq!(reject.reason, reason),
+                event => panic!("Expected a reject, got {event:?}"),
+            }
+        }
+    }
+}
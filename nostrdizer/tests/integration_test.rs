@@ -1 +1,52 @@
+// Needs a signet bitcoin core node and a nostr relay, run with
+// SIGNET_RPC_URL=... SIGNET_RPC_USERNAME=... SIGNET_RPC_PASSWORD=... SIGNET_WALLET=... \
+// cargo test --features bitcoincore -- --ignored
+#[cfg(feature = "bitcoincore")]
+mod signet {
+    use nostrdizer::{
+        errors::Error,
+        taker::Taker,
+        types::{BitcoinCoreCredentials, BlockchainConfig, Network},
+    };
 
+    use std::env;
+
+    fn credentials(network: Network) -> BlockchainConfig {
+        BlockchainConfig::CoreRPC(BitcoinCoreCredentials {
+            rpc_url: env::var("SIGNET_RPC_URL").unwrap(),
+            wallet_name: env::var("SIGNET_WALLET").unwrap(),
+            rpc_username: env::var("SIGNET_RPC_USERNAME").unwrap(),
+            rpc_password: env::var("SIGNET_RPC_PASSWORD").unwrap(),
+            network,
+            create_wallet: false,
+            wallet_passphrase: env::var("SIGNET_WALLET_PASSPHRASE").ok(),
+        })
+    }
+
+    fn relays() -> Vec<String> {
+        vec![env::var("SIGNET_NOSTR_RELAY").unwrap_or_else(|_| "ws://localhost:7000".to_string())]
+    }
+
+    #[test]
+    #[ignore]
+    fn test_signet_taker() {
+        let relays = relays();
+        let relays = relays.iter().map(|r| r.as_str()).collect();
+        let mut taker = Taker::new(None, relays, credentials(Network::Signet)).unwrap();
+
+        assert_eq!(taker.network, Network::Signet);
+        // Only signet offers make it into the book
+        taker.get_offers().unwrap();
+        taker.generate_podle(None).unwrap();
+    }
+
+    #[test]
+    #[ignore]
+    fn test_wrong_network_rejected() {
+        let relays = relays();
+        let relays = relays.iter().map(|r| r.as_str()).collect();
+        let result = Taker::new(None, relays, credentials(Network::Regtest));
+
+        assert!(matches!(result, Err(Error::WrongNetwork(Network::Regtest))));
+    }
+}
@@ -0,0 +1,43 @@
+use super::context::Context;
+use super::paths::{audit_path, payout_history_path, round_history_path};
+
+use nostrdizer::{audit::AUDIT_TRANSACTIONS, bitcoincore::utils};
+
+use anyhow::Result;
+use clap::Args;
+use serde::{Deserialize, Serialize};
+
+use std::env;
+
+#[derive(Args, Debug, Serialize, Deserialize)]
+pub struct AuditWalletArgs {
+    /// File earned fees and sweeps are recorded in
+    #[arg(long)]
+    pub payout_history: Option<String>,
+    #[arg(long)]
+    pub round_history: Option<String>,
+    /// File spends already alerted on are kept in
+    #[arg(long)]
+    pub audit_file: Option<String>,
+    /// Shell command run on each unexpected spend, with NOSTRDIZER_ALERT_TXID and NOSTRDIZER_ALERT_AMOUNT set
+    #[arg(long)]
+    pub alert_command: Option<String>,
+}
+
+/// Audits the bitcoin core wallet, alerting with the command from the flag or env
+pub fn run(args: &AuditWalletArgs, ctx: &Context) -> Result<()> {
+    let creds = ctx
+        .core_credentials("The audit needs a bitcoin core wallet")?
+        .clone();
+    let alert_command = args
+        .alert_command
+        .clone()
+        .or_else(|| env::var("MAKER_ALERT_COMMAND").ok());
+    super::maker::audit_wallet(
+        || Ok(utils::wallet_spends(&creds, AUDIT_TRANSACTIONS)?),
+        &audit_path(&args.audit_file),
+        &payout_history_path(&args.payout_history),
+        &round_history_path(&args.round_history, true),
+        alert_command.as_deref(),
+    )
+}
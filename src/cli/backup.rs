@@ -0,0 +1,84 @@
+use nostrdizer::{
+    backup::{connect, fetch_backup, publish_backup, Backup},
+    keystore::Keystore,
+    reputation::Reputation,
+    transcript::RoundHistory,
+};
+
+use anyhow::{bail, Result};
+
+use std::path::Path;
+
+/// Nostr key backups are encrypted to, a maker falls back to the key of its keystore
+pub fn backup_key(priv_key: Option<String>, keystore_path: &Path, maker: bool) -> Result<String> {
+    match priv_key {
+        Some(priv_key) => Ok(priv_key),
+        None if maker => match Keystore::load(keystore_path)?.current {
+            Some(priv_key) => Ok(priv_key),
+            None => bail!(
+                "No key in {}, give one with --priv-key",
+                keystore_path.display()
+            ),
+        },
+        None => bail!("Backups are encrypted to the nostr key, give it with --priv-key"),
+    }
+}
+
+/// Publishes the reputation, podle commitments and round history encrypted to the identity
+/// Makers keep no reputation so have none to back up
+pub fn backup(
+    priv_key: &str,
+    relay_urls: Vec<&str>,
+    reputation_path: Option<&Path>,
+    keystore_path: &Path,
+    round_history_path: &Path,
+) -> Result<()> {
+    let (identity, mut nostr_client) = connect(priv_key, relay_urls)?;
+    let reputation = match reputation_path {
+        Some(path) => Reputation::load(path)?,
+        None => Reputation::default(),
+    };
+    let keystore = Keystore::load(keystore_path)?;
+    let round_history = RoundHistory::load(round_history_path)?;
+
+    let backup = Backup::new(&reputation, &keystore, &round_history);
+    let event_id = publish_backup(&mut nostr_client, &identity, &backup)?;
+    println!(
+        "Backed up {} makers, {} commitments and {} rounds in event {event_id}",
+        backup.reputation.makers.len(),
+        backup.commitments.len(),
+        backup.round_history.rounds.len()
+    );
+    Ok(())
+}
+
+/// Merges the latest backup of the identity into the local stores
+pub fn restore(
+    priv_key: &str,
+    relay_urls: Vec<&str>,
+    reputation_path: Option<&Path>,
+    keystore_path: &Path,
+    round_history_path: &Path,
+) -> Result<()> {
+    let (identity, mut nostr_client) = connect(priv_key, relay_urls)?;
+    let backup = match fetch_backup(&mut nostr_client, &identity)? {
+        Some(backup) => backup,
+        None => bail!("No backup of {} on the relays", identity.public_key_str),
+    };
+
+    let mut reputation = match reputation_path {
+        Some(path) => Reputation::load(path)?,
+        None => Reputation::default(),
+    };
+    let mut keystore = Keystore::load(keystore_path)?;
+    let mut round_history = RoundHistory::load(round_history_path)?;
+
+    let added = backup.merge(&mut reputation, &mut keystore, &mut round_history);
+    if let Some(path) = reputation_path {
+        reputation.save(path)?;
+    }
+    keystore.save(keystore_path)?;
+    round_history.save(round_history_path)?;
+    println!("Restored {added} entries from the backup");
+    Ok(())
+}
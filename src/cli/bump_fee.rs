@@ -0,0 +1,41 @@
+use super::context::Context;
+
+use nostrdizer::{bitcoincore::utils, types::Txid};
+
+use anyhow::Result;
+use clap::Args;
+use serde::{Deserialize, Serialize};
+
+use std::path::PathBuf;
+use std::str::FromStr;
+
+#[derive(Args, Debug, Serialize, Deserialize)]
+pub struct BumpFeeArgs {
+    pub txid: String,
+    /// Fee rate in sat/vB the transaction and its child pay together
+    #[arg(long)]
+    pub fee_rate: f32,
+    /// Replace the transaction (RBF) with one paying the fee rate from the wallet's change instead
+    #[arg(long)]
+    pub replace: bool,
+    /// File a replacement that makers have to sign again is written to as a base64 PSBT
+    #[arg(long)]
+    pub psbt: Option<String>,
+}
+
+/// Bumps the fee with a child, or replaces the transaction when asked to
+pub fn run(args: &BumpFeeArgs, ctx: &Context) -> Result<()> {
+    let creds = ctx.core_credentials("Bumping fees needs a bitcoin core wallet")?;
+    let txid = Txid::from_str(&args.txid)?;
+    if args.replace {
+        let psbt_path = PathBuf::from(args.psbt.as_deref().unwrap_or("replacement.psbt"));
+        super::taker::replace_cj(creds, &txid, args.fee_rate, &psbt_path)?;
+    } else {
+        let child = utils::bump_fee(creds, &txid, args.fee_rate)?;
+        println!(
+            "Submitted {txid} with child {child} paying {} sat/vB for both",
+            args.fee_rate
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,66 @@
+use nostrdizer::config::{self, Config, SealedConfig, TEMPLATE};
+
+use anyhow::{bail, Result};
+use clap::Subcommand;
+use serde::{Deserialize, Serialize};
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+#[derive(Subcommand, Debug, Serialize, Deserialize)]
+pub enum ConfigCommand {
+    /// Write a config with every setting commented out
+    Init {
+        /// Replace a config that is already there
+        #[arg(long)]
+        force: bool,
+    },
+    /// Encrypt the config with a passphrase, it is asked for on every run after
+    Encrypt,
+    /// Decrypt the config to edit it
+    Decrypt,
+}
+
+/// Passphrase of an encrypted config, from `CONFIG_PASSPHRASE` or asked for
+pub fn passphrase() -> Option<String> {
+    match env::var("CONFIG_PASSPHRASE") {
+        Ok(passphrase) => Some(passphrase),
+        Err(_) => rpassword::prompt_password("Config passphrase: ").ok(),
+    }
+}
+
+pub fn run(command: &ConfigCommand, path: &Path) -> Result<()> {
+    match command {
+        ConfigCommand::Init { force } => {
+            if path.exists() && !force {
+                bail!("{} already exists, replace it with --force", path.display());
+            }
+            config::write(path, TEMPLATE)?;
+            println!("Wrote {}", path.display());
+        }
+        ConfigCommand::Encrypt => {
+            let toml = fs::read_to_string(path)?;
+            if SealedConfig::from_toml(&toml)?.is_some() {
+                bail!("{} is already encrypted", path.display());
+            }
+            let config = Config::from_toml(&toml)?;
+            let passphrase = rpassword::prompt_password("New config passphrase: ")?;
+            if passphrase != rpassword::prompt_password("Repeat passphrase: ")? {
+                bail!("Passphrases do not match");
+            }
+            // Comments of the plain config are not kept
+            config::write(path, &config.seal(&passphrase)?.to_toml()?)?;
+            println!("Encrypted {}", path.display());
+        }
+        ConfigCommand::Decrypt => {
+            let config = match Config::load(path, passphrase)? {
+                Some(config) => config,
+                None => bail!("No config at {}", path.display()),
+            };
+            config::write(path, &config.to_toml()?)?;
+            println!("Decrypted {}", path.display());
+        }
+    }
+    Ok(())
+}
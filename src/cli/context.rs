@@ -0,0 +1,70 @@
+use nostrdizer::{
+    bitcoincore::utils,
+    builder::TakerBuilder,
+    config::{MakerSection, TakerSection},
+    fiat::FiatConfig,
+    publication::PublishQuorum,
+    relay_auth::RelayAuthConfig,
+    retention::RetentionPolicy,
+    taker::Taker,
+    types::{BitcoinCoreCredentials, BlockchainConfig, Network, TakerConfig},
+};
+
+use anyhow::{bail, Result};
+
+use std::collections::HashMap;
+
+/// Settings of the global flags every command runs with
+pub struct Context {
+    pub priv_key: Option<String>,
+    pub relay_urls: Vec<String>,
+    /// Relays a maker publishes offers on
+    pub offer_relay_urls: Vec<String>,
+    pub blockchain_config: BlockchainConfig,
+    pub network: Network,
+    pub publish_quorum: PublishQuorum,
+    pub relay_auth: RelayAuthConfig,
+    pub fiat: Option<FiatConfig>,
+    /// Trust weight of offers seen on each relay
+    pub relay_trust: HashMap<String, f64>,
+    pub retention: RetentionPolicy,
+    /// Maker settings of the config, the defaults of their flags and env vars
+    pub maker: MakerSection,
+    /// Taker settings of the config, the defaults of their flags and env vars
+    pub taker: TakerSection,
+}
+
+impl Context {
+    pub fn relays(&self) -> Vec<&str> {
+        self.relay_urls.iter().map(String::as_str).collect()
+    }
+
+    /// Taker with the default config, authenticating to the relays that require it when connecting
+    pub fn new_taker(&self) -> Result<Taker> {
+        let mut builder = TakerBuilder::new()
+            .relays(&self.relay_urls)
+            .blockchain(self.blockchain_config.clone())
+            .config(TakerConfig {
+                relay_auth: self.relay_auth.clone(),
+                ..TakerConfig::default()
+            });
+        if let Some(priv_key) = &self.priv_key {
+            builder = builder.priv_key(priv_key);
+        }
+        Ok(builder.build()?)
+    }
+
+    /// Credentials of the bitcoin core wallet, errors with `missing` when there is none
+    pub fn core_credentials(&self, missing: &str) -> Result<&BitcoinCoreCredentials> {
+        match &self.blockchain_config {
+            BlockchainConfig::CoreRPC(creds) => Ok(creds),
+            BlockchainConfig::RPC(_) => bail!("{missing}"),
+        }
+    }
+
+    /// Nostr private key derived from the wallet
+    pub fn wallet_nostr_key(&self, index: u32) -> Result<String> {
+        let creds = self.core_credentials("The wallet identity needs a bitcoin core wallet")?;
+        Ok(utils::wallet_nostr_key(creds, index)?)
+    }
+}
@@ -0,0 +1,38 @@
+use anyhow::Result;
+
+use std::env;
+use std::str::FromStr;
+
+/// Setting from the flag, then the env var, none when neither is set
+pub fn or_env<T>(value: &Option<T>, var: &str) -> Result<Option<T>>
+where
+    T: FromStr + Clone,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    Ok(match value {
+        Some(value) => Some(value.clone()),
+        None => match env::var(var) {
+            Ok(value) => Some(value.parse()?),
+            Err(_) => None,
+        },
+    })
+}
+
+/// Setting from the flag, then the env var, then the default
+pub fn or_env_default<T>(value: &Option<T>, var: &str, default: T) -> Result<T>
+where
+    T: FromStr + Clone,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    Ok(or_env(value, var)?.unwrap_or(default))
+}
+
+/// Index of the identity derived from the wallet
+pub fn identity_index(identity_index: &Option<u32>) -> Result<u32> {
+    or_env_default(identity_index, "MAKER_IDENTITY_INDEX", 0)
+}
+
+/// Whether more than one maker of a suspected sybil cluster is used, from the flag then `TAKER_ALLOW_CLUSTERS`
+pub fn allow_clusters(allow_clusters: &Option<bool>) -> Result<bool> {
+    or_env_default(allow_clusters, "TAKER_ALLOW_CLUSTERS", false)
+}
@@ -0,0 +1,104 @@
+use nostrdizer::errors::{Error as NostrdizerError, ErrorKind};
+
+use anyhow::{bail, Result};
+use serde::Serialize;
+
+use std::str::FromStr;
+
+/// How the CLI reports a failure on stderr
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => bail!("Unknown output {}, expected text or json", s),
+        }
+    }
+}
+
+/// Exit code of a failure category, these do not change between releases
+pub fn exit_code(kind: ErrorKind) -> i32 {
+    match kind {
+        ErrorKind::Other => 1,
+        ErrorKind::NoOffers => 2,
+        ErrorKind::InsufficientFunds => 3,
+        ErrorKind::VerificationFailed => 4,
+        ErrorKind::RelayFailure => 5,
+        ErrorKind::Timeout => 6,
+    }
+}
+
+/// Category of the first nostrdizer error in the chain of err
+pub fn kind(err: &anyhow::Error) -> ErrorKind {
+    err.chain()
+        .find_map(|cause| cause.downcast_ref::<NostrdizerError>())
+        .map_or(ErrorKind::Other, NostrdizerError::kind)
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorReport {
+    error: ErrorKind,
+    code: i32,
+    message: String,
+    /// Causes of the error, outermost first
+    causes: Vec<String>,
+}
+
+impl ErrorReport {
+    fn new(err: &anyhow::Error) -> Self {
+        let kind = kind(err);
+        Self {
+            error: kind,
+            code: exit_code(kind),
+            message: err.to_string(),
+            causes: err.chain().skip(1).map(|cause| cause.to_string()).collect(),
+        }
+    }
+}
+
+/// Prints err to stderr in output format, returns the code to exit with
+pub fn report(err: &anyhow::Error, output: OutputFormat) -> i32 {
+    let report = ErrorReport::new(err);
+    match output {
+        OutputFormat::Text => eprintln!("Error: {:?}", err),
+        OutputFormat::Json => match serde_json::to_string(&report) {
+            Ok(json) => eprintln!("{json}"),
+            Err(_) => eprintln!("Error: {:?}", err),
+        },
+    }
+    report.code
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn test_kind_through_context() {
+        let err = Err::<(), _>(NostrdizerError::NotEnoughMakers)
+            .context("Not enough makers left after dropping makers that reused addresses")
+            .unwrap_err();
+        assert_eq!(kind(&err), ErrorKind::NoOffers);
+        assert_eq!(exit_code(kind(&err)), 2);
+
+        let report = ErrorReport::new(&err);
+        assert_eq!(report.causes, vec!["Not enough makers".to_string()]);
+        assert_eq!(serde_json::to_value(&report).unwrap()["error"], "no_offers");
+    }
+
+    #[test]
+    fn test_untyped_error_is_other() {
+        let err = anyhow::anyhow!("A private key can not be given with the wallet identity");
+        assert_eq!(exit_code(kind(&err)), 1);
+    }
+}
@@ -0,0 +1,36 @@
+#[cfg(feature = "fiat")]
+use nostrdizer::fiat::HttpPriceSource;
+use nostrdizer::fiat::{FiatConfig, Price, PriceCache, PriceSource};
+
+use log::warn;
+
+/// Price of BTC in the configured currency, fetched once the cached one is older than the max age
+/// Without the `fiat` feature only a cached price is shown, none without a config or a price
+pub fn price(config: &Option<FiatConfig>) -> Option<Price> {
+    let config = config.as_ref()?;
+    let mut cache = match PriceCache::load(&config.cache_path) {
+        Ok(cache) => cache,
+        Err(err) => {
+            warn!("Could not load the price cache, fetching again: {err}");
+            PriceCache::default()
+        }
+    };
+
+    #[cfg(feature = "fiat")]
+    let http = HttpPriceSource::default();
+    #[cfg(feature = "fiat")]
+    let source: Option<&dyn PriceSource> = Some(&http);
+    #[cfg(not(feature = "fiat"))]
+    let source: Option<&dyn PriceSource> = None;
+
+    let now = chrono::Utc::now().timestamp() as u64;
+    let price = cache.price(source, &config.currency, config.max_age, now);
+    if let Some(price) = &price {
+        if price.fetched_at == now {
+            if let Err(err) = cache.save(&config.cache_path) {
+                warn!("Could not save the price cache: {err}");
+            }
+        }
+    }
+    price
+}
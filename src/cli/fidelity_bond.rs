@@ -0,0 +1,100 @@
+use nostrdizer::{
+    bitcoincore::utils,
+    display,
+    fidelity_bond::FidelityBond,
+    keystore::Keystore,
+    types::{Amount, BitcoinCoreCredentials},
+};
+
+use anyhow::{Context, Result};
+use chrono::{NaiveDate, Utc};
+use clap::Subcommand;
+use serde::{Deserialize, Serialize};
+
+use std::path::Path;
+
+#[derive(Subcommand, Debug, Serialize, Deserialize)]
+pub enum BondCommand {
+    /// Lock coins of the wallet in a new bond, the maker advertises it from its next start
+    Create {
+        /// Sats locked in the bond
+        #[arg(long)]
+        amount: u64,
+        /// Month the coins are locked until, from its first day, as YYYY-MM
+        #[arg(long)]
+        until: String,
+    },
+    /// List the bonds in the keystore
+    List {
+        /// Show the descriptors, with private keys, to spend expired bonds from another wallet
+        #[arg(long)]
+        descriptors: bool,
+    },
+}
+
+/// Unix time of the first day of a month given as YYYY-MM
+fn month_start(month: &str) -> Result<u32> {
+    let date = NaiveDate::parse_from_str(&format!("{month}-01"), "%Y-%m-%d")
+        .with_context(|| format!("{month} is not a month as YYYY-MM"))?;
+    let midnight = date.and_hms_opt(0, 0, 0).context("No midnight")?;
+    Ok(u32::try_from(midnight.timestamp())?)
+}
+
+pub fn run(
+    command: &BondCommand,
+    keystore_path: &Path,
+    creds: &BitcoinCoreCredentials,
+) -> Result<()> {
+    let mut keystore = Keystore::load(keystore_path)?;
+    let now = Utc::now().timestamp() as u64;
+    match command {
+        BondCommand::Create { amount, until } => {
+            let bond = FidelityBond::generate(month_start(until)?, Amount::from_sat(*amount), now)?;
+            // The key is kept before any coins are sent to it
+            keystore.fidelity_bonds.push(bond.clone());
+            keystore.save(keystore_path)?;
+
+            let outpoint = utils::fund_fidelity_bond(creds, &bond)?;
+            if let Some(kept) = keystore.fidelity_bonds.last_mut() {
+                kept.outpoint = Some(outpoint);
+            }
+            keystore.save(keystore_path)?;
+            println!(
+                "Locked {} in {} until {}",
+                display::sats(bond.amount),
+                outpoint,
+                display::timestamp(bond.locktime as u64)
+            );
+            println!("Takers count the bond once it confirms");
+        }
+        BondCommand::List { descriptors } => {
+            if keystore.fidelity_bonds.is_empty() {
+                println!("No fidelity bonds");
+            }
+            let advertised = keystore.fidelity_bond(now).cloned();
+            for bond in &keystore.fidelity_bonds {
+                let state = match (bond.outpoint, bond.is_locked(now)) {
+                    (None, _) => "coins not sent",
+                    (Some(_), false) => "expired",
+                    (Some(_), true) if Some(bond) == advertised.as_ref() => "advertised",
+                    (Some(_), true) => "locked",
+                };
+                let outpoint = match bond.outpoint {
+                    Some(outpoint) => outpoint.to_string(),
+                    None => "-".to_string(),
+                };
+                println!(
+                    "{} {} until {}, {}",
+                    outpoint,
+                    display::sats(bond.amount),
+                    display::timestamp(bond.locktime as u64),
+                    state
+                );
+                if *descriptors {
+                    println!("  {}", bond.descriptor(creds.network)?);
+                }
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,76 @@
+use super::context::Context;
+use super::paths::keystore_path;
+
+use nostrdizer::{
+    inspect::{decode, fetch_event, parse_event},
+    keystore::Keystore,
+};
+
+use anyhow::{bail, Result};
+use clap::Args;
+use serde::{Deserialize, Serialize};
+
+use std::io::{self, Read};
+
+#[derive(Args, Debug, Serialize, Deserialize)]
+pub struct DecodeEventArgs {
+    /// Raw event json, read from stdin when neither it nor an id is given
+    pub event: Option<String>,
+    /// Id of the event to fetch from the relays instead
+    #[arg(long)]
+    pub id: Option<String>,
+    /// Relay to fetch the event from, defaults to the nostr relays
+    #[arg(long)]
+    pub relay: Option<String>,
+    /// Decrypt with the maker keystore keys when no key is given
+    #[arg(long)]
+    pub maker: bool,
+    #[arg(long)]
+    pub keystore: Option<String>,
+}
+
+/// Decodes the event with the key given, or the maker keystore's keys
+pub fn run(args: &DecodeEventArgs, ctx: &Context) -> Result<()> {
+    let relay_urls = match &args.relay {
+        Some(relay) => vec![relay.as_str()],
+        None => ctx.relays(),
+    };
+    // Messages to a maker may be encrypted to its encryption key rather than the nostr key
+    let keystore = match args.maker {
+        true => Keystore::load(&keystore_path(&args.keystore, true))?,
+        false => Keystore::default(),
+    };
+    let priv_key = ctx.priv_key.clone().or(keystore.current);
+    decode_event(
+        args.event.clone(),
+        args.id.clone(),
+        relay_urls,
+        priv_key.as_deref(),
+        keystore.encryption_key.as_deref(),
+    )
+}
+
+/// Prints a nostrdizer event with its content parsed, and decrypted when the key is a party to it
+/// The event is fetched by id from the relays, or read as raw json from `raw` or stdin
+pub fn decode_event(
+    raw: Option<String>,
+    id: Option<String>,
+    relay_urls: Vec<&str>,
+    priv_key: Option<&str>,
+    encryption_key: Option<&str>,
+) -> Result<()> {
+    let event = match (raw, id) {
+        (Some(_), Some(_)) => bail!("Give either the raw event or its id, not both"),
+        (Some(raw), None) => parse_event(&raw)?,
+        (None, Some(id)) => fetch_event(relay_urls, &id)?,
+        (None, None) => {
+            let mut raw = String::new();
+            io::stdin().read_to_string(&mut raw)?;
+            parse_event(&raw)?
+        }
+    };
+
+    let decoded = decode(event, priv_key, encryption_key)?;
+    println!("{}", serde_json::to_string_pretty(&decoded)?);
+    Ok(())
+}
@@ -0,0 +1,36 @@
+use super::context::Context;
+use super::env::or_env_default;
+
+use nostrdizer::types::Amount;
+
+use anyhow::Result;
+use clap::Args;
+use serde::{Deserialize, Serialize};
+
+#[derive(Args, Debug, Serialize, Deserialize)]
+pub struct JoinCoopArgs {
+    /// Nostr key of the taker leading the round
+    pub lead: String,
+    #[arg(short, long)]
+    pub send_amount: u64,
+    /// Most sats paid of the round's fees
+    #[arg(long)]
+    pub max_fee: Option<u64>,
+    /// Makers picked for the lead to fill on top of its own
+    #[arg(long)]
+    pub number_of_makers: Option<usize>,
+}
+
+/// Joins the round of the lead with the max fee and makers from the flags, env and defaults
+pub fn run(args: &JoinCoopArgs, ctx: &Context) -> Result<()> {
+    let mut taker = ctx.new_taker()?;
+    let max_fee = or_env_default(&args.max_fee, "TAKER_COOP_MAX_FEE", 10_000)?;
+    let number_of_makers = or_env_default(&args.number_of_makers, "TAKER_COOP_MAKERS", 2)?;
+    super::taker::join_coop(
+        &mut taker,
+        &args.lead,
+        Amount::from_sat(args.send_amount),
+        Amount::from_sat(max_fee),
+        number_of_makers,
+    )
+}
@@ -0,0 +1,43 @@
+use nostrdizer::{labels::LabelStore, transcript::RoundHistory};
+
+use anyhow::Result;
+
+use std::fs;
+use std::path::Path;
+
+/// Writes the label store in BIP-329 format to file, or prints it
+/// CJs of the round history that were never labelled are labelled first
+pub fn export_labels(
+    labels_path: &Path,
+    round_history_path: &Path,
+    file: Option<&Path>,
+) -> Result<()> {
+    let mut labels = LabelStore::load(labels_path)?;
+    let added = labels.label_history(&RoundHistory::load(round_history_path)?);
+    if added > 0 {
+        labels.save(labels_path)?;
+    }
+
+    let export = labels.export()?;
+    match file {
+        Some(file) => {
+            fs::write(file, export)?;
+            println!(
+                "Exported {} labels to {}",
+                labels.labels.len(),
+                file.display()
+            );
+        }
+        None => print!("{export}"),
+    }
+    Ok(())
+}
+
+/// Adds BIP-329 labels exported by another wallet to the label store
+pub fn import_labels(labels_path: &Path, file: &Path) -> Result<()> {
+    let mut labels = LabelStore::load(labels_path)?;
+    let count = labels.import(&fs::read_to_string(file)?)?;
+    labels.save(labels_path)?;
+    println!("Imported {count} labels from {}", file.display());
+    Ok(())
+}
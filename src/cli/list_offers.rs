@@ -0,0 +1,42 @@
+use super::context::Context;
+use super::env::allow_clusters;
+use super::paths::{offer_book_path, reputation_path};
+
+use anyhow::Result;
+use clap::Args;
+use serde::{Deserialize, Serialize};
+
+#[derive(Args, Debug, Serialize, Deserialize)]
+pub struct ListOffersArgs {
+    /// File response times and outcomes of makers are kept in
+    #[arg(long)]
+    pub reputation: Option<String>,
+    /// Show every maker of a suspected sybil cluster rather than one
+    #[arg(long)]
+    pub allow_clusters: Option<bool>,
+    /// File the offer book is cached in, so only offers that changed since are fetched
+    #[arg(long)]
+    pub offer_book: Option<String>,
+    /// Show totals of the stats takers shared in the last days
+    #[arg(long)]
+    pub network_stats: Option<u64>,
+}
+
+/// Lists offers from the cached book when there is one, then the network stats when asked for
+pub fn run(args: &ListOffersArgs, ctx: &Context) -> Result<()> {
+    let mut taker = ctx.new_taker()?;
+    taker.order_book.trust.weights = ctx.relay_trust.clone();
+    taker.order_book.one_per_cluster = !allow_clusters(&args.allow_clusters)?;
+    let offer_book = offer_book_path(&args.offer_book);
+    if let Some(path) = &offer_book {
+        taker.order_book.load_cache(path)?;
+    }
+    super::taker::list_offers(&mut taker, &reputation_path(&args.reputation))?;
+    if let Some(path) = &offer_book {
+        taker.order_book.save_cache(path)?;
+    }
+    if let Some(days) = args.network_stats {
+        super::taker::network_stats(&mut taker, days)?;
+    }
+    Ok(())
+}
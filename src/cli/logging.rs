@@ -0,0 +1,240 @@
+use anyhow::{bail, Result};
+use log::LevelFilter;
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
+
+/// Seconds between checks of the level file of a running maker
+const LEVEL_POLL_SECS: u64 = 2;
+
+/// Levels from quietest to most verbose, the default is debug
+const LEVELS: [LevelFilter; 6] = [
+    LevelFilter::Off,
+    LevelFilter::Error,
+    LevelFilter::Warn,
+    LevelFilter::Info,
+    LevelFilter::Debug,
+    LevelFilter::Trace,
+];
+
+/// Level of nostrdizer logs, each -v is one level more verbose and each -q one quieter than debug
+pub fn level(verbose: u8, quiet: u8) -> LevelFilter {
+    let index = (4 + verbose as i32 - quiet as i32).clamp(0, LEVELS.len() as i32 - 1);
+    LEVELS[index as usize]
+}
+
+/// Sets up logging of nostrdizer to stdout, or to files rolled over each day when a log file is set
+/// Files older than keep days are removed as the log rolls over
+/// Everything is let through the filter so the level can be raised while running
+pub fn init(level: LevelFilter, log_file: Option<&Path>, keep_days: Option<u64>) -> Result<()> {
+    let mut builder = env_logger::Builder::new();
+    builder
+        .format(|buf, record| {
+            writeln!(
+                buf,
+                "{}:{} {} [{}] - {}",
+                record.file().unwrap_or("unknown"),
+                record.line().unwrap_or(0),
+                chrono::Local::now().format("%Y-%m-%dT%H:%M:%S"),
+                record.level(),
+                record.args()
+            )
+        })
+        .filter(Some("nostrdizer"), LevelFilter::Trace);
+    if let Some(path) = log_file {
+        builder.target(env_logger::Target::Pipe(Box::new(RollingFile::new(
+            path, keep_days,
+        )?)));
+    }
+    builder.try_init()?;
+    log::set_max_level(level);
+    Ok(())
+}
+
+/// File a running maker takes its log level from, next to its status file
+pub fn level_path(status_path: &Path) -> PathBuf {
+    status_path.with_extension("loglevel")
+}
+
+/// Asks the maker writing to the status file to log at level
+pub fn set_level(status_path: &Path, level: &str) -> Result<()> {
+    let level = parse_level(level)?;
+    fs::write(level_path(status_path), level.to_string())?;
+    println!("Maker logs at {level} once it picks it up");
+    Ok(())
+}
+
+/// Applies levels written to the level file while the maker runs
+pub fn watch_level(status_path: &Path) {
+    let path = level_path(status_path);
+    // A level left from an earlier run does not override the flags
+    fs::remove_file(&path).ok();
+    thread::spawn(move || loop {
+        thread::sleep(Duration::from_secs(LEVEL_POLL_SECS));
+        if let Ok(level) = fs::read_to_string(&path) {
+            fs::remove_file(&path).ok();
+            match parse_level(&level) {
+                Ok(level) => {
+                    log::set_max_level(level);
+                    log::info!("Log level set to {level}");
+                }
+                Err(err) => log::warn!("{err}"),
+            }
+        }
+    });
+}
+
+fn parse_level(level: &str) -> Result<LevelFilter> {
+    match LevelFilter::from_str(level.trim()) {
+        Ok(level) => Ok(level),
+        Err(_) => bail!(
+            "Unknown log level {}, expected off, error, warn, info, debug or trace",
+            level.trim()
+        ),
+    }
+}
+
+/// Log file that starts a new file, suffixed with the date, each day
+struct RollingFile {
+    path: PathBuf,
+    date: String,
+    file: File,
+    keep_days: Option<u64>,
+}
+
+impl RollingFile {
+    fn new(path: &Path, keep_days: Option<u64>) -> io::Result<Self> {
+        let date = today();
+        let file = open(&dated_path(path, &date))?;
+        let rolling = Self {
+            path: path.to_path_buf(),
+            date,
+            file,
+            keep_days,
+        };
+        rolling.remove_expired();
+        Ok(rolling)
+    }
+
+    /// Removes the files of days more than keep days ago
+    fn remove_expired(&self) {
+        let keep_days = match self.keep_days {
+            Some(keep_days) => keep_days,
+            None => return,
+        };
+        let dir = match self.path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+        let oldest = (chrono::Local::now() - chrono::Duration::days(keep_days as i64))
+            .format("%Y-%m-%d")
+            .to_string();
+        for entry in entries.flatten() {
+            if let Some(date) = log_date(&self.path, &entry.file_name().to_string_lossy()) {
+                if date < oldest.as_str() {
+                    fs::remove_file(entry.path()).ok();
+                }
+            }
+        }
+    }
+}
+
+impl Write for RollingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let date = today();
+        if date != self.date {
+            self.file = open(&dated_path(&self.path, &date))?;
+            self.date = date;
+            self.remove_expired();
+        }
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+fn today() -> String {
+    chrono::Local::now().format("%Y-%m-%d").to_string()
+}
+
+/// Path of the log file of a day, nostrdizer.log is rolled into nostrdizer.2026-01-31.log
+fn dated_path(path: &Path, date: &str) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(extension) => format!("{stem}.{date}.{}", extension.to_string_lossy()),
+        None => format!("{stem}.{date}"),
+    };
+    path.with_file_name(name)
+}
+
+/// Date of a file the log was rolled into, none for other files
+fn log_date<'a>(path: &Path, name: &'a str) -> Option<&'a str> {
+    let stem = path.file_stem()?.to_string_lossy();
+    let date = name.strip_prefix(stem.as_ref())?.strip_prefix('.')?;
+    let date = match path.extension() {
+        Some(extension) => date
+            .strip_suffix(extension.to_string_lossy().as_ref())?
+            .strip_suffix('.')?,
+        None => date,
+    };
+    chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()?;
+    Some(date)
+}
+
+fn open(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_level_flags() {
+        assert_eq!(level(0, 0), LevelFilter::Debug);
+        assert_eq!(level(1, 0), LevelFilter::Trace);
+        assert_eq!(level(3, 0), LevelFilter::Trace);
+        assert_eq!(level(0, 2), LevelFilter::Warn);
+        assert_eq!(level(1, 1), LevelFilter::Debug);
+        assert_eq!(level(0, 9), LevelFilter::Off);
+        assert!(parse_level(" info\n").is_ok());
+        assert!(parse_level("loud").is_err());
+    }
+
+    #[test]
+    fn test_dated_path() {
+        assert_eq!(
+            dated_path(Path::new("logs/nostrdizer.log"), "2026-01-31"),
+            PathBuf::from("logs/nostrdizer.2026-01-31.log")
+        );
+        assert_eq!(
+            dated_path(Path::new("maker"), "2026-01-31"),
+            PathBuf::from("maker.2026-01-31")
+        );
+    }
+
+    #[test]
+    fn test_log_date() {
+        let path = Path::new("logs/nostrdizer.log");
+        assert_eq!(
+            log_date(path, "nostrdizer.2026-01-31.log"),
+            Some("2026-01-31")
+        );
+        assert_eq!(log_date(path, "nostrdizer.log"), None);
+        assert_eq!(log_date(path, "nostrdizer.backup.log"), None);
+        assert_eq!(
+            log_date(Path::new("maker"), "maker.2026-01-31"),
+            Some("2026-01-31")
+        );
+    }
+}
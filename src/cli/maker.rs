@@ -0,0 +1,1293 @@
+use nostrdizer::{
+    audit::{Audit, WalletSpend, AUDIT_INTERVAL},
+    backup::{fetch_backup, Backup},
+    blacklist::{Commitment, CommitmentBlacklist},
+    denomination, display,
+    errors::Error as NostrdizerError,
+    fees,
+    fiat::FiatConfig,
+    greylist::TakerGreylist,
+    identity,
+    invite::InviteStore,
+    keystore::Keystore,
+    labels::LabelStore,
+    maker::Maker,
+    payout::{PayoutConfig, PayoutEntry, PayoutHistory},
+    reputation::Reputation,
+    retention::RetentionPolicy,
+    standby::{self, POLL_INTERVAL},
+    transcript::{Completion, RoundHistory, RoundRecord},
+    types::{
+        Amount, Fill, IoAuth, MakerStatus, OutPoint, PartiallySignedTransaction, PresenceStatus,
+        RejectReason, Transaction, Txid, VerifyCJInfo,
+    },
+};
+
+use anyhow::{Context, Result};
+use log::{debug, error, warn};
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+
+/// Times a maker signs a CJ the taker rebuilt in one round
+const MAX_ROUND_REBUILDS: usize = 2;
+
+/// Offer book side of the maker, between rounds
+pub trait MakerBook {
+    fn status(&self) -> MakerStatus;
+    fn sweep_if_due(
+        &mut self,
+        payout: &PayoutConfig,
+        history: &mut PayoutHistory,
+    ) -> Result<Option<Txid>, NostrdizerError>;
+    fn publish_offer(&mut self) -> Result<(), NostrdizerError>;
+    fn publish_presence(&mut self, status: PresenceStatus) -> Result<(), NostrdizerError>;
+    fn replicate(&mut self, backup: &Backup) -> Result<(), NostrdizerError>;
+    fn close_fill_subscription(&mut self) -> Result<(), NostrdizerError>;
+    /// Commitments makers gossiped since a time
+    fn fetch_used_commitments(&mut self, since: u64) -> Result<Vec<Commitment>, NostrdizerError>;
+    fn get_fill_offer(&mut self) -> Result<(String, Fill), NostrdizerError>;
+}
+
+/// Maker in a round with a taker
+pub trait MakerRound {
+    fn round_id(&self) -> Option<String>;
+    fn delete_active_offer(&mut self) -> Result<(), NostrdizerError>;
+    /// Gossips a commitment opened in the round
+    fn publish_used_commitment(&mut self, commit: Commitment) -> Result<(), NostrdizerError>;
+    fn send_fill_ack(&mut self, peer_pub_key: &str) -> Result<(), NostrdizerError>;
+    fn send_fill_reject(
+        &mut self,
+        peer_pub_key: &str,
+        reason: RejectReason,
+    ) -> Result<(), NostrdizerError>;
+    fn send_reject(
+        &mut self,
+        peer_pub_key: &str,
+        reason: RejectReason,
+    ) -> Result<(), NostrdizerError>;
+    /// Why the maker's round policy turns down the fill, none if it does not
+    fn check_policy(&self, fill_offer: &Fill) -> Option<RejectReason>;
+    /// Checks the fill's amount, countering fills just over the max size
+    fn check_fill_amount(&mut self, fill_offer: &Fill) -> Option<RejectReason>;
+    /// Waits for the taker's podle commitment and verifies it
+    fn verify_auth(&mut self) -> Result<(), NostrdizerError>;
+    /// Counts an auth of taker that failed to verify, returns true if it greylists the taker
+    fn record_auth_failure(&mut self, peer_pub_key: &str) -> bool;
+    /// Takers whose auths failed to verify
+    fn greylist(&self) -> &TakerGreylist;
+    fn get_inputs(&mut self, fill_offer: &Fill) -> Result<IoAuth, NostrdizerError>;
+    /// Own utxos a transaction in the mempool already spends
+    fn mempool_conflicts(&self, maker_input: &IoAuth) -> Result<Vec<OutPoint>, NostrdizerError>;
+    fn send_maker_input(
+        &mut self,
+        peer_pub_key: &str,
+        maker_input: IoAuth,
+    ) -> Result<(), NostrdizerError>;
+    fn get_unsigned_cj_transaction(&mut self) -> Result<Transaction, NostrdizerError>;
+    fn verify_claimed_participants(
+        &self,
+        transaction: &Transaction,
+        send_amount: Amount,
+    ) -> Option<RejectReason>;
+    fn verify_transaction(
+        &mut self,
+        psbt: &PartiallySignedTransaction,
+        fill_offer: &Fill,
+        maker_input: &IoAuth,
+    ) -> Result<VerifyCJInfo, NostrdizerError>;
+    fn sign_psbt(
+        &mut self,
+        unsigned_psbt: PartiallySignedTransaction,
+    ) -> Result<PartiallySignedTransaction, NostrdizerError>;
+    fn publish_signed_psbt(
+        &mut self,
+        peer_pub_key: &str,
+        psbt: PartiallySignedTransaction,
+    ) -> Result<(), NostrdizerError>;
+    fn confirm_round(
+        &mut self,
+        peer_pub_key: &str,
+        txid: &Txid,
+    ) -> Result<RoundRecord, NostrdizerError>;
+}
+
+/// Maker the command handlers run against
+pub trait MakerOps: MakerBook + MakerRound {}
+
+impl<M: MakerBook + MakerRound> MakerOps for M {}
+
+impl MakerBook for Maker {
+    fn status(&self) -> MakerStatus {
+        Maker::status(self)
+    }
+
+    fn sweep_if_due(
+        &mut self,
+        payout: &PayoutConfig,
+        history: &mut PayoutHistory,
+    ) -> Result<Option<Txid>, NostrdizerError> {
+        Maker::sweep_if_due(self, payout, history)
+    }
+
+    fn publish_offer(&mut self) -> Result<(), NostrdizerError> {
+        Maker::publish_offer(self)
+    }
+
+    fn publish_presence(&mut self, status: PresenceStatus) -> Result<(), NostrdizerError> {
+        Maker::publish_presence(self, status)
+    }
+
+    fn replicate(&mut self, backup: &Backup) -> Result<(), NostrdizerError> {
+        Maker::replicate(self, backup)
+    }
+
+    fn close_fill_subscription(&mut self) -> Result<(), NostrdizerError> {
+        Maker::close_fill_subscription(self)
+    }
+
+    fn fetch_used_commitments(&mut self, since: u64) -> Result<Vec<Commitment>, NostrdizerError> {
+        Maker::fetch_used_commitments(self, since)
+    }
+
+    fn get_fill_offer(&mut self) -> Result<(String, Fill), NostrdizerError> {
+        Maker::get_fill_offer(self)
+    }
+}
+
+impl MakerRound for Maker {
+    fn round_id(&self) -> Option<String> {
+        self.round_id.clone()
+    }
+
+    fn delete_active_offer(&mut self) -> Result<(), NostrdizerError> {
+        Maker::delete_active_offer(self)
+    }
+
+    fn publish_used_commitment(&mut self, commit: Commitment) -> Result<(), NostrdizerError> {
+        Maker::publish_used_commitment(self, commit)
+    }
+
+    fn send_fill_ack(&mut self, peer_pub_key: &str) -> Result<(), NostrdizerError> {
+        Maker::send_fill_ack(self, peer_pub_key)
+    }
+
+    fn send_fill_reject(
+        &mut self,
+        peer_pub_key: &str,
+        reason: RejectReason,
+    ) -> Result<(), NostrdizerError> {
+        Maker::send_fill_reject(self, peer_pub_key, reason)
+    }
+
+    fn send_reject(
+        &mut self,
+        peer_pub_key: &str,
+        reason: RejectReason,
+    ) -> Result<(), NostrdizerError> {
+        Maker::send_reject(self, peer_pub_key, reason)
+    }
+
+    fn verify_auth(&mut self) -> Result<(), NostrdizerError> {
+        let auth_proof = self.get_commitment_auth()?;
+        self.verify_commitment(&auth_proof)
+    }
+
+    fn record_auth_failure(&mut self, peer_pub_key: &str) -> bool {
+        Maker::record_auth_failure(self, peer_pub_key)
+    }
+
+    fn greylist(&self) -> &TakerGreylist {
+        &self.greylist
+    }
+
+    fn get_inputs(&mut self, fill_offer: &Fill) -> Result<IoAuth, NostrdizerError> {
+        Maker::get_inputs(self, fill_offer)
+    }
+
+    fn mempool_conflicts(&self, maker_input: &IoAuth) -> Result<Vec<OutPoint>, NostrdizerError> {
+        let outpoints: Vec<OutPoint> = maker_input
+            .utxos
+            .iter()
+            .map(|(outpoint, _)| *outpoint)
+            .collect();
+        Maker::mempool_conflicts(self, &outpoints)
+    }
+
+    fn send_maker_input(
+        &mut self,
+        peer_pub_key: &str,
+        maker_input: IoAuth,
+    ) -> Result<(), NostrdizerError> {
+        Maker::send_maker_input(self, peer_pub_key, maker_input)
+    }
+
+    fn get_unsigned_cj_transaction(&mut self) -> Result<Transaction, NostrdizerError> {
+        Maker::get_unsigned_cj_transaction(self)
+    }
+
+    fn verify_claimed_participants(
+        &self,
+        transaction: &Transaction,
+        send_amount: Amount,
+    ) -> Option<RejectReason> {
+        Maker::verify_claimed_participants(self, transaction, send_amount)
+    }
+
+    fn check_policy(&self, fill_offer: &Fill) -> Option<RejectReason> {
+        self.config.policy.check_fill(fill_offer)
+    }
+
+    fn check_fill_amount(&mut self, fill_offer: &Fill) -> Option<RejectReason> {
+        Maker::check_fill_amount(self, fill_offer)
+    }
+
+    fn verify_transaction(
+        &mut self,
+        psbt: &PartiallySignedTransaction,
+        fill_offer: &Fill,
+        maker_input: &IoAuth,
+    ) -> Result<VerifyCJInfo, NostrdizerError> {
+        Maker::verify_transaction(self, psbt, fill_offer, maker_input)
+    }
+
+    fn sign_psbt(
+        &mut self,
+        unsigned_psbt: PartiallySignedTransaction,
+    ) -> Result<PartiallySignedTransaction, NostrdizerError> {
+        Maker::sign_psbt(self, unsigned_psbt)
+    }
+
+    fn publish_signed_psbt(
+        &mut self,
+        peer_pub_key: &str,
+        psbt: PartiallySignedTransaction,
+    ) -> Result<(), NostrdizerError> {
+        Maker::publish_signed_psbt(self, peer_pub_key, psbt)
+    }
+
+    fn confirm_round(
+        &mut self,
+        peer_pub_key: &str,
+        txid: &Txid,
+    ) -> Result<RoundRecord, NostrdizerError> {
+        Maker::confirm_round(self, peer_pub_key, txid)
+    }
+}
+
+/// Files a maker keeps across its rounds
+pub struct MakerFiles<'a> {
+    pub status: &'a Path,
+    /// Payout config with the history of the earnings swept by it
+    pub payouts: &'a Option<(PayoutConfig, PathBuf)>,
+    pub round_history: &'a Path,
+    pub labels: &'a Path,
+    /// Only fills with a valid invite token are taken when there is an invites path
+    pub invites: Option<&'a Path>,
+    /// With a replica keystore path its commitments and the round history are replicated for a standby each round
+    pub replica_keystore: Option<&'a Path>,
+    /// Fills with a commitment in the blacklist are turned down, commitments other makers gossip are added each round
+    pub blacklist: &'a Path,
+    /// Takers' failed auths are counted in the greylist file, saved each round
+    pub greylist: &'a Path,
+}
+
+/// Runs maker rounds until an error
+/// Offers are not left behind when the maker stops on an error
+/// The round history is pruned by the retention policy each round
+pub fn run_maker(
+    maker: &mut dyn MakerOps,
+    files: &MakerFiles,
+    retention: &RetentionPolicy,
+) -> Result<()> {
+    let result = run_maker_rounds(maker, files, retention);
+    maker.delete_active_offer()?;
+    maker.publish_presence(PresenceStatus::Offline)?;
+    maker.close_fill_subscription()?;
+    result
+}
+
+/// Prints the fees the maker earned and swept, with their value in fiat when a price is known
+pub fn payout_report(history_path: &Path, fiat: &Option<FiatConfig>) -> Result<()> {
+    let history = PayoutHistory::load(history_path)?;
+    let price = super::fiat::price(fiat);
+    let mut earned = Amount::ZERO;
+    let mut sweep_fees = Amount::ZERO;
+    for entry in &history.entries {
+        match entry {
+            PayoutEntry::Earned {
+                txid,
+                amount,
+                created_at,
+            } => {
+                earned = fees::checked_add(earned, *amount)?;
+                println!(
+                    "{}: earned {} in {txid}",
+                    display::timestamp(*created_at),
+                    display::with_fiat(amount.to_signed()?, price.as_ref())
+                );
+            }
+            PayoutEntry::Sweep {
+                txid,
+                amount,
+                fee,
+                created_at,
+            } => {
+                sweep_fees = fees::checked_add(sweep_fees, *fee)?;
+                println!(
+                    "{}: swept {} for a fee of {} in {txid}",
+                    display::timestamp(*created_at),
+                    display::with_fiat(amount.to_signed()?, price.as_ref()),
+                    display::with_fiat(fee.to_signed()?, price.as_ref())
+                );
+            }
+        }
+    }
+    println!(
+        "Earned: {}",
+        display::with_fiat(earned.to_signed()?, price.as_ref())
+    );
+    println!(
+        "Sweep fees: {}",
+        display::with_fiat(sweep_fees.to_signed()?, price.as_ref())
+    );
+    println!(
+        "Unswept: {}",
+        display::with_fiat(history.unswept()?.to_signed()?, price.as_ref())
+    );
+    if let Some(price) = price {
+        println!(
+            "At {:.2} {} per BTC as of {}",
+            price.btc_price,
+            price.currency,
+            display::timestamp(price.fetched_at)
+        );
+    }
+    Ok(())
+}
+
+pub fn maker_status(status_path: &Path) -> Result<()> {
+    let status: MakerStatus = serde_json::from_str(&fs::read_to_string(status_path)?)?;
+    println!("{}", serde_json::to_string_pretty(&status)?);
+    Ok(())
+}
+
+/// Prints the invite token of a taker, minting the secret of the store if there is none
+pub fn mint_invite(invites_path: &Path, taker: &str) -> Result<()> {
+    let mut invites = InviteStore::load(invites_path)?;
+    let token = invites.mint(taker)?;
+    invites.save(invites_path)?;
+    println!("Invite token of {taker}: {token}");
+    Ok(())
+}
+
+/// Turns down fills of the taker from the next round of makers with the store
+pub fn revoke_invite(invites_path: &Path, taker: &str) -> Result<()> {
+    let mut invites = InviteStore::load(invites_path)?;
+    invites.revoke(taker);
+    invites.save(invites_path)?;
+    println!("Revoked the invite of {taker}");
+    Ok(())
+}
+
+/// Watches the maker wallet for spends that are not a CJ or sweep in the maker's histories, a sign the keys leaked
+/// Each unexpected spend is alerted on once, logged as an error and passed to `alert_command` if there is one
+pub fn audit_wallet(
+    mut wallet_spends: impl FnMut() -> Result<Vec<WalletSpend>>,
+    audit_path: &Path,
+    payout_history_path: &Path,
+    round_history_path: &Path,
+    alert_command: Option<&str>,
+) -> Result<()> {
+    let mut audit = Audit::load(audit_path)?;
+    loop {
+        let spends = wallet_spends()?;
+        // The running maker records to the histories, they are loaded again each check
+        let payouts = PayoutHistory::load(payout_history_path)?;
+        let rounds = RoundHistory::load(round_history_path)?;
+        let now = chrono::Utc::now().timestamp() as u64;
+        for spend in audit.unexpected(&spends, &payouts, &rounds, now) {
+            alert(&spend, alert_command);
+            audit.record_alert(spend.txid);
+            audit.save(audit_path)?;
+        }
+        audit.checked(now);
+        audit.save(audit_path)?;
+        thread::sleep(Duration::from_secs(AUDIT_INTERVAL));
+    }
+}
+
+/// Logs an unexpected spend and runs the alert command with it in `NOSTRDIZER_ALERT_TXID` and `NOSTRDIZER_ALERT_AMOUNT`
+fn alert(spend: &WalletSpend, alert_command: Option<&str>) {
+    error!(
+        "Unexpected spend of the maker wallet in {}: {} sent",
+        spend.txid,
+        display::sats_and_btc(spend.amount)
+    );
+    if let Some(command) = alert_command {
+        let status = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .env("NOSTRDIZER_ALERT_TXID", spend.txid.to_string())
+            .env("NOSTRDIZER_ALERT_AMOUNT", spend.amount.to_sat().to_string())
+            .status();
+        match status {
+            Ok(status) if status.success() => (),
+            Ok(status) => warn!("Alert command failed with {status}"),
+            Err(err) => warn!("Could not run the alert command: {err}"),
+        }
+    }
+}
+
+/// Stays silent while the primary sharing the maker identity heartbeats, then merges the keystore commitments and
+/// round history it replicated into the local stores so the maker takes over with them
+pub fn wait_as_standby(
+    maker: &mut Maker,
+    takeover_after: u64,
+    keystore_path: &Path,
+    round_history_path: &Path,
+) -> Result<()> {
+    println!("Standing by for {}...", maker.identity.public_key_str);
+    let watching_since = chrono::Utc::now().timestamp() as u64;
+    loop {
+        let presence = standby::fetch_presence(
+            &mut maker.offer_client,
+            &maker.identity.public_key_str,
+            maker.network,
+        )?;
+        if standby::primary_stopped(
+            presence.as_ref(),
+            watching_since,
+            takeover_after,
+            chrono::Utc::now().timestamp() as u64,
+        ) {
+            break;
+        }
+        debug!("Primary is online");
+        thread::sleep(Duration::from_secs(POLL_INTERVAL));
+    }
+
+    match fetch_backup(&mut maker.nostr_client, &maker.identity)? {
+        Some(replica) => {
+            let mut keystore = Keystore::load(keystore_path)?;
+            let mut round_history = RoundHistory::load(round_history_path)?;
+            let added = replica.merge(
+                &mut Reputation::default(),
+                &mut keystore,
+                &mut round_history,
+            );
+            keystore.save(keystore_path)?;
+            round_history.save(round_history_path)?;
+            println!("Primary stopped, taking over with {added} entries it replicated");
+        }
+        None => println!("Primary stopped, taking over without a replica"),
+    }
+    Ok(())
+}
+
+/// Replaces the encryption key in the keystore, a running maker keeps its key until restarted
+pub fn rotate_encryption_key(keystore_path: &Path) -> Result<()> {
+    let mut keystore = Keystore::load(keystore_path)?;
+    let encryption_key = keystore.rotate_encryption_key();
+    keystore.save(keystore_path)?;
+    println!(
+        "Encryption public key: {}",
+        identity::nostr_pub_key(&encryption_key)?
+    );
+    Ok(())
+}
+
+/// Prints the nostr identity derived from the wallet
+pub fn show_identity(priv_key: &str, index: u32) -> Result<()> {
+    println!("Nostr public key: {}", identity::nostr_pub_key(priv_key)?);
+    println!("Derived at: {}", identity::nostr_key_path(index)?);
+    Ok(())
+}
+
+fn run_maker_rounds(
+    maker: &mut dyn MakerOps,
+    files: &MakerFiles,
+    retention: &RetentionPolicy,
+) -> Result<()> {
+    let mut blacklist = CommitmentBlacklist::load(files.blacklist)?;
+    loop {
+        fs::write(files.status, serde_json::to_string_pretty(&maker.status())?)?;
+        super::retention::prune(retention, files.round_history, None)?;
+
+        // Commitments opened with other makers are burned here too, gossip is best effort
+        let synced_at = chrono::Utc::now().timestamp() as u64;
+        match maker.fetch_used_commitments(blacklist.synced_at) {
+            Ok(gossiped) => {
+                let added = blacklist.merge(gossiped);
+                if added > 0 {
+                    debug!("Burned {added} gossiped commitments");
+                }
+                blacklist.synced_at = synced_at;
+                blacklist.save(files.blacklist)?;
+            }
+            Err(err) => warn!("Could not fetch used commitments: {err}"),
+        }
+
+        // Standby has the rounds up to now when it takes over
+        if let Some(keystore_path) = files.replica_keystore {
+            let backup = Backup::new(
+                &Reputation::default(),
+                &Keystore::load(keystore_path)?,
+                &RoundHistory::load(files.round_history)?,
+            );
+            maker.replicate(&backup)?;
+        }
+
+        if let Some((payout, history_path)) = files.payouts {
+            let mut history = PayoutHistory::load(history_path)?;
+            if let Some(txid) = maker.sweep_if_due(payout, &mut history)? {
+                println!("Swept earnings to {} in {}", payout.address, txid);
+                history.save(history_path)?;
+            }
+        }
+
+        // Step 1: Publish order (!ordertype)
+        maker.publish_offer()?;
+
+        println!("Waiting for takers...");
+
+        // Step 2: Receives fill offer (!fill)
+        let (peer_pubkey, fill_offer) = maker.get_fill_offer()?;
+        let round_id = maker.round_id().unwrap_or_default();
+
+        println!(
+            "Received fill in round {} for {}",
+            round_id,
+            display::sats(fill_offer.amount)
+        );
+        fs::write(files.status, serde_json::to_string_pretty(&maker.status())?)?;
+
+        // Reloaded each round so revoked tokens are turned down without a restart
+        let invites = files.invites.map(InviteStore::load).transpose()?;
+        let result = run_maker_round(
+            maker,
+            &peer_pubkey,
+            &fill_offer,
+            files,
+            invites.as_ref(),
+            &mut blacklist,
+        );
+        blacklist.save(files.blacklist)?;
+        maker.greylist().save(files.greylist)?;
+        result.with_context(|| format!("Round {round_id} with taker {peer_pubkey} failed"))?;
+    }
+}
+
+/// Answers a fill from taker until the CJ is signed or rejected
+/// The fill's commitment is burned and gossiped once the taker opens it
+fn run_maker_round<M: MakerRound + ?Sized>(
+    maker: &mut M,
+    peer_pubkey: &str,
+    fill_offer: &Fill,
+    files: &MakerFiles,
+    invites: Option<&InviteStore>,
+    blacklist: &mut CommitmentBlacklist,
+) -> Result<()> {
+    let round_id = maker.round_id().unwrap_or_default();
+
+    // Fills asking for outputs the maker can not add, or not worth adding, are turned down before the session starts
+    // Fills just over the max size get a counter-offer in place of the reject
+    // A private pool maker turns down takers that were not invited first
+    // Commitments opened before are turned down so a taker can not probe makers with one utxo
+    if let Some(reason) = invites
+        .and_then(|invites| invites.check(peer_pubkey, fill_offer.invite.as_deref()))
+        .or_else(|| blacklist.check(&fill_offer.commitment))
+        .or_else(|| denomination::check_fill(fill_offer))
+        .or_else(|| maker.check_policy(fill_offer))
+        .or_else(|| maker.check_fill_amount(fill_offer))
+    {
+        warn!(
+            "[round {round_id}] Rejecting fill: {}",
+            display::reject_reason(&reason)
+        );
+        maker.send_fill_reject(peer_pubkey, reason)?;
+        return Ok(());
+    }
+
+    // Inputs are picked before the fill is taken so a maker that can not fund it turns it down
+    let maker_input = match maker.get_inputs(fill_offer) {
+        Ok(maker_input) => maker_input,
+        Err(
+            err @ (NostrdizerError::InsufficientFunds
+            | NostrdizerError::ChangeTooLarge(_)
+            | NostrdizerError::OverRoundCap(_)
+            | NostrdizerError::SilentPaymentKeysUnavailable),
+        ) => {
+            warn!("[round {round_id}] Rejecting fill: {}", err);
+            maker.send_fill_reject(peer_pubkey, RejectReason::NoSuitableInputs)?;
+            return Ok(());
+        }
+        Err(err) => return Err(err.into()),
+    };
+
+    // Tell taker where the rest of the session is
+    maker.send_fill_ack(peer_pubkey)?;
+
+    maker.delete_active_offer()?;
+
+    // Step 4: Receives !auth
+    // Taker aborts the session when it has enough makers without this one
+    match maker.verify_auth() {
+        Ok(()) => {
+            debug!("[round {round_id}] Verified auth");
+            if blacklist.insert(fill_offer.commitment) {
+                if let Err(err) = maker.publish_used_commitment(fill_offer.commitment) {
+                    warn!("[round {round_id}] Could not gossip the used commitment: {err}");
+                }
+            }
+        }
+        Err(NostrdizerError::TakerAborted) => {
+            debug!("[round {round_id}] Taker aborted the session");
+            return Ok(());
+        }
+        // Peer passed on an opening another taker sent it, or sent one that does not open its commitment
+        // Takers that keep sending them are probing and have their fills ignored for a while
+        Err(
+            err @ (NostrdizerError::AuthBindingMismatch
+            | NostrdizerError::PodleVerifyFailed
+            | NostrdizerError::PodleCommitment
+            | NostrdizerError::PodleIndexNotAccepted { .. }),
+        ) => {
+            warn!("[round {round_id}] Rejecting auth: {err}");
+            maker.record_auth_failure(peer_pubkey);
+            return Ok(());
+        }
+        Err(err) => return Err(err.into()),
+    }
+
+    // Inputs may have been spent elsewhere since they were picked, the taker fills in with spare makers
+    let conflicts = maker.mempool_conflicts(&maker_input)?;
+    if !conflicts.is_empty() {
+        warn!("[round {round_id}] Inputs already spent in the mempool, not sending them: {conflicts:?}");
+        return Ok(());
+    }
+
+    // Step 5: sends (!ioauth)
+    maker.send_maker_input(peer_pubkey, maker_input.clone())?;
+    debug!("[round {round_id}] Sent inputs");
+
+    // Step 6: Receives Transaction Hex (!tx)
+    match maker.get_unsigned_cj_transaction() {
+        Ok(unsigned_tx) => sign_round(
+            maker,
+            peer_pubkey,
+            fill_offer,
+            &maker_input,
+            unsigned_tx,
+            files,
+        )?,
+        Err(NostrdizerError::TakerFailedToSendTransaction) => {
+            warn!("[round {round_id}] Taker did not send transaction");
+        }
+        Err(NostrdizerError::TakerAborted) => {
+            debug!("[round {round_id}] Taker aborted the session");
+        }
+        Err(err) => error!("[round {round_id}] {:?}", err),
+    }
+
+    Ok(())
+}
+
+/// Verifies and signs the CJ from taker, then records the round once the taker confirms it
+/// A CJ the taker rebuilt without makers that did not sign is verified and signed again, up to `MAX_ROUND_REBUILDS` times
+fn sign_round<M: MakerRound + ?Sized>(
+    maker: &mut M,
+    peer_pubkey: &str,
+    fill_offer: &Fill,
+    maker_input: &IoAuth,
+    mut unsigned_tx: Transaction,
+    files: &MakerFiles,
+) -> Result<()> {
+    let round_id = maker.round_id().unwrap_or_default();
+    let mut rebuilds = 0;
+    loop {
+        // A taker lying about how many are in the round is not signed for
+        if let Some(reason) =
+            maker.verify_claimed_participants(&unsigned_tx, fill_offer.participant_amount())
+        {
+            warn!(
+                "[round {round_id}] Rejecting: {}",
+                display::reject_reason(&reason)
+            );
+            maker.send_reject(peer_pubkey, reason)?;
+            return Ok(());
+        }
+        let unsigned_psbt = unsigned_tx.psbt;
+        let tx_info = match maker.verify_transaction(&unsigned_psbt, fill_offer, maker_input) {
+            Ok(tx_info) => tx_info,
+            Err(err) => {
+                warn!("[round {round_id}] Transaction could not be verified: {err}");
+                return Ok(());
+            }
+        };
+        if !tx_info.verifyed {
+            warn!("[round {round_id}] Transaction could not be verified");
+            if let Some(reason) = tx_info.reject_reason {
+                warn!(
+                    "[round {round_id}] Rejecting: {}",
+                    display::reject_reason(&reason)
+                );
+                maker.send_reject(peer_pubkey, reason)?;
+            }
+            return Ok(());
+        }
+
+        let unsigned_cj = unsigned_psbt.unsigned_tx.clone();
+        let txid = unsigned_cj.txid();
+        // Step 7: Signs and sends transaction to taker if verified (!sig)
+        let signed_psbt = maker.sign_psbt(unsigned_psbt)?;
+
+        maker.publish_signed_psbt(peer_pubkey, signed_psbt)?;
+        debug!("[round {round_id}] Sent signed transaction {txid}");
+
+        // Step 8: Confirms the transcript once the taker broadcasts (!confirm)
+        let record = match maker.confirm_round(peer_pubkey, &txid) {
+            Ok(record) => record,
+            Err(NostrdizerError::RoundRebuilt(rebuilt)) if rebuilds < MAX_ROUND_REBUILDS => {
+                debug!("[round {round_id}] Taker rebuilt the transaction, signing it again");
+                rebuilds += 1;
+                unsigned_tx = *rebuilt;
+                continue;
+            }
+            Err(err) => return Err(err.into()),
+        };
+        match record.completion {
+            Completion::Confirmed => {
+                debug!("[round {round_id}] Taker confirmed the round")
+            }
+            // A relay changed or dropped messages of the round
+            Completion::Mismatch => {
+                warn!("[round {round_id}] Transcript differs from the taker's")
+            }
+            Completion::Unconfirmed => {
+                debug!("[round {round_id}] Taker did not confirm the round")
+            }
+        }
+        let mut history = RoundHistory::load(files.round_history)?;
+        history.record(record);
+        history.save(files.round_history)?;
+
+        // Only the CJ the maker signed last can be broadcast
+        if let Some((_, history_path)) = files.payouts {
+            let mut history = PayoutHistory::load(history_path)?;
+            history.record_earned(
+                txid,
+                tx_info.maker_fee,
+                chrono::Utc::now().timestamp() as u64,
+            );
+            history.save(history_path)?;
+        }
+
+        let own_scripts: Vec<_> = maker_input
+            .addresses()
+            .iter()
+            .map(|address| address.script_pubkey())
+            .collect();
+        let mut labels = LabelStore::load(files.labels)?;
+        labels.label_cj(&unsigned_cj, |output| {
+            own_scripts.contains(&output.script_pubkey)
+        });
+        labels.save(files.labels)?;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostrdizer::{
+        fees,
+        greylist::GreylistPolicy,
+        policy::RoundPolicy,
+        test_utils::{fill, io_auth, maker_config, psbt},
+        transcript::Transcript,
+        types::{CounterOffer, Denomination, MakerConfig, SignedAmount},
+    };
+
+    /// Maker that records what it sent the taker, and never receives a transaction
+    #[derive(Default)]
+    struct MockMaker {
+        /// Change the maker's inputs would leave over its max
+        excess_change: Option<Amount>,
+        /// Own utxos spent in the mempool after they were picked
+        conflicts: Vec<OutPoint>,
+        acked: Vec<String>,
+        rejects: Vec<RejectReason>,
+        sent_inputs: usize,
+        policy: RoundPolicy,
+        config: Option<MakerConfig>,
+        gossiped: Vec<Commitment>,
+        /// Taker sends an opening that does not verify
+        bad_auth: bool,
+        greylist: TakerGreylist,
+        /// CJ the taker sends, none is sent without one
+        unsigned_tx: Option<Transaction>,
+        /// CJs the taker rebuilds the round with, in order, while waiting to confirm
+        rebuilt: Vec<Transaction>,
+        /// Txids of the CJs signed
+        signed: Vec<Txid>,
+    }
+
+    impl MakerRound for MockMaker {
+        fn round_id(&self) -> Option<String> {
+            Some("round".to_string())
+        }
+
+        fn delete_active_offer(&mut self) -> Result<(), NostrdizerError> {
+            Ok(())
+        }
+
+        fn publish_used_commitment(&mut self, commit: Commitment) -> Result<(), NostrdizerError> {
+            self.gossiped.push(commit);
+            Ok(())
+        }
+
+        fn send_fill_ack(&mut self, peer_pub_key: &str) -> Result<(), NostrdizerError> {
+            self.acked.push(peer_pub_key.to_string());
+            Ok(())
+        }
+
+        fn send_fill_reject(
+            &mut self,
+            _peer_pub_key: &str,
+            reason: RejectReason,
+        ) -> Result<(), NostrdizerError> {
+            self.rejects.push(reason);
+            Ok(())
+        }
+
+        fn send_reject(
+            &mut self,
+            _peer_pub_key: &str,
+            reason: RejectReason,
+        ) -> Result<(), NostrdizerError> {
+            self.rejects.push(reason);
+            Ok(())
+        }
+
+        fn verify_auth(&mut self) -> Result<(), NostrdizerError> {
+            match self.bad_auth {
+                true => Err(NostrdizerError::PodleVerifyFailed),
+                false => Ok(()),
+            }
+        }
+
+        fn record_auth_failure(&mut self, peer_pub_key: &str) -> bool {
+            self.greylist
+                .record_failure(peer_pub_key, 0, &GreylistPolicy::default())
+        }
+
+        fn greylist(&self) -> &TakerGreylist {
+            &self.greylist
+        }
+
+        fn get_inputs(&mut self, _fill_offer: &Fill) -> Result<IoAuth, NostrdizerError> {
+            match self.excess_change {
+                Some(change) => Err(NostrdizerError::ChangeTooLarge(change)),
+                None => Ok(io_auth(0)),
+            }
+        }
+
+        fn mempool_conflicts(
+            &self,
+            _maker_input: &IoAuth,
+        ) -> Result<Vec<OutPoint>, NostrdizerError> {
+            Ok(self.conflicts.clone())
+        }
+
+        fn send_maker_input(
+            &mut self,
+            _peer_pub_key: &str,
+            _maker_input: IoAuth,
+        ) -> Result<(), NostrdizerError> {
+            self.sent_inputs += 1;
+            Ok(())
+        }
+
+        fn get_unsigned_cj_transaction(&mut self) -> Result<Transaction, NostrdizerError> {
+            self.unsigned_tx
+                .clone()
+                .ok_or(NostrdizerError::TakerFailedToSendTransaction)
+        }
+
+        fn verify_claimed_participants(
+            &self,
+            _transaction: &Transaction,
+            _send_amount: Amount,
+        ) -> Option<RejectReason> {
+            None
+        }
+
+        fn check_policy(&self, fill_offer: &Fill) -> Option<RejectReason> {
+            self.policy.check_fill(fill_offer)
+        }
+
+        fn check_fill_amount(&mut self, fill_offer: &Fill) -> Option<RejectReason> {
+            let config = self.config.as_ref()?;
+            fees::check_fill_amount(config, fill_offer).err()
+        }
+
+        fn verify_transaction(
+            &mut self,
+            _psbt: &PartiallySignedTransaction,
+            _fill_offer: &Fill,
+            _maker_input: &IoAuth,
+        ) -> Result<VerifyCJInfo, NostrdizerError> {
+            Ok(VerifyCJInfo {
+                mining_fee: SignedAmount::ZERO,
+                maker_fee: SignedAmount::from_sat(1000),
+                fee_rate: 1.0,
+                verifyed: true,
+                reject_reason: None,
+            })
+        }
+
+        fn sign_psbt(
+            &mut self,
+            unsigned_psbt: PartiallySignedTransaction,
+        ) -> Result<PartiallySignedTransaction, NostrdizerError> {
+            self.signed.push(unsigned_psbt.unsigned_tx.txid());
+            Ok(unsigned_psbt)
+        }
+
+        fn publish_signed_psbt(
+            &mut self,
+            _peer_pub_key: &str,
+            _psbt: PartiallySignedTransaction,
+        ) -> Result<(), NostrdizerError> {
+            Ok(())
+        }
+
+        fn confirm_round(
+            &mut self,
+            peer_pub_key: &str,
+            txid: &Txid,
+        ) -> Result<RoundRecord, NostrdizerError> {
+            if !self.rebuilt.is_empty() {
+                let rebuilt = self.rebuilt.remove(0);
+                return Err(NostrdizerError::RoundRebuilt(Box::new(rebuilt)));
+            }
+            Ok(RoundRecord {
+                round_id: "round".to_string(),
+                peer: peer_pub_key.to_string(),
+                txid: *txid,
+                transcript: Transcript::default().hash(txid),
+                completion: Completion::Confirmed,
+                created_at: 0,
+            })
+        }
+    }
+
+    /// Files of a maker whose rounds are not recorded
+    fn unused_files() -> MakerFiles<'static> {
+        MakerFiles {
+            status: Path::new("unused_status.json"),
+            payouts: &None,
+            round_history: Path::new("unused_rounds.json"),
+            labels: Path::new("unused_labels.jsonl"),
+            invites: None,
+            replica_keystore: None,
+            blacklist: Path::new("unused_commitments.json"),
+            greylist: Path::new("unused_greylist.json"),
+        }
+    }
+
+    #[test]
+    fn test_unsupported_denominations_rejected() {
+        let mut maker = MockMaker::default();
+        let mut fill_offer = fill(250_000);
+        fill_offer.denominations = vec![Denomination {
+            amount: Amount::from_sat(100_000),
+            count: 2,
+        }];
+
+        run_maker_round(
+            &mut maker,
+            "taker",
+            &fill_offer,
+            &unused_files(),
+            None,
+            &mut CommitmentBlacklist::default(),
+        )
+        .unwrap();
+        assert!(maker.acked.is_empty());
+        assert_eq!(maker.rejects, vec![RejectReason::UnsupportedDenominations]);
+    }
+
+    #[test]
+    fn test_rebuilt_round_signed_again() {
+        let transaction = |outputs: &[u64]| Transaction {
+            psbt: psbt(&[100_000, 100_000], outputs),
+            participants: Some(2),
+        };
+        let first = transaction(&[100_000, 100_000, 99_000]);
+        let rebuilt = transaction(&[100_000, 99_500]);
+        let mut maker = MockMaker {
+            unsigned_tx: Some(first.clone()),
+            rebuilt: vec![rebuilt.clone()],
+            ..Default::default()
+        };
+        let rounds = std::env::temp_dir().join("nostrdizer-rebuilt-rounds.json");
+        let labels = std::env::temp_dir().join("nostrdizer-rebuilt-labels.jsonl");
+        let files = MakerFiles {
+            round_history: &rounds,
+            labels: &labels,
+            ..unused_files()
+        };
+
+        let result = run_maker_round(
+            &mut maker,
+            "taker",
+            &fill(100_000),
+            &files,
+            None,
+            &mut CommitmentBlacklist::default(),
+        );
+        let history = RoundHistory::load(&rounds);
+        let _ = fs::remove_file(&rounds);
+        let _ = fs::remove_file(&labels);
+        result.unwrap();
+
+        let rebuilt_txid = rebuilt.psbt.unsigned_tx.txid();
+        assert_eq!(
+            maker.signed,
+            vec![first.psbt.unsigned_tx.txid(), rebuilt_txid]
+        );
+        // Only the CJ that can still be broadcast is recorded
+        let history = history.unwrap();
+        assert_eq!(history.confirmed_rounds("taker"), 1);
+        assert_eq!(history.rounds[0].txid, rebuilt_txid);
+    }
+
+    #[test]
+    fn test_fill_below_privacy_floor_rejected() {
+        let mut maker = MockMaker::default();
+
+        run_maker_round(
+            &mut maker,
+            "taker",
+            &fill(5_000),
+            &unused_files(),
+            None,
+            &mut CommitmentBlacklist::default(),
+        )
+        .unwrap();
+        assert!(maker.acked.is_empty());
+        assert_eq!(
+            maker.rejects,
+            vec![RejectReason::BelowPrivacyFloor {
+                amount: Amount::from_sat(5_000)
+            }]
+        );
+    }
+
+    #[test]
+    fn test_taker_sends_no_transaction() {
+        let mut maker = MockMaker::default();
+
+        run_maker_round(
+            &mut maker,
+            "taker",
+            &fill(100_000),
+            &unused_files(),
+            None,
+            &mut CommitmentBlacklist::default(),
+        )
+        .unwrap();
+        assert_eq!(maker.acked, vec!["taker".to_string()]);
+        assert_eq!(maker.sent_inputs, 1);
+        assert!(maker.rejects.is_empty());
+    }
+
+    #[test]
+    fn test_failed_auth_counted() {
+        let mut maker = MockMaker {
+            bad_auth: true,
+            ..Default::default()
+        };
+        let mut blacklist = CommitmentBlacklist::default();
+
+        for _ in 0..GreylistPolicy::default().max_failures {
+            run_maker_round(
+                &mut maker,
+                "taker",
+                &fill(100_000),
+                &unused_files(),
+                None,
+                &mut blacklist,
+            )
+            .unwrap();
+        }
+        // Inputs are not sent and the commitment is not burned for an opening that does not verify
+        assert_eq!(maker.sent_inputs, 0);
+        assert!(maker.gossiped.is_empty());
+        assert_eq!(maker.greylist.failures("taker"), 3);
+        assert!(maker.greylist.is_greylisted("taker", 1));
+    }
+
+    #[test]
+    fn test_inputs_spent_in_mempool_not_sent() {
+        let mut maker = MockMaker {
+            conflicts: vec![OutPoint::null()],
+            ..Default::default()
+        };
+
+        run_maker_round(
+            &mut maker,
+            "taker",
+            &fill(100_000),
+            &unused_files(),
+            None,
+            &mut CommitmentBlacklist::default(),
+        )
+        .unwrap();
+        assert_eq!(maker.acked, vec!["taker".to_string()]);
+        assert_eq!(maker.sent_inputs, 0);
+    }
+
+    #[test]
+    fn test_no_suitable_inputs_rejected() {
+        let mut maker = MockMaker {
+            excess_change: Some(Amount::from_sat(900_000)),
+            ..Default::default()
+        };
+
+        run_maker_round(
+            &mut maker,
+            "taker",
+            &fill(100_000),
+            &unused_files(),
+            None,
+            &mut CommitmentBlacklist::default(),
+        )
+        .unwrap();
+        assert!(maker.acked.is_empty());
+        assert_eq!(maker.rejects, vec![RejectReason::NoSuitableInputs]);
+    }
+
+    #[test]
+    fn test_fill_over_maxsize_countered() {
+        let mut config = maker_config();
+        config.maxsize = Some(Amount::from_sat(100_000));
+        config.counter_offer_margin = Some(0.05);
+        let mut maker = MockMaker {
+            config: Some(config),
+            ..Default::default()
+        };
+        let mut fill_offer = fill(103_000);
+
+        // Taker that does not take counter-offers
+        run_maker_round(
+            &mut maker,
+            "taker",
+            &fill_offer,
+            &unused_files(),
+            None,
+            &mut CommitmentBlacklist::default(),
+        )
+        .unwrap();
+        fill_offer.counter = true;
+        run_maker_round(
+            &mut maker,
+            "taker",
+            &fill_offer,
+            &unused_files(),
+            None,
+            &mut CommitmentBlacklist::default(),
+        )
+        .unwrap();
+        let counter = CounterOffer {
+            maxsize: Amount::from_sat(103_000),
+            cjfee: Amount::from_sat(1_050),
+        };
+        assert!(maker.acked.is_empty());
+        assert_eq!(
+            maker.rejects,
+            vec![
+                RejectReason::AmountOutOfRange,
+                RejectReason::CounterOffer(counter)
+            ]
+        );
+
+        // Taker fills again at the counter-offer's fee
+        fill_offer.cjfee = Some(counter.cjfee);
+        run_maker_round(
+            &mut maker,
+            "taker",
+            &fill_offer,
+            &unused_files(),
+            None,
+            &mut CommitmentBlacklist::default(),
+        )
+        .unwrap();
+        assert_eq!(maker.acked, vec!["taker".to_string()]);
+    }
+
+    #[test]
+    fn test_uninvited_taker_rejected() {
+        let mut invites = InviteStore::default();
+        let mut fill_offer = fill(50_000);
+        fill_offer.invite = Some(invites.mint("taker").unwrap());
+        let round = |maker: &mut MockMaker, taker: &str, invites: &InviteStore| {
+            run_maker_round(
+                maker,
+                taker,
+                &fill_offer,
+                &unused_files(),
+                Some(invites),
+                &mut CommitmentBlacklist::default(),
+            )
+            .unwrap();
+        };
+
+        let mut maker = MockMaker::default();
+        round(&mut maker, "other", &invites);
+        assert!(maker.acked.is_empty());
+        assert_eq!(maker.rejects, vec![RejectReason::InviteRequired]);
+
+        let mut maker = MockMaker::default();
+        round(&mut maker, "taker", &invites);
+        assert_eq!(maker.acked, vec!["taker"]);
+
+        invites.revoke("taker");
+        let mut maker = MockMaker::default();
+        round(&mut maker, "taker", &invites);
+        assert_eq!(maker.rejects, vec![RejectReason::InviteRequired]);
+    }
+
+    #[test]
+    fn test_used_commitment_burned() {
+        let mut maker = MockMaker::default();
+        let mut blacklist = CommitmentBlacklist::default();
+        let fill_offer = fill(100_000);
+        let round = |maker: &mut MockMaker, blacklist: &mut CommitmentBlacklist| {
+            run_maker_round(
+                maker,
+                "taker",
+                &fill_offer,
+                &unused_files(),
+                None,
+                blacklist,
+            )
+            .unwrap();
+        };
+
+        // Opened commitment is gossiped once
+        round(&mut maker, &mut blacklist);
+        assert_eq!(maker.gossiped, vec![fill_offer.commitment]);
+
+        // Taker can not probe the maker again with it
+        round(&mut maker, &mut blacklist);
+        assert_eq!(maker.acked, vec!["taker"]);
+        assert_eq!(maker.rejects, vec![RejectReason::CommitmentUsed]);
+        assert_eq!(maker.gossiped.len(), 1);
+    }
+}
@@ -0,0 +1,26 @@
+pub mod audit_wallet;
+pub mod backup;
+pub mod bump_fee;
+pub mod config;
+pub mod context;
+pub mod env;
+pub mod error;
+pub mod fiat;
+pub mod fidelity_bond;
+pub mod inspect;
+pub mod join_coop;
+pub mod labels;
+pub mod list_offers;
+pub mod logging;
+pub mod maker;
+pub mod paths;
+pub mod purge_data;
+pub mod retention;
+pub mod run_maker;
+pub mod send_transaction;
+#[cfg(feature = "dev-swarm")]
+pub mod swarm;
+pub mod taker;
+pub mod taker_args;
+pub mod tumble;
+pub mod version;
@@ -0,0 +1,110 @@
+use super::env::or_env_default;
+
+use anyhow::Result;
+
+use std::env;
+use std::path::PathBuf;
+
+/// Path from the flag, then the env var, then the default file name
+fn path_or_env(path: &Option<String>, var: &str, default: &str) -> PathBuf {
+    match path {
+        Some(path) => PathBuf::from(path),
+        None => PathBuf::from(env::var(var).unwrap_or_else(|_| default.to_string())),
+    }
+}
+
+/// Path of the maker status file
+pub fn status_path(status_file: &Option<String>) -> PathBuf {
+    path_or_env(status_file, "MAKER_STATUS_FILE", "maker_status.json")
+}
+
+/// Path of the maker's payout history
+pub fn payout_history_path(payout_history: &Option<String>) -> PathBuf {
+    path_or_env(payout_history, "MAKER_PAYOUT_HISTORY", "maker_payouts.json")
+}
+
+/// Path of the round history of the maker or taker
+pub fn round_history_path(round_history: &Option<String>, maker: bool) -> PathBuf {
+    match maker {
+        true => path_or_env(round_history, "MAKER_ROUND_HISTORY", "maker_rounds.json"),
+        false => path_or_env(round_history, "TAKER_ROUND_HISTORY", "taker_rounds.json"),
+    }
+}
+
+/// Path of the BIP-329 label store of the maker or taker
+pub fn labels_path(labels: &Option<String>, maker: bool) -> PathBuf {
+    match maker {
+        true => path_or_env(labels, "MAKER_LABELS", "maker_labels.jsonl"),
+        false => path_or_env(labels, "TAKER_LABELS", "taker_labels.jsonl"),
+    }
+}
+
+/// Path of the invites of an invite only maker
+pub fn invites_path(invites: &Option<String>) -> PathBuf {
+    path_or_env(invites, "MAKER_INVITES", "maker_invites.json")
+}
+
+/// Path of the maker's blacklist of used podle commitments
+pub fn commitment_blacklist_path(commitment_blacklist: &Option<String>) -> PathBuf {
+    path_or_env(
+        commitment_blacklist,
+        "MAKER_COMMITMENT_BLACKLIST",
+        "maker_commitments.json",
+    )
+}
+
+/// Path of the maker's counts of takers' failed auths
+pub fn greylist_path(greylist: &Option<String>) -> PathBuf {
+    path_or_env(greylist, "MAKER_GREYLIST", "maker_greylist.json")
+}
+
+/// Path of the keystore of the maker or taker
+pub fn keystore_path(keystore: &Option<String>, maker: bool) -> PathBuf {
+    match maker {
+        true => path_or_env(keystore, "MAKER_KEYSTORE", "maker_keystore.json"),
+        false => path_or_env(keystore, "TAKER_KEYSTORE", "taker_keystore.json"),
+    }
+}
+
+/// Path of the taker reputation file
+pub fn reputation_path(reputation: &Option<String>) -> PathBuf {
+    path_or_env(reputation, "TAKER_REPUTATION", "taker_reputation.json")
+}
+
+/// Path of the addresses makers have given the taker
+pub fn address_store_path(address_store: &Option<String>) -> PathBuf {
+    path_or_env(address_store, "TAKER_ADDRESS_STORE", "taker_addresses.json")
+}
+
+/// Path of the taker's tumble schedule
+pub fn schedule_path(schedule: &Option<String>) -> PathBuf {
+    path_or_env(schedule, "TAKER_SCHEDULE", "taker_schedule.json")
+}
+
+/// Path of the maker's wallet audit
+pub fn audit_path(audit_file: &Option<String>) -> PathBuf {
+    path_or_env(audit_file, "MAKER_AUDIT_FILE", "maker_audit.json")
+}
+
+/// File the offer book is cached in, from the flag then `TAKER_OFFER_BOOK`, the book is not cached when neither is set
+pub fn offer_book_path(offer_book: &Option<String>) -> Option<PathBuf> {
+    match offer_book {
+        Some(path) => Some(PathBuf::from(path)),
+        None => env::var("TAKER_OFFER_BOOK").ok().map(PathBuf::from),
+    }
+}
+
+/// File the taker logs rounds for the network stats in, none unless it opted in to sharing them
+pub fn stats_log_path(
+    share_stats: &Option<bool>,
+    stats_log: &Option<String>,
+) -> Result<Option<PathBuf>> {
+    if !or_env_default(share_stats, "TAKER_SHARE_STATS", false)? {
+        return Ok(None);
+    }
+    Ok(Some(path_or_env(
+        stats_log,
+        "TAKER_STATS_LOG",
+        "taker_stats.json",
+    )))
+}
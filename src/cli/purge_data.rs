@@ -0,0 +1,68 @@
+use super::context::Context;
+use super::paths::{keystore_path, reputation_path, round_history_path};
+
+use nostrdizer::types::OutPoint;
+
+use anyhow::Result;
+use clap::Args;
+use serde::{Deserialize, Serialize};
+
+use std::env;
+use std::path::{Path, PathBuf};
+
+#[derive(Args, Debug, Serialize, Deserialize)]
+pub struct PurgeDataArgs {
+    /// Stores of the maker rather than the taker
+    #[arg(long)]
+    pub maker: bool,
+    #[arg(long)]
+    pub keystore: Option<String>,
+    #[arg(long)]
+    pub round_history: Option<String>,
+    #[arg(long)]
+    pub reputation: Option<String>,
+    /// PSBT files to delete besides the co-signing ones of the taker
+    #[arg(long)]
+    pub psbt: Vec<String>,
+}
+
+/// Prunes the stores by the retention policy, then deletes commitments of spent utxos and the PSBT files
+pub fn run(args: &PurgeDataArgs, ctx: &Context) -> Result<()> {
+    let reputation_path = (!args.maker).then(|| reputation_path(&args.reputation));
+    super::retention::prune(
+        &ctx.retention,
+        &round_history_path(&args.round_history, args.maker),
+        reputation_path.as_deref(),
+    )?;
+
+    // PSBTs of the last co-signed round and any given
+    let mut psbts: Vec<PathBuf> = args.psbt.iter().map(PathBuf::from).collect();
+    if !args.maker {
+        psbts.extend(
+            ["TAKER_EXPORT_UNSIGNED", "TAKER_IMPORT_SIGNED"]
+                .iter()
+                .filter_map(|var| env::var(var).ok())
+                .map(PathBuf::from),
+        );
+    }
+    // Only a taker generates podle commitments
+    let unspent = match args.maker {
+        true => None,
+        false => {
+            let mut taker = ctx.new_taker()?;
+            let unspent = taker.get_unspent()?;
+            Some(
+                unspent
+                    .iter()
+                    .map(|utxo| OutPoint::new(utxo.txid, utxo.vout))
+                    .collect::<Vec<OutPoint>>(),
+            )
+        }
+    };
+    let psbts: Vec<&Path> = psbts.iter().map(PathBuf::as_path).collect();
+    super::retention::purge_data(
+        &keystore_path(&args.keystore, args.maker),
+        unspent.as_deref(),
+        &psbts,
+    )
+}
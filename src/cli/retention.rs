@@ -0,0 +1,56 @@
+use nostrdizer::{
+    keystore::Keystore,
+    reputation::Reputation,
+    retention::{secure_delete, RetentionPolicy},
+    transcript::RoundHistory,
+    types::OutPoint,
+};
+
+use anyhow::Result;
+use log::info;
+
+use std::path::Path;
+
+/// Prunes the round history and reputation by the policy, files are only written when something is pruned
+pub fn prune(
+    policy: &RetentionPolicy,
+    round_history_path: &Path,
+    reputation_path: Option<&Path>,
+) -> Result<()> {
+    let now = chrono::Utc::now().timestamp() as u64;
+    let mut history = RoundHistory::load(round_history_path)?;
+    let pruned = policy.prune_rounds(&mut history, now)?;
+    if pruned > 0 {
+        history.save(round_history_path)?;
+        info!("Pruned {pruned} rounds from the round history");
+    }
+    if let Some(path) = reputation_path {
+        let mut reputation = Reputation::load(path)?;
+        let pruned = policy.prune_reputation(&mut reputation, now);
+        if pruned > 0 {
+            reputation.save(path)?;
+            info!("Pruned {pruned} makers from the reputation");
+        }
+    }
+    Ok(())
+}
+
+/// Removes revealed commitments of spent utxos from the keystore and securely deletes the PSBT files
+/// The keystore is left as it is without the unspent utxos
+pub fn purge_data(
+    keystore_path: &Path,
+    unspent: Option<&[OutPoint]>,
+    psbts: &[&Path],
+) -> Result<()> {
+    if let Some(unspent) = unspent {
+        let mut keystore = Keystore::load(keystore_path)?;
+        let purged = keystore.purge_revealed(unspent);
+        keystore.save(keystore_path)?;
+        println!("Purged {purged} revealed commitments");
+    }
+    for psbt in psbts.iter().filter(|psbt| psbt.exists()) {
+        secure_delete(psbt)?;
+        println!("Deleted {}", psbt.display());
+    }
+    Ok(())
+}
@@ -0,0 +1,356 @@
+use super::context::Context;
+use super::env::{identity_index, or_env, or_env_default};
+use super::maker::MakerFiles;
+use super::paths::{
+    commitment_blacklist_path, greylist_path, invites_path, keystore_path, labels_path,
+    payout_history_path, round_history_path, status_path,
+};
+
+use nostrdizer::{
+    builder::MakerBuilder,
+    coin_selection::SelectionStrategy,
+    display,
+    greylist::{GreylistPolicy, TakerGreylist},
+    jitter::OfferJitter,
+    keystore::Keystore,
+    latency::LatencyClass,
+    payout::PayoutConfig,
+    podle::MAX_PODLE_INDEX,
+    policy::RoundPolicy,
+    standby::TAKEOVER_AFTER,
+    types::{Amount, MakerConfig, TxFeeRate},
+};
+
+use anyhow::{bail, Result};
+use clap::Args;
+use serde::{Deserialize, Serialize};
+
+use std::env;
+use std::str::FromStr;
+
+#[derive(Args, Debug, Serialize, Deserialize)]
+pub struct RunMakerArgs {
+    #[arg(long)]
+    pub abs_fee: Option<u64>,
+    #[arg(long)]
+    pub rel_fee: Option<f64>,
+    #[arg(long)]
+    pub minsize: Option<u64>,
+    #[arg(long)]
+    pub maxsize: Option<u64>,
+    /// Max sats of the maker's balance that enter one CJ
+    #[arg(long)]
+    pub max_per_round: Option<u64>,
+    #[arg(long)]
+    pub will_broadcast: Option<bool>,
+    /// Min mining fee rate in sat/vB
+    #[arg(long)]
+    pub min_fee_rate: Option<f32>,
+    /// Min number of equal valued outputs in a CJ the maker will sign
+    #[arg(long)]
+    pub min_participants: Option<usize>,
+    /// Add own unconfirmed utxos to CJs
+    #[arg(long)]
+    pub allow_unconfirmed: Option<bool>,
+    /// Max change, as a ratio of the fill amount, the maker's inputs may leave
+    #[arg(long)]
+    pub max_change_ratio: Option<f64>,
+    /// Ratio over maxsize, up to 0.1, the maker counters fills up to at its fee raised by the ratio
+    #[arg(long)]
+    pub counter_offer_margin: Option<f64>,
+    /// How quickly the maker answers (clearnet-fast, clearnet, tor-slow), takers wait longer for slow makers
+    #[arg(long)]
+    pub latency_class: Option<String>,
+    /// Unix time offers can be filled from, announced ahead for liquidity that is not ready yet
+    #[arg(long)]
+    pub valid_from: Option<u64>,
+    /// Highest podle index accepted, lower lets each taker utxo into fewer rounds with the maker
+    #[arg(long)]
+    pub max_podle_index: Option<u8>,
+    /// How utxos are picked: least-change, largest-first, random or avoid-linking, defaults to least-change
+    #[arg(long)]
+    pub coin_selection: Option<String>,
+    /// Spend utxos worth the fill to within dust without change, the difference goes to the mining fee
+    #[arg(long)]
+    pub avoid_change: Option<bool>,
+    /// Sats of change too little to track, it goes to the mining fee rather than a change output
+    #[arg(long)]
+    pub donate_change_below: Option<u64>,
+    /// Sats of the smallest CJ output the maker mixes
+    #[arg(long)]
+    pub min_round_amount: Option<u64>,
+    /// Most outputs near dust a CJ the maker signs may have
+    #[arg(long)]
+    pub max_dust_outputs: Option<usize>,
+    /// Sats an output may be off the CJ output value and still count as a mixing output
+    #[arg(long)]
+    pub uniformity_tolerance: Option<u64>,
+    /// Most seconds offers are held back before they are published, so makers restarting together do not publish together
+    #[arg(long)]
+    pub offer_delay: Option<u64>,
+    /// Most seconds each offer refresh comes early or late
+    #[arg(long)]
+    pub offer_refresh_spread: Option<u64>,
+    /// Most seconds between publishing the maker's relative and absolute offers
+    #[arg(long)]
+    pub offer_stagger: Option<u64>,
+    /// Mining fee rate in sat/vB the maker contributes for its inputs and outputs
+    #[arg(long)]
+    pub txfee_rate: Option<f32>,
+    /// Max vbytes the maker contributes mining fee for
+    #[arg(long)]
+    pub txfee_max_vbytes: Option<usize>,
+    /// File the maker nostr keys are kept in
+    #[arg(long)]
+    pub keystore: Option<String>,
+    /// Advertise a key of the keystore for messages to be encrypted to, rotated apart from the nostr identity
+    #[arg(long)]
+    pub encryption_key: Option<bool>,
+    /// Derive the nostr identity from the wallet so a wallet backup restores it
+    #[arg(long)]
+    pub wallet_identity: Option<bool>,
+    /// Index of the identity derived from the wallet
+    #[arg(long)]
+    pub identity_index: Option<u32>,
+    /// File the maker writes its status to
+    #[arg(long)]
+    pub status_file: Option<String>,
+    /// Cold address earned fees are swept to
+    #[arg(long)]
+    pub payout_address: Option<String>,
+    /// Sats of earned fees to sweep at once
+    #[arg(long)]
+    pub payout_threshold: Option<u64>,
+    /// Min seconds between sweeps
+    #[arg(long)]
+    pub payout_interval: Option<u64>,
+    /// File earned fees and sweeps are recorded in
+    #[arg(long)]
+    pub payout_history: Option<String>,
+    /// File rounds takers confirmed are recorded in
+    #[arg(long)]
+    pub round_history: Option<String>,
+    /// File BIP-329 labels of the maker's CJs are kept in
+    #[arg(long)]
+    pub labels: Option<String>,
+    /// Only take fills from takers with an invite token minted from the invites file
+    #[arg(long)]
+    pub invite_only: Option<bool>,
+    /// File the invite secret and revoked takers are kept in
+    #[arg(long)]
+    pub invites: Option<String>,
+    /// Stay silent while a primary sharing the keystore heartbeats, take over when its presence stops
+    #[arg(long)]
+    pub standby: Option<bool>,
+    /// Seconds without a heartbeat of the primary before the standby takes over
+    #[arg(long)]
+    pub takeover_after: Option<u64>,
+    /// Replicate keystore commitments and round history to the relays each round for a standby
+    #[arg(long)]
+    pub replicate: Option<bool>,
+    /// File podle commitments opened in rounds or gossiped by other makers are kept in
+    #[arg(long)]
+    pub commitment_blacklist: Option<String>,
+    /// Failed auths a taker is greylisted after, its fills are ignored until the cooldown is over
+    #[arg(long)]
+    pub greylist_after: Option<u32>,
+    /// Seconds fills from a greylisted taker are ignored
+    #[arg(long)]
+    pub greylist_cooldown: Option<u64>,
+    /// File failed auths of takers are counted in
+    #[arg(long)]
+    pub greylist: Option<String>,
+}
+
+/// Sets up the maker from the flags, env and defaults, takes over from a primary when standing by, then runs rounds
+pub fn run(args: &RunMakerArgs, ctx: &Context) -> Result<()> {
+    let config = maker_config(args, ctx)?;
+
+    // Earned fees are only swept when a payout address is set
+    let payouts = match or_env(&args.payout_address, "MAKER_PAYOUT_ADDRESS")? {
+        Some(address) => {
+            let threshold =
+                or_env_default(&args.payout_threshold, "MAKER_PAYOUT_THRESHOLD", 100_000)?;
+            let interval = or_env_default(&args.payout_interval, "MAKER_PAYOUT_INTERVAL", 86_400)?;
+            Some((
+                PayoutConfig::new(&address, ctx.network, Amount::from_sat(threshold), interval)?,
+                payout_history_path(&args.payout_history),
+            ))
+        }
+        None => None,
+    };
+
+    // Maker identity is kept between runs so offers can be cleaned up
+    let keystore_path = keystore_path(&args.keystore, true);
+    let priv_key = match or_env_default(&args.wallet_identity, "MAKER_WALLET_IDENTITY", false)? {
+        true if ctx.priv_key.is_some() => {
+            bail!("A private key can not be given with the wallet identity")
+        }
+        true => Some(ctx.wallet_nostr_key(identity_index(&args.identity_index)?)?),
+        false => ctx.priv_key.clone(),
+    };
+    let mut keystore = Keystore::load(&keystore_path)?;
+    // A replaced key is kept so its offers are still deleted
+    let priv_key = keystore.priv_key(priv_key);
+    keystore.save(&keystore_path)?;
+
+    let mut maker = MakerBuilder::new()
+        .priv_key(priv_key)
+        .relays(&ctx.relay_urls)
+        .offer_relays(&ctx.offer_relay_urls)
+        .blockchain(ctx.blockchain_config.clone())
+        .config(config)
+        .build()?;
+
+    let round_history_path = round_history_path(&args.round_history, true);
+    if or_env_default(&args.standby, "MAKER_STANDBY", false)? {
+        let takeover_after =
+            or_env_default(&args.takeover_after, "MAKER_TAKEOVER_AFTER", TAKEOVER_AFTER)?;
+        super::maker::wait_as_standby(
+            &mut maker,
+            takeover_after,
+            &keystore_path,
+            &round_history_path,
+        )?;
+        keystore = Keystore::load(&keystore_path)?;
+    }
+    let replicate = or_env_default(&args.replicate, "MAKER_REPLICATE", false)?;
+
+    if or_env_default(&args.encryption_key, "MAKER_ENCRYPTION_KEY", false)? {
+        maker.encryption.secret_key = Some(keystore.encryption_key()?);
+    }
+    let now = chrono::Utc::now().timestamp() as u64;
+    if let Some(bond) = keystore.fidelity_bond(now) {
+        maker.fidelity_bond = Some(bond.proof(&maker.identity.public_key_str)?);
+        println!(
+            "Advertising the fidelity bond of {} locked until {}",
+            display::sats(bond.amount),
+            display::timestamp(bond.locktime as u64)
+        );
+    }
+
+    // Remove offers left by keys this maker used before
+    let stale = maker.delete_stale_offers(&keystore.previous_identities()?)?;
+    keystore.remove_previous(&stale)?;
+    keystore.save(&keystore_path)?;
+
+    let invite_only = or_env_default(&args.invite_only, "MAKER_INVITE_ONLY", false)?;
+    let invites_path = invites_path(&args.invites);
+    let greylist_path = greylist_path(&args.greylist);
+    maker.greylist = TakerGreylist::load(&greylist_path)?;
+
+    let status_path = status_path(&args.status_file);
+    super::logging::watch_level(&status_path);
+    let files = MakerFiles {
+        status: &status_path,
+        payouts: &payouts,
+        round_history: &round_history_path,
+        labels: &labels_path(&args.labels, true),
+        invites: invite_only.then_some(invites_path.as_path()),
+        replica_keystore: replicate.then_some(keystore_path.as_path()),
+        blacklist: &commitment_blacklist_path(&args.commitment_blacklist),
+        greylist: &greylist_path,
+    };
+    super::maker::run_maker(&mut maker, &files, &ctx.retention)
+}
+
+/// Offer, round policy and publishing settings of the maker from the flags, env and defaults
+fn maker_config(args: &RunMakerArgs, ctx: &Context) -> Result<MakerConfig> {
+    let max_podle_index = or_env(&args.max_podle_index, "MAKER_MAX_PODLE_INDEX")?;
+    if let Some(max_podle_index) = max_podle_index {
+        if max_podle_index > MAX_PODLE_INDEX {
+            bail!("Max podle index can be at most {MAX_PODLE_INDEX}, takers never commit higher");
+        }
+    }
+
+    // Rounds not worth the maker's liquidity are turned down
+    let mut policy = RoundPolicy::default();
+    if let Some(min_amount) = or_env(&args.min_round_amount, "MAKER_MIN_ROUND_AMOUNT")? {
+        policy.min_amount = Amount::from_sat(min_amount);
+    }
+    if let Some(max_dust_outputs) = or_env(&args.max_dust_outputs, "MAKER_MAX_DUST_OUTPUTS")? {
+        policy.max_dust_outputs = max_dust_outputs;
+    }
+    if let Some(tolerance) = or_env(&args.uniformity_tolerance, "MAKER_UNIFORMITY_TOLERANCE")? {
+        policy.uniformity_tolerance = Amount::from_sat(tolerance);
+    }
+
+    // Offers are published at random times so the book does not show makers that started together
+    let mut offer_jitter = OfferJitter::default();
+    if let Some(max_delay) = or_env(&args.offer_delay, "MAKER_OFFER_DELAY")? {
+        offer_jitter.max_delay = max_delay;
+    }
+    if let Some(refresh_spread) = or_env(&args.offer_refresh_spread, "MAKER_OFFER_REFRESH_SPREAD")?
+    {
+        offer_jitter.refresh_spread = refresh_spread;
+    }
+    if let Some(max_stagger) = or_env(&args.offer_stagger, "MAKER_OFFER_STAGGER")? {
+        offer_jitter.max_stagger = max_stagger;
+    }
+
+    // Takers probing with openings that do not verify have their fills ignored for a while
+    let mut greylist = GreylistPolicy::default();
+    if let Some(max_failures) = or_env(&args.greylist_after, "MAKER_GREYLIST_AFTER")? {
+        greylist.max_failures = max_failures;
+    }
+    if let Some(cooldown) = or_env(&args.greylist_cooldown, "MAKER_GREYLIST_COOLDOWN")? {
+        greylist.cooldown = cooldown;
+    }
+
+    // Maker only contributes to the mining fee when a fee rate is set
+    let txfee_rate = match or_env(&args.txfee_rate, "MAKER_TXFEE_RATE")? {
+        Some(fee_rate) => Some(TxFeeRate {
+            fee_rate,
+            max_vbytes: or_env_default(&args.txfee_max_vbytes, "MAKER_TXFEE_MAX_VBYTES", 1000)?,
+        }),
+        None => None,
+    };
+
+    Ok(MakerConfig {
+        rel_fee: or_env(&args.rel_fee, "MAKER_REL_FEE")?
+            .or(ctx.maker.rel_fee)
+            .unwrap_or(0.0),
+        abs_fee: Amount::from_sat(
+            or_env(&args.abs_fee, "MAKER_ABS_FEE")?
+                .or(ctx.maker.abs_fee)
+                .unwrap_or(0),
+        ),
+        minsize: Amount::from_sat(
+            or_env(&args.minsize, "MAKER_MINSIZE")?
+                .or(ctx.maker.minsize)
+                .unwrap_or(5000),
+        ),
+        maxsize: or_env(&args.maxsize, "MAKER_MAXSIZE")?
+            .or(ctx.maker.maxsize)
+            .map(Amount::from_sat),
+        max_per_round: or_env(&args.max_per_round, "MAKER_MAX_PER_ROUND")?.map(Amount::from_sat),
+        will_broadcast: or_env_default(&args.will_broadcast, "WILL_BROADCAST", true)?,
+        min_fee_rate: or_env(&args.min_fee_rate, "MAKER_MIN_FEE_RATE")?,
+        min_participants: or_env(&args.min_participants, "MAKER_MIN_PARTICIPANTS")?,
+        allow_unconfirmed: or_env(&args.allow_unconfirmed, "MAKER_ALLOW_UNCONFIRMED")?
+            .or(ctx.maker.allow_unconfirmed)
+            .unwrap_or(false),
+        max_change_ratio: or_env(&args.max_change_ratio, "MAKER_MAX_CHANGE_RATIO")?,
+        txfee_rate,
+        avoid_change: or_env_default(&args.avoid_change, "MAKER_AVOID_CHANGE", false)?,
+        donate_change_below: or_env(&args.donate_change_below, "MAKER_DONATE_CHANGE_BELOW")?
+            .map(Amount::from_sat),
+        policy,
+        publish_quorum: ctx.publish_quorum.clone(),
+        counter_offer_margin: or_env(&args.counter_offer_margin, "MAKER_COUNTER_OFFER_MARGIN")?,
+        latency_class: or_env::<String>(&args.latency_class, "MAKER_LATENCY_CLASS")?
+            .map(|latency_class| LatencyClass::from_str(&latency_class))
+            .transpose()?,
+        relay_auth: ctx.relay_auth.clone(),
+        valid_from: or_env(&args.valid_from, "MAKER_VALID_FROM")?,
+        max_podle_index,
+        coin_selection: match or_env::<String>(&args.coin_selection, "MAKER_COIN_SELECTION")?
+            .or_else(|| ctx.maker.coin_selection.clone())
+        {
+            Some(coin_selection) => SelectionStrategy::from_str(&coin_selection)?,
+            None => SelectionStrategy::default(),
+        },
+        offer_jitter,
+        greylist,
+    })
+}
@@ -0,0 +1,223 @@
+use super::context::Context;
+use super::env::{allow_clusters, or_env, or_env_default};
+use super::paths::{
+    address_store_path, keystore_path, labels_path, offer_book_path, reputation_path,
+    round_history_path, stats_log_path,
+};
+use super::taker::TakerFiles;
+use super::taker_args::TakerArgs;
+
+use nostrdizer::{
+    cosign::CoSigning,
+    maker_selection::MakerSelection,
+    types::{Amount, InputLimits, Payment},
+};
+
+use anyhow::{bail, Result};
+use clap::Args;
+use rand::{thread_rng, Rng};
+use serde::{Deserialize, Serialize};
+
+use std::path::PathBuf;
+use std::str::FromStr;
+
+#[derive(Args, Debug, Serialize, Deserialize)]
+pub struct SendTransactionArgs {
+    #[arg(short, long, required_unless_present = "sweep")]
+    pub send_amount: Option<u64>,
+    /// Send every eligible utxo with no change output, fees are taken out of the send amount
+    #[arg(long, conflicts_with = "send_amount")]
+    pub sweep: bool,
+    #[arg(long)]
+    pub number_of_makers: Option<usize>,
+    /// File addresses makers have given are kept in
+    #[arg(long)]
+    pub address_store: Option<String>,
+    /// File rounds makers confirmed are recorded in
+    #[arg(long)]
+    pub round_history: Option<String>,
+    /// File podle commitments of the taker's utxos are kept in
+    #[arg(long)]
+    pub keystore: Option<String>,
+    /// File BIP-329 labels of the taker's CJs are kept in
+    #[arg(long)]
+    pub labels: Option<String>,
+    /// Only fill makers that have published online presence
+    #[arg(long)]
+    pub online_only: Option<bool>,
+    /// Share the final transaction with makers so makers that broadcast also do
+    #[arg(long)]
+    pub share_final_tx: Option<bool>,
+    /// Makers to fill on top of number of makers, to stand in for makers that do not send inputs
+    #[arg(long)]
+    pub spare_makers: Option<usize>,
+    /// File response times and outcomes of makers are kept in
+    #[arg(long)]
+    pub reputation: Option<String>,
+    /// Fill more than one maker of a suspected sybil cluster
+    #[arg(long)]
+    pub allow_clusters: Option<bool>,
+    /// File the offer book is cached in, so only offers that changed since are fetched
+    #[arg(long)]
+    pub offer_book: Option<String>,
+    /// Fill makers just under the send amount and take their counter-offers within the fee limits
+    #[arg(long)]
+    pub accept_counter_offers: Option<bool>,
+    /// Split change in two outputs of random value when both are worth spending
+    #[arg(long)]
+    pub split_change: Option<bool>,
+    /// External address the CJ also pays, making it a payment round, a silent payment address is paid at an output derived from the CJ's inputs
+    #[arg(long)]
+    pub pay_to: Option<String>,
+    /// Sats paid to the pay-to address
+    #[arg(long)]
+    pub pay_amount: Option<u64>,
+    /// Wait for fee estimates to drop under the max fee rate and retry a round aborted on a fee spike
+    #[arg(long)]
+    pub wait_for_fees: Option<bool>,
+    /// Invite token sent in fills, for makers of a private pool
+    #[arg(long)]
+    pub invite: Option<String>,
+    /// File the maker-signed transaction is written to as a base64 PSBT before the taker signs
+    #[arg(long)]
+    pub export_unsigned: Option<String>,
+    /// File the transaction is read back from once other tools signed it, only signatures may be added
+    #[arg(long)]
+    pub import_signed: Option<String>,
+    /// Directory the signed transaction of each round is archived to as base64 PSBT version 0 and 2
+    #[arg(long)]
+    pub archive_dir: Option<String>,
+    /// Most inputs taken from one maker
+    #[arg(long)]
+    pub max_inputs_per_maker: Option<usize>,
+    /// Most inputs taken from all makers of the round together
+    #[arg(long)]
+    pub max_total_inputs: Option<usize>,
+    /// Nostr key of a taker co-funding the round, experimental
+    #[arg(long)]
+    pub coop_partner: Option<String>,
+    /// Blocks the CJ should confirm within, the fee rate is estimated for it
+    #[arg(long)]
+    pub conf_target: Option<u16>,
+    /// Spend coins of several sources together when no one source covers the round, linking them
+    #[arg(long)]
+    pub link_sources: Option<bool>,
+    /// Order makers without a fidelity bond are filled in: cheapest, random or fee-weighted
+    #[arg(long)]
+    pub maker_selection: Option<String>,
+    /// Share anonymous stats of completed rounds with the network, once a day
+    #[arg(long)]
+    pub share_stats: Option<bool>,
+    /// File rounds not yet in the shared stats are kept in
+    #[arg(long)]
+    pub stats_log: Option<String>,
+    #[command(flatten)]
+    pub taker: TakerArgs,
+}
+
+/// Sets up the taker from the flags, env and defaults, then sends the CJ
+pub fn run(args: &SendTransactionArgs, ctx: &Context) -> Result<()> {
+    let mut taker = ctx.new_taker()?;
+    args.taker.configure(&mut taker, ctx)?;
+    taker.config.publish_quorum = ctx.publish_quorum.clone();
+    taker.order_book.trust.weights = ctx.relay_trust.clone();
+    taker.order_book.one_per_cluster = !allow_clusters(&args.allow_clusters)?;
+    taker.order_book.online_only = or_env_default(&args.online_only, "TAKER_ONLINE_ONLY", false)?;
+    taker.config.share_final_tx =
+        or_env_default(&args.share_final_tx, "TAKER_SHARE_FINAL_TX", false)?;
+    taker.config.spare_makers = or_env_default(&args.spare_makers, "TAKER_SPARE_MAKERS", 0)?;
+    taker.config.accept_counter_offers = or_env_default(
+        &args.accept_counter_offers,
+        "TAKER_ACCEPT_COUNTER_OFFERS",
+        false,
+    )?;
+    taker.config.split_change = or_env_default(&args.split_change, "TAKER_SPLIT_CHANGE", false)?;
+    let pay_to = or_env(&args.pay_to, "TAKER_PAY_TO")?;
+    let pay_amount = or_env(&args.pay_amount, "TAKER_PAY_AMOUNT")?;
+    taker.config.payment = match (pay_to, pay_amount) {
+        (Some(address), Some(amount)) => Some(Payment::new(
+            &address,
+            Amount::from_sat(amount),
+            ctx.network,
+        )?),
+        (None, None) => None,
+        _ => bail!("A payment needs both --pay-to and --pay-amount"),
+    };
+    taker.config.wait_for_fees = or_env_default(&args.wait_for_fees, "TAKER_WAIT_FOR_FEES", false)?;
+    taker.config.invite = or_env(&args.invite, "TAKER_INVITE")?;
+    taker.config.coop_partner = or_env(&args.coop_partner, "TAKER_COOP_PARTNER")?;
+    taker.config.conf_target = or_env_default(&args.conf_target, "TAKER_CONF_TARGET", 1)?;
+    taker.config.link_sources = or_env_default(&args.link_sources, "TAKER_LINK_SOURCES", false)?;
+    taker.config.sweep = args.sweep;
+    if args.sweep && taker.config.coop_partner.is_some() {
+        bail!("A co-op round can not be a sweep");
+    }
+    // The partner sends no share of its input keys to derive the output with
+    let silent_payment = taker
+        .config
+        .payment
+        .as_ref()
+        .and_then(|payment| payment.silent_payment());
+    if silent_payment.is_some() && taker.config.coop_partner.is_some() {
+        bail!("A co-op round can not pay a silent payment address");
+    }
+    taker.config.fiat = ctx.fiat.clone();
+    taker.config.cosigning = CoSigning {
+        export_unsigned: or_env(&args.export_unsigned, "TAKER_EXPORT_UNSIGNED")?.map(PathBuf::from),
+        import_signed: or_env(&args.import_signed, "TAKER_IMPORT_SIGNED")?.map(PathBuf::from),
+    };
+    taker.config.maker_selection =
+        match or_env::<String>(&args.maker_selection, "TAKER_MAKER_SELECTION")? {
+            Some(maker_selection) => MakerSelection::from_str(&maker_selection)?,
+            None => MakerSelection::default(),
+        };
+    taker.config.archive_dir = or_env(&args.archive_dir, "TAKER_ARCHIVE_DIR")?.map(PathBuf::from);
+    taker.config.input_limits = InputLimits {
+        per_maker: or_env(&args.max_inputs_per_maker, "TAKER_MAX_INPUTS_PER_MAKER")?,
+        total: or_env(&args.max_total_inputs, "TAKER_MAX_TOTAL_INPUTS")?,
+    };
+
+    let number_of_makers = match args.number_of_makers {
+        Some(num) if num < taker.config.minium_makers => bail!(
+            "Number of makers is under the min makers of {}",
+            taker.config.minium_makers
+        ),
+        Some(num) => num,
+        None => {
+            let mut rng = thread_rng();
+            rng.gen_range(3..9).max(taker.config.minium_makers)
+        }
+    };
+
+    let round_history_path = round_history_path(&args.round_history, false);
+    let reputation_path = reputation_path(&args.reputation);
+    super::retention::prune(&ctx.retention, &round_history_path, Some(&reputation_path))?;
+
+    let offer_book = offer_book_path(&args.offer_book);
+    if let Some(path) = &offer_book {
+        taker.order_book.load_cache(path)?;
+    }
+    let stats_log = stats_log_path(&args.share_stats, &args.stats_log)?;
+    let files = TakerFiles {
+        address_store: &address_store_path(&args.address_store),
+        round_history: &round_history_path,
+        reputation: &reputation_path,
+        keystore: &keystore_path(&args.keystore, false),
+        labels: &labels_path(&args.labels, false),
+        stats_log: stats_log.as_deref(),
+    };
+    // A sweep works out the send amount from the balance
+    let result = super::taker::send_transaction(
+        &mut taker,
+        Amount::from_sat(args.send_amount.unwrap_or_default()),
+        number_of_makers,
+        &files,
+        &mut |_| Ok(()),
+    );
+    // Offers fetched are cached even when the round fails, a retry only fetches what changed
+    if let Some(path) = &offer_book {
+        taker.order_book.save_cache(path)?;
+    }
+    result?;
+    Ok(())
+}
@@ -0,0 +1,239 @@
+use super::context::Context;
+use super::taker::TakerFiles;
+
+use nostrdizer::{
+    bitcoincore::regtest,
+    display,
+    errors::Error as NostrdizerError,
+    taker::Taker,
+    types::{Amount, BitcoinCoreCredentials, Network},
+};
+
+use anyhow::{bail, Result};
+use clap::Args;
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+use std::env;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Seconds the makers have to publish their offers
+const OFFER_TIMEOUT: u64 = 60;
+
+/// Makers and rounds of a local regtest swarm
+#[derive(Debug, Clone)]
+pub struct SwarmConfig {
+    pub makers: usize,
+    pub rounds: usize,
+    pub send_amount: Amount,
+    /// Sent to each maker wallet before the makers start
+    pub fund_amount: Amount,
+    /// Directory the maker keystores, status files and logs are kept in
+    pub dir: PathBuf,
+}
+
+#[derive(Args, Debug, Serialize, Deserialize)]
+pub struct SwarmArgs {
+    /// Number of makers, defaults to 3
+    #[arg(long)]
+    pub makers: Option<usize>,
+    /// Number of taker rounds, defaults to 1
+    #[arg(long)]
+    pub rounds: Option<usize>,
+    /// Sats sent in each round, defaults to 100,000
+    #[arg(long)]
+    pub send_amount: Option<u64>,
+    /// Sats sent to each maker wallet, defaults to 1,000,000
+    #[arg(long)]
+    pub fund_amount: Option<u64>,
+    /// Directory maker files and logs are kept in, defaults to swarm
+    #[arg(long)]
+    pub swarm_dir: Option<String>,
+}
+
+/// Runs the swarm on regtest with the config from the flags and defaults
+pub fn run(args: &SwarmArgs, ctx: &Context) -> Result<()> {
+    if ctx.network != Network::Regtest {
+        bail!("The swarm only runs on regtest");
+    }
+    let config = SwarmConfig {
+        makers: args.makers.unwrap_or(3),
+        rounds: args.rounds.unwrap_or(1),
+        send_amount: Amount::from_sat(args.send_amount.unwrap_or(100_000)),
+        fund_amount: Amount::from_sat(args.fund_amount.unwrap_or(1_000_000)),
+        dir: PathBuf::from(args.swarm_dir.as_deref().unwrap_or("swarm")),
+    };
+    let taker_creds = ctx
+        .core_credentials("The swarm needs a bitcoin core node")?
+        .clone();
+    let mut taker = ctx.new_taker()?;
+    run_swarm(&mut taker, &taker_creds, &ctx.relay_urls, &config)
+}
+
+/// Running maker processes, killed when dropped so an error does not leave them running
+struct Swarm {
+    makers: Vec<Child>,
+}
+
+impl Drop for Swarm {
+    fn drop(&mut self) {
+        for maker in self.makers.iter_mut() {
+            if let Err(err) = maker.kill() {
+                debug!("Could not stop maker {}: {err:?}", maker.id());
+            }
+            let _ = maker.wait();
+        }
+    }
+}
+
+pub fn maker_wallet(index: usize) -> String {
+    format!("swarm-maker-{index}")
+}
+
+/// Credentials of a maker wallet on the same node as the taker
+pub fn maker_credentials(taker: &BitcoinCoreCredentials, index: usize) -> BitcoinCoreCredentials {
+    BitcoinCoreCredentials {
+        rpc_url: taker.rpc_url.clone(),
+        wallet_name: maker_wallet(index),
+        rpc_username: taker.rpc_username.clone(),
+        rpc_password: taker.rpc_password.clone(),
+        network: Network::Regtest,
+        create_wallet: true,
+        wallet_passphrase: None,
+    }
+}
+
+/// Arguments a maker of the swarm is run with, each maker has its own wallet and files
+pub fn maker_args(dir: &Path, index: usize) -> Vec<String> {
+    let file = |name: &str| {
+        dir.join(format!("maker-{index}-{name}"))
+            .to_string_lossy()
+            .to_string()
+    };
+    vec![
+        "--wallet".to_string(),
+        maker_wallet(index),
+        "--create-wallet".to_string(),
+        "run-maker".to_string(),
+        "--keystore".to_string(),
+        file("keystore.json"),
+        "--status-file".to_string(),
+        file("status.json"),
+        "--round-history".to_string(),
+        file("rounds.json"),
+        "--labels".to_string(),
+        file("labels.jsonl"),
+    ]
+}
+
+/// Funds and starts makers against the local node and relays, then runs taker rounds with them
+pub fn run_swarm(
+    taker: &mut Taker,
+    taker_creds: &BitcoinCoreCredentials,
+    relays: &[String],
+    config: &SwarmConfig,
+) -> Result<()> {
+    fs::create_dir_all(&config.dir)?;
+
+    let maker_creds: Vec<BitcoinCoreCredentials> = (0..config.makers)
+        .map(|index| maker_credentials(taker_creds, index))
+        .collect();
+    let maker_clients = maker_creds
+        .iter()
+        .map(regtest::wallet_client)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Taker keeps enough to send every round after funding the makers
+    let taker_amount = config
+        .send_amount
+        .checked_mul(2 * config.rounds as u64)
+        .ok_or(NostrdizerError::AmountOverflow)?;
+    regtest::fund(&taker.rpc_client, &maker_clients, config.fund_amount)?;
+    regtest::ensure_balance(&taker.rpc_client, taker_amount)?;
+    println!(
+        "Funded {} makers with {}",
+        config.makers,
+        display::sats(config.fund_amount)
+    );
+
+    let exe = env::current_exe()?;
+    let mut swarm = Swarm { makers: vec![] };
+    for index in 0..config.makers {
+        let log = File::create(config.dir.join(format!("maker-{index}.log")))?;
+        let maker = Command::new(&exe)
+            .args(maker_args(&config.dir, index))
+            .env("NETWORK", "regtest")
+            .env("RPC_URL", &taker_creds.rpc_url)
+            .env("NOSTR_RELAYS", serde_json::to_string(relays)?)
+            .env_remove("NOSTR_OFFER_RELAYS")
+            .env_remove("WALLET_PASSPHRASE")
+            .stdout(Stdio::from(log.try_clone()?))
+            .stderr(Stdio::from(log))
+            .spawn()?;
+        debug!("Started maker {index} as process {}", maker.id());
+        swarm.makers.push(maker);
+    }
+
+    let started = Instant::now();
+    while taker.get_offers()?.len() < config.makers {
+        if started.elapsed() > Duration::from_secs(OFFER_TIMEOUT) {
+            bail!(
+                "Makers did not publish offers within {} seconds, see the logs in {}",
+                OFFER_TIMEOUT,
+                config.dir.display()
+            );
+        }
+        thread::sleep(Duration::from_secs(2));
+    }
+    println!("{} makers are publishing offers", config.makers);
+
+    for round in 1..=config.rounds {
+        println!("Round {round} of {}", config.rounds);
+        let files = TakerFiles {
+            address_store: &config.dir.join("taker-addresses.json"),
+            round_history: &config.dir.join("taker-rounds.json"),
+            reputation: &config.dir.join("taker-reputation.json"),
+            keystore: &config.dir.join("taker-keystore.json"),
+            labels: &config.dir.join("taker-labels.jsonl"),
+            // Regtest rounds are not the network's
+            stats_log: None,
+        };
+        super::taker::send_transaction(
+            taker,
+            config.send_amount,
+            config.makers,
+            &files,
+            &mut |_| Ok(()),
+        )?;
+        // Confirms the CJ so its outputs can go into the next round
+        regtest::mine(&taker.rpc_client, 1)?;
+    }
+
+    drop(swarm);
+    println!("Swarm done, maker logs are in {}", config.dir.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_makers_do_not_share_files() {
+        let dir = PathBuf::from("swarm");
+        let first = maker_args(&dir, 0);
+        let second = maker_args(&dir, 1);
+
+        assert_eq!(first[1], "swarm-maker-0");
+        for (first, second) in first.iter().zip(&second) {
+            if first.starts_with("swarm/") {
+                assert_ne!(first, second);
+            }
+        }
+        assert!(first.contains(&"swarm/maker-0-keystore.json".to_string()));
+    }
+}
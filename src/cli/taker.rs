@@ -0,0 +1,1204 @@
+#[cfg(test)]
+pub(crate) mod mock;
+mod ops;
+
+pub use ops::{RoundBuilder, TakerNetwork, TakerOps, TakerSession, TakerState, TakerWallet};
+
+use nostrdizer::{
+    address_store::AddressStore,
+    bitcoincore::utils::{self, Replacement},
+    coop,
+    cosign::{self, CoSigning},
+    display,
+    errors::Error as NostrdizerError,
+    keystore::Keystore,
+    labels::LabelStore,
+    latency,
+    maker_selection::MakerSelection,
+    psbt_v2,
+    reputation::Reputation,
+    stats::{NetworkTotals, RoundSample, StatsLog, STATS_INTERVAL},
+    taker::Taker,
+    transcript::{Completion, RoundHistory, RoundRecord},
+    types::{
+        Amount, BitcoinCoreCredentials, BitcoinTransaction, CoopJoin, IoAuth, NostrdizerOffer,
+        OutPoint, PartiallySignedTransaction, Txid,
+    },
+};
+
+use anyhow::{Context, Result};
+use log::{debug, warn};
+
+use std::collections::HashSet;
+use std::io;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+/// Seconds between fee estimates while waiting for fees to drop
+const FEE_POLL_SECS: u64 = 60;
+
+pub fn list_unspent(taker: &mut dyn TakerWallet) -> Result<()> {
+    println!("{}", taker.unspent()?);
+    Ok(())
+}
+
+pub fn get_eligible_balance(taker: &mut dyn TakerWallet) -> Result<()> {
+    println!("{}", display::sats_and_btc(taker.get_eligible_balance()?));
+    Ok(())
+}
+
+/// Lists offers, then upcoming offers that can not be filled yet, then the makers with offers ranked by how
+/// reliable they have been
+pub fn list_offers(taker: &mut dyn TakerNetwork, reputation_path: &Path) -> Result<()> {
+    let offers = taker.get_offers()?;
+    let now = chrono::Utc::now().timestamp() as u64;
+    let (current, upcoming): (Vec<_>, Vec<_>) =
+        offers.iter().partition(|(_, offer)| offer.is_valid(now));
+    for (i, (maker, offer)) in current.iter().enumerate() {
+        println!("Offer {} from {}: {}", i, maker, display::offer(offer));
+    }
+    if !upcoming.is_empty() {
+        println!("Upcoming offers:");
+        for (maker, offer) in upcoming {
+            println!(
+                "From {} at {}: {}",
+                maker,
+                display::timestamp(offer.valid_from().unwrap_or_default()),
+                display::offer(offer)
+            );
+        }
+    }
+
+    let reputation = Reputation::load(reputation_path)?;
+    let ranked: Vec<_> = reputation
+        .ranked()
+        .into_iter()
+        .filter(|quality| offers.iter().any(|(maker, _)| maker == &quality.maker))
+        .collect();
+    if !ranked.is_empty() {
+        println!("Makers by reliability:");
+        for quality in ranked {
+            println!("{}: {}", quality.maker, display::quality(&quality));
+        }
+    }
+    Ok(())
+}
+
+/// Totals of the stats reports takers published in the last days
+pub fn network_stats(taker: &mut dyn TakerNetwork, days: u64) -> Result<()> {
+    let now = chrono::Utc::now().timestamp() as u64;
+    let reports = taker.fetch_network_stats(now.saturating_sub(days * 24 * 60 * 60))?;
+    let totals = NetworkTotals::new(&reports);
+    println!(
+        "Network stats of the last {} days, from {} reports:",
+        days, totals.reports
+    );
+    if totals.rounds == 0 {
+        println!("No rounds reported");
+        return Ok(());
+    }
+    println!("Rounds: {}", totals.rounds);
+    if let Some(fee_ppm) = totals.median_fee_ppm {
+        println!(
+            "Median maker fee: {}",
+            display::percent(fee_ppm as f64 / 1_000_000.0)
+        );
+    }
+    if let Some(makers) = totals.median_makers {
+        println!("Median makers per round: {makers}");
+    }
+    if let Some(size) = totals.typical_size() {
+        println!("Typical round size: {} and up", display::sats(size));
+    }
+    for (size, rounds) in &totals.sizes {
+        println!(
+            "  {} and up: {} rounds",
+            display::sats(Amount::from_sat(*size)),
+            rounds
+        );
+    }
+    Ok(())
+}
+
+/// Measures relay round trip times against the protocol timeouts
+pub fn benchmark(taker: &mut dyn TakerNetwork) -> Result<()> {
+    let latencies = taker.probe_relays()?;
+    for latency in &latencies {
+        match latency.rtt {
+            Some(rtt) => println!("{}: {}", latency.relay, display::millis(rtt)),
+            None => println!("{}: no answer", latency.relay),
+        }
+    }
+
+    for step in latency::budget(&latencies)? {
+        println!(
+            "{}: about {} of {}{}",
+            step.step.name(),
+            display::millis(step.estimate),
+            display::millis(step.timeout),
+            match step.fits() {
+                true => "",
+                false => ", too slow",
+            }
+        );
+    }
+    Ok(())
+}
+
+/// Files a taker keeps across its rounds
+pub struct TakerFiles<'a> {
+    pub address_store: &'a Path,
+    pub round_history: &'a Path,
+    /// Response times and outcomes of the makers are added to the reputation, whether or not the round succeeds
+    pub reputation: &'a Path,
+    /// Podle commitments are kept in the keystore so a retried round reuses one that was not revealed
+    pub keystore: &'a Path,
+    /// Taker inputs are of one source, deposits traced through the wallet's spends or a label of the labels file
+    pub labels: &'a Path,
+    /// Rounds that complete are logged for the network stats when there is a stats log, reported once a period passed
+    pub stats_log: Option<&'a Path>,
+}
+
+/// Sends `send_amount` in a CJ with `number_of_makers` makers
+/// A round aborted on a fee spike is retried once fees drop when the taker waits for fees
+/// `on_broadcast` is called with the txid as soon as the CJ is broadcast, steps after it only warn when they fail
+/// Returns the txid of the CJ
+pub fn send_transaction(
+    taker: &mut dyn TakerOps,
+    send_amount: Amount,
+    number_of_makers: usize,
+    files: &TakerFiles,
+    on_broadcast: &mut dyn FnMut(Txid) -> Result<()>,
+) -> Result<Txid> {
+    *taker.reputation() = Reputation::load(files.reputation)?;
+    *taker.keystore() = Keystore::load(files.keystore)?;
+    // Labelled utxos are only spent with utxos of the same label
+    *taker.labels() = LabelStore::load(files.labels)?;
+    let result = loop {
+        let result = run_round(taker, send_amount, number_of_makers, files, on_broadcast);
+        // Each attempt is a round of its own, with the session relays of the makers it fills
+        taker.end_round();
+        match result {
+            Err(err) if taker.config().wait_for_fees && is_fee_spike(&err) => wait_for_fees(taker),
+            result => break result,
+        }
+    };
+    if let Err(err) = taker.reputation().save(files.reputation) {
+        warn!("Could not save the reputation of makers: {err}");
+    }
+    result
+}
+
+fn is_fee_spike(err: &anyhow::Error) -> bool {
+    matches!(
+        err.downcast_ref::<NostrdizerError>(),
+        Some(NostrdizerError::FeeSpike(..))
+    )
+}
+
+/// Polls fee estimates until they are under the max fee rate
+fn wait_for_fees(taker: &mut dyn TakerOps) {
+    while let Err(err) = taker.check_fees() {
+        println!("{err}, waiting for fees to drop...");
+        thread::sleep(Duration::from_secs(FEE_POLL_SECS));
+    }
+}
+
+/// Aborts the sessions with makers if fees spiked over the max fee rate since the round started
+/// The round stops before the taker reveals more of itself for a CJ it would not sign
+fn abort_on_fee_spike(taker: &mut dyn TakerOps, makers: &[NostrdizerOffer]) -> Result<()> {
+    if let Err(err) = taker.check_fees() {
+        println!("{err}, aborting the round");
+        taker.send_abort(makers)?;
+        return Err(err.into());
+    }
+    Ok(())
+}
+
+/// Runs one attempt of the round, the steps of the protocol in order
+fn run_round(
+    taker: &mut dyn TakerOps,
+    send_amount: Amount,
+    number_of_makers: usize,
+    files: &TakerFiles,
+    on_broadcast: &mut dyn FnMut(Txid) -> Result<()>,
+) -> Result<Txid> {
+    let send_amount = round_amount(taker, send_amount, number_of_makers)?;
+    println!(
+        "Looking for offers to send {} with {} peers.",
+        display::sats(send_amount),
+        number_of_makers
+    );
+
+    // Check to make sure taker has sufficient balance
+    if taker.get_eligible_balance()? < send_amount {
+        return Err(NostrdizerError::InsufficientFunds.into());
+    }
+    taker.check_fees()?;
+
+    let matching_peers = taker.get_matching_offers(send_amount)?;
+
+    if matching_peers.is_empty() {
+        return Err(NostrdizerError::NoMatchingOffers.into());
+    }
+
+    // Relays too slow for the protocol timeouts would fail the round after the podle is revealed
+    latency::check_budget(&taker.probe_relays()?)?;
+
+    let coop = wait_for_partner(taker, send_amount)?;
+    let partner = coop.as_ref().map(|(partner, _)| partner.clone());
+    let is_partner = |maker: &str| partner.as_deref() == Some(maker);
+
+    let FilledMakers {
+        filled,
+        makers,
+        spares,
+    } = fill_makers(
+        taker,
+        send_amount,
+        number_of_makers,
+        coop.as_ref().map(|(_, join)| join),
+        matching_peers,
+    )?;
+
+    // Podle is not revealed if fees spiked while makers answered the fills
+    abort_on_fee_spike(taker, &filled)?;
+
+    // Move to the relays makers negotiate on
+    taker.connect_session_relays()?;
+
+    let peer_inputs = collect_inputs(taker, makers, spares, files)?;
+    let mut peer_inputs = keep_enough_makers(taker, filled, peer_inputs)?;
+    let makers: Vec<NostrdizerOffer> = peer_inputs.iter().map(|(o, _)| o.clone()).collect();
+    abort_on_fee_spike(taker, &makers)?;
+
+    if let Some((partner, join)) = &coop {
+        add_partner(taker, partner, join, &mut peer_inputs)?;
+    }
+    println!("Peers have sent inputs creating transaction...");
+
+    // Makers waiting on the transaction or the broadcast go back to their offers when the round is cancelled
+    let signed_psbt = match sign_round(taker, send_amount, &mut peer_inputs, &is_partner) {
+        Ok(signed_psbt) => signed_psbt,
+        Err(err) => {
+            if let Err(abort_err) = taker.send_abort(&round_makers(&peer_inputs, &is_partner)) {
+                debug!("Could not abort the sessions: {abort_err}");
+            }
+            return Err(err);
+        }
+    };
+    println!("Finalized transaction, broadcasting ...");
+
+    let (txid, final_tx) = broadcast_round(taker, signed_psbt, on_broadcast)?;
+    // The co-op partner is not a maker, it has no reputation or session to confirm
+    let makers = round_makers(&peer_inputs, &is_partner);
+    for offer in &makers {
+        taker.reputation().record_outcome(&offer.maker, true);
+    }
+
+    // Step 8: Confirm the transcript of each session (!confirm)
+    match taker.confirm_round(&makers, &final_tx) {
+        Ok(records) => {
+            if let Err(err) = record_round(files.round_history, records) {
+                warn!("Could not save the round history: {err}");
+            }
+        }
+        Err(err) => warn!("Transaction was broadcast but makers could not be confirmed: {err}"),
+    }
+
+    if let Err(err) = label_cj(files.labels, &peer_inputs, &final_tx) {
+        warn!("Could not label the CJ: {err}");
+    }
+
+    // Takers that opted in count the round in their stats, only its size bucket and fee rate are kept
+    if let Some(path) = files.stats_log {
+        let maker_fees: u64 = makers.iter().map(|offer| offer.cjfee.to_sat()).sum();
+        let sample = RoundSample::new(send_amount, Amount::from_sat(maker_fees), makers.len());
+        if let Err(err) = share_stats(taker, path, sample) {
+            warn!("Could not log the round for the network stats: {err}");
+        }
+    }
+    Ok(txid)
+}
+
+/// Amount the round sends, a sweep sends what the balance leaves once fees are taken out
+fn round_amount(
+    taker: &mut dyn TakerOps,
+    send_amount: Amount,
+    number_of_makers: usize,
+) -> Result<Amount> {
+    if !taker.config().sweep {
+        return Ok(send_amount);
+    }
+    // Fees of a sweep are of offers for the whole balance
+    let balance = taker.get_eligible_balance()?;
+    let offers = taker.get_matching_offers(balance)?;
+    let send_amount = taker.sweep_amount(&offers, number_of_makers)?;
+    println!(
+        "Sweeping {} with no change, sending {}",
+        display::sats(balance),
+        display::sats(send_amount)
+    );
+    Ok(send_amount)
+}
+
+/// A co-op partner brings its own inputs and makers, and pays its share of the fees
+fn wait_for_partner(
+    taker: &mut dyn TakerOps,
+    send_amount: Amount,
+) -> Result<Option<(String, CoopJoin)>> {
+    let partner = match taker.config().coop_partner.clone() {
+        Some(partner) => partner,
+        None => return Ok(None),
+    };
+    println!("Waiting for co-op partner {} to join ...", partner);
+    let join = taker.get_coop_join(&partner, send_amount)?;
+    Ok(Some((partner, join)))
+}
+
+/// Makers of a round that acked their fills
+struct FilledMakers {
+    /// Every maker filled, told the session is over if it does not end up in the CJ
+    filled: Vec<NostrdizerOffer>,
+    /// Makers sent auth first
+    makers: Vec<NostrdizerOffer>,
+    /// Makers sent auth in place of makers that do not send usable inputs
+    spares: Vec<NostrdizerOffer>,
+}
+
+/// Fills the makers of the round, the co-op partner's and the taker's own
+fn fill_makers(
+    taker: &mut dyn TakerOps,
+    send_amount: Amount,
+    number_of_makers: usize,
+    join: Option<&CoopJoin>,
+    mut matching_peers: Vec<NostrdizerOffer>,
+) -> Result<FilledMakers> {
+    // Spare makers are filled too, to stand in for makers that do not send usable inputs
+    let spare_makers = taker.config().spare_makers;
+    let selection = match taker.config().maker_selection {
+        MakerSelection::Cheapest => "with the lowest fee",
+        MakerSelection::Random => "at random",
+        MakerSelection::FeeWeighted => "at random, cheaper ones more often",
+    };
+    println!(
+        "Choosing {} peers {} and {} spares",
+        number_of_makers, selection, spare_makers
+    );
+    let bonded = matching_peers
+        .iter()
+        .filter(|o| taker.fidelity_bond(&o.maker).is_some())
+        .count();
+    if bonded > 0 {
+        println!("{bonded} makers have fidelity bonds, they are picked first by bond value");
+    }
+
+    // Step 2: Send fill offer (!fill)
+    // Makers the co-op partner picked are filled on top of the taker's own
+    let mut matched_offers = match join {
+        Some(join) => {
+            let mut picked: Vec<NostrdizerOffer> = matching_peers
+                .iter()
+                .filter(|o| join.makers.contains(&o.maker))
+                .cloned()
+                .collect();
+            matching_peers.retain(|o| !join.makers.contains(&o.maker));
+            match picked.is_empty() {
+                true => vec![],
+                false => taker.send_fill_offer_message(send_amount, picked.len(), &mut picked)?,
+            }
+        }
+        None => vec![],
+    };
+    let partner_makers = matched_offers.len();
+    matched_offers.append(&mut taker.send_fill_offer_message(
+        send_amount,
+        number_of_makers + spare_makers,
+        &mut matching_peers,
+    )?);
+    let number_of_makers = number_of_makers + partner_makers;
+    debug!("{:?}", matched_offers);
+
+    println!("Sent fill offers to peers");
+    for offer in &matched_offers {
+        match taker.fidelity_bond(&offer.maker) {
+            Some(bond) => println!(
+                "Round {} with maker {}, {} locked until {} in its bond, worth {}",
+                taker.maker_round_id(&offer.maker),
+                offer.maker,
+                display::sats(bond.amount),
+                display::timestamp(bond.locktime as u64),
+                display::sats(bond.value)
+            ),
+            None => println!(
+                "Round {} with maker {}",
+                taker.maker_round_id(&offer.maker),
+                offer.maker
+            ),
+        }
+    }
+
+    replace_busy_makers(taker, send_amount, &matching_peers, &mut matched_offers)?;
+    let filled = matched_offers.clone();
+    // The podle is only revealed to spares that stand in, so as few makers as possible see it
+    let spares = matched_offers.split_off(number_of_makers.min(matched_offers.len()));
+    Ok(FilledMakers {
+        filled,
+        makers: matched_offers,
+        spares,
+    })
+}
+
+/// Busy makers are replaced with the next makers of the selection
+/// Makers whose counter-offer was taken are candidates again at its fee, once
+fn replace_busy_makers(
+    taker: &mut dyn TakerOps,
+    send_amount: Amount,
+    matching_peers: &[NostrdizerOffer],
+    matched_offers: &mut Vec<NostrdizerOffer>,
+) -> Result<()> {
+    let mut tried: HashSet<String> = matched_offers.iter().map(|o| o.maker.clone()).collect();
+    let mut countered: HashSet<String> = HashSet::new();
+    let mut busy = taker.get_fill_acks(send_amount, matched_offers)?;
+    while !busy.is_empty() {
+        println!("{} makers are busy, trying others", busy.len());
+        matched_offers.retain(|o| !busy.contains(&o.maker));
+        let mut candidates: Vec<_> = matching_peers
+            .iter()
+            .filter_map(|o| match taker.counter_offer(&o.maker) {
+                Some(counter) if busy.contains(&o.maker) && !countered.contains(&o.maker) => {
+                    Some(NostrdizerOffer {
+                        cjfee: counter.cjfee,
+                        ..o.clone()
+                    })
+                }
+                _ if !tried.contains(&o.maker) => Some(o.clone()),
+                _ => None,
+            })
+            .collect();
+        if candidates.is_empty() {
+            break;
+        }
+        let mut replacements =
+            taker.send_fill_offer_message(send_amount, busy.len(), &mut candidates)?;
+        tried.extend(replacements.iter().map(|o| o.maker.clone()));
+        countered.extend(
+            replacements
+                .iter()
+                .filter(|o| taker.counter_offer(&o.maker).is_some())
+                .map(|o| o.maker.clone()),
+        );
+        busy = taker.get_fill_acks(send_amount, &replacements)?;
+        matched_offers.append(&mut replacements);
+    }
+    Ok(())
+}
+
+/// Inputs the makers of a round sent, with the makers dropped for the inputs they sent
+struct PeerInputs {
+    inputs: Vec<(NostrdizerOffer, IoAuth)>,
+    unconfirmed: Vec<(String, Vec<OutPoint>)>,
+    reusing_makers: Vec<String>,
+}
+
+/// Sends auth to the makers and reads their inputs, spares are sent auth for makers that did not send usable ones
+fn collect_inputs(
+    taker: &mut dyn TakerOps,
+    makers: Vec<NostrdizerOffer>,
+    mut spares: Vec<NostrdizerOffer>,
+    files: &TakerFiles,
+) -> Result<PeerInputs> {
+    println!("Waiting for peer inputs...");
+    let number_of_makers = makers.len();
+    let mut peer_inputs = PeerInputs {
+        inputs: vec![],
+        unconfirmed: vec![],
+        reusing_makers: vec![],
+    };
+    let mut pending = makers;
+    loop {
+        // Step 4: Send auth (!auth)
+        // Revealed commitment is saved even if sending fails, as some makers may have it
+        let sent = taker.send_auth(pending.clone());
+        taker.keystore().save(files.keystore)?;
+        sent?;
+        debug!("Sent auth");
+
+        // Step 5: Receive maker inputs (!ioauth)
+        // loops until the makers sent auth respond or time out, only makers sent auth are read
+        let mut inputs = taker.get_peer_inputs(pending.len(), pending.clone())?;
+        for offer in &pending {
+            if !inputs.iter().any(|(o, _)| o.maker == offer.maker) {
+                taker.reputation().record_outcome(&offer.maker, false);
+            }
+        }
+
+        // Counterparty inputs must be confirmed
+        for (maker, outpoints) in taker.drop_unconfirmed_inputs(&mut inputs)? {
+            println!(
+                "Dropped maker {}: {}",
+                maker,
+                NostrdizerError::UnconfirmedInputs(outpoints.clone())
+            );
+            peer_inputs.unconfirmed.push((maker, outpoints));
+        }
+
+        // Store is reloaded so addresses of rounds running in parallel are seen
+        *taker.address_store() = AddressStore::load(files.address_store)?;
+        let reusing = taker.drop_reused_addresses(&mut inputs);
+        taker.address_store().save(files.address_store)?;
+        if !reusing.is_empty() {
+            println!(
+                "Dropped makers that reused addresses: {}",
+                reusing.join(", ")
+            );
+            peer_inputs.reusing_makers.extend(reusing);
+        }
+
+        peer_inputs.inputs.append(&mut inputs);
+        let missing = number_of_makers - peer_inputs.inputs.len();
+        if missing == 0 || spares.is_empty() {
+            break;
+        }
+        pending = spares.drain(..missing.min(spares.len())).collect();
+        println!(
+            "{} makers did not send usable inputs, trying {} spares",
+            missing,
+            pending.len()
+        );
+    }
+    Ok(peer_inputs)
+}
+
+/// Makers filled but not in the CJ are told the session is over, all of them if the round fails
+/// The round fails when fewer than the minimum makers sent usable inputs
+fn keep_enough_makers(
+    taker: &mut dyn TakerOps,
+    filled: Vec<NostrdizerOffer>,
+    peer_inputs: PeerInputs,
+) -> Result<Vec<(NostrdizerOffer, IoAuth)>> {
+    let PeerInputs {
+        inputs,
+        unconfirmed,
+        reusing_makers,
+    } = peer_inputs;
+    let enough_makers = inputs.len() >= taker.config().minium_makers;
+    let unused: Vec<NostrdizerOffer> = filled
+        .into_iter()
+        .filter(|o| !enough_makers || !inputs.iter().any(|(p, _)| p.maker == o.maker))
+        .collect();
+    taker.send_abort(&unused)?;
+
+    if !enough_makers {
+        if !unconfirmed.is_empty() {
+            let outpoints = unconfirmed.into_iter().flat_map(|(_, o)| o).collect();
+            return Err(NostrdizerError::UnconfirmedInputs(outpoints))
+                .context("Not enough makers left after dropping makers with unconfirmed inputs");
+        }
+        if !reusing_makers.is_empty() {
+            return Err(NostrdizerError::NotEnoughMakers)
+                .context("Not enough makers left after dropping makers that reused addresses");
+        }
+        return Err(NostrdizerError::MakersFailedToRespond.into());
+    }
+    Ok(inputs)
+}
+
+/// Partner joins the CJ like a maker taking no fee, paying its share from its change
+fn add_partner(
+    taker: &mut dyn TakerOps,
+    partner: &str,
+    join: &CoopJoin,
+    peer_inputs: &mut Vec<(NostrdizerOffer, IoAuth)>,
+) -> Result<()> {
+    let share = taker.coop_fee_share(peer_inputs, join)?;
+    match share > join.max_fee {
+        true => println!(
+            "Co-op partner's share of {} is over its max fee, sending without it",
+            display::sats(share)
+        ),
+        false => {
+            println!(
+                "Co-op partner {} pays {} of the fees",
+                partner,
+                display::sats(share)
+            );
+            peer_inputs.push((coop::partner_offer(partner, share), join.io_auth.clone()));
+        }
+    }
+    Ok(())
+}
+
+/// Makers of the CJ, leaving out the co-op partner
+fn round_makers(
+    peer_inputs: &[(NostrdizerOffer, IoAuth)],
+    is_partner: &dyn Fn(&str) -> bool,
+) -> Vec<NostrdizerOffer> {
+    peer_inputs
+        .iter()
+        .map(|(offer, _)| offer.clone())
+        .filter(|offer| !is_partner(offer.maker.as_str()))
+        .collect()
+}
+
+/// Broadcasts the signed CJ, makers that will broadcast may have sent it first, that is not an error
+/// The CJ is recorded as soon as it is out, nothing after fails the round so it is not sent again
+fn broadcast_round(
+    taker: &mut dyn TakerOps,
+    signed_psbt: PartiallySignedTransaction,
+    on_broadcast: &mut dyn FnMut(Txid) -> Result<()>,
+) -> Result<(Txid, BitcoinTransaction)> {
+    let final_tx = signed_psbt.clone().extract_tx();
+    // Archived before the broadcast takes the PSBT
+    let archived = match &taker.config().archive_dir {
+        Some(dir) => Some(psbt_v2::archive(&signed_psbt, dir)?),
+        None => None,
+    };
+    let txid = taker.broadcast_psbt(signed_psbt)?;
+    println!("TXID: {}", txid);
+    on_broadcast(txid)?;
+    if let Some((v0_path, v2_path)) = archived {
+        println!(
+            "Archived the PSBT to {} and {}",
+            v0_path.display(),
+            v2_path.display()
+        );
+    }
+    Ok((txid, final_tx))
+}
+
+/// Labels the outputs of the CJ not paying a maker as the taker's
+fn label_cj(
+    labels_path: &Path,
+    peer_inputs: &[(NostrdizerOffer, IoAuth)],
+    final_tx: &BitcoinTransaction,
+) -> Result<()> {
+    let maker_scripts: Vec<_> = peer_inputs
+        .iter()
+        .flat_map(|(_, io_auth)| io_auth.addresses())
+        .map(|address| address.script_pubkey())
+        .collect();
+    let mut labels = LabelStore::load(labels_path)?;
+    labels.label_cj(final_tx, |output| {
+        !maker_scripts.contains(&output.script_pubkey)
+    });
+    labels.save(labels_path)?;
+    Ok(())
+}
+
+/// Records the sessions makers confirmed in the round history
+fn record_round(round_history_path: &Path, records: Vec<RoundRecord>) -> Result<()> {
+    let mut history = RoundHistory::load(round_history_path)?;
+    for record in records {
+        let (maker, round_id) = (record.peer.clone(), record.round_id.clone());
+        let completion = record.completion;
+        history.record(record);
+        match completion {
+            Completion::Confirmed => println!(
+                "Maker {} confirmed round {}, {} confirmed rounds",
+                maker,
+                round_id,
+                history.confirmed_rounds(&maker)
+            ),
+            // A relay changed or dropped messages of the session
+            Completion::Mismatch => println!(
+                "Maker {} saw different messages in round {}",
+                maker, round_id
+            ),
+            Completion::Unconfirmed => {
+                println!("Maker {} did not confirm round {}", maker, round_id)
+            }
+        }
+    }
+    history.save(round_history_path)?;
+    Ok(())
+}
+
+/// Logs the round and reports the rounds logged once a period has passed since the last report
+/// A report that could not be published is tried again after the next round, the round itself went through
+fn share_stats(taker: &mut dyn TakerOps, stats_log_path: &Path, sample: RoundSample) -> Result<()> {
+    let now = chrono::Utc::now().timestamp() as u64;
+    let mut log = StatsLog::load(stats_log_path)?;
+    log.record(sample, now);
+    if log.is_due(now, STATS_INTERVAL) {
+        let stats = log.stats(now);
+        match taker.publish_network_stats(&stats) {
+            Ok(event_id) => {
+                println!(
+                    "Shared stats of {} rounds with the network, event {}",
+                    stats.rounds, event_id
+                );
+                log.clear(now);
+            }
+            Err(err) => println!("Could not share stats, trying again after the next round: {err}"),
+        }
+    }
+    log.save(stats_log_path)?;
+    Ok(())
+}
+
+/// Builds the CJ, has the makers sign it and signs it, makers that do not sign are dropped from peer_inputs
+fn sign_round(
+    taker: &mut dyn TakerOps,
+    send_amount: Amount,
+    peer_inputs: &mut Vec<(NostrdizerOffer, IoAuth)>,
+    is_partner: &dyn Fn(&str) -> bool,
+) -> Result<PartiallySignedTransaction> {
+    // Step 6: Send CJ transaction (!tx)
+    let mut cj = taker.create_cj(send_amount, peer_inputs)?;
+    let mut accounting = taker.round_accounting(send_amount, peer_inputs, &cj)?;
+    // Wallet's view of the taker's inputs, checked again before signing
+    let mut snapshot = taker.snapshot_inputs(&cj, peer_inputs)?;
+
+    // Step 7: Sign TX (!sig)
+    // If not all makers sign the round is rebuilt with the ones that did
+    let peer_signed_psbts = loop {
+        // Accounting regressions are caught before makers sign
+        if cfg!(debug_assertions) {
+            let violations = taker.check_invariants(&accounting, peer_inputs, &cj)?;
+            debug_assert!(
+                violations.is_empty(),
+                "Round accounting broken: {violations:?}"
+            );
+        }
+
+        // Send unsigned tx to peers
+        for (offer, _maker_input) in peer_inputs.iter() {
+            taker.send_unsigned_transaction(&offer.maker, &cj, accounting.cj_outputs())?;
+        }
+
+        println!("Waiting for peer signatures...");
+        // Wait for signed txs
+        let makers: Vec<String> = peer_inputs.iter().map(|(o, _)| o.maker.clone()).collect();
+        match taker.get_signed_peer_transaction(&makers) {
+            Ok(psbts) => break psbts,
+            Err(NostrdizerError::MakersFailedToSign(signed_makers)) => {
+                println!(
+                    "Only {} of {} makers signed, rebuilding transaction ...",
+                    signed_makers.len(),
+                    peer_inputs.len()
+                );
+                for maker in makers
+                    .iter()
+                    .filter(|m| !signed_makers.contains(m) && !is_partner(m.as_str()))
+                {
+                    taker.reputation().record_outcome(maker, false);
+                }
+                accounting = match taker.rebuild_round(&accounting, peer_inputs, &signed_makers) {
+                    Ok(accounting) => accounting,
+                    Err(err) => {
+                        // Makers that signed wait on the round until it is aborted
+                        let signed: Vec<NostrdizerOffer> = peer_inputs
+                            .iter()
+                            .filter(|(offer, _)| signed_makers.contains(&offer.maker))
+                            .map(|(offer, _)| offer.clone())
+                            .collect();
+                        taker.send_abort(&signed)?;
+                        return Err(err.into());
+                    }
+                };
+                cj = taker.create_cj(send_amount, peer_inputs)?;
+                snapshot = taker.snapshot_inputs(&cj, peer_inputs)?;
+            }
+            Err(err) => {
+                return Err(err)
+                    .with_context(|| format!("Signing failed, rounds: {}", round_summary(taker)))
+            }
+        }
+    };
+    println!("Makers have signed transaction, signing ...");
+
+    // Another app sharing the wallet spent an input or a reorg dropped one while makers signed
+    taker
+        .check_inputs(&snapshot)
+        .context("Wallet changed during the round, not signing")?;
+
+    // Combine signed tx
+    let combined_psbt = taker.combine_psbts(&peer_signed_psbts)?;
+    let combined_psbt = cosign(&taker.config().cosigning, combined_psbt)?;
+
+    // Taker Sign tx
+    if let Ok(tx_info) =
+        taker.verify_transaction(&combined_psbt, &send_amount, accounting.donated_change())
+    {
+        let price = super::fiat::price(&taker.config().fiat);
+        println!(
+            "Total fee to makers: {}",
+            display::with_fiat(tx_info.maker_fee, price.as_ref())
+        );
+        println!(
+            "Mining fee: {} at {}",
+            display::with_fiat(tx_info.mining_fee, price.as_ref()),
+            display::fee_rate(tx_info.fee_rate)
+        );
+        let bonds = peer_inputs
+            .iter()
+            .filter_map(|(offer, _)| taker.fidelity_bond(&offer.maker))
+            .fold(Amount::ZERO, |total, bond| total + bond.value);
+        println!("Fidelity bonds backing the round: {}", display::sats(bonds));
+        if tx_info.verifyed && accounting.verify(&combined_psbt, &tx_info) {
+            println!("Transaction passed verification, signing ...");
+            return Ok(taker.sign_psbt(combined_psbt)?);
+        }
+    }
+    Err(NostrdizerError::TransactionNotVerified).with_context(|| {
+        format!(
+            "Transaction could not be verified, rounds: {}",
+            round_summary(taker)
+        )
+    })
+}
+
+/// Co-funds the round of the lead taker, experimental
+/// The cheapest makers for send_amount are picked for the lead to fill, the partner signs the lead's
+/// CJ once it pays its output and costs it at most max_fee
+pub fn join_coop(
+    taker: &mut Taker,
+    lead: &str,
+    send_amount: Amount,
+    max_fee: Amount,
+    number_of_makers: usize,
+) -> Result<()> {
+    let mut offers = taker.get_matching_offers(send_amount)?;
+    offers.retain(|offer| offer.maker != lead);
+    offers.sort_by_key(|offer| offer.cjfee);
+    let makers: Vec<String> = offers
+        .into_iter()
+        .take(number_of_makers)
+        .map(|offer| offer.maker)
+        .collect();
+
+    let join = CoopJoin {
+        amount: send_amount,
+        max_fee,
+        io_auth: taker.coop_io_auth(send_amount, max_fee)?,
+        makers,
+    };
+    println!(
+        "Joining the round of {} with {} makers ...",
+        lead,
+        join.makers.len()
+    );
+    taker.send_coop_join(lead, join.clone())?;
+
+    let psbt = taker
+        .get_coop_transaction(lead)
+        .context("Lead did not send the transaction")?;
+    println!("Got the transaction, signing ...");
+    let signed_psbt = taker.sign_coop_cj(psbt, &join)?;
+    taker.send_coop_signature(lead, signed_psbt)?;
+    println!("Sent signatures, the lead broadcasts the transaction");
+    Ok(())
+}
+
+/// Replaces a stuck CJ with one paying fee_rate from the taker's change
+/// A replacement makers have to sign again is written to psbt_path for them
+pub fn replace_cj(
+    creds: &BitcoinCoreCredentials,
+    txid: &Txid,
+    fee_rate: f32,
+    psbt_path: &Path,
+) -> Result<()> {
+    match utils::replace_by_fee(creds, txid, fee_rate)? {
+        Replacement::Broadcast(replacement) => {
+            println!("Replaced {txid} with {replacement} paying {fee_rate} sat/vB")
+        }
+        Replacement::Unsigned(psbt) => {
+            cosign::export(&psbt, psbt_path)?;
+            println!(
+                "Signed the replacement of {txid}, makers have to sign {} again before it is broadcast",
+                psbt_path.display()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Routes the maker-signed CJ through the co-signing files, the CJ read back may only add signatures
+fn cosign(
+    cosigning: &CoSigning,
+    psbt: PartiallySignedTransaction,
+) -> Result<PartiallySignedTransaction> {
+    if let Some(path) = &cosigning.export_unsigned {
+        cosign::export(&psbt, path)?;
+        println!("Exported the transaction to {}", path.display());
+    }
+    let path = match &cosigning.import_signed {
+        Some(path) => path,
+        None => return Ok(psbt),
+    };
+    println!(
+        "Press enter once the co-signed transaction is in {}",
+        path.display()
+    );
+    io::stdin().read_line(&mut String::new())?;
+    let imported = cosign::import(path)?;
+    cosign::check_signatures_added(&psbt, &imported)
+        .context("Not signing the imported transaction")?;
+    Ok(imported)
+}
+
+/// Round id of each maker the taker filled, to match up with maker logs
+fn round_summary(taker: &dyn TakerOps) -> String {
+    taker
+        .round_ids()
+        .iter()
+        .map(|(maker, round_id)| format!("{round_id} ({maker})"))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::mock::MockTaker;
+    use super::*;
+    use nostrdizer::{
+        test_utils::{io_auth, offer, psbt},
+        types::REBUILD_VERSION,
+    };
+    use std::collections::VecDeque;
+
+    /// Sends with 2 makers, `test` names the stores so tests do not share them
+    fn send(taker: &mut MockTaker, send_amount: u64, test: &str) -> Result<Txid> {
+        let path = |name: &str| std::env::temp_dir().join(format!("nostrdizer-{test}-{name}"));
+        let (address_store, rounds, reputation) = (
+            path("addresses.json"),
+            path("rounds.json"),
+            path("reputation.json"),
+        );
+        let (keystore, labels) = (path("keystore.json"), path("labels.jsonl"));
+        let files = TakerFiles {
+            address_store: &address_store,
+            round_history: &rounds,
+            reputation: &reputation,
+            keystore: &keystore,
+            labels: &labels,
+            stats_log: None,
+        };
+        let result = send_transaction(taker, Amount::from_sat(send_amount), 2, &files, &mut |_| {
+            Ok(())
+        });
+        for path in [address_store, rounds, reputation, keystore, labels] {
+            let _ = std::fs::remove_file(path);
+        }
+        result
+    }
+
+    /// Makers a, b and c on `protocol_version`, with their inputs
+    fn round_inputs(protocol_version: u16) -> Vec<(NostrdizerOffer, IoAuth)> {
+        ["a", "b", "c"]
+            .iter()
+            .enumerate()
+            .map(|(i, maker)| {
+                let offer = NostrdizerOffer {
+                    protocol_version,
+                    ..offer(maker, 0)
+                };
+                (offer, io_auth(i as u8))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_round_rebuilt_without_unsigned_maker() {
+        let mut peer_inputs = round_inputs(REBUILD_VERSION);
+        let mut taker = MockTaker::new(100_000, vec![]);
+        taker.cj = Some(psbt(&[100_000], &[50_000]));
+        taker.unsigned_makers = vec!["c".to_string()];
+        taker.wallet_changed = true;
+
+        let err = sign_round(
+            &mut taker,
+            Amount::from_sat(50_000),
+            &mut peer_inputs,
+            &|_| false,
+        )
+        .unwrap_err();
+        // Makers left signed the rebuilt CJ, the round gets as far as the wallet check
+        assert!(matches!(
+            err.downcast_ref::<NostrdizerError>(),
+            Some(NostrdizerError::InputsDrifted(_))
+        ));
+        assert_eq!(taker.unsigned_sent, vec!["a", "b", "c", "a", "b"]);
+        assert_eq!(peer_inputs.len(), 2);
+        assert_eq!(taker.reputation.quality("c").success_rate, Some(0.0));
+        assert!(taker.aborted.is_empty());
+    }
+
+    #[test]
+    fn test_rebuild_aborted_for_outdated_makers() {
+        let mut peer_inputs = round_inputs(REBUILD_VERSION);
+        peer_inputs[1].0.protocol_version = REBUILD_VERSION - 1;
+        let mut taker = MockTaker::new(100_000, vec![]);
+        taker.cj = Some(psbt(&[100_000], &[50_000]));
+        taker.unsigned_makers = vec!["c".to_string()];
+
+        let err = sign_round(
+            &mut taker,
+            Amount::from_sat(50_000),
+            &mut peer_inputs,
+            &|_| false,
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<NostrdizerError>(),
+            Some(NostrdizerError::RebuildUnsupported(makers)) if makers == &["b"]
+        ));
+        // Makers that signed are not left waiting on a CJ that is never broadcast
+        assert_eq!(taker.unsigned_sent, vec!["a", "b", "c"]);
+        assert_eq!(taker.aborted, vec!["a", "b"]);
+    }
+
+    #[test]
+    fn test_round_broadcast() {
+        let mut taker = MockTaker::new(100_000, vec![offer("a", 0), offer("b", 0)]);
+        taker.peer_inputs = vec![(offer("a", 0), io_auth(0)), (offer("b", 0), io_auth(1))];
+        taker.cj = Some(psbt(&[100_000], &[50_000]));
+
+        let txid = send(&mut taker, 50_000, "broadcast").unwrap();
+        assert_eq!(taker.broadcast, vec![txid]);
+        assert_eq!(taker.reputation.quality("a").success_rate, Some(1.0));
+        assert!(taker.aborted.is_empty());
+    }
+
+    #[test]
+    fn test_insufficient_funds() {
+        let mut taker = MockTaker::new(10_000, vec![offer("maker", 0)]);
+        let err = send(&mut taker, 50_000, "insufficient").unwrap_err();
+        assert_eq!(err.to_string(), "Insufficient funds");
+    }
+
+    #[test]
+    fn test_no_matching_offers() {
+        let mut taker = MockTaker::new(100_000, vec![]);
+        let err = send(&mut taker, 50_000, "no_offers").unwrap_err();
+        assert_eq!(err.to_string(), "There are no makers that match this order");
+    }
+
+    #[test]
+    fn test_slow_relays_fail_before_fill() {
+        let mut taker = MockTaker::new(100_000, vec![offer("a", 0), offer("b", 0)]);
+        taker.relay_rtt = Some(20_000);
+
+        // Fails before the fill, so no maker is sent auth
+        let err = send(&mut taker, 50_000, "slow_relays").unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<NostrdizerError>(),
+            Some(NostrdizerError::RelaysTooSlow(_, _, _))
+        ));
+        assert!(taker.auths.is_empty());
+
+        taker.relay_rtt = None;
+        let err = send(&mut taker, 50_000, "slow_relays").unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<NostrdizerError>(),
+            Some(NostrdizerError::NoRelayAnswered)
+        ));
+    }
+
+    #[test]
+    fn test_not_enough_confirmed_makers() {
+        let mut taker = MockTaker::new(100_000, vec![offer("a", 0), offer("b", 0)]);
+        taker.peer_inputs = vec![(offer("a", 0), io_auth(0)), (offer("b", 0), io_auth(1))];
+        taker.unconfirmed = vec!["b".to_string()];
+
+        let err = send(&mut taker, 50_000, "unconfirmed").unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<NostrdizerError>(),
+            Some(NostrdizerError::UnconfirmedInputs(_))
+        ));
+    }
+
+    #[test]
+    fn test_spare_stands_in() {
+        let offers = vec![offer("a", 0), offer("b", 0), offer("c", 0), offer("d", 0)];
+        let mut taker = MockTaker::new(100_000, offers.clone());
+        taker.config.spare_makers = 2;
+        taker.peer_inputs = offers
+            .into_iter()
+            .enumerate()
+            .map(|(i, offer)| (offer, io_auth(i as u8)))
+            .collect();
+        taker.unconfirmed = vec!["b".to_string(), "c".to_string(), "d".to_string()];
+
+        // Every maker that stood in was dropped, so the round fails and all sessions are aborted
+        let err = send(&mut taker, 50_000, "spares").unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<NostrdizerError>(),
+            Some(NostrdizerError::UnconfirmedInputs(_))
+        ));
+        // Spares are only sent auth one at a time as makers drop out
+        assert_eq!(
+            taker.auths,
+            vec![
+                vec!["a".to_string(), "b".to_string()],
+                vec!["c".to_string()],
+                vec!["d".to_string()]
+            ]
+        );
+        assert_eq!(taker.aborted, vec!["a", "b", "c", "d"]);
+    }
+
+    #[test]
+    fn test_silent_maker_recorded() {
+        let mut taker = MockTaker::new(100_000, vec![offer("a", 0), offer("b", 0)]);
+        taker.peer_inputs = vec![(offer("a", 0), io_auth(0))];
+
+        let err = send(&mut taker, 50_000, "silent").unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<NostrdizerError>(),
+            Some(NostrdizerError::MakersFailedToRespond)
+        ));
+        assert_eq!(taker.reputation.quality("b").success_rate, Some(0.0));
+        assert_eq!(taker.reputation.quality("a").sessions, 0);
+    }
+
+    #[test]
+    fn test_round_ended_after_each_attempt() {
+        let mut taker = MockTaker::new(100_000, vec![offer("a", 0), offer("b", 0)]);
+        taker.peer_inputs = vec![(offer("a", 0), io_auth(0)), (offer("b", 0), io_auth(1))];
+        taker.config.wait_for_fees = true;
+        taker.fee_estimates = VecDeque::from([5.0, 50.0]);
+
+        // The attempt fees spiked in and the one after they dropped each drop their session relays
+        assert!(send(&mut taker, 50_000, "fee_retry").is_err());
+        assert_eq!(taker.rounds_ended, 2);
+    }
+
+    #[test]
+    fn test_fee_spike_aborts_before_auth() {
+        let mut taker = MockTaker::new(100_000, vec![offer("a", 0), offer("b", 0)]);
+        taker.peer_inputs = vec![(offer("a", 0), io_auth(0)), (offer("b", 0), io_auth(1))];
+        // Fees spike over the max of 10 sat/vB while makers answer the fills
+        taker.fee_estimates = VecDeque::from([5.0, 50.0]);
+
+        let err = send(&mut taker, 50_000, "fee_spike").unwrap_err();
+        assert!(is_fee_spike(&err));
+        assert!(taker.auths.is_empty());
+        assert_eq!(taker.aborted, vec!["a", "b"]);
+
+        // No maker is filled while fees are over the max
+        taker.fee_estimates = VecDeque::from([50.0]);
+        taker.aborted.clear();
+        assert!(is_fee_spike(
+            &send(&mut taker, 50_000, "fee_spike").unwrap_err()
+        ));
+        assert!(taker.aborted.is_empty());
+    }
+
+    #[test]
+    fn test_cancelled_round_aborts_makers() {
+        let mut taker = MockTaker::new(100_000, vec![offer("a", 0), offer("b", 0)]);
+        taker.peer_inputs = vec![(offer("a", 0), io_auth(0)), (offer("b", 0), io_auth(1))];
+
+        // Makers sent inputs and wait for the transaction when building it fails
+        let err = send(&mut taker, 50_000, "cancelled").unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<NostrdizerError>(),
+            Some(NostrdizerError::InsufficientFunds)
+        ));
+        assert_eq!(taker.auths, vec![vec!["a".to_string(), "b".to_string()]]);
+        assert_eq!(taker.aborted, vec!["a", "b"]);
+    }
+}
@@ -0,0 +1,448 @@
+use super::ops::{RoundBuilder, TakerNetwork, TakerSession, TakerState, TakerWallet};
+
+use nostrdizer::{
+    address_store::AddressStore,
+    cosign::CoSigning,
+    errors::Error as NostrdizerError,
+    fees,
+    fidelity_bond::VerifiedBond,
+    invariants::Violation,
+    keystore::Keystore,
+    labels::LabelStore,
+    latency::RelayLatency,
+    maker_selection::MakerSelection,
+    publication::PublishQuorum,
+    relay_auth::RelayAuthConfig,
+    reputation::Reputation,
+    round::RoundAccounting,
+    snapshot::UtxoSnapshot,
+    stats::NetworkStats,
+    taker::rebuild_unsupported,
+    transcript::RoundRecord,
+    types::{
+        Amount, BitcoinTransaction, CJFee, CoopJoin, CounterOffer, InputLimits, IoAuth,
+        MaxMineingFee, NostrdizerOffer, Offer, OutPoint, PartiallySignedTransaction, SignedAmount,
+        TakerConfig, Txid, VerifyCJInfo,
+    },
+};
+
+use anyhow::Result;
+
+use std::collections::{HashMap, VecDeque};
+
+/// Taker that answers from fixed offers and inputs, recording what it sends
+pub(crate) struct MockTaker {
+    pub(crate) config: TakerConfig,
+    pub(crate) address_store: AddressStore,
+    pub(crate) keystore: Keystore,
+    pub(crate) reputation: Reputation,
+    pub(crate) labels: LabelStore,
+    pub(crate) round_ids: HashMap<String, String>,
+    pub(crate) balance: Amount,
+    pub(crate) offers: Vec<NostrdizerOffer>,
+    pub(crate) peer_inputs: Vec<(NostrdizerOffer, IoAuth)>,
+    /// Makers whose inputs are unconfirmed
+    pub(crate) unconfirmed: Vec<String>,
+    /// Makers sent auth, in each batch
+    pub(crate) auths: Vec<Vec<String>>,
+    pub(crate) aborted: Vec<String>,
+    /// Round trip time of the one relay
+    pub(crate) relay_rtt: Option<u64>,
+    /// Fee estimates in sat/vB, in the order the taker checks them, 1 after
+    pub(crate) fee_estimates: VecDeque<f32>,
+    /// CJ the round builds, rounds without one are cancelled when building it
+    pub(crate) cj: Option<PartiallySignedTransaction>,
+    /// Makers the CJ was sent to, in order
+    pub(crate) unsigned_sent: Vec<String>,
+    /// Makers that never sign
+    pub(crate) unsigned_makers: Vec<String>,
+    /// Whether the wallet changes while makers sign
+    pub(crate) wallet_changed: bool,
+    /// Txids broadcast
+    pub(crate) broadcast: Vec<Txid>,
+    /// Whether makers fail to confirm the round after the broadcast
+    pub(crate) confirm_fails: bool,
+    /// Stats published to the network
+    pub(crate) stats: Vec<NetworkStats>,
+    pub(crate) rounds_ended: usize,
+}
+
+impl MockTaker {
+    pub(crate) fn new(balance: u64, offers: Vec<NostrdizerOffer>) -> Self {
+        Self {
+            config: TakerConfig {
+                cj_fee: CJFee {
+                    abs_fee: Amount::from_sat(1000),
+                    rel_fee: 0.01,
+                },
+                mining_fee: MaxMineingFee {
+                    abs_fee: Amount::from_sat(2000),
+                    rel_fee: 0.05,
+                    fee_rate: 10.0,
+                },
+                minium_makers: 2,
+                allow_unconfirmed: false,
+                script_type: None,
+                share_final_tx: false,
+                spare_makers: 0,
+                publish_quorum: PublishQuorum::default(),
+                accept_counter_offers: false,
+                split_change: false,
+                payment: None,
+                wait_for_fees: false,
+                invite: None,
+                relay_auth: RelayAuthConfig::default(),
+                cosigning: CoSigning::default(),
+                archive_dir: None,
+                min_bond_value: None,
+                maker_selection: MakerSelection::default(),
+                input_limits: InputLimits::default(),
+                fiat: None,
+                coop_partner: None,
+                conf_target: 1,
+                link_sources: false,
+                sweep: false,
+            },
+            address_store: AddressStore::default(),
+            keystore: Keystore::default(),
+            labels: LabelStore::default(),
+            reputation: Reputation::default(),
+            round_ids: HashMap::new(),
+            balance: Amount::from_sat(balance),
+            offers,
+            peer_inputs: vec![],
+            unconfirmed: vec![],
+            auths: vec![],
+            aborted: vec![],
+            relay_rtt: Some(100),
+            fee_estimates: VecDeque::new(),
+            cj: None,
+            unsigned_sent: vec![],
+            unsigned_makers: vec![],
+            wallet_changed: false,
+            broadcast: vec![],
+            confirm_fails: false,
+            stats: vec![],
+            rounds_ended: 0,
+        }
+    }
+}
+
+impl TakerState for MockTaker {
+    fn config(&self) -> &TakerConfig {
+        &self.config
+    }
+
+    fn config_mut(&mut self) -> &mut TakerConfig {
+        &mut self.config
+    }
+
+    fn address_store(&mut self) -> &mut AddressStore {
+        &mut self.address_store
+    }
+
+    fn keystore(&mut self) -> &mut Keystore {
+        &mut self.keystore
+    }
+
+    fn reputation(&mut self) -> &mut Reputation {
+        &mut self.reputation
+    }
+
+    fn labels(&mut self) -> &mut LabelStore {
+        &mut self.labels
+    }
+
+    fn round_ids(&self) -> &HashMap<String, String> {
+        &self.round_ids
+    }
+
+    fn maker_round_id(&self, _maker: &str) -> &str {
+        "unknown"
+    }
+
+    fn fidelity_bond(&self, _maker: &str) -> Option<VerifiedBond> {
+        None
+    }
+
+    fn counter_offer(&self, _maker: &str) -> Option<CounterOffer> {
+        None
+    }
+}
+
+impl TakerWallet for MockTaker {
+    fn unspent(&mut self) -> Result<String, NostrdizerError> {
+        Ok("[]".to_string())
+    }
+
+    fn get_eligible_balance(&mut self) -> Result<Amount, NostrdizerError> {
+        Ok(self.balance)
+    }
+
+    fn check_fees(&mut self) -> Result<(), NostrdizerError> {
+        let estimate = self.fee_estimates.pop_front().unwrap_or(1.0);
+        fees::check_fee_estimate(&self.config, estimate)
+    }
+
+    fn snapshot_inputs(
+        &self,
+        _psbt: &PartiallySignedTransaction,
+        _maker_inputs: &[(NostrdizerOffer, IoAuth)],
+    ) -> Result<UtxoSnapshot, NostrdizerError> {
+        Ok(UtxoSnapshot::default())
+    }
+
+    fn check_inputs(&self, _snapshot: &UtxoSnapshot) -> Result<(), NostrdizerError> {
+        match self.wallet_changed {
+            true => Err(NostrdizerError::InputsDrifted(vec![])),
+            false => Ok(()),
+        }
+    }
+
+    fn sign_psbt(
+        &mut self,
+        unsigned_psbt: PartiallySignedTransaction,
+    ) -> Result<PartiallySignedTransaction, NostrdizerError> {
+        Ok(unsigned_psbt)
+    }
+
+    fn broadcast_psbt(
+        &mut self,
+        final_psbt: PartiallySignedTransaction,
+    ) -> Result<Txid, NostrdizerError> {
+        let txid = final_psbt.unsigned_tx.txid();
+        self.broadcast.push(txid);
+        Ok(txid)
+    }
+}
+
+impl TakerNetwork for MockTaker {
+    fn probe_relays(&mut self) -> Result<Vec<RelayLatency>, NostrdizerError> {
+        Ok(vec![RelayLatency {
+            relay: "wss://relay".to_string(),
+            rtt: self.relay_rtt,
+        }])
+    }
+
+    fn publish_network_stats(&mut self, stats: &NetworkStats) -> Result<String, NostrdizerError> {
+        self.stats.push(stats.clone());
+        Ok(format!("stats-{}", self.stats.len()))
+    }
+
+    fn fetch_network_stats(&mut self, since: u64) -> Result<Vec<NetworkStats>, NostrdizerError> {
+        Ok(self
+            .stats
+            .iter()
+            .filter(|stats| stats.until >= since)
+            .cloned()
+            .collect())
+    }
+
+    fn get_offers(&mut self) -> Result<Vec<(String, Offer)>, NostrdizerError> {
+        Ok(vec![])
+    }
+
+    fn get_matching_offers(
+        &mut self,
+        _send_amount: Amount,
+    ) -> Result<Vec<NostrdizerOffer>, NostrdizerError> {
+        Ok(self.offers.clone())
+    }
+}
+
+impl TakerSession for MockTaker {
+    fn send_fill_offer_message(
+        &mut self,
+        _send_amount: Amount,
+        peer_count: usize,
+        matching_offers: &mut Vec<NostrdizerOffer>,
+    ) -> Result<Vec<NostrdizerOffer>, NostrdizerError> {
+        Ok(matching_offers.iter().take(peer_count).cloned().collect())
+    }
+
+    fn get_fill_acks(
+        &mut self,
+        _send_amount: Amount,
+        _matched_offers: &[NostrdizerOffer],
+    ) -> Result<Vec<String>, NostrdizerError> {
+        Ok(vec![])
+    }
+
+    fn connect_session_relays(&mut self) -> Result<(), NostrdizerError> {
+        Ok(())
+    }
+
+    fn end_round(&mut self) {
+        self.rounds_ended += 1;
+    }
+
+    fn send_auth(&mut self, matched_offers: Vec<NostrdizerOffer>) -> Result<(), NostrdizerError> {
+        self.auths
+            .push(matched_offers.into_iter().map(|o| o.maker).collect());
+        Ok(())
+    }
+
+    fn get_peer_inputs(
+        &mut self,
+        _peer_count: usize,
+        matching_offers: Vec<NostrdizerOffer>,
+    ) -> Result<Vec<(NostrdizerOffer, IoAuth)>, NostrdizerError> {
+        Ok(self
+            .peer_inputs
+            .iter()
+            .filter(|(offer, _)| matching_offers.iter().any(|o| o.maker == offer.maker))
+            .cloned()
+            .collect())
+    }
+
+    fn send_abort(&mut self, makers: &[NostrdizerOffer]) -> Result<(), NostrdizerError> {
+        self.aborted.extend(makers.iter().map(|o| o.maker.clone()));
+        Ok(())
+    }
+
+    fn send_unsigned_transaction(
+        &mut self,
+        peer_pub_key: &str,
+        _psbt: &PartiallySignedTransaction,
+        _participants: usize,
+    ) -> Result<(), NostrdizerError> {
+        self.unsigned_sent.push(peer_pub_key.to_string());
+        Ok(())
+    }
+
+    fn get_signed_peer_transaction(
+        &mut self,
+        makers: &[String],
+    ) -> Result<Vec<PartiallySignedTransaction>, NostrdizerError> {
+        let signed: Vec<String> = makers
+            .iter()
+            .filter(|maker| !self.unsigned_makers.contains(maker))
+            .cloned()
+            .collect();
+        match signed.len() == makers.len() {
+            true => Ok(vec![]),
+            false => Err(NostrdizerError::MakersFailedToSign(signed)),
+        }
+    }
+
+    fn confirm_round(
+        &mut self,
+        _makers: &[NostrdizerOffer],
+        _final_tx: &BitcoinTransaction,
+    ) -> Result<Vec<RoundRecord>, NostrdizerError> {
+        match self.confirm_fails {
+            true => Err(NostrdizerError::MakersFailedToRespond),
+            false => Ok(vec![]),
+        }
+    }
+
+    fn get_coop_join(
+        &mut self,
+        partner: &str,
+        _send_amount: Amount,
+    ) -> Result<CoopJoin, NostrdizerError> {
+        Err(NostrdizerError::NoCoopJoin(partner.to_string()))
+    }
+}
+
+impl RoundBuilder for MockTaker {
+    /// Balance less the fees of the first `makers` offers, mining fees are left out
+    fn sweep_amount(
+        &mut self,
+        offers: &[NostrdizerOffer],
+        makers: usize,
+    ) -> Result<Amount, NostrdizerError> {
+        let maker_fees: Vec<Amount> = offers.iter().take(makers).map(|o| o.cjfee).collect();
+        fees::sweep_amount(self.balance, &maker_fees, Amount::ZERO)
+    }
+
+    fn drop_unconfirmed_inputs(
+        &self,
+        peer_inputs: &mut Vec<(NostrdizerOffer, IoAuth)>,
+    ) -> Result<Vec<(String, Vec<OutPoint>)>, NostrdizerError> {
+        let dropped = peer_inputs
+            .iter()
+            .filter(|(offer, _)| self.unconfirmed.contains(&offer.maker))
+            .map(|(offer, _)| (offer.maker.clone(), vec![OutPoint::null()]))
+            .collect();
+        peer_inputs.retain(|(offer, _)| !self.unconfirmed.contains(&offer.maker));
+        Ok(dropped)
+    }
+
+    fn drop_reused_addresses(
+        &mut self,
+        _peer_inputs: &mut Vec<(NostrdizerOffer, IoAuth)>,
+    ) -> Vec<String> {
+        vec![]
+    }
+
+    fn create_cj(
+        &mut self,
+        _send_amount: Amount,
+        _maker_inputs: &[(NostrdizerOffer, IoAuth)],
+    ) -> Result<PartiallySignedTransaction, NostrdizerError> {
+        self.cj.clone().ok_or(NostrdizerError::InsufficientFunds)
+    }
+
+    fn round_accounting(
+        &self,
+        send_amount: Amount,
+        _maker_inputs: &[(NostrdizerOffer, IoAuth)],
+        _psbt: &PartiallySignedTransaction,
+    ) -> Result<RoundAccounting, NostrdizerError> {
+        Ok(RoundAccounting::new(send_amount, vec![], 1, 1.0))
+    }
+
+    fn rebuild_round(
+        &self,
+        accounting: &RoundAccounting,
+        peer_inputs: &mut Vec<(NostrdizerOffer, IoAuth)>,
+        signed_makers: &[String],
+    ) -> Result<RoundAccounting, NostrdizerError> {
+        let outdated = rebuild_unsupported(peer_inputs, signed_makers);
+        if !outdated.is_empty() {
+            return Err(NostrdizerError::RebuildUnsupported(outdated));
+        }
+        peer_inputs.retain(|(offer, _)| signed_makers.contains(&offer.maker));
+        Ok(accounting.clone())
+    }
+
+    fn check_invariants(
+        &self,
+        _accounting: &RoundAccounting,
+        _maker_inputs: &[(NostrdizerOffer, IoAuth)],
+        _psbt: &PartiallySignedTransaction,
+    ) -> Result<Vec<Violation>, NostrdizerError> {
+        Ok(vec![])
+    }
+
+    fn combine_psbts(
+        &mut self,
+        _psbts: &[PartiallySignedTransaction],
+    ) -> Result<PartiallySignedTransaction, NostrdizerError> {
+        self.cj.clone().ok_or(NostrdizerError::InsufficientFunds)
+    }
+
+    fn verify_transaction(
+        &mut self,
+        _psbt: &PartiallySignedTransaction,
+        _send_amount: &Amount,
+        _donated_change: Amount,
+    ) -> Result<VerifyCJInfo, NostrdizerError> {
+        Ok(VerifyCJInfo {
+            mining_fee: SignedAmount::from_sat(1000),
+            maker_fee: SignedAmount::ZERO,
+            fee_rate: 1.0,
+            verifyed: true,
+            reject_reason: None,
+        })
+    }
+
+    fn coop_fee_share(
+        &self,
+        _peer_inputs: &[(NostrdizerOffer, IoAuth)],
+        _join: &CoopJoin,
+    ) -> Result<Amount, NostrdizerError> {
+        Ok(Amount::ZERO)
+    }
+}
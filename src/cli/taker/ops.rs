@@ -0,0 +1,457 @@
+use nostrdizer::{
+    address_store::AddressStore,
+    errors::Error as NostrdizerError,
+    fidelity_bond::VerifiedBond,
+    invariants::Violation,
+    keystore::Keystore,
+    labels::LabelStore,
+    latency::RelayLatency,
+    reputation::Reputation,
+    round::RoundAccounting,
+    snapshot::UtxoSnapshot,
+    stats::NetworkStats,
+    taker::Taker,
+    transcript::RoundRecord,
+    types::{
+        Amount, BitcoinTransaction, CoopJoin, CounterOffer, IoAuth, NostrdizerOffer, Offer,
+        OutPoint, PartiallySignedTransaction, TakerConfig, Txid, VerifyCJInfo,
+    },
+};
+
+use anyhow::Result;
+
+use std::collections::HashMap;
+
+/// Stores and round state of the taker
+pub trait TakerState {
+    fn config(&self) -> &TakerConfig;
+    fn config_mut(&mut self) -> &mut TakerConfig;
+    fn address_store(&mut self) -> &mut AddressStore;
+    fn keystore(&mut self) -> &mut Keystore;
+    fn reputation(&mut self) -> &mut Reputation;
+    fn labels(&mut self) -> &mut LabelStore;
+    fn round_ids(&self) -> &HashMap<String, String>;
+    fn maker_round_id(&self, maker: &str) -> &str;
+    /// Fidelity bond of a maker of the round being matched that verified
+    fn fidelity_bond(&self, maker: &str) -> Option<VerifiedBond>;
+    /// Counter-offer of maker the taker took this round
+    fn counter_offer(&self, maker: &str) -> Option<CounterOffer>;
+}
+
+/// Wallet of the taker
+pub trait TakerWallet {
+    /// Unspent utxos of the wallet formatted for display
+    fn unspent(&mut self) -> Result<String, NostrdizerError>;
+    fn get_eligible_balance(&mut self) -> Result<Amount, NostrdizerError>;
+    /// Errors when fees have spiked over the max fee rate
+    fn check_fees(&mut self) -> Result<(), NostrdizerError>;
+    fn snapshot_inputs(
+        &self,
+        psbt: &PartiallySignedTransaction,
+        maker_inputs: &[(NostrdizerOffer, IoAuth)],
+    ) -> Result<UtxoSnapshot, NostrdizerError>;
+    /// Errors when the taker's inputs changed in the wallet since the snapshot
+    fn check_inputs(&self, snapshot: &UtxoSnapshot) -> Result<(), NostrdizerError>;
+    fn sign_psbt(
+        &mut self,
+        unsigned_psbt: PartiallySignedTransaction,
+    ) -> Result<PartiallySignedTransaction, NostrdizerError>;
+    fn broadcast_psbt(
+        &mut self,
+        final_psbt: PartiallySignedTransaction,
+    ) -> Result<Txid, NostrdizerError>;
+}
+
+/// Offers and stats on the relays, outside of a round
+pub trait TakerNetwork {
+    fn probe_relays(&mut self) -> Result<Vec<RelayLatency>, NostrdizerError>;
+    /// Publishes stats of the taker's rounds from a fresh key, returns the id of the event
+    fn publish_network_stats(&mut self, stats: &NetworkStats) -> Result<String, NostrdizerError>;
+    fn fetch_network_stats(&mut self, since: u64) -> Result<Vec<NetworkStats>, NostrdizerError>;
+    fn get_offers(&mut self) -> Result<Vec<(String, Offer)>, NostrdizerError>;
+    fn get_matching_offers(
+        &mut self,
+        send_amount: Amount,
+    ) -> Result<Vec<NostrdizerOffer>, NostrdizerError>;
+}
+
+/// Messages of a round with its makers and co-op partner
+pub trait TakerSession {
+    fn send_fill_offer_message(
+        &mut self,
+        send_amount: Amount,
+        peer_count: usize,
+        matching_offers: &mut Vec<NostrdizerOffer>,
+    ) -> Result<Vec<NostrdizerOffer>, NostrdizerError>;
+    fn get_fill_acks(
+        &mut self,
+        send_amount: Amount,
+        matched_offers: &[NostrdizerOffer],
+    ) -> Result<Vec<String>, NostrdizerError>;
+    fn connect_session_relays(&mut self) -> Result<(), NostrdizerError>;
+    fn end_round(&mut self);
+    /// Generates the podle commitment and sends it to the makers
+    fn send_auth(&mut self, matched_offers: Vec<NostrdizerOffer>) -> Result<(), NostrdizerError>;
+    fn get_peer_inputs(
+        &mut self,
+        peer_count: usize,
+        matching_offers: Vec<NostrdizerOffer>,
+    ) -> Result<Vec<(NostrdizerOffer, IoAuth)>, NostrdizerError>;
+    /// Tells makers that were filled but are not in the CJ that the session is over
+    fn send_abort(&mut self, makers: &[NostrdizerOffer]) -> Result<(), NostrdizerError>;
+    fn send_unsigned_transaction(
+        &mut self,
+        peer_pub_key: &str,
+        psbt: &PartiallySignedTransaction,
+        participants: usize,
+    ) -> Result<(), NostrdizerError>;
+    fn get_signed_peer_transaction(
+        &mut self,
+        makers: &[String],
+    ) -> Result<Vec<PartiallySignedTransaction>, NostrdizerError>;
+    fn confirm_round(
+        &mut self,
+        makers: &[NostrdizerOffer],
+        final_tx: &BitcoinTransaction,
+    ) -> Result<Vec<RoundRecord>, NostrdizerError>;
+    /// Waits for the co-op partner to join the round
+    fn get_coop_join(
+        &mut self,
+        partner: &str,
+        send_amount: Amount,
+    ) -> Result<CoopJoin, NostrdizerError>;
+}
+
+/// Builds and checks the CJ from the inputs makers sent
+pub trait RoundBuilder {
+    fn sweep_amount(
+        &mut self,
+        offers: &[NostrdizerOffer],
+        makers: usize,
+    ) -> Result<Amount, NostrdizerError>;
+    fn drop_unconfirmed_inputs(
+        &self,
+        peer_inputs: &mut Vec<(NostrdizerOffer, IoAuth)>,
+    ) -> Result<Vec<(String, Vec<OutPoint>)>, NostrdizerError>;
+    fn drop_reused_addresses(
+        &mut self,
+        peer_inputs: &mut Vec<(NostrdizerOffer, IoAuth)>,
+    ) -> Vec<String>;
+    fn create_cj(
+        &mut self,
+        send_amount: Amount,
+        maker_inputs: &[(NostrdizerOffer, IoAuth)],
+    ) -> Result<PartiallySignedTransaction, NostrdizerError>;
+    fn round_accounting(
+        &self,
+        send_amount: Amount,
+        maker_inputs: &[(NostrdizerOffer, IoAuth)],
+        psbt: &PartiallySignedTransaction,
+    ) -> Result<RoundAccounting, NostrdizerError>;
+    fn rebuild_round(
+        &self,
+        accounting: &RoundAccounting,
+        peer_inputs: &mut Vec<(NostrdizerOffer, IoAuth)>,
+        signed_makers: &[String],
+    ) -> Result<RoundAccounting, NostrdizerError>;
+    /// Accounting invariants the built CJ breaks
+    fn check_invariants(
+        &self,
+        accounting: &RoundAccounting,
+        maker_inputs: &[(NostrdizerOffer, IoAuth)],
+        psbt: &PartiallySignedTransaction,
+    ) -> Result<Vec<Violation>, NostrdizerError>;
+    fn combine_psbts(
+        &mut self,
+        psbts: &[PartiallySignedTransaction],
+    ) -> Result<PartiallySignedTransaction, NostrdizerError>;
+    fn verify_transaction(
+        &mut self,
+        psbt: &PartiallySignedTransaction,
+        send_amount: &Amount,
+        donated_change: Amount,
+    ) -> Result<VerifyCJInfo, NostrdizerError>;
+    /// Partner's share of the fees, once the makers sent their inputs
+    fn coop_fee_share(
+        &self,
+        peer_inputs: &[(NostrdizerOffer, IoAuth)],
+        join: &CoopJoin,
+    ) -> Result<Amount, NostrdizerError>;
+}
+
+/// Taker the command handlers run against
+pub trait TakerOps: TakerState + TakerWallet + TakerNetwork + TakerSession + RoundBuilder {}
+
+impl<T: TakerState + TakerWallet + TakerNetwork + TakerSession + RoundBuilder> TakerOps for T {}
+
+impl TakerState for Taker {
+    fn config(&self) -> &TakerConfig {
+        &self.config
+    }
+
+    fn config_mut(&mut self) -> &mut TakerConfig {
+        &mut self.config
+    }
+
+    fn address_store(&mut self) -> &mut AddressStore {
+        &mut self.address_store
+    }
+
+    fn keystore(&mut self) -> &mut Keystore {
+        &mut self.keystore
+    }
+
+    fn reputation(&mut self) -> &mut Reputation {
+        &mut self.reputation
+    }
+
+    fn labels(&mut self) -> &mut LabelStore {
+        &mut self.labels
+    }
+
+    fn round_ids(&self) -> &HashMap<String, String> {
+        &self.round_ids
+    }
+
+    fn maker_round_id(&self, maker: &str) -> &str {
+        Taker::maker_round_id(self, maker)
+    }
+
+    fn fidelity_bond(&self, maker: &str) -> Option<VerifiedBond> {
+        self.fidelity_bonds.get(maker).copied()
+    }
+
+    fn counter_offer(&self, maker: &str) -> Option<CounterOffer> {
+        self.counter_offers.get(maker).copied()
+    }
+}
+
+impl TakerWallet for Taker {
+    fn unspent(&mut self) -> Result<String, NostrdizerError> {
+        Ok(format!("{:#?}", Taker::get_unspent(self)?))
+    }
+
+    fn get_eligible_balance(&mut self) -> Result<Amount, NostrdizerError> {
+        Taker::get_eligible_balance(self)
+    }
+
+    fn check_fees(&mut self) -> Result<(), NostrdizerError> {
+        Taker::check_fees(self)
+    }
+
+    fn snapshot_inputs(
+        &self,
+        psbt: &PartiallySignedTransaction,
+        maker_inputs: &[(NostrdizerOffer, IoAuth)],
+    ) -> Result<UtxoSnapshot, NostrdizerError> {
+        Taker::snapshot_inputs(self, psbt, maker_inputs)
+    }
+
+    fn check_inputs(&self, snapshot: &UtxoSnapshot) -> Result<(), NostrdizerError> {
+        Taker::check_inputs(self, snapshot)
+    }
+
+    fn sign_psbt(
+        &mut self,
+        unsigned_psbt: PartiallySignedTransaction,
+    ) -> Result<PartiallySignedTransaction, NostrdizerError> {
+        Taker::sign_psbt(self, unsigned_psbt)
+    }
+
+    fn broadcast_psbt(
+        &mut self,
+        final_psbt: PartiallySignedTransaction,
+    ) -> Result<Txid, NostrdizerError> {
+        Taker::broadcast_psbt(self, final_psbt)
+    }
+}
+
+impl TakerNetwork for Taker {
+    fn probe_relays(&mut self) -> Result<Vec<RelayLatency>, NostrdizerError> {
+        Taker::probe_relays(self)
+    }
+
+    fn publish_network_stats(&mut self, stats: &NetworkStats) -> Result<String, NostrdizerError> {
+        Taker::publish_network_stats(self, stats)
+    }
+
+    fn fetch_network_stats(&mut self, since: u64) -> Result<Vec<NetworkStats>, NostrdizerError> {
+        Taker::fetch_network_stats(self, since)
+    }
+
+    fn get_offers(&mut self) -> Result<Vec<(String, Offer)>, NostrdizerError> {
+        Taker::get_offers(self)
+    }
+
+    fn get_matching_offers(
+        &mut self,
+        send_amount: Amount,
+    ) -> Result<Vec<NostrdizerOffer>, NostrdizerError> {
+        Taker::get_matching_offers(self, send_amount)
+    }
+}
+
+impl TakerSession for Taker {
+    fn send_fill_offer_message(
+        &mut self,
+        send_amount: Amount,
+        peer_count: usize,
+        matching_offers: &mut Vec<NostrdizerOffer>,
+    ) -> Result<Vec<NostrdizerOffer>, NostrdizerError> {
+        Taker::send_fill_offer_message(self, send_amount, peer_count, matching_offers)
+    }
+
+    fn get_fill_acks(
+        &mut self,
+        send_amount: Amount,
+        matched_offers: &[NostrdizerOffer],
+    ) -> Result<Vec<String>, NostrdizerError> {
+        Taker::get_fill_acks(self, send_amount, matched_offers)
+    }
+
+    fn connect_session_relays(&mut self) -> Result<(), NostrdizerError> {
+        Taker::connect_session_relays(self)
+    }
+
+    fn end_round(&mut self) {
+        Taker::end_round(self)
+    }
+
+    fn send_auth(&mut self, matched_offers: Vec<NostrdizerOffer>) -> Result<(), NostrdizerError> {
+        self.reveal_commitment();
+        // Each maker gets an opening bound to its own session
+        let makers: Vec<String> = matched_offers.iter().map(|o| o.maker.clone()).collect();
+        for offer in matched_offers {
+            let auth_proof = self.generate_auth(&offer, self.auth_binding(&makers, &offer))?;
+            self.send_auth_message(auth_proof, vec![offer])?;
+        }
+        Ok(())
+    }
+
+    fn get_peer_inputs(
+        &mut self,
+        peer_count: usize,
+        matching_offers: Vec<NostrdizerOffer>,
+    ) -> Result<Vec<(NostrdizerOffer, IoAuth)>, NostrdizerError> {
+        Taker::get_peer_inputs(self, peer_count, matching_offers)
+    }
+
+    fn send_abort(&mut self, makers: &[NostrdizerOffer]) -> Result<(), NostrdizerError> {
+        Taker::send_abort(self, makers)
+    }
+
+    fn send_unsigned_transaction(
+        &mut self,
+        peer_pub_key: &str,
+        psbt: &PartiallySignedTransaction,
+        participants: usize,
+    ) -> Result<(), NostrdizerError> {
+        Taker::send_unsigned_transaction(self, peer_pub_key, psbt, participants)
+    }
+
+    fn get_signed_peer_transaction(
+        &mut self,
+        makers: &[String],
+    ) -> Result<Vec<PartiallySignedTransaction>, NostrdizerError> {
+        Taker::get_signed_peer_transaction(self, makers)
+    }
+
+    fn confirm_round(
+        &mut self,
+        makers: &[NostrdizerOffer],
+        final_tx: &BitcoinTransaction,
+    ) -> Result<Vec<RoundRecord>, NostrdizerError> {
+        Taker::confirm_round(self, makers, final_tx)
+    }
+
+    fn get_coop_join(
+        &mut self,
+        partner: &str,
+        send_amount: Amount,
+    ) -> Result<CoopJoin, NostrdizerError> {
+        Taker::get_coop_join(self, partner, send_amount)
+    }
+}
+
+impl RoundBuilder for Taker {
+    fn sweep_amount(
+        &mut self,
+        offers: &[NostrdizerOffer],
+        makers: usize,
+    ) -> Result<Amount, NostrdizerError> {
+        Taker::sweep_amount(self, offers, makers)
+    }
+
+    fn drop_unconfirmed_inputs(
+        &self,
+        peer_inputs: &mut Vec<(NostrdizerOffer, IoAuth)>,
+    ) -> Result<Vec<(String, Vec<OutPoint>)>, NostrdizerError> {
+        Taker::drop_unconfirmed_inputs(self, peer_inputs)
+    }
+
+    fn drop_reused_addresses(
+        &mut self,
+        peer_inputs: &mut Vec<(NostrdizerOffer, IoAuth)>,
+    ) -> Vec<String> {
+        Taker::drop_reused_addresses(self, peer_inputs)
+    }
+
+    fn create_cj(
+        &mut self,
+        send_amount: Amount,
+        maker_inputs: &[(NostrdizerOffer, IoAuth)],
+    ) -> Result<PartiallySignedTransaction, NostrdizerError> {
+        Taker::create_cj(self, send_amount, maker_inputs)
+    }
+
+    fn round_accounting(
+        &self,
+        send_amount: Amount,
+        maker_inputs: &[(NostrdizerOffer, IoAuth)],
+        psbt: &PartiallySignedTransaction,
+    ) -> Result<RoundAccounting, NostrdizerError> {
+        Taker::round_accounting(self, send_amount, maker_inputs, psbt)
+    }
+
+    fn rebuild_round(
+        &self,
+        accounting: &RoundAccounting,
+        peer_inputs: &mut Vec<(NostrdizerOffer, IoAuth)>,
+        signed_makers: &[String],
+    ) -> Result<RoundAccounting, NostrdizerError> {
+        Taker::rebuild_round(self, accounting, peer_inputs, signed_makers)
+    }
+
+    fn check_invariants(
+        &self,
+        accounting: &RoundAccounting,
+        maker_inputs: &[(NostrdizerOffer, IoAuth)],
+        psbt: &PartiallySignedTransaction,
+    ) -> Result<Vec<Violation>, NostrdizerError> {
+        Taker::check_invariants(self, accounting, maker_inputs, psbt)
+    }
+
+    fn combine_psbts(
+        &mut self,
+        psbts: &[PartiallySignedTransaction],
+    ) -> Result<PartiallySignedTransaction, NostrdizerError> {
+        Taker::combine_psbts(self, psbts)
+    }
+
+    fn verify_transaction(
+        &mut self,
+        psbt: &PartiallySignedTransaction,
+        send_amount: &Amount,
+        donated_change: Amount,
+    ) -> Result<VerifyCJInfo, NostrdizerError> {
+        Taker::verify_transaction(self, psbt, send_amount, donated_change)
+    }
+
+    fn coop_fee_share(
+        &self,
+        peer_inputs: &[(NostrdizerOffer, IoAuth)],
+        join: &CoopJoin,
+    ) -> Result<Amount, NostrdizerError> {
+        Taker::coop_fee_share(self, peer_inputs, join)
+    }
+}
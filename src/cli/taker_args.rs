@@ -0,0 +1,89 @@
+use super::context::Context;
+use super::env::or_env;
+
+use nostrdizer::{
+    taker::Taker,
+    types::{Amount, DescriptorType},
+};
+
+use anyhow::{bail, Result};
+use clap::Args;
+use serde::{Deserialize, Serialize};
+
+use std::str::FromStr;
+
+/// Limits of the rounds a taker sends, shared by the commands that send CJs
+#[derive(Args, Debug, Serialize, Deserialize)]
+pub struct TakerArgs {
+    /// Spend own unconfirmed utxos
+    #[arg(long)]
+    pub allow_unconfirmed: Option<bool>,
+    /// Script type all inputs of the round must be: wpkh, shwpkh or tr
+    #[arg(long)]
+    pub script_type: Option<String>,
+    /// Most sats paid to the makers of the round together
+    #[arg(long)]
+    pub max_abs_cj_fee: Option<u64>,
+    /// Most paid to the makers of the round together, as a ratio of the send amount
+    #[arg(long)]
+    pub max_rel_cj_fee: Option<f64>,
+    /// Most sats of mining fee paid
+    #[arg(long)]
+    pub max_mining_fee: Option<u64>,
+    /// Most sat/vB the CJ pays, offers are checked against the rate estimated for the conf target under it
+    #[arg(long)]
+    pub max_fee_rate: Option<f32>,
+    /// Fewest makers the round goes ahead with
+    #[arg(long)]
+    pub min_makers: Option<usize>,
+    /// Only fill makers with a fidelity bond worth at least these sats
+    #[arg(long)]
+    pub min_bond_value: Option<u64>,
+}
+
+impl TakerArgs {
+    /// Sets the limits from the flags, env, then config, the defaults are kept for those not set anywhere
+    pub fn configure(&self, taker: &mut Taker, ctx: &Context) -> Result<()> {
+        taker.config.allow_unconfirmed =
+            or_env(&self.allow_unconfirmed, "TAKER_ALLOW_UNCONFIRMED")?
+                .or(ctx.taker.allow_unconfirmed)
+                .unwrap_or(false);
+        taker.config.script_type = or_env::<String>(&self.script_type, "TAKER_SCRIPT_TYPE")?
+            .or_else(|| ctx.taker.script_type.clone())
+            .map(|script_type| DescriptorType::from_str(&script_type))
+            .transpose()?;
+        // Fee limits the CJ is verified against
+        if let Some(abs_fee) =
+            or_env(&self.max_abs_cj_fee, "TAKER_MAX_ABS_CJ_FEE")?.or(ctx.taker.max_abs_cj_fee)
+        {
+            taker.config.cj_fee.abs_fee = Amount::from_sat(abs_fee);
+        }
+        if let Some(rel_fee) =
+            or_env(&self.max_rel_cj_fee, "TAKER_MAX_REL_CJ_FEE")?.or(ctx.taker.max_rel_cj_fee)
+        {
+            if !(0.0..=1.0).contains(&rel_fee) {
+                bail!("Max relative CJ fee has to be between 0 and 1");
+            }
+            taker.config.cj_fee.rel_fee = rel_fee;
+        }
+        if let Some(abs_fee) =
+            or_env(&self.max_mining_fee, "TAKER_MAX_MINING_FEE")?.or(ctx.taker.max_mining_fee)
+        {
+            taker.config.mining_fee.abs_fee = Amount::from_sat(abs_fee);
+        }
+        if let Some(fee_rate) =
+            or_env(&self.max_fee_rate, "TAKER_MAX_FEE_RATE")?.or(ctx.taker.max_fee_rate)
+        {
+            taker.config.mining_fee.fee_rate = fee_rate;
+        }
+        if let Some(min_makers) =
+            or_env(&self.min_makers, "TAKER_MIN_MAKERS")?.or(ctx.taker.min_makers)
+        {
+            taker.config.minium_makers = min_makers;
+        }
+        taker.config.min_bond_value = or_env(&self.min_bond_value, "TAKER_MIN_BOND_VALUE")?
+            .or(ctx.taker.min_bond_value)
+            .map(Amount::from_sat);
+        Ok(())
+    }
+}
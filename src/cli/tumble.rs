@@ -0,0 +1,302 @@
+use super::context::Context;
+use super::env::{or_env, or_env_default};
+use super::paths::{
+    address_store_path, keystore_path, labels_path, reputation_path, round_history_path,
+    schedule_path, stats_log_path,
+};
+use super::taker::{send_transaction, TakerFiles, TakerOps, TakerState};
+use super::taker_args::TakerArgs;
+
+use nostrdizer::{
+    display,
+    maker_selection::MakerSelection,
+    taker::scheduler::{Schedule, ScheduleConfig},
+    types::{Amount, Network, Txid},
+};
+
+use anyhow::{bail, Result};
+use clap::Args;
+use rand::thread_rng;
+use serde::{Deserialize, Serialize};
+
+use std::env;
+use std::path::Path;
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
+
+#[derive(Args, Debug, Serialize, Deserialize)]
+pub struct TumbleArgs {
+    /// Sats paid to the destinations in all, only used to make a new schedule
+    #[arg(short, long)]
+    pub amount: Option<u64>,
+    /// Address paid a share of the amount in a round of its own, can be given more than once
+    #[arg(long)]
+    pub destination: Vec<String>,
+    /// Rounds to the wallet before any destination is paid
+    #[arg(long)]
+    pub mixing_rounds: Option<usize>,
+    /// Most makers filled in a round
+    #[arg(long)]
+    pub max_makers: Option<usize>,
+    /// Fewest seconds waited between rounds
+    #[arg(long)]
+    pub min_delay: Option<u64>,
+    /// Most seconds waited between rounds
+    #[arg(long)]
+    pub max_delay: Option<u64>,
+    /// File the schedule and its progress are kept in
+    #[arg(long)]
+    pub schedule: Option<String>,
+    /// File addresses makers have given are kept in
+    #[arg(long)]
+    pub address_store: Option<String>,
+    /// File rounds makers confirmed are recorded in
+    #[arg(long)]
+    pub round_history: Option<String>,
+    /// File podle commitments of the taker's utxos are kept in
+    #[arg(long)]
+    pub keystore: Option<String>,
+    /// File BIP-329 labels of the taker's CJs are kept in
+    #[arg(long)]
+    pub labels: Option<String>,
+    /// File response times and outcomes of makers are kept in
+    #[arg(long)]
+    pub reputation: Option<String>,
+    /// Order makers without a fidelity bond are filled in: cheapest, random or fee-weighted
+    #[arg(long)]
+    pub maker_selection: Option<String>,
+    /// Share anonymous stats of completed rounds with the network, once a day
+    #[arg(long)]
+    pub share_stats: Option<bool>,
+    /// File rounds not yet in the shared stats are kept in
+    #[arg(long)]
+    pub stats_log: Option<String>,
+    /// `--min-makers` is also the fewest makers a round of a new schedule fills
+    #[command(flatten)]
+    pub taker: TakerArgs,
+}
+
+/// Sets up the taker and the schedule config from the flags, env and defaults, then runs the schedule
+pub fn run(args: &TumbleArgs, ctx: &Context) -> Result<()> {
+    let mut taker = ctx.new_taker()?;
+    args.taker.configure(&mut taker, ctx)?;
+    taker.config.publish_quorum = ctx.publish_quorum.clone();
+    taker.order_book.trust.weights = ctx.relay_trust.clone();
+    taker.config.fiat = ctx.fiat.clone();
+    taker.config.maker_selection =
+        match or_env::<String>(&args.maker_selection, "TAKER_MAKER_SELECTION")? {
+            Some(maker_selection) => MakerSelection::from_str(&maker_selection)?,
+            None => MakerSelection::default(),
+        };
+
+    let mut destinations = args.destination.clone();
+    if destinations.is_empty() {
+        if let Ok(env_destinations) = env::var("TAKER_TUMBLE_DESTINATIONS") {
+            destinations = env_destinations.split(',').map(String::from).collect();
+        }
+    }
+    // Flags only shape a new schedule, a schedule that exists is resumed as it is
+    let config = match args.amount {
+        Some(amount) => Some(ScheduleConfig {
+            amount: Amount::from_sat(amount),
+            destinations,
+            mixing_rounds: or_env_default(&args.mixing_rounds, "TAKER_TUMBLE_MIXING_ROUNDS", 2)?,
+            // Rounds fill no fewer makers than they go ahead with
+            makers: or_env_default(&args.taker.min_makers, "TAKER_TUMBLE_MIN_MAKERS", 3)?
+                .max(taker.config.minium_makers)
+                ..=or_env_default(&args.max_makers, "TAKER_TUMBLE_MAX_MAKERS", 8)?,
+            delay: or_env_default(&args.min_delay, "TAKER_TUMBLE_MIN_DELAY", 600)?
+                ..=or_env_default(&args.max_delay, "TAKER_TUMBLE_MAX_DELAY", 3600)?,
+        }),
+        None => None,
+    };
+    let stats_log = stats_log_path(&args.share_stats, &args.stats_log)?;
+    let files = TakerFiles {
+        address_store: &address_store_path(&args.address_store),
+        round_history: &round_history_path(&args.round_history, false),
+        reputation: &reputation_path(&args.reputation),
+        keystore: &keystore_path(&args.keystore, false),
+        labels: &labels_path(&args.labels, false),
+        stats_log: stats_log.as_deref(),
+    };
+    run_schedule(
+        &mut taker,
+        config,
+        ctx.network,
+        &schedule_path(&args.schedule),
+        &files,
+    )
+}
+
+/// Runs the schedule at schedule path round by round, saving progress after each so a stopped tumble resumes
+/// A new schedule is made from config when there is none
+pub fn run_schedule(
+    taker: &mut dyn TakerOps,
+    config: Option<ScheduleConfig>,
+    network: Network,
+    schedule_path: &Path,
+    files: &TakerFiles,
+) -> Result<()> {
+    let mut schedule = match Schedule::load(schedule_path)? {
+        Some(schedule) => {
+            println!(
+                "Resuming the schedule in {}, {} of {} rounds completed",
+                schedule_path.display(),
+                schedule.completed(),
+                schedule.rounds.len()
+            );
+            schedule
+        }
+        None => {
+            let config = match config {
+                Some(config) => config,
+                None => bail!(
+                    "There is no schedule to resume, give an amount and destinations to make one"
+                ),
+            };
+            let now = chrono::Utc::now().timestamp() as u64;
+            let schedule = Schedule::generate(&config, network, now, &mut thread_rng())?;
+            schedule.save(schedule_path)?;
+            println!(
+                "Made a schedule of {} rounds in {}",
+                schedule.rounds.len(),
+                schedule_path.display()
+            );
+            schedule
+        }
+    };
+
+    while let Some(index) = schedule.next_round() {
+        let round = schedule.rounds[index].clone();
+        let now = chrono::Utc::now().timestamp() as u64;
+        let starts_at = schedule.starts_at(index);
+        if starts_at > now {
+            println!(
+                "Round {} of {} starts at {}",
+                index + 1,
+                schedule.rounds.len(),
+                display::timestamp(starts_at)
+            );
+            thread::sleep(Duration::from_secs(starts_at - now));
+        }
+        match &round.destination {
+            Some(destination) => println!(
+                "Round {}: paying {} to {destination} with {} makers",
+                index + 1,
+                display::sats(round.amount),
+                round.makers
+            ),
+            None => println!(
+                "Round {}: mixing {} with {} makers",
+                index + 1,
+                display::sats(round.amount),
+                round.makers
+            ),
+        }
+
+        // A round to a destination pays it one of the equal outputs, a mixing round sends to the wallet
+        taker.config_mut().payment = round.payment(network)?;
+        // The round is saved as completed once it is broadcast, so it is not sent again whatever fails after
+        let mut broadcast = None;
+        let result = send_transaction(
+            taker,
+            round.amount,
+            round.makers,
+            files,
+            &mut |txid: Txid| -> Result<()> {
+                broadcast = Some(txid);
+                schedule.complete(index, txid, chrono::Utc::now().timestamp() as u64);
+                Ok(schedule.save(schedule_path)?)
+            },
+        );
+        match (result, broadcast) {
+            (Ok(_), _) => (),
+            (Err(err), Some(txid)) => bail!(
+                "Round {} was broadcast as {txid}, stopping the tumble: {err}",
+                index + 1
+            ),
+            (Err(err), None) => {
+                let now = chrono::Utc::now().timestamp() as u64;
+                let retry = schedule.fail(index, now);
+                schedule.save(schedule_path)?;
+                if !retry {
+                    bail!(
+                        "Round {} failed {} times, stopping the tumble: {err}",
+                        index + 1,
+                        round.attempts + 1
+                    );
+                }
+                println!("Round {} failed, it is tried again: {err}", index + 1);
+            }
+        }
+    }
+    println!(
+        "All {} rounds of the schedule completed",
+        schedule.rounds.len()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::taker::mock::MockTaker;
+    use nostrdizer::{
+        taker::scheduler::ScheduledRound,
+        test_utils::{io_auth, offer, psbt},
+    };
+    use rand::random;
+
+    #[test]
+    fn test_broadcast_round_not_retried() {
+        let mut taker = MockTaker::new(100_000, vec![offer("a", 0), offer("b", 0)]);
+        taker.peer_inputs = vec![(offer("a", 0), io_auth(0)), (offer("b", 0), io_auth(1))];
+        taker.cj = Some(psbt(&[100_000], &[50_000]));
+        taker.confirm_fails = true;
+
+        let test = random::<u64>();
+        let path =
+            |name: &str| std::env::temp_dir().join(format!("nostrdizer-tumble-{test}-{name}"));
+        let schedule_path = path("schedule.json");
+        let schedule = Schedule {
+            rounds: vec![ScheduledRound {
+                amount: Amount::from_sat(50_000),
+                makers: 2,
+                delay: 0,
+                destination: None,
+                txid: None,
+                attempts: 0,
+            }],
+            last_round_at: 0,
+        };
+        schedule.save(&schedule_path).unwrap();
+        let stores = [
+            "addresses.json",
+            "rounds.json",
+            "reputation.json",
+            "keystore.json",
+            "labels.jsonl",
+        ]
+        .map(path);
+        let files = TakerFiles {
+            address_store: &stores[0],
+            round_history: &stores[1],
+            reputation: &stores[2],
+            keystore: &stores[3],
+            labels: &stores[4],
+            stats_log: None,
+        };
+        let result = run_schedule(&mut taker, None, Network::Regtest, &schedule_path, &files);
+        let saved = Schedule::load(&schedule_path).unwrap().unwrap();
+        for file in stores.iter().chain([&schedule_path]) {
+            let _ = std::fs::remove_file(file);
+        }
+
+        // Makers not confirming the round after the broadcast does not send it again
+        result.unwrap();
+        assert_eq!(taker.broadcast.len(), 1);
+        assert_eq!(saved.rounds[0].txid, Some(taker.broadcast[0]));
+        assert_eq!(saved.rounds[0].attempts, 0);
+    }
+}
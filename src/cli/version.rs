@@ -0,0 +1,68 @@
+use nostrdizer::{
+    build_info::{self, BACKEND, EVENT_KINDS, NIPS},
+    types::{Network, PROTOCOL_VERSION},
+};
+
+/// Build configuration printed by `--version --verbose`, so bug reports name the exact build
+pub fn report(network: Network, rpc_url: &str, relay: &str) -> String {
+    let mut features = build_info::features();
+    if cfg!(feature = "dev-swarm") {
+        features.push("dev-swarm");
+    }
+    let nips: Vec<String> = NIPS
+        .iter()
+        .map(|(nip, name)| format!("{nip} ({name})"))
+        .collect();
+    let kinds: Vec<String> = EVENT_KINDS
+        .iter()
+        .map(|(message, kind)| format!("{message} {kind}"))
+        .collect();
+
+    let mut report = format!("nostrdizer {}\n", env!("CARGO_PKG_VERSION"));
+    report.push_str(&format!("Backend: {BACKEND}\n"));
+    report.push_str(&format!("Features: {}\n", features.join(", ")));
+    report.push_str(&format!("Protocol version: {PROTOCOL_VERSION}\n"));
+    report.push_str(&format!("NIPs: {}\n", nips.join(", ")));
+    report.push_str(&format!("Event kinds: {}\n", kinds.join(", ")));
+    report.push_str(&format!(
+        "Defaults: network {network}, rpc url {rpc_url}, relay {relay}\n"
+    ));
+    report
+}
+
+/// Whether the arguments ask for the build report, clap only prints the version
+pub fn requested(args: &[String]) -> bool {
+    let version = args.iter().any(|arg| arg == "--version" || arg == "-V");
+    let verbose = args.iter().any(|arg| {
+        arg == "--verbose"
+            || (arg.starts_with("-v") && arg.trim_start_matches('-').chars().all(|c| c == 'v'))
+    });
+    version && verbose
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_names_build() {
+        let report = report(
+            Network::Regtest,
+            "http://localhost:8332",
+            "ws://localhost:7000",
+        );
+        assert!(report.contains(&format!("Backend: {BACKEND}")));
+        assert!(report.contains("fill 125"));
+        assert!(report.contains("Defaults: network regtest"));
+    }
+
+    #[test]
+    fn test_requested() {
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        assert!(requested(&args(&["nostrdizer", "--version", "--verbose"])));
+        assert!(requested(&args(&["nostrdizer", "-vv", "-V"])));
+        assert!(!requested(&args(&["nostrdizer", "--version"])));
+        // Verbose run of a command
+        assert!(!requested(&args(&["nostrdizer", "-v", "run-maker"])));
+    }
+}
@@ -1,22 +1,26 @@
 use clap::{Parser, Subcommand};
 
 use dotenvy::dotenv;
+use std::collections::HashMap;
 use std::env;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
 
-use log::{debug, error, warn, LevelFilter};
-use nostrdizer::{
-    errors::Error as NostrdizerError,
-    types::{Amount, BlockchainConfig, MakerConfig},
-};
-
-use nostrdizer::types::BitcoinCoreCredentials;
+// debug is used for BDK
+#[allow(unused)]
+use log::debug;
+use nostrdizer::types::{BitcoinCoreCredentials, BlockchainConfig};
 
-// These are used for BDK
+// RpcInfo is used for BDK
 #[allow(unused)]
 use nostrdizer::types::{Network, RpcInfo};
 use nostrdizer::{
-    maker::Maker,
-    taker::Taker,
+    config::Config,
+    fiat::{FiatConfig, PRICE_MAX_AGE},
+    publication::PublishQuorum,
+    relay_auth::RelayAuthConfig,
+    retention::RetentionPolicy,
     // These are needed for BDK
     //utils::{new_rpc_blockchain, new_wallet},
 };
@@ -25,11 +29,21 @@ use nostrdizer::{
 
 use serde::{Deserialize, Serialize};
 
-use rand::{thread_rng, Rng};
-use std::io::Write;
-
 use anyhow::{bail, Result};
 
+mod cli;
+
+use cli::{
+    config::ConfigCommand,
+    context::Context,
+    error::OutputFormat,
+    fidelity_bond::BondCommand,
+    paths::{
+        invites_path, keystore_path, labels_path, payout_history_path, reputation_path,
+        round_history_path, status_path,
+    },
+};
+
 /// CLI for nostrdizer
 #[derive(Parser, Debug, Serialize, Deserialize)]
 #[command(name = "nostrdizer")]
@@ -44,24 +58,76 @@ struct Cli {
     /// Bitcoin core rpc rpc_url
     #[arg(long, value_parser)]
     rpc_url: Option<String>,
+    /// Bitcoin core wallet, the config's when not given
     #[arg(short, long)]
-    wallet: String,
+    wallet: Option<String>,
+    /// Create the bitcoin core wallet if it does not exist
+    #[arg(long)]
+    create_wallet: bool,
+    /// Prompt for the passphrase of an encrypted wallet if WALLET_PASSPHRASE is not set
+    #[arg(long)]
+    prompt_passphrase: bool,
 
     /// Nostr relays
     #[arg(long, value_parser)]
     nostr_relays: Option<Vec<String>>,
 
+    /// Nostr relays a maker publishes offers on, defaults to the nostr relays
+    #[arg(long, value_parser)]
+    offer_relays: Option<Vec<String>>,
+
+    /// Relays that have to accept auth and transaction messages before a round goes on
+    #[arg(long, value_parser)]
+    publish_quorum: Option<usize>,
+
+    /// Relays that require NIP-42 AUTH, as url or url=hex key to authenticate with other than the nostr key
+    #[arg(long, value_parser)]
+    relay_auth: Option<Vec<String>>,
+
+    /// Currency fees are also shown in, such as USD
+    #[arg(long, value_parser)]
+    fiat_currency: Option<String>,
+
+    /// File the last fetched BTC prices are kept in, shown when prices can not be fetched
+    #[arg(long, value_parser)]
+    price_cache: Option<String>,
+
+    /// Bitcoin network: bitcoin, testnet, signet or regtest
+    #[arg(long, value_parser)]
+    network: Option<String>,
+
+    /// How errors are printed on stderr: text or json
+    #[arg(long, value_parser)]
+    output: Option<String>,
+
+    /// Log more, each -v is one level more verbose than debug
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Log less, each -q is one level quieter than debug
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    quiet: u8,
+
+    /// File logs are written to rather than stdout, a new file is started each day
+    #[arg(long, value_parser)]
+    log_file: Option<String>,
+
+    /// Days round history, reputation and log files are kept, kept forever when not set
+    #[arg(long, value_parser)]
+    retention_days: Option<u64>,
+
+    /// Bytes the round history is kept under, the oldest rounds are pruned first
+    #[arg(long, value_parser)]
+    retention_max_size: Option<u64>,
+
+    /// Config file flags and env vars override, ~/.config/nostrdizer/config.toml when not set
+    #[arg(long, value_parser)]
+    config: Option<String>,
+
     #[command(subcommand)]
     command: Commands,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct Config {
-    priv_key: Option<String>,
-    rpc_url: Option<String>,
-    nostr_relays: Option<Vec<String>>,
-}
-
 #[derive(Subcommand, Debug, Serialize, Deserialize)]
 enum Commands {
     /// Genrate a BDK wallet
@@ -74,62 +140,238 @@ enum Commands {
     /// Show wallet balance
     GetEligibleBalance,
     /// List offers
-    ListOffers,
+    ListOffers(cli::list_offers::ListOffersArgs),
+    /// Measure relay round trip times against the protocol timeouts
+    Benchmark,
     /// Send with coinjoin
-    SendTransaction {
-        #[arg(short, long)]
-        send_amount: u64,
+    SendTransaction(cli::send_transaction::SendTransactionArgs),
+    /// Run a randomized schedule of CJs paying the destinations, resumed from the schedule file after a restart
+    Tumble(cli::tumble::TumbleArgs),
+    /// Co-fund the round of another taker, paying a share of its fees, experimental
+    JoinCoop(cli::join_coop::JoinCoopArgs),
+    /// Run as maker
+    RunMaker(cli::run_maker::RunMakerArgs),
+    /// Mint the invite token of a taker for an invite only maker, share the invites file with makers of the pool
+    MintInvite {
+        /// Nostr pubkey of the taker
+        taker: String,
+        /// File the invite secret and revoked takers are kept in
         #[arg(long)]
-        number_of_makers: Option<usize>,
-        // Add: max fee
+        invites: Option<String>,
     },
-    /// Run as maker
-    RunMaker {
+    /// Revoke the invite token of a taker
+    RevokeInvite {
+        /// Nostr pubkey of the taker
+        taker: String,
+        /// File the invite secret and revoked takers are kept in
+        #[arg(long)]
+        invites: Option<String>,
+    },
+    /// Export CJ labels in BIP-329 format, to import into other wallets
+    ExportLabels {
+        /// Labels of the maker rather than the taker
+        #[arg(long)]
+        maker: bool,
+        /// File the labels are kept in
         #[arg(long)]
-        abs_fee: Option<u64>,
+        labels: Option<String>,
+        /// Round history CJs that were never labelled are taken from
         #[arg(long)]
-        rel_fee: Option<f64>,
+        round_history: Option<String>,
+        /// File the export is written to, printed when not set
         #[arg(long)]
-        minsize: Option<u64>,
+        file: Option<String>,
+    },
+    /// Import BIP-329 labels exported by another wallet
+    ImportLabels {
+        /// File of labels to import
+        file: String,
+        /// Labels of the maker rather than the taker
         #[arg(long)]
-        maxsize: Option<u64>,
+        maker: bool,
+        /// File the labels are kept in
         #[arg(long)]
-        will_broadcast: Option<bool>,
+        labels: Option<String>,
+    },
+    /// Back up reputation, podle commitments and round history to relays, encrypted to the nostr key
+    Backup {
+        /// Stores of the maker rather than the taker, the maker keystore key is used when no key is given
+        #[arg(long)]
+        maker: bool,
+        #[arg(long)]
+        reputation: Option<String>,
+        #[arg(long)]
+        keystore: Option<String>,
+        #[arg(long)]
+        round_history: Option<String>,
+    },
+    /// Merge the latest backup of the nostr key on the relays into the local stores
+    Restore {
+        /// Stores of the maker rather than the taker
+        #[arg(long)]
+        maker: bool,
+        #[arg(long)]
+        reputation: Option<String>,
+        #[arg(long)]
+        keystore: Option<String>,
+        #[arg(long)]
+        round_history: Option<String>,
+    },
+    /// Show fees the maker earned and swept
+    PayoutReport {
+        /// File earned fees and sweeps are recorded in
+        #[arg(long)]
+        payout_history: Option<String>,
+    },
+    /// Watch the maker wallet and alert on spends that are not a CJ or sweep of the maker
+    /// Run against a watch-only wallet with the maker's public descriptors to keep the keys off the machine
+    AuditWallet(cli::audit_wallet::AuditWalletArgs),
+    /// Prune stores by the retention policy and securely delete revealed commitments of spent utxos and PSBT files
+    PurgeData(cli::purge_data::PurgeDataArgs),
+    /// Bump the fee of a stuck CJ or other wallet transaction with a child spending the wallet's outputs of it
+    BumpFee(cli::bump_fee::BumpFeeArgs),
+    /// Show status of a running maker
+    MakerStatus {
+        /// File the maker writes its status to
+        #[arg(long)]
+        status_file: Option<String>,
+    },
+    /// Change the log level of a running maker: off, error, warn, info, debug or trace
+    SetLogLevel {
+        level: String,
+        /// File the maker writes its status to
+        #[arg(long)]
+        status_file: Option<String>,
+    },
+    /// Fund and run makers on local regtest wallets and send rounds with them
+    #[cfg(feature = "dev-swarm")]
+    DevSwarm(cli::swarm::SwarmArgs),
+    /// Lock coins in fidelity bonds the maker advertises, making sybil makers costly
+    FidelityBond {
+        #[command(subcommand)]
+        command: BondCommand,
+        /// File the maker nostr keys and bond keys are kept in
+        #[arg(long)]
+        keystore: Option<String>,
+    },
+    /// Replace the maker encryption key, takers encrypt to the new key once the maker republishes its offers
+    RotateEncryptionKey {
+        /// File the maker nostr keys are kept in
+        #[arg(long)]
+        keystore: Option<String>,
+    },
+    /// Show the nostr identity derived from the wallet
+    ShowIdentity {
+        /// Index of the identity derived from the wallet
+        #[arg(long)]
+        identity_index: Option<u32>,
+    },
+    /// Decode a nostrdizer event, decrypting it when the key is its author or recipient
+    DecodeEvent(cli::inspect::DecodeEventArgs),
+    /// Write, encrypt or decrypt the config file
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
     },
 }
-fn main() -> Result<()> {
-    env_logger::Builder::new()
-        .format(|buf, record| {
-            writeln!(
-                buf,
-                "{}:{} {} [{}] - {}",
-                record.file().unwrap_or("unknown"),
-                record.line().unwrap_or(0),
-                chrono::Local::now().format("%Y-%m-%dT%H:%M:%S"),
-                record.level(),
-                record.args()
-            )
-        })
-        .filter(Some("nostrdizer"), LevelFilter::Debug)
-        .init();
+/// Defaults used when neither a flag nor the env sets them
+const DEFAULT_NETWORK: Network = Network::Regtest;
+const DEFAULT_RPC_URL: &str = "http://localhost:8332";
+const DEFAULT_RELAY: &str = "ws://localhost:7000";
+
+fn main() {
+    if cli::version::requested(&env::args().collect::<Vec<String>>()) {
+        print!(
+            "{}",
+            cli::version::report(DEFAULT_NETWORK, DEFAULT_RPC_URL, DEFAULT_RELAY)
+        );
+        return;
+    }
     // Parse input
     let args: Cli = Cli::parse();
     dotenv().ok();
 
+    let output = match output_or_env(&args.output) {
+        Ok(output) => output,
+        Err(err) => process::exit(cli::error::report(&err, OutputFormat::Text)),
+    };
+    if let Err(err) = run(args) {
+        process::exit(cli::error::report(&err, output));
+    }
+}
+
+fn run(mut args: Cli) -> Result<()> {
+    // Settings of the config are defaults of the env, so flags, .env and the env win over it
+    let config_path = match args
+        .config
+        .clone()
+        .or_else(|| env::var("NOSTRDIZER_CONFIG").ok())
+    {
+        Some(path) => PathBuf::from(path),
+        None => match nostrdizer::config::default_path() {
+            Some(path) => path,
+            None => bail!("No home directory for the config, set it with --config"),
+        },
+    };
+    // Commands that only read or write local files run without a wallet or node
+    match &args.command {
+        Commands::Config { command } => return cli::config::run(command, &config_path),
+        Commands::MakerStatus { status_file } => {
+            return cli::maker::maker_status(&status_path(status_file));
+        }
+        Commands::SetLogLevel { level, status_file } => {
+            return cli::logging::set_level(&status_path(status_file), level);
+        }
+        _ => (),
+    }
+    let config = Config::load(&config_path, cli::config::passphrase)?.unwrap_or_default();
+    args.priv_key = args.priv_key.or(config.nostr.priv_key);
+    let wallet = match args.wallet.clone().or(config.bitcoin.wallet) {
+        Some(wallet) => wallet,
+        None => bail!("No wallet given, set --wallet or wallet in the config"),
+    };
+
+    let retention = retention_or_env(&args.retention_days, &args.retention_max_size)?;
+    let log_file = args.log_file.clone().or_else(|| env::var("LOG_FILE").ok());
+    cli::logging::init(
+        cli::logging::level(args.verbose, args.quiet),
+        log_file.as_deref().map(Path::new),
+        retention.max_days,
+    )?;
+
     let rpc_url = match args.rpc_url {
         Some(url) => url,
         // TODO: Add port
-        None => {
-            if let Ok(url) = env::var("RPC_URL") {
-                url
-            } else {
-                "http://localhost:8332".to_string()
-            }
-        }
+        None => env_or_config("RPC_URL", config.bitcoin.rpc_url)
+            .unwrap_or_else(|| DEFAULT_RPC_URL.to_string()),
     };
+    let network = match args.network {
+        Some(network) => Network::from_str(&network)?,
+        None => match env_or_config("NETWORK", config.bitcoin.network) {
+            Some(network) => Network::from_str(&network)?,
+            None => DEFAULT_NETWORK,
+        },
+    };
+
     // RPC config
-    let rpc_username = env::var("RPC_USERNAME")?;
-    let rpc_password = env::var("RPC_PASSWORD")?;
+    let rpc_username = match env_or_config("RPC_USERNAME", config.bitcoin.rpc_username) {
+        Some(username) => username,
+        None => bail!("No rpc username, set RPC_USERNAME or rpc_username in the config"),
+    };
+    let rpc_password = match env_or_config("RPC_PASSWORD", config.bitcoin.rpc_password) {
+        Some(password) => password,
+        None => bail!("No rpc password, set RPC_PASSWORD or rpc_password in the config"),
+    };
+
+    // Wallet passphrase is not taken as an arg so it is not left in shell history
+    let wallet_passphrase =
+        match env_or_config("WALLET_PASSPHRASE", config.bitcoin.wallet_passphrase) {
+            Some(passphrase) => Some(passphrase),
+            None if args.prompt_passphrase => {
+                Some(rpassword::prompt_password("Wallet passphrase: ")?)
+            }
+            None => None,
+        };
 
     /*
     // Config to use for BDK
@@ -137,7 +379,7 @@ fn main() -> Result<()> {
         url: rpc_url,
         username: rpc_username,
         password: rpc_password,
-        network: Network::Regtest,
+        network,
         wallet_name: args.wallet,
     });
 
@@ -146,32 +388,110 @@ fn main() -> Result<()> {
     // Config to use wit Bitcore RPC
     let blockchain_config = BlockchainConfig::CoreRPC(BitcoinCoreCredentials {
         rpc_url,
-        wallet_name: args.wallet,
+        wallet_name: wallet,
         rpc_username,
         rpc_password,
+        network,
+        create_wallet: args.create_wallet,
+        wallet_passphrase,
     });
 
     let relay_urls = match args.nostr_relays {
         Some(nostr) => nostr,
-        None => {
-            if let Ok(nostr_relays) = env::var("NOSTR_RELAYS") {
-                serde_json::from_str(&nostr_relays)?
-            } else {
-                vec!["ws://localhost:7000".to_string()]
-            }
-        }
+        None => match env::var("NOSTR_RELAYS") {
+            Ok(nostr_relays) => serde_json::from_str(&nostr_relays)?,
+            Err(_) => config
+                .nostr
+                .relays
+                .unwrap_or_else(|| vec![DEFAULT_RELAY.to_string()]),
+        },
     };
 
-    // REVIEW: be nice to get rid of this
-    let relay_urls: Vec<&str> = relay_urls.iter().map(|x| x as &str).collect();
+    let offer_relay_urls = match args.offer_relays {
+        Some(nostr) => nostr,
+        None => match env::var("NOSTR_OFFER_RELAYS") {
+            Ok(offer_relays) => serde_json::from_str(&offer_relays)?,
+            Err(_) => config
+                .nostr
+                .offer_relays
+                .unwrap_or_else(|| relay_urls.clone()),
+        },
+    };
+
+    let publish_quorum = PublishQuorum {
+        relays: match args.publish_quorum {
+            Some(relays) => relays,
+            None => match env::var("PUBLISH_QUORUM") {
+                Ok(relays) => relays.parse()?,
+                Err(_) => config
+                    .nostr
+                    .publish_quorum
+                    .unwrap_or(PublishQuorum::default().relays),
+            },
+        },
+        ..PublishQuorum::default()
+    };
+
+    let mut relay_auth = RelayAuthConfig::default();
+    let auth_relays: Vec<String> = match &args.relay_auth {
+        Some(relays) => relays.clone(),
+        None => match env::var("RELAY_AUTH") {
+            Ok(relays) => serde_json::from_str(&relays)?,
+            Err(_) => config.nostr.relay_auth.unwrap_or_default(),
+        },
+    };
+    for relay in &auth_relays {
+        relay_auth.add(relay)?;
+    }
+
+    // Fees are shown in fiat as well when there is a currency
+    let fiat = args
+        .fiat_currency
+        .clone()
+        .or_else(|| env::var("FIAT_CURRENCY").ok())
+        .map(|currency| FiatConfig {
+            currency,
+            cache_path: PathBuf::from(
+                args.price_cache
+                    .clone()
+                    .or_else(|| env::var("FIAT_PRICE_CACHE").ok())
+                    .unwrap_or_else(|| "fiat_prices.json".to_string()),
+            ),
+            max_age: PRICE_MAX_AGE,
+        });
+
+    // Trust weight of offers seen on each relay
+    let relay_trust: HashMap<String, f64> = match env::var("NOSTR_RELAY_TRUST") {
+        Ok(relay_trust) => serde_json::from_str(&relay_trust)?,
+        Err(_) => config
+            .nostr
+            .relay_trust
+            .map(|trust| trust.into_iter().collect())
+            .unwrap_or_default(),
+    };
+
+    let ctx = Context {
+        priv_key: args.priv_key,
+        relay_urls,
+        offer_relay_urls,
+        blockchain_config,
+        network,
+        publish_quorum,
+        relay_auth,
+        fiat,
+        relay_trust,
+        retention,
+        maker: config.maker,
+        taker: config.taker,
+    };
 
     match &args.command {
         #[cfg(feature = "bdk")]
         Commands::GenerateWallet => {
-            let des = get_descriptors();
+            let des = get_descriptors(network);
             debug!("{:?}", des);
 
-            let BlockchainConfig::RPC(rpc_info) = blockchain_config;
+            let BlockchainConfig::RPC(rpc_info) = ctx.blockchain_config;
             /*
             // For when i add other configs
             //electrum etc
@@ -182,10 +502,10 @@ fn main() -> Result<()> {
             */
 
             let blockchain = new_rpc_blockchain(rpc_info)?;
-            let _wallet = new_wallet(&blockchain, des)?;
+            let _wallet = new_wallet(&blockchain, des, network)?;
         }
         Commands::TestPoodle => {
-            let _taker = Taker::new(args.priv_key, relay_urls, blockchain_config)?;
+            let _taker = ctx.new_taker()?;
             // let commit = taker.generate_podle()?;
 
             // if let Err(_err) = verify_podle(255, commit.clone(), commit.commit) {
@@ -197,247 +517,135 @@ fn main() -> Result<()> {
             // println!("{:?}", num);
         }
         Commands::ListUnspent => {
-            let mut taker = Taker::new(args.priv_key, relay_urls, blockchain_config)?;
-            let unspent = taker.get_unspent();
-            println!("{:#?}", unspent);
+            cli::taker::list_unspent(&mut ctx.new_taker()?)?;
         }
         Commands::GetEligibleBalance => {
-            let mut taker = Taker::new(args.priv_key, relay_urls, blockchain_config)?;
-            let balance = taker.get_eligible_balance()?;
-            println!("{:?}", balance);
+            cli::taker::get_eligible_balance(&mut ctx.new_taker()?)?;
         }
-        Commands::ListOffers => {
-            let mut taker = Taker::new(args.priv_key, relay_urls, blockchain_config)?;
-            let offers = taker.get_offers()?;
-            for (i, offer) in offers.iter().enumerate() {
-                println!("Offer {}: {:?}", i, offer);
-            }
+        Commands::ListOffers(list_args) => cli::list_offers::run(list_args, &ctx)?,
+        Commands::Benchmark => {
+            cli::taker::benchmark(&mut ctx.new_taker()?)?;
         }
-        Commands::SendTransaction {
-            send_amount,
-            number_of_makers,
+        Commands::SendTransaction(send_args) => cli::send_transaction::run(send_args, &ctx)?,
+        Commands::Tumble(tumble_args) => cli::tumble::run(tumble_args, &ctx)?,
+        Commands::JoinCoop(coop_args) => cli::join_coop::run(coop_args, &ctx)?,
+        Commands::RunMaker(maker_args) => cli::run_maker::run(maker_args, &ctx)?,
+        Commands::ExportLabels {
+            maker,
+            labels,
+            round_history,
+            file,
         } => {
-            let mut taker = Taker::new(args.priv_key, relay_urls, blockchain_config)?;
-
-            let number_of_makers = match number_of_makers {
-                Some(num) => *num,
-                None => {
-                    let mut rng = thread_rng();
-                    rng.gen_range(3..9)
-                }
-            };
-
-            let send_amount = Amount::from_sat(*send_amount);
-
-            println!(
-                "Looking for offers to send {} sats with {} peers.",
-                send_amount.to_sat(),
-                number_of_makers
-            );
-
-            // Check to make sure taker has sufficient balance
-            if taker.get_eligible_balance()? < send_amount {
-                bail!("Insufficient funds")
-            }
-
-            // REVIEW: if there are no matching offers it just ends
-            let mut matching_peers = taker.get_matching_offers(send_amount)?;
-            // debug!("Matching peers {:?}", matching_peers);
-            // println!("{} makers matched your order", matching_peers.len());
-
-            if matching_peers.is_empty() {
-                bail!("There are no makers that match this order")
-            }
-
-            println!("Choosing {} peers with the lowest fee", number_of_makers);
-
-            // Step 2: Send fill offer (!fill)
-            let matched_offers = taker.send_fill_offer_message(
-                send_amount,
-                number_of_makers,
-                &mut matching_peers,
+            cli::labels::export_labels(
+                &labels_path(labels, *maker),
+                &round_history_path(round_history, *maker),
+                file.as_ref().map(Path::new),
             )?;
-            debug!("{:?}", matched_offers);
-
-            println!("Sent fill offers to peers");
-
-            // Step 3: Receive maker pub key (!pubkey)
-            // TODO: Just gonna skip this for now
-            //taker.get_maker_pubkey()?;
-            //debug!("got pub key");
-
-            println!("Waiting for peer inputs...");
-            // Step 4: Send auth (!auth)
-            let auth_commitment = taker.generate_podle()?;
-            taker.send_auth_message(auth_commitment, matched_offers)?;
-            debug!("Sent auth");
-
-            // Step 5: Receive maker inputs (!ioauth)
-            // wait for responses from peers
-            // Gets peers tx inputs
-            // loops until enough peers have responded
-            let peer_inputs = taker.get_peer_inputs(number_of_makers, matching_peers)?;
-            println!("Peers have sent inputs creating transaction...");
-
-            // Step 6: Send CJ transaction (!tx)
-            let cj = taker.create_cj(send_amount, &peer_inputs)?;
-            // Send unsigned tx to peers
-            for (offer, _maker_input) in peer_inputs {
-                taker.send_unsigned_transaction(&offer.maker, &cj)?;
-            }
-
-            // Step 7: Sign TX (!sig)
-            println!("Waiting for peer signatures...");
-            // Wait for signed txs
-            // Combine signed tx
-            let peer_signed_psbts = taker.get_signed_peer_transaction(number_of_makers)?;
-            println!("Makers have signed transaction, signing ...");
-
-            let combined_psbt = taker.combine_psbts(&peer_signed_psbts)?;
-
-            // Taker Sign tx
-            if let Ok(tx_info) = taker.verify_transaction(&combined_psbt, &send_amount) {
-                println!("Total fee to makers: {} sats.", tx_info.maker_fee.to_sat());
-                println!("Mining fee: {} sats", tx_info.mining_fee.to_sat());
-                if tx_info.verifyed {
-                    println!("Transaction passed verification, signing ...");
-                    let signed_psbt = taker.sign_psbt(combined_psbt)?;
-                    println!("Finalized transaction, broadcasting ...");
-
-                    // Broadcast signed tx
-                    let txid = taker.broadcast_psbt(signed_psbt)?;
-                    println!("TXID: {:?}", txid);
-                } else {
-                    bail!("Transaction could not be verified")
-                }
-            } else {
-                bail!("Transaction could not be verified")
-            }
         }
-        Commands::RunMaker {
-            abs_fee,
-            rel_fee,
-            minsize,
-            maxsize,
-            will_broadcast,
+        Commands::ImportLabels {
+            file,
+            maker,
+            labels,
         } => {
-            let abs_fee = match abs_fee {
-                Some(abs_fee) => Amount::from_sat(*abs_fee),
-                None => {
-                    if let Ok(abs_fee) = env::var("MAKER_ABS_FEE") {
-                        Amount::from_sat(abs_fee.parse::<u64>()?)
-                    } else {
-                        Amount::ZERO
-                    }
-                }
-            };
-
-            let rel_fee = match rel_fee {
-                Some(rel_fee) => *rel_fee,
-                None => {
-                    if let Ok(rel_fee) = env::var("MAKER_REL_FEE") {
-                        rel_fee.parse::<f64>()?
-                    } else {
-                        0.0
-                    }
-                }
-            };
-
-            let minsize = match minsize {
-                Some(minsize) => Amount::from_sat(*minsize),
-                None => {
-                    if let Ok(minsize) = env::var("MAKER_MINSIZE") {
-                        Amount::from_sat(minsize.parse()?)
-                    } else {
-                        Amount::from_sat(5000)
-                    }
-                }
-            };
-
-            let maxsize = match maxsize {
-                Some(maxsize) => Some(Amount::from_sat(*maxsize)),
-                None => {
-                    if let Ok(maxsize) = env::var("MAKER_MAXSIZE") {
-                        Some(Amount::from_sat(maxsize.parse()?))
-                    } else {
-                        None
-                    }
-                }
-            };
-
-            let will_broadcast = match will_broadcast {
-                Some(will_broadcast) => *will_broadcast,
-                None => {
-                    if let Ok(will_broadcast) = env::var("WILL_BROADCAST") {
-                        will_broadcast.parse()?
-                    } else {
-                        true
-                    }
-                }
-            };
-
-            let mut config = MakerConfig {
-                rel_fee,
-                abs_fee,
-                minsize,
-                maxsize,
-                will_broadcast,
-            };
-            let mut maker = Maker::new(
-                args.priv_key,
-                relay_urls.clone(),
-                &mut config,
-                blockchain_config,
+            cli::labels::import_labels(&labels_path(labels, *maker), Path::new(file))?;
+        }
+        Commands::Backup {
+            maker,
+            reputation,
+            keystore,
+            round_history,
+        } => {
+            let keystore_path = keystore_path(keystore, *maker);
+            let priv_key = cli::backup::backup_key(ctx.priv_key.clone(), &keystore_path, *maker)?;
+            let reputation_file = (!*maker).then(|| reputation_path(reputation));
+            cli::backup::backup(
+                &priv_key,
+                ctx.relays(),
+                reputation_file.as_deref(),
+                &keystore_path,
+                &round_history_path(round_history, *maker),
             )?;
-            loop {
-                // Step 1: Publish order (!ordertype)
-                maker.publish_offer()?;
-
-                // println!("Running maker with {:?}", offer);
-                println!("Waiting for takers...");
-
-                // Step 2: Receives fill offer (!fill)
-                let (peer_pubkey, fill_offer) = maker.get_fill_offer()?;
-
-                println!("Received fill Offer: {:?}", fill_offer);
-
-                maker.delete_active_offer()?;
-
-                // Step 3: sends maker (!pubkey)
-                //maker.send_pubkey(&peer_pubkey)?;
-
-                // Step 4: Receives !auth
-                let auth_commitment = maker.get_commitment_auth()?;
-                // TODO: Handle errors
-                maker.verify_podle(auth_commitment)?;
-
-                // Step 5: sends (!ioauth)
-                let maker_input = maker.get_inputs(&fill_offer)?;
-                maker.send_maker_input(&peer_pubkey, maker_input)?;
-
-                // Step 6: Receives Transaction Hex (!tx)
-                match maker.get_unsigned_cj_transaction() {
-                    Ok(unsigned_psbt) => {
-                        if let Ok(tx_info) =
-                            maker.verify_transaction(&unsigned_psbt, &fill_offer.amount)
-                        {
-                            if tx_info.verifyed {
-                                // Step 7: Signs and sends transaction to taker if verified (!sig)
-                                let signed_psbt = maker.sign_psbt(unsigned_psbt)?;
-
-                                maker.publish_signed_psbt(&peer_pubkey, signed_psbt)?;
-                            } else {
-                                warn!("Transaction could not be verified");
-                            }
-                        }
-                    }
-                    Err(NostrdizerError::TakerFailedToSendTransaction) => {
-                        warn!("Taker did not send transaction");
-                    }
-                    Err(err) => error!("{:?}", err),
-                }
-            }
         }
+        Commands::Restore {
+            maker,
+            reputation,
+            keystore,
+            round_history,
+        } => {
+            let keystore_path = keystore_path(keystore, *maker);
+            let priv_key = cli::backup::backup_key(ctx.priv_key.clone(), &keystore_path, *maker)?;
+            let reputation_file = (!*maker).then(|| reputation_path(reputation));
+            cli::backup::restore(
+                &priv_key,
+                ctx.relays(),
+                reputation_file.as_deref(),
+                &keystore_path,
+                &round_history_path(round_history, *maker),
+            )?;
+        }
+        Commands::PayoutReport { payout_history } => {
+            cli::maker::payout_report(&payout_history_path(payout_history), &ctx.fiat)?;
+        }
+        Commands::AuditWallet(audit_args) => cli::audit_wallet::run(audit_args, &ctx)?,
+        Commands::PurgeData(purge_args) => cli::purge_data::run(purge_args, &ctx)?,
+        Commands::BumpFee(bump_args) => cli::bump_fee::run(bump_args, &ctx)?,
+        #[cfg(feature = "dev-swarm")]
+        Commands::DevSwarm(swarm_args) => cli::swarm::run(swarm_args, &ctx)?,
+        Commands::MintInvite { taker, invites } => {
+            cli::maker::mint_invite(&invites_path(invites), taker)?;
+        }
+        Commands::RevokeInvite { taker, invites } => {
+            cli::maker::revoke_invite(&invites_path(invites), taker)?;
+        }
+        Commands::FidelityBond { command, keystore } => {
+            let creds = ctx.core_credentials("Fidelity bonds need a bitcoin core wallet")?;
+            cli::fidelity_bond::run(command, &keystore_path(keystore, true), creds)?;
+        }
+        Commands::RotateEncryptionKey { keystore } => {
+            cli::maker::rotate_encryption_key(&keystore_path(keystore, true))?;
+        }
+        Commands::ShowIdentity { identity_index } => {
+            let index = cli::env::identity_index(identity_index)?;
+            cli::maker::show_identity(&ctx.wallet_nostr_key(index)?, index)?;
+        }
+        Commands::DecodeEvent(decode_args) => cli::inspect::run(decode_args, &ctx)?,
+        // Run before the wallet is needed
+        Commands::Config { .. } | Commands::MakerStatus { .. } | Commands::SetLogLevel { .. } => {}
     }
     Ok(())
 }
+
+/// Retention policy from the flags or env, everything is kept when neither is set
+fn retention_or_env(
+    retention_days: &Option<u64>,
+    retention_max_size: &Option<u64>,
+) -> Result<RetentionPolicy> {
+    let or_env = |value: &Option<u64>, var: &str| -> Result<Option<u64>> {
+        Ok(match value {
+            Some(value) => Some(*value),
+            None => match env::var(var) {
+                Ok(value) => Some(value.parse()?),
+                Err(_) => None,
+            },
+        })
+    };
+    Ok(RetentionPolicy {
+        max_days: or_env(retention_days, "RETENTION_DAYS")?,
+        max_size: or_env(retention_max_size, "RETENTION_MAX_SIZE")?,
+    })
+}
+
+/// Setting from the env var, then the config
+fn env_or_config(var: &str, config: Option<String>) -> Option<String> {
+    env::var(var).ok().or(config)
+}
+
+fn output_or_env(output: &Option<String>) -> Result<OutputFormat> {
+    match output {
+        Some(output) => OutputFormat::from_str(output),
+        None => match env::var("OUTPUT") {
+            Ok(output) => OutputFormat::from_str(&output),
+            Err(_) => Ok(OutputFormat::Text),
+        },
+    }
+}